@@ -0,0 +1,12 @@
+fn main() {
+    // Generated into OUT_DIR; the checked-in include/cubiq.h, for callers
+    // that only link the built library, is tested against it
+    let dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+    let out = std::env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).expect("cbindgen.toml parses");
+    cbindgen::generate_with_config(&dir, config)
+        .expect("cbindgen reads the crate")
+        .write_to_file(format!("{}/cubiq.h", out));
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
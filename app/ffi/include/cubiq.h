@@ -0,0 +1,187 @@
+/* Generated by cbindgen from app/ffi; do not edit. */
+
+#ifndef CUBIQ_H
+#define CUBIQ_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Bumped when a function is removed or changes its signature.
+ */
+#define CUBIQ_ABI_VERSION 1
+
+typedef enum CubiqStatus {
+  CUBIQ_STATUS_OK = 0,
+  /**
+   * An argument is null, not UTF-8, or malformed
+   */
+  CUBIQ_STATUS_INVALID_ARGUMENT = 1,
+  /**
+   * A proof or signature does not check out
+   */
+  CUBIQ_STATUS_VERIFICATION_FAILED = 2,
+  /**
+   * A proof could not be fetched
+   */
+  CUBIQ_STATUS_RESOLVE_FAILED = 3,
+  /**
+   * The library panicked; its state is still sound, but the call did
+   * nothing
+   */
+  CUBIQ_STATUS_PANIC = 4,
+} CubiqStatus;
+
+typedef struct CubiqResolver CubiqResolver;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The ABI version the library implements.
+ */
+uint32_t cubiq_abi_version(void);
+
+/**
+ * What went wrong in the last call on this thread that failed. The
+ * string stays valid until the next failure on the thread; it is empty
+ * if no call has failed.
+ */
+const char *cubiq_last_error(void);
+
+/**
+ * Frees a string the library handed back. Null is ignored.
+ *
+ * # Safety
+ *
+ * `s` must be null or a string from this library, not yet freed.
+ */
+void cubiq_string_free(char *s);
+
+/**
+ * Hands back the account that `secret` holds through `out`.
+ *
+ * # Safety
+ *
+ * `secret` must point to 32 readable bytes and `out` be writable.
+ */
+enum CubiqStatus cubiq_address(const uint8_t *secret, char **out);
+
+/**
+ * Signs a transaction given as a JSON object of its fields, as
+ * `tx_submit` takes them, handing the signed transaction back through
+ * `out_json`. Fields left out take their defaults; signing sets `from`,
+ * `hash` and `signature`.
+ *
+ * # Safety
+ *
+ * `secret` must point to 32 readable bytes, `tx_json` be a
+ * NUL-terminated string and `out_json` writable.
+ */
+enum CubiqStatus cubiq_sign_transaction(const uint8_t *secret,
+                                        const char *tx_json,
+                                        char **out_json);
+
+/**
+ * Checks a signed transaction's signature, or a multisig transaction's
+ * cosignatures, as the node checks them on submission.
+ *
+ * # Safety
+ *
+ * `tx_json` must be a NUL-terminated string.
+ */
+enum CubiqStatus cubiq_verify_transaction(const char *tx_json);
+
+/**
+ * Parses `zkurl`, handing it back through `out_json` as JSON:
+ * `{prover_id, host, port, path_prefix, proof_id, metadata}`.
+ *
+ * # Safety
+ *
+ * `zkurl` must be a NUL-terminated string and `out_json` writable.
+ */
+enum CubiqStatus cubiq_zkurl_parse(const char *zkurl, char **out_json);
+
+/**
+ * Hands back `zkurl` in canonical form through `out`.
+ *
+ * # Safety
+ *
+ * `zkurl` must be a NUL-terminated string and `out` writable.
+ */
+enum CubiqStatus cubiq_zkurl_normalize(const char *zkurl, char **out);
+
+/**
+ * A resolver falling back to the `count` endpoints at `endpoints`, such
+ * as `https://proofs.example`, when a zkURL's own host fails. Free it
+ * with `cubiq_resolver_free`.
+ *
+ * # Safety
+ *
+ * `endpoints` must point to `count` NUL-terminated strings, or be null
+ * with a count of 0, and `out` must be writable.
+ */
+enum CubiqStatus cubiq_resolver_new(const char *const *endpoints,
+                                    size_t count,
+                                    struct CubiqResolver **out);
+
+/**
+ * Fetches the proof bundle `zkurl` names, handing it back through
+ * `out_json` as JSON. The bundle is not verified; see
+ * `cubiq_verify_bundle`.
+ *
+ * # Safety
+ *
+ * `resolver` must be from `cubiq_resolver_new` and not yet freed,
+ * `zkurl` a NUL-terminated string and `out_json` writable.
+ */
+enum CubiqStatus cubiq_resolver_fetch(const struct CubiqResolver *resolver,
+                                      const char *zkurl,
+                                      char **out_json);
+
+/**
+ * Frees a resolver, once fetches on other threads have returned. Null
+ * is ignored.
+ *
+ * # Safety
+ *
+ * `resolver` must be null or from `cubiq_resolver_new`, not yet freed.
+ */
+void cubiq_resolver_free(struct CubiqResolver *resolver);
+
+/**
+ * Checks that `proof` is a block finality proof on chain `chain_id` over
+ * `public_inputs`, encoded as a proof bundle's are:
+ * `CUBIQ_STATUS_OK` if it is, `CUBIQ_STATUS_VERIFICATION_FAILED` if not.
+ *
+ * # Safety
+ *
+ * `proof` and `public_inputs` must point to `proof_len` and
+ * `public_inputs_len` readable bytes, or be null with a length of 0.
+ */
+enum CubiqStatus cubiq_verify_proof(const uint8_t *proof,
+                                    size_t proof_len,
+                                    const uint8_t *public_inputs,
+                                    size_t public_inputs_len,
+                                    uint64_t chain_id);
+
+/**
+ * Checks a proof bundle, as JSON, the way `cubiq_verify_proof` checks
+ * its proof over its public inputs. The prover's signature is not
+ * checked; that needs the prover's key.
+ *
+ * # Safety
+ *
+ * `bundle_json` must be a NUL-terminated string.
+ */
+enum CubiqStatus cubiq_verify_bundle(const char *bundle_json, uint64_t chain_id);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CUBIQ_H */
@@ -0,0 +1,95 @@
+//! Signing transactions with an ed25519 secret the caller keeps, and
+//! checking signed ones. Secrets are 32 bytes and never stored.
+
+use consensus::address::Address;
+use consensus::Transaction;
+use ed25519_dalek::SigningKey;
+use serde_json::{json, Value};
+use std::ffi::c_char;
+
+use crate::{bytes_arg, guard, invalid, put_string, str_arg, CubiqStatus, Failure};
+
+/// Hands back the account that `secret` holds through `out`.
+///
+/// # Safety
+///
+/// `secret` must point to 32 readable bytes and `out` be writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_address(secret: *const u8, out: *mut *mut c_char) -> CubiqStatus {
+    guard(|| {
+        let key = key(secret)?;
+        put_string(out, Address::from_key(&key.verifying_key()).to_string())
+    })
+}
+
+/// Signs a transaction given as a JSON object of its fields, as
+/// `tx_submit` takes them, handing the signed transaction back through
+/// `out_json`. Fields left out take their defaults; signing sets `from`,
+/// `hash` and `signature`.
+///
+/// # Safety
+///
+/// `secret` must point to 32 readable bytes, `tx_json` be a
+/// NUL-terminated string and `out_json` writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_sign_transaction(secret: *const u8, tx_json: *const c_char, out_json: *mut *mut c_char) -> CubiqStatus {
+    guard(|| {
+        let key = key(secret)?;
+        let fields: serde_json::Map<String, Value> =
+            serde_json::from_str(str_arg(tx_json, "tx_json")?).map_err(|e| invalid(format!("transaction: {}", e)))?;
+        let mut draft = json!(Transaction::default());
+        draft.as_object_mut().expect("transactions serialize as objects").extend(fields);
+        let tx: Transaction = serde_json::from_value(draft).map_err(|e| invalid(format!("transaction: {}", e)))?;
+        put_string(out_json, serde_json::to_string(&tx.sign(&key)).expect("transactions encode"))
+    })
+}
+
+/// Checks a signed transaction's signature, or a multisig transaction's
+/// cosignatures, as the node checks them on submission.
+///
+/// # Safety
+///
+/// `tx_json` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_verify_transaction(tx_json: *const c_char) -> CubiqStatus {
+    guard(|| {
+        let tx: Transaction =
+            serde_json::from_str(str_arg(tx_json, "tx_json")?).map_err(|e| invalid(format!("transaction: {}", e)))?;
+        tx.verify_signature().map_err(|e| (CubiqStatus::VerificationFailed, e.to_string()))
+    })
+}
+
+unsafe fn key(secret: *const u8) -> Result<SigningKey, Failure> {
+    let secret: [u8; 32] = bytes_arg(secret, 32, "secret")?.try_into().expect("32 bytes were read");
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn signs_transactions_the_node_accepts() {
+        unsafe {
+            let secret = [7u8; 32];
+            let mut out = std::ptr::null_mut();
+            assert_eq!(cubiq_address(secret.as_ptr(), &mut out), CubiqStatus::Ok);
+            let address = CStr::from_ptr(out).to_str().unwrap().to_string();
+            crate::cubiq_string_free(out);
+            assert!(address.starts_with("cubiq1"));
+            assert_eq!(cubiq_address(std::ptr::null(), &mut out), CubiqStatus::InvalidArgument);
+
+            let fields = CString::new(r#"{"chain_id":7,"nonce":1,"to":"0xbob","value":5,"gas_limit":21000}"#).unwrap();
+            assert_eq!(cubiq_sign_transaction(secret.as_ptr(), fields.as_ptr(), &mut out), CubiqStatus::Ok);
+            let signed = CStr::from_ptr(out).to_owned();
+            crate::cubiq_string_free(out);
+            let tx: Transaction = serde_json::from_str(signed.to_str().unwrap()).unwrap();
+            assert_eq!((tx.from, tx.value), (address, 5));
+            assert_eq!(cubiq_verify_transaction(signed.as_ptr()), CubiqStatus::Ok);
+
+            let forged = CString::new(signed.to_str().unwrap().replace(r#""value":5"#, r#""value":6"#)).unwrap();
+            assert_eq!(cubiq_verify_transaction(forged.as_ptr()), CubiqStatus::VerificationFailed);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! C ABI for embedding Cubiq verification in services written in other
+//! languages (Go through cgo, Python through ctypes, Swift), without
+//! running a node: proof verification (`verify`), zkURL parsing and
+//! resolution (`resolver`) and transaction signing (`keys`).
+//!
+//! The header, `include/cubiq.h`, is generated by cbindgen when the crate
+//! builds, into its `OUT_DIR`; the tests fail while the checked-in copy
+//! differs from it. Link against `libcubiq`. Within an ABI version
+//! (`cubiq_abi_version`), functions are only ever added.
+//!
+//! - Every function but the accessors returns a `CubiqStatus`. On anything
+//!   but `CUBIQ_STATUS_OK`, `cubiq_last_error` says what went wrong.
+//! - Strings are NUL-terminated UTF-8. A string handed back through an
+//!   out parameter belongs to the caller, who frees it with
+//!   `cubiq_string_free`.
+//! - zkURLs, proof bundles and transactions cross as JSON, encoded as the
+//!   node's JSON-RPC encodes them.
+//! - A panic is caught and reported as `CUBIQ_STATUS_PANIC` rather than
+//!   unwinding into the caller.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Bumped when a function is removed or changes its signature.
+pub const CUBIQ_ABI_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CubiqStatus {
+    Ok = 0,
+    /// An argument is null, not UTF-8, or malformed
+    InvalidArgument = 1,
+    /// A proof or signature does not check out
+    VerificationFailed = 2,
+    /// A proof could not be fetched
+    ResolveFailed = 3,
+    /// The library panicked; its state is still sound, but the call did
+    /// nothing
+    Panic = 4,
+}
+
+/// A failed call's status and message.
+pub(crate) type Failure = (CubiqStatus, String);
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+pub(crate) fn invalid(what: impl std::fmt::Display) -> Failure {
+    (CubiqStatus::InvalidArgument, what.to_string())
+}
+
+/// Runs `f`, recording its failure, or its panic, for `cubiq_last_error`.
+pub(crate) fn guard(f: impl FnOnce() -> Result<(), Failure>) -> CubiqStatus {
+    let (status, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return CubiqStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            (CubiqStatus::Panic, format!("panicked: {}", message))
+        }
+    };
+    let message = CString::new(message.replace('\0', " ")).expect("NULs are replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+/// The string at `ptr`, which must be null or NUL-terminated.
+pub(crate) unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(invalid(format!("{} is null", name)));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| invalid(format!("{} is not UTF-8", name)))
+}
+
+/// The `len` bytes at `ptr`, which may be null when `len` is 0.
+pub(crate) unsafe fn bytes_arg<'a>(ptr: *const u8, len: usize, name: &str) -> Result<&'a [u8], Failure> {
+    match (ptr.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(invalid(format!("{} is null", name))),
+        (false, _) => Ok(std::slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// Hands `value` to the caller through `out`.
+pub(crate) unsafe fn put_string(out: *mut *mut c_char, value: String) -> Result<(), Failure> {
+    if out.is_null() {
+        return Err(invalid("out is null"));
+    }
+    *out = CString::new(value).map_err(|_| invalid("result holds a NUL"))?.into_raw();
+    Ok(())
+}
+
+/// The ABI version the library implements.
+#[no_mangle]
+pub extern "C" fn cubiq_abi_version() -> u32 {
+    CUBIQ_ABI_VERSION
+}
+
+/// What went wrong in the last call on this thread that failed. The
+/// string stays valid until the next failure on the thread; it is empty
+/// if no call has failed.
+#[no_mangle]
+pub extern "C" fn cubiq_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// Frees a string the library handed back. Null is ignored.
+///
+/// # Safety
+///
+/// `s` must be null or a string from this library, not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+pub mod keys;
+pub mod resolver;
+pub mod verify;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_in_header_is_current() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/cubiq.h"));
+        let checked_in = include_str!("../include/cubiq.h");
+        assert!(generated == checked_in, "include/cubiq.h is out of date; copy {}/cubiq.h over it", env!("OUT_DIR"));
+    }
+
+    #[test]
+    fn reports_failures_and_panics_per_thread() {
+        assert_eq!(guard(|| Ok(())), CubiqStatus::Ok);
+        let message = || unsafe { CStr::from_ptr(cubiq_last_error()) }.to_str().unwrap().to_string();
+        assert_eq!(message(), "");
+        assert_eq!(guard(|| Err(invalid("bad\0input"))), CubiqStatus::InvalidArgument);
+        assert_eq!(message(), "bad input");
+        assert_eq!(guard(|| panic!("boom")), CubiqStatus::Panic);
+        assert_eq!(message(), "panicked: boom");
+        std::thread::spawn(move || assert_eq!(message(), "")).join().unwrap();
+
+        unsafe {
+            assert_eq!(bytes_arg(std::ptr::null(), 0, "proof"), Ok(&[][..]));
+            assert!(bytes_arg(std::ptr::null(), 3, "proof").is_err());
+            assert!(str_arg(std::ptr::null(), "zkurl").is_err());
+            let mut out = std::ptr::null_mut();
+            put_string(&mut out, "hello".to_string()).unwrap();
+            assert_eq!(CStr::from_ptr(out).to_str(), Ok("hello"));
+            cubiq_string_free(out);
+            cubiq_string_free(std::ptr::null_mut());
+        }
+    }
+}
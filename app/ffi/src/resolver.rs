@@ -0,0 +1,156 @@
+//! Parsing zkURLs and resolving them to proof bundles.
+//!
+//! A `CubiqResolver` owns a `zkurl::resolver::ZkURLResolver` and the
+//! runtime its fetches run on, so callers need no async runtime of their
+//! own: `cubiq_resolver_fetch` blocks until the bundle arrives or every
+//! endpoint fails. One resolver may be used from several threads at once.
+
+use std::ffi::c_char;
+use std::str::FromStr;
+use tokio::runtime::Runtime;
+use zkurl::resolver::ZkURLResolver;
+use zkurl::ZkURL;
+
+use crate::{guard, invalid, put_string, str_arg, CubiqStatus};
+
+/// Worker threads of a resolver's runtime; fetches mostly wait on the
+/// network.
+const RUNTIME_THREADS: usize = 2;
+
+pub struct CubiqResolver {
+    runtime: Runtime,
+    resolver: ZkURLResolver,
+}
+
+/// Parses `zkurl`, handing it back through `out_json` as JSON:
+/// `{prover_id, host, port, path_prefix, proof_id, metadata}`.
+///
+/// # Safety
+///
+/// `zkurl` must be a NUL-terminated string and `out_json` writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_zkurl_parse(zkurl: *const c_char, out_json: *mut *mut c_char) -> CubiqStatus {
+    guard(|| {
+        let zkurl = parse(str_arg(zkurl, "zkurl")?)?;
+        put_string(out_json, serde_json::to_string(&zkurl).expect("zkURLs encode"))
+    })
+}
+
+/// Hands back `zkurl` in canonical form through `out`.
+///
+/// # Safety
+///
+/// `zkurl` must be a NUL-terminated string and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_zkurl_normalize(zkurl: *const c_char, out: *mut *mut c_char) -> CubiqStatus {
+    guard(|| {
+        let zkurl = parse(str_arg(zkurl, "zkurl")?)?;
+        put_string(out, zkurl.to_string())
+    })
+}
+
+/// A resolver falling back to the `count` endpoints at `endpoints`, such
+/// as `https://proofs.example`, when a zkURL's own host fails. Free it
+/// with `cubiq_resolver_free`.
+///
+/// # Safety
+///
+/// `endpoints` must point to `count` NUL-terminated strings, or be null
+/// with a count of 0, and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_resolver_new(
+    endpoints: *const *const c_char,
+    count: usize,
+    out: *mut *mut CubiqResolver,
+) -> CubiqStatus {
+    guard(|| {
+        if out.is_null() || (endpoints.is_null() && count > 0) {
+            return Err(invalid("out or endpoints is null"));
+        }
+        let endpoints = (0..count).map(|i| str_arg(*endpoints.add(i), "endpoint").map(str::to_string)).collect::<Result<Vec<_>, _>>()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(RUNTIME_THREADS)
+            .thread_name("cubiq-resolver")
+            .enable_all()
+            .build()
+            .map_err(|e| (CubiqStatus::ResolveFailed, format!("starting the resolver runtime: {}", e)))?;
+        let resolver = ZkURLResolver::new(endpoints);
+        *out = Box::into_raw(Box::new(CubiqResolver { runtime, resolver }));
+        Ok(())
+    })
+}
+
+/// Fetches the proof bundle `zkurl` names, handing it back through
+/// `out_json` as JSON. The bundle is not verified; see
+/// `cubiq_verify_bundle`.
+///
+/// # Safety
+///
+/// `resolver` must be from `cubiq_resolver_new` and not yet freed,
+/// `zkurl` a NUL-terminated string and `out_json` writable.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_resolver_fetch(
+    resolver: *const CubiqResolver,
+    zkurl: *const c_char,
+    out_json: *mut *mut c_char,
+) -> CubiqStatus {
+    guard(|| {
+        let resolver = resolver.as_ref().ok_or_else(|| invalid("resolver is null"))?;
+        let zkurl = parse(str_arg(zkurl, "zkurl")?)?;
+        let bundle = resolver
+            .runtime
+            .block_on(resolver.resolver.fetch_proof(&zkurl))
+            .map_err(|e| (CubiqStatus::ResolveFailed, e.to_string()))?;
+        put_string(out_json, serde_json::to_string(&bundle).expect("proof bundles encode"))
+    })
+}
+
+/// Frees a resolver, once fetches on other threads have returned. Null
+/// is ignored.
+///
+/// # Safety
+///
+/// `resolver` must be null or from `cubiq_resolver_new`, not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_resolver_free(resolver: *mut CubiqResolver) {
+    if !resolver.is_null() {
+        let resolver = Box::from_raw(resolver);
+        resolver.runtime.shutdown_background();
+    }
+}
+
+fn parse(zkurl: &str) -> Result<ZkURL, crate::Failure> {
+    ZkURL::from_str(zkurl).map_err(|e| invalid(format!("zkurl: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::{CStr, CString};
+
+    #[test]
+    fn parses_and_resolves_through_the_c_abi() {
+        unsafe {
+            let zkurl = CString::new("zk://prover@domain.com/block7#v2&&snark").unwrap();
+            let mut out = std::ptr::null_mut();
+            assert_eq!(cubiq_zkurl_normalize(zkurl.as_ptr(), &mut out), CubiqStatus::Ok);
+            assert_eq!(CStr::from_ptr(out).to_str(), Ok("zk://prover@domain.com/block7#version=v2&type=snark"));
+            crate::cubiq_string_free(out);
+            assert_eq!(cubiq_zkurl_parse(zkurl.as_ptr(), &mut out), CubiqStatus::Ok);
+            let parsed: serde_json::Value = serde_json::from_str(CStr::from_ptr(out).to_str().unwrap()).unwrap();
+            assert_eq!(parsed["proof_id"], "block7");
+            crate::cubiq_string_free(out);
+            let bad = CString::new("http://domain.com/block").unwrap();
+            assert_eq!(cubiq_zkurl_parse(bad.as_ptr(), &mut out), CubiqStatus::InvalidArgument);
+
+            let endpoints = [CString::new("http://127.0.0.1:9").unwrap()];
+            let endpoints = endpoints.iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
+            let mut resolver = std::ptr::null_mut();
+            assert_eq!(cubiq_resolver_new(endpoints.as_ptr(), 1, &mut resolver), CubiqStatus::Ok);
+            let unreachable = CString::new("zk://prover@localhost:9/block1").unwrap();
+            assert_eq!(cubiq_resolver_fetch(resolver, unreachable.as_ptr(), &mut out), CubiqStatus::ResolveFailed);
+            assert_eq!(cubiq_resolver_fetch(std::ptr::null(), unreachable.as_ptr(), &mut out), CubiqStatus::InvalidArgument);
+            cubiq_resolver_free(resolver);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Checking block finality proofs, as bytes or as the proof bundles
+//! zkURLs resolve to, with the mobile verifier.
+
+use prover::MobileProofVerifier;
+use std::ffi::c_char;
+use zkurl::resolver::ProofBundle;
+
+use crate::{bytes_arg, guard, invalid, str_arg, CubiqStatus};
+
+/// Checks that `proof` is a block finality proof on chain `chain_id` over
+/// `public_inputs`, encoded as a proof bundle's are:
+/// `CUBIQ_STATUS_OK` if it is, `CUBIQ_STATUS_VERIFICATION_FAILED` if not.
+///
+/// # Safety
+///
+/// `proof` and `public_inputs` must point to `proof_len` and
+/// `public_inputs_len` readable bytes, or be null with a length of 0.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_verify_proof(
+    proof: *const u8,
+    proof_len: usize,
+    public_inputs: *const u8,
+    public_inputs_len: usize,
+    chain_id: u64,
+) -> CubiqStatus {
+    guard(|| {
+        let proof = bytes_arg(proof, proof_len, "proof")?;
+        let public_inputs = bytes_arg(public_inputs, public_inputs_len, "public_inputs")?;
+        verify(proof, public_inputs, chain_id)
+    })
+}
+
+/// Checks a proof bundle, as JSON, the way `cubiq_verify_proof` checks
+/// its proof over its public inputs. The prover's signature is not
+/// checked; that needs the prover's key.
+///
+/// # Safety
+///
+/// `bundle_json` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn cubiq_verify_bundle(bundle_json: *const c_char, chain_id: u64) -> CubiqStatus {
+    guard(|| {
+        let bundle: ProofBundle =
+            serde_json::from_str(str_arg(bundle_json, "bundle_json")?).map_err(|e| invalid(format!("proof bundle: {}", e)))?;
+        verify(&bundle.proof, &bundle.public_inputs.transcript_bytes(), chain_id)
+    })
+}
+
+fn verify(proof: &[u8], public_inputs: &[u8], chain_id: u64) -> Result<(), crate::Failure> {
+    cubiq_light::certificate::verify_proof(&MobileProofVerifier::new(), proof, public_inputs, chain_id)
+        .map_err(|e| (CubiqStatus::VerificationFailed, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::ffi::CString;
+
+    #[test]
+    fn rejects_malformed_proofs_and_arguments() {
+        unsafe {
+            assert_eq!(cubiq_verify_proof([1, 2, 3].as_ptr(), 3, std::ptr::null(), 0, 7), CubiqStatus::VerificationFailed);
+            assert_eq!(cubiq_verify_proof(std::ptr::null(), 3, std::ptr::null(), 0, 7), CubiqStatus::InvalidArgument);
+
+            let bundle = json!({
+                "proof": [1, 2, 3],
+                "public_inputs": { "block_hash": "0xb1", "state_root": "0x00", "gas_used": 0, "transaction_count": 0 },
+                "signature": "", "prover_id": "p", "timestamp": 0,
+                "metadata": { "version": "v1", "compression": null, "size_bytes": 3 },
+            });
+            let bundle = CString::new(bundle.to_string()).unwrap();
+            assert_eq!(cubiq_verify_bundle(bundle.as_ptr(), 7), CubiqStatus::VerificationFailed);
+            let malformed = CString::new("{\"proof\":1}").unwrap();
+            assert_eq!(cubiq_verify_bundle(malformed.as_ptr(), 7), CubiqStatus::InvalidArgument);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Keys and signing.
+//!
+//! A `Wallet` is one ed25519 key. Its 32-byte secret is handed to the app
+//! to keep in the platform's keychain or keystore, which guard it better
+//! than any file this crate could write, and handed back to open the
+//! wallet again. Transactions are signed as the node checks them; see
+//! `consensus::Transaction`.
+
+use consensus::address::{self, Address};
+use consensus::Transaction;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::MobileError;
+
+/// A transfer to sign, with the fees to offer; see `fee_estimate` for
+/// them and `state_getNonce` for `nonce`.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct Transfer {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub to: String,
+    pub value: u64,
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SignedTransaction {
+    pub hash: String,
+    /// The transaction as `tx_submit` takes it
+    pub json: String,
+}
+
+#[derive(uniffi::Object)]
+pub struct Wallet {
+    key: SigningKey,
+}
+
+#[uniffi::export]
+impl Wallet {
+    /// A wallet with a new key from the OS's random number generator.
+    #[uniffi::constructor]
+    pub fn generate() -> Arc<Self> {
+        Arc::new(Wallet { key: SigningKey::generate(&mut OsRng) })
+    }
+
+    /// The wallet whose `secret` this is.
+    #[uniffi::constructor]
+    pub fn from_secret(secret: Vec<u8>) -> Result<Arc<Self>, MobileError> {
+        let secret: [u8; 32] = secret
+            .try_into()
+            .map_err(|secret: Vec<u8>| MobileError::Invalid(format!("a secret is 32 bytes, not {}", secret.len())))?;
+        Ok(Arc::new(Wallet { key: SigningKey::from_bytes(&secret) }))
+    }
+
+    /// The secret to store, in the keychain or keystore, to open the
+    /// wallet again.
+    pub fn secret(&self) -> Vec<u8> {
+        self.key.to_bytes().to_vec()
+    }
+
+    /// Hex-encoded public key.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// The account the key holds.
+    pub fn address(&self) -> String {
+        Address::from_key(&self.key.verifying_key()).to_string()
+    }
+
+    pub fn sign_transfer(&self, transfer: Transfer) -> Result<SignedTransaction, MobileError> {
+        address::check_account(&transfer.to).map_err(|e| MobileError::Invalid(format!("recipient {}: {}", transfer.to, e)))?;
+        let tx = Transaction {
+            chain_id: transfer.chain_id,
+            nonce: transfer.nonce,
+            to: transfer.to,
+            value: transfer.value,
+            gas_limit: transfer.gas_limit,
+            max_fee_per_gas: transfer.max_fee_per_gas,
+            max_priority_fee_per_gas: transfer.max_priority_fee_per_gas,
+            ..Transaction::default()
+        };
+        self.sign(tx)
+    }
+
+    /// Signs a transaction of any kind, given as a JSON object of its
+    /// fields as `tx_submit` takes them. Fields left out take their
+    /// defaults; signing sets `from`, `hash` and `signature`.
+    pub fn sign_transaction(&self, json: String) -> Result<SignedTransaction, MobileError> {
+        let fields: serde_json::Map<String, Value> =
+            serde_json::from_str(&json).map_err(|e| MobileError::Invalid(format!("transaction: {}", e)))?;
+        let mut draft = json!(Transaction::default());
+        draft.as_object_mut().expect("transactions serialize as objects").extend(fields);
+        let tx: Transaction = serde_json::from_value(draft).map_err(|e| MobileError::Invalid(format!("transaction: {}", e)))?;
+        self.sign(tx)
+    }
+}
+
+impl Wallet {
+    fn sign(&self, tx: Transaction) -> Result<SignedTransaction, MobileError> {
+        let tx = tx.sign(&self.key);
+        let json = serde_json::to_string(&tx).map_err(|e| MobileError::Invalid(format!("transaction: {}", e)))?;
+        Ok(SignedTransaction { hash: tx.hash, json })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_transactions_the_node_accepts() {
+        let wallet = Wallet::generate();
+        let reopened = Wallet::from_secret(wallet.secret()).unwrap();
+        assert_eq!((reopened.address(), reopened.public_key()), (wallet.address(), wallet.public_key()));
+        assert!(matches!(Wallet::from_secret(vec![1; 31]), Err(MobileError::Invalid(e)) if e.contains("not 31")));
+
+        let to = Wallet::generate().address();
+        let transfer = Transfer { chain_id: 7, nonce: 0, to, value: 5, gas_limit: 21_000, max_fee_per_gas: 3, max_priority_fee_per_gas: 1 };
+        let signed = wallet.sign_transfer(transfer.clone()).unwrap();
+        let tx: Transaction = serde_json::from_str(&signed.json).unwrap();
+        tx.verify_signature().unwrap();
+        assert_eq!((tx.hash, tx.from, tx.value), (signed.hash, wallet.address(), 5));
+        assert!(wallet.sign_transfer(Transfer { to: "cubiq1nope".to_string(), ..transfer }).is_err());
+
+        let fields = r#"{"chain_id":7,"nonce":1,"gas_limit":90000,"max_fee_per_gas":3,"kind":"deploy","data":[0,97,115,109]}"#;
+        let deploy: Transaction = serde_json::from_str(&wallet.sign_transaction(fields.to_string()).unwrap().json).unwrap();
+        deploy.verify_signature().unwrap();
+        assert_eq!((deploy.nonce, deploy.data.len()), (1, 4));
+        assert!(wallet.sign_transaction("[]".to_string()).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Mobile SDK: what an iOS or Android app needs to hold keys, sign
+//! transactions and follow Cubiq finality without trusting the node it
+//! talks to, exported to Swift and Kotlin through uniffi.
+//!
+//! - `Wallet` holds an ed25519 key and signs transactions; see `keys`.
+//! - `LightSync` follows finality in short, resumable steps and checks
+//!   accounts against certified state; see `sync`.
+//! - `verify_proof` checks a block finality proof with the mobile
+//!   verifier.
+//!
+//! Bindings are generated from the built library:
+//!
+//! ```text
+//! cargo build -p cubiq-mobile --release
+//! cargo run -p cubiq-mobile --bin uniffi-bindgen -- generate \
+//!     --library target/release/libcubiq_mobile.so --language swift --out-dir bindings
+//! ```
+
+use prover::MobileProofVerifier;
+use std::fmt;
+
+uniffi::setup_scaffolding!();
+
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum MobileError {
+    /// An argument, such as a key, address or transaction, is malformed
+    Invalid(String),
+    /// The node could not be reached, or answered with an error
+    Node(String),
+    /// What the node served does not check out
+    Verification(String),
+}
+
+impl fmt::Display for MobileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MobileError::Invalid(what) => write!(f, "invalid argument: {}", what),
+            MobileError::Node(what) => write!(f, "node error: {}", what),
+            MobileError::Verification(what) => write!(f, "verification failed: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for MobileError {}
+
+impl From<cubiq_client::Error> for MobileError {
+    fn from(e: cubiq_client::Error) -> Self {
+        MobileError::Node(e.to_string())
+    }
+}
+
+impl From<cubiq_light::LightError> for MobileError {
+    fn from(e: cubiq_light::LightError) -> Self {
+        MobileError::Verification(e.to_string())
+    }
+}
+
+/// Whether `proof` is a block finality proof on chain `chain_id` over
+/// `public_inputs`, encoded as a proof bundle's are.
+#[uniffi::export]
+pub fn verify_proof(proof: Vec<u8>, public_inputs: Vec<u8>, chain_id: u64) -> bool {
+    cubiq_light::certificate::verify_proof(&MobileProofVerifier::new(), &proof, &public_inputs, chain_id).is_ok()
+}
+
+pub mod keys;
+pub mod sync;
@@ -0,0 +1,217 @@
+//! Following finality in steps short enough for the few seconds an app
+//! gets in the background, such as an iOS background refresh or an
+//! Android WorkManager job.
+//!
+//! `LightSync::step` asks the node for its finalized head and checks the
+//! newest finality certificate it can get, with at most `attempts`
+//! requests, so one step's work is bounded however far behind the app is.
+//! The light client needs only the newest certified checkpoint to prove
+//! against, not every one before it. `save` gives the client's state for
+//! the app to persist, and `LightSync::restore` picks up from it on the
+//! next launch.
+
+use consensus::state::Account;
+use cubiq_client::Client;
+use cubiq_light::certificate::{Checkpoint, FinalityCertificate};
+use cubiq_light::proofs::{AccountState, ContractRoots, Hash, StateProof};
+use cubiq_light::validators::ValidatorSnapshot;
+use cubiq_light::{LightClient, SavedState};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::keys::SignedTransaction;
+use crate::MobileError;
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct CheckpointInfo {
+    pub height: u64,
+    pub block_hash: String,
+    pub state_root: String,
+    pub transactions_root: String,
+}
+
+impl From<&Checkpoint> for CheckpointInfo {
+    fn from(checkpoint: &Checkpoint) -> Self {
+        CheckpointInfo {
+            height: checkpoint.height,
+            block_hash: checkpoint.block_hash.clone(),
+            state_root: checkpoint.state_root.to_string(),
+            transactions_root: checkpoint.transactions_root.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct SyncProgress {
+    /// The newest certified checkpoint, if any
+    pub head: Option<CheckpointInfo>,
+    /// The node's finalized height
+    pub node_height: u64,
+    /// Whether the head has caught up with the node
+    pub synced: bool,
+}
+
+/// An account as the certified state at `height` holds it.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct VerifiedAccount {
+    pub height: u64,
+    pub balance: u64,
+    pub nonce: u64,
+    pub is_contract: bool,
+    pub is_multisig: bool,
+}
+
+/// What `state_getProof` answers.
+#[derive(Deserialize)]
+struct AccountProof {
+    account: Option<Account>,
+    proof: StateProof,
+}
+
+#[derive(uniffi::Object)]
+pub struct LightSync {
+    node: Client,
+    client: Mutex<LightClient>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl LightSync {
+    /// Follows chain `chain_id`, whose epochs are `epoch_length` blocks,
+    /// through the node at `rpc_url`, trusting `validator_set`, as
+    /// `validator_set` answers it, from its epoch on.
+    #[uniffi::constructor]
+    pub fn new(rpc_url: String, chain_id: u64, epoch_length: u64, validator_set: String) -> Result<Arc<Self>, MobileError> {
+        let snapshot = snapshot(&validator_set)?;
+        let client = LightClient::new(chain_id, epoch_length, snapshot)?;
+        Ok(Arc::new(LightSync { node: Client::new(rpc_url)?, client: Mutex::new(client) }))
+    }
+
+    /// Picks up from what `save` gave, following the node at `rpc_url`.
+    #[uniffi::constructor]
+    pub fn restore(rpc_url: String, saved: String) -> Result<Arc<Self>, MobileError> {
+        let saved: SavedState = serde_json::from_str(&saved).map_err(|e| MobileError::Invalid(format!("saved state: {}", e)))?;
+        let client = LightClient::restore(saved)?;
+        Ok(Arc::new(LightSync { node: Client::new(rpc_url)?, client: Mutex::new(client) }))
+    }
+
+    /// What the light client knows, as JSON for `restore`.
+    pub fn save(&self) -> String {
+        serde_json::to_string(&self.client().save()).expect("saved states encode")
+    }
+
+    /// Trusts a later validator set, as `validator_set` answers it with
+    /// the `epoch` it takes effect at.
+    pub fn add_validator_set(&self, validator_set: String) -> Result<(), MobileError> {
+        let snapshot = snapshot(&validator_set)?;
+        Ok(self.client().add_snapshot(snapshot)?)
+    }
+
+    pub fn head(&self) -> Option<CheckpointInfo> {
+        self.client().head().map(CheckpointInfo::from)
+    }
+
+    /// Certifies the newest finalized block it can, trying heights down
+    /// from the node's finalized head for at most `attempts` requests
+    /// (nodes answer null for blocks whose votes they no longer hold).
+    pub async fn step(&self, attempts: u32) -> Result<SyncProgress, MobileError> {
+        let node_height = self.node.finalized_head().await?.map_or(0, |head| head.height);
+        let known = self.client().head().map_or(0, |head| head.height);
+        for height in (known + 1..=node_height).rev().take(attempts as usize) {
+            let certificate: Option<FinalityCertificate> = self.node.call("chain_getFinalityCertificate", json!([height])).await?;
+            let Some(certificate) = certificate else {
+                continue;
+            };
+            if certificate.checkpoint.height != height {
+                let served = certificate.checkpoint.height;
+                return Err(MobileError::Verification(format!("asked for a certificate at height {}, served one at {}", height, served)));
+            }
+            self.client().verify_certificate(&certificate)?;
+            break;
+        }
+        let head = self.head();
+        let synced = head.as_ref().is_some_and(|head| head.height >= node_height);
+        Ok(SyncProgress { head, node_height, synced })
+    }
+
+    /// The account at `address` in the state certified at the head, or
+    /// an empty one if there is none.
+    pub async fn account(&self, address: String) -> Result<VerifiedAccount, MobileError> {
+        let height = self.head().map(|head| head.height).ok_or_else(|| MobileError::Verification("no checkpoint is certified yet".to_string()))?;
+        let answer: Option<AccountProof> = self.node.call("state_getProof", json!([address, height])).await?;
+        let answer = answer.ok_or_else(|| MobileError::Node(format!("no state proof at height {}", height)))?;
+        let account = answer.account.as_ref().map(account_state);
+        self.client().verify_account(height, &address, account.as_ref(), &answer.proof)?;
+        let account = answer.account.unwrap_or_default();
+        Ok(VerifiedAccount {
+            height,
+            balance: account.balance,
+            nonce: account.nonce,
+            is_contract: account.contract.is_some(),
+            is_multisig: account.multisig.is_some(),
+        })
+    }
+
+    /// Submits a signed transaction, returning its hash.
+    pub async fn submit(&self, tx: SignedTransaction) -> Result<String, MobileError> {
+        let tx: serde_json::Value = serde_json::from_str(&tx.json).map_err(|e| MobileError::Invalid(format!("transaction: {}", e)))?;
+        Ok(self.node.call("tx_submit", json!([tx])).await?)
+    }
+}
+
+impl LightSync {
+    /// Never held across an await.
+    fn client(&self) -> MutexGuard<'_, LightClient> {
+        self.client.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn snapshot(validator_set: &str) -> Result<ValidatorSnapshot, MobileError> {
+    serde_json::from_str(validator_set).map_err(|e| MobileError::Invalid(format!("validator set: {}", e)))
+}
+
+/// What the state trie commits to of `account`.
+fn account_state(account: &Account) -> AccountState {
+    AccountState {
+        balance: account.balance,
+        nonce: account.nonce,
+        contract: account.contract.as_ref().map(|contract| ContractRoots {
+            code_hash: Hash(contract.code_hash().0),
+            storage_root: Hash(contract.storage_root().0),
+        }),
+        multisig_address: account.multisig.as_ref().map(|multisig| Hash(*multisig.address().as_bytes())),
+        bridge_record: account.bridge.as_ref().map(|record| Hash(record.hash().0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::contracts::Contract;
+    use consensus::multisig::Multisig;
+    use consensus::state::StateTrie;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn checks_accounts_of_every_kind_and_restores() {
+        let contract = Contract { code: vec![0, 97, 115, 109], storage: BTreeMap::new() };
+        let multisig = Multisig::new(1, ["cd".repeat(32)]).unwrap();
+        let accounts = BTreeMap::from([
+            ("0xcontract".to_string(), Account { balance: 3, contract: Some(contract), ..Account::default() }),
+            ("0xshared".to_string(), Account { balance: 4, multisig: Some(multisig), ..Account::default() }),
+        ]);
+        let trie = StateTrie::from_accounts(accounts);
+        for address in ["0xcontract", "0xshared"] {
+            let proof: StateProof = serde_json::from_value(serde_json::to_value(trie.prove(address)).unwrap()).unwrap();
+            let root = Hash(trie.root().0);
+            assert!(proof.verify(&root, address, Some(&account_state(&trie.get(address)))), "{address}");
+        }
+
+        let url = "http://127.0.0.1:8545".to_string();
+        assert!(matches!(LightSync::new(url.clone(), 7, 100, "{}".to_string()), Err(MobileError::Invalid(_))));
+        let validator_set = json!({ "validators": [{ "node_id": "v1", "public_key": "00".repeat(32), "stake": 10 }], "supermajority_threshold": 7 });
+        let sync = LightSync::new(url.clone(), 7, 100, validator_set.to_string()).unwrap();
+        let restored = LightSync::restore(url, sync.save()).unwrap();
+        assert_eq!((restored.save(), restored.head()), (sync.save(), None));
+    }
+}
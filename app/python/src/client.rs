@@ -0,0 +1,145 @@
+//! The node's JSON-RPC API, through `cubiq_client::Client` on a runtime
+//! of the binding's own.
+
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+use crate::transaction::{Transaction, Wallet};
+use crate::{invalid, NodeError};
+
+#[pyclass(module = "cubiq", frozen)]
+pub struct Client {
+    inner: cubiq_client::Client,
+    runtime: Runtime,
+}
+
+impl Client {
+    /// Runs `call` to completion with the GIL released.
+    fn block_on<T: Send>(&self, py: Python<'_>, call: impl Future<Output = Result<T, cubiq_client::Error>> + Send) -> PyResult<T> {
+        py.allow_threads(|| self.runtime.block_on(call)).map_err(|e| NodeError::new_err(e.to_string()))
+    }
+
+    fn request(&self, py: Python<'_>, method: &str, params: Value) -> PyResult<PyObject> {
+        let result: Value = self.block_on(py, self.inner.call(method, params))?;
+        Ok(pythonize::pythonize(py, &result).map_err(invalid)?.unbind())
+    }
+}
+
+#[pymethods]
+impl Client {
+    /// A client of the JSON-RPC endpoint at `url`, such as
+    /// `http://127.0.0.1:8545`, sending `token` as a bearer token if given.
+    #[new]
+    #[pyo3(signature = (url, token = None))]
+    fn new(url: String, token: Option<String>) -> PyResult<Self> {
+        let mut inner = cubiq_client::Client::new(url).map_err(|e| NodeError::new_err(e.to_string()))?;
+        if let Some(token) = token {
+            inner = inner.with_token(token);
+        }
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().map_err(|e| NodeError::new_err(e.to_string()))?;
+        Ok(Client { inner, runtime })
+    }
+
+    /// Calls any method, with `params` as a list.
+    #[pyo3(signature = (method, params = None))]
+    fn call(&self, py: Python<'_>, method: &str, params: Option<&Bound<'_, PyList>>) -> PyResult<PyObject> {
+        let params = match params {
+            Some(params) => pythonize::depythonize(params.as_any()).map_err(invalid)?,
+            None => json!([]),
+        };
+        self.request(py, method, params)
+    }
+
+    fn status(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.request(py, "node_status", json!([]))
+    }
+
+    /// A verified block, or None if it is unknown or pruned.
+    fn block(&self, py: Python<'_>, hash: &str) -> PyResult<PyObject> {
+        self.request(py, "chain_getBlock", json!([hash]))
+    }
+
+    /// The finalized block at `height`, counting from 1, or None.
+    fn block_by_height(&self, py: Python<'_>, height: u64) -> PyResult<PyObject> {
+        self.request(py, "chain_getBlockByHeight", json!([height]))
+    }
+
+    fn finalized_head(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.request(py, "chain_getFinalizedHead", json!([]))
+    }
+
+    fn balance(&self, py: Python<'_>, account: &str) -> PyResult<u64> {
+        self.block_on(py, self.inner.balance(account))
+    }
+
+    /// The nonce `account`'s next transaction takes, counting those pending.
+    fn nonce(&self, py: Python<'_>, account: &str) -> PyResult<u64> {
+        self.block_on(py, self.inner.nonce(account))
+    }
+
+    fn fee_estimate(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.request(py, "fee_estimate", json!([]))
+    }
+
+    fn validators(&self, py: Python<'_>) -> PyResult<PyObject> {
+        self.request(py, "validator_set", json!([]))
+    }
+
+    /// Submits a signed transaction, returning its hash.
+    fn submit(&self, py: Python<'_>, tx: &Transaction) -> PyResult<String> {
+        self.block_on(py, self.inner.submit(&tx.inner))
+    }
+
+    /// Signs a transfer of `value` from `wallet` to `to` and submits it,
+    /// returning its hash. The nonce, chain id and fees are the node's:
+    /// its next nonce for the wallet and its `fee_estimate`.
+    fn transfer(&self, py: Python<'_>, wallet: &Wallet, to: String, value: u64) -> PyResult<String> {
+        let from = wallet.address();
+        let (chain_id, nonce, fees) = self.block_on(py, async {
+            let status = self.inner.status().await?;
+            let nonce = self.inner.nonce(&from).await?;
+            Ok((status.chain_id, nonce, self.inner.fee_estimate().await?))
+        })?;
+        let tx = Transaction::transfer(to, value, chain_id, nonce, fees.max_fee_per_gas, fees.max_priority_fee_per_gas, consensus::execution::TRANSFER_GAS)?;
+        self.submit(py, &tx.sign(wallet))
+    }
+
+    fn receipt(&self, py: Python<'_>, tx_hash: &str) -> PyResult<PyObject> {
+        self.request(py, "tx_getReceipt", json!([tx_hash]))
+    }
+
+    /// Polls for `tx_hash`'s receipt every `interval` seconds, returning
+    /// None if there is none after `timeout`.
+    #[pyo3(signature = (tx_hash, timeout = 60.0, interval = 1.0))]
+    fn wait_for_receipt(&self, py: Python<'_>, tx_hash: &str, timeout: f64, interval: f64) -> PyResult<PyObject> {
+        let deadline = Instant::now() + Duration::try_from_secs_f64(timeout).map_err(invalid)?;
+        let interval = Duration::try_from_secs_f64(interval).map_err(invalid)?;
+        let receipt: Value = self.block_on(py, async {
+            loop {
+                let receipt: Value = self.inner.call("tx_getReceipt", json!([tx_hash])).await?;
+                if !receipt.is_null() || Instant::now() + interval > deadline {
+                    return Ok(receipt);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })?;
+        Ok(pythonize::pythonize(py, &receipt).map_err(invalid)?.unbind())
+    }
+
+    /// Logs matching `filter`, as `logs_get` takes it.
+    fn logs(&self, py: Python<'_>, filter: &Bound<'_, PyDict>) -> PyResult<PyObject> {
+        let filter: Value = pythonize::depythonize(filter.as_any()).map_err(invalid)?;
+        self.request(py, "logs_get", json!([filter]))
+    }
+
+    /// A page of `address`'s finalized transactions, newest first, from
+    /// before the cursor `before` when given.
+    #[pyo3(signature = (address, before = None, limit = None))]
+    fn transactions_by_address(&self, py: Python<'_>, address: &str, before: Option<u64>, limit: Option<usize>) -> PyResult<PyObject> {
+        self.request(py, "index_getTransactionsByAddress", json!([address, { "cursor": before, "limit": limit }]))
+    }
+}
@@ -0,0 +1,70 @@
+//! Python bindings, the `cubiq` module: the node's RPC client, keys and
+//! transactions, and the proof verifier, for scripting against Cubiq.
+//!
+//! ```python
+//! import cubiq
+//!
+//! node = cubiq.Client("http://127.0.0.1:8545")
+//! wallet = cubiq.Wallet.generate()
+//! tx_hash = node.transfer(wallet, "cubiq1...", 5)
+//! receipt = node.wait_for_receipt(tx_hash, timeout=30)
+//! ```
+//!
+//! Built as a wheel with maturin (`maturin build --release` in this
+//! directory). Calls block, with the GIL released, until the node
+//! answers. Results are the node's JSON as dicts, lists and ints; errors
+//! raise `CubiqError` subclasses, or `ValueError` for malformed
+//! arguments.
+
+use prover::MobileProofVerifier;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use zkurl::resolver::ProofBundle;
+
+use client::Client;
+use transaction::{Transaction, Wallet};
+
+create_exception!(cubiq, CubiqError, PyException, "Base of the errors Cubiq calls raise.");
+create_exception!(cubiq, NodeError, CubiqError, "The node could not be reached, or answered with an error.");
+create_exception!(cubiq, VerificationError, CubiqError, "A proof or signature does not check out.");
+
+pub(crate) fn invalid(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Whether `proof` is a block finality proof on chain `chain_id` over
+/// `public_inputs`, encoded as a proof bundle's are.
+#[pyfunction]
+fn verify_proof(py: Python<'_>, proof: &[u8], public_inputs: &[u8], chain_id: u64) -> bool {
+    py.allow_threads(|| cubiq_light::certificate::verify_proof(&MobileProofVerifier::new(), proof, public_inputs, chain_id).is_ok())
+}
+
+/// Whether a proof bundle, as a zkURL resolves to, proves its public
+/// inputs on chain `chain_id`. The prover's signature is not checked.
+#[pyfunction]
+fn verify_bundle(py: Python<'_>, bundle: &Bound<'_, PyDict>, chain_id: u64) -> PyResult<bool> {
+    let bundle: ProofBundle = pythonize::depythonize(bundle.as_any()).map_err(invalid)?;
+    let public_inputs = bundle.public_inputs.transcript_bytes();
+    Ok(verify_proof(py, &bundle.proof, &public_inputs, chain_id))
+}
+
+#[pymodule]
+#[pyo3(name = "cubiq")]
+fn cubiq(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add("CubiqError", py.get_type::<CubiqError>())?;
+    m.add("NodeError", py.get_type::<NodeError>())?;
+    m.add("VerificationError", py.get_type::<VerificationError>())?;
+    m.add_class::<Client>()?;
+    m.add_class::<Transaction>()?;
+    m.add_class::<Wallet>()?;
+    m.add_function(wrap_pyfunction!(verify_proof, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_bundle, m)?)?;
+    Ok(())
+}
+
+pub mod client;
+pub mod transaction;
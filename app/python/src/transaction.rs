@@ -0,0 +1,177 @@
+//! Keys and transactions.
+//!
+//! A `Transaction` is built from keyword arguments named as the node's
+//! JSON names its fields, with those left out taking their defaults, or
+//! with `Transaction.transfer`; `sign` fills in `from`, `hash` and
+//! `signature`. A `Wallet` is one ed25519 key, whose 32-byte `secret`
+//! the caller keeps.
+
+use consensus::address::{self, Address};
+use consensus::execution::TRANSFER_GAS;
+use ed25519_dalek::SigningKey;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use rand::rngs::OsRng;
+use serde_json::{json, Map, Value};
+
+use crate::{invalid, VerificationError};
+
+/// A transaction with `fields` set and the rest defaulted.
+fn draft(fields: Map<String, Value>) -> Result<consensus::Transaction, String> {
+    let mut draft = json!(consensus::Transaction::default());
+    draft.as_object_mut().expect("transactions serialize as objects").extend(fields);
+    serde_json::from_value(draft).map_err(|e| format!("transaction: {}", e))
+}
+
+#[pyclass(module = "cubiq", frozen)]
+pub struct Wallet {
+    key: SigningKey,
+}
+
+#[pymethods]
+impl Wallet {
+    /// A wallet with a new key from the OS's random number generator.
+    #[staticmethod]
+    fn generate() -> Self {
+        Wallet { key: SigningKey::generate(&mut OsRng) }
+    }
+
+    /// The wallet whose `secret` this is.
+    #[staticmethod]
+    fn from_secret(secret: &[u8]) -> PyResult<Self> {
+        let secret: [u8; 32] = secret.try_into().map_err(|_| invalid(format!("a secret is 32 bytes, not {}", secret.len())))?;
+        Ok(Wallet { key: SigningKey::from_bytes(&secret) })
+    }
+
+    #[getter]
+    fn secret<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.key.to_bytes())
+    }
+
+    /// Hex-encoded public key.
+    #[getter]
+    fn public_key(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// The account the key holds.
+    #[getter]
+    pub(crate) fn address(&self) -> String {
+        Address::from_key(&self.key.verifying_key()).to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Wallet({})", self.address())
+    }
+}
+
+#[pyclass(module = "cubiq", frozen)]
+#[derive(Clone)]
+pub struct Transaction {
+    pub(crate) inner: consensus::Transaction,
+}
+
+#[pymethods]
+impl Transaction {
+    #[new]
+    #[pyo3(signature = (**fields))]
+    fn new(fields: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let fields: Map<String, Value> = match fields {
+            Some(fields) => pythonize::depythonize(fields.as_any()).map_err(invalid)?,
+            None => Map::new(),
+        };
+        Ok(Transaction { inner: draft(fields).map_err(invalid)? })
+    }
+
+    /// A transfer of `value` to the account `to`.
+    #[staticmethod]
+    #[pyo3(signature = (to, value, *, chain_id, nonce, max_fee_per_gas, max_priority_fee_per_gas, gas_limit = TRANSFER_GAS))]
+    pub(crate) fn transfer(
+        to: String,
+        value: u64,
+        chain_id: u64,
+        nonce: u64,
+        max_fee_per_gas: u64,
+        max_priority_fee_per_gas: u64,
+        gas_limit: u64,
+    ) -> PyResult<Self> {
+        address::check_account(&to).map_err(|e| invalid(format!("recipient {}: {}", to, e)))?;
+        let inner = consensus::Transaction {
+            chain_id,
+            nonce,
+            to,
+            value,
+            gas_limit,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            ..consensus::Transaction::default()
+        };
+        Ok(Transaction { inner })
+    }
+
+    /// A copy signed by `wallet`.
+    pub(crate) fn sign(&self, wallet: &Wallet) -> Self {
+        Transaction { inner: self.inner.clone().sign(&wallet.key) }
+    }
+
+    /// Raises `VerificationError` unless the transaction is signed as the
+    /// node checks on submission.
+    fn verify(&self) -> PyResult<()> {
+        self.inner.verify_signature().map_err(VerificationError::new_err)
+    }
+
+    /// Empty until signed.
+    #[getter]
+    fn hash(&self) -> &str {
+        &self.inner.hash
+    }
+
+    /// The account the transaction is from; empty until signed.
+    #[getter]
+    fn sender(&self) -> &str {
+        &self.inner.from
+    }
+
+    #[getter]
+    fn to(&self) -> &str {
+        &self.inner.to
+    }
+
+    #[getter]
+    fn value(&self) -> u64 {
+        self.inner.value
+    }
+
+    #[getter]
+    fn nonce(&self) -> u64 {
+        self.inner.nonce
+    }
+
+    /// The transaction as the node's JSON encodes it.
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        Ok(pythonize::pythonize(py, &self.inner).map_err(invalid)?.unbind())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Transaction(hash={:?}, from={:?}, to={:?}, value={})", self.inner.hash, self.inner.from, self.inner.to, self.inner.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drafts_take_defaults_for_fields_left_out() {
+        let fields = json!({ "chain_id": 7, "kind": "deploy", "data": [0, 97, 115, 109] });
+        let tx = draft(fields.as_object().unwrap().clone()).unwrap();
+        assert_eq!((tx.chain_id, tx.data.len(), tx.value, tx.signature.as_str()), (7, 4, 0, ""));
+
+        let wallet = Wallet::generate();
+        let signed = Transaction { inner: tx }.sign(&wallet);
+        signed.verify().unwrap();
+        assert_eq!(signed.sender(), wallet.address());
+        assert!(draft(json!({ "value": "lots" }).as_object().unwrap().clone()).is_err());
+        assert!(Transaction::transfer("cubiq1nope".to_string(), 1, 7, 0, 3, 1, TRANSFER_GAS).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! HTTP API.
+//!
+//! | Route                           | Purpose                                         |
+//! |---------------------------------|-------------------------------------------------|
+//! | `PUT`/`POST /proof/{proof_id}`  | Upload a signed bundle                          |
+//! | `GET /proof/{proof_id}`         | The bundle, as `ProofServer` serves it          |
+//! | `GET /proofs`                   | Every proof id                                  |
+//! | `GET /block/{block_hash}`       | Entries of the bundles proving a block          |
+//! | `GET /zkurl?url={zkurl}`        | Entry of the bundle a zkURL names               |
+//! | `POST /proof/{proof_id}/report` | A validator's verdict on the proof              |
+//! | `GET /provers`                  | Every prover's reliability, most reliable first |
+//! | `GET /provers/{prover_id}`      | One prover's reliability                        |
+//!
+//! Uploads are what `ProofPublisher` sends to an endpoint, in any bundle
+//! encoding, so a prover publishes to the relay like to its own server;
+//! the `GET` routes are what the resolver fetches, so validators list the
+//! relay as a fallback endpoint. A new upload answers `201 Created` with
+//! the bundle's entry, a repeated one `200 OK`.
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::sync::Arc;
+use zkurl::codec::{decode_bundle, BundleEncoding};
+use zkurl::download::DEFAULT_MAX_BODY_BYTES;
+use zkurl::server::ProofServer;
+use zkurl::ZkURL;
+
+use crate::index::{RelayIndex, UploadError};
+use crate::scores::{ReportError, Scores};
+
+pub struct Relay {
+    pub index: RelayIndex,
+    pub scores: Scores,
+}
+
+pub fn router(relay: Arc<Relay>) -> Router {
+    let api = Router::new()
+        .route("/proof/:proof_id", put(upload).post(upload))
+        .layer(DefaultBodyLimit::max(DEFAULT_MAX_BODY_BYTES as usize))
+        .route("/proof/:proof_id/report", post(report))
+        .route("/block/:block_hash", get(for_block))
+        .route("/zkurl", get(lookup))
+        .route("/provers", get(provers))
+        .route("/provers/:prover_id", get(prover))
+        .with_state(Arc::clone(&relay));
+    ProofServer::new(relay.index.store()).router().merge(api)
+}
+
+fn error(status: StatusCode, message: impl ToString) -> Response {
+    (status, message.to_string()).into_response()
+}
+
+async fn upload(State(relay): State<Arc<Relay>>, Path(proof_id): Path<String>, headers: HeaderMap, body: Bytes) -> Response {
+    let encoding = BundleEncoding::from_content_type(headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()));
+    let bundle = match decode_bundle(&body, encoding) {
+        Ok(bundle) => bundle,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    match relay.index.accept(&proof_id, bundle, now) {
+        Ok((entry, true)) => {
+            if let Err(e) = relay.scores.uploaded(&entry.prover_id) {
+                tracing::warn!(error = %e, "cannot save prover scores");
+            }
+            tracing::info!(proof_id, prover = entry.prover_id, block = entry.block_hash, "bundle accepted");
+            (StatusCode::CREATED, Json(entry)).into_response()
+        }
+        Ok((entry, false)) => Json(entry).into_response(),
+        Err(e @ UploadError::UnknownProver(_)) => error(StatusCode::FORBIDDEN, e),
+        Err(e @ UploadError::BadSignature) => error(StatusCode::UNAUTHORIZED, e),
+        Err(e @ UploadError::Invalid(_)) => error(StatusCode::UNPROCESSABLE_ENTITY, e),
+        Err(e @ UploadError::Conflict) => error(StatusCode::CONFLICT, e),
+        Err(e @ UploadError::Storage(_)) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn report(
+    State(relay): State<Arc<Relay>>,
+    Path(proof_id): Path<String>,
+    Json(report): Json<crate::scores::Report>,
+) -> Response {
+    let Some(entry) = relay.index.get(&proof_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    match relay.scores.report(&entry, &report) {
+        Ok(reliability) => Json(reliability).into_response(),
+        Err(e @ ReportError::UnknownValidator(_)) => error(StatusCode::FORBIDDEN, e),
+        Err(e @ ReportError::BadSignature) => error(StatusCode::UNAUTHORIZED, e),
+        Err(e @ ReportError::Duplicate) => error(StatusCode::CONFLICT, e),
+        Err(e @ ReportError::Storage(_)) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+async fn for_block(State(relay): State<Arc<Relay>>, Path(block_hash): Path<String>) -> Response {
+    Json(relay.index.for_block(&block_hash)).into_response()
+}
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    url: String,
+}
+
+async fn lookup(State(relay): State<Arc<Relay>>, Query(query): Query<LookupQuery>) -> Response {
+    let zkurl = match query.url.parse::<ZkURL>() {
+        Ok(zkurl) => zkurl,
+        Err(e) => return error(StatusCode::BAD_REQUEST, e),
+    };
+    match relay.index.lookup(&zkurl) {
+        Some(entry) => Json(entry).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn provers(State(relay): State<Arc<Relay>>) -> Response {
+    Json(relay.scores.all()).into_response()
+}
+
+async fn prover(State(relay): State<Arc<Relay>>, Path(prover_id): Path<String>) -> Response {
+    match relay.scores.get(&prover_id) {
+        Some(reliability) => Json(reliability).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::{key, provers, signed};
+    use crate::index::Entry;
+    use crate::scores::{report_signing_bytes, Reliability, Report};
+    use ed25519_dalek::Signer;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+    use zkurl::codec::encode_bundle;
+    use zkurl::store::MemoryProofStore;
+
+    async fn spawn_relay() -> String {
+        let index = RelayIndex::open(Arc::new(MemoryProofStore::default()), provers()).unwrap();
+        let scores = Scores::open(HashMap::from([("v1".to_string(), key(11).verifying_key())]), None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(Arc::new(Relay { index, scores }))).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn takes_uploads_serves_them_and_scores_provers() {
+        let base = spawn_relay().await;
+        let client = reqwest::Client::new();
+        let bundle = signed("alice", &key(1), "0xb1", vec![7; 64]);
+        let cbor = encode_bundle(&bundle, BundleEncoding::Cbor).unwrap();
+        let upload = || client.put(format!("{}/proof/p1", base)).header("content-type", "application/cbor").body(cbor.clone());
+
+        let created = upload().send().await.unwrap();
+        assert_eq!(created.status().as_u16(), 201);
+        let entry: Entry = serde_json::from_slice(&created.bytes().await.unwrap()).unwrap();
+        assert_eq!(upload().send().await.unwrap().status().as_u16(), 200);
+        let forged = encode_bundle(&signed("bob", &key(1), "0xb1", vec![7; 64]), BundleEncoding::Json).unwrap();
+        let refused = client.post(format!("{}/proof/p2", base)).body(forged).send().await.unwrap();
+        assert_eq!(refused.status().as_u16(), 401);
+
+        let served = client.get(format!("{}/proof/p1", base)).header("accept", "application/json").send().await.unwrap();
+        let served: zkurl::resolver::ProofBundle = served.json().await.unwrap();
+        assert_eq!((served.proof, served.signature), (bundle.proof.clone(), bundle.signature.clone()));
+        let ids: Vec<String> = client.get(format!("{}/proofs", base)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(ids, ["p1"]);
+        let for_block: serde_json::Value = client.get(format!("{}/block/0xb1", base)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(for_block[0]["proof_id"], "p1");
+        let found = client.get(format!("{}/zkurl", base)).query(&[("url", entry.zkurl.as_str())]).send().await.unwrap();
+        assert_eq!(found.json::<serde_json::Value>().await.unwrap()["checksum"], entry.checksum.as_str());
+
+        let signature = key(11).sign(&report_signing_bytes("p1", &entry.checksum, true));
+        let verdict = Report { validator_id: "v1".to_string(), valid: true, signature: hex::encode(signature.to_bytes()) };
+        let reported = client.post(format!("{}/proof/p1/report", base)).json(&verdict).send().await.unwrap();
+        assert_eq!(reported.json::<Reliability>().await.unwrap().verified, 1);
+        let again = client.post(format!("{}/proof/p1/report", base)).json(&verdict).send().await.unwrap();
+        assert_eq!(again.status().as_u16(), 409);
+        let unknown = client.post(format!("{}/proof/p9/report", base)).json(&verdict).send().await.unwrap();
+        assert_eq!(unknown.status().as_u16(), 404);
+
+        let alice: Reliability = client.get(format!("{}/provers/alice", base)).send().await.unwrap().json().await.unwrap();
+        assert_eq!((alice.uploads, alice.verified, alice.reliability), (1, 1, 2.0 / 3.0));
+        let all: Vec<Reliability> = client.get(format!("{}/provers", base)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(all, [alice]);
+    }
+}
@@ -0,0 +1,300 @@
+//! Bundles the relay holds.
+//!
+//! Bundles live in a `ProofStore` under their proof id, the layout
+//! `ProofServer` serves, and are indexed by the block they prove and by
+//! content address: the CID of their JSON encoding, the raw block peers
+//! fetch over Bitswap. Each bundle gets a content-addressed zkURL naming
+//! its prover, which is what the relay announces; a zkURL naming the
+//! relay (or any host) finds a bundle by proof id instead, and must agree
+//! with it on prover and checksum.
+//!
+//! Only bundles signed by a known prover are taken. A proof id is taken
+//! once: uploading the same bundle again is a no-op, a different one is
+//! refused.
+
+use ed25519_dalek::VerifyingKey;
+use networking::bitswap::MemoryBlockStore;
+use networking::{NetworkMessage, ProofAnnouncement};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+use zkurl::codec::{encode_bundle, BundleEncoding};
+use zkurl::host::raw_block_cid;
+use zkurl::p2p::AvailabilityHints;
+use zkurl::resolver::{ProofBundle, MAX_PROOF_BYTES};
+use zkurl::store::ProofStore;
+use zkurl::{ZkURL, ZkURLError};
+
+/// What the relay knows of a bundle without loading it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub proof_id: String,
+    pub prover_id: String,
+    pub block_hash: String,
+    /// Content-addressed zkURL of the bundle
+    pub zkurl: String,
+    /// blake3 hex of the proof bytes, as zkURL `checksum` hints carry it
+    pub checksum: String,
+    /// When the prover signed the bundle
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadError {
+    UnknownProver(String),
+    BadSignature,
+    Invalid(String),
+    /// The proof id holds a different bundle
+    Conflict,
+    Storage(ZkURLError),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::UnknownProver(prover_id) => write!(f, "unknown prover {:?}", prover_id),
+            UploadError::BadSignature => write!(f, "bundle is not signed by its prover"),
+            UploadError::Invalid(reason) => write!(f, "invalid bundle: {}", reason),
+            UploadError::Conflict => write!(f, "proof id already holds a different bundle"),
+            UploadError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+/// Where accepted bundles are served and announced over libp2p.
+pub struct Announcer {
+    pub blocks: MemoryBlockStore,
+    pub gossip: mpsc::UnboundedSender<NetworkMessage>,
+    pub peer_id: String,
+}
+
+#[derive(Default)]
+struct Indexes {
+    entries: BTreeMap<String, Entry>,
+    by_block: BTreeMap<String, BTreeSet<String>>,
+    /// CID to proof id
+    by_cid: HashMap<String, String>,
+}
+
+pub struct RelayIndex {
+    store: Arc<dyn ProofStore>,
+    provers: HashMap<String, VerifyingKey>,
+    indexes: RwLock<Indexes>,
+    announcer: Option<Announcer>,
+}
+
+impl RelayIndex {
+    /// Indexes what `store` already holds, taking uploads from `provers`.
+    pub fn open(store: Arc<dyn ProofStore>, provers: HashMap<String, VerifyingKey>) -> Result<Self, ZkURLError> {
+        let index = Self { store, provers, indexes: RwLock::default(), announcer: None };
+        for proof_id in index.store.proof_ids() {
+            let Some(bundle) = index.store.get(&proof_id) else {
+                continue;
+            };
+            let (entry, _) = index.entry(&proof_id, &bundle)?;
+            index.insert(entry);
+        }
+        Ok(index)
+    }
+
+    /// Serves every bundle over Bitswap from now on, and announces new
+    /// ones on the proofs topic.
+    pub fn with_announcer(mut self, announcer: Announcer) -> Self {
+        for proof_id in self.store.proof_ids() {
+            if let Some(bundle) = self.store.get(&proof_id) {
+                if let Err(e) = self.provide(&announcer, &bundle) {
+                    tracing::warn!(proof_id, error = %e, "cannot serve bundle over bitswap");
+                }
+            }
+        }
+        self.announcer = Some(announcer);
+        self
+    }
+
+    pub fn store(&self) -> Arc<dyn ProofStore> {
+        Arc::clone(&self.store)
+    }
+
+    /// Takes `bundle` as `proof_id` if its prover signed it; returns its
+    /// entry and whether it is new. Bundles signed after `now` are refused.
+    pub fn accept(&self, proof_id: &str, bundle: ProofBundle, now: u64) -> Result<(Entry, bool), UploadError> {
+        let key = self
+            .provers
+            .get(&bundle.prover_id)
+            .ok_or_else(|| UploadError::UnknownProver(bundle.prover_id.clone()))?;
+        if !bundle.verify_signature(key) {
+            return Err(UploadError::BadSignature);
+        }
+        if bundle.proof.len() > MAX_PROOF_BYTES {
+            return Err(UploadError::Invalid(format!("proof exceeds {} bytes", MAX_PROOF_BYTES)));
+        }
+        if bundle.timestamp > now {
+            return Err(UploadError::Invalid("signed in the future".to_string()));
+        }
+        let (entry, block) = self.entry(proof_id, &bundle).map_err(|e| UploadError::Invalid(e.to_string()))?;
+
+        if let Some(held) = self.get(proof_id) {
+            return if held == entry { Ok((held, false)) } else { Err(UploadError::Conflict) };
+        }
+        self.store.put(proof_id, bundle).map_err(UploadError::Storage)?;
+        self.insert(entry.clone());
+
+        if let Some(announcer) = &self.announcer {
+            if let Err(e) = self.announce(announcer, &entry, block) {
+                tracing::warn!(proof_id, error = %e, "cannot announce bundle");
+            }
+        }
+        Ok((entry, true))
+    }
+
+    pub fn get(&self, proof_id: &str) -> Option<Entry> {
+        self.indexes.read().expect("Relay index lock poisoned").entries.get(proof_id).cloned()
+    }
+
+    /// Bundles proving `block_hash`, by proof id.
+    pub fn for_block(&self, block_hash: &str) -> Vec<Entry> {
+        let indexes = self.indexes.read().expect("Relay index lock poisoned");
+        let Some(proof_ids) = indexes.by_block.get(block_hash) else {
+            return Vec::new();
+        };
+        proof_ids.iter().filter_map(|proof_id| indexes.entries.get(proof_id).cloned()).collect()
+    }
+
+    /// The bundle `zkurl` names: by CID when content-addressed, otherwise
+    /// by proof id, provided its prover and checksum hints match.
+    pub fn lookup(&self, zkurl: &ZkURL) -> Option<Entry> {
+        let entry = match zkurl.host.as_cid() {
+            Some(cid) => {
+                let indexes = self.indexes.read().expect("Relay index lock poisoned");
+                let proof_id = indexes.by_cid.get(&cid.to_string())?;
+                indexes.entries.get(proof_id).cloned()?
+            }
+            None => self.get(&zkurl.proof_id)?,
+        };
+        let checksum = zkurl.metadata.as_ref().and_then(|metadata| metadata.checksum.as_deref());
+        let matches = zkurl.prover_id.as_deref().is_none_or(|prover_id| prover_id == entry.prover_id)
+            && checksum.is_none_or(|checksum| checksum.eq_ignore_ascii_case(&entry.checksum));
+        matches.then_some(entry)
+    }
+
+    /// The bundle's entry and its JSON block.
+    fn entry(&self, proof_id: &str, bundle: &ProofBundle) -> Result<(Entry, Vec<u8>), ZkURLError> {
+        let block = encode_bundle(bundle, BundleEncoding::Json)?;
+        let zkurl = ZkURL::builder()
+            .prover_id(&bundle.prover_id)
+            .proof_id(proof_id)
+            .ipfs_cid(raw_block_cid(&block).to_string())
+            .build()?;
+        let entry = Entry {
+            proof_id: proof_id.to_string(),
+            prover_id: bundle.prover_id.clone(),
+            block_hash: bundle.public_inputs.block_hash.clone(),
+            zkurl: zkurl.to_string(),
+            checksum: blake3::hash(&bundle.proof).to_hex().to_string(),
+            timestamp: bundle.timestamp,
+        };
+        Ok((entry, block))
+    }
+
+    fn insert(&self, entry: Entry) {
+        let cid = entry.zkurl.parse::<ZkURL>().ok().and_then(|zkurl| zkurl.host.as_cid().map(ToString::to_string));
+        let mut indexes = self.indexes.write().expect("Relay index lock poisoned");
+        indexes.by_block.entry(entry.block_hash.clone()).or_default().insert(entry.proof_id.clone());
+        if let Some(cid) = cid {
+            indexes.by_cid.insert(cid, entry.proof_id.clone());
+        }
+        indexes.entries.insert(entry.proof_id.clone(), entry);
+    }
+
+    fn provide(&self, announcer: &Announcer, bundle: &ProofBundle) -> Result<(), String> {
+        let block = encode_bundle(bundle, BundleEncoding::Json).map_err(|e| e.to_string())?;
+        announcer.blocks.provide(&raw_block_cid(&block), block);
+        Ok(())
+    }
+
+    fn announce(&self, announcer: &Announcer, entry: &Entry, block: Vec<u8>) -> Result<(), String> {
+        let zkurl = entry.zkurl.parse::<ZkURL>().map_err(|e| e.to_string())?;
+        announcer.blocks.provide(&raw_block_cid(&block), block);
+        let hints = AvailabilityHints { bitswap_providers: vec![announcer.peer_id.clone()] };
+        announcer
+            .gossip
+            .send(NetworkMessage::ProofAnnouncement(ProofAnnouncement { zkurl, hints }))
+            .map_err(|_| "networking stopped".to_string())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use zkurl::resolver::{ProofMetadata, PublicInputs};
+    use zkurl::store::MemoryProofStore;
+    use zkurl::ZkURLMetadata;
+
+    pub(crate) fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    /// A bundle for `block_hash` signed by `prover_id`'s key.
+    pub(crate) fn signed(prover_id: &str, key: &SigningKey, block_hash: &str, proof: Vec<u8>) -> ProofBundle {
+        let mut bundle = ProofBundle {
+            proof,
+            public_inputs: PublicInputs {
+                block_hash: block_hash.to_string(),
+                state_root: "0xdef".to_string(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: prover_id.to_string(),
+            timestamp: 100,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: 0 },
+        };
+        bundle.signature = hex::encode(key.sign(&bundle.signing_bytes()).to_bytes());
+        bundle
+    }
+
+    pub(crate) fn provers() -> HashMap<String, VerifyingKey> {
+        HashMap::from([("alice".to_string(), key(1).verifying_key()), ("bob".to_string(), key(2).verifying_key())])
+    }
+
+    #[test]
+    fn accepts_signed_bundles_once_and_indexes_them() {
+        let store: Arc<dyn ProofStore> = Arc::new(MemoryProofStore::default());
+        let index = RelayIndex::open(Arc::clone(&store), provers()).unwrap();
+
+        let bundle = signed("alice", &key(1), "0xb1", vec![1; 32]);
+        let (entry, new) = index.accept("p1", bundle.clone(), 100).unwrap();
+        assert!(new);
+        assert_eq!(index.accept("p1", bundle.clone(), 100), Ok((entry.clone(), false)));
+        let other = signed("alice", &key(1), "0xb1", vec![2; 32]);
+        assert_eq!(index.accept("p1", other.clone(), 100), Err(UploadError::Conflict));
+        index.accept("p2", other, 100).unwrap();
+
+        assert_eq!(index.accept("p3", signed("carol", &key(3), "0xb1", vec![3]), 100), Err(UploadError::UnknownProver("carol".to_string())));
+        assert_eq!(index.accept("p3", signed("bob", &key(1), "0xb1", vec![3]), 100), Err(UploadError::BadSignature));
+        assert!(matches!(index.accept("p3", bundle.clone(), 99), Err(UploadError::Invalid(_))));
+        assert!(matches!(index.accept("not/an/id", bundle, 100), Err(UploadError::Invalid(_))));
+
+        let for_block: Vec<String> = index.for_block("0xb1").into_iter().map(|entry| entry.proof_id).collect();
+        assert_eq!(for_block, ["p1", "p2"]);
+        assert!(index.for_block("0xb2").is_empty());
+
+        let by_cid = entry.zkurl.parse::<ZkURL>().unwrap();
+        assert_eq!(index.lookup(&by_cid), Some(entry.clone()));
+        let hosted = ZkURL::builder().prover_id("alice").domain("relay.example.com").proof_id("p1");
+        assert_eq!(index.lookup(&hosted.clone().build().unwrap()), Some(entry.clone()));
+        let wrong_checksum = ZkURLMetadata { checksum: Some("00".repeat(32)), ..ZkURLMetadata::new("v1", None, "stark") };
+        assert_eq!(index.lookup(&hosted.metadata(wrong_checksum).build().unwrap()), None);
+        let wrong_prover = ZkURL::builder().prover_id("bob").domain("relay.example.com").proof_id("p1").build().unwrap();
+        assert_eq!(index.lookup(&wrong_prover), None);
+
+        let reopened = RelayIndex::open(store, provers()).unwrap();
+        assert_eq!(reopened.get("p1"), Some(entry));
+        assert_eq!(reopened.for_block("0xb1").len(), 2);
+    }
+}
@@ -0,0 +1,121 @@
+//! `proof-relay`: a marketplace between provers and validators.
+//!
+//! Provers upload signed bundles (see `index`); validators fetch them over
+//! HTTP (see `http`) or Bitswap, report whether they verified, and query
+//! how reliable each prover has been (see `scores`).
+
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use ed25519_dalek::VerifyingKey;
+use networking::{Multiaddr, NetworkConfig, P2PNetworking, PROOFS_TOPIC};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
+use zkurl::store::{DiskProofStore, MemoryProofStore, ProofStore, RetentionPolicy};
+
+mod http;
+mod index;
+mod scores;
+
+use index::{Announcer, RelayIndex};
+use scores::Scores;
+
+#[derive(Debug, Parser)]
+#[command(name = "proof-relay", version, about = "Relays signed proof bundles from provers to validators")]
+struct Cli {
+    /// HTTP API bind address
+    #[arg(long, env = "CUBIQ_RELAY_HTTP_ADDR", default_value = "0.0.0.0:8650")]
+    http_addr: SocketAddr,
+
+    /// Where bundles and prover scores are kept; in memory when unset
+    #[arg(long, env = "CUBIQ_RELAY_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Prover whose bundles are taken, as `<prover id>=<hex ed25519 key>`;
+    /// repeat for several
+    #[arg(long = "prover", value_parser = named_key)]
+    provers: Vec<(String, VerifyingKey)>,
+
+    /// Validator whose reports count, as `<node id>=<hex ed25519 key>`;
+    /// repeat for several
+    #[arg(long = "validator", value_parser = named_key)]
+    validators: Vec<(String, VerifyingKey)>,
+
+    /// Multiaddr to serve bundles over Bitswap on; repeat for several.
+    /// Without one the relay is HTTP only
+    #[arg(long = "listen-addr")]
+    listen_addrs: Vec<String>,
+
+    /// Peer multiaddr dialled at startup; repeat for several
+    #[arg(long = "bootnode")]
+    bootnodes: Vec<String>,
+
+    /// Log filter, e.g. `info` or `warn,proof_relay=debug`
+    #[arg(long, env = "CUBIQ_LOG", default_value = "info")]
+    log_level: String,
+}
+
+/// Parses `<id>=<hex ed25519 public key>`.
+fn named_key(arg: &str) -> Result<(String, VerifyingKey), String> {
+    let (id, key) = arg.split_once('=').ok_or("expected <id>=<hex public key>")?;
+    let key: [u8; 32] = hex::decode(key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("public key is not 32 hex-encoded bytes")?;
+    let key = VerifyingKey::from_bytes(&key).map_err(|e| format!("public key: {}", e))?;
+    Ok((id.to_string(), key))
+}
+
+fn multiaddrs(addrs: &[String]) -> Result<Vec<Multiaddr>> {
+    addrs
+        .iter()
+        .map(|addr| addr.parse().map_err(|e| anyhow!("{:?}: {}", addr, e)))
+        .collect()
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt().with_env_filter(EnvFilter::try_new(&cli.log_level)?).init();
+
+    let store: Arc<dyn ProofStore> = match &cli.data_dir {
+        Some(dir) => Arc::new(DiskProofStore::open(dir.join("proofs"), RetentionPolicy::default()).context("open proof store")?),
+        None => Arc::new(MemoryProofStore::default()),
+    };
+    let provers: HashMap<String, VerifyingKey> = cli.provers.into_iter().collect();
+    if provers.is_empty() {
+        tracing::warn!("no --prover given; every upload will be refused");
+    }
+    let mut index = RelayIndex::open(store, provers).context("index stored bundles")?;
+    let scores_path = cli.data_dir.as_ref().map(|dir| dir.join("scores.json"));
+    let scores = Scores::open(cli.validators.into_iter().collect(), scores_path).map_err(|e| anyhow!("prover scores: {}", e))?;
+
+    if !cli.listen_addrs.is_empty() {
+        let network = P2PNetworking::with_config(NetworkConfig {
+            listen_addrs: multiaddrs(&cli.listen_addrs).context("--listen-addr")?,
+            bootnodes: multiaddrs(&cli.bootnodes).context("--bootnode")?,
+            topics: vec![PROOFS_TOPIC],
+            ..NetworkConfig::default()
+        })
+        .await?;
+        index = index.with_announcer(Announcer {
+            blocks: network.block_store(),
+            gossip: network.sender.clone(),
+            peer_id: network.local_peer_id(),
+        });
+        tokio::spawn(async move {
+            if let Err(e) = network.run().await {
+                tracing::error!(error = %e, "networking stopped");
+            }
+        });
+    }
+
+    let relay = Arc::new(http::Relay { index, scores });
+    let listener = TcpListener::bind(cli.http_addr).await.with_context(|| format!("bind {}", cli.http_addr))?;
+    tracing::info!(http = %cli.http_addr, "proof relay started");
+    axum::serve(listener, http::router(relay)).await?;
+    Ok(())
+}
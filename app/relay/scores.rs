@@ -0,0 +1,235 @@
+//! Prover reliability.
+//!
+//! Validators that fetch a bundle through the relay report whether its
+//! proof verified, signing the report with their validator key. Each
+//! validator's first report on a proof counts; the relay itself never
+//! judges proofs, and bundles with bad signatures count against no one,
+//! since anyone can claim a prover's id.
+//!
+//! A prover's reliability is `(verified + 1) / (verified + failed + 2)`:
+//! one half with no reports, approaching the share of reports that
+//! verified as they accumulate.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::index::Entry;
+
+/// Prefix of the bytes a report signs.
+pub const REPORT_DOMAIN: &[u8] = b"cubiq-proof-report-v1";
+
+/// A validator's verdict on a proof.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub validator_id: String,
+    pub valid: bool,
+    /// Hex-encoded ed25519 signature over `report_signing_bytes`
+    pub signature: String,
+}
+
+/// Bytes a report on the proof with blake3 hex digest `checksum` signs.
+pub fn report_signing_bytes(proof_id: &str, checksum: &str, valid: bool) -> Vec<u8> {
+    let mut out = REPORT_DOMAIN.to_vec();
+    for field in [proof_id, checksum] {
+        out.extend_from_slice(&(field.len() as u64).to_le_bytes());
+        out.extend_from_slice(field.as_bytes());
+    }
+    out.push(valid as u8);
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportError {
+    UnknownValidator(String),
+    BadSignature,
+    /// The validator already reported on the proof
+    Duplicate,
+    Storage(String),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::UnknownValidator(validator_id) => write!(f, "unknown validator {:?}", validator_id),
+            ReportError::BadSignature => write!(f, "report is not signed by its validator"),
+            ReportError::Duplicate => write!(f, "validator already reported on this proof"),
+            ReportError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Tally {
+    uploads: u64,
+    verified: u64,
+    failed: u64,
+}
+
+/// A prover's record, as validators query it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reliability {
+    pub prover_id: String,
+    pub uploads: u64,
+    pub verified: u64,
+    pub failed: u64,
+    pub reliability: f64,
+}
+
+impl Reliability {
+    fn new(prover_id: &str, tally: Tally) -> Self {
+        Self {
+            prover_id: prover_id.to_string(),
+            uploads: tally.uploads,
+            verified: tally.verified,
+            failed: tally.failed,
+            reliability: (tally.verified + 1) as f64 / (tally.verified + tally.failed + 2) as f64,
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    provers: BTreeMap<String, Tally>,
+    /// Proof id to the validators that reported on it
+    reports: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+pub struct Scores {
+    validators: HashMap<String, VerifyingKey>,
+    /// Saved after every change when set
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+}
+
+impl Scores {
+    /// Scores from `path`, if it exists, counting reports from `validators`.
+    pub fn open(validators: HashMap<String, VerifyingKey>, path: Option<PathBuf>) -> Result<Self, String> {
+        let state = match &path {
+            Some(path) => match std::fs::read(path) {
+                Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| format!("parse {}: {}", path.display(), e))?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+                Err(e) => return Err(format!("read {}: {}", path.display(), e)),
+            },
+            None => State::default(),
+        };
+        Ok(Self { validators, path, state: Mutex::new(state) })
+    }
+
+    pub fn uploaded(&self, prover_id: &str) -> Result<(), String> {
+        let mut state = self.state.lock().expect("Scores lock poisoned");
+        state.provers.entry(prover_id.to_string()).or_default().uploads += 1;
+        self.save(&state)
+    }
+
+    /// Counts `report` on `entry`'s proof; returns the prover's record.
+    pub fn report(&self, entry: &Entry, report: &Report) -> Result<Reliability, ReportError> {
+        let key = self
+            .validators
+            .get(&report.validator_id)
+            .ok_or_else(|| ReportError::UnknownValidator(report.validator_id.clone()))?;
+        let signature = hex::decode(&report.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or(ReportError::BadSignature)?;
+        key.verify_strict(&report_signing_bytes(&entry.proof_id, &entry.checksum, report.valid), &signature)
+            .map_err(|_| ReportError::BadSignature)?;
+
+        let mut state = self.state.lock().expect("Scores lock poisoned");
+        let reporters = state.reports.entry(entry.proof_id.clone()).or_default();
+        if reporters.contains_key(&report.validator_id) {
+            return Err(ReportError::Duplicate);
+        }
+        reporters.insert(report.validator_id.clone(), report.valid);
+        let tally = state.provers.entry(entry.prover_id.clone()).or_default();
+        if report.valid {
+            tally.verified += 1;
+        } else {
+            tally.failed += 1;
+        }
+        let reliability = Reliability::new(&entry.prover_id, *tally);
+        self.save(&state).map_err(ReportError::Storage)?;
+        Ok(reliability)
+    }
+
+    pub fn get(&self, prover_id: &str) -> Option<Reliability> {
+        let state = self.state.lock().expect("Scores lock poisoned");
+        state.provers.get(prover_id).map(|tally| Reliability::new(prover_id, *tally))
+    }
+
+    /// Every prover with uploads or reports, most reliable first.
+    pub fn all(&self) -> Vec<Reliability> {
+        let state = self.state.lock().expect("Scores lock poisoned");
+        let mut all: Vec<Reliability> = state.provers.iter().map(|(prover_id, tally)| Reliability::new(prover_id, *tally)).collect();
+        all.sort_by(|a, b| b.reliability.total_cmp(&a.reliability).then_with(|| a.prover_id.cmp(&b.prover_id)));
+        all
+    }
+
+    fn save(&self, state: &State) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec(state).map_err(|e| e.to_string())?;
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|e| format!("write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::tests::key;
+    use ed25519_dalek::Signer;
+
+    fn report(validator_id: &str, seed: u8, entry: &Entry, valid: bool) -> Report {
+        let signature = key(seed).sign(&report_signing_bytes(&entry.proof_id, &entry.checksum, valid));
+        Report { validator_id: validator_id.to_string(), valid, signature: hex::encode(signature.to_bytes()) }
+    }
+
+    #[test]
+    fn counts_each_validators_first_signed_report() {
+        let dir = std::env::temp_dir().join(format!("relay-scores-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scores.json");
+        let validators = HashMap::from([("v1".to_string(), key(11).verifying_key()), ("v2".to_string(), key(12).verifying_key())]);
+        let scores = Scores::open(validators.clone(), Some(path.clone())).unwrap();
+        let entry = |proof_id: &str, prover_id: &str| Entry {
+            proof_id: proof_id.to_string(),
+            prover_id: prover_id.to_string(),
+            block_hash: "0xb1".to_string(),
+            zkurl: String::new(),
+            checksum: "ab".repeat(32),
+            timestamp: 0,
+        };
+        let (good, bad) = (entry("p1", "alice"), entry("p2", "bob"));
+
+        scores.uploaded("alice").unwrap();
+        assert_eq!(scores.get("alice").unwrap().reliability, 0.5);
+        assert_eq!(scores.report(&good, &report("v1", 11, &good, true)).unwrap().reliability, 2.0 / 3.0);
+        assert_eq!(scores.report(&good, &report("v1", 11, &good, true)), Err(ReportError::Duplicate));
+        scores.report(&good, &report("v2", 12, &good, true)).unwrap();
+        scores.report(&bad, &report("v1", 11, &bad, false)).unwrap();
+
+        assert_eq!(scores.report(&bad, &report("v3", 11, &bad, false)), Err(ReportError::UnknownValidator("v3".to_string())));
+        assert_eq!(scores.report(&bad, &report("v2", 11, &bad, false)), Err(ReportError::BadSignature));
+        // A signature over the other verdict
+        let flipped = Report { valid: true, ..report("v2", 12, &bad, false) };
+        assert_eq!(scores.report(&bad, &flipped), Err(ReportError::BadSignature));
+
+        let ranked: Vec<(String, u64, u64, u64)> =
+            scores.all().into_iter().map(|r| (r.prover_id, r.uploads, r.verified, r.failed)).collect();
+        assert_eq!(ranked, [("alice".to_string(), 1, 2, 0), ("bob".to_string(), 0, 0, 1)]);
+
+        let reopened = Scores::open(validators, Some(path)).unwrap();
+        assert_eq!(reopened.get("alice"), scores.get("alice"));
+        assert_eq!(reopened.report(&good, &report("v2", 12, &good, true)), Err(ReportError::Duplicate));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Who may call which JSON-RPC methods, and how often.
+//!
+//! Methods are safe (chain queries, transaction submission) or unsafe
+//! (anything about how the node itself runs: `admin_*`). Unsafe methods
+//! need the bearer token from `rpc.auth_token_file`, or, when no token is
+//! configured, a caller on this machine. Every call draws from a token
+//! bucket per caller IP, and browsers may only call from the origins in
+//! `rpc.cors_origins`.
+//!
+//! A reverse proxy on this machine makes every caller look local, so
+//! without a token it would hand out unsafe methods to anyone. Listing the
+//! proxy in `rpc.trusted_proxies` makes the caller the client it names in
+//! `X-Forwarded-For` instead; a request through it that names none is
+//! treated as remote.
+
+use anyhow::{bail, Context, Result};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, ORIGIN};
+use axum::http::{HeaderMap, HeaderValue, Method};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Instant;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::RpcConfig;
+use crate::rpc_server::{error, RpcError};
+
+/// Method prefixes that need authorization.
+pub const UNSAFE_PREFIXES: &[&str] = &["admin_"];
+
+pub const UNAUTHORIZED: i64 = -32001;
+pub const LIMIT_EXCEEDED: i64 = -32005;
+
+/// Callers tracked at most; buckets that have refilled go first, then the
+/// least recently used.
+const MAX_TRACKED_IPS: usize = 10_000;
+
+pub fn is_unsafe(method: &str) -> bool {
+    UNSAFE_PREFIXES.iter().any(|prefix| method.starts_with(prefix))
+}
+
+/// Where a request came from.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub ip: IpAddr,
+    /// From an `Authorization: Bearer` header
+    pub token: Option<String>,
+}
+
+pub struct Policy {
+    token: Option<String>,
+    origins: Vec<String>,
+    limiter: Option<Mutex<RateLimiter>>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl Policy {
+    /// A policy with no rate limit when `requests_per_second` is 0.
+    pub fn new(token: Option<String>, origins: Vec<String>, requests_per_second: u32, burst: u32) -> Self {
+        let limiter = (requests_per_second > 0).then(|| Mutex::new(RateLimiter::new(requests_per_second, burst)));
+        Self { token, origins, limiter, trusted_proxies: vec![] }
+    }
+
+    /// Takes callers through `proxies` to be the clients they forward for.
+    pub fn with_trusted_proxies(mut self, proxies: Vec<IpAddr>) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    /// Who a request from `addr` is from.
+    pub fn caller(&self, addr: SocketAddr, headers: &HeaderMap) -> Caller {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let mut ip = addr.ip();
+        if self.trusted_proxies.contains(&ip) {
+            // The nearest hop that isn't one of our proxies; hops before
+            // it are only the client's word
+            let hops: Option<Vec<IpAddr>> = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .map(|value| value.to_str().ok())
+                .collect::<Option<Vec<_>>>()
+                .and_then(|values| values.iter().flat_map(|value| value.split(',')).map(|hop| hop.trim().parse().ok()).collect());
+            ip = hops
+                .and_then(|hops| hops.into_iter().rev().find(|hop| !self.trusted_proxies.contains(hop)))
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        }
+        Caller { ip, token }
+    }
+
+    pub fn from_config(config: &RpcConfig) -> Result<Self> {
+        let token = match &config.auth_token_file {
+            Some(path) => {
+                let token = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+                if token.trim().is_empty() {
+                    bail!("{} is empty", path.display());
+                }
+                Some(token.trim().to_string())
+            }
+            None => None,
+        };
+        let policy = Self::new(token, config.cors_origins.clone(), config.requests_per_second, config.request_burst);
+        Ok(policy.with_trusted_proxies(config.trusted_proxies.clone()))
+    }
+
+    /// Admits one call of `method`, or says why not.
+    pub fn permit(&self, caller: &Caller, method: &str) -> Result<(), RpcError> {
+        if let Some(limiter) = &self.limiter {
+            if !limiter.lock().expect("Rate limiter lock poisoned").take(caller.ip, Instant::now()) {
+                return Err(error(LIMIT_EXCEEDED, "rate limit exceeded; slow down"));
+            }
+        }
+        if !is_unsafe(method) {
+            return Ok(());
+        }
+        let authorized = match (&self.token, &caller.token) {
+            (Some(expected), Some(given)) => constant_time_eq(expected.as_bytes(), given.as_bytes()),
+            (Some(_), None) => false,
+            (None, _) => caller.ip.is_loopback(),
+        };
+        if authorized {
+            Ok(())
+        } else if self.token.is_some() {
+            Err(error(UNAUTHORIZED, format!("{} needs a bearer token", method)))
+        } else {
+            Err(error(UNAUTHORIZED, format!("{} is only served locally; set rpc.auth_token_file to allow remote calls", method)))
+        }
+    }
+
+    /// CORS headers for the HTTP endpoint.
+    pub fn cors(&self) -> CorsLayer {
+        let layer = CorsLayer::new().allow_methods([Method::GET, Method::POST]).allow_headers([CONTENT_TYPE, AUTHORIZATION]);
+        if self.origins.iter().any(|origin| origin == "*") {
+            layer.allow_origin(AllowOrigin::any())
+        } else {
+            layer.allow_origin(self.origins.iter().filter_map(|origin| origin.parse::<HeaderValue>().ok()).collect::<Vec<_>>())
+        }
+    }
+
+    /// Whether a WebSocket handshake may proceed. Browsers always send
+    /// `Origin`; other clients need not.
+    pub fn allows_origin(&self, headers: &HeaderMap) -> bool {
+        match headers.get(ORIGIN).and_then(|value| value.to_str().ok()) {
+            Some(origin) => self.origins.iter().any(|allowed| allowed == "*" || allowed == origin),
+            None => true,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, rate: f64, burst: f64) -> f64 {
+        (self.tokens + now.saturating_duration_since(self.updated).as_secs_f64() * rate).min(burst)
+    }
+}
+
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: u32, burst: u32) -> Self {
+        Self { rate: requests_per_second as f64, burst: burst as f64, buckets: HashMap::new() }
+    }
+
+    fn take(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let (rate, burst) = (self.rate, self.burst);
+        if self.buckets.len() >= MAX_TRACKED_IPS && !self.buckets.contains_key(&ip) {
+            // A full bucket is the same as no bucket
+            self.buckets.retain(|_, bucket| bucket.refilled(now, rate, burst) < burst);
+            if self.buckets.len() >= MAX_TRACKED_IPS {
+                if let Some(idlest) = self.buckets.iter().min_by_key(|(_, bucket)| bucket.updated).map(|(ip, _)| *ip) {
+                    self.buckets.remove(&idlest);
+                }
+            }
+        }
+        let bucket = self.buckets.entry(ip).or_insert(Bucket { tokens: burst, updated: now });
+        let tokens = bucket.refilled(now, rate, burst);
+        let admitted = tokens >= 1.0;
+        *bucket = Bucket { tokens: if admitted { tokens - 1.0 } else { tokens }, updated: now };
+        admitted
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn caller(ip: [u8; 4], token: Option<&str>) -> Caller {
+        Caller { ip: IpAddr::from(ip), token: token.map(str::to_string) }
+    }
+
+    #[test]
+    fn unsafe_methods_need_a_token_or_a_local_caller() {
+        let local = Policy::new(None, vec![], 0, 0);
+        assert!(local.permit(&caller([127, 0, 0, 1], None), "admin_peers").is_ok());
+        assert!(local.permit(&caller([10, 0, 0, 1], None), "admin_peers").is_err());
+        assert!(local.permit(&caller([10, 0, 0, 1], None), "chain_getBlock").is_ok());
+        assert!(local.permit(&caller([10, 0, 0, 1], None), "validator_set").is_ok());
+
+        let tokened = Policy::new(Some("s3cret".to_string()), vec![], 0, 0);
+        assert!(tokened.permit(&caller([10, 0, 0, 1], Some("s3cret")), "admin_peers").is_ok());
+        assert!(tokened.permit(&caller([127, 0, 0, 1], None), "admin_peers").is_err());
+        let wrong = tokened.permit(&caller([10, 0, 0, 1], Some("guess")), "admin_peers").unwrap_err();
+        assert_eq!(wrong.code, UNAUTHORIZED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer s3cret"));
+        assert_eq!(tokened.caller("10.0.0.1:5000".parse().unwrap(), &headers).token.as_deref(), Some("s3cret"));
+        headers.insert(ORIGIN, HeaderValue::from_static("https://evil.example"));
+        assert!(!tokened.allows_origin(&headers));
+    }
+
+    #[test]
+    fn callers_through_a_trusted_proxy_are_the_clients_it_names() {
+        let proxied = Policy::new(None, vec![], 0, 0).with_trusted_proxies(vec![IpAddr::from([127, 0, 0, 1])]);
+        let via_proxy = |forwarded: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(forwarded) = forwarded {
+                headers.insert("x-forwarded-for", HeaderValue::from_str(forwarded).unwrap());
+            }
+            proxied.caller("127.0.0.1:5000".parse().unwrap(), &headers).ip
+        };
+        assert_eq!(via_proxy(Some("127.0.0.1, 203.0.113.9, 127.0.0.1")), IpAddr::from([203, 0, 113, 9]));
+        assert_eq!(via_proxy(None), IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(via_proxy(Some("not an ip")), IpAddr::from([0, 0, 0, 0]));
+        let remote = Caller { ip: via_proxy(Some("203.0.113.9")), token: None };
+        assert!(proxied.permit(&remote, "admin_peers").is_err());
+        // Only the proxies listed are believed
+        let direct = proxied.caller("198.51.100.1:5000".parse().unwrap(), &HeaderMap::new());
+        assert_eq!(direct.ip, IpAddr::from([198, 51, 100, 1]));
+    }
+
+    #[test]
+    fn buckets_allow_bursts_then_refill_at_the_sustained_rate() {
+        let mut limiter = RateLimiter::new(2, 3);
+        let (ip, other) = (IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2]));
+        let start = Instant::now();
+        assert_eq!((0..4).filter(|_| limiter.take(ip, start)).count(), 3);
+        assert!(limiter.take(other, start));
+        assert!(limiter.take(ip, start + Duration::from_millis(500)));
+        assert!(!limiter.take(ip, start + Duration::from_millis(600)));
+        assert_eq!((0..5).filter(|_| limiter.take(ip, start + Duration::from_secs(60))).count(), 3);
+
+        let mut limiter = RateLimiter::new(1, 5);
+        for n in 0..=MAX_TRACKED_IPS as u32 {
+            limiter.take(IpAddr::from(n.to_be_bytes()), start);
+        }
+        assert_eq!(limiter.buckets.len(), MAX_TRACKED_IPS);
+    }
+}
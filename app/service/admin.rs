@@ -0,0 +1,255 @@
+//! Operator controls, served as `admin_*` JSON-RPC methods to callers
+//! `access` authorizes:
+//!
+//! | method                | params        | result                                    |
+//! |-----------------------|---------------|-------------------------------------------|
+//! | `admin_peers`         | `[]`          | known, static and banned peers            |
+//! | `admin_addPeer`       | `[multiaddr]` | peer id; the peer is redialled when lost  |
+//! | `admin_removePeer`    | `[peer_id]`   | whether it was a static peer              |
+//! | `admin_banPeer`       | `[peer_id]`   | null; the peer is disconnected            |
+//! | `admin_unbanPeer`     | `[peer_id]`   | whether it was banned                     |
+//! | `admin_setLogLevel`   | `[filter]`    | null                                      |
+//! | `admin_setLogFormat`  | `[format]`    | null; `text`, `pretty` or `json`          |
+//! | `admin_snapshot`      | `[]`          | `{path, height}` of the written snapshot  |
+//! | `admin_pauseVoting`   | `[]`          | whether voting was already paused         |
+//! | `admin_resumeVoting`  | `[]`          | whether voting was paused                 |
+//! | `admin_dumpConsensus` | `[]`          | heights, votes, pending and recent blocks |
+//! | `admin_faults`        | `[]`          | faults being injected                     |
+//! | `admin_setFaults`     | `[faults]`    | faults injected before                    |
+//!
+//! The fault methods are only served by builds with the `chaos` feature,
+//! for devnets and resilience tests; see `consensus::faults`.
+//!
+//! Nodes do not propose blocks; provers do. Pausing stops this node's part
+//! in producing them: it keeps verifying and following proposals but
+//! casts no votes. Static peers, bans and the log filter and format last
+//! until the node restarts, the log filter and format only until
+//! `node.log_level` and `log.format` are reloaded.
+
+use anyhow::Context;
+use consensus::state::{Account, Hash};
+use consensus::QubeNode;
+use networking::{Multiaddr, PeerControl};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing_subscriber::EnvFilter;
+
+use crate::logging::{LogFormat, LogHandle};
+use crate::rpc_server::{error, param, to_value, Backend, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND, SERVER_ERROR};
+
+/// Directory under the data dir that `admin_snapshot` writes to.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Blocks listed by `admin_dumpConsensus`, newest last.
+const DUMP_BLOCKS: usize = 32;
+
+/// The parts of the running node admin methods act on, besides consensus.
+pub struct Admin {
+    pub peers: PeerControl,
+    pub log: LogHandle,
+    pub snapshot_dir: PathBuf,
+}
+
+impl Admin {
+    pub fn new(peers: PeerControl, log: LogHandle, data_dir: &Path) -> Self {
+        Self { peers, log, snapshot_dir: data_dir.join(SNAPSHOTS_DIR) }
+    }
+}
+
+/// Whether `method` is an admin method.
+pub fn handles(method: &str) -> bool {
+    method.starts_with("admin_")
+}
+
+pub async fn call(backend: &Backend, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    let (consensus, admin) = (&*backend.consensus, &backend.admin);
+    let network = |e: anyhow::Error| error(SERVER_ERROR, e.to_string());
+    match method {
+        "admin_peers" => to_value(admin.peers.peers().await.map_err(network)?),
+        "admin_addPeer" => {
+            let addr: String = param(params, 0, "multiaddr")?;
+            let addr: Multiaddr =
+                addr.parse().map_err(|e| error(INVALID_PARAMS, format!("{} is not a multiaddr: {}", addr, e)))?;
+            admin.peers.add_static(addr).await.map(Value::String).map_err(network)
+        }
+        "admin_removePeer" => {
+            let peer_id: String = param(params, 0, "peer_id")?;
+            admin.peers.remove_static(&peer_id).await.map(Value::Bool).map_err(network)
+        }
+        "admin_banPeer" => {
+            let peer_id: String = param(params, 0, "peer_id")?;
+            admin.peers.ban(&peer_id).await.map_err(network)?;
+            tracing::warn!(peer = %peer_id, "peer banned by operator");
+            Ok(Value::Null)
+        }
+        "admin_unbanPeer" => {
+            let peer_id: String = param(params, 0, "peer_id")?;
+            admin.peers.unban(&peer_id).await.map(Value::Bool).map_err(network)
+        }
+        "admin_setLogLevel" => {
+            let filter: String = param(params, 0, "filter")?;
+            let parsed = EnvFilter::try_new(&filter).map_err(|e| error(INVALID_PARAMS, format!("bad filter {}: {}", filter, e)))?;
+            admin.log.set_filter(parsed).map_err(|e| error(SERVER_ERROR, e.to_string()))?;
+            tracing::info!(filter = %filter, "log filter changed by operator");
+            Ok(Value::Null)
+        }
+        "admin_setLogFormat" => {
+            let format: LogFormat = param(params, 0, "format")?;
+            admin.log.set_format(format).map_err(|e| error(SERVER_ERROR, format!("{:#}", e)))?;
+            tracing::info!(format = %format, "log format changed by operator");
+            Ok(Value::Null)
+        }
+        "admin_snapshot" => {
+            let snapshot = Snapshot::take(consensus).await;
+            let path = snapshot.write(&admin.snapshot_dir).map_err(|e| error(SERVER_ERROR, format!("{:#}", e)))?;
+            tracing::info!(path = %path.display(), height = snapshot.height, "state snapshot written");
+            Ok(json!({ "path": path, "height": snapshot.height }))
+        }
+        "admin_pauseVoting" | "admin_resumeVoting" => {
+            let pause = method == "admin_pauseVoting";
+            let was_paused = consensus.set_paused(pause);
+            if pause != was_paused {
+                tracing::warn!(paused = pause, "voting changed by operator");
+            }
+            Ok(Value::Bool(was_paused))
+        }
+        "admin_dumpConsensus" => Ok(dump(consensus).await),
+        #[cfg(feature = "chaos")]
+        "admin_faults" => to_value(consensus.faults().config()),
+        #[cfg(feature = "chaos")]
+        "admin_setFaults" => {
+            let faults: consensus::faults::FaultConfig = param(params, 0, "faults")?;
+            let before = consensus.faults().set(faults).map_err(|e| error(INVALID_PARAMS, e))?;
+            tracing::warn!(?faults, "fault injection changed by operator");
+            to_value(before)
+        }
+        _ => Err(error(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+/// Account state and finalized history at one finalized height.
+#[derive(Debug, Serialize)]
+struct Snapshot {
+    chain_id: u64,
+    height: u64,
+    taken_at: u64,
+    finalized_blocks: Vec<String>,
+    state_root: Hash,
+    accounts: BTreeMap<String, Account>,
+    validators: Vec<consensus::Validator>,
+}
+
+impl Snapshot {
+    async fn take(consensus: &QubeNode) -> Self {
+        let mut validators: Vec<_> = consensus.validator_set.read().await.validators.values().cloned().collect();
+        validators.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        let state = consensus.consensus_state.read().await;
+        Self {
+            chain_id: consensus.chain_id,
+            height: state.finalized_blocks.len() as u64,
+            taken_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            finalized_blocks: state.finalized_blocks.clone(),
+            state_root: state.accounts.root(),
+            accounts: state.accounts.accounts(),
+            validators,
+        }
+    }
+
+    /// Writes `state-<height>-<unix time>.json` into `dir`, atomically.
+    fn write(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let path = dir.join(format!("state-{}-{}.json", self.height, self.taken_at));
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?).with_context(|| format!("writing {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("replacing {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+async fn dump(consensus: &QubeNode) -> Value {
+    let state = consensus.consensus_state.read().await;
+    let mut votes: Vec<Value> = state
+        .group_votes_by_block()
+        .into_iter()
+        .map(|(block_hash, votes)| {
+            let mut voters: Vec<&str> = votes.iter().map(|vote| vote.voter_id.as_str()).collect();
+            voters.sort();
+            json!({
+                "block_hash": block_hash,
+                "voters": voters,
+                "stake": votes.iter().map(|vote| vote.stake).sum::<u64>(),
+            })
+        })
+        .collect();
+    votes.sort_by(|a, b| a["block_hash"].as_str().cmp(&b["block_hash"].as_str()));
+    let skip = |len: usize| len.saturating_sub(DUMP_BLOCKS);
+    json!({
+        "node_id": consensus.node_id,
+        "voting": consensus.voting,
+        "paused": consensus.is_paused(),
+        "current_height": state.current_height,
+        "current_round": state.current_round,
+        "finalized_height": state.finalized_blocks.len(),
+        "finalized_blocks": state.finalized_blocks[skip(state.finalized_blocks.len())..],
+        "recent_blocks": state.recent_blocks.iter().skip(skip(state.recent_blocks.len())).map(|block| block.header()).collect::<Vec<_>>(),
+        "votes": votes,
+        "pending_transactions": state.mempool.best().iter().map(|tx| &tx.hash).collect::<Vec<_>>(),
+        "receipts": state.receipts.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::NodeRole;
+    use crate::status::NodeInfo;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use networking::{P2PNetworking, PeerCount};
+    use std::sync::Arc;
+
+    async fn backend(data_dir: &Path) -> Backend {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator { node_id: "v1".to_string(), public_key: "11".repeat(32), stake: 10, registration: None };
+        node.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        let log = LogHandle::detached();
+        let peers = P2PNetworking::new().await.unwrap().peer_control();
+        Backend {
+            consensus: Arc::new(node),
+            info: NodeInfo::new(NodeRole::Validator, PeerCount::default(), false),
+            admin: Admin::new(peers, log, data_dir),
+            metrics: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn pauses_voting_and_dumps_consensus_state() {
+        let backend = backend(Path::new("unused")).await;
+        assert_eq!(call(&backend, "admin_pauseVoting", &[]).await.unwrap(), false);
+        assert_eq!(call(&backend, "admin_pauseVoting", &[]).await.unwrap(), true);
+        let dump = call(&backend, "admin_dumpConsensus", &[]).await.unwrap();
+        assert_eq!((dump["paused"].as_bool(), dump["finalized_height"].as_u64()), (Some(true), Some(0)));
+        assert_eq!(call(&backend, "admin_resumeVoting", &[]).await.unwrap(), true);
+        assert!(!backend.consensus.is_paused());
+
+        let bad = call(&backend, "admin_setLogLevel", &[json!("consensus=loud")]).await.unwrap_err();
+        assert_eq!(bad.code, INVALID_PARAMS);
+        assert_eq!(call(&backend, "admin_addPeer", &[json!("not an addr")]).await.unwrap_err().code, INVALID_PARAMS);
+        assert_eq!(call(&backend, "admin_restart", &[]).await.unwrap_err().code, METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn writes_snapshots_under_the_data_dir() {
+        let dir = std::env::temp_dir().join(format!("cubiq-admin-{}", std::process::id()));
+        let backend = backend(&dir).await;
+        let written = call(&backend, "admin_snapshot", &[]).await.unwrap();
+        assert_eq!(written["height"], 0);
+        let path = PathBuf::from(written["path"].as_str().unwrap());
+        assert!(path.starts_with(dir.join(SNAPSHOTS_DIR)));
+        let snapshot: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!((snapshot["chain_id"].as_u64(), snapshot["validators"][0]["node_id"].as_str()), (Some(7), Some("v1")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
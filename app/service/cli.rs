@@ -0,0 +1,765 @@
+//! Command-line interface of the `cubiq` binary.
+
+use clap::{Args, Parser, Subcommand};
+use consensus::address::Address;
+use consensus::index::DEFAULT_PAGE;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::keys::{DerivationPath, ACCOUNT_PATH, NETWORK_KEY, VALIDATOR_KEY};
+use crate::keystore::{Kdf, KeyType};
+use crate::logging::LogFormat;
+use crate::role::NodeRole;
+
+/// JSON-RPC endpoint of a local node.
+pub const DEFAULT_RPC_URL: &str = "http://127.0.0.1:8545";
+
+#[derive(Debug, Parser)]
+#[command(name = "cubiq", version, about = "Cubiq node and operator tools")]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Flags accepted by every subcommand.
+#[derive(Debug, Args)]
+pub struct GlobalArgs {
+    /// Node configuration file [default: <data-dir>/config.toml]
+    #[arg(long, global = true, env = "CUBIQ_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Directory holding keys and chain data
+    #[arg(long, global = true, env = "CUBIQ_DATA_DIR", default_value_os_t = default_data_dir())]
+    pub data_dir: PathBuf,
+
+    /// Log filter, e.g. `info` or `warn,consensus=debug` [default: `node.log_level`
+    /// for `run`, else info]
+    #[arg(long, global = true, env = "CUBIQ_LOG")]
+    pub log_level: Option<String>,
+
+    /// Log output format [default: `log.format` for `run`, else text]
+    #[arg(long, global = true, env = "CUBIQ_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+impl GlobalArgs {
+    pub fn config_path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(|| self.data_dir.join("config.toml"))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the node
+    Run(RunArgs),
+    /// Create the data directory, config file, chain spec and validator key
+    Init(InitArgs),
+    /// Maintain the data directory
+    #[command(subcommand)]
+    Db(DbCommand),
+    /// Export or import the state at a finalized height
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// Re-execute stored blocks, re-verify their proofs and compare the
+    /// results with the recorded roots; the node must be stopped
+    Replay {
+        /// First height to replay
+        #[arg(long)]
+        from: u64,
+
+        /// Last height to replay [default: the newest]
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Only re-execute blocks, without fetching their proofs
+        #[arg(long)]
+        skip_proofs: bool,
+
+        /// Print each replayed block as a JSON line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Manage node keys
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Validator operations
+    #[command(subcommand)]
+    Validator(ValidatorCommand),
+    /// Build and submit transactions
+    #[command(subcommand)]
+    Tx(TxCommand),
+    /// An account's balance and history, transfers, staking and multisigs
+    #[command(subcommand)]
+    Wallet(WalletCommand),
+    /// Collect diagnostics for bug reports
+    #[command(subcommand)]
+    Debug(DebugCommand),
+}
+
+/// Flags overriding `config.toml` for one run.
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Subsystems to run
+    #[arg(long, value_enum)]
+    pub role: Option<NodeRole>,
+
+    /// Identifier this node votes as
+    #[arg(long)]
+    pub node_id: Option<String>,
+
+    /// Stake this node votes with
+    #[arg(long)]
+    pub stake: Option<u64>,
+
+    /// Multiaddr to listen on; repeat for several
+    #[arg(long = "listen-addr")]
+    pub listen_addrs: Vec<String>,
+
+    /// Peer multiaddr dialled at startup; repeat for several
+    #[arg(long = "bootnode")]
+    pub bootnodes: Vec<String>,
+
+    /// Fallback proof endpoint; repeat for several
+    #[arg(long = "resolver-endpoint")]
+    pub resolver_endpoints: Vec<String>,
+
+    /// JSON-RPC HTTP bind address
+    #[arg(long)]
+    pub rpc_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    /// Chain id of a new single-validator development chain
+    #[arg(long, default_value_t = consensus::genesis::DEV_CHAIN_ID, conflicts_with = "genesis")]
+    pub chain_id: u64,
+
+    /// Join an existing chain by copying its chain spec instead
+    #[arg(long)]
+    pub genesis: Option<PathBuf>,
+
+    /// Replace an existing config file, chain spec and validator key
+    #[arg(long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Upgrade the data directory layout, as `run` does on startup
+    Migrate {
+        /// List pending migrations without running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Verify the data directory, as `run` does on startup
+    Check {
+        /// Repair what can be repaired, as `run` does
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Show the size of the block store and its column families
+    Stats {
+        /// Print the stats as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotCommand {
+    /// Write the accounts and block hashes at a finalized height to an
+    /// archive; the node must be stopped
+    Export {
+        /// Finalized height [default: the newest]
+        #[arg(long)]
+        height: Option<u64>,
+
+        /// Archive to write [default: <data-dir>/snapshots/snapshot-<chain id>-<height>.snap]
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Verify an archive and start an empty data dir from it
+    Import {
+        /// Archive to read
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// Generate a key under <data-dir>/keys, encrypted with a password
+    #[command(alias = "generate")]
+    Create {
+        /// Key file name, without extension
+        #[arg(long, default_value = VALIDATOR_KEY)]
+        name: String,
+
+        #[command(flatten)]
+        key: NewKeyArgs,
+    },
+    /// Encrypt an existing secret key into <data-dir>/keys
+    Import {
+        #[arg(long, default_value = VALIDATOR_KEY)]
+        name: String,
+
+        /// File holding the hex-encoded 32-byte secret, such as an
+        /// unencrypted key file from before the keystore
+        #[arg(long)]
+        secret_file: PathBuf,
+
+        #[command(flatten)]
+        key: NewKeyArgs,
+    },
+    /// Print a key's hex-encoded secret
+    Export {
+        #[arg(long, default_value = VALIDATOR_KEY)]
+        name: String,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// List the keys with their types, public keys and, for ed25519
+    /// keys, addresses
+    List,
+    /// Print a new recovery phrase to derive keys from
+    Mnemonic {
+        /// 12, 15, 18, 21 or 24
+        #[arg(long, default_value_t = 24)]
+        words: usize,
+    },
+    /// Derive a key from a recovery phrase into <data-dir>/keys
+    Derive {
+        #[arg(long)]
+        name: String,
+
+        /// BIP-32 path, e.g. m/44'/7171'/0'/0'/1'; ed25519 keys need every
+        /// index hardened
+        #[arg(long)]
+        path: DerivationPath,
+
+        #[command(flatten)]
+        mnemonic: MnemonicArgs,
+
+        #[command(flatten)]
+        key: NewKeyArgs,
+    },
+    /// Print the address and public key of an account on a Ledger, and
+    /// show it on the device to be checked against
+    Ledger {
+        #[arg(long, default_value = ACCOUNT_PATH)]
+        path: DerivationPath,
+    },
+    /// Derive the account, validator and network keys from a recovery
+    /// phrase into <data-dir>/keys, at their standard paths
+    Recover {
+        #[command(flatten)]
+        mnemonic: MnemonicArgs,
+
+        #[arg(long, value_enum, default_value_t = Kdf::Scrypt)]
+        kdf: Kdf,
+
+        /// Replace existing keys of the same names
+        #[arg(long)]
+        force: bool,
+
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+}
+
+/// How a key saved to the keystore is made.
+#[derive(Debug, Args)]
+pub struct NewKeyArgs {
+    #[arg(long = "type", value_enum, default_value_t = KeyType::Ed25519)]
+    pub key_type: KeyType,
+
+    /// How the password is stretched into the encryption key
+    #[arg(long, value_enum, default_value_t = Kdf::Scrypt)]
+    pub kdf: Kdf,
+
+    /// Replace an existing key of the same name
+    #[arg(long)]
+    pub force: bool,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct MnemonicArgs {
+    /// File whose first line is the recovery phrase [default:
+    /// $CUBIQ_MNEMONIC, else asked for]
+    #[arg(long, env = "CUBIQ_MNEMONIC_FILE")]
+    pub mnemonic_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ValidatorCommand {
+    /// Submit a registration transaction bonding `stake`, signed by the
+    /// reward account, e.g. `--key account` or `--ledger`
+    Register {
+        /// Identifier the validator will vote as
+        #[arg(long)]
+        node_id: String,
+
+        #[arg(long)]
+        stake: u64,
+
+        #[command(flatten)]
+        keys: ValidatorKeyArgs,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+    /// Submit a transaction switching the validator to a new key at the
+    /// start of `epoch`, signed by its current key, or by the reward
+    /// account of a registered validator
+    RotateKey {
+        #[arg(long)]
+        node_id: String,
+
+        /// Key in the keystore to vote with from `epoch`
+        #[arg(long)]
+        new_key: String,
+
+        /// Key in the keystore the node connects as from `epoch`, which a
+        /// registered validator re-signs its registration with, together
+        /// with the new key
+        #[arg(long)]
+        network_key: Option<String>,
+
+        /// Epoch the new key takes effect at, after the current one
+        #[arg(long)]
+        epoch: u64,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TxCommand {
+    /// Transfer value to an account
+    Send {
+        /// Recipient account
+        #[arg(long)]
+        to: String,
+
+        #[arg(long)]
+        value: u64,
+
+        /// Hex-encoded call data
+        #[arg(long, default_value = "")]
+        data: String,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        #[command(flatten)]
+        fee: FeeArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WalletCommand {
+    /// Print an account's balance
+    Balance {
+        /// Address to look up [default: the signer's]
+        account: Option<Address>,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+    /// Print an account's finalized transactions, newest first, as
+    /// height, hash, direction, counterparty and value
+    History {
+        /// Address to look up [default: the signer's]
+        account: Option<Address>,
+
+        #[arg(long, default_value_t = DEFAULT_PAGE)]
+        limit: usize,
+
+        /// Cursor printed at the end of the previous page
+        #[arg(long)]
+        before: Option<u64>,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+    /// Transfer value to an account
+    Transfer {
+        /// Address of the recipient
+        #[arg(long)]
+        to: Address,
+
+        #[arg(long)]
+        value: u64,
+
+        #[command(flatten)]
+        send: SendArgs,
+    },
+    /// Bond stake to a validator
+    Delegate {
+        /// Node id of the validator
+        #[arg(long)]
+        validator: String,
+
+        #[arg(long)]
+        amount: u64,
+
+        #[command(flatten)]
+        send: SendArgs,
+    },
+    /// Ask for stake bonded to a validator back
+    Undelegate {
+        #[arg(long)]
+        validator: String,
+
+        #[arg(long)]
+        amount: u64,
+
+        #[command(flatten)]
+        send: SendArgs,
+    },
+    /// Submit a transaction signed with --offline, or a multisig's once
+    /// combined
+    Broadcast {
+        /// File holding it, or - for stdin
+        path: PathBuf,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+    /// Create multisig accounts and cosign their transactions
+    #[command(subcommand)]
+    Multisig(MultisigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MultisigCommand {
+    /// Create and fund a multisig account, and print its address
+    Create {
+        /// Cosignatures each of its transactions needs
+        #[arg(long)]
+        threshold: u32,
+
+        /// Address of a key holder; repeat for each
+        #[arg(long = "member", required = true)]
+        members: Vec<Address>,
+
+        /// Balance to move to it from the sending account
+        #[arg(long, default_value_t = 0)]
+        value: u64,
+
+        #[command(flatten)]
+        send: SendArgs,
+    },
+    /// Print a transfer from a multisig, as JSON, for its key holders to
+    /// sign
+    Propose {
+        /// Address of the multisig
+        #[arg(long)]
+        from: Address,
+
+        /// Address of the recipient
+        #[arg(long)]
+        to: Address,
+
+        #[arg(long)]
+        value: u64,
+
+        #[command(flatten)]
+        fee: FeeArgs,
+
+        #[command(flatten)]
+        offline: OfflineArgs,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+    /// Print a proposed transaction with the signer's cosignature added
+    Sign {
+        /// File holding it, or - for stdin
+        path: PathBuf,
+
+        #[command(flatten)]
+        signer: SignerArgs,
+    },
+    /// Print a proposed transaction with the cosignatures of copies signed
+    /// separately
+    Combine {
+        /// Files holding the copies
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+/// How a wallet transaction is signed and sent.
+#[derive(Debug, Args)]
+pub struct SendArgs {
+    #[command(flatten)]
+    pub signer: SignerArgs,
+
+    #[command(flatten)]
+    pub fee: FeeArgs,
+
+    #[command(flatten)]
+    pub offline: OfflineArgs,
+
+    #[command(flatten)]
+    pub rpc: RpcArgs,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct OfflineArgs {
+    /// Print the signed transaction as JSON instead of submitting it,
+    /// without contacting a node
+    #[arg(long, requires_all = ["chain_id", "nonce", "max_fee_per_gas", "max_priority_fee_per_gas"])]
+    pub offline: bool,
+
+    /// Chain to sign for [default: the node's]
+    #[arg(long)]
+    pub chain_id: Option<u64>,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct FeeArgs {
+    /// Most to pay per unit of gas, base fee and tip together
+    /// [default: twice the node's base fee, plus the tip]
+    #[arg(long)]
+    pub max_fee_per_gas: Option<u64>,
+
+    /// Most tip per unit of gas for the block's proposer [default: what
+    /// recent blocks paid, as the node estimates it]
+    #[arg(long)]
+    pub max_priority_fee_per_gas: Option<u64>,
+
+    /// Nonce to send with, replacing the pending transaction that has it
+    /// [default: the account's next]
+    #[arg(long)]
+    pub nonce: Option<u64>,
+}
+
+/// What signs a transaction: a keystore key, or a Ledger.
+#[derive(Debug, Args)]
+pub struct SignerArgs {
+    /// Key of the sending account
+    #[arg(long, default_value = VALIDATOR_KEY, conflicts_with = "ledger")]
+    pub key: String,
+
+    /// Sign on a Ledger, unlocked with the Cubiq app open, instead
+    #[arg(long)]
+    pub ledger: bool,
+
+    /// Account path on the Ledger
+    #[arg(long, default_value = ACCOUNT_PATH, requires = "ledger")]
+    pub ledger_path: DerivationPath,
+
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+/// The hot keys a validator registers, unlocked with the signer's
+/// password.
+#[derive(Debug, Args)]
+pub struct ValidatorKeyArgs {
+    /// Key the validator votes with
+    #[arg(long, default_value = VALIDATOR_KEY)]
+    pub consensus_key: String,
+
+    /// Key the node's peer id derives from
+    #[arg(long, default_value = NETWORK_KEY)]
+    pub network_key: String,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct PasswordArgs {
+    /// File whose first line is the key password [default:
+    /// $CUBIQ_KEY_PASSWORD, else asked for]
+    #[arg(long, env = "CUBIQ_KEY_PASSWORD_FILE")]
+    pub password_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DebugCommand {
+    /// Pack the config with its secrets redacted, block store stats, the
+    /// running node's status and peers, and crash reports into one archive
+    Bundle {
+        /// Archive to write [default: <data-dir>/debug-bundle-<unix time>.tar.gz]
+        #[arg(long)]
+        out: Option<PathBuf>,
+
+        #[command(flatten)]
+        rpc: RpcArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct RpcArgs {
+    /// Node JSON-RPC endpoint
+    #[arg(long, env = "CUBIQ_RPC", default_value = DEFAULT_RPC_URL)]
+    pub rpc: String,
+}
+
+/// `$HOME/.cubiq`, or `.cubiq` when there is no home directory.
+fn default_data_dir() -> PathBuf {
+    std::env::var_os("HOME").map_or_else(|| PathBuf::from(".cubiq"), |home| PathBuf::from(home).join(".cubiq"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn global_flags_are_accepted_after_subcommands() {
+        let cli = Cli::try_parse_from([
+            "cubiq", "tx", "send", "--to", "0xbob", "--value", "5", "--data-dir", "/var/lib/cubiq", "--log-level", "debug",
+        ])
+        .unwrap();
+        assert_eq!(cli.global.data_dir, PathBuf::from("/var/lib/cubiq"));
+        assert_eq!(cli.global.config_path(), PathBuf::from("/var/lib/cubiq/config.toml"));
+        assert_eq!(cli.global.log_level.as_deref(), Some("debug"));
+        let Command::Tx(TxCommand::Send { to, value, rpc, .. }) = cli.command else {
+            panic!("expected tx send");
+        };
+        assert_eq!((to.as_str(), value, rpc.rpc.as_str()), ("0xbob", 5, DEFAULT_RPC_URL));
+
+        let cli = Cli::try_parse_from(["cubiq", "tx", "send", "--to", "0xbob", "--value", "5", "--ledger"]).unwrap();
+        let Command::Tx(TxCommand::Send { signer, .. }) = cli.command else {
+            panic!("expected tx send");
+        };
+        assert!(signer.ledger);
+        assert_eq!(signer.ledger_path.to_string(), ACCOUNT_PATH);
+        assert!(Cli::try_parse_from(["cubiq", "tx", "send", "--to", "0xbob", "--value", "5", "--ledger", "--key", "a"]).is_err());
+        assert!(Cli::try_parse_from(["cubiq", "tx", "send", "--to", "0xbob", "--value", "5", "--ledger-path", "m/0'"]).is_err());
+    }
+
+    #[test]
+    fn offline_wallet_transactions_need_every_field_the_node_would_fill() {
+        let offline = ["cubiq", "wallet", "delegate", "--validator", "validator-1", "--amount", "10", "--offline", "--chain-id", "7"];
+        assert!(Cli::try_parse_from(offline).is_err());
+        let fees = ["--nonce", "0", "--max-fee-per-gas", "20", "--max-priority-fee-per-gas", "1"];
+        let cli = Cli::try_parse_from(offline.iter().chain(&fees)).unwrap();
+        let Command::Wallet(WalletCommand::Delegate { validator, amount, send }) = cli.command else {
+            panic!("expected wallet delegate");
+        };
+        assert_eq!((validator.as_str(), amount, send.offline.chain_id, send.fee.nonce), ("validator-1", 10, Some(7), Some(0)));
+        let alice = Address::from_bytes([0xaa; 32]).to_string();
+        let cli = Cli::try_parse_from(["cubiq", "wallet", "history", &alice]).unwrap();
+        assert!(matches!(cli.command, Command::Wallet(WalletCommand::History { account: Some(_), limit: DEFAULT_PAGE, .. })));
+        let typo = alice.replacen('4', "5", 1);
+        let error = Cli::try_parse_from(["cubiq", "wallet", "transfer", "--to", &typo, "--value", "1"]).unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn multisigs_are_created_from_members_and_proposed_offline() {
+        let (alice, bob) = (Address::from_bytes([0xaa; 32]).to_string(), Address::from_bytes([0xbb; 32]).to_string());
+        let create = ["cubiq", "wallet", "multisig", "create", "--threshold", "2", "--member", &alice, "--member", &bob];
+        let cli = Cli::try_parse_from(create).unwrap();
+        let Command::Wallet(WalletCommand::Multisig(MultisigCommand::Create { threshold, members, value, .. })) = cli.command else {
+            panic!("expected wallet multisig create");
+        };
+        assert_eq!((threshold, members.len(), value), (2, 2, 0));
+        assert!(Cli::try_parse_from(["cubiq", "wallet", "multisig", "create", "--threshold", "1"]).is_err());
+
+        let propose = ["cubiq", "wallet", "multisig", "propose", "--from", &alice, "--to", &bob, "--value", "5", "--offline"];
+        assert!(Cli::try_parse_from(propose).is_err());
+        let fees = ["--chain-id", "7", "--nonce", "0", "--max-fee-per-gas", "20", "--max-priority-fee-per-gas", "1"];
+        assert!(Cli::try_parse_from(propose.iter().chain(&fees)).is_ok());
+        let cli = Cli::try_parse_from(["cubiq", "wallet", "multisig", "combine", "a.json", "b.json"]).unwrap();
+        assert!(matches!(cli.command, Command::Wallet(WalletCommand::Multisig(MultisigCommand::Combine { paths })) if paths.len() == 2));
+    }
+
+    #[test]
+    fn registrations_default_to_the_standard_hot_keys() {
+        let cli = Cli::try_parse_from(["cubiq", "validator", "register", "--node-id", "validator-1", "--stake", "10", "--key", "account"]).unwrap();
+        let Command::Validator(ValidatorCommand::Register { keys, signer, .. }) = cli.command else {
+            panic!("expected validator register");
+        };
+        assert_eq!((keys.consensus_key.as_str(), keys.network_key.as_str(), signer.key.as_str()), (VALIDATOR_KEY, NETWORK_KEY, "account"));
+    }
+
+    #[test]
+    fn key_rotations_name_the_new_key_and_its_epoch() {
+        let rotate = ["cubiq", "validator", "rotate-key", "--node-id", "validator-1", "--new-key", "validator-2", "--epoch", "4"];
+        let cli = Cli::try_parse_from(rotate).unwrap();
+        let Command::Validator(ValidatorCommand::RotateKey { node_id, new_key, network_key, epoch, signer, .. }) = cli.command else {
+            panic!("expected validator rotate-key");
+        };
+        assert_eq!((node_id.as_str(), new_key.as_str(), epoch, signer.key.as_str()), ("validator-1", "validator-2", 4, VALIDATOR_KEY));
+        assert_eq!(network_key, None);
+        assert!(Cli::try_parse_from(&rotate[..rotate.len() - 2]).is_err());
+
+        let registered = rotate.iter().chain(&["--network-key", "network-2", "--key", "account"]);
+        let Command::Validator(ValidatorCommand::RotateKey { network_key, signer, .. }) = Cli::try_parse_from(registered).unwrap().command else {
+            panic!("expected validator rotate-key");
+        };
+        assert_eq!((network_key.as_deref(), signer.key.as_str()), (Some("network-2"), "account"));
+    }
+
+    #[test]
+    fn snapshot_commands_take_a_height_or_a_path() {
+        let cli = Cli::try_parse_from(["cubiq", "snapshot", "export", "--height", "120"]).unwrap();
+        assert!(matches!(cli.command, Command::Snapshot(SnapshotCommand::Export { height: Some(120), out: None })));
+        let cli = Cli::try_parse_from(["cubiq", "snapshot", "import", "state.snap"]).unwrap();
+        let Command::Snapshot(SnapshotCommand::Import { path }) = cli.command else {
+            panic!("expected snapshot import");
+        };
+        assert_eq!(path, PathBuf::from("state.snap"));
+        assert!(Cli::try_parse_from(["cubiq", "snapshot", "import"]).is_err());
+    }
+
+    #[test]
+    fn replay_needs_a_first_height() {
+        let cli = Cli::try_parse_from(["cubiq", "replay", "--from", "10", "--to", "20", "--skip-proofs"]).unwrap();
+        assert!(matches!(cli.command, Command::Replay { from: 10, to: Some(20), skip_proofs: true, json: false }));
+        assert!(Cli::try_parse_from(["cubiq", "replay", "--to", "20"]).is_err());
+    }
+
+    #[test]
+    fn key_commands_default_to_the_validator_key() {
+        let cli = Cli::try_parse_from(["cubiq", "key", "generate", "--kdf", "argon2id"]).unwrap();
+        let Command::Key(KeyCommand::Create { name, key }) = cli.command else {
+            panic!("expected key create");
+        };
+        assert_eq!((name.as_str(), key.key_type, key.kdf, key.force), (VALIDATOR_KEY, KeyType::Ed25519, Kdf::Argon2id, false));
+        let cli = Cli::try_parse_from(["cubiq", "key", "import", "--type", "secp256k1", "--secret-file", "old.key"]).unwrap();
+        assert!(matches!(cli.command, Command::Key(KeyCommand::Import { key: NewKeyArgs { key_type: KeyType::Secp256k1, .. }, .. })));
+        assert!(Cli::try_parse_from(["cubiq", "key", "import"]).is_err());
+
+        let cli = Cli::try_parse_from(["cubiq", "key", "derive", "--name", "account-2", "--path", "m/44h/7171h/0h/0h/2h"]).unwrap();
+        let Command::Key(KeyCommand::Derive { path, .. }) = cli.command else {
+            panic!("expected key derive");
+        };
+        assert_eq!(path.to_string(), "m/44'/7171'/0'/0'/2'");
+        assert!(Cli::try_parse_from(["cubiq", "key", "derive", "--name", "a", "--path", "44/0"]).is_err());
+    }
+}
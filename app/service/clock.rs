@@ -0,0 +1,200 @@
+//! Clock checks against SNTP time servers.
+//!
+//! Proposals carry their proposer's timestamp, and a validator voting for
+//! one vouches for it, so a node whose clock is off shouldn't vote. At
+//! startup and every `clock.interval_secs` the node asks each of
+//! `clock.servers` for the time and takes the median offset from its own
+//! clock among those that answer. Past `clock.warn_skew_ms` it warns; past
+//! `clock.max_skew_ms` it stops voting, still following the chain, until
+//! a check finds its clock back within bounds. When no server answers, it
+//! keeps to what the last check found.
+//!
+//! How far ahead of the local clock a proposal's own timestamp may be is
+//! `clock.proposal_skew_secs`, checked by consensus.
+
+use anyhow::{bail, ensure, Context, Result};
+use consensus::QubeNode;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+use crate::config::ClockConfig;
+
+/// How long a server has to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Seconds from the NTP era, 1900, to the Unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Version 4, client mode.
+const CLIENT_HEADER: u8 = 0x23;
+const SERVER_MODE: u8 = 4;
+
+/// How far the local clock is off, by the bounds in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Skew {
+    Within,
+    Warn,
+    /// Too far off to vote
+    Excessive,
+}
+
+#[derive(Clone)]
+pub struct ClockCheck {
+    config: ClockConfig,
+    consensus: Arc<QubeNode>,
+    skew: Skew,
+}
+
+impl ClockCheck {
+    /// A check for `config`, unless it disables them.
+    pub fn new(config: &ClockConfig, consensus: Arc<QubeNode>) -> Option<Self> {
+        (config.interval_secs > 0).then(|| Self { config: config.clone(), consensus, skew: Skew::Within })
+    }
+
+    /// Checks the clock until `shutdown` completes.
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tokio::pin!(shutdown);
+        let mut ticks = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = ticks.tick() => {}
+            }
+            let queries = self.config.servers.iter().map(|server| async move { (server, query(server).await) });
+            let mut offsets = Vec::new();
+            for (server, result) in futures::future::join_all(queries).await {
+                match result {
+                    Ok(offset) => offsets.push(offset),
+                    Err(e) => tracing::debug!(server = %server, error = %e, "time server did not answer"),
+                }
+            }
+            match median(&mut offsets) {
+                Some(offset) => self.apply(offset),
+                None => tracing::warn!(servers = ?self.config.servers, "no time server answered; clock unchecked"),
+            }
+        }
+    }
+
+    /// Logs a change in skew, and stops or restarts voting.
+    fn apply(&mut self, offset_ms: i64) {
+        let skew = assess(&self.config, offset_ms);
+        tracing::debug!(offset_ms, "clock checked");
+        if skew != self.skew {
+            match skew {
+                Skew::Within => tracing::info!(offset_ms, "clock back within bounds"),
+                Skew::Warn => tracing::warn!(offset_ms, bound_ms = self.config.warn_skew_ms, "clock is drifting from the time servers"),
+                Skew::Excessive => {
+                    tracing::error!(offset_ms, bound_ms = self.config.max_skew_ms, "clock is too far off; not voting until it is fixed")
+                }
+            }
+        }
+        self.skew = skew;
+        if self.consensus.set_clock_skewed(skew == Skew::Excessive) && skew != Skew::Excessive {
+            tracing::info!("voting again");
+        }
+    }
+}
+
+/// How far off `offset_ms` puts the clock.
+pub fn assess(config: &ClockConfig, offset_ms: i64) -> Skew {
+    match offset_ms.unsigned_abs() {
+        skew if skew > config.max_skew_ms => Skew::Excessive,
+        skew if skew > config.warn_skew_ms => Skew::Warn,
+        _ => Skew::Within,
+    }
+}
+
+fn median(offsets: &mut [i64]) -> Option<i64> {
+    offsets.sort_unstable();
+    offsets.get(offsets.len() / 2).copied()
+}
+
+/// Asks `server` for the time, returning how far in milliseconds its
+/// clock is ahead of the local one, as SNTP works out from the times the
+/// request and reply left and arrived.
+pub async fn query(server: &str) -> Result<i64> {
+    let addr = tokio::net::lookup_host(server).await?.next().context("the server has no address")?;
+    let socket = UdpSocket::bind(if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }).await?;
+    socket.connect(addr).await?;
+
+    let mut request = [0; 48];
+    request[0] = CLIENT_HEADER;
+    let sent = SystemTime::now();
+    request[40..].copy_from_slice(&to_ntp(sent).to_be_bytes());
+    socket.send(&request).await?;
+    let mut reply = [0; 48];
+    let len = tokio::time::timeout(QUERY_TIMEOUT, socket.recv(&mut reply)).await.context("timed out")??;
+    let received = SystemTime::now();
+
+    ensure!(len == reply.len(), "the reply is {} bytes, not {}", len, reply.len());
+    ensure!(reply[0] & 0x7 == SERVER_MODE, "the reply is not from a server");
+    if reply[1] == 0 {
+        bail!("the server refused the request");
+    }
+    ensure!(reply[24..32] == request[40..], "the reply is to another request");
+    let timestamp = |at: usize| from_ntp(u64::from_be_bytes(reply[at..at + 8].try_into().expect("eight bytes")));
+    let (server_received, server_sent) = (timestamp(32), timestamp(40));
+    let offset = ((server_received - unix_secs(sent)) + (server_sent - unix_secs(received))) / 2.0;
+    Ok((offset * 1000.0).round() as i64)
+}
+
+fn unix_secs(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+/// `time` as an NTP timestamp: seconds since 1900 in the high half, and
+/// the fraction of a second in the low half.
+fn to_ntp(time: SystemTime) -> u64 {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let fraction = ((since.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    ((since.as_secs() + NTP_UNIX_OFFSET) << 32) | fraction
+}
+
+/// An NTP timestamp in seconds since the Unix epoch.
+fn from_ntp(timestamp: u64) -> f64 {
+    let secs = (timestamp >> 32) as f64 - NTP_UNIX_OFFSET as f64;
+    secs + (timestamp & 0xffff_ffff) as f64 / (1u64 << 32) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers one request with a clock `ahead` of the local one.
+    async fn server(ahead: Duration) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut request = [0; 48];
+            let (_, client) = socket.recv_from(&mut request).await.unwrap();
+            let now = to_ntp(SystemTime::now() + ahead).to_be_bytes();
+            let mut reply = [0; 48];
+            reply[0] = 0x24;
+            reply[1] = 2;
+            reply[24..32].copy_from_slice(&request[40..]);
+            reply[32..40].copy_from_slice(&now);
+            reply[40..].copy_from_slice(&now);
+            socket.send_to(&reply, client).await.unwrap();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn stops_voting_while_the_clock_is_too_far_off() {
+        let offset = query(&server(Duration::from_secs(10)).await).await.unwrap();
+        assert!((9_900..=10_100).contains(&offset), "{offset}");
+        assert_eq!(median(&mut [300, -40_000, 20]), Some(20));
+
+        let config = ClockConfig::default();
+        assert_eq!([assess(&config, -200), assess(&config, 900), assess(&config, offset)], [Skew::Within, Skew::Warn, Skew::Excessive]);
+        let consensus = Arc::new(QubeNode::new("v1".to_string(), 7, 10, vec![]).await);
+        let mut check = ClockCheck::new(&config, Arc::clone(&consensus)).unwrap();
+        check.apply(offset);
+        assert!(consensus.set_clock_skewed(true));
+        check.apply(0);
+        assert!(!consensus.set_clock_skewed(false));
+        assert!(ClockCheck::new(&ClockConfig { interval_secs: 0, ..config }, consensus).is_none());
+    }
+}
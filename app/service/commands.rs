@@ -0,0 +1,609 @@
+//! Subcommand implementations.
+
+use anyhow::{anyhow, bail, Context, Result};
+use consensus::address;
+use consensus::genesis::{ChainSpec, GenesisValidator};
+use consensus::registration::Registration;
+use consensus::replay::{Divergence, ProofChecker, Replayer};
+use consensus::rotation::KeyRotation;
+use consensus::snapshot::{self, Snapshot};
+use consensus::Transaction;
+use cubiq_client::staking::RegistryCall;
+use cubiq_client::Client;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Value;
+use tracing_subscriber::EnvFilter;
+use zkurl::resolver::ZkURLResolver;
+use zkurl::ZkURL;
+
+use crate::admin::SNAPSHOTS_DIR;
+use crate::cli::{FeeArgs, GlobalArgs, InitArgs, MnemonicArgs, NewKeyArgs, OfflineArgs, PasswordArgs, RunArgs, SignerArgs, ValidatorKeyArgs};
+use crate::config::NodeConfig;
+use crate::datadir;
+use crate::diagnostics::{self, Bundle, CRASHES_DIR};
+use crate::integrity;
+use crate::keys::{self, DerivationPath, ACCOUNT_KEY, KEYS_DIR, NETWORK_KEY, STANDARD_KEYS, VALIDATOR_KEY};
+use crate::keystore::{Kdf, KeyFile, KeyType, Keystore};
+use crate::ledger::Ledger;
+use crate::node::{open_store, Node};
+use crate::logging::LogHandle;
+use crate::reload::ConfigWatcher;
+use crate::signer::Signer;
+use crate::wallet::Sender;
+
+pub async fn run(global: &GlobalArgs, args: RunArgs, log: LogHandle) -> Result<()> {
+    if global.data_dir.exists() {
+        datadir::migrate(&global.data_dir, false)?;
+    }
+    let mut overrides = overrides(&args)?;
+    if let Some(log_level) = &global.log_level {
+        overrides.push(("node.log_level", Value::String(log_level.clone())));
+    }
+    if let Some(format) = global.log_format {
+        overrides.push(("log.format", Value::String(format.to_string())));
+    }
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &overrides)?;
+    log.set_filter(EnvFilter::new(&config.node.log_level))?;
+    log.set_output(&config.log)?;
+    let findings = integrity::check(&global.data_dir, &config, true);
+    for finding in findings.iter().filter(|f| f.status != integrity::Status::Ok) {
+        tracing::warn!("{}", finding);
+    }
+    if !findings.iter().all(integrity::Finding::is_healthy) {
+        bail!("data dir {} failed its integrity check; see `cubiq db check`", global.data_dir.display());
+    }
+    let spec = ChainSpec::load(&config.consensus.chain_spec)?;
+    tracing::info!(
+        node_id = %config.consensus.node_id,
+        role = %config.node.role,
+        backend = %config.storage.backend,
+        retain_blocks = ?config.retained_blocks(),
+        state_pruning = %config.state_pruning(),
+        freeze_after = ?config.freeze_after(),
+        compaction_interval = ?config.compaction_interval(),
+        chain = %spec.name,
+        chain_id = spec.chain_id,
+        genesis = %spec.genesis_hash(),
+        data_dir = %global.data_dir.display(),
+        "starting node"
+    );
+    let node = Node::new(&config, &spec, &global.data_dir, log.clone()).await?;
+    let watcher = ConfigWatcher::new(
+        global.config_path(),
+        global.config.is_some(),
+        global.data_dir.clone(),
+        overrides,
+        config,
+        log,
+        node.consensus(),
+    );
+    let reloads = tokio::spawn(watcher.run());
+    let result = node.run(termination()).await;
+    reloads.abort();
+    result
+}
+
+/// Completes on SIGINT or, on Unix, SIGTERM.
+async fn termination() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT"),
+                    _ = sigterm.recv() => tracing::info!("received SIGTERM"),
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "cannot listen for SIGTERM"),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::error!(error = %e, "cannot listen for Ctrl-C; stopping");
+    }
+}
+
+/// `run` flags as configuration overrides.
+fn overrides(args: &RunArgs) -> Result<Vec<(&'static str, Value)>> {
+    let list = |items: &[String]| Value::Array(items.iter().cloned().map(Value::String).collect());
+    let mut overrides = Vec::new();
+    if let Some(role) = args.role {
+        overrides.push(("node.role", Value::String(role.to_string())));
+    }
+    if let Some(node_id) = &args.node_id {
+        overrides.push(("consensus.node_id", Value::String(node_id.clone())));
+    }
+    if let Some(stake) = args.stake {
+        let stake = i64::try_from(stake).map_err(|_| anyhow!("--stake {} is too large", stake))?;
+        overrides.push(("consensus.stake", Value::Integer(stake)));
+    }
+    if !args.listen_addrs.is_empty() {
+        overrides.push(("network.listen_addrs", list(&args.listen_addrs)));
+    }
+    if !args.bootnodes.is_empty() {
+        overrides.push(("network.bootnodes", list(&args.bootnodes)));
+    }
+    if !args.resolver_endpoints.is_empty() {
+        overrides.push(("resolver.endpoints", list(&args.resolver_endpoints)));
+    }
+    if let Some(addr) = args.rpc_addr {
+        overrides.push(("rpc.http_addr", Value::String(addr.to_string())));
+    }
+    Ok(overrides)
+}
+
+pub fn init(global: &GlobalArgs, args: InitArgs) -> Result<()> {
+    fs::create_dir_all(&global.data_dir).with_context(|| format!("creating {}", global.data_dir.display()))?;
+    let empty = fs::read_dir(&global.data_dir)?.next().is_none();
+    if empty {
+        datadir::write_version(&global.data_dir, datadir::LAYOUT_VERSION)?;
+    } else {
+        datadir::migrate(&global.data_dir, false)?;
+    }
+    let config_path = global.config_path();
+    if config_path.exists() && !args.force {
+        bail!("{} already exists; pass --force to replace it", config_path.display());
+    }
+    let config = NodeConfig::load(&config_path, false, &global.data_dir, &[])?;
+    // A chain to join is checked before any key is made
+    let genesis = args.genesis.as_deref().map(ChainSpec::load).transpose()?;
+    let keystore = Keystore::new(&global.data_dir);
+    for name in [VALIDATOR_KEY, NETWORK_KEY, ACCOUNT_KEY] {
+        let path = keystore.path(name)?;
+        if path.exists() && !args.force {
+            bail!("{} already exists; pass --force to replace it", path.display());
+        }
+    }
+    let password = keys::password(args.password.password_file.as_deref(), true)?;
+
+    // Everything is written to a staging dir and moved into place once it
+    // all succeeded, the config last, so a failed init leaves the existing
+    // setup as it was
+    let staging = global.data_dir.join(".init");
+    if staging.exists() {
+        fs::remove_dir_all(&staging).with_context(|| format!("removing {}", staging.display()))?;
+    }
+    let staged = Keystore::new(&staging);
+    let create = |name| staged.create(name, KeyType::Ed25519, &password, Kdf::default().params(), false);
+    let (key, network_key, account_key) = (create(VALIDATOR_KEY)?, create(NETWORK_KEY)?, create(ACCOUNT_KEY)?);
+    let reward_account = account_key.address().context("account keys are ed25519")?;
+
+    let spec = match genesis {
+        Some(spec) => spec,
+        None => {
+            let node_id = &config.consensus.node_id;
+            let registration = Registration::signed(node_id, &key.signing_key(&password)?, &network_key.signing_key(&password)?, &reward_account);
+            let validator = GenesisValidator {
+                node_id: node_id.clone(),
+                public_key: key.public_key.clone(),
+                stake: config.consensus.stake,
+                registration: Some(registration),
+            };
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+            ChainSpec::dev(args.chain_id, validator, now)
+        }
+    };
+    let spec_name = config.consensus.chain_spec.file_name().context("the chain spec path names no file")?;
+    let staged_spec = staging.join(spec_name);
+    spec.save(&staged_spec)?;
+    let mut written = NodeConfig::default();
+    written.network.identity_key = Some(Path::new(KEYS_DIR).join(format!("{}.json", NETWORK_KEY)));
+    let staged_config = staging.join("config.toml");
+    fs::write(&staged_config, written.to_toml()).with_context(|| format!("writing {}", staged_config.display()))?;
+
+    let install = |from: &Path, to: &Path| fs::rename(from, to).with_context(|| format!("moving {} to {}", from.display(), to.display()));
+    fs::create_dir_all(global.data_dir.join(KEYS_DIR))?;
+    for name in [VALIDATOR_KEY, NETWORK_KEY, ACCOUNT_KEY] {
+        install(&staged.path(name)?, &keystore.path(name)?)?;
+    }
+    install(&staged_spec, &config.consensus.chain_spec)?;
+    install(&staged_config, &config_path)?;
+    fs::remove_dir_all(&staging).with_context(|| format!("removing {}", staging.display()))?;
+
+    println!("Initialized {}", global.data_dir.display());
+    println!("Config: {}", config_path.display());
+    println!("Chain: {} (id {}, genesis {})", spec.name, spec.chain_id, spec.genesis_hash());
+    println!("Validator key: {} ({})", key.public_key, keystore.path(VALIDATOR_KEY)?.display());
+    println!("Network key: {} ({})", network_key.public_key, keystore.path(NETWORK_KEY)?.display());
+    println!("Reward account: {} ({})", reward_account, keystore.path(ACCOUNT_KEY)?.display());
+    Ok(())
+}
+
+pub fn migrate(global: &GlobalArgs, dry_run: bool) -> Result<()> {
+    if !global.data_dir.is_dir() {
+        bail!("{} does not exist; run `cubiq init` first", global.data_dir.display());
+    }
+    let from = datadir::version(&global.data_dir)?;
+    let migrations = datadir::migrate(&global.data_dir, dry_run)?;
+    if migrations.is_empty() {
+        println!("{} is up to date (layout {})", global.data_dir.display(), from);
+        return Ok(());
+    }
+    let verb = if dry_run { "Would apply" } else { "Applied" };
+    for migration in migrations {
+        println!("{} layout {}: {}", verb, migration.to, migration.description);
+    }
+    Ok(())
+}
+
+pub fn check(global: &GlobalArgs, repair: bool) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    let findings = integrity::check(&global.data_dir, &config, repair);
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    if !findings.iter().all(integrity::Finding::is_healthy) {
+        bail!("{} needs attention", global.data_dir.display());
+    }
+    Ok(())
+}
+
+pub fn db_stats(global: &GlobalArgs, json: bool) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    if !config.storage.backend.persistent() {
+        bail!("storage.backend is memory, so there are no stored blocks");
+    }
+    let store = open_store(&config)
+        .with_context(|| format!("opening {}; is the node still running?", config.storage.db_path.display()))?;
+    let stats = store.stats()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    let known = |value: Option<u64>, show: fn(u64) -> String| value.map_or("-".to_string(), show);
+    println!("Backend {} at {}, {}", stats.kv.backend, config.storage.db_path.display(), known(stats.kv.total_bytes, size));
+    match stats.freezer_bytes {
+        Some(bytes) => println!("Height {}, {} frozen ({} in the freezer)", stats.latest_height, stats.frozen_height, size(bytes)),
+        None => println!("Height {}, no freezer", stats.latest_height),
+    }
+    if let Some(running) = stats.kv.running_compactions {
+        println!("Compactions running: {}", running);
+    }
+    let columns = &stats.kv.columns;
+    if columns.iter().all(|column| column.keys.is_none() && column.bytes.is_none()) {
+        let names: Vec<_> = columns.iter().map(|column| column.name.as_str()).collect();
+        println!("Column families {}; {} keeps no figures for each", names.join(", "), stats.kv.backend);
+        return Ok(());
+    }
+    println!("{:<10} {:>12} {:>12} {:>20}", "column", "keys", "size", "pending compaction");
+    for column in columns {
+        let keys = known(column.keys, |keys| keys.to_string());
+        let pending = known(column.pending_compaction_bytes, size);
+        println!("{:<10} {:>12} {:>12} {:>20}", column.name, keys, known(column.bytes, size), pending);
+    }
+    Ok(())
+}
+
+/// `bytes` in binary units, e.g. `1.5 GiB`.
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+pub fn export_snapshot(global: &GlobalArgs, height: Option<u64>, out: Option<PathBuf>) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    let spec = ChainSpec::load(&config.consensus.chain_spec)?;
+    if !config.storage.backend.persistent() {
+        bail!("storage.backend is memory, so there are no stored blocks");
+    }
+    let store = open_store(&config)
+        .with_context(|| format!("opening {}; is the node still running?", config.storage.db_path.display()))?;
+    let height = match height {
+        Some(height) => height,
+        None => store.latest_height()?,
+    };
+    let snapshot = snapshot::export(&store, &spec, height)?;
+    let path = match out {
+        Some(path) => path,
+        None => {
+            let dir = global.data_dir.join(SNAPSHOTS_DIR);
+            fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+            dir.join(format!("snapshot-{}-{}.snap", spec.chain_id, height))
+        }
+    };
+    snapshot.write(&path).with_context(|| format!("writing {}", path.display()))?;
+    let manifest = &snapshot.manifest;
+    println!("{}", path.display());
+    eprintln!("Height {} ({}), state root {}, {} chunks", manifest.height, manifest.block_hash, manifest.state_root, manifest.chunks.len());
+    eprintln!("Snapshot id {}", manifest.id());
+    Ok(())
+}
+
+pub fn import_snapshot(global: &GlobalArgs, path: &Path) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    let spec = ChainSpec::load(&config.consensus.chain_spec)?;
+    let snapshot = Snapshot::read(path).with_context(|| format!("reading {}", path.display()))?;
+    if !config.storage.backend.persistent() {
+        bail!("storage.backend is memory, so there are no stored blocks");
+    }
+    let store = open_store(&config)
+        .with_context(|| format!("opening {}; is the node still running?", config.storage.db_path.display()))?;
+    snapshot::import(&store, &spec, &snapshot)?;
+    let manifest = &snapshot.manifest;
+    println!("Imported height {} ({}), state root {}", manifest.height, manifest.block_hash, manifest.state_root);
+    Ok(())
+}
+
+pub async fn replay(global: &GlobalArgs, from: u64, to: Option<u64>, skip_proofs: bool, json: bool) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    let spec = ChainSpec::load(&config.consensus.chain_spec)?;
+    if !config.storage.backend.persistent() {
+        bail!("storage.backend is memory, so there are no stored blocks");
+    }
+    let store = open_store(&config)
+        .with_context(|| format!("opening {}; is the node still running?", config.storage.db_path.display()))?;
+    let to = match to {
+        Some(to) => to,
+        None => store.latest_height()?,
+    };
+    let resolver = ZkURLResolver::new(config.resolver.endpoints.clone());
+    let checker = ProofChecker::new(spec.chain_id);
+    let (mut replayed, mut diverged) = (0, Vec::new());
+    for block in Replayer::new(&store, &spec, from, to)? {
+        let mut block = block?;
+        if !skip_proofs {
+            let fetched = match block.header.zkurl.parse::<ZkURL>() {
+                Ok(zkurl) => resolver.fetch_proof(&zkurl).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match fetched {
+                Ok(bundle) => block.divergences.extend(checker.check(&block.header, &bundle)),
+                Err(e) => block.divergences.push(Divergence {
+                    field: "proof".to_string(),
+                    recorded: block.header.zkurl.clone(),
+                    replayed: format!("not fetched: {}", e),
+                }),
+            }
+        }
+        replayed += 1;
+        if block.diverged() {
+            diverged.push(block.height);
+        }
+        if json {
+            println!("{}", serde_json::to_string(&block)?);
+            continue;
+        }
+        match block.diverged() {
+            false => println!("{} {} ok, state root {}", block.height, block.header.block_hash, block.state_root),
+            true => {
+                println!("{} {} DIVERGED", block.height, block.header.block_hash);
+                for divergence in &block.divergences {
+                    println!("    {}", divergence);
+                }
+            }
+        }
+    }
+    if replayed < to - from + 1 {
+        eprintln!("Stopped after height {}: later blocks cannot be replayed on the state it gives", from + replayed - 1);
+    }
+    match diverged.as_slice() {
+        [] => {
+            eprintln!("Replayed {} blocks, {}..={}; all match", replayed, from, to);
+            Ok(())
+        }
+        heights => {
+            let heights: Vec<String> = heights.iter().map(u64::to_string).collect();
+            bail!("{} of {} replayed blocks diverge: {}", heights.len(), replayed, heights.join(", "))
+        }
+    }
+}
+
+pub async fn debug_bundle(global: &GlobalArgs, out: Option<PathBuf>, rpc: &str) -> Result<()> {
+    let config = NodeConfig::load(&global.config_path(), global.config.is_some(), &global.data_dir, &[])?;
+    let path = match out {
+        Some(path) => path,
+        None => {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+            fs::create_dir_all(&global.data_dir).with_context(|| format!("creating {}", global.data_dir.display()))?;
+            global.data_dir.join(format!("debug-bundle-{}.tar.gz", now.as_secs()))
+        }
+    };
+    let mut bundle = Bundle::create(&path)?;
+
+    let mut redacted = toml::Value::try_from(&config)?;
+    diagnostics::redact(&mut redacted);
+    bundle.add("config.toml", toml::to_string_pretty(&redacted)?.as_bytes())?;
+    let stats = match config.storage.backend.persistent() {
+        true => open_store(&config)
+            .with_context(|| format!("opening {}; is the node still running?", config.storage.db_path.display()))
+            .and_then(|store| Ok(serde_json::to_vec_pretty(&store.stats()?)?)),
+        false => Err(anyhow!("storage.backend is memory, so there are no stored blocks")),
+    };
+    bundle.add_or_note("db-stats.json", stats)?;
+
+    let token = match &config.rpc.auth_token_file {
+        Some(file) => Some(fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?.trim().to_string()),
+        None => None,
+    };
+    let client = Client::new(rpc)?;
+    let client = match token {
+        Some(token) => client.with_token(token),
+        None => client,
+    };
+    for (name, method) in [("status.json", "node_status"), ("peers.json", "admin_peers")] {
+        let answer = client.call::<serde_json::Value>(method, serde_json::json!([])).await;
+        let contents = answer.with_context(|| format!("asking the node at {}", rpc)).and_then(|value| Ok(serde_json::to_vec_pretty(&value)?));
+        bundle.add_or_note(name, contents)?;
+    }
+    bundle.add_dir(CRASHES_DIR, &global.data_dir.join(CRASHES_DIR))?;
+    for (name, reason) in bundle.missing() {
+        eprintln!("Left out {}: {}", name, reason);
+    }
+    println!("{}", bundle.finish()?.display());
+    Ok(())
+}
+
+pub fn create_key(global: &GlobalArgs, name: &str, args: &NewKeyArgs) -> Result<()> {
+    let password = keys::password(args.password.password_file.as_deref(), true)?;
+    let keystore = Keystore::new(&global.data_dir);
+    let key = keystore.create(name, args.key_type, &password, args.kdf.params(), args.force)?;
+    println!("{}", describe(&key));
+    eprintln!("Saved to {}", keystore.path(name)?.display());
+    Ok(())
+}
+
+pub fn import_key(global: &GlobalArgs, name: &str, secret_file: &Path, args: &NewKeyArgs) -> Result<()> {
+    let secret = keys::read_secret(secret_file)?;
+    let password = keys::password(args.password.password_file.as_deref(), true)?;
+    let keystore = Keystore::new(&global.data_dir);
+    let key = keystore.import(name, args.key_type, &secret, &password, args.kdf.params(), args.force)?;
+    println!("{}", describe(&key));
+    eprintln!("Saved to {}; {} can be deleted", keystore.path(name)?.display(), secret_file.display());
+    Ok(())
+}
+
+pub fn export_key(global: &GlobalArgs, name: &str, password: &PasswordArgs) -> Result<()> {
+    let key = Keystore::new(&global.data_dir).load(name)?;
+    let secret = key.open(&keys::password(password.password_file.as_deref(), false)?)?;
+    eprintln!("Whoever holds this secret controls the key; keep it offline");
+    println!("{}", hex::encode(secret));
+    Ok(())
+}
+
+pub fn list_keys(global: &GlobalArgs) -> Result<()> {
+    for (name, key) in Keystore::new(&global.data_dir).list()? {
+        println!("{}\t{}\t{}", name, key.key_type, describe(&key));
+    }
+    Ok(())
+}
+
+/// The public key, then for an ed25519 key its address, tab-separated.
+fn describe(key: &KeyFile) -> String {
+    match key.address() {
+        Some(address) => format!("{}\t{}", key.public_key, address),
+        None => key.public_key.clone(),
+    }
+}
+
+pub fn new_mnemonic(words: usize) -> Result<()> {
+    let phrase = keys::new_mnemonic(words)?;
+    eprintln!("Write this phrase down and keep it offline; it recovers every key derived from it");
+    println!("{}", phrase);
+    Ok(())
+}
+
+pub fn derive_key(global: &GlobalArgs, name: &str, path: &DerivationPath, mnemonic: &MnemonicArgs, args: &NewKeyArgs) -> Result<()> {
+    let seed = keys::seed(&keys::mnemonic(mnemonic.mnemonic_file.as_deref())?)?;
+    let secret = keys::derive(&seed, path, args.key_type)?;
+    let password = keys::password(args.password.password_file.as_deref(), true)?;
+    let keystore = Keystore::new(&global.data_dir);
+    let key = keystore.import(name, args.key_type, &secret, &password, args.kdf.params(), args.force)?;
+    println!("{}", describe(&key));
+    eprintln!("Saved {} to {}", path, keystore.path(name)?.display());
+    Ok(())
+}
+
+/// Derives `keys::STANDARD_KEYS`, all ed25519, under one password.
+pub fn recover_keys(global: &GlobalArgs, mnemonic: &MnemonicArgs, kdf: Kdf, force: bool, password: &PasswordArgs) -> Result<()> {
+    let seed = keys::seed(&keys::mnemonic(mnemonic.mnemonic_file.as_deref())?)?;
+    let password = keys::password(password.password_file.as_deref(), true)?;
+    let keystore = Keystore::new(&global.data_dir);
+    if !force {
+        if let Some(path) = STANDARD_KEYS.iter().map(|(name, _)| keystore.path(name)).find(|path| path.as_ref().is_ok_and(|path| path.exists())) {
+            bail!("{} already exists; pass --force to replace it", path?.display());
+        }
+    }
+    for (name, path) in STANDARD_KEYS {
+        let secret = keys::derive(&seed, &path.parse()?, KeyType::Ed25519)?;
+        let key = keystore.import(name, KeyType::Ed25519, &secret, &password, kdf.params(), force)?;
+        println!("{}\t{}\t{}", name, path, describe(&key));
+    }
+    Ok(())
+}
+
+/// What `args` pick to sign with: a Ledger, or a keystore key unlocked
+/// with its password.
+pub fn signer(global: &GlobalArgs, args: &SignerArgs) -> Result<Box<dyn Signer>> {
+    if args.ledger {
+        return Ok(Box::new(Ledger::connect(args.ledger_path.clone())?));
+    }
+    let key = Keystore::new(&global.data_dir).load(&args.key)?;
+    Ok(Box::new(key.signing_key(&keys::password(args.password.password_file.as_deref(), false)?)?))
+}
+
+pub fn ledger_address(path: DerivationPath) -> Result<()> {
+    let mut ledger = Ledger::connect(path)?;
+    println!("{}\t{}", ledger.public_key(), ledger.address());
+    ledger.confirm_address()?;
+    eprintln!("Approved on the Ledger");
+    Ok(())
+}
+
+/// Registers `node_id` with the hot keys `keys` names, unlocked with
+/// `password`, bonding `stake` from the reward account `signer` signs
+/// for.
+pub async fn register_validator(
+    global: &GlobalArgs,
+    node_id: &str,
+    stake: u64,
+    keys: &ValidatorKeyArgs,
+    password: &PasswordArgs,
+    signer: &mut dyn Signer,
+    rpc: &str,
+) -> Result<()> {
+    let keystore = Keystore::new(&global.data_dir);
+    let password = keys::password(password.password_file.as_deref(), false)?;
+    let consensus = keystore.load(&keys.consensus_key)?.signing_key(&password)?;
+    let network = keystore.load(&keys.network_key)?.signing_key(&password)?;
+    let registration = Registration::signed(node_id, &consensus, &network, &signer.address());
+    registration.verify().map_err(|e| anyhow!("{}; sign with the reward account, e.g. --key {} or --ledger", e, ACCOUNT_KEY))?;
+    let call = RegistryCall::Register(registration);
+    Sender::new(&OfflineArgs::default(), rpc)?.send(signer, call.transaction(stake), &FeeArgs::default()).await
+}
+
+/// Schedules `node_id`'s switch to the keystore key `new_key` at `epoch`,
+/// signed by what `args` pick, its current key. With `network_key`, the
+/// keys are unlocked with the signer's password to re-sign the
+/// validator's registration, and the signer is its reward account.
+pub async fn rotate_key(
+    global: &GlobalArgs,
+    node_id: &str,
+    new_key: &str,
+    network_key: Option<&str>,
+    epoch: u64,
+    args: &SignerArgs,
+    rpc: &str,
+) -> Result<()> {
+    let mut signer = signer(global, args)?;
+    let keystore = Keystore::new(&global.data_dir);
+    let key = keystore.load(new_key)?;
+    if key.key_type != KeyType::Ed25519 {
+        bail!("{} is a {} key; validators vote with ed25519 keys", new_key, key.key_type);
+    }
+    let registration = match network_key {
+        Some(network_key) => {
+            let password = keys::password(args.password.password_file.as_deref(), false)?;
+            let network = keystore.load(network_key)?.signing_key(&password)?;
+            let registration = Registration::signed(node_id, &key.signing_key(&password)?, &network, &signer.address());
+            registration.verify().map_err(|e| anyhow!("{}; sign with the reward account, e.g. --key {} or --ledger", e, ACCOUNT_KEY))?;
+            Some(registration)
+        }
+        None => None,
+    };
+    let rotation = KeyRotation { node_id: node_id.to_string(), public_key: key.public_key, epoch, registration };
+    Sender::new(&OfflineArgs::default(), rpc)?.send(signer.as_mut(), rotation.transaction(), &FeeArgs::default()).await?;
+    println!("Set consensus.next_validator_key to {} and restart before epoch {} begins", keystore.path(new_key)?.display(), epoch);
+    if let Some(network_key) = network_key {
+        println!("Set network.identity_key to {} and restart once epoch {} begins", keystore.path(network_key)?.display(), epoch);
+    }
+    Ok(())
+}
+
+pub async fn send_transaction(to: &str, value: u64, data: &str, signer: &mut dyn Signer, fee: &FeeArgs, rpc: &str) -> Result<()> {
+    address::check_account(to).with_context(|| format!("--to {}", to))?;
+    let data = hex::decode(data.trim_start_matches("0x")).context("--data must be hex")?;
+    let unsigned = Transaction { to: to.to_string(), value, data, ..Transaction::default() };
+    Sender::new(&OfflineArgs::default(), rpc)?.send(signer, unsigned, fee).await
+}
@@ -0,0 +1,621 @@
+//! Node configuration.
+//!
+//! Settings are layered, later layers winning: built-in defaults, the
+//! `config.toml` file, `CUBIQ_<SECTION>__<KEY>` environment variables
+//! (e.g. `CUBIQ_RPC__HTTP_ADDR=0.0.0.0:8545`), then command-line flags.
+//! Errors name the offending key, e.g. `consensus.stake`.
+
+use consensus::kv::Backend;
+use consensus::pruning::Pruning;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::{Table, Value};
+use tracing_subscriber::EnvFilter;
+
+use crate::logging::LogFormat;
+use crate::role::NodeRole;
+
+/// Prefix of configuration environment variables.
+pub const ENV_PREFIX: &str = "CUBIQ_";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct NodeConfig {
+    pub node: NodeSettings,
+    pub network: NetworkConfig,
+    pub consensus: ConsensusConfig,
+    pub resolver: ResolverConfig,
+    pub rpc: RpcConfig,
+    pub storage: StorageConfig,
+    pub metrics: MetricsConfig,
+    pub log: LogConfig,
+    pub telemetry: TelemetryConfig,
+    pub watchdog: WatchdogConfig,
+    pub clock: ClockConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct NodeSettings {
+    /// Which subsystems run: `validator`, `full`, `light` or `archive`
+    pub role: NodeRole,
+    /// Log filter, e.g. `info` or `warn,consensus=debug`
+    pub log_level: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct NetworkConfig {
+    /// Multiaddrs to listen on
+    pub listen_addrs: Vec<String>,
+    /// Multiaddrs, ending in `/p2p/<peer id>`, dialled at startup
+    pub bootnodes: Vec<String>,
+    /// Peers remembered across restarts; relative paths are under the
+    /// data dir
+    pub peer_store: PathBuf,
+    /// Encrypted key the node's peer id derives from, unlocked with the
+    /// validator key's password [default: a new peer id each start];
+    /// relative paths are under the data dir
+    pub identity_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConsensusConfig {
+    pub node_id: String,
+    /// Chain specification (JSON or TOML); relative paths are under the
+    /// data dir
+    pub chain_spec: PathBuf,
+    /// Encrypted key file; relative paths are under the data dir
+    pub validator_key: PathBuf,
+    /// File whose first line unlocks `validator_key` [default:
+    /// $CUBIQ_KEY_PASSWORD, else asked for]; relative paths are under
+    /// the data dir
+    pub validator_key_password_file: Option<PathBuf>,
+    /// Encrypted key the validator rotates to, unlocked with the same
+    /// password, which it votes with once the rotation takes effect;
+    /// relative paths are under the data dir
+    pub next_validator_key: Option<PathBuf>,
+    pub stake: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ResolverConfig {
+    /// Fallback proof endpoints
+    pub endpoints: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RpcConfig {
+    pub http_addr: SocketAddr,
+    pub ws_addr: SocketAddr,
+    pub grpc_addr: SocketAddr,
+    /// File holding the bearer token that unsafe methods require; without
+    /// one they are only served to callers on this machine
+    pub auth_token_file: Option<PathBuf>,
+    /// Also serve REST resources on `http_addr`
+    pub rest: bool,
+    /// Browser origins allowed to call the node, or "*" for any
+    pub cors_origins: Vec<String>,
+    /// Sustained JSON-RPC calls per second from one IP; 0 disables the limit
+    pub requests_per_second: u32,
+    /// Calls one IP may make at once above the sustained rate
+    pub request_burst: u32,
+    /// Reverse proxies whose `X-Forwarded-For` names the caller. A proxy
+    /// on this machine left out makes every caller local to unsafe methods
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct StorageConfig {
+    /// `sled`, `rocksdb`, or `memory` to keep nothing on disk, e.g. for a
+    /// throwaway devnet
+    pub backend: Backend,
+    /// Relative paths are under the data dir
+    pub db_path: PathBuf,
+    /// Recent blocks kept when pruning; archive nodes keep everything
+    pub retain_blocks: u64,
+    /// Earlier account states kept: `archive`, `keep-last-<N>` finalized
+    /// blocks or `finalized-only`; archive nodes keep everything
+    pub state_pruning: Pruning,
+    /// Archive nodes move blocks this far behind the head out of the
+    /// database into flat files under `<db_path>/freezer`; 0 keeps them in
+    /// the database
+    pub freeze_after: u64,
+    /// Seconds between background compactions of column families with
+    /// much compaction pending, which only RocksDB has; 0 disables them
+    pub compaction_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct MetricsConfig {
+    /// Serve Prometheus metrics at `http://<addr>/metrics`
+    pub enabled: bool,
+    pub addr: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct LogConfig {
+    /// `text`, `pretty` or `json`
+    pub format: LogFormat,
+    /// File to log to instead of stdout; relative paths are under the data
+    /// dir
+    pub file: Option<PathBuf>,
+    /// Size at which the file is rotated; 0 disables size rotation
+    pub rotate_bytes: u64,
+    /// Seconds after which the file is rotated; 0 disables time rotation
+    pub rotate_secs: u64,
+    /// Rotated files kept besides the current one
+    pub keep_files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct TelemetryConfig {
+    /// ws(s) URL of the collector to report to [default: no reporting]
+    pub url: Option<String>,
+    /// Name shown for the node [default: `consensus.node_id`]
+    pub name: Option<String>,
+    /// Seconds between reports
+    pub interval_secs: u64,
+}
+
+/// Thresholds of the resource watchdog; see `watchdog`. Past a soft
+/// threshold the node warns; past a hard one it stops admitting
+/// transactions and answering RPC calls until the resource recovers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct WatchdogConfig {
+    /// Seconds between checks; 0 disables the watchdog
+    pub interval_secs: u64,
+    /// Free space left on the data dir's filesystem
+    pub disk_soft_bytes: u64,
+    pub disk_hard_bytes: u64,
+    /// Resident memory, as a percentage of the machine's
+    pub memory_soft_percent: u8,
+    pub memory_hard_percent: u8,
+    /// Open file descriptors, as a percentage of the process's limit
+    pub fds_soft_percent: u8,
+    pub fds_hard_percent: u8,
+}
+
+/// Checks of the local clock against time servers; see `clock`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ClockConfig {
+    /// SNTP servers, as host:port
+    pub servers: Vec<String>,
+    /// Seconds between checks, the first at startup; 0 disables them
+    pub interval_secs: u64,
+    /// Offset from the servers' time beyond which the node warns
+    pub warn_skew_ms: u64,
+    /// Offset beyond which the node stops voting until its clock is fixed
+    pub max_skew_ms: u64,
+    /// How far ahead of the local clock a proposal's timestamp may be
+    pub proposal_skew_secs: u64,
+}
+
+impl Default for NodeSettings {
+    fn default() -> Self {
+        Self { role: NodeRole::default(), log_level: "info".to_string() }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec!["/ip4/0.0.0.0/tcp/30333".to_string()],
+            bootnodes: Vec::new(),
+            peer_store: PathBuf::from("peers.json"),
+            identity_key: None,
+        }
+    }
+}
+
+impl Default for ConsensusConfig {
+    fn default() -> Self {
+        Self {
+            node_id: "node1".to_string(),
+            chain_spec: PathBuf::from("genesis.json"),
+            validator_key: PathBuf::from("keys/validator.json"),
+            validator_key_password_file: None,
+            next_validator_key: None,
+            stake: 10_000,
+        }
+    }
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self { endpoints: vec!["https://zkproof.cubiq.dev".to_string()] }
+    }
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            http_addr: SocketAddr::from(([127, 0, 0, 1], 8545)),
+            ws_addr: SocketAddr::from(([127, 0, 0, 1], 8546)),
+            grpc_addr: SocketAddr::from(([127, 0, 0, 1], 9090)),
+            auth_token_file: None,
+            rest: false,
+            cors_origins: Vec::new(),
+            requests_per_second: 50,
+            request_burst: 100,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            db_path: PathBuf::from("db"),
+            retain_blocks: 100_000,
+            state_pruning: Pruning::default(),
+            freeze_after: 90_000,
+            compaction_interval_secs: 6 * 60 * 60,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { format: LogFormat::default(), file: None, rotate_bytes: 100 << 20, rotate_secs: 24 * 60 * 60, keep_files: 7 }
+    }
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self { url: None, name: None, interval_secs: 15 }
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 10,
+            disk_soft_bytes: 10 << 30,
+            disk_hard_bytes: 2 << 30,
+            memory_soft_percent: 80,
+            memory_hard_percent: 95,
+            fds_soft_percent: 80,
+            fds_hard_percent: 95,
+        }
+    }
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        Self {
+            servers: vec!["pool.ntp.org:123".to_string(), "time.cloudflare.com:123".to_string()],
+            interval_secs: 15 * 60,
+            warn_skew_ms: 500,
+            max_skew_ms: 5_000,
+            proposal_skew_secs: consensus::DEFAULT_CLOCK_SKEW.as_secs(),
+        }
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self { enabled: true, addr: SocketAddr::from(([127, 0, 0, 1], 9615)) }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    /// The file is not valid TOML
+    Parse(PathBuf, String),
+    /// A setting, from any layer, is unknown or has an unusable value
+    Invalid { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "reading {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Invalid { key, message } => write!(f, "invalid `{}`: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn invalid(key: &str, message: impl Into<String>) -> ConfigError {
+    ConfigError::Invalid { key: key.to_string(), message: message.into() }
+}
+
+impl NodeConfig {
+    /// Loads `path` (defaults when it doesn't exist and `required` is
+    /// false), applies environment and `overrides` (dotted key, value),
+    /// validates, and resolves relative paths against `data_dir`.
+    pub fn load(path: &Path, required: bool, data_dir: &Path, overrides: &[(&str, Value)]) -> Result<Self, ConfigError> {
+        let file = match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Table::new(),
+            Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+        };
+        Self::layered(file, std::env::vars(), overrides, data_dir)
+    }
+
+    fn layered(
+        file: Table,
+        env: impl IntoIterator<Item = (String, String)>,
+        overrides: &[(&str, Value)],
+        data_dir: &Path,
+    ) -> Result<Self, ConfigError> {
+        let mut merged = match Value::try_from(NodeConfig::default()) {
+            Ok(Value::Table(table)) => table,
+            _ => unreachable!("NodeConfig serializes to a table"),
+        };
+        merge(&mut merged, file);
+        for (name, raw) in env {
+            let Some(key) = name.strip_prefix(ENV_PREFIX).filter(|key| key.contains("__")) else {
+                continue;
+            };
+            let key = key.to_lowercase().replace("__", ".");
+            let value = env_value(&merged, &key, &raw);
+            set(&mut merged, &key, value)?;
+        }
+        for (key, value) in overrides {
+            set(&mut merged, key, value.clone())?;
+        }
+
+        let mut config: NodeConfig = serde_path_to_error::deserialize(Value::Table(merged))
+            .map_err(|e| invalid(&e.path().to_string(), e.inner().message()))?;
+        config.validate()?;
+        config.network.peer_store = data_dir.join(&config.network.peer_store);
+        config.network.identity_key = config.network.identity_key.map(|path| data_dir.join(path));
+        config.consensus.chain_spec = data_dir.join(&config.consensus.chain_spec);
+        config.consensus.validator_key = data_dir.join(&config.consensus.validator_key);
+        config.consensus.validator_key_password_file =
+            config.consensus.validator_key_password_file.map(|path| data_dir.join(path));
+        config.consensus.next_validator_key = config.consensus.next_validator_key.map(|path| data_dir.join(path));
+        config.storage.db_path = data_dir.join(&config.storage.db_path);
+        config.rpc.auth_token_file = config.rpc.auth_token_file.map(|path| data_dir.join(path));
+        config.log.file = config.log.file.map(|path| data_dir.join(path));
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if let Err(e) = EnvFilter::try_new(&self.node.log_level) {
+            return Err(invalid("node.log_level", e.to_string()));
+        }
+        if self.network.listen_addrs.is_empty() {
+            return Err(invalid("network.listen_addrs", "at least one listen address is required"));
+        }
+        if let Some(addr) = self.network.listen_addrs.iter().find(|addr| !addr.starts_with('/')) {
+            return Err(invalid("network.listen_addrs", format!("{:?} is not a multiaddr", addr)));
+        }
+        if let Some(addr) = self.network.bootnodes.iter().find(|addr| !addr.starts_with('/') || !addr.contains("/p2p/")) {
+            return Err(invalid("network.bootnodes", format!("{:?} must be a multiaddr ending in /p2p/<peer id>", addr)));
+        }
+        if self.consensus.node_id.is_empty() {
+            return Err(invalid("consensus.node_id", "must not be empty"));
+        }
+        if self.consensus.stake == 0 {
+            return Err(invalid("consensus.stake", "must be positive"));
+        }
+        if self.storage.retain_blocks == 0 {
+            return Err(invalid("storage.retain_blocks", "must be positive; use the archive role to keep all blocks"));
+        }
+        for origin in self.rpc.cors_origins.iter().filter(|origin| *origin != "*") {
+            match reqwest::Url::parse(origin) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") && url.origin().ascii_serialization() == *origin => {}
+                _ => return Err(invalid("rpc.cors_origins", format!("{:?} is not an origin like https://app.example", origin))),
+            }
+        }
+        if self.rpc.requests_per_second > 0 && self.rpc.request_burst == 0 {
+            return Err(invalid("rpc.request_burst", "must be positive while rpc.requests_per_second is set"));
+        }
+        for endpoint in &self.resolver.endpoints {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => return Err(invalid("resolver.endpoints", format!("{:?} is not an http(s) URL", endpoint))),
+            }
+        }
+        if let Some(url) = &self.telemetry.url {
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "ws" | "wss") => {}
+                _ => return Err(invalid("telemetry.url", format!("{:?} is not a ws(s) URL", url))),
+            }
+        }
+        if self.telemetry.interval_secs == 0 {
+            return Err(invalid("telemetry.interval_secs", "must be positive"));
+        }
+        let watchdog = &self.watchdog;
+        if watchdog.disk_soft_bytes < watchdog.disk_hard_bytes {
+            return Err(invalid("watchdog.disk_soft_bytes", "must be at least watchdog.disk_hard_bytes"));
+        }
+        let percents = [
+            ("memory", watchdog.memory_soft_percent, watchdog.memory_hard_percent),
+            ("fds", watchdog.fds_soft_percent, watchdog.fds_hard_percent),
+        ];
+        for (resource, soft, hard) in percents {
+            if hard > 100 {
+                return Err(invalid(&format!("watchdog.{}_hard_percent", resource), "must be at most 100"));
+            }
+            if soft > hard {
+                return Err(invalid(&format!("watchdog.{}_soft_percent", resource), format!("must be at most watchdog.{}_hard_percent", resource)));
+            }
+        }
+        if self.clock.interval_secs > 0 && self.clock.servers.is_empty() {
+            return Err(invalid("clock.servers", "at least one server is required while clock.interval_secs is set"));
+        }
+        if let Some(server) = self.clock.servers.iter().find(|server| server.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err())) {
+            return Err(invalid("clock.servers", format!("{:?} is not a host:port", server)));
+        }
+        if self.clock.warn_skew_ms > self.clock.max_skew_ms {
+            return Err(invalid("clock.warn_skew_ms", "must be at most clock.max_skew_ms"));
+        }
+        Ok(())
+    }
+
+    /// Blocks kept when pruning, or `None` when the role keeps all history.
+    pub fn retained_blocks(&self) -> Option<u64> {
+        (!self.node.role.keeps_history()).then_some(self.storage.retain_blocks)
+    }
+
+    /// How far behind the head blocks move to the freezer, or `None` when
+    /// they all stay in the database. Only roles keeping all history on
+    /// disk freeze, since frozen blocks are never pruned.
+    pub fn freeze_after(&self) -> Option<u64> {
+        let keeps_blocks = self.node.role.keeps_history() && self.storage.backend.persistent();
+        (keeps_blocks && self.storage.freeze_after > 0).then_some(self.storage.freeze_after)
+    }
+
+    /// Time between background compactions, or `None` when disabled.
+    pub fn compaction_interval(&self) -> Option<Duration> {
+        (self.storage.compaction_interval_secs > 0).then(|| Duration::from_secs(self.storage.compaction_interval_secs))
+    }
+
+    /// How account state is pruned, given the role.
+    pub fn state_pruning(&self) -> Pruning {
+        if self.node.role.keeps_history() {
+            Pruning::Archive
+        } else {
+            self.storage.state_pruning
+        }
+    }
+
+    /// The configuration as it would appear in `config.toml`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("NodeConfig serializes to TOML")
+    }
+}
+
+/// Recursively overlays `layer` onto `base`.
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Sets a dotted `key`, which must name an existing setting.
+fn set(table: &mut Table, key: &str, value: Value) -> Result<(), ConfigError> {
+    let (section, name) = key.split_once('.').ok_or_else(|| invalid(key, "unknown setting"))?;
+    match table.get_mut(section) {
+        Some(Value::Table(section)) if section.contains_key(name) => {
+            section.insert(name.to_string(), value);
+            Ok(())
+        }
+        _ => Err(invalid(key, "unknown setting")),
+    }
+}
+
+/// Environment values are TOML literals where they parse as one, else
+/// strings. Lists may also be given comma-separated.
+fn env_value(table: &Table, key: &str, raw: &str) -> Value {
+    let current = key
+        .split_once('.')
+        .and_then(|(section, name)| table.get(section)?.get(name));
+    if let Ok(parsed) = format!("v = {}", raw).parse::<Table>() {
+        if let Some(value) = parsed.get("v") {
+            return value.clone();
+        }
+    }
+    match current {
+        Some(Value::Array(_)) => Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layered(file: &str, env: &[(&str, &str)], overrides: &[(&str, Value)]) -> Result<NodeConfig, ConfigError> {
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        NodeConfig::layered(file.parse().unwrap(), env, overrides, Path::new("/data"))
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let file = r#"
+            [consensus]
+            stake = 500
+            node_id = "from-file"
+
+            [rpc]
+            http_addr = "0.0.0.0:9000"
+        "#;
+        let env = [
+            ("CUBIQ_CONSENSUS__STAKE", "700"),
+            ("CUBIQ_NETWORK__BOOTNODES", "/ip4/10.0.0.1/tcp/30333/p2p/12D3KooWA, /dns/boot.cubiq.dev/tcp/30333/p2p/12D3KooWB"),
+            ("CUBIQ_DATA_DIR", "/ignored"),
+            ("CUBIQ_STORAGE__STATE_PRUNING", "keep-last-16"),
+            ("CUBIQ_METRICS__ENABLED", "false"),
+            ("CUBIQ_LOG__FORMAT", "json"),
+        ];
+        let config = layered(file, &env, &[("consensus.node_id", Value::from("from-flag"))]).unwrap();
+
+        assert_eq!(config.consensus.stake, 700);
+        assert_eq!(config.consensus.node_id, "from-flag");
+        assert_eq!(config.rpc.http_addr, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.network.bootnodes.len(), 2);
+        assert_eq!(config.resolver.endpoints, ResolverConfig::default().endpoints);
+        assert_eq!(config.consensus.validator_key, PathBuf::from("/data/keys/validator.json"));
+        assert_eq!(config.state_pruning(), Pruning::KeepLast(16));
+        assert_eq!(config.metrics, MetricsConfig { enabled: false, ..MetricsConfig::default() });
+        assert_eq!(config.log.format, LogFormat::Json);
+
+        let archive = layered("[node]\nrole = \"full\"", &[("CUBIQ_NODE__ROLE", "archive")], &[]).unwrap();
+        assert_eq!(archive.node.role, NodeRole::Archive);
+        assert_eq!((archive.retained_blocks(), archive.state_pruning()), (None, Pruning::Archive));
+        assert_eq!((archive.freeze_after(), config.freeze_after()), (Some(90_000), None));
+        let devnet = layered("[node]\nrole = \"archive\"\n[storage]\nbackend = \"memory\"", &[], &[]).unwrap();
+        assert_eq!((devnet.storage.backend, devnet.freeze_after()), (Backend::Memory, None));
+        let uncompacted = layered("[storage]\ncompaction_interval_secs = 0", &[], &[]).unwrap();
+        assert_eq!((uncompacted.compaction_interval(), config.compaction_interval()), (None, Some(Duration::from_secs(21_600))));
+    }
+
+    #[test]
+    fn errors_name_the_offending_key() {
+        let key = |result: Result<NodeConfig, ConfigError>| match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected an invalid setting, got {:?}", other),
+        };
+        assert_eq!(key(layered("[rpc]\nhttp_addr = \"localhost\"", &[], &[])), "rpc.http_addr");
+        assert_eq!(key(layered("", &[("CUBIQ_CONSENSUS__STAKE", "lots")], &[])), "consensus.stake");
+        assert_eq!(key(layered("", &[("CUBIQ_RPC__HTTP_ADR", "0.0.0.0:1")], &[])), "rpc.http_adr");
+        assert_eq!(key(layered("[resolver]\nendpoints = [\"ftp://x\"]", &[], &[])), "resolver.endpoints");
+        assert_eq!(key(layered("[consensus]\nstake = 0", &[], &[])), "consensus.stake");
+        assert_eq!(key(layered("[node]\nrole = \"observer\"", &[], &[])), "node.role");
+        assert_eq!(key(layered("[storage]\nstate_pruning = \"keep-last-0\"", &[], &[])), "storage.state_pruning");
+        assert_eq!(key(layered("[rpc]\ncors_origins = [\"https://app.example/\"]", &[], &[])), "rpc.cors_origins");
+        assert_eq!(key(layered("[telemetry]\nurl = \"https://telemetry.example\"", &[], &[])), "telemetry.url");
+        assert_eq!(key(layered("[watchdog]\nfds_soft_percent = 99", &[], &[])), "watchdog.fds_soft_percent");
+        assert_eq!(key(layered("[clock]\nservers = [\"pool.ntp.org\"]", &[], &[])), "clock.servers");
+        assert!(key(layered("[network]\nlisten = []", &[], &[])).starts_with("network"));
+    }
+
+    #[test]
+    fn default_config_round_trips_through_toml() {
+        let config = NodeConfig::default();
+        let parsed: NodeConfig = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(parsed, config);
+    }
+}
@@ -0,0 +1,161 @@
+//! Versioned data-dir layout and its migrations.
+//!
+//! The layout version is stored in `<data-dir>/VERSION`; data dirs created
+//! before versioning have no such file and count as version 0. On startup
+//! each pending migration runs in order, after the data dir is copied to
+//! `<data-dir>/backups/v<version>-<unix time>`. A node refuses to start on
+//! a data dir written by a newer version.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const VERSION_FILE: &str = "VERSION";
+pub const BACKUPS_DIR: &str = "backups";
+
+/// Layout written by this version of the node.
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// One step of the layout, from version `to - 1` to `to`.
+#[derive(Debug)]
+pub struct Migration {
+    pub to: u32,
+    pub description: &'static str,
+    pub apply: fn(&Path) -> Result<()>,
+}
+
+/// Every migration, in order; the last one's `to` is `LAYOUT_VERSION`.
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    to: 1,
+    description: "record the layout version",
+    apply: |_| Ok(()),
+}];
+
+/// Reads the layout version; 0 when the data dir predates versioning.
+pub fn version(data_dir: &Path) -> Result<u32> {
+    let path = data_dir.join(VERSION_FILE);
+    match fs::read_to_string(&path) {
+        Ok(text) => text.trim().parse().with_context(|| format!("{} is not a layout version", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+pub fn write_version(data_dir: &Path, version: u32) -> Result<()> {
+    let path = data_dir.join(VERSION_FILE);
+    fs::write(&path, format!("{}\n", version)).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Brings `data_dir` to `LAYOUT_VERSION`, returning the migrations that
+/// ran, or with `dry_run` that would run.
+pub fn migrate(data_dir: &Path, dry_run: bool) -> Result<Vec<&'static Migration>> {
+    migrate_with(data_dir, MIGRATIONS, LAYOUT_VERSION, dry_run)
+}
+
+fn migrate_with<'a>(data_dir: &Path, migrations: &'a [Migration], target: u32, dry_run: bool) -> Result<Vec<&'a Migration>> {
+    let current = version(data_dir)?;
+    if current > target {
+        bail!(
+            "{} has layout version {}, but this node only understands up to {}; upgrade the node",
+            data_dir.display(),
+            current,
+            target
+        );
+    }
+    let pending: Vec<_> = migrations.iter().filter(|m| m.to > current && m.to <= target).collect();
+    if dry_run || pending.is_empty() {
+        return Ok(pending);
+    }
+
+    let backup = backup(data_dir, current)?;
+    tracing::info!(from = current, to = target, backup = %backup.display(), "migrating data dir");
+    for migration in &pending {
+        (migration.apply)(data_dir).with_context(|| {
+            format!("migration to layout {} ({}); backup in {}", migration.to, migration.description, backup.display())
+        })?;
+        write_version(data_dir, migration.to)?;
+        tracing::info!(version = migration.to, "{}", migration.description);
+    }
+    Ok(pending)
+}
+
+/// Copies everything but earlier backups to a new backup directory.
+fn backup(data_dir: &Path, version: u32) -> Result<PathBuf> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    let backup = data_dir.join(BACKUPS_DIR).join(format!("v{}-{}", version, now));
+    fs::create_dir_all(&backup).with_context(|| format!("creating {}", backup.display()))?;
+    for entry in fs::read_dir(data_dir).with_context(|| format!("reading {}", data_dir.display()))? {
+        let entry = entry?;
+        if entry.file_name() != BACKUPS_DIR {
+            copy(&entry.path(), &backup.join(entry.file_name()))?;
+        }
+    }
+    Ok(backup)
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to).with_context(|| format!("creating {}", to.display()))?;
+        for entry in fs::read_dir(from).with_context(|| format!("reading {}", from.display()))? {
+            let entry = entry?;
+            copy(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to).with_context(|| format!("copying {} to {}", from.display(), to.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_MIGRATIONS: &[Migration] = &[
+        Migration { to: 1, description: "record the layout version", apply: |_| Ok(()) },
+        Migration {
+            to: 2,
+            description: "move peers into network/",
+            apply: |dir| {
+                fs::create_dir_all(dir.join("network"))?;
+                Ok(fs::rename(dir.join("peers.json"), dir.join("network/peers.json"))?)
+            },
+        },
+    ];
+
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubiq-datadir-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("keys")).unwrap();
+        fs::write(dir.join("peers.json"), "[]").unwrap();
+        fs::write(dir.join("keys/validator.key"), "00").unwrap();
+        dir
+    }
+
+    #[test]
+    fn migrates_unversioned_dirs_after_a_backup() {
+        let dir = data_dir("migrate");
+        assert_eq!(migrate_with(&dir, TEST_MIGRATIONS, 2, true).unwrap().len(), 2);
+        assert_eq!(version(&dir).unwrap(), 0);
+        assert!(dir.join("peers.json").exists());
+
+        let ran = migrate_with(&dir, TEST_MIGRATIONS, 2, false).unwrap();
+        assert_eq!(ran.iter().map(|m| m.to).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(version(&dir).unwrap(), 2);
+        assert!(dir.join("network/peers.json").exists());
+        let backups: Vec<_> = fs::read_dir(dir.join(BACKUPS_DIR)).unwrap().collect();
+        assert_eq!(backups.len(), 1);
+        let backup = backups[0].as_ref().unwrap().path();
+        assert!(backup.join("peers.json").exists() && backup.join("keys/validator.key").exists());
+
+        assert!(migrate_with(&dir, TEST_MIGRATIONS, 2, false).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_data_dirs_from_newer_nodes() {
+        let dir = data_dir("newer");
+        write_version(&dir, LAYOUT_VERSION + 1).unwrap();
+        assert!(migrate(&dir, false).unwrap_err().to_string().contains("upgrade the node"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
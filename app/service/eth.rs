@@ -0,0 +1,398 @@
+//! Ethereum JSON-RPC compatibility, so wallets and indexers that only
+//! speak `eth_*` can read the chain and submit transactions.
+//!
+//! Quantities are 0x-prefixed hex as Ethereum tools expect. The mapping
+//! is not exact:
+//!
+//! - Block numbers are finalized heights; `latest`, `safe` and
+//!   `finalized` all mean the finalized head, `earliest` the lowest height
+//!   whose block the node still holds, and `pending` is refused. Only
+//!   blocks the node still holds can be fetched, and receipts only appear
+//!   once a transaction's block is finalized.
+//! - Balances and transaction counts are those after the newest verified
+//!   block, whatever block is asked for.
+//! - `eth_sendRawTransaction` takes a hex-encoded JSON Cubiq transaction,
+//!   of any kind. RLP-encoded, secp256k1-signed Ethereum transactions are
+//!   rejected, as senders sign with their ed25519 keys.
+//! - `eth_gasPrice` is the next block's base fee plus the suggested tip,
+//!   and `eth_maxPriorityFeePerGas` the tip, as `fee_estimate` has them.
+//! - `eth_call` and `eth_estimateGas` simulate the call as its sender's
+//!   next transaction in the next block, whatever block is asked for, so
+//!   the sender must be able to pay for its gas. A call with no `to`
+//!   deploys its data, and one to a contract calls it. `eth_call` fails
+//!   if the call would and returns what an EVM contract returned.
+//! - Receipts carry an EVM contract's logs, and a transaction that failed
+//!   has status 0.
+//! - Accounts keep their Cubiq form, and `0x` followed by an address's
+//!   32 bytes in hex names the same account. On a chain that runs the
+//!   EVM, an Ethereum address is the account of that address in
+//!   lowercase hex and a sender calls from the address `consensus::evm`
+//!   derives from its address; elsewhere 20-byte addresses only match
+//!   accounts created with that exact string.
+
+use consensus::address::Address;
+use consensus::events::TxStatus;
+use consensus::fees;
+use consensus::receipts::{Receipt, TxEvent};
+use consensus::state::{Account, StateTrie};
+use consensus::{BlockProposal, QubeNode, Transaction, TxKind};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::rpc_server::{error, param, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND, SERVER_ERROR};
+
+pub const CLIENT_VERSION: &str = concat!("cubiq/v", env!("CARGO_PKG_VERSION"));
+
+/// Whether `method` belongs to the shim.
+pub fn handles(method: &str) -> bool {
+    method.starts_with("eth_") || method.starts_with("net_") || method.starts_with("web3_")
+}
+
+pub async fn call(consensus: &QubeNode, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    match method {
+        "web3_clientVersion" => Ok(json!(CLIENT_VERSION)),
+        "net_version" => Ok(json!(consensus.chain_id.to_string())),
+        "eth_chainId" => Ok(quantity(consensus.chain_id)),
+        "eth_syncing" => Ok(Value::Bool(false)),
+        "eth_blockNumber" => Ok(quantity(finalized_height(consensus).await)),
+        "eth_gasPrice" => {
+            let estimate = consensus.fee_estimate().await;
+            Ok(quantity(estimate.base_fee.saturating_add(estimate.max_priority_fee_per_gas)))
+        }
+        "eth_maxPriorityFeePerGas" => Ok(quantity(consensus.fee_estimate().await.max_priority_fee_per_gas)),
+        "eth_getTransactionCount" => {
+            let address: String = param(params, 0, "address")?;
+            let state = consensus.consensus_state.read().await;
+            Ok(quantity(state.accounts.get(&address).nonce))
+        }
+        "eth_getBalance" => {
+            let address: String = param(params, 0, "address")?;
+            let state = consensus.consensus_state.read().await;
+            Ok(quantity(state.accounts.get(&resolve(&state.accounts, &address)).balance))
+        }
+        "eth_call" => {
+            let call: CallRequest = param(params, 0, "call")?;
+            let tx = call.into_transaction(consensus).await?;
+            let simulation = consensus.simulate(&tx).await.map_err(|e| error(SERVER_ERROR, e))?;
+            match simulation.error {
+                Some(e) => Err(error(SERVER_ERROR, format!("execution failed: {}", e))),
+                None => Ok(json!(format!("0x{}", hex::encode(simulation.output)))),
+            }
+        }
+        "eth_estimateGas" => {
+            let call: CallRequest = param(params, 0, "call")?;
+            let tx = call.into_transaction(consensus).await?;
+            let simulation = consensus.simulate(&tx).await.map_err(|e| error(SERVER_ERROR, e))?;
+            match simulation.error {
+                Some(e) => Err(error(SERVER_ERROR, format!("execution failed: {}", e))),
+                None => Ok(quantity(simulation.gas_used)),
+            }
+        }
+        "eth_sendRawTransaction" => {
+            let raw: String = param(params, 0, "data")?;
+            let bytes = hex::decode(raw.trim_start_matches("0x"))
+                .map_err(|e| error(INVALID_PARAMS, format!("data is not hex: {}", e)))?;
+            let tx: Transaction = serde_json::from_slice(&bytes).map_err(|_| {
+                error(INVALID_PARAMS, "data must be a hex-encoded JSON Cubiq transaction; RLP transactions are not supported")
+            })?;
+            consensus.submit_transaction(tx).await.map(Value::String).map_err(|e| error(SERVER_ERROR, e))
+        }
+        "eth_getTransactionReceipt" => {
+            let hash: String = param(params, 0, "hash")?;
+            let receipt = consensus.consensus_state.read().await.receipts.get(&hash).cloned();
+            match receipt {
+                Some(receipt) if receipt.status == TxStatus::Finalized => Ok(eth_receipt(consensus, receipt).await),
+                // Ethereum has no receipt for pending or dropped transactions
+                _ => Ok(Value::Null),
+            }
+        }
+        "eth_getBlockByHash" => {
+            let hash: String = param(params, 0, "hash")?;
+            let full: bool = param(params, 1, "full").unwrap_or(false);
+            let height = consensus.consensus_state.read().await.finalized_blocks.iter().position(|h| *h == hash);
+            match (height, consensus.block(&hash).await) {
+                (Some(height), Some(block)) => Ok(eth_block(consensus, &block, height as u64 + 1, full).await),
+                _ => Ok(Value::Null),
+            }
+        }
+        "eth_getBlockByNumber" => {
+            let tag: String = param(params, 0, "block")?;
+            let full: bool = param(params, 1, "full").unwrap_or(false);
+            let height = match tag.as_str() {
+                "latest" | "safe" | "finalized" => finalized_height(consensus).await,
+                "earliest" => consensus.earliest_height().await,
+                "pending" => return Err(error(INVALID_PARAMS, "pending blocks are not exposed")),
+                number => parse_quantity(number).ok_or_else(|| error(INVALID_PARAMS, format!("bad block number {}", number)))?,
+            };
+            let hash = {
+                let state = consensus.consensus_state.read().await;
+                height.checked_sub(1).and_then(|index| state.finalized_blocks.get(index as usize).cloned())
+            };
+            let block = match hash {
+                Some(hash) => consensus.block(&hash).await,
+                None => None,
+            };
+            match block {
+                Some(block) => Ok(eth_block(consensus, &block, height, full).await),
+                None => Ok(Value::Null),
+            }
+        }
+        _ => Err(error(METHOD_NOT_FOUND, format!("{} is not supported by the eth compatibility layer", method))),
+    }
+}
+
+/// An `eth_call`-style transaction object; all of it optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallRequest {
+    from: Option<String>,
+    to: Option<String>,
+    value: Option<String>,
+    gas: Option<String>,
+    gas_price: Option<String>,
+    max_fee_per_gas: Option<String>,
+    max_priority_fee_per_gas: Option<String>,
+    #[serde(alias = "input")]
+    data: Option<String>,
+}
+
+impl CallRequest {
+    async fn into_transaction(self, consensus: &QubeNode) -> Result<Transaction, RpcError> {
+        let number = |field: &str, text: Option<String>| match text {
+            Some(text) => parse_quantity(&text).ok_or_else(|| error(INVALID_PARAMS, format!("bad {} {}", field, text))),
+            None => Ok(0),
+        };
+        // A legacy gas price is both the cap and the tip
+        let gas_price = number("gasPrice", self.gas_price)?;
+        let max_fee_per_gas = number("maxFeePerGas", self.max_fee_per_gas)?.max(gas_price);
+        let max_priority_fee_per_gas = number("maxPriorityFeePerGas", self.max_priority_fee_per_gas)?.max(gas_price);
+        let data = match self.data {
+            Some(data) => hex::decode(data.trim_start_matches("0x")).map_err(|e| error(INVALID_PARAMS, format!("data is not hex: {}", e)))?,
+            None => vec![],
+        };
+        let state = consensus.consensus_state.read().await;
+        let to = self.to.map(|to| resolve(&state.accounts, &to)).unwrap_or_default();
+        let kind = match state.accounts.get(&to).contract {
+            _ if to.is_empty() && !data.is_empty() => TxKind::Deploy,
+            Some(_) => TxKind::Call,
+            None => TxKind::Transfer,
+        };
+        Ok(Transaction {
+            chain_id: consensus.chain_id,
+            from: resolve(&state.accounts, self.from.as_deref().unwrap_or_default()),
+            to,
+            kind,
+            value: number("value", self.value)?,
+            gas_limit: number("gas", self.gas)?,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            data,
+            ..Transaction::default()
+        })
+    }
+}
+
+/// `address` as the account that holds a balance or code: as given, as
+/// the address of the 32 bytes it may be in hex, or in lowercase.
+fn resolve(accounts: &StateTrie, address: &str) -> String {
+    let lowercase = address.to_ascii_lowercase();
+    let bytes = hex::decode(address.trim_start_matches("0x")).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+    let native = bytes.map(|bytes| Address::from_bytes(bytes).to_string()).unwrap_or_default();
+    let candidates = [address, &native, &lowercase];
+    let found = candidates.into_iter().find(|key| accounts.get(key) != Account::default());
+    found.unwrap_or(address).to_string()
+}
+
+async fn finalized_height(consensus: &QubeNode) -> u64 {
+    consensus.consensus_state.read().await.finalized_blocks.len() as u64
+}
+
+fn quantity(n: u64) -> Value {
+    json!(format!("0x{:x}", n))
+}
+
+fn parse_quantity(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+async fn eth_receipt(consensus: &QubeNode, receipt: Receipt) -> Value {
+    let (block, height) = match &receipt.block_hash {
+        Some(hash) => {
+            let position = consensus.consensus_state.read().await.finalized_blocks.iter().position(|h| h == hash);
+            (consensus.block(hash).await, position.map(|index| index as u64 + 1))
+        }
+        None => (None, None),
+    };
+    let tx = block.as_ref().and_then(|block| block.transactions.iter().find(|tx| tx.hash == receipt.tx_hash));
+    json!({
+        "transactionHash": receipt.tx_hash,
+        "blockHash": receipt.block_hash,
+        "blockNumber": height.map(quantity),
+        "from": tx.map(|tx| tx.from.clone()),
+        "to": tx.map(|tx| tx.to.clone()),
+        "gasUsed": quantity(receipt.gas_used),
+        "cumulativeGasUsed": quantity(receipt.gas_used),
+        "effectiveGasPrice": quantity(receipt.gas_price),
+        "logs": receipt.events.iter().filter(|event| event.kind == "log").map(eth_log).collect::<Vec<_>>(),
+        "status": quantity(u64::from(receipt.error.is_none())),
+        "type": quantity(2),
+    })
+}
+
+/// An EVM log as an event; a WASM contract's events of kind `log` have
+/// no address or topics.
+fn eth_log(event: &TxEvent) -> Value {
+    let attribute = |key: &str| event.attributes.get(key).map_or("", String::as_str);
+    let topics: Vec<&str> = attribute("topics").split(',').filter(|topic| !topic.is_empty()).collect();
+    json!({ "address": attribute("address"), "topics": topics, "data": attribute("data") })
+}
+
+async fn eth_block(consensus: &QubeNode, block: &BlockProposal, height: u64, full: bool) -> Value {
+    let gas_limit = consensus.consensus_state.read().await.params.block_gas_limit;
+    let transactions: Vec<Value> = block
+        .transactions
+        .iter()
+        .map(|tx| {
+            if full {
+                json!({
+                    "hash": tx.hash,
+                    "from": tx.from,
+                    "nonce": quantity(tx.nonce),
+                    "to": tx.to,
+                    "value": quantity(tx.value),
+                    "gas": quantity(tx.gas_limit),
+                    "gasPrice": fees::effective_tip(tx, block.base_fee).map(|tip| quantity(block.base_fee + tip)),
+                    "maxFeePerGas": quantity(tx.max_fee_per_gas),
+                    "maxPriorityFeePerGas": quantity(tx.max_priority_fee_per_gas),
+                    "type": quantity(2),
+                    "input": format!("0x{}", hex::encode(&tx.data)),
+                    "blockHash": block.block_hash,
+                    "blockNumber": quantity(height),
+                })
+            } else {
+                json!(tx.hash)
+            }
+        })
+        .collect();
+    json!({
+        "number": quantity(height),
+        "hash": block.block_hash,
+        "stateRoot": block.state_root,
+        "miner": block.proposer_id,
+        "timestamp": quantity(block.timestamp),
+        "gasUsed": quantity(block.gas_used),
+        "gasLimit": quantity(gas_limit),
+        "baseFeePerGas": quantity(block.base_fee),
+        "transactions": transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use ed25519_dalek::SigningKey;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    async fn consensus() -> QubeNode {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        node.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        node
+    }
+
+    #[tokio::test]
+    async fn reports_chain_state_as_hex_quantities() {
+        let consensus = consensus().await;
+        assert_eq!(call(&consensus, "eth_chainId", &[]).await.unwrap(), "0x7");
+        assert_eq!(call(&consensus, "net_version", &[]).await.unwrap(), "7");
+        assert_eq!(call(&consensus, "eth_blockNumber", &[]).await.unwrap(), "0x0");
+        // The least base fee and the default tip
+        assert_eq!(call(&consensus, "eth_gasPrice", &[]).await.unwrap(), "0x2");
+        assert_eq!(call(&consensus, "eth_maxPriorityFeePerGas", &[]).await.unwrap(), "0x1");
+        let balance = call(&consensus, "eth_getBalance", &[json!(format!("0x{}", crate::keys::public_key_hex(&signer()))), json!("latest")]).await;
+        assert_eq!(balance.unwrap(), "0x3b9aca00");
+        assert_eq!(call(&consensus, "eth_getBlockByNumber", &[json!("latest"), json!(false)]).await.unwrap(), Value::Null);
+        assert_eq!(call(&consensus, "eth_getLogs", &[]).await.unwrap_err().code, METHOD_NOT_FOUND);
+        assert!(handles("net_version") && !handles("chain_getBlock"));
+    }
+
+    #[tokio::test]
+    async fn accepts_hex_encoded_cubiq_transactions_only() {
+        let consensus = consensus().await;
+        let tx = Transaction {
+            chain_id: 7,
+            to: BOB.to_string(),
+            value: 5,
+            gas_limit: 21_000,
+            max_fee_per_gas: 1,
+            ..Transaction::default()
+        }
+        .sign(&signer());
+        let raw = format!("0x{}", hex::encode(serde_json::to_vec(&tx).unwrap()));
+        assert_eq!(call(&consensus, "eth_sendRawTransaction", &[json!(raw)]).await.unwrap(), tx.hash);
+        // Pending transactions have no Ethereum receipt
+        assert_eq!(call(&consensus, "eth_getTransactionReceipt", &[json!(tx.hash)]).await.unwrap(), Value::Null);
+
+        let rlp = call(&consensus, "eth_sendRawTransaction", &[json!("0xf86c0985")]).await.unwrap_err();
+        assert!(rlp.message.contains("RLP transactions are not supported"));
+    }
+
+    #[tokio::test]
+    async fn estimates_gas_by_simulating_the_call() {
+        let consensus = consensus().await;
+        let from = format!("0x{}", crate::keys::public_key_hex(&signer()));
+        let transfer = json!({ "from": from, "to": BOB, "value": "0x5" });
+        assert_eq!(call(&consensus, "eth_estimateGas", &[transfer, json!("latest")]).await.unwrap(), "0x5208");
+        let with_input = json!({ "from": from, "to": BOB, "input": "0x0001" });
+        assert_eq!(call(&consensus, "eth_estimateGas", &[with_input]).await.unwrap(), "0x521c");
+        assert_eq!(consensus.consensus_state.read().await.accounts.get(BOB).balance, 0);
+
+        let overdrawn = json!({ "from": from, "to": BOB, "value": "0x3b9aca00", "gas": "0x5208" });
+        assert!(call(&consensus, "eth_estimateGas", &[overdrawn]).await.unwrap_err().message.contains("insufficient balance"));
+        assert_eq!(call(&consensus, "eth_estimateGas", &[json!({ "value": "5" })]).await.unwrap_err().code, INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn calls_by_simulating_them() {
+        let consensus = consensus().await;
+        let from = format!("0x{}", crate::keys::public_key_hex(&signer()));
+        let transfer = json!({ "from": from, "to": BOB, "value": "0x5" });
+        assert_eq!(call(&consensus, "eth_call", &[transfer, json!("latest")]).await.unwrap(), "0x");
+        // With no recipient the data is deployed, and isn't a WASM module
+        let deploy = json!({ "from": from, "data": "0x6000" });
+        assert!(call(&consensus, "eth_call", &[deploy]).await.unwrap_err().message.contains("invalid code"));
+    }
+
+    #[cfg(feature = "evm")]
+    #[tokio::test]
+    async fn calls_evm_contracts() {
+        let consensus = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        let mut spec = ChainSpec::dev(7, validator, 0);
+        spec.params.vm = consensus::genesis::Vm::Evm;
+        consensus.load_genesis(&spec).await.unwrap();
+        let from = Address::from_key(&signer().verifying_key()).to_string();
+        // Init code returning the runtime STOP, a zero byte
+        let deploy = json!({ "from": from, "data": "0x60016000f3" });
+        assert_eq!(call(&consensus, "eth_call", &[deploy]).await.unwrap(), "0x00");
+        let reverts = json!({ "from": from, "data": "0x60006000fd" });
+        assert!(call(&consensus, "eth_call", &[reverts]).await.unwrap_err().message.contains("reverted"));
+    }
+}
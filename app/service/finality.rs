@@ -0,0 +1,140 @@
+//! Finality certificates: a finalized block's roots, the votes that
+//! finalized it and its proof. Validators gossip one for each block they
+//! see finalized, `chain_getFinalityCertificate` answers them by height,
+//! and light nodes, which follow nothing else, check the ones gossiped to
+//! them with `cubiq_light`.
+//!
+//! A light node trusts the chain spec's validators, for every epoch: it
+//! does not follow key rotations yet, so once one takes effect the
+//! certificates it is sent no longer check out.
+
+use anyhow::Result;
+use consensus::genesis::ChainSpec;
+use consensus::{QubeNode, ValidatorSet};
+use cubiq_light::validators::{SnapshotValidator, ValidatorSnapshot};
+use cubiq_light::LightClient;
+use networking::{Checkpoint, FinalityCertificate};
+
+use crate::node::bridge;
+
+/// The certificate of the block finalized at `height`, counting from 1,
+/// from the votes it has had. `None` unless the node holds the block's
+/// proof, having verified it; it is never fetched.
+pub async fn certificate(consensus: &QubeNode, height: u64) -> Option<FinalityCertificate> {
+    let (hash, mut votes) = {
+        let state = consensus.consensus_state.read().await;
+        let hash = state.finalized_blocks.get(height.checked_sub(1)? as usize)?.clone();
+        let votes: Vec<consensus::Vote> = state.votes.values().filter(|vote| vote.block_hash == hash).cloned().collect();
+        (hash, votes)
+    };
+    votes.sort_by(|a, b| a.voter_id.cmp(&b.voter_id));
+    let block = consensus.block(&hash).await?;
+    let bundle = consensus.zkurl_resolver.held_proof(&block.zkurl)?;
+    let header = block.header();
+    Some(FinalityCertificate {
+        checkpoint: Checkpoint {
+            height,
+            block_hash: header.block_hash,
+            state_root: header.state_root,
+            transactions_root: header.transactions_root,
+            gas_used: header.gas_used,
+            transaction_count: block.transactions.len() as u32,
+        },
+        votes: votes.iter().map(bridge).collect::<Result<_>>().ok()?,
+        proof: bundle.proof,
+    })
+}
+
+/// A light client of `spec`'s chain trusting `validators`, the set
+/// loaded from its genesis.
+pub fn light_client(spec: &ChainSpec, validators: &ValidatorSet) -> Result<LightClient> {
+    let snapshot = ValidatorSnapshot {
+        epoch: 0,
+        validators: validators
+            .validators
+            .values()
+            .map(|v| SnapshotValidator { node_id: v.node_id.clone(), public_key: v.public_key.clone(), stake: v.stake })
+            .collect(),
+        supermajority_threshold: validators.supermajority_threshold,
+    };
+    Ok(LightClient::new(spec.chain_id, spec.params.epoch_length, snapshot)?)
+}
+
+/// Checks a gossiped certificate and, if it holds, keeps its checkpoint.
+/// Returns whether it was new: copies other validators sent of a
+/// checkpoint already kept are not checked again.
+pub fn follow(light: &mut LightClient, certificate: &FinalityCertificate) -> Result<bool> {
+    let checkpoint = &certificate.checkpoint;
+    if light.checkpoint(checkpoint.height).is_some_and(|kept| kept.block_hash == checkpoint.block_hash) {
+        return Ok(false);
+    }
+    let certificate: cubiq_light::certificate::FinalityCertificate = bridge(certificate)?;
+    light.verify_certificate(&certificate)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::genesis::GenesisValidator;
+    use cubiq_light::LightError;
+    use ed25519_dalek::SigningKey;
+
+    async fn genesis() -> (ChainSpec, QubeNode) {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&SigningKey::from_bytes(&[1; 32])),
+            stake: 10,
+            registration: None,
+        };
+        let spec = ChainSpec::dev(7, validator, 0);
+        node.load_genesis(&spec).await.unwrap();
+        (spec, node)
+    }
+
+    fn certificate(votes: Vec<networking::Vote>) -> FinalityCertificate {
+        FinalityCertificate {
+            checkpoint: Checkpoint {
+                height: 1,
+                block_hash: "0xabc".to_string(),
+                state_root: format!("0x{}", "00".repeat(32)),
+                transactions_root: format!("0x{}", "00".repeat(32)),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            votes,
+            proof: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn light_nodes_trust_the_genesis_validators_only() {
+        let (spec, consensus) = genesis().await;
+        let mut light = light_client(&spec, &*consensus.validator_set.read().await).unwrap();
+        let snapshot = light.snapshot_at(spec.params.epoch_length * 3).unwrap();
+        assert_eq!(snapshot.validator("v1").map(|v| v.stake), Some(10));
+        assert_eq!(snapshot.supermajority_threshold, consensus.validator_set.read().await.supermajority_threshold);
+
+        let unsigned = certificate(vec![]);
+        let threshold = snapshot.supermajority_threshold;
+        let err = follow(&mut light, &unsigned).unwrap_err();
+        assert_eq!(err.downcast_ref::<LightError>(), Some(&LightError::InsufficientStake { signed: 0, threshold }));
+
+        let mut malformed = certificate(vec![]);
+        malformed.checkpoint.state_root = "0xdef".to_string();
+        assert!(follow(&mut light, &malformed).unwrap_err().downcast_ref::<LightError>().is_none());
+
+        // Signed by a supermajority, but with no proof
+        let vote = consensus::Vote {
+            block_hash: "0xabc".to_string(),
+            voter_id: "v1".to_string(),
+            stake: 10,
+            ..consensus::Vote::default()
+        };
+        let signed = certificate(vec![bridge(&vote.sign(&SigningKey::from_bytes(&[1; 32]))).unwrap()]);
+        let err = follow(&mut light, &signed).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LightError>(), Some(LightError::InvalidProof(_))));
+        assert_eq!(light.head(), None);
+    }
+}
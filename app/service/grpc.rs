@@ -0,0 +1,369 @@
+//! gRPC API on `rpc.grpc_addr`, defined in `proto/cubiq.proto`.
+//!
+//! Serves the same chain queries as JSON-RPC for integrations that prefer
+//! generated clients, plus a server stream of finalized blocks. Calls are
+//! admitted by the same `access::Policy` as JSON-RPC's; none is unsafe, so
+//! they are only rate limited.
+
+use anyhow::Result;
+use consensus::events::ConsensusEvent;
+use consensus::QubeNode;
+use futures::stream::{self, Stream};
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::service::Interceptor;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("cubiq.v1");
+}
+
+use crate::access::{Policy, LIMIT_EXCEEDED};
+use proto::chain_server::{self, ChainServer};
+
+/// What calls to the Chain service count as under `Policy::permit`.
+const SERVICE: &str = "cubiq.v1.Chain";
+
+/// Serves requests on `listener` until `shutdown` completes.
+pub async fn serve(
+    listener: TcpListener,
+    consensus: Arc<QubeNode>,
+    policy: Arc<Policy>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let service = ChainServer::with_interceptor(ChainService { consensus }, Admit(policy));
+    Server::builder()
+        .add_service(service)
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await?;
+    Ok(())
+}
+
+/// Passes requests on if the policy admits their caller.
+#[derive(Clone)]
+struct Admit(Arc<Policy>);
+
+impl Interceptor for Admit {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let addr = request.remote_addr().unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+        let caller = self.0.caller(addr, &request.metadata().clone().into_headers());
+        match self.0.permit(&caller, SERVICE) {
+            Ok(()) => Ok(request),
+            Err(e) if e.code == LIMIT_EXCEEDED => Err(Status::resource_exhausted(e.message)),
+            Err(e) => Err(Status::permission_denied(e.message)),
+        }
+    }
+}
+
+struct ChainService {
+    consensus: Arc<QubeNode>,
+}
+
+type FinalizedBlocks = Pin<Box<dyn Stream<Item = Result<proto::FinalizedBlock, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl chain_server::Chain for ChainService {
+    async fn get_block(&self, request: Request<proto::GetBlockRequest>) -> Result<Response<proto::Block>, Status> {
+        let hash = request.into_inner().block_hash;
+        match self.consensus.block(&hash).await {
+            Some(block) => Ok(Response::new(block.into())),
+            None => Err(Status::not_found(format!("no recent block {}", hash))),
+        }
+    }
+
+    async fn get_finalized_head(
+        &self,
+        _request: Request<proto::GetFinalizedHeadRequest>,
+    ) -> Result<Response<proto::FinalizedHead>, Status> {
+        let state = self.consensus.consensus_state.read().await;
+        match state.finalized_blocks.last() {
+            Some(hash) => Ok(Response::new(proto::FinalizedHead {
+                block_hash: hash.clone(),
+                height: state.finalized_blocks.len() as u64,
+            })),
+            None => Err(Status::not_found("no block is finalized yet")),
+        }
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let hash = self.consensus.submit_transaction(request.into_inner().into()).await.map_err(Status::failed_precondition)?;
+        Ok(Response::new(proto::SubmitTransactionResponse { hash }))
+    }
+
+    async fn get_votes(&self, request: Request<proto::GetVotesRequest>) -> Result<Response<proto::Votes>, Status> {
+        let hash = request.into_inner().block_hash;
+        let state = self.consensus.consensus_state.read().await;
+        let mut votes: Vec<proto::Vote> = state
+            .votes
+            .values()
+            .filter(|vote| vote.block_hash == hash)
+            .map(|vote| proto::Vote {
+                block_hash: vote.block_hash.clone(),
+                voter_id: vote.voter_id.clone(),
+                stake: vote.stake,
+                timestamp: vote.timestamp,
+                signature: vote.signature.clone(),
+                state_root: vote.state_root.to_string(),
+                transactions_root: vote.transactions_root.to_string(),
+                gas_used: vote.gas_used,
+                transaction_count: vote.transaction_count,
+            })
+            .collect();
+        votes.sort_by(|a, b| a.voter_id.cmp(&b.voter_id));
+        Ok(Response::new(proto::Votes { votes }))
+    }
+
+    async fn get_validator_set(
+        &self,
+        _request: Request<proto::GetValidatorSetRequest>,
+    ) -> Result<Response<proto::ValidatorSet>, Status> {
+        let set = self.consensus.validator_set.read().await;
+        let mut validators: Vec<proto::Validator> = set
+            .validators
+            .values()
+            .map(|v| proto::Validator {
+                node_id: v.node_id.clone(),
+                stake: v.stake,
+                public_key: v.public_key.clone(),
+                is_active: v.is_active,
+                reward_account: set.account(&v.node_id).unwrap_or_default(),
+                network_key: v.registration.as_ref().map(|r| r.network_key.clone()).unwrap_or_default(),
+            })
+            .collect();
+        validators.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        Ok(Response::new(proto::ValidatorSet {
+            validators,
+            total_stake: set.total_stake,
+            supermajority_threshold: set.supermajority_threshold,
+        }))
+    }
+
+    type StreamFinalizedBlocksStream = FinalizedBlocks;
+
+    async fn stream_finalized_blocks(
+        &self,
+        _request: Request<proto::StreamFinalizedBlocksRequest>,
+    ) -> Result<Response<FinalizedBlocks>, Status> {
+        let events = self.consensus.subscribe();
+        let consensus = Arc::clone(&self.consensus);
+        // The state is `None` once the stream has ended with an error
+        let blocks = stream::unfold(Some((events, consensus)), |state| async move {
+            let (mut events, consensus) = state?;
+            loop {
+                match events.recv().await {
+                    Ok(ConsensusEvent::Finalized { block_hash, height }) => {
+                        let block = consensus.block(&block_hash).await.map(Into::into);
+                        let finalized = proto::FinalizedBlock { block_hash, height, block };
+                        return Some((Ok(finalized), Some((events, consensus))));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        let status = Status::data_loss(format!("missed {} events; resume from GetFinalizedHead", missed));
+                        return Some((Err(status), None));
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(blocks)))
+    }
+}
+
+impl From<consensus::Transaction> for proto::Transaction {
+    fn from(tx: consensus::Transaction) -> Self {
+        Self {
+            hash: tx.hash,
+            chain_id: tx.chain_id,
+            nonce: tx.nonce,
+            from: tx.from,
+            to: tx.to,
+            value: tx.value,
+            gas_limit: tx.gas_limit,
+            max_fee_per_gas: tx.max_fee_per_gas,
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+            kind: proto::TxKind::from(tx.kind).into(),
+            data: tx.data,
+            signature: tx.signature,
+            cosignatures: tx.cosignatures.into_iter().map(|c| proto::Cosignature { public_key: c.public_key, signature: c.signature }).collect(),
+        }
+    }
+}
+
+impl From<proto::Transaction> for consensus::Transaction {
+    fn from(tx: proto::Transaction) -> Self {
+        // An unknown kind reads as a transfer, and so fails its signature
+        let kind = tx.kind().into();
+        Self {
+            hash: tx.hash,
+            chain_id: tx.chain_id,
+            nonce: tx.nonce,
+            from: tx.from,
+            to: tx.to,
+            value: tx.value,
+            gas_limit: tx.gas_limit,
+            max_fee_per_gas: tx.max_fee_per_gas,
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas,
+            kind,
+            data: tx.data,
+            signature: tx.signature,
+            cosignatures: tx.cosignatures.into_iter().map(|c| consensus::Cosignature { public_key: c.public_key, signature: c.signature }).collect(),
+        }
+    }
+}
+
+impl From<consensus::TxKind> for proto::TxKind {
+    fn from(kind: consensus::TxKind) -> Self {
+        match kind {
+            consensus::TxKind::Transfer => Self::Transfer,
+            consensus::TxKind::Deploy => Self::Deploy,
+            consensus::TxKind::Call => Self::Call,
+            consensus::TxKind::CreateMultisig => Self::CreateMultisig,
+            consensus::TxKind::RotateKey => Self::RotateKey,
+            consensus::TxKind::Bridge => Self::Bridge,
+        }
+    }
+}
+
+impl From<proto::TxKind> for consensus::TxKind {
+    fn from(kind: proto::TxKind) -> Self {
+        match kind {
+            proto::TxKind::Transfer => Self::Transfer,
+            proto::TxKind::Deploy => Self::Deploy,
+            proto::TxKind::Call => Self::Call,
+            proto::TxKind::CreateMultisig => Self::CreateMultisig,
+            proto::TxKind::RotateKey => Self::RotateKey,
+            proto::TxKind::Bridge => Self::Bridge,
+        }
+    }
+}
+
+impl From<consensus::BlockProposal> for proto::Block {
+    fn from(block: consensus::BlockProposal) -> Self {
+        Self {
+            block_hash: block.block_hash,
+            state_root: block.state_root,
+            zkurl: block.zkurl.to_string(),
+            proposer_id: block.proposer_id,
+            timestamp: block.timestamp,
+            transactions: block.transactions.into_iter().map(Into::into).collect(),
+            base_fee: block.base_fee,
+            gas_used: block.gas_used,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::address::Address;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use consensus::Vote;
+    use ed25519_dalek::SigningKey;
+    use futures::StreamExt;
+    use proto::chain_client::ChainClient;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    async fn serving_with(policy: Policy) -> (Arc<QubeNode>, ChainClient<tonic::transport::Channel>, tokio::sync::oneshot::Sender<()>) {
+        let consensus = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        consensus.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        let consensus = Arc::new(consensus);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(serve(listener, Arc::clone(&consensus), Arc::new(policy), async {
+            let _ = stopped.await;
+        }));
+        (consensus, ChainClient::connect(url).await.unwrap(), stop)
+    }
+
+    async fn serving() -> (Arc<QubeNode>, ChainClient<tonic::transport::Channel>, tokio::sync::oneshot::Sender<()>) {
+        serving_with(Policy::new(None, vec![], 0, 0)).await
+    }
+
+    #[tokio::test]
+    async fn answers_chain_queries() {
+        let (_consensus, mut client, _stop) = serving().await;
+        let set = client.get_validator_set(proto::GetValidatorSetRequest {}).await.unwrap().into_inner();
+        assert_eq!(set.validators[0].node_id, "v1");
+        assert_eq!(set.validators[0].reward_account, Address::from_key(&signer().verifying_key()).to_string());
+        assert_eq!(set.total_stake, 10);
+
+        let tx = consensus::Transaction {
+            chain_id: 7,
+            to: BOB.to_string(),
+            value: 5,
+            gas_limit: 21_032,
+            max_fee_per_gas: 1,
+            data: vec![1, 2],
+            ..consensus::Transaction::default()
+        }
+        .sign(&signer());
+        let submitted = client.submit_transaction(proto::Transaction::from(tx.clone())).await.unwrap().into_inner();
+        assert_eq!(submitted.hash, tx.hash);
+        let resubmitted = client.submit_transaction(proto::Transaction::from(tx)).await.unwrap_err();
+        assert_eq!(resubmitted.code(), tonic::Code::FailedPrecondition);
+
+        let missing = client.get_block(proto::GetBlockRequest { block_hash: "0xnone".to_string() }).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+        let head = client.get_finalized_head(proto::GetFinalizedHeadRequest {}).await.unwrap_err();
+        assert_eq!(head.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_calls_per_caller() {
+        let (_consensus, mut client, _stop) = serving_with(Policy::new(None, vec![], 1, 2)).await;
+        for _ in 0..2 {
+            client.get_validator_set(proto::GetValidatorSetRequest {}).await.unwrap();
+        }
+        let limited = client.get_validator_set(proto::GetValidatorSetRequest {}).await.unwrap_err();
+        assert_eq!(limited.code(), tonic::Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn streams_blocks_as_they_finalize() {
+        let (consensus, mut client, _stop) = serving().await;
+        let mut blocks = client.stream_finalized_blocks(proto::StreamFinalizedBlocksRequest {}).await.unwrap().into_inner();
+
+        // Votes only finalize blocks the node has verified
+        let block = consensus::BlockProposal {
+            block_hash: "0xb1".to_string(),
+            state_root: format!("0x{}", "ab".repeat(32)),
+            zkurl: "zk://prover@example.invalid/block1".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        };
+        consensus.consensus_state.write().await.recent_blocks.push_back(block.clone());
+        let vote = Vote::new(&block, "v1", 10, 1).sign(&signer());
+        assert!(consensus.record_vote(vote).await);
+        let finalized = blocks.next().await.unwrap().unwrap();
+        assert_eq!((finalized.block_hash.as_str(), finalized.height), ("0xb1", 1));
+        assert_eq!(finalized.block.unwrap().state_root, block.state_root);
+
+        let votes = client.get_votes(proto::GetVotesRequest { block_hash: "0xb1".to_string() }).await.unwrap().into_inner();
+        assert_eq!(votes.votes[0].voter_id, "v1");
+    }
+}
@@ -0,0 +1,212 @@
+//! Data-dir integrity checks, run by `cubiq db check` and on every start.
+//!
+//! Each check covers one file the node keeps in its data dir. Damage that
+//! the node can recover from on its own, like a corrupt peer store or a
+//! write interrupted by a crash, is repaired when asked to; anything else
+//! stops the node until an operator fixes it.
+//!
+//! The block store is replayed from its state base, as the node will on
+//! start. Blocks a crash left past the last height whose state replays to
+//! its recorded root are rolled back, and the node fetches them again from
+//! peers. Rolling back keeps the vote log, the block this node last voted
+//! for, so a validator never votes for another block at a height it voted
+//! at before the crash; a validator whose vote log is unreadable is not
+//! started, since it could not tell what it voted for.
+
+use consensus::genesis::ChainSpec;
+use consensus::replay;
+use networking::peers;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::NodeConfig;
+use crate::datadir::{self, LAYOUT_VERSION};
+use crate::keystore::KeyFile;
+use crate::node;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Damaged, and repairable by `--repair` or a restart
+    Repairable(String),
+    Repaired(String),
+    /// Damaged beyond what the node can fix on its own
+    Broken(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub item: &'static str,
+    pub status: Status,
+}
+
+impl Finding {
+    pub fn is_healthy(&self) -> bool {
+        matches!(self.status, Status::Ok | Status::Repaired(_))
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.status {
+            Status::Ok => write!(f, "{}: ok", self.item),
+            Status::Repairable(problem) => write!(f, "{}: {} (repairable)", self.item, problem),
+            Status::Repaired(repair) => write!(f, "{}: repaired, {}", self.item, repair),
+            Status::Broken(problem) => write!(f, "{}: {}", self.item, problem),
+        }
+    }
+}
+
+/// Checks the data dir that `config` points into, repairing what can be
+/// repaired when `repair` is set.
+pub fn check(data_dir: &Path, config: &NodeConfig, repair: bool) -> Vec<Finding> {
+    let finding = |item, status| Finding { item, status };
+    vec![
+        finding("layout", layout(data_dir)),
+        finding("chain spec", chain_spec(&config.consensus.chain_spec)),
+        finding("validator key", validator_key(config)),
+        finding("peer store", peer_store(&config.network.peer_store, repair)),
+        finding("block store", block_store(config, repair)),
+    ]
+}
+
+fn layout(data_dir: &Path) -> Status {
+    match datadir::version(data_dir) {
+        Ok(LAYOUT_VERSION) => Status::Ok,
+        Ok(version) if version < LAYOUT_VERSION => {
+            Status::Broken(format!("layout {} is out of date; run `cubiq db migrate`", version))
+        }
+        Ok(version) => Status::Broken(format!("layout {} was written by a newer node", version)),
+        Err(e) => Status::Broken(format!("{:#}", e)),
+    }
+}
+
+fn chain_spec(path: &Path) -> Status {
+    match ChainSpec::load(path) {
+        Ok(_) => Status::Ok,
+        Err(e) => Status::Broken(e.to_string()),
+    }
+}
+
+fn validator_key(config: &NodeConfig) -> Status {
+    if !config.node.role.votes() {
+        return Status::Ok;
+    }
+    // Unlocking needs the password, which the node asks for later
+    match KeyFile::read(&config.consensus.validator_key) {
+        Ok(_) => Status::Ok,
+        Err(e) => Status::Broken(format!("{:#}", e)),
+    }
+}
+
+/// Peers are only a head start on discovery, so a damaged store is set
+/// aside rather than fixed by hand.
+fn peer_store(path: &Path, repair: bool) -> Status {
+    let interrupted = path.with_extension("tmp");
+    let mut repairs = Vec::new();
+    if interrupted.exists() {
+        if !repair {
+            return Status::Repairable(format!("{} is left from an interrupted write", interrupted.display()));
+        }
+        if let Err(e) = fs::remove_file(&interrupted) {
+            return Status::Broken(format!("removing {}: {}", interrupted.display(), e));
+        }
+        repairs.push(format!("removed {}", interrupted.display()));
+    }
+    if let Err(e) = peers::load(path) {
+        if !repair {
+            return Status::Repairable(format!("{:#}", e));
+        }
+        let aside = PathBuf::from(format!("{}.corrupt", path.display()));
+        if let Err(e) = fs::rename(path, &aside) {
+            return Status::Broken(format!("moving {} aside: {}", path.display(), e));
+        }
+        repairs.push(format!("moved the unreadable store to {}", aside.display()));
+    }
+    if repairs.is_empty() {
+        Status::Ok
+    } else {
+        Status::Repaired(repairs.join("; "))
+    }
+}
+
+fn block_store(config: &NodeConfig, repair: bool) -> Status {
+    let Ok(spec) = ChainSpec::load(&config.consensus.chain_spec) else {
+        return Status::Broken("not checked without a readable chain spec".to_string());
+    };
+    let store = match node::open_store(config) {
+        Ok(store) => store,
+        Err(e) => return Status::Broken(format!("{:#}", e)),
+    };
+    if let Some(Err(e)) = config.node.role.votes().then(|| store.last_vote()) {
+        return Status::Broken(format!("vote log: {}", e));
+    }
+    let found = match replay::check_consistency(&store, &spec) {
+        Ok(found) => found,
+        Err(e) => return Status::Broken(e.to_string()),
+    };
+    let Some(problem) = found.problem else {
+        return Status::Ok;
+    };
+    if !repair {
+        return Status::Repairable(format!("{}; heights {}..={} would be rolled back", problem, found.consistent + 1, found.latest));
+    }
+    match store.truncate(found.consistent) {
+        Ok(removed) => Status::Repaired(format!("rolled back {} blocks to height {} ({})", removed, found.consistent, problem)),
+        Err(e) => Status::Broken(format!("{}; {}", problem, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::NodeRole;
+
+    fn data_dir(name: &str) -> (PathBuf, NodeConfig) {
+        let dir = std::env::temp_dir().join(format!("cubiq-integrity-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        datadir::write_version(&dir, LAYOUT_VERSION).unwrap();
+        let mut config = NodeConfig::load(&dir.join("config.toml"), false, &dir, &[]).unwrap();
+        config.node.role = NodeRole::Full;
+        let validator = consensus::genesis::GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: "11".repeat(32),
+            stake: 10,
+            registration: None,
+        };
+        ChainSpec::dev(7, validator, 0).save(&config.consensus.chain_spec).unwrap();
+        (dir, config)
+    }
+
+    #[test]
+    fn repairs_a_damaged_peer_store_only_when_asked() {
+        let (dir, config) = data_dir("peers");
+        fs::write(&config.network.peer_store, "[{\"peer_id\":").unwrap();
+        fs::write(config.network.peer_store.with_extension("tmp"), "[]").unwrap();
+
+        let findings = check(&dir, &config, false);
+        assert!(matches!(findings[3].status, Status::Repairable(_)));
+        assert!(findings[..3].iter().all(Finding::is_healthy));
+
+        let repaired = check(&dir, &config, true);
+        assert!(matches!(&repaired[3].status, Status::Repaired(how) if how.contains("removed") && how.contains("moved")));
+        assert!(check(&dir, &config, false).iter().all(|f| f.status == Status::Ok));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reports_what_the_node_cannot_repair() {
+        let (dir, mut config) = data_dir("broken");
+        datadir::write_version(&dir, 0).unwrap();
+        fs::write(&config.consensus.chain_spec, "{}").unwrap();
+        config.node.role = NodeRole::Validator;
+
+        let findings = check(&dir, &config, true);
+        let broken: Vec<_> = findings.iter().filter(|f| matches!(f.status, Status::Broken(_))).map(|f| f.item).collect();
+        assert_eq!(broken, ["layout", "chain spec", "validator key", "block store"]);
+        assert!(findings[0].to_string().contains("cubiq db migrate"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
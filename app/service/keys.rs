@@ -0,0 +1,284 @@
+//! Node keys.
+//!
+//! Keys are kept encrypted under `<data-dir>/keys`; see
+//! [`crate::keystore`]. Commands and the node unlock them with a password
+//! from a file, from `$CUBIQ_KEY_PASSWORD`, or typed at the terminal.
+//!
+//! Keys can also be derived from one BIP-39 recovery phrase, so that the
+//! phrase backs up all of them. The phrase's seed is derived along a
+//! BIP-32 path per SLIP-0010, where ed25519 keys only have hardened
+//! children. `STANDARD_KEYS` lists where each of the node's keys lives.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bip39::Mnemonic;
+use ed25519_dalek::SigningKey;
+use hmac::{Hmac, Mac};
+use k256::elliptic_curve::PrimeField;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha512;
+use std::fmt;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::keystore::KeyType;
+
+pub const KEYS_DIR: &str = "keys";
+
+/// Key that signs votes.
+pub const VALIDATOR_KEY: &str = "validator";
+
+/// Key the node's peer id derives from.
+pub const NETWORK_KEY: &str = "network";
+
+/// Key of the account that holds funds, such as a validator's rewards.
+pub const ACCOUNT_KEY: &str = "account";
+
+/// Holds the key password, for scripts that can't type it.
+pub const PASSWORD_ENV: &str = "CUBIQ_KEY_PASSWORD";
+
+/// Holds the recovery phrase, for scripts that can't type it.
+pub const MNEMONIC_ENV: &str = "CUBIQ_MNEMONIC";
+
+/// Where the first account is derived, by the keystore and on a Ledger.
+pub const ACCOUNT_PATH: &str = "m/44'/7171'/0'/0'/0'";
+
+/// The node's keys by name, with the paths they are derived at, under
+/// coin type 7171 (not registered in SLIP-0044).
+pub const STANDARD_KEYS: [(&str, &str); 3] = [
+    (ACCOUNT_KEY, ACCOUNT_PATH),
+    (VALIDATOR_KEY, "m/44'/7171'/1'/0'/0'"),
+    (NETWORK_KEY, "m/44'/7171'/2'/0'/0'"),
+];
+
+/// Indices from here on are hardened.
+const HARDENED: u32 = 1 << 31;
+
+pub fn public_key_hex(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().to_bytes())
+}
+
+/// The first line of `file` if given, else `$CUBIQ_KEY_PASSWORD`, else
+/// asked for at the terminal. A new password (`confirm`) is asked for
+/// twice and must not be empty.
+pub fn password(file: Option<&Path>, confirm: bool) -> Result<String> {
+    let (password, typed) = secret_input(file, PASSWORD_ENV, "Key password: ", "--password-file")?;
+    if confirm && typed && rpassword::prompt_password("Repeat the password: ")? != password {
+        bail!("the passwords do not match");
+    }
+    if confirm && password.is_empty() {
+        bail!("the key password must not be empty");
+    }
+    Ok(password)
+}
+
+/// A recovery phrase, from where `password` would read a password.
+pub fn mnemonic(file: Option<&Path>) -> Result<String> {
+    Ok(secret_input(file, MNEMONIC_ENV, "Recovery phrase: ", "--mnemonic-file")?.0)
+}
+
+/// The first line of `file`, `$env`, or what is typed at `prompt`, and
+/// whether it was typed.
+fn secret_input(file: Option<&Path>, env: &str, prompt: &str, flag: &str) -> Result<(String, bool)> {
+    if let Some(path) = file {
+        let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        return Ok((text.lines().next().unwrap_or_default().to_string(), false));
+    }
+    if let Ok(value) = std::env::var(env) {
+        return Ok((value, false));
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("nothing to read; pass {} or set {}", flag, env);
+    }
+    Ok((rpassword::prompt_password(prompt)?, true))
+}
+
+/// A new English recovery phrase of `words` words, from 12 to 24 in
+/// steps of 3.
+pub fn new_mnemonic(words: usize) -> Result<String> {
+    if !(12..=24).contains(&words) || !words.is_multiple_of(3) {
+        bail!("a recovery phrase has 12, 15, 18, 21 or 24 words, not {}", words);
+    }
+    let mut entropy = [0; 32];
+    OsRng.fill_bytes(&mut entropy);
+    Ok(Mnemonic::from_entropy(&entropy[..words / 3 * 4])?.to_string())
+}
+
+/// The BIP-39 seed of `phrase`, without a passphrase.
+pub fn seed(phrase: &str) -> Result<[u8; 64]> {
+    let mnemonic = Mnemonic::parse(phrase).map_err(|e| anyhow!("not a valid recovery phrase: {}", e))?;
+    Ok(mnemonic.to_seed(""))
+}
+
+/// A BIP-32 path such as `m/44'/7171'/1'/0'/0'`, where `'` or `h` marks
+/// a hardened index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl FromStr for DerivationPath {
+    type Err = anyhow::Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            bail!("derivation path {:?} does not start with m", path);
+        }
+        let indices = parts.map(|part| {
+            let (number, hardened) = match part.strip_suffix(['\'', 'h']) {
+                Some(number) => (number, true),
+                None => (part, false),
+            };
+            let index: u32 = number
+                .parse()
+                .ok()
+                .filter(|index| *index < HARDENED)
+                .with_context(|| format!("{:?} in {:?} is not a derivation index", part, path))?;
+            Ok(if hardened { index | HARDENED } else { index })
+        });
+        Ok(DerivationPath(indices.collect::<Result<_>>()?))
+    }
+}
+
+impl DerivationPath {
+    pub fn indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("m")?;
+        for index in &self.0 {
+            match index & HARDENED {
+                0 => write!(f, "/{}", index)?,
+                _ => write!(f, "/{}'", index & !HARDENED)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `key_type` secret at `path` below `seed`, per SLIP-0010.
+pub fn derive(seed: &[u8], path: &DerivationPath, key_type: KeyType) -> Result<[u8; 32]> {
+    let curve: &[u8] = match key_type {
+        KeyType::Ed25519 => b"ed25519 seed",
+        KeyType::Secp256k1 => b"Bitcoin seed",
+    };
+    let (mut key, mut chain_code) = hmac_sha512(curve, &[seed]);
+    while key_type == KeyType::Secp256k1 && secp256k1_scalar(&key).is_none_or(|scalar| bool::from(scalar.is_zero())) {
+        (key, chain_code) = hmac_sha512(curve, &[&key, &chain_code]);
+    }
+    for &index in &path.0 {
+        (key, chain_code) = match key_type {
+            KeyType::Ed25519 if index & HARDENED == 0 => {
+                bail!("ed25519 keys only have hardened children; mark every index in {} with '", path)
+            }
+            KeyType::Ed25519 => hmac_sha512(&chain_code, &[&[0], &key, &index.to_be_bytes()]),
+            KeyType::Secp256k1 => secp256k1_child(&key, &chain_code, index)?,
+        };
+    }
+    Ok(key)
+}
+
+fn secp256k1_child(key: &[u8; 32], chain_code: &[u8; 32], index: u32) -> Result<([u8; 32], [u8; 32])> {
+    let parent = secp256k1_scalar(key).context("not a secp256k1 key")?;
+    let (mut tweak, mut child_chain_code) = match index & HARDENED {
+        0 => {
+            let public = k256::SecretKey::from_slice(key)?.public_key().to_sec1_bytes();
+            hmac_sha512(chain_code, &[&public, &index.to_be_bytes()])
+        }
+        _ => hmac_sha512(chain_code, &[&[0], key, &index.to_be_bytes()]),
+    };
+    // An out-of-range tweak or a zero key is retried, as SLIP-0010 says
+    loop {
+        if let Some(child) = secp256k1_scalar(&tweak).map(|tweak| tweak + parent).filter(|child| !bool::from(child.is_zero())) {
+            return Ok((child.to_bytes().into(), child_chain_code));
+        }
+        (tweak, child_chain_code) = hmac_sha512(chain_code, &[&[1], &child_chain_code, &index.to_be_bytes()]);
+    }
+}
+
+fn secp256k1_scalar(bytes: &[u8; 32]) -> Option<k256::Scalar> {
+    k256::Scalar::from_repr((*bytes).into()).into()
+}
+
+/// The two halves of HMAC-SHA512 over `parts`.
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    let output = mac.finalize().into_bytes();
+    let (left, right) = output.split_at(32);
+    (left.try_into().expect("32 bytes"), right.try_into().expect("32 bytes"))
+}
+
+/// Reads a hex-encoded 32-byte secret, as unencrypted key files from
+/// before the keystore hold them.
+pub fn read_secret(path: &Path) -> Result<[u8; 32]> {
+    let encoded = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    hex::decode(encoded.trim().trim_start_matches("0x"))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .with_context(|| format!("{} does not hold a hex-encoded 32-byte secret", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_and_secrets_are_read_from_files() {
+        let dir = std::env::temp_dir().join(format!("cubiq-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("password");
+        fs::write(&file, "correct horse\nbattery staple\n").unwrap();
+        assert_eq!(password(Some(&file), true).unwrap(), "correct horse");
+        fs::write(&file, "\n").unwrap();
+        assert_eq!(password(Some(&file), false).unwrap(), "");
+        assert!(password(Some(&file), true).is_err());
+
+        let secret = dir.join("validator.key");
+        fs::write(&secret, format!("{}\n", "ab".repeat(32))).unwrap();
+        assert_eq!(read_secret(&secret).unwrap(), [0xab; 32]);
+        fs::write(&secret, "abcd").unwrap();
+        assert!(read_secret(&secret).unwrap_err().to_string().contains("32-byte secret"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn paths_derive_the_slip10_test_vectors() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let derive = |path: &str, key_type| hex::encode(derive(&seed, &path.parse().unwrap(), key_type).unwrap());
+        assert_eq!(derive("m", KeyType::Ed25519), "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7");
+        assert_eq!(derive("m/0'", KeyType::Ed25519), "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3");
+        assert_eq!(derive("m/0h/1h", KeyType::Ed25519), "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2");
+        assert_eq!(derive("m", KeyType::Secp256k1), "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35");
+        assert_eq!(derive("m/0'", KeyType::Secp256k1), "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea");
+        assert_eq!(derive("m/0'/1", KeyType::Secp256k1), "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368");
+
+        let unhardened: DerivationPath = "m/0'/1".parse().unwrap();
+        assert!(super::derive(&seed, &unhardened, KeyType::Ed25519).unwrap_err().to_string().contains("hardened"));
+        assert_eq!(unhardened.to_string(), "m/0'/1");
+        for bad in ["", "0'/1", "m/x", "m/2147483648", "m//1"] {
+            assert!(bad.parse::<DerivationPath>().is_err(), "{}", bad);
+        }
+        for (_, path) in STANDARD_KEYS {
+            assert!(path.parse::<DerivationPath>().unwrap().to_string().contains("/44'/7171'/"));
+        }
+    }
+
+    #[test]
+    fn recovery_phrases_round_trip_to_their_seed() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        assert!(hex::encode(seed(phrase).unwrap()).starts_with("5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc1"));
+        assert!(seed(&phrase.replace("about", "abandon")).is_err());
+        let fresh = new_mnemonic(24).unwrap();
+        assert_eq!(fresh.split(' ').count(), 24);
+        assert_ne!(seed(&fresh).unwrap(), seed(&new_mnemonic(24).unwrap()).unwrap());
+        assert_eq!(new_mnemonic(12).unwrap().split(' ').count(), 12);
+        assert!(new_mnemonic(13).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Encrypted key files.
+//!
+//! Each key is a JSON file `<data-dir>/keys/<name>.json` holding its type
+//! and public key in the clear and its 32-byte secret sealed with
+//! AES-256-GCM, under a key stretched from a password by scrypt or
+//! argon2id. The type and public key are sealed as associated data, so
+//! editing either makes the file fail to open. On Unix, key files are
+//! readable only by their owner.
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "key_type": "ed25519",
+//!   "public_key": "<hex>",
+//!   "crypto": {
+//!     "cipher": "aes-256-gcm",
+//!     "ciphertext": "<hex secret and tag>",
+//!     "nonce": "<hex, 12 bytes>",
+//!     "kdf": { "function": "scrypt", "log_n": 17, "r": 8, "p": 1, "salt": "<hex>" }
+//!   }
+//! }
+//! ```
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use consensus::address::Address;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::keys::KEYS_DIR;
+
+/// Format of the key files this node writes and reads.
+pub const KEY_FILE_VERSION: u32 = 1;
+
+const CIPHER: &str = "aes-256-gcm";
+const SALT_BYTES: usize = 32;
+const NONCE_BYTES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+    /// Signs transactions and votes
+    Ed25519,
+    /// Kept for accounts on other chains; cannot sign here yet
+    Secp256k1,
+}
+
+impl KeyType {
+    /// Hex public key of `secret`: the ed25519 key's 32 bytes, or the
+    /// compressed secp256k1 point.
+    pub fn public_key(self, secret: &[u8; 32]) -> Result<String> {
+        Ok(match self {
+            KeyType::Ed25519 => hex::encode(SigningKey::from_bytes(secret).verifying_key().to_bytes()),
+            KeyType::Secp256k1 => {
+                let key = k256::SecretKey::from_slice(secret).map_err(|_| anyhow!("not a valid secp256k1 secret key"))?;
+                hex::encode(key.public_key().to_sec1_bytes())
+            }
+        })
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyType::Ed25519 => "ed25519",
+            KeyType::Secp256k1 => "secp256k1",
+        })
+    }
+}
+
+/// Password stretching for new key files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Kdf {
+    #[default]
+    Scrypt,
+    Argon2id,
+}
+
+impl Kdf {
+    /// This function at its standard cost, with a fresh salt.
+    pub fn params(self) -> KdfParams {
+        let salt = hex::encode(random::<SALT_BYTES>());
+        match self {
+            Kdf::Scrypt => KdfParams::Scrypt { log_n: 17, r: 8, p: 1, salt },
+            Kdf::Argon2id => KdfParams::Argon2id { memory_kib: 64 * 1024, iterations: 3, parallelism: 1, salt },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "snake_case")]
+pub enum KdfParams {
+    Scrypt { log_n: u8, r: u32, p: u32, salt: String },
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32, salt: String },
+}
+
+impl KdfParams {
+    fn derive(&self, password: &str) -> Result<[u8; 32]> {
+        let mut key = [0; 32];
+        match self {
+            KdfParams::Scrypt { log_n, r, p, salt } => {
+                let params = scrypt::Params::new(*log_n, *r, *p, key.len()).map_err(|e| anyhow!("bad scrypt parameters: {}", e))?;
+                scrypt::scrypt(password.as_bytes(), &hex::decode(salt).context("salt is not hex")?, &params, &mut key)
+                    .map_err(|e| anyhow!("scrypt: {}", e))?;
+            }
+            KdfParams::Argon2id { memory_kib, iterations, parallelism, salt } => {
+                let params = argon2::Params::new(*memory_kib, *iterations, *parallelism, Some(key.len()))
+                    .map_err(|e| anyhow!("bad argon2 parameters: {}", e))?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password.as_bytes(), &hex::decode(salt).context("salt is not hex")?, &mut key)
+                    .map_err(|e| anyhow!("argon2: {}", e))?;
+            }
+        }
+        Ok(key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Crypto {
+    pub cipher: String,
+    /// Hex-encoded sealed secret, tag included
+    pub ciphertext: String,
+    pub nonce: String,
+    pub kdf: KdfParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub version: u32,
+    pub key_type: KeyType,
+    pub public_key: String,
+    pub crypto: Crypto,
+}
+
+impl KeyFile {
+    /// Seals `secret` under `password`, stretched per `kdf`.
+    pub fn seal(key_type: KeyType, secret: &[u8; 32], password: &str, kdf: KdfParams) -> Result<Self> {
+        let public_key = key_type.public_key(secret)?;
+        let nonce = random::<NONCE_BYTES>();
+        let cipher = Aes256Gcm::new(&kdf.derive(password)?.into());
+        let aad = associated_data(key_type, &public_key);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: secret, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("sealing the key failed"))?;
+        Ok(Self {
+            version: KEY_FILE_VERSION,
+            key_type,
+            public_key,
+            crypto: Crypto { cipher: CIPHER.to_string(), ciphertext: hex::encode(ciphertext), nonce: hex::encode(nonce), kdf },
+        })
+    }
+
+    /// The secret, if `password` is the one the file was sealed under.
+    pub fn open(&self, password: &str) -> Result<[u8; 32]> {
+        if self.version != KEY_FILE_VERSION {
+            bail!("key file version {} is not supported; this node reads {}", self.version, KEY_FILE_VERSION);
+        }
+        if self.crypto.cipher != CIPHER {
+            bail!("cipher {:?} is not supported", self.crypto.cipher);
+        }
+        let nonce = hex::decode(&self.crypto.nonce).ok().filter(|nonce| nonce.len() == NONCE_BYTES).context("nonce is not 12 hex bytes")?;
+        let ciphertext = hex::decode(&self.crypto.ciphertext).context("ciphertext is not hex")?;
+        let cipher = Aes256Gcm::new(&self.crypto.kdf.derive(password)?.into());
+        let aad = associated_data(self.key_type, &self.public_key);
+        let secret = cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| anyhow!("wrong password, or the key file was altered"))?;
+        secret.try_into().map_err(|_| anyhow!("sealed secret is not 32 bytes"))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path).with_context(|| format!("reading key {}", path.display()))?;
+        serde_json::from_slice(&bytes).with_context(|| {
+            match path.extension().is_some_and(|extension| extension == "key") {
+                true => format!(
+                    "{} is an unencrypted key; seal it with `cubiq key import --secret-file {}`",
+                    path.display(),
+                    path.display()
+                ),
+                false => format!("{} is not a key file", path.display()),
+            }
+        })
+    }
+
+    /// The account of an ed25519 key; other keys hold none here.
+    pub fn address(&self) -> Option<Address> {
+        (self.key_type == KeyType::Ed25519).then(|| Address::from_public_key(&self.public_key).ok()).flatten()
+    }
+
+    /// Unlocks an ed25519 key for signing.
+    pub fn signing_key(&self, password: &str) -> Result<SigningKey> {
+        if self.key_type != KeyType::Ed25519 {
+            bail!("{} keys cannot sign transactions or votes", self.key_type);
+        }
+        Ok(SigningKey::from_bytes(&self.open(password)?))
+    }
+}
+
+fn associated_data(key_type: KeyType, public_key: &str) -> String {
+    format!("{}:{}", key_type, public_key)
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// The key files under a data dir.
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(data_dir: &Path) -> Self {
+        Self { dir: data_dir.join(KEYS_DIR) }
+    }
+
+    pub fn path(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            bail!("invalid key name {:?}: use letters, digits, '-' and '_'", name);
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+
+    /// Generates a key and saves it as `name`, refusing to replace an
+    /// existing key unless `overwrite` is set.
+    pub fn create(&self, name: &str, key_type: KeyType, password: &str, kdf: KdfParams, overwrite: bool) -> Result<KeyFile> {
+        let secret = loop {
+            let secret = random::<32>();
+            // Almost every 32 bytes are a valid secp256k1 secret
+            if key_type.public_key(&secret).is_ok() {
+                break secret;
+            }
+        };
+        self.import(name, key_type, &secret, password, kdf, overwrite)
+    }
+
+    /// Saves `secret` as `name`, as `create` does.
+    pub fn import(&self, name: &str, key_type: KeyType, secret: &[u8; 32], password: &str, kdf: KdfParams, overwrite: bool) -> Result<KeyFile> {
+        let path = self.path(name)?;
+        if path.exists() && !overwrite {
+            bail!("{} already exists; pass --force to replace it", path.display());
+        }
+        let file = KeyFile::seal(key_type, secret, password, kdf)?;
+        fs::create_dir_all(&self.dir).with_context(|| format!("creating {}", self.dir.display()))?;
+        write_secret(&path, &serde_json::to_vec_pretty(&file)?).with_context(|| format!("writing {}", path.display()))?;
+        Ok(file)
+    }
+
+    pub fn load(&self, name: &str) -> Result<KeyFile> {
+        KeyFile::read(&self.path(name)?)
+    }
+
+    /// Every key file, by name.
+    pub fn list(&self) -> Result<Vec<(String, KeyFile)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", self.dir.display())),
+        };
+        let mut keys = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if path.extension().is_some_and(|extension| extension == "json") {
+                keys.push((name.to_string(), KeyFile::read(&path)?));
+            }
+        }
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(keys)
+    }
+}
+
+fn write_secret(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap enough for tests; real keys use `Kdf::params`.
+    fn light(kdf: Kdf) -> KdfParams {
+        match kdf.params() {
+            KdfParams::Scrypt { salt, .. } => KdfParams::Scrypt { log_n: 4, r: 8, p: 1, salt },
+            KdfParams::Argon2id { salt, .. } => KdfParams::Argon2id { memory_kib: 64, iterations: 1, parallelism: 1, salt },
+        }
+    }
+
+    #[test]
+    fn key_files_open_only_with_their_password() {
+        for kdf in [Kdf::Scrypt, Kdf::Argon2id] {
+            let file = KeyFile::seal(KeyType::Ed25519, &[7; 32], "hunter2", light(kdf)).unwrap();
+            assert_eq!(file.open("hunter2").unwrap(), [7; 32]);
+            assert!(file.open("hunter3").unwrap_err().to_string().contains("wrong password"));
+            let json: KeyFile = serde_json::from_str(&serde_json::to_string(&file).unwrap()).unwrap();
+            assert_eq!(json.signing_key("hunter2").unwrap().to_bytes(), [7; 32]);
+            let swapped = KeyFile { public_key: KeyType::Ed25519.public_key(&[8; 32]).unwrap(), ..file };
+            assert!(swapped.open("hunter2").is_err());
+        }
+        let secp = KeyFile::seal(KeyType::Secp256k1, &[7; 32], "pw", light(Kdf::Scrypt)).unwrap();
+        assert_eq!(secp.public_key.len(), 66);
+        assert!(secp.signing_key("pw").unwrap_err().to_string().contains("cannot sign"));
+        assert!(KeyFile::seal(KeyType::Secp256k1, &[0; 32], "pw", light(Kdf::Scrypt)).is_err());
+    }
+
+    #[test]
+    fn keystores_create_import_and_list_keys() {
+        let dir = std::env::temp_dir().join(format!("cubiq-keystore-{}", std::process::id()));
+        let keystore = Keystore::new(&dir);
+        let created = keystore.create("validator", KeyType::Ed25519, "pw", light(Kdf::Scrypt), false).unwrap();
+        assert!(keystore.create("validator", KeyType::Ed25519, "pw", light(Kdf::Scrypt), false).is_err());
+        keystore.import("rewards-1", KeyType::Secp256k1, &[9; 32], "other", light(Kdf::Argon2id), false).unwrap();
+        assert_eq!(keystore.load("rewards-1").unwrap().open("other").unwrap(), [9; 32]);
+
+        let listed = keystore.list().unwrap();
+        assert_eq!(listed.iter().map(|(name, file)| (name.as_str(), file.key_type)).collect::<Vec<_>>(), [
+            ("rewards-1", KeyType::Secp256k1),
+            ("validator", KeyType::Ed25519)
+        ]);
+        assert_eq!(listed[1].1, created);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = keystore.path("validator").unwrap();
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(keystore.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn key_names_cannot_escape_the_keys_dir() {
+        let keystore = Keystore::new(Path::new("/data"));
+        assert!(keystore.path("../etc/passwd").is_err());
+        assert!(keystore.path("").is_err());
+        assert_eq!(keystore.path("rewards-1").unwrap(), PathBuf::from("/data/keys/rewards-1.json"));
+    }
+}
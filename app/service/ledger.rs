@@ -0,0 +1,385 @@
+//! Signing on a Ledger, so an account's key never leaves the device.
+//!
+//! The Cubiq app on the device takes APDUs (ISO 7816-4 command units)
+//! carried in 64-byte USB HID reports. It derives keys along BIP-32 paths
+//! as `keys::derive` does, shows a transaction's recipient, value and fee
+//! until they are approved or rejected on the device, and can show an
+//! account's address to be checked against the one the host printed. A
+//! multisig's transaction is shown and signed the same way, from the
+//! multisig's address.
+//! Opening the device needs the `ledger` build feature.
+
+use anyhow::{anyhow, bail, Result};
+use consensus::address::Address;
+use consensus::{Cosignature, Transaction};
+
+use crate::keys::DerivationPath;
+use crate::signer::Signer;
+
+const CLA: u8 = 0xe0;
+const INS_GET_PUBLIC_KEY: u8 = 0x02;
+const INS_SIGN_TX: u8 = 0x03;
+
+/// P1 of `INS_GET_PUBLIC_KEY` that has the device show the key and wait
+/// for it to be approved.
+const P1_CONFIRM: u8 = 0x01;
+
+/// P1 of the first `INS_SIGN_TX` command, which holds the path, and of
+/// the rest, which hold the transaction's canonical encoding.
+const P1_FIRST: u8 = 0x00;
+const P1_MORE: u8 = 0x80;
+
+/// P2 of every `INS_SIGN_TX` command but the last.
+const P2_MORE: u8 = 0x80;
+const P2_LAST: u8 = 0x00;
+
+/// Most data bytes one command carries.
+const MAX_APDU_DATA: usize = 255;
+
+const SW_OK: u16 = 0x9000;
+const SW_WRONG_LENGTH: u16 = 0x6700;
+const SW_DENIED: u16 = 0x6985;
+const SW_INS_NOT_SUPPORTED: u16 = 0x6d00;
+const SW_CLA_NOT_SUPPORTED: u16 = 0x6e00;
+const SW_LOCKED: u16 = 0x5515;
+
+pub const REPORT_SIZE: usize = 64;
+
+/// Header of every report: channel, tag and sequence number.
+const REPORT_HEADER: usize = 5;
+const CHANNEL: u16 = 0x0101;
+const TAG_APDU: u8 = 0x05;
+
+/// How long the device has to answer, which includes someone reading
+/// its screen and approving.
+const TIMEOUT_MS: i32 = 120_000;
+
+/// A device exchanging HID reports.
+pub trait Hid {
+    fn write_report(&mut self, report: &[u8; REPORT_SIZE]) -> Result<()>;
+
+    /// The next report, or an error once `timeout_ms` passes without one.
+    fn read_report(&mut self, timeout_ms: i32) -> Result<[u8; REPORT_SIZE]>;
+}
+
+/// The account at one path on a Ledger.
+pub struct Ledger {
+    device: Box<dyn Hid>,
+    path: DerivationPath,
+    public_key: String,
+    address: Address,
+}
+
+impl Ledger {
+    /// The account at `path` on the first Ledger connected, which must be
+    /// unlocked with the Cubiq app open.
+    pub fn connect(path: DerivationPath) -> Result<Self> {
+        Self::open(hid::open()?, path)
+    }
+
+    fn open(device: Box<dyn Hid>, path: DerivationPath) -> Result<Self> {
+        let mut ledger = Ledger { device, path, public_key: String::new(), address: Address::from_bytes([0; 32]) };
+        ledger.public_key = ledger.get_public_key(false)?;
+        ledger.address = Address::from_public_key(&ledger.public_key)?;
+        Ok(ledger)
+    }
+
+    /// Shows the account's address on the device until it is approved
+    /// there, and returns it.
+    pub fn confirm_address(&mut self) -> Result<Address> {
+        eprintln!("Check that the Ledger shows {} and approve it", self.address);
+        let shown = self.get_public_key(true)?;
+        if shown != self.public_key {
+            bail!("the Ledger showed the key {} but had reported {}", shown, self.public_key);
+        }
+        Ok(self.address)
+    }
+
+    fn get_public_key(&mut self, confirm: bool) -> Result<String> {
+        let p1 = if confirm { P1_CONFIRM } else { 0 };
+        let key = self.exchange(INS_GET_PUBLIC_KEY, p1, 0, &encode_path(&self.path))?;
+        if key.len() != 32 {
+            bail!("the Ledger sent a {}-byte public key", key.len());
+        }
+        Ok(hex::encode(key))
+    }
+
+    /// The device's signature of a transaction's canonical `encoding`,
+    /// once it is approved there.
+    fn sign_encoding(&mut self, encoding: &[u8]) -> Result<String> {
+        self.exchange(INS_SIGN_TX, P1_FIRST, P2_MORE, &encode_path(&self.path))?;
+        eprintln!("Review the transaction on the Ledger and approve it");
+        let chunks = encoding.len().div_ceil(MAX_APDU_DATA);
+        let mut signature = vec![];
+        for (i, chunk) in encoding.chunks(MAX_APDU_DATA).enumerate() {
+            let p2 = if i + 1 == chunks { P2_LAST } else { P2_MORE };
+            signature = self.exchange(INS_SIGN_TX, P1_MORE, p2, chunk)?;
+        }
+        Ok(hex::encode(signature))
+    }
+
+    /// Sends one command and returns the response's data.
+    fn exchange(&mut self, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let mut apdu = vec![CLA, ins, p1, p2, data.len() as u8];
+        apdu.extend_from_slice(data);
+        for report in frame(&apdu) {
+            self.device.write_report(&report)?;
+        }
+        let mut reports = Unframer::default();
+        loop {
+            if let Some(response) = reports.push(&self.device.read_report(TIMEOUT_MS)?)? {
+                return status(response);
+            }
+        }
+    }
+}
+
+impl Signer for Ledger {
+    fn public_key(&self) -> String {
+        self.public_key.clone()
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn sign_transaction(&mut self, tx: Transaction) -> Result<Transaction> {
+        let mut tx = tx.from_account(&self.address);
+        tx.signature = self.sign_encoding(&tx.encode())?;
+        tx.verify_signature().map_err(|e| anyhow!("the Ledger's signature is not valid: {}", e))?;
+        Ok(tx)
+    }
+
+    fn cosign(&mut self, tx: &Transaction) -> Result<Cosignature> {
+        let cosignature = Cosignature { public_key: self.public_key.clone(), signature: self.sign_encoding(&tx.encode())? };
+        let cosigned = Transaction { signature: String::new(), cosignatures: vec![cosignature.clone()], ..tx.clone() };
+        cosigned.verify_signature().map_err(|e| anyhow!("the Ledger's cosignature is not valid: {}", e))?;
+        Ok(cosignature)
+    }
+}
+
+/// The number of indices, then each as a big-endian u32.
+fn encode_path(path: &DerivationPath) -> Vec<u8> {
+    let mut bytes = vec![path.indices().len() as u8];
+    for index in path.indices() {
+        bytes.extend_from_slice(&index.to_be_bytes());
+    }
+    bytes
+}
+
+/// The reports carrying `apdu`: its length as a big-endian u16 then the
+/// APDU itself, split after each report's header and zero-padded.
+fn frame(apdu: &[u8]) -> Vec<[u8; REPORT_SIZE]> {
+    let mut payload = (apdu.len() as u16).to_be_bytes().to_vec();
+    payload.extend_from_slice(apdu);
+    payload
+        .chunks(REPORT_SIZE - REPORT_HEADER)
+        .enumerate()
+        .map(|(seq, chunk)| {
+            let mut report = [0; REPORT_SIZE];
+            report[..2].copy_from_slice(&CHANNEL.to_be_bytes());
+            report[2] = TAG_APDU;
+            report[3..REPORT_HEADER].copy_from_slice(&(seq as u16).to_be_bytes());
+            report[REPORT_HEADER..REPORT_HEADER + chunk.len()].copy_from_slice(chunk);
+            report
+        })
+        .collect()
+}
+
+/// Reassembles what `frame` split.
+#[derive(Debug, Default)]
+struct Unframer {
+    payload: Vec<u8>,
+    next: u16,
+}
+
+impl Unframer {
+    /// Takes the next report; returns the APDU once it is whole.
+    fn push(&mut self, report: &[u8; REPORT_SIZE]) -> Result<Option<Vec<u8>>> {
+        if u16::from_be_bytes([report[0], report[1]]) != CHANNEL || report[2] != TAG_APDU {
+            bail!("the Ledger sent a report on another channel");
+        }
+        let seq = u16::from_be_bytes([report[3], report[4]]);
+        if seq != self.next {
+            bail!("the Ledger sent report {} where {} was next", seq, self.next);
+        }
+        self.next += 1;
+        self.payload.extend_from_slice(&report[REPORT_HEADER..]);
+        let len = u16::from_be_bytes([self.payload[0], self.payload[1]]) as usize;
+        if self.payload.len() < 2 + len {
+            return Ok(None);
+        }
+        self.payload.truncate(2 + len);
+        Ok(Some(self.payload.split_off(2)))
+    }
+}
+
+/// The data of `response`, or why its status word says it failed.
+fn status(mut response: Vec<u8>) -> Result<Vec<u8>> {
+    if response.len() < 2 {
+        bail!("the Ledger sent a {}-byte response", response.len());
+    }
+    let sw = response.split_off(response.len() - 2);
+    match u16::from_be_bytes([sw[0], sw[1]]) {
+        SW_OK => Ok(response),
+        SW_DENIED => bail!("rejected on the Ledger"),
+        SW_LOCKED => bail!("the Ledger is locked; unlock it and try again"),
+        SW_CLA_NOT_SUPPORTED | SW_INS_NOT_SUPPORTED => bail!("open the Cubiq app on the Ledger"),
+        SW_WRONG_LENGTH => bail!("the Cubiq app on the Ledger could not read the request; it may need updating"),
+        sw => bail!("the Ledger failed with status {:#06x}", sw),
+    }
+}
+
+#[cfg(feature = "ledger")]
+mod hid {
+    use anyhow::{bail, Context, Result};
+
+    use super::{Hid, REPORT_SIZE};
+
+    const LEDGER_VENDOR_ID: u16 = 0x2c97;
+
+    /// HID usage page of the interface apps answer on.
+    const USAGE_PAGE: u16 = 0xffa0;
+
+    struct Device(hidapi::HidDevice);
+
+    pub fn open() -> Result<Box<dyn Hid>> {
+        let api = hidapi::HidApi::new().context("listing USB HID devices")?;
+        let info = api
+            .device_list()
+            .find(|info| info.vendor_id() == LEDGER_VENDOR_ID && (info.usage_page() == USAGE_PAGE || info.interface_number() == 0))
+            .context("no Ledger found; connect and unlock it")?;
+        Ok(Box::new(Device(info.open_device(&api).context("opening the Ledger")?)))
+    }
+
+    impl Hid for Device {
+        fn write_report(&mut self, report: &[u8; REPORT_SIZE]) -> Result<()> {
+            // Report ID 0, as the device uses no numbered reports
+            let mut bytes = [0; REPORT_SIZE + 1];
+            bytes[1..].copy_from_slice(report);
+            self.0.write(&bytes).context("writing to the Ledger")?;
+            Ok(())
+        }
+
+        fn read_report(&mut self, timeout_ms: i32) -> Result<[u8; REPORT_SIZE]> {
+            let mut report = [0; REPORT_SIZE];
+            if self.0.read_timeout(&mut report, timeout_ms).context("reading from the Ledger")? == 0 {
+                bail!("the Ledger did not answer in time");
+            }
+            Ok(report)
+        }
+    }
+}
+
+#[cfg(not(feature = "ledger"))]
+mod hid {
+    use anyhow::{bail, Result};
+
+    use super::Hid;
+
+    pub fn open() -> Result<Box<dyn Hid>> {
+        bail!("this cubiq was built without Ledger support; rebuild it with --features ledger")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::ACCOUNT_PATH;
+    use anyhow::Context;
+    use ed25519_dalek::SigningKey;
+    use std::collections::VecDeque;
+
+    /// Answers as the Cubiq app would, holding `key`.
+    struct App {
+        key: SigningKey,
+        reject: bool,
+        commands: Unframer,
+        replies: VecDeque<[u8; REPORT_SIZE]>,
+        signing: Vec<u8>,
+    }
+
+    impl App {
+        fn new(reject: bool) -> Self {
+            let key = SigningKey::from_bytes(&[7; 32]);
+            App { key, reject, commands: Unframer::default(), replies: VecDeque::new(), signing: vec![] }
+        }
+
+        fn answer(&mut self, apdu: &[u8]) -> Vec<u8> {
+            let (ins, p1, p2, data) = (apdu[1], apdu[2], apdu[3], &apdu[5..]);
+            assert_eq!((apdu[0], apdu[4] as usize), (CLA, data.len()));
+            let mut response = match ins {
+                INS_GET_PUBLIC_KEY => {
+                    assert_eq!(data, encode_path(&ACCOUNT_PATH.parse().unwrap()));
+                    self.key.verifying_key().to_bytes().to_vec()
+                }
+                INS_SIGN_TX if p1 == P1_FIRST => {
+                    self.signing.clear();
+                    vec![]
+                }
+                INS_SIGN_TX if p2 == P2_MORE => {
+                    self.signing.extend_from_slice(data);
+                    vec![]
+                }
+                INS_SIGN_TX if self.reject => return SW_DENIED.to_be_bytes().to_vec(),
+                INS_SIGN_TX => {
+                    self.signing.extend_from_slice(data);
+                    let tx: Transaction = Transaction { data: vec![1; 600], ..Transaction::default() };
+                    let signed = tx.sign(&self.key);
+                    assert_eq!(self.signing, signed.encode(), "the app signs what it was sent");
+                    hex::decode(signed.signature).unwrap()
+                }
+                _ => return SW_INS_NOT_SUPPORTED.to_be_bytes().to_vec(),
+            };
+            response.extend_from_slice(&SW_OK.to_be_bytes());
+            response
+        }
+    }
+
+    impl Hid for App {
+        fn write_report(&mut self, report: &[u8; REPORT_SIZE]) -> Result<()> {
+            if let Some(apdu) = self.commands.push(report)? {
+                self.commands = Unframer::default();
+                let response = self.answer(&apdu);
+                self.replies.extend(frame(&response));
+            }
+            Ok(())
+        }
+
+        fn read_report(&mut self, _: i32) -> Result<[u8; REPORT_SIZE]> {
+            self.replies.pop_front().context("nothing to read")
+        }
+    }
+
+    #[test]
+    fn apdus_survive_framing_into_reports() {
+        let apdu: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let reports = frame(&apdu);
+        assert_eq!(reports.len(), (2 + apdu.len()).div_ceil(REPORT_SIZE - REPORT_HEADER));
+        let mut unframer = Unframer::default();
+        let (last, rest) = reports.split_last().unwrap();
+        assert!(rest.iter().all(|report| unframer.push(report).unwrap().is_none()));
+        assert_eq!(unframer.push(last).unwrap(), Some(apdu));
+
+        let mut unframer = Unframer::default();
+        assert!(unframer.push(&reports[1]).is_err(), "out of order");
+        assert_eq!(status(vec![1, 2, 0x90, 0x00]).unwrap(), [1, 2]);
+        assert!(status(vec![0x6e, 0x00]).unwrap_err().to_string().contains("Cubiq app"));
+    }
+
+    #[test]
+    fn ledger_signs_transactions_over_several_commands() {
+        let mut ledger = Ledger::open(Box::new(App::new(false)), ACCOUNT_PATH.parse().unwrap()).unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]);
+        assert_eq!(ledger.public_key(), crate::keys::public_key_hex(&key));
+        assert_eq!(ledger.confirm_address().unwrap(), Address::from_key(&key.verifying_key()));
+
+        let tx = ledger.sign_transaction(Transaction { data: vec![1; 600], ..Transaction::default() }).unwrap();
+        assert_eq!(tx.from, ledger.address().to_string());
+        assert!(tx.verify_signature().is_ok());
+
+        let mut ledger = Ledger::open(Box::new(App::new(true)), ACCOUNT_PATH.parse().unwrap()).unwrap();
+        let rejected = ledger.sign_transaction(Transaction { data: vec![1; 600], ..Transaction::default() });
+        assert!(rejected.unwrap_err().to_string().contains("rejected"));
+    }
+}
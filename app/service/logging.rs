@@ -0,0 +1,314 @@
+//! Node logging.
+//!
+//! Events from every crate go through one `tracing` subscriber: the
+//! `node.log_level` filter, which takes per-module directives like
+//! `warn,consensus=debug,networking=trace`, then one output configured by
+//! the `[log]` section. Output is `text`, one line per event, `pretty`,
+//! several lines per event for reading at a terminal, or `json`, one
+//! object per line for log collectors. It goes to stdout, or to
+//! `log.file`, which is rotated once it reaches `log.rotate_bytes` or has
+//! been written to for `log.rotate_secs`; the previous files are kept as
+//! `<file>.1`, the newest, up to `<file>.<keep_files>`.
+//!
+//! Filter and output both change at runtime, through `admin_setLogLevel`
+//! and `admin_setLogFormat` or a config reload.
+//!
+//! Whatever the output, the newest `RECENT_LINES` lines the filter lets
+//! through are also kept in memory as text, for crash reports.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, Instant};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::config::LogConfig;
+
+/// Log lines kept in memory for crash reports.
+pub const RECENT_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event
+    #[default]
+    Text,
+    /// Several indented lines per event
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogFormat::Text => "text",
+            LogFormat::Pretty => "pretty",
+            LogFormat::Json => "json",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The registry with the reloadable filter, which outputs sit on.
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+type Output = Box<dyn Layer<Filtered> + Send + Sync>;
+
+/// Changes the filter and output of the running subscriber.
+#[derive(Clone)]
+pub struct LogHandle {
+    filter: reload::Handle<EnvFilter, Registry>,
+    output: reload::Handle<Output, Filtered>,
+    /// Settings the output was last built from
+    config: Arc<Mutex<LogConfig>>,
+    recent: RecentLogs,
+}
+
+impl LogHandle {
+    pub fn set_filter(&self, filter: EnvFilter) -> Result<(), reload::Error> {
+        self.filter.reload(filter)
+    }
+
+    /// Rebuilds the output from `config`, opening its file if it has one.
+    /// The current output is kept if that fails.
+    pub fn set_output(&self, config: &LogConfig) -> Result<()> {
+        self.output.reload(output(config)?)?;
+        *self.config.lock().expect("Log config lock poisoned") = config.clone();
+        Ok(())
+    }
+
+    pub fn set_format(&self, format: LogFormat) -> Result<()> {
+        let config = self.config.lock().expect("Log config lock poisoned").clone();
+        self.set_output(&LogConfig { format, ..config })
+    }
+
+    pub fn recent(&self) -> RecentLogs {
+        self.recent.clone()
+    }
+
+    /// A handle to a subscriber that is dropped at once, so changes
+    /// through it fail.
+    #[cfg(test)]
+    pub fn detached() -> Self {
+        subscriber(EnvFilter::new("info"), &LogConfig::default()).unwrap().1
+    }
+}
+
+/// Installs the node's subscriber with `filter`, printing text to stdout
+/// until `LogHandle::set_output` is given the configured output.
+pub fn init(filter: &str) -> Result<LogHandle> {
+    let (subscriber, log) = subscriber(EnvFilter::try_new(filter)?, &LogConfig::default())?;
+    subscriber.init();
+    Ok(log)
+}
+
+/// The node's subscriber, not yet installed.
+pub fn subscriber(filter: EnvFilter, config: &LogConfig) -> Result<(impl tracing::Subscriber + Send + Sync, LogHandle)> {
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    let (output, output_handle) = reload::Layer::new(output(config)?);
+    let recent = RecentLogs::default();
+    let writer = recent.clone();
+    let recent_layer = tracing_subscriber::fmt::layer().with_writer(move || writer.clone()).with_ansi(false);
+    let log = LogHandle { filter: filter_handle, output: output_handle, config: Arc::new(Mutex::new(config.clone())), recent };
+    Ok((tracing_subscriber::registry().with(filter).with(output).with(recent_layer), log))
+}
+
+/// The newest `RECENT_LINES` log lines, oldest first.
+#[derive(Clone, Default)]
+pub struct RecentLogs(Arc<Mutex<VecDeque<String>>>);
+
+impl RecentLogs {
+    /// The lines, unless a thread is adding one; for a panic hook, whose
+    /// thread may be the one holding the lock.
+    pub fn try_lines(&self) -> Option<Vec<String>> {
+        let lines = match self.0.try_lock() {
+            Ok(lines) => lines,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        Some(lines.iter().cloned().collect())
+    }
+}
+
+/// Takes one formatted event per write.
+impl Write for RecentLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for line in String::from_utf8_lossy(buf).lines() {
+            if lines.len() == RECENT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn output(config: &LogConfig) -> Result<Output> {
+    let writer = match &config.file {
+        Some(path) => {
+            let rotation = Rotation {
+                max_bytes: Some(config.rotate_bytes).filter(|bytes| *bytes > 0),
+                max_age: Some(Duration::from_secs(config.rotate_secs)).filter(|age| !age.is_zero()),
+                keep: config.keep_files,
+            };
+            let file = RollingFile::open(path, rotation).with_context(|| format!("opening log.file {}", path.display()))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(io::stdout),
+    };
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(config.file.is_none());
+    Ok(match config.format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    })
+}
+
+/// When a log file is rotated, and how many rotated files are kept.
+#[derive(Debug, Clone, Copy)]
+struct Rotation {
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    keep: usize,
+}
+
+/// A log file that moves aside once it is big or old enough, before the
+/// write that would grow it further. Age counts from when the node opened
+/// the file, so restarts reset it.
+struct RollingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+impl RollingFile {
+    fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), rotation, file, size, opened: Instant::now() })
+    }
+
+    fn due(&self) -> bool {
+        let full = self.rotation.max_bytes.is_some_and(|max| self.size >= max);
+        let old = self.rotation.max_age.is_some_and(|max| self.opened.elapsed() >= max);
+        self.size > 0 && (full || old)
+    }
+
+    /// `<path>.<n>`, the `n`th newest rotated file.
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let oldest = self.rotated(self.rotation.keep.max(1));
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+        for n in (1..self.rotation.keep).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(from, self.rotated(n + 1))?;
+            }
+        }
+        match self.rotation.keep {
+            0 => fs::remove_file(&self.path)?,
+            _ => fs::rename(&self.path, self.rotated(1))?,
+        }
+        *self = Self::open(&self.path, self.rotation)?;
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due() {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubiq-logging-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn rotates_full_files_keeping_the_newest() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("logs/cubiq.log");
+        let mut file = RollingFile::open(&path, Rotation { max_bytes: Some(10), max_age: None, keep: 2 }).unwrap();
+        for line in ["first line\n", "second line\n", "third line\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |path: &Path| fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "fourth\n");
+        assert_eq!((read(&file.rotated(1)), read(&file.rotated(2))), ("third line\n".to_string(), "second line\n".to_string()));
+        assert!(!file.rotated(3).exists());
+
+        let mut aged = RollingFile::open(&path, Rotation { max_bytes: None, max_age: Some(Duration::ZERO), keep: 2 }).unwrap();
+        aged.write_all(b"fifth\n").unwrap();
+        assert_eq!((read(&path), read(&aged.rotated(1))), ("fifth\n".to_string(), "fourth\n".to_string()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn switches_output_at_runtime() {
+        let dir = temp_dir("output");
+        let path = dir.join("cubiq.log");
+        let (subscriber, log) = subscriber(EnvFilter::new("info"), &LogConfig::default()).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            log.set_output(&LogConfig { file: Some(path.clone()), ..LogConfig::default() }).unwrap();
+            tracing::info!(height = 3, "as text");
+            log.set_format(LogFormat::Json).unwrap();
+            tracing::info!(height = 4, "as json");
+            tracing::debug!("filtered out");
+            log.set_filter(EnvFilter::new("debug")).unwrap();
+            tracing::debug!("let through");
+        });
+        let logged = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = logged.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("as text height=3") && !lines[0].contains('\u{1b}'));
+        let json: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!((&json["message"], &json["height"], &json["level"]), (&"as json".into(), &4.into(), &"INFO".into()));
+        assert!(lines[2].contains("let through"));
+        let recent = log.recent().try_lines().unwrap();
+        assert_eq!(recent.len(), 3);
+        assert!(recent[1].contains("as json height=4") && !recent.iter().any(|line| line.contains("filtered out")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
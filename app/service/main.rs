@@ -1,35 +1,106 @@
-use core::consensus::{QubeNode, BlockProposal, Transaction};
-use tokio::sync::mpsc;
+use anyhow::Result;
+use clap::Parser;
 
-#[tokio::main]
-async fn main() {
-    let node = QubeNode::new(
-        "node1".to_string(),
-        10_000,
-        vec!["https://zkproof.cubiq.dev".to_string()],
-    ).await;
-
-    let (mut proposal_tx, proposal_rx) = mpsc::channel(10);
-    let (vote_tx, mut vote_rx) = mpsc::channel(10);
+mod access;
+mod admin;
+mod cli;
+mod clock;
+mod commands;
+mod config;
+mod datadir;
+mod diagnostics;
+mod eth;
+mod finality;
+mod grpc;
+mod integrity;
+mod keys;
+mod keystore;
+mod ledger;
+mod logging;
+mod metrics;
+mod node;
+mod reload;
+mod rest;
+mod role;
+mod rpc_server;
+mod signer;
+mod status;
+mod subscriptions;
+mod supervisor;
+mod telemetry;
+mod wallet;
+mod watchdog;
 
-    // Spawn node handler
-    tokio::spawn(async move {
-        node.run(proposal_rx, vote_tx).await;
-    });
+use cli::{Cli, Command, DbCommand, DebugCommand, KeyCommand, MultisigCommand, SnapshotCommand, TxCommand, ValidatorCommand, WalletCommand};
 
-    // Dummy block proposal
-    let proposal = BlockProposal {
-        block_hash: "0xabc...".to_string(),
-        state_root: "0xbeef...".to_string(),
-        zkurl: "zk://prover@domain.com/block1#v1&gzip&stark".to_string(),
-        transactions: vec![],
-        proposer_id: "node1".to_string(),
-        timestamp: 123456789,
-    };
-    proposal_tx.send(proposal).await.ok();
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // `run` replaces the filter and output once it has loaded `node.log_level`
+    // and `[log]`.
+    let log = logging::init(cli.global.log_level.as_deref().unwrap_or("info"))?;
+    if let Some(format) = cli.global.log_format {
+        log.set_format(format)?;
+    }
 
-    // Votes returned on vote_rx
-    if let Some(vote) = vote_rx.recv().await {
-        println!("Voted block: {:?}", vote);
+    match cli.command {
+        Command::Run(args) => commands::run(&cli.global, args, log).await,
+        Command::Init(args) => commands::init(&cli.global, args),
+        Command::Db(DbCommand::Migrate { dry_run }) => commands::migrate(&cli.global, dry_run),
+        Command::Db(DbCommand::Check { repair }) => commands::check(&cli.global, repair),
+        Command::Db(DbCommand::Stats { json }) => commands::db_stats(&cli.global, json),
+        Command::Snapshot(SnapshotCommand::Export { height, out }) => commands::export_snapshot(&cli.global, height, out),
+        Command::Snapshot(SnapshotCommand::Import { path }) => commands::import_snapshot(&cli.global, &path),
+        Command::Replay { from, to, skip_proofs, json } => commands::replay(&cli.global, from, to, skip_proofs, json).await,
+        Command::Key(KeyCommand::Create { name, key }) => commands::create_key(&cli.global, &name, &key),
+        Command::Key(KeyCommand::Import { name, secret_file, key }) => commands::import_key(&cli.global, &name, &secret_file, &key),
+        Command::Key(KeyCommand::Export { name, password }) => commands::export_key(&cli.global, &name, &password),
+        Command::Key(KeyCommand::List) => commands::list_keys(&cli.global),
+        Command::Key(KeyCommand::Mnemonic { words }) => commands::new_mnemonic(words),
+        Command::Key(KeyCommand::Derive { name, path, mnemonic, key }) => {
+            commands::derive_key(&cli.global, &name, &path, &mnemonic, &key)
+        }
+        Command::Key(KeyCommand::Ledger { path }) => commands::ledger_address(path),
+        Command::Key(KeyCommand::Recover { mnemonic, kdf, force, password }) => {
+            commands::recover_keys(&cli.global, &mnemonic, kdf, force, &password)
+        }
+        Command::Validator(ValidatorCommand::Register { node_id, stake, keys, signer: args, rpc }) => {
+            let mut signer = commands::signer(&cli.global, &args)?;
+            commands::register_validator(&cli.global, &node_id, stake, &keys, &args.password, signer.as_mut(), &rpc.rpc).await
+        }
+        Command::Validator(ValidatorCommand::RotateKey { node_id, new_key, network_key, epoch, signer, rpc }) => {
+            commands::rotate_key(&cli.global, &node_id, &new_key, network_key.as_deref(), epoch, &signer, &rpc.rpc).await
+        }
+        Command::Tx(TxCommand::Send { to, value, data, signer, fee, rpc }) => {
+            let mut signer = commands::signer(&cli.global, &signer)?;
+            commands::send_transaction(&to, value, &data, signer.as_mut(), &fee, &rpc.rpc).await
+        }
+        Command::Wallet(WalletCommand::Balance { account, signer, rpc }) => {
+            wallet::balance(&wallet::account(&cli.global, account, &signer)?, &rpc.rpc).await
+        }
+        Command::Wallet(WalletCommand::History { account, limit, before, signer, rpc }) => {
+            wallet::history(&wallet::account(&cli.global, account, &signer)?, limit, before, &rpc.rpc).await
+        }
+        Command::Wallet(WalletCommand::Transfer { to, value, send }) => {
+            wallet::transfer(&cli.global, &to, value, &send).await
+        }
+        Command::Wallet(WalletCommand::Delegate { validator, amount, send }) => {
+            wallet::delegate(&cli.global, &validator, amount, &send).await
+        }
+        Command::Wallet(WalletCommand::Undelegate { validator, amount, send }) => {
+            wallet::undelegate(&cli.global, &validator, amount, &send).await
+        }
+        Command::Wallet(WalletCommand::Broadcast { path, rpc }) => wallet::broadcast(&path, &rpc.rpc).await,
+        Command::Wallet(WalletCommand::Multisig(MultisigCommand::Create { threshold, members, value, send })) => {
+            wallet::create_multisig(&cli.global, threshold, &members, value, &send).await
+        }
+        Command::Wallet(WalletCommand::Multisig(MultisigCommand::Propose { from, to, value, fee, offline, rpc })) => {
+            wallet::propose(&from, &to, value, &fee, &offline, &rpc.rpc).await
+        }
+        Command::Wallet(WalletCommand::Multisig(MultisigCommand::Sign { path, signer })) => {
+            wallet::cosign(&path, commands::signer(&cli.global, &signer)?.as_mut())
+        }
+        Command::Wallet(WalletCommand::Multisig(MultisigCommand::Combine { paths })) => wallet::combine(&paths),
+        Command::Debug(DebugCommand::Bundle { out, rpc }) => commands::debug_bundle(&cli.global, out, &rpc.rpc).await,
     }
 }
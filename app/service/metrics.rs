@@ -0,0 +1,100 @@
+//! Prometheus metrics endpoint.
+//!
+//! Every subsystem registers its metrics with one registry, under its own
+//! prefix: `network_`, `consensus_`, `mempool_`, `zkurl_`, `storage_` and
+//! `rpc_`, each after the registry's `cubiq_`. The endpoint serves them in
+//! the text format at `GET /metrics` on `metrics.addr`, apart from the
+//! RPC server so scrapers need neither its auth token nor its rate limit.
+
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Prefix of every metric the node exports.
+pub const NAMESPACE: &str = "cubiq";
+
+/// Label for calls to methods the node doesn't have, so callers can't
+/// grow the label set without bound.
+const UNKNOWN_METHOD: &str = "unknown";
+
+/// A registry whose metrics are all prefixed with `NAMESPACE`.
+pub fn registry() -> Registry {
+    Registry::new_custom(Some(NAMESPACE.to_string()), None).expect("the namespace is a valid metric name")
+}
+
+pub struct RpcMetrics {
+    requests: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl RpcMetrics {
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("rpc_requests_total", "JSON-RPC calls by method and whether they succeeded"),
+                &["method", "outcome"],
+            )?,
+            duration: HistogramVec::new(HistogramOpts::new("rpc_request_duration_seconds", "Time taken to answer JSON-RPC calls"), &["method"])?,
+        };
+        registry.register(Box::new(metrics.requests.clone()))?;
+        registry.register(Box::new(metrics.duration.clone()))?;
+        Ok(metrics)
+    }
+
+    /// Records a call to `method`, or to no known method if `None`.
+    pub fn observe(&self, method: Option<&str>, succeeded: bool, elapsed: Duration) {
+        let method = method.unwrap_or(UNKNOWN_METHOD);
+        self.requests.with_label_values(&[method, if succeeded { "ok" } else { "error" }]).inc();
+        self.duration.with_label_values(&[method]).observe(elapsed.as_secs_f64());
+    }
+}
+
+/// Serves `registry` on `listener` until `shutdown` completes.
+pub async fn serve(listener: TcpListener, registry: Registry, shutdown: impl Future<Output = ()> + Send + 'static) -> Result<()> {
+    let app = Router::new().route("/metrics", get(metrics)).with_state(registry);
+    axum::serve(listener, app).with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+async fn metrics(State(registry): State<Registry>) -> Response {
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    match encoder.encode(&registry.gather(), &mut body) {
+        Ok(()) => ([(CONTENT_TYPE, encoder.format_type().to_string())], body).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_every_registered_metric_under_the_namespace() {
+        let registry = registry();
+        let rpc = RpcMetrics::register(&registry).unwrap();
+        rpc.observe(Some("node_status"), true, Duration::from_millis(3));
+        rpc.observe(None, false, Duration::from_millis(1));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, registry, async {
+            let _ = stopped.await;
+        }));
+        let body = reqwest::get(format!("http://{}/metrics", addr)).await.unwrap().text().await.unwrap();
+        assert!(body.contains(r#"cubiq_rpc_requests_total{method="node_status",outcome="ok"} 1"#));
+        assert!(body.contains(r#"cubiq_rpc_requests_total{method="unknown",outcome="error"} 1"#));
+        assert!(body.contains("cubiq_rpc_request_duration_seconds_count"));
+        let _ = stop.send(());
+        server.await.unwrap().unwrap();
+    }
+}
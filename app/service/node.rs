@@ -0,0 +1,726 @@
+//! Node orchestrator: builds every subsystem from `NodeConfig`, connects
+//! their channels and supervises their tasks, restarting the API servers
+//! and background jobs when they fail; see [`crate::supervisor`].
+//!
+//! Networking is constructed first, since the resolver fetches CID proofs
+//! through it, but its event loop starts last, once consensus is ready for
+//! messages. Shutdown runs the other way: the network stops delivering
+//! messages and saves its peer store, then consensus drains the proposals
+//! it has already accepted. The RPC server stops taking requests at the
+//! same time as the network, and background compaction stops scheduling.
+//! There is no write-ahead log to flush: a validator logs each vote in the
+//! block store, synced to disk, before sending it.
+//!
+//! Transactions gossiped by peers go to the mempool like those submitted
+//! over RPC; only the latter are gossiped on, since gossipsub already
+//! forwards the former.
+//!
+//! Every subsystem reports to one metrics registry, served on its own
+//! listener when `metrics.enabled`; see [`crate::metrics`]. Nodes with a
+//! `telemetry.url` also report to that collector; see [`crate::telemetry`].
+//! Panics while the node runs leave crash reports in the data dir; see
+//! [`crate::diagnostics`]. A watchdog pauses admission and the API when
+//! the node runs short of disk, memory or file descriptors; see
+//! [`crate::watchdog`]. Validators stop voting while their clock is too
+//! far off the time servers'; see [`crate::clock`].
+//!
+//! Validators gossip a finality certificate for each block they see
+//! finalized, which light nodes check in place of verifying blocks; see
+//! [`crate::finality`].
+//!
+//! The node's role decides which of these run; see [`crate::role`].
+//! Validators unlock their key from the keystore before anything starts,
+//! and sign their votes with it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use consensus::compaction::Compactor;
+use consensus::events::{ConsensusEvent, TxStatus};
+use consensus::genesis::ChainSpec;
+use consensus::mempool::Origin;
+use consensus::metrics::{ConsensusMetrics, StorageMetrics};
+use consensus::store::BlockStore;
+use consensus::{QubeNode, ValidatorSet};
+use cubiq_light::LightClient;
+use ed25519_dalek::SigningKey;
+use networking::metrics::NetworkMetrics;
+use networking::{Multiaddr, NetworkConfig, NetworkMessage, P2PNetworking};
+use prometheus::Registry;
+use prover::MobileProofVerifier;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot, watch};
+use zkurl::cache::{BundleCache, CacheConfig};
+use zkurl::metrics::ResolverMetrics;
+use zkurl::p2p::P2pMode;
+use zkurl::resolver::ZkURLResolver;
+
+use crate::access::Policy;
+use crate::admin::Admin;
+use crate::clock::ClockCheck;
+use crate::config::NodeConfig;
+use crate::diagnostics::CrashReporter;
+use crate::finality;
+use crate::grpc;
+use crate::keys;
+use crate::keystore::KeyFile;
+use crate::metrics::{self, RpcMetrics};
+use crate::logging::LogHandle;
+use crate::role::NodeRole;
+use crate::rpc_server::{self, Backend};
+use crate::status::NodeInfo;
+use crate::subscriptions;
+use crate::supervisor::{Restart, Supervisor};
+use crate::telemetry::Telemetry;
+use crate::watchdog::Watchdog;
+
+/// Proposals buffered ahead of consensus before the router applies
+/// backpressure.
+const PROPOSAL_QUEUE: usize = 64;
+const VOTE_QUEUE: usize = 64;
+
+/// How long subsystems get to wind down before they are aborted.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+pub struct Node {
+    role: NodeRole,
+    consensus: Arc<QubeNode>,
+    /// What a light node checks gossiped certificates with
+    light: Option<LightClient>,
+    network: P2PNetworking,
+    rpc: TcpListener,
+    ws: TcpListener,
+    grpc: TcpListener,
+    policy: Arc<Policy>,
+    backend: Arc<Backend>,
+    /// Whether the RPC server also serves the REST gateway
+    rest: bool,
+    compactor: Compactor,
+    /// Where metrics are served, if they are
+    metrics: Option<(TcpListener, Registry)>,
+    telemetry: Option<Telemetry>,
+    crashes: CrashReporter,
+    watchdog: Option<Watchdog>,
+    clock: Option<ClockCheck>,
+}
+
+impl Node {
+    /// `data_dir` is where operators' snapshots and crash reports go; `log`
+    /// lets them change the log filter and keeps the lines reports include.
+    pub async fn new(config: &NodeConfig, spec: &ChainSpec, data_dir: &Path, log: LogHandle) -> Result<Self> {
+        let role = config.node.role;
+        #[cfg(feature = "chaos")]
+        tracing::warn!("built with fault injection; not for production networks");
+        if role.votes() && !spec.validators.iter().any(|v| v.node_id == config.consensus.node_id) {
+            tracing::warn!(
+                node_id = %config.consensus.node_id,
+                "not a genesis validator; votes are ignored until the node is registered"
+            );
+        }
+        let password = match role.votes() || config.network.identity_key.is_some() {
+            true => keys::password(config.consensus.validator_key_password_file.as_deref(), false)
+                .context("consensus.validator_key_password_file")?,
+            false => String::new(),
+        };
+        let validator_keys = match role.votes() {
+            true => validator_keys(config, &password)?,
+            false => vec![],
+        };
+        let identity = match &config.network.identity_key {
+            Some(path) => Some(unlock("network.identity_key", path, &password)?),
+            None => None,
+        };
+        let network_config = NetworkConfig {
+            listen_addrs: multiaddrs(&config.network.listen_addrs).context("network.listen_addrs")?,
+            bootnodes: multiaddrs(&config.network.bootnodes).context("network.bootnodes")?,
+            peer_store: Some(config.network.peer_store.clone()),
+            topics: role.topics(),
+            identity: identity.as_ref().map(SigningKey::to_bytes),
+        };
+        let registry = metrics::registry();
+        let network = P2PNetworking::with_config(network_config)
+            .await
+            .context("starting networking")?
+            .with_metrics(Arc::new(NetworkMetrics::register(&registry)?));
+
+        // Proofs fetched to verify blocks are what RPC serves proofs from
+        let resolver = ZkURLResolver::new(config.resolver.endpoints.clone())
+            .with_cache(BundleCache::new(CacheConfig::default()))
+            .with_block_fetcher(Arc::new(network.block_fetcher()), P2pMode::default())
+            .with_metrics(Arc::new(ResolverMetrics::register(&registry)?));
+        let store = open_store(config)
+            .with_context(|| format!("opening storage.db_path {}", config.storage.db_path.display()))?;
+        let compactor = Compactor::new(store.clone(), config.compaction_interval())
+            .with_metrics(Arc::new(StorageMetrics::register(&registry)?));
+        let mut consensus = QubeNode::new(
+            config.consensus.node_id.clone(),
+            spec.chain_id,
+            config.consensus.stake,
+            config.resolver.endpoints.clone(),
+        )
+        .await
+        .with_resolver(resolver)
+        .with_verifier(MobileProofVerifier::with_config(role.verifier_config()))
+        .with_voting(role.votes())
+        .with_store(store)
+        .with_pruning(config.state_pruning())
+        .with_max_clock_skew(Duration::from_secs(config.clock.proposal_skew_secs))
+        .with_metrics(Arc::new(ConsensusMetrics::register(&registry)?));
+        let held: Vec<String> = validator_keys.iter().map(keys::public_key_hex).collect();
+        for key in validator_keys {
+            consensus = consensus.with_validator_key(key);
+        }
+        consensus.load_genesis(spec).await.map_err(anyhow::Error::msg)?;
+        let light = match role {
+            NodeRole::Light => {
+                let verifier = MobileProofVerifier::with_config(role.verifier_config());
+                Some(finality::light_client(spec, &*consensus.validator_set.read().await)?.with_verifier(verifier))
+            }
+            NodeRole::Validator | NodeRole::Full | NodeRole::Archive => None,
+        };
+        let height = consensus.restore().await.map_err(anyhow::Error::msg).context("loading finalized blocks")?;
+        tracing::info!(height, "restored finalized chain");
+        if role.votes() {
+            let network_key = identity.as_ref().map(keys::public_key_hex);
+            check_validator_keys(config, &*consensus.validator_set.read().await, &held, network_key.as_deref())?;
+        }
+
+        let rpc = TcpListener::bind(config.rpc.http_addr)
+            .await
+            .with_context(|| format!("binding rpc.http_addr {}", config.rpc.http_addr))?;
+        let ws = TcpListener::bind(config.rpc.ws_addr)
+            .await
+            .with_context(|| format!("binding rpc.ws_addr {}", config.rpc.ws_addr))?;
+        let grpc = TcpListener::bind(config.rpc.grpc_addr)
+            .await
+            .with_context(|| format!("binding rpc.grpc_addr {}", config.rpc.grpc_addr))?;
+        let rpc_metrics = Arc::new(RpcMetrics::register(&registry)?);
+        let metrics = match config.metrics.enabled {
+            true => {
+                let listener = TcpListener::bind(config.metrics.addr)
+                    .await
+                    .with_context(|| format!("binding metrics.addr {}", config.metrics.addr))?;
+                Some((listener, registry))
+            }
+            false => None,
+        };
+
+        let policy = Policy::from_config(&config.rpc).context("rpc.auth_token_file")?;
+
+        let consensus = Arc::new(consensus);
+        let info = NodeInfo::new(role, network.peer_count(), !config.network.bootnodes.is_empty());
+        let telemetry = Telemetry::new(&config.telemetry, Arc::clone(&consensus), info.clone(), spec);
+        let crashes = CrashReporter::new(data_dir, log.recent(), Arc::clone(&consensus), info.clone());
+        let watchdog = Watchdog::new(&config.watchdog, data_dir, Arc::clone(&consensus), info.pressure.clone());
+        let clock = ClockCheck::new(&config.clock, Arc::clone(&consensus));
+        let admin = Admin::new(network.peer_control(), log, data_dir);
+        let backend = Arc::new(Backend { consensus: Arc::clone(&consensus), info, admin, metrics: Some(rpc_metrics) });
+
+        Ok(Self {
+            role,
+            consensus,
+            light,
+            network,
+            rpc,
+            ws,
+            grpc,
+            policy: Arc::new(policy),
+            backend,
+            rest: config.rpc.rest,
+            compactor,
+            metrics,
+            telemetry,
+            crashes,
+            watchdog,
+            clock,
+        })
+    }
+
+    pub fn consensus(&self) -> Arc<QubeNode> {
+        Arc::clone(&self.consensus)
+    }
+
+    /// Runs until `shutdown` completes or a subsystem exits on its own,
+    /// then stops everything in order. Fails if any subsystem failed.
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        let inbound = self.network.inbound().context("network inbound stream already taken")?;
+        let outbound = self.network.sender.clone();
+        let (proposal_tx, proposal_rx) = mpsc::channel(PROPOSAL_QUEUE);
+        let (vote_tx, vote_rx) = mpsc::channel(VOTE_QUEUE);
+
+        let mut supervisor = Supervisor::new(self.backend.info.subsystems.clone());
+        let proposals = if self.role.verifies_blocks() {
+            let consensus = Arc::clone(&self.consensus);
+            supervisor.spawn("consensus", async move {
+                consensus.run(proposal_rx, vote_tx).await;
+                Ok(())
+            });
+            supervisor.spawn("vote relay", relay_votes(vote_rx, outbound, Arc::clone(&self.consensus)));
+            Some(proposal_tx)
+        } else {
+            None
+        };
+        supervisor.spawn("message router", route_inbound(inbound, proposals, self.light, Arc::clone(&self.consensus)));
+        let (stop_apis, apis_stopped) = watch::channel(());
+        let api_stopped = move || {
+            let mut stopped = apis_stopped.clone();
+            async move {
+                let _ = stopped.changed().await;
+            }
+        };
+
+        // Subsystems below are rebuilt when they stop, from clones of what
+        // they were built from; the API servers from duplicates of their
+        // listening sockets
+        let (consensus, sender, stopped) = (Arc::clone(&self.consensus), self.network.sender.clone(), api_stopped.clone());
+        supervisor.spawn_restarting("transaction relay", Restart::BACKGROUND, move || {
+            relay_transactions(Arc::clone(&consensus), sender.clone(), stopped())
+        });
+        if self.role.votes() {
+            let (consensus, sender, stopped) = (Arc::clone(&self.consensus), self.network.sender.clone(), api_stopped.clone());
+            supervisor.spawn_restarting("finalization relay", Restart::BACKGROUND, move || {
+                relay_finalizations(Arc::clone(&consensus), sender.clone(), stopped())
+            });
+        }
+        let (rpc_addr, ws_addr, grpc_addr) = (self.rpc.local_addr()?, self.ws.local_addr()?, self.grpc.local_addr()?);
+        let (rpc, backend, policy, rest, stopped) =
+            (self.rpc.into_std()?, Arc::clone(&self.backend), Arc::clone(&self.policy), self.rest, api_stopped.clone());
+        supervisor.spawn_restarting("rpc", Restart::BACKGROUND, move || {
+            let (listener, backend, policy, stopped) = (duplicate(&rpc), Arc::clone(&backend), Arc::clone(&policy), stopped());
+            async move { rpc_server::serve(listener?, backend, policy, rest, stopped).await }
+        });
+        let (ws, backend, policy, stopped) = (self.ws.into_std()?, Arc::clone(&self.backend), Arc::clone(&self.policy), api_stopped.clone());
+        supervisor.spawn_restarting("websocket", Restart::BACKGROUND, move || {
+            let (listener, backend, policy, stopped) = (duplicate(&ws), Arc::clone(&backend), Arc::clone(&policy), stopped());
+            async move { subscriptions::serve(listener?, backend, policy, stopped).await }
+        });
+        let (grpc, consensus, policy, stopped) = (self.grpc.into_std()?, Arc::clone(&self.consensus), Arc::clone(&self.policy), api_stopped.clone());
+        supervisor.spawn_restarting("grpc", Restart::BACKGROUND, move || {
+            let (listener, consensus, policy, stopped) = (duplicate(&grpc), Arc::clone(&consensus), Arc::clone(&policy), stopped());
+            async move { grpc::serve(listener?, consensus, policy, stopped).await }
+        });
+        let (compactor, consensus, stopped) = (self.compactor, Arc::clone(&self.consensus), api_stopped.clone());
+        supervisor.spawn_restarting("compaction", Restart::BACKGROUND, move || {
+            let (compactor, consensus, stopped) = (compactor.clone(), Arc::clone(&consensus), stopped());
+            async move {
+                compactor.run(consensus, stopped).await;
+                Ok(())
+            }
+        });
+        let metrics_addr = match self.metrics {
+            Some((listener, registry)) => {
+                let addr = listener.local_addr()?;
+                let (listener, stopped) = (listener.into_std()?, api_stopped.clone());
+                supervisor.spawn_restarting("metrics", Restart::BACKGROUND, move || {
+                    let (duplicated, registry, stopped) = (duplicate(&listener), registry.clone(), stopped());
+                    async move { metrics::serve(duplicated?, registry, stopped).await }
+                });
+                Some(addr)
+            }
+            None => None,
+        };
+        if let Some(telemetry) = self.telemetry {
+            let stopped = api_stopped.clone();
+            supervisor.spawn_restarting("telemetry", Restart::BACKGROUND, move || telemetry.clone().run(stopped()));
+        }
+        supervisor.spawn("crash reports", self.crashes.run(api_stopped()));
+        if let Some(watchdog) = self.watchdog {
+            let stopped = api_stopped.clone();
+            supervisor.spawn_restarting("watchdog", Restart::BACKGROUND, move || watchdog.clone().run(stopped()));
+        }
+        if let Some(clock) = self.clock {
+            let stopped = api_stopped.clone();
+            supervisor.spawn_restarting("clock check", Restart::BACKGROUND, move || clock.clone().run(stopped()));
+        }
+        let (stop_network, network_stopped) = oneshot::channel::<()>();
+        supervisor.spawn("networking", self.network.run_until(async {
+            let _ = network_stopped.await;
+        }));
+        tracing::info!(role = %self.role, rpc = %rpc_addr, ws = %ws_addr, grpc = %grpc_addr, metrics = ?metrics_addr, "node started");
+
+        let mut failed = Vec::new();
+        tokio::select! {
+            _ = shutdown => tracing::info!("shutdown requested"),
+            Some((name, result)) = supervisor.join_next() => {
+                tracing::error!(subsystem = name, error = ?result.err(), "subsystem exited unexpectedly");
+                failed.push(name);
+            }
+        }
+
+        // Stopping the network closes the inbound stream, which ends the
+        // router, which closes the proposal queue once consensus drains it.
+        supervisor.stop();
+        let _ = stop_network.send(());
+        let _ = stop_apis.send(());
+        let drain = async {
+            while let Some((name, result)) = supervisor.join_next().await {
+                match result {
+                    Ok(()) => tracing::debug!(subsystem = name, "stopped"),
+                    Err(e) => {
+                        tracing::error!(subsystem = name, error = %e, "failed during shutdown");
+                        failed.push(name);
+                    }
+                }
+            }
+        };
+        if tokio::time::timeout(SHUTDOWN_GRACE, drain).await.is_err() {
+            tracing::error!("subsystems did not stop within {:?}; aborting them", SHUTDOWN_GRACE);
+            supervisor.abort_all();
+            failed.push("shutdown");
+        }
+
+        if !failed.is_empty() {
+            bail!("subsystems failed: {}", failed.join(", "));
+        }
+        tracing::info!("node stopped");
+        Ok(())
+    }
+}
+
+/// Another handle on `listener`'s socket, for a restarted server.
+fn duplicate(listener: &std::net::TcpListener) -> Result<TcpListener> {
+    Ok(TcpListener::from_std(listener.try_clone()?)?)
+}
+
+/// Unlocks `consensus.validator_key`, and `consensus.next_validator_key`
+/// if set, with `password`.
+fn validator_keys(config: &NodeConfig, password: &str) -> Result<Vec<SigningKey>> {
+    let current = Some(("consensus.validator_key", &config.consensus.validator_key));
+    let next = config.consensus.next_validator_key.as_ref().map(|path| ("consensus.next_validator_key", path));
+    current.into_iter().chain(next).map(|(name, path)| unlock(name, path, password)).collect()
+}
+
+/// Unlocks the key file at `path`, set as config key `name`.
+fn unlock(name: &str, path: &Path, password: &str) -> Result<SigningKey> {
+    KeyFile::read(path)?.signing_key(password).with_context(|| format!("unlocking {} {}", name, path.display()))
+}
+
+/// Checks that the node holds, among `held`, the key `validators` has for
+/// its id, if it is a validator, and warns if it is rotating to a key it
+/// does not hold or connects with a network key other than the one it
+/// registered, or re-registered with in a pending rotation.
+fn check_validator_keys(config: &NodeConfig, validators: &ValidatorSet, held: &[String], network_key: Option<&str>) -> Result<()> {
+    let node_id = &config.consensus.node_id;
+    let holds = |public_key: &str| held.iter().any(|key| key.eq_ignore_ascii_case(public_key));
+    if let Some(validator) = validators.validators.get(node_id).filter(|v| !holds(&v.public_key)) {
+        bail!(
+            "{} votes with {}, but consensus.validator_key {} holds {}{}",
+            node_id,
+            validator.public_key,
+            config.consensus.validator_key.display(),
+            held[0],
+            held.get(1).map(|next| format!(" and consensus.next_validator_key {}", next)).unwrap_or_default()
+        );
+    }
+    if let Some(rotation) = validators.pending_rotations.get(node_id).filter(|r| !holds(&r.public_key)) {
+        tracing::warn!(
+            node_id = %node_id,
+            public_key = %rotation.public_key,
+            epoch = rotation.epoch,
+            "rotating to a key the node does not hold; set consensus.next_validator_key before the epoch begins"
+        );
+    }
+    let registered = validators.validators.get(node_id).and_then(|v| v.registration.as_ref());
+    if let Some(registration) = registered.filter(|r| network_key.is_none_or(|key| !key.eq_ignore_ascii_case(&r.network_key))) {
+        tracing::warn!(
+            node_id = %node_id,
+            network_key = %registration.network_key,
+            "connecting as a peer other than the registered one; set network.identity_key to the registered network key"
+        );
+    }
+    let rotating = validators.pending_rotations.get(node_id).and_then(|r| Some((r.epoch, r.registration.as_ref()?)));
+    if let Some((epoch, next)) = rotating.filter(|(_, next)| network_key.is_none_or(|key| !key.eq_ignore_ascii_case(&next.network_key))) {
+        tracing::warn!(
+            node_id = %node_id,
+            network_key = %next.network_key,
+            epoch,
+            "re-registered to connect as another peer; set network.identity_key to the new network key once the epoch begins"
+        );
+    }
+    Ok(())
+}
+
+/// Hands proposals from peers to consensus, when the node verifies them,
+/// and records their votes. A light node checks the finality certificates
+/// they gossip with `light`.
+async fn route_inbound(
+    mut inbound: mpsc::UnboundedReceiver<NetworkMessage>,
+    proposals: Option<mpsc::Sender<consensus::BlockProposal>>,
+    mut light: Option<LightClient>,
+    consensus: Arc<QubeNode>,
+) -> Result<()> {
+    while let Some(message) = inbound.recv().await {
+        #[cfg(feature = "chaos")]
+        if consensus.faults().drop_gossip() {
+            tracing::trace!("dropping gossip: injected fault");
+            continue;
+        }
+        match message {
+            NetworkMessage::BlockProposal(proposal) => {
+                let Some(proposals) = &proposals else {
+                    continue;
+                };
+                match bridge(&proposal) {
+                    Ok(proposal) => {
+                        if proposals.send(proposal).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => tracing::warn!(error = %e, "dropping malformed proposal"),
+                }
+            }
+            NetworkMessage::Vote(vote) => match bridge(&vote) {
+                Ok(vote) => {
+                    consensus.record_vote(vote).await;
+                }
+                Err(e) => tracing::warn!(error = %e, "dropping malformed vote"),
+            },
+            NetworkMessage::ProofAnnouncement(announcement) => {
+                tracing::debug!(zkurl = %announcement.zkurl, "proof announced");
+            }
+            NetworkMessage::Finalization(certificate) => {
+                let checkpoint = &certificate.checkpoint;
+                let Some(light) = &mut light else {
+                    tracing::debug!(block = %checkpoint.block_hash, "block finalized");
+                    continue;
+                };
+                match finality::follow(light, &certificate) {
+                    Ok(true) => tracing::info!(height = checkpoint.height, block = %checkpoint.block_hash, "block finalized"),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!(block = %checkpoint.block_hash, error = %e, "dropping finality certificate"),
+                }
+            }
+            NetworkMessage::Transaction(tx) => match bridge(&tx) {
+                Ok(tx) => {
+                    if let Err(e) = consensus.receive_transaction(tx).await {
+                        tracing::debug!(error = %e, "dropping gossiped transaction");
+                    }
+                }
+                Err(e) => tracing::warn!(error = %e, "dropping malformed transaction"),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Gossips transactions submitted to this node as the mempool admits
+/// them, until `shutdown` completes.
+async fn relay_transactions(
+    consensus: Arc<QubeNode>,
+    outbound: mpsc::UnboundedSender<NetworkMessage>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut events = consensus.subscribe();
+    loop {
+        let hash = tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            event = events.recv() => match event {
+                Ok(ConsensusEvent::TxStatus { hash, status: TxStatus::Pending, .. }) => hash,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "transaction relay fell behind; some transactions were not gossiped");
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        };
+        let tx = {
+            let state = consensus.consensus_state.read().await;
+            match state.mempool.origin(&hash) {
+                Some(Origin::Local) => state.mempool.get(&hash).cloned(),
+                _ => None,
+            }
+        };
+        if let Some(tx) = tx {
+            // Sends fail only once the network has stopped
+            let _ = outbound.send(NetworkMessage::Transaction(bridge(&tx)?));
+        }
+    }
+}
+
+/// Gossips the certificate of each block finalized here, until
+/// `shutdown` completes. Blocks whose proof the node does not hold, not
+/// having verified them, have none.
+async fn relay_finalizations(
+    consensus: Arc<QubeNode>,
+    outbound: mpsc::UnboundedSender<NetworkMessage>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    let mut events = consensus.subscribe();
+    loop {
+        let height = tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            event = events.recv() => match event {
+                Ok(ConsensusEvent::Finalized { height, .. }) => height,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "finalization relay fell behind; some certificates were not gossiped");
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        };
+        match finality::certificate(&consensus, height).await {
+            // Sends fail only once the network has stopped
+            Some(certificate) => {
+                let _ = outbound.send(NetworkMessage::Finalization(certificate));
+            }
+            None => tracing::debug!(height, "no certificate to gossip; the block's proof is not held"),
+        }
+    }
+}
+
+/// Records this node's votes and gossips them.
+async fn relay_votes(
+    mut votes: mpsc::Receiver<consensus::Vote>,
+    outbound: mpsc::UnboundedSender<NetworkMessage>,
+    consensus: Arc<QubeNode>,
+) -> Result<()> {
+    while let Some(vote) = votes.recv().await {
+        let message = NetworkMessage::Vote(bridge(&vote)?);
+        consensus.record_vote(vote).await;
+        // The network is the first subsystem to stop; votes cast while
+        // draining are kept locally only.
+        let _ = outbound.send(message);
+    }
+    Ok(())
+}
+
+/// Networking and consensus define the same wire types separately.
+pub fn bridge<T: Serialize, U: DeserializeOwned>(value: &T) -> Result<U> {
+    Ok(serde_json::from_value(serde_json::to_value(value)?)?)
+}
+
+/// Opens the block store with the backend, retention and freezer the
+/// config asks for.
+pub fn open_store(config: &NodeConfig) -> Result<BlockStore> {
+    let kv = config.storage.backend.open(&config.storage.db_path)?;
+    let mut store = BlockStore::with_kv(kv, config.retained_blocks())?;
+    if let Some(freeze_after) = config.freeze_after() {
+        store = store.with_freezer(&config.storage.db_path.join("freezer"), freeze_after)?;
+    }
+    Ok(store)
+}
+
+fn multiaddrs(addrs: &[String]) -> Result<Vec<Multiaddr>> {
+    addrs
+        .iter()
+        .map(|addr| addr.parse().map_err(|e| anyhow!("{:?}: {}", addr, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::genesis::GenesisValidator;
+    use ed25519_dalek::SigningKey;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    async fn consensus() -> Arc<QubeNode> {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        node.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        Arc::new(node)
+    }
+
+    /// `voter`'s vote for 0xabc, signed with the dev validator's key.
+    fn vote(voter: &str) -> networking::Vote {
+        let vote = consensus::Vote {
+            block_hash: "0xabc".to_string(),
+            voter_id: voter.to_string(),
+            stake: 10,
+            timestamp: 0,
+            ..consensus::Vote::default()
+        };
+        bridge(&vote.sign(&signer())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_proposals_to_consensus_and_records_votes() {
+        let consensus = consensus().await;
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+        let (proposal_tx, mut proposal_rx) = mpsc::channel(4);
+        let router = tokio::spawn(route_inbound(inbound_rx, Some(proposal_tx), None, Arc::clone(&consensus)));
+
+        inbound_tx.send(NetworkMessage::Vote(vote("v1"))).unwrap();
+        inbound_tx
+            .send(NetworkMessage::BlockProposal(networking::BlockProposal {
+                block_hash: "0xabc".to_string(),
+                state_root: "0xdef".to_string(),
+                zkurl: "zk://prover@example.com/block1".parse().unwrap(),
+                transactions: vec![],
+                proposer_id: "v1".to_string(),
+                timestamp: 0,
+                base_fee: 1,
+                gas_used: 0,
+            }))
+            .unwrap();
+        drop(inbound_tx);
+
+        assert_eq!(proposal_rx.recv().await.unwrap().block_hash, "0xabc");
+        router.await.unwrap().unwrap();
+        assert_eq!(consensus.consensus_state.read().await.votes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn gossips_only_transactions_submitted_locally() {
+        let consensus = consensus().await;
+        let tx = |nonce| {
+            consensus::Transaction {
+                chain_id: 7,
+                nonce,
+                to: BOB.to_string(),
+                value: 5,
+                gas_limit: 21_000,
+                max_fee_per_gas: 1,
+                ..consensus::Transaction::default()
+            }
+            .sign(&signer())
+        };
+        let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let relay = tokio::spawn(relay_transactions(Arc::clone(&consensus), outbound_tx, async {
+            let _ = stopped.await;
+        }));
+        tokio::task::yield_now().await;
+
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+        inbound_tx.send(NetworkMessage::Transaction(bridge(&tx(0)).unwrap())).unwrap();
+        drop(inbound_tx);
+        route_inbound(inbound_rx, None, None, Arc::clone(&consensus)).await.unwrap();
+        assert!(consensus.consensus_state.read().await.mempool.get(&tx(0).hash).is_some());
+        consensus.submit_transaction(tx(1)).await.unwrap();
+
+        match outbound_rx.recv().await.unwrap() {
+            NetworkMessage::Transaction(gossiped) => assert_eq!(gossiped.hash, tx(1).hash),
+            other => panic!("gossiped {:?}", other),
+        }
+        stop.send(()).unwrap();
+        relay.await.unwrap().unwrap();
+        assert!(outbound_rx.try_recv().is_err());
+
+        let multisig = consensus::Transaction { kind: consensus::TxKind::CreateMultisig, signature: String::new(), ..tx(2) };
+        let multisig = consensus::Transaction { cosignatures: vec![multisig.cosign(&signer())], ..multisig };
+        let gossiped: networking::Transaction = bridge(&multisig).unwrap();
+        let received: consensus::Transaction = bridge(&gossiped).unwrap();
+        assert_eq!((received.kind, received.cosignatures), (multisig.kind, multisig.cosignatures));
+    }
+}
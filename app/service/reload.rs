@@ -0,0 +1,229 @@
+//! Runtime reload of `config.toml`.
+//!
+//! On SIGHUP, or when the file's modification time changes, the config is
+//! loaded again through the same layers as at startup. Settings listed in
+//! [`RELOADABLE`] take effect immediately; changes to anything else are
+//! logged and ignored until the node restarts.
+
+use consensus::genesis::ChainSpec;
+use consensus::QubeNode;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use toml::Value;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::NodeConfig;
+use crate::logging::LogHandle;
+
+/// Settings that can change without a restart.
+pub const RELOADABLE: [&str; 7] = [
+    "node.log_level",
+    "resolver.endpoints",
+    "log.format",
+    "log.file",
+    "log.rotate_bytes",
+    "log.rotate_secs",
+    "log.keep_files",
+];
+
+/// How often the config file's modification time is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct ConfigWatcher {
+    path: PathBuf,
+    required: bool,
+    data_dir: PathBuf,
+    overrides: Vec<(&'static str, Value)>,
+    current: NodeConfig,
+    chain_id: u64,
+    log: LogHandle,
+    consensus: Arc<QubeNode>,
+}
+
+impl ConfigWatcher {
+    /// `path`, `required`, `data_dir` and `overrides` are as given to
+    /// `NodeConfig::load` for `current`.
+    pub fn new(
+        path: PathBuf,
+        required: bool,
+        data_dir: PathBuf,
+        overrides: Vec<(&'static str, Value)>,
+        current: NodeConfig,
+        log: LogHandle,
+        consensus: Arc<QubeNode>,
+    ) -> Self {
+        let chain_id = consensus.chain_id;
+        Self { path, required, data_dir, overrides, current, chain_id, log, consensus }
+    }
+
+    /// Reloads on every SIGHUP or file change; never returns.
+    pub async fn run(mut self) {
+        let mut hangup = Hangup::new();
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        let mut last_modified = modified(&self.path);
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => tracing::info!("received SIGHUP; reloading config"),
+                _ = poll.tick() => {
+                    if modified(&self.path) == last_modified {
+                        continue;
+                    }
+                    tracing::info!(path = %self.path.display(), "config file changed; reloading");
+                }
+            }
+            last_modified = modified(&self.path);
+            self.reload();
+        }
+    }
+
+    fn reload(&mut self) {
+        let config = match NodeConfig::load(&self.path, self.required, &self.data_dir, &self.overrides) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!(error = %e, "config reload failed; keeping the current settings");
+                return;
+            }
+        };
+        match ChainSpec::load(&config.consensus.chain_spec) {
+            Ok(spec) if spec.chain_id != self.chain_id => tracing::error!(
+                running = self.chain_id,
+                configured = spec.chain_id,
+                "the chain id cannot change at runtime; restart the node on the new chain"
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = %e, "cannot check the chain spec on reload"),
+        }
+
+        let (reloadable, fixed): (Vec<_>, Vec<_>) =
+            changed_settings(&self.current, &config).into_iter().partition(|key| RELOADABLE.contains(&key.as_str()));
+        if !fixed.is_empty() {
+            tracing::error!(settings = %fixed.join(", "), "these settings only take effect after a restart; ignoring them");
+        }
+        for key in reloadable {
+            match key.as_str() {
+                "node.log_level" => match self.log.set_filter(EnvFilter::new(&config.node.log_level)) {
+                    Ok(()) => self.current.node.log_level = config.node.log_level.clone(),
+                    Err(e) => {
+                        tracing::error!(error = %e, "cannot change the log filter");
+                        continue;
+                    }
+                },
+                "resolver.endpoints" => {
+                    self.consensus.zkurl_resolver.set_fallback_endpoints(config.resolver.endpoints.clone());
+                    self.current.resolver.endpoints = config.resolver.endpoints.clone();
+                }
+                // The output is rebuilt once for all of them
+                _ if key.starts_with("log.") && self.current.log == config.log => continue,
+                _ if key.starts_with("log.") => match self.log.set_output(&config.log) {
+                    Ok(()) => self.current.log = config.log.clone(),
+                    Err(e) => {
+                        tracing::error!(error = %format!("{:#}", e), "cannot change the log output");
+                        continue;
+                    }
+                },
+                _ => unreachable!("{} is listed as reloadable", key),
+            }
+            tracing::info!(setting = %key, "applied");
+        }
+    }
+}
+
+/// Dotted keys whose values differ between `old` and `new`.
+fn changed_settings(old: &NodeConfig, new: &NodeConfig) -> Vec<String> {
+    let table = |config: &NodeConfig| match Value::try_from(config) {
+        Ok(Value::Table(table)) => table,
+        _ => unreachable!("NodeConfig serializes to a table"),
+    };
+    let (old, new) = (table(old), table(new));
+    let mut changed = Vec::new();
+    for (section, settings) in &new {
+        let Some(settings) = settings.as_table() else {
+            continue;
+        };
+        for (name, value) in settings {
+            if old.get(section).and_then(|old| old.get(name)) != Some(value) {
+                changed.push(format!("{}.{}", section, name));
+            }
+        }
+    }
+    changed
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// SIGHUP, where the platform has it.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let signal = signal(SignalKind::hangup())
+                .map_err(|e| tracing::warn!(error = %e, "cannot listen for SIGHUP; watching the config file only"))
+                .ok();
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::{self, LogFormat};
+    use consensus::genesis::GenesisValidator;
+
+    #[test]
+    fn reports_changed_settings_by_key() {
+        let old = NodeConfig::default();
+        let mut new = old.clone();
+        new.node.log_level = "debug".to_string();
+        new.consensus.stake += 1;
+        assert_eq!(changed_settings(&old, &new), ["consensus.stake", "node.log_level"]);
+        assert!(changed_settings(&old, &old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn applies_reloadable_settings_and_ignores_the_rest() {
+        let dir = std::env::temp_dir().join(format!("cubiq-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let validator = GenesisValidator { node_id: "v1".to_string(), public_key: "11".repeat(32), stake: 10, registration: None };
+        ChainSpec::dev(7, validator, 0).save(&dir.join("genesis.json")).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "").unwrap();
+        let current = NodeConfig::load(&path, true, &dir, &[]).unwrap();
+
+        let (_subscriber, log) = logging::subscriber(EnvFilter::new("info"), &current.log).unwrap();
+        let consensus = Arc::new(QubeNode::new("v1".to_string(), 7, 10, current.resolver.endpoints.clone()).await);
+        let mut watcher = ConfigWatcher::new(path.clone(), true, dir.clone(), vec![], current, log, Arc::clone(&consensus));
+
+        let config = "[node]\nlog_level = \"debug\"\n[resolver]\nendpoints = [\"https://proofs.example\"]\n[consensus]\nstake = 99\n";
+        let logging = "[log]\nformat = \"json\"\nfile = \"logs/cubiq.log\"\nkeep_files = 2\n";
+        std::fs::write(&path, format!("{}{}", config, logging)).unwrap();
+        watcher.reload();
+
+        assert_eq!(consensus.zkurl_resolver.fallback_endpoints(), ["https://proofs.example"]);
+        assert_eq!(watcher.current.node.log_level, "debug");
+        assert_eq!(watcher.current.consensus.stake, NodeConfig::default().consensus.stake);
+        assert_eq!((watcher.current.log.format, watcher.current.log.keep_files), (LogFormat::Json, 2));
+        assert!(dir.join("logs/cubiq.log").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+//! REST gateway for integrations that would rather not frame JSON-RPC,
+//! served next to JSON-RPC on `rpc.http_addr` when `rpc.rest` is set:
+//!
+//! | route                  | JSON-RPC method          | success                     |
+//! |------------------------|--------------------------|-----------------------------|
+//! | `GET /blocks/{height}` | `chain_getBlockByHeight` | 200 with the block          |
+//! | `GET /tx/{hash}`       | `tx_getReceipt`          | 200 with the receipt        |
+//! | `POST /tx`             | `tx_submit`              | 202 with `{hash}`           |
+//! | `GET /openapi.json`    |                          | 200 with the OpenAPI schema |
+//!
+//! Each route runs its JSON-RPC method, so both answer alike and share
+//! rate limits. A null result is a 404. Errors carry `{error}` and a
+//! status derived from the JSON-RPC error code.
+
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use consensus::receipts::Receipt;
+use consensus::{BlockProposal, Transaction};
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+
+use crate::access::{Caller, LIMIT_EXCEEDED, UNAUTHORIZED};
+use crate::rpc_server::{self, RpcError, RpcState, INVALID_PARAMS, INVALID_REQUEST, SERVER_ERROR};
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct Submitted {
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
+pub fn routes() -> Router<RpcState> {
+    Router::new()
+        .route("/blocks/:height", get(block))
+        .route("/tx/:hash", get(receipt))
+        .route("/tx", post(submit))
+        .route("/openapi.json", get(|| async { Json(openapi()) }))
+}
+
+async fn block(
+    State(state): State<RpcState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(height): Path<u64>,
+) -> Response {
+    match answer(&state, state.policy.caller(addr, &headers), "chain_getBlockByHeight", json!(height)).await {
+        Ok(block) => Json(block).into_response(),
+        Err(failure) => failure,
+    }
+}
+
+async fn receipt(
+    State(state): State<RpcState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Path(hash): Path<String>,
+) -> Response {
+    match answer(&state, state.policy.caller(addr, &headers), "tx_getReceipt", json!(hash)).await {
+        Ok(receipt) => Json(receipt).into_response(),
+        Err(failure) => failure,
+    }
+}
+
+async fn submit(
+    State(state): State<RpcState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let tx: Value = match serde_json::from_slice(&body) {
+        Ok(tx) => tx,
+        Err(e) => return failure(StatusCode::BAD_REQUEST, format!("body is not JSON: {}", e)),
+    };
+    match answer(&state, state.policy.caller(addr, &headers), "tx_submit", tx).await {
+        Ok(Value::String(hash)) => (StatusCode::ACCEPTED, Json(Submitted { hash })).into_response(),
+        Ok(other) => failure(StatusCode::INTERNAL_SERVER_ERROR, format!("unexpected result {}", other)),
+        Err(failure) => failure,
+    }
+}
+
+/// Runs `method` with `param` as the caller would over JSON-RPC.
+async fn answer(state: &RpcState, caller: Caller, method: &str, param: Value) -> Result<Value, Response> {
+    let rejected = |e: RpcError| failure(status(e.code), e.message);
+    state.policy.permit(&caller, method).map_err(rejected)?;
+    match rpc_server::call(&state.backend, method, &[param]).await {
+        Ok(Value::Null) => Err(failure(StatusCode::NOT_FOUND, "not found")),
+        Ok(result) => Ok(result),
+        Err(e) => Err(rejected(e)),
+    }
+}
+
+fn status(code: i64) -> StatusCode {
+    match code {
+        INVALID_PARAMS | INVALID_REQUEST => StatusCode::BAD_REQUEST,
+        UNAUTHORIZED => StatusCode::UNAUTHORIZED,
+        LIMIT_EXCEEDED => StatusCode::TOO_MANY_REQUESTS,
+        // The node understood the request and refused it
+        SERVER_ERROR => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn failure(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, Json(ErrorBody { error: message.into() })).into_response()
+}
+
+/// OpenAPI 3 description of the routes, with schemas generated from the
+/// types they return.
+pub fn openapi() -> Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let block = generator.subschema_for::<BlockProposal>();
+    let receipt = generator.subschema_for::<Receipt>();
+    let tx = generator.subschema_for::<Transaction>();
+    let submitted = generator.subschema_for::<Submitted>();
+    let error = generator.subschema_for::<ErrorBody>();
+    let content = |schema: &schemars::schema::Schema| json!({ "application/json": { "schema": schema } });
+    let errors = |codes: &[&str]| {
+        codes
+            .iter()
+            .map(|code| (code.to_string(), json!({ "description": "error", "content": content(&error) })))
+            .collect::<serde_json::Map<_, _>>()
+    };
+    let with_errors = |ok: (&str, Value), codes: &[&str]| {
+        let mut responses = errors(codes);
+        responses.insert(ok.0.to_string(), ok.1);
+        Value::Object(responses)
+    };
+    let path_param = |name: &str, schema: Value| json!({ "name": name, "in": "path", "required": true, "schema": schema });
+    json!({
+        "openapi": "3.0.3",
+        "info": { "title": "Cubiq node REST gateway", "version": env!("CARGO_PKG_VERSION") },
+        "paths": {
+            "/blocks/{height}": {
+                "get": {
+                    "summary": "Finalized block at a height the node still holds",
+                    "parameters": [path_param("height", json!({ "type": "integer", "format": "uint64", "minimum": 1 }))],
+                    "responses": with_errors(("200", json!({ "description": "the block", "content": content(&block) })), &["400", "404", "429"]),
+                }
+            },
+            "/tx/{hash}": {
+                "get": {
+                    "summary": "Receipt of a transaction",
+                    "parameters": [path_param("hash", json!({ "type": "string" }))],
+                    "responses": with_errors(("200", json!({ "description": "the receipt", "content": content(&receipt) })), &["404", "429"]),
+                }
+            },
+            "/tx": {
+                "post": {
+                    "summary": "Submit a signed transaction",
+                    "requestBody": { "required": true, "content": content(&tx) },
+                    "responses": with_errors(
+                        ("202", json!({ "description": "accepted into the pending pool", "content": content(&submitted) })),
+                        &["400", "422", "429"],
+                    ),
+                }
+            },
+        },
+        "components": { "schemas": generator.take_definitions() },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::Policy;
+    use crate::admin::Admin;
+    use crate::logging::LogHandle;
+    use crate::role::NodeRole;
+    use crate::rpc_server::Backend;
+    use crate::status::NodeInfo;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use consensus::QubeNode;
+    use ed25519_dalek::SigningKey;
+    use networking::{P2PNetworking, PeerCount};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    #[test]
+    fn schema_covers_every_route_and_type() {
+        let schema = openapi();
+        for path in ["/blocks/{height}", "/tx/{hash}", "/tx"] {
+            assert!(schema["paths"][path].is_object(), "{} is missing", path);
+        }
+        for name in ["BlockProposal", "Receipt", "Transaction", "ZkURL", "TxStatus", "ErrorBody"] {
+            assert!(schema["components"]["schemas"][name].is_object(), "{} is missing", name);
+        }
+        let receipt = &schema["paths"]["/tx/{hash}"]["get"]["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(receipt["$ref"], "#/components/schemas/Receipt");
+    }
+
+    #[tokio::test]
+    async fn serves_resources_over_the_rpc_handlers() {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        node.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        let log = LogHandle::detached();
+        let admin = Admin::new(P2PNetworking::new().await.unwrap().peer_control(), log, std::path::Path::new("unused"));
+        let info = NodeInfo::new(NodeRole::Full, PeerCount::default(), false);
+        let backend = Arc::new(Backend { consensus: Arc::new(node), info, admin, metrics: None });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let policy = Arc::new(Policy::new(None, vec![], 0, 0));
+        let server = tokio::spawn(rpc_server::serve(listener, backend, policy, true, async {
+            let _ = stopped.await;
+        }));
+
+        let tx = Transaction {
+            chain_id: 7,
+            to: BOB.to_string(),
+            value: 5,
+            gas_limit: 21_000,
+            max_fee_per_gas: 1,
+            ..Transaction::default()
+        }
+        .sign(&signer());
+        let client = reqwest::Client::new();
+        let accepted = client.post(format!("{}/tx", url)).json(&tx).send().await.unwrap();
+        assert_eq!(accepted.status(), reqwest::StatusCode::ACCEPTED);
+        assert_eq!(accepted.json::<Submitted>().await.unwrap().hash, tx.hash);
+        let again = client.post(format!("{}/tx", url)).json(&tx).send().await.unwrap();
+        assert_eq!(again.status(), reqwest::StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(again.json::<ErrorBody>().await.unwrap().error.contains("already pending"));
+        let malformed = client.post(format!("{}/tx", url)).json(&json!({ "to": BOB })).send().await.unwrap();
+        assert_eq!(malformed.status(), reqwest::StatusCode::BAD_REQUEST);
+
+        let receipt: Value = client.get(format!("{}/tx/{}", url, tx.hash)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(receipt["status"], "pending");
+        let missing = client.get(format!("{}/blocks/1", url)).send().await.unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Node roles and the subsystems each one runs.
+//!
+//! | role      | gossip topics            | verifies blocks | votes | history | verifier budget |
+//! |-----------|--------------------------|-----------------|-------|---------|-----------------|
+//! | validator | all                      | yes             | yes   | pruned  | full node       |
+//! | full      | all                      | yes             | no    | pruned  | full node       |
+//! | light     | finalization             | no              | no    | pruned  | mobile          |
+//! | archive   | all                      | yes             | no    | full    | full node       |
+//!
+//! Light nodes check the finality certificates validators gossip instead
+//! of verifying blocks; see [`crate::finality`].
+
+use clap::ValueEnum;
+use networking::{ALL_TOPICS, FINALIZATION_TOPIC};
+use prover::VerifierConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    /// Verifies proposals and signs votes with the validator key
+    #[default]
+    Validator,
+    /// Verifies proposals and serves data without voting
+    Full,
+    /// Follows finalized blocks only
+    Light,
+    /// A full node that never prunes history
+    Archive,
+}
+
+impl NodeRole {
+    /// Gossip topics the node subscribes to.
+    pub fn topics(self) -> Vec<&'static str> {
+        match self {
+            NodeRole::Light => vec![FINALIZATION_TOPIC],
+            NodeRole::Validator | NodeRole::Full | NodeRole::Archive => ALL_TOPICS.to_vec(),
+        }
+    }
+
+    /// Whether the node fetches and verifies the proof of every proposal.
+    pub fn verifies_blocks(self) -> bool {
+        self != NodeRole::Light
+    }
+
+    pub fn votes(self) -> bool {
+        self == NodeRole::Validator
+    }
+
+    /// What proofs are verified with: a phone's budget on light nodes,
+    /// which may well run on one, and a server's on the rest. Every role
+    /// checks the same queries and grinding.
+    pub fn verifier_config(self) -> VerifierConfig {
+        match self {
+            NodeRole::Light => VerifierConfig::mobile_optimized(),
+            NodeRole::Validator | NodeRole::Full | NodeRole::Archive => VerifierConfig::full_node(),
+        }
+    }
+
+    /// Whether blocks older than `storage.retain_blocks`, and account
+    /// states `storage.state_pruning` would drop, are kept.
+    pub fn keeps_history(self) -> bool {
+        self == NodeRole::Archive
+    }
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NodeRole::Validator => "validator",
+            NodeRole::Full => "full",
+            NodeRole::Light => "light",
+            NodeRole::Archive => "archive",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_validators_vote_and_only_light_nodes_skip_verification() {
+        let roles = [NodeRole::Validator, NodeRole::Full, NodeRole::Light, NodeRole::Archive];
+        let voting: Vec<_> = roles.iter().filter(|role| role.votes()).collect();
+        assert_eq!(voting, [&NodeRole::Validator]);
+        let following: Vec<_> = roles.iter().filter(|role| !role.verifies_blocks()).collect();
+        assert_eq!(following, [&NodeRole::Light]);
+        assert_eq!(NodeRole::Light.topics(), [FINALIZATION_TOPIC]);
+        assert_eq!(NodeRole::Light.verifier_config(), VerifierConfig::mobile_optimized());
+        assert_eq!(NodeRole::Full.verifier_config(), VerifierConfig::full_node());
+    }
+
+    #[test]
+    fn names_match_config_and_flag_spelling() {
+        for role in [NodeRole::Validator, NodeRole::Full, NodeRole::Light, NodeRole::Archive] {
+            let name = role.to_string();
+            assert_eq!(NodeRole::from_str(&name, false), Ok(role));
+            assert_eq!(toml::Value::try_from(role).unwrap().as_str(), Some(name.as_str()));
+        }
+    }
+}
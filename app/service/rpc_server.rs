@@ -0,0 +1,671 @@
+//! JSON-RPC 2.0 server for wallets and explorers.
+//!
+//! Requests are POSTed to `/` on `rpc.http_addr`, singly or in batches of
+//! up to `MAX_BATCH`, with positional params:
+//!
+//! | method                           | params               | result                                 |
+//! |----------------------------------|----------------------|----------------------------------------|
+//! | `chain_getBlock`                 | `[block_hash]`       | recently verified block, or null       |
+//! | `chain_getBlockByHeight`         | `[height]`           | finalized block, or null if pruned     |
+//! | `chain_getFinalizedHead`         | `[]`                 | `{hash, height}`, or null              |
+//! | `chain_getFinalityCertificate`   | `[height]`           | finalized block's certificate, or null |
+//! | `state_getBalance`               | `[account]`          | balance                                |
+//! | `state_getNonce`                 | `[account]`          | next nonce, after pending transactions |
+//! | `state_getProof`                 | `[account, height?]` | account with its state proof, or null  |
+//! | `tx_submit`                      | `[tx]`               | transaction hash                       |
+//! | `tx_simulate`                    | `[tx]`               | gas, state diffs and events, unapplied |
+//! | `tx_getReceipt`                  | `[tx_hash]`          | receipt with status and gas, or null   |
+//! | `tx_getInclusionProof`           | `[tx_hash]`          | inclusion proof in its block, or null  |
+//! | `tx_getPoolCounts`               | `[account]`          | `{pending, queued}` in the mempool     |
+//! | `logs_get`                       | `[filter]`           | matching logs, oldest first            |
+//! | `fee_estimate`                   | `[]`                 | base fee and suggested fees            |
+//! | `node_status`                    | `[]`                 | sync state, heights, peers, mempool    |
+//! | `validator_set`                  | `[]`                 | `{validators, total_stake, threshold}` |
+//! | `consensus_health`               | `[]`                 | finality latency, missed votes, rounds |
+//! | `proof_getByBlock`               | `[block_hash]`       | `{zkurl, bundle}`, or null             |
+//! | `index_getTransactionsByAddress` | `[address, page?]`   | page of transactions sent or received  |
+//! | `index_getBlocksByProposer`      | `[proposer, page?]`  | page of finalized blocks               |
+//! | `index_getVotesByValidator`      | `[validator, page?]` | page of votes                          |
+//! | `bridge_getClient`               | `[client_id]`        | bridge client's state, or null         |
+//! | `bridge_getChannel`              | `[channel_id]`       | bridge channel, or null                |
+//! | `bridge_getProof`                | `[path, height?]`    | bridge record with its state proof     |
+//!
+//! `state_getProof` answers `{height, block_hash, state_root, account,
+//! proof}`, checked with `StateProof::verify`. Without a height it proves
+//! against the head; with one, against that finalized height while
+//! `storage.state_pruning` still keeps its state. `tx_getInclusionProof`
+//! answers `{block_hash, block_height, transactions_root, proof}`, checked
+//! with `InclusionProof::verify`; a block header carries the same
+//! `transactions_root`. `fee_estimate` answers the next block's
+//! `base_fee` with a `max_priority_fee_per_gas` from the tips recent
+//! blocks paid and a `max_fee_per_gas` to go with it; see `fees`.
+//! `tx_simulate` takes a transaction with any of its fields, needing no
+//! hash or signature, and answers what it would do in the next block
+//! without applying it; see `execution::simulate`. `logs_get` takes a
+//! `{from_height, to_height, block_hash, kinds, attributes}` filter, all
+//! optional, and answers the events of recently verified blocks that
+//! match; see `logs`. `validator_set` also answers `pending_rotations`,
+//! the key rotations yet to take effect; see `consensus::rotation`.
+//! `chain_getFinalityCertificate` answers `{checkpoint, votes, proof}`:
+//! the finalized block's roots, the votes it has had and its proof
+//! bundle's `proof`, for light clients to check with
+//! `cubiq_light::certificate::FinalityCertificate::verify`.
+//! `proof_getByBlock` and `chain_getFinalityCertificate` answer only from
+//! proofs the node already holds, having verified them, and never fetch
+//! one for a caller: `bundle` is then null, and the certificate is null.
+//! `consensus_health` answers finality latency and the share of it spent
+//! fetching proofs, each validator's missed votes and the rate of round
+//! changes, over recent blocks; see `consensus::health`.
+//! `bridge_getProof` is for relayers: it answers `{height, block_hash,
+//! state_root, address, record, proof}` for the bridge record at a path,
+//! such as a packet's commitment, proved as `state_getProof` proves the
+//! account at `address`; see `consensus::bridge`. Relayers find packets
+//! to relay in `logs_get`'s `bridge_send_packet` and `bridge_write_ack`
+//! events.
+//!
+//! An `account` or `address` param is an address (see
+//! `consensus::address`), or on EVM chains an EVM account; one that fails
+//! its checksum is refused as an invalid param.
+//!
+//! Index pages list newest entries first. `page` is `{cursor, limit}`, both
+//! optional; pass a result's `next` as the cursor to fetch older entries.
+//!
+//! `GET /health` and `GET /ready` on the same address serve load balancers;
+//! see `status`. With `rpc.rest` set, `rest` serves some methods as plain
+//! HTTP resources there too.
+//!
+//! `eth_*`, `net_*` and `web3_*` methods are served by the `eth`
+//! compatibility layer and `admin_*` methods by `admin`. `admin_*`
+//! methods are unsafe; see `access` for who may call them.
+//! While the node is short of disk, memory or file descriptors, calls
+//! other than `admin_*` fail with `UNAVAILABLE`; see `watchdog`.
+
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use consensus::address;
+use consensus::bridge;
+use consensus::index::DEFAULT_PAGE;
+use consensus::logs::LogFilter;
+use consensus::merkle;
+use consensus::state::Hash;
+use consensus::{ConsensusState, QubeNode, Transaction};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::net::TcpListener;
+
+use crate::access::{Caller, Policy};
+use crate::admin::{self, Admin};
+use crate::eth;
+use crate::finality;
+use crate::metrics::RpcMetrics;
+use crate::rest;
+use crate::status::{self, NodeInfo};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The request was well formed but the node could not carry it out.
+pub const SERVER_ERROR: i64 = -32000;
+/// The node is short of a resource and refuses calls until it recovers.
+pub const UNAVAILABLE: i64 = -32002;
+
+/// Requests in one batch, which is served as a single HTTP request.
+pub const MAX_BATCH: usize = 100;
+
+#[derive(Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+pub fn error(code: i64, message: impl Into<String>) -> RpcError {
+    RpcError { code, message: message.into() }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PageParams {
+    cursor: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// What the API servers answer from.
+pub struct Backend {
+    pub consensus: Arc<QubeNode>,
+    pub info: NodeInfo,
+    pub admin: Admin,
+    pub metrics: Option<Arc<RpcMetrics>>,
+}
+
+#[derive(Clone)]
+pub struct RpcState {
+    pub backend: Arc<Backend>,
+    pub policy: Arc<Policy>,
+}
+
+/// Serves requests on `listener` until `shutdown` completes.
+pub async fn serve(
+    listener: TcpListener,
+    backend: Arc<Backend>,
+    policy: Arc<Policy>,
+    rest: bool,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let cors = policy.cors();
+    let mut app = Router::new().route("/", post(handle_http)).route("/health", get(health)).route("/ready", get(ready));
+    if rest {
+        app = app.merge(rest::routes());
+    }
+    let app = app
+        .layer(cors)
+        .with_state(RpcState { backend, policy });
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn ready(State(RpcState { backend, .. }): State<RpcState>) -> Response {
+    let status = status::status(&backend.consensus, &backend.info).await;
+    let code = if status.is_ready() { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(status)).into_response()
+}
+
+async fn handle_http(
+    State(RpcState { backend, policy }): State<RpcState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let caller = policy.caller(addr, &headers);
+    let request: Value = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return Json(response(Value::Null, Err(error(PARSE_ERROR, e.to_string())))).into_response(),
+    };
+    let reply = match request {
+        Value::Array(batch) if batch.is_empty() => Some(response(Value::Null, Err(error(INVALID_REQUEST, "empty batch")))),
+        Value::Array(batch) if batch.len() > MAX_BATCH => {
+            let message = format!("batch of {} requests is over the limit of {}", batch.len(), MAX_BATCH);
+            Some(response(Value::Null, Err(error(INVALID_REQUEST, message))))
+        }
+        Value::Array(batch) => {
+            let mut replies = Vec::new();
+            for request in batch {
+                replies.extend(handle(&backend, &policy, &caller, request).await);
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        request => handle(&backend, &policy, &caller, request).await,
+    };
+    match reply {
+        Some(reply) => Json(reply).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Answers one request; notifications, which have no id, get no answer.
+pub async fn handle(backend: &Backend, policy: &Policy, caller: &Caller, request: Value) -> Option<Value> {
+    let started = Instant::now();
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str);
+    // Only methods the node answered are labelled by name in metrics
+    let mut dispatched = false;
+    let result = match (request.get("jsonrpc").and_then(Value::as_str), method) {
+        (Some("2.0"), Some(method)) => match request.get("params").cloned().unwrap_or(Value::Array(vec![])) {
+            Value::Array(params) => match policy.permit(caller, method) {
+                Ok(()) => {
+                    let short = backend.info.pressure.critical();
+                    if short.is_empty() || admin::handles(method) {
+                        dispatched = true;
+                        call(backend, method, &params).await
+                    } else {
+                        Err(error(UNAVAILABLE, format!("node is short of resources: {}", short.join("; "))))
+                    }
+                }
+                Err(e) => Err(e),
+            },
+            _ => Err(error(INVALID_PARAMS, "params must be an array")),
+        },
+        _ => Err(error(INVALID_REQUEST, "expected a JSON-RPC 2.0 request")),
+    };
+    if let Err(e) = &result {
+        tracing::debug!(method = method.unwrap_or_default(), code = e.code, error = %e.message, "rpc call failed");
+    }
+    if let Some(metrics) = &backend.metrics {
+        let known = dispatched && !matches!(&result, Err(e) if e.code == METHOD_NOT_FOUND);
+        metrics.observe(method.filter(|_| known), result.is_ok(), started.elapsed());
+    }
+    id.map(|id| response(id, result))
+}
+
+pub async fn call(backend: &Backend, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+    let consensus = &*backend.consensus;
+    match method {
+        "node_status" => to_value(status::status(consensus, &backend.info).await),
+        "consensus_health" => to_value(consensus.health()),
+        "chain_getBlock" => {
+            let hash: String = param(params, 0, "block_hash")?;
+            to_value(consensus.block(&hash).await)
+        }
+        "chain_getBlockByHeight" => {
+            let height: u64 = param(params, 0, "height")?;
+            let hash = {
+                let state = consensus.consensus_state.read().await;
+                height.checked_sub(1).and_then(|index| state.finalized_blocks.get(index as usize).cloned())
+            };
+            match hash {
+                Some(hash) => to_value(consensus.block(&hash).await),
+                None => Ok(Value::Null),
+            }
+        }
+        "chain_getFinalityCertificate" => {
+            let height: u64 = param(params, 0, "height")?;
+            to_value(finality::certificate(consensus, height).await)
+        }
+        "chain_getFinalizedHead" => {
+            let state = consensus.consensus_state.read().await;
+            Ok(state.finalized_blocks.last().map_or(Value::Null, |hash| {
+                json!({ "hash": hash, "height": state.finalized_blocks.len() })
+            }))
+        }
+        "state_getBalance" => {
+            let account = account_param(params, 0, "account")?;
+            let state = consensus.consensus_state.read().await;
+            Ok(json!(state.accounts.get(&account).balance))
+        }
+        "state_getNonce" => {
+            let account = account_param(params, 0, "account")?;
+            let state = consensus.consensus_state.read().await;
+            Ok(json!(state.mempool.next_nonce(&account, &state.accounts)))
+        }
+        "state_getProof" => {
+            let account = account_param(params, 0, "account")?;
+            let height: Option<u64> = if params.len() > 1 { param(params, 1, "height")? } else { None };
+            let state = consensus.consensus_state.read().await;
+            let Some((height, block_hash, root)) = proof_root(&state, height) else {
+                return Ok(Value::Null);
+            };
+            let Some(proof) = state.accounts.prove_at(&root, &account) else {
+                return Ok(Value::Null);
+            };
+            Ok(json!({
+                "height": height,
+                "block_hash": block_hash,
+                "state_root": root,
+                "account": state.accounts.get_at(&root, &account).flatten(),
+                "proof": proof,
+            }))
+        }
+        "bridge_getClient" | "bridge_getChannel" => {
+            let id: String = param(params, 0, "id")?;
+            let path = match method {
+                "bridge_getClient" => bridge::client_path(&id),
+                _ => bridge::channel_path(&id),
+            };
+            to_value(consensus.consensus_state.read().await.accounts.get(&bridge::path_address(&path)).bridge)
+        }
+        "bridge_getProof" => {
+            let path: String = param(params, 0, "path")?;
+            let height: Option<u64> = if params.len() > 1 { param(params, 1, "height")? } else { None };
+            let address = bridge::path_address(&path);
+            let state = consensus.consensus_state.read().await;
+            let Some((height, block_hash, root)) = proof_root(&state, height) else {
+                return Ok(Value::Null);
+            };
+            let Some(proof) = state.accounts.prove_at(&root, &address) else {
+                return Ok(Value::Null);
+            };
+            let account = state.accounts.get_at(&root, &address).flatten();
+            Ok(json!({
+                "height": height,
+                "block_hash": block_hash,
+                "state_root": root,
+                "address": address,
+                "record": account.and_then(|account| account.bridge),
+                "proof": proof,
+            }))
+        }
+        "tx_submit" => {
+            let tx: Transaction = param(params, 0, "tx")?;
+            consensus.submit_transaction(tx).await.map(Value::String).map_err(|e| error(SERVER_ERROR, e))
+        }
+        "tx_simulate" => {
+            let Some(fields) = params.first().and_then(Value::as_object) else {
+                return Err(error(INVALID_PARAMS, "param tx must be an object"));
+            };
+            // Fields left out take their defaults, the chain this node's
+            let mut draft = json!(Transaction { chain_id: consensus.chain_id, ..Transaction::default() });
+            draft.as_object_mut().expect("transactions serialize as objects").extend(fields.clone());
+            let tx: Transaction = serde_json::from_value(draft).map_err(|e| error(INVALID_PARAMS, format!("param tx: {}", e)))?;
+            to_value(consensus.simulate(&tx).await.map_err(|e| error(SERVER_ERROR, e))?)
+        }
+        "tx_getReceipt" => {
+            let hash: String = param(params, 0, "tx_hash")?;
+            to_value(consensus.consensus_state.read().await.receipts.get(&hash))
+        }
+        "tx_getPoolCounts" => {
+            let account = account_param(params, 0, "account")?;
+            to_value(consensus.consensus_state.read().await.mempool.counts(&account))
+        }
+        "fee_estimate" => to_value(consensus.fee_estimate().await),
+        "logs_get" => {
+            let filter: LogFilter = param(params, 0, "filter")?;
+            to_value(consensus.consensus_state.read().await.logs.query(&filter).map_err(|e| error(SERVER_ERROR, e))?)
+        }
+        "tx_getInclusionProof" => {
+            let hash: String = param(params, 0, "tx_hash")?;
+            let receipt = consensus.consensus_state.read().await.receipts.get(&hash).cloned();
+            let Some((block_hash, block_height)) = receipt.and_then(|receipt| receipt.block_hash.zip(receipt.block_height)) else {
+                return Ok(Value::Null);
+            };
+            // The body may have been pruned since
+            let Some(block) = consensus.block(&block_hash).await else {
+                return Ok(Value::Null);
+            };
+            let index = block.transactions.iter().position(|tx| tx.hash == hash);
+            let Some(proof) = index.and_then(|index| merkle::prove(&block.transactions, index)) else {
+                return Ok(Value::Null);
+            };
+            Ok(json!({
+                "block_hash": block_hash,
+                "block_height": block_height,
+                "transactions_root": merkle::transactions_root(&block.transactions),
+                "proof": proof,
+            }))
+        }
+        "validator_set" => {
+            let set = consensus.validator_set.read().await;
+            let mut validators: Vec<_> = set.validators.values().collect();
+            validators.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+            Ok(json!({
+                "validators": validators,
+                "total_stake": set.total_stake,
+                "supermajority_threshold": set.supermajority_threshold,
+                "pending_rotations": set.pending_rotations.values().collect::<Vec<_>>(),
+            }))
+        }
+        "proof_getByBlock" => {
+            let hash: String = param(params, 0, "block_hash")?;
+            let Some(block) = consensus.block(&hash).await else {
+                return Ok(Value::Null);
+            };
+            let bundle = consensus.zkurl_resolver.held_proof(&block.zkurl);
+            Ok(json!({ "zkurl": block.zkurl.to_string(), "bundle": bundle }))
+        }
+        "index_getTransactionsByAddress" | "index_getBlocksByProposer" | "index_getVotesByValidator" => {
+            let key: String = match method {
+                "index_getTransactionsByAddress" => account_param(params, 0, "address")?,
+                _ => param(params, 0, "key")?,
+            };
+            let page: PageParams = if params.len() > 1 { param(params, 1, "page")? } else { PageParams::default() };
+            let (cursor, limit) = (page.cursor, page.limit.unwrap_or(DEFAULT_PAGE));
+            let index = &consensus.consensus_state.read().await.index;
+            match method {
+                "index_getTransactionsByAddress" => to_value(index.transactions(&key, cursor, limit)),
+                "index_getBlocksByProposer" => to_value(index.blocks_by_proposer(&key, cursor, limit)),
+                _ => to_value(index.votes_by_validator(&key, cursor, limit)),
+            }
+        }
+        method if eth::handles(method) => eth::call(consensus, method, params).await,
+        method if admin::handles(method) => admin::call(backend, method, params).await,
+        _ => Err(error(METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+/// The height, block hash and state root a proof is against: `height`'s
+/// while its state is kept, or without one the head's.
+fn proof_root(state: &ConsensusState, height: Option<u64>) -> Option<(u64, Option<String>, Hash)> {
+    match height {
+        Some(height) => {
+            let root = state.pruner.root_at(height)?;
+            Some((height, state.finalized_blocks.get(height as usize - 1).cloned(), root))
+        }
+        None => {
+            let head = state.recent_blocks.back().map(|block| block.block_hash.clone());
+            Some((state.current_height, head, state.accounts.root()))
+        }
+    }
+}
+
+pub fn param<T: DeserializeOwned>(params: &[Value], index: usize, name: &str) -> Result<T, RpcError> {
+    let value = params.get(index).ok_or_else(|| error(INVALID_PARAMS, format!("missing param {}", name)))?;
+    serde_json::from_value(value.clone()).map_err(|e| error(INVALID_PARAMS, format!("param {}: {}", name, e)))
+}
+
+/// Param `index`, which must name an account.
+fn account_param(params: &[Value], index: usize, name: &str) -> Result<String, RpcError> {
+    let account: String = param(params, index, name)?;
+    address::check_account(&account).map_err(|e| error(INVALID_PARAMS, format!("param {}: {}", name, e)))?;
+    Ok(account)
+}
+
+pub fn to_value<T: serde::Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| error(SERVER_ERROR, e.to_string()))
+}
+
+pub fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::LogHandle;
+    use crate::role::NodeRole;
+    use consensus::events::TxStatus;
+    use consensus::address::Address;
+    use consensus::execution::TxOutcome;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use consensus::merkle::InclusionProof;
+    use consensus::state::{Account, Hash, StateProof};
+    use consensus::BlockProposal;
+    use cubiq_client::Client;
+    use ed25519_dalek::SigningKey;
+    use networking::{P2PNetworking, PeerCount};
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn account() -> String {
+        Address::from_key(&signer().verifying_key()).to_string()
+    }
+
+    async fn backend() -> Arc<Backend> {
+        let node = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        node.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        let info = NodeInfo::new(NodeRole::Full, PeerCount::default(), false);
+        let log = LogHandle::detached();
+        let admin = Admin::new(P2PNetworking::new().await.unwrap().peer_control(), log, std::path::Path::new("unused"));
+        Arc::new(Backend { consensus: Arc::new(node), info, admin, metrics: None })
+    }
+
+    /// Answers as the node would a local caller, without a rate limit.
+    async fn answer(backend: &Backend, request: Value) -> Option<Value> {
+        let caller = Caller { ip: [127, 0, 0, 1].into(), token: None };
+        handle(backend, &Policy::new(None, vec![], 0, 0), &caller, request).await
+    }
+
+    #[tokio::test]
+    async fn answers_requests_and_reports_errors_by_code() {
+        let backend = backend().await;
+        let consensus = &backend.consensus;
+        let request = |method: &str, params: Value| json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+
+        let reply = answer(&backend, request("state_getBalance", json!([account()]))).await.unwrap();
+        assert_eq!(reply["result"], 1_000_000_000);
+        let reply = answer(&backend, request("state_getNonce", json!([account()]))).await.unwrap();
+        assert_eq!(reply["result"], 0);
+        let reply = answer(&backend, request("tx_getPoolCounts", json!([account()]))).await.unwrap();
+        assert_eq!(reply["result"], json!({ "pending": 0, "queued": 0 }));
+        let reply = answer(&backend, request("fee_estimate", json!([]))).await.unwrap();
+        assert_eq!(reply["result"], json!({ "base_fee": 1, "max_priority_fee_per_gas": 1, "max_fee_per_gas": 3, "blocks": 0 }));
+        let sender = account();
+        let draft = json!({ "from": sender, "to": BOB, "value": 5, "gas_limit": 30_000 });
+        let simulation = answer(&backend, request("tx_simulate", json!([draft]))).await.unwrap()["result"].clone();
+        assert_eq!((simulation["gas_used"].as_u64(), &simulation["error"]), (Some(21_000), &Value::Null));
+        assert_eq!(simulation["diffs"][BOB]["after"]["balance"], 5);
+        assert_eq!(simulation["diffs"][&sender]["after"]["balance"], 1_000_000_000 - 5 - 21_000);
+        assert_eq!(consensus.consensus_state.read().await.accounts.get(BOB), Account::default());
+        let reply = answer(&backend, request("logs_get", json!([{ "kinds": ["transfer"], "from_height": 1 }]))).await.unwrap();
+        assert_eq!(reply["result"], json!([]));
+        let reply = answer(&backend, request("validator_set", json!([]))).await.unwrap();
+        assert_eq!(reply["result"]["validators"][0]["node_id"], "v1");
+        assert_eq!(reply["result"]["pending_rotations"], json!([]));
+        let health = answer(&backend, request("consensus_health", json!([]))).await.unwrap()["result"].clone();
+        assert_eq!((&health["finalized_samples"], &health["missed_votes"]), (&json!(0), &json!({})));
+        assert_eq!(answer(&backend, request("chain_getFinalizedHead", json!([]))).await.unwrap()["result"], Value::Null);
+        let certificate = answer(&backend, request("chain_getFinalityCertificate", json!([1]))).await.unwrap();
+        assert_eq!(certificate["result"], Value::Null);
+        assert_eq!(answer(&backend, request("bridge_getClient", json!(["other-0"]))).await.unwrap()["result"], Value::Null);
+        let path = bridge::commitment_path("ch-a", 1);
+        let absent = answer(&backend, request("bridge_getProof", json!([path]))).await.unwrap()["result"].clone();
+        let proof: StateProof = serde_json::from_value(absent["proof"].clone()).unwrap();
+        let root: Hash = serde_json::from_value(absent["state_root"].clone()).unwrap();
+        assert!(absent["record"].is_null() && proof.verify(&root, absent["address"].as_str().unwrap(), None));
+
+        let code = |reply: Option<Value>| reply.unwrap()["error"]["code"].as_i64();
+        assert_eq!(code(answer(&backend, request("chain_getBlocks", json!([]))).await), Some(METHOD_NOT_FOUND));
+        assert_eq!(code(answer(&backend, request("tx_submit", json!([{ "to": BOB }]))).await), Some(INVALID_PARAMS));
+        let typo = account().replacen('q', "p", 2);
+        assert_eq!(code(answer(&backend, request("state_getBalance", json!([typo]))).await), Some(INVALID_PARAMS));
+        assert_eq!(code(answer(&backend, request("tx_simulate", json!([{ "value": "lots" }]))).await), Some(INVALID_PARAMS));
+        assert_eq!(code(answer(&backend, request("tx_simulate", json!([{ "gas_limit": 1 }]))).await), Some(SERVER_ERROR));
+        assert_eq!(code(answer(&backend, request("logs_get", json!([{ "kind": "transfer" }]))).await), Some(INVALID_PARAMS));
+        assert_eq!(code(answer(&backend, json!({ "id": 2, "method": "validator_set" })).await), Some(INVALID_REQUEST));
+        assert!(answer(&backend, json!({ "jsonrpc": "2.0", "method": "validator_set" })).await.is_none());
+
+        let vote = consensus::Vote { block_hash: "0xb1".to_string(), voter_id: "v1".to_string(), stake: 10, timestamp: 3, ..consensus::Vote::default() }
+            .sign(&signer());
+        consensus.record_vote(vote).await;
+        let page = answer(&backend, request("index_getVotesByValidator", json!(["v1", { "limit": 10 }]))).await.unwrap();
+        assert_eq!((page["result"]["items"][0]["block_hash"].as_str(), &page["result"]["next"]), (Some("0xb1"), &Value::Null));
+        let bad_page = request("index_getVotesByValidator", json!(["v1", { "size": 10 }]));
+        assert_eq!(code(answer(&backend, bad_page).await), Some(INVALID_PARAMS));
+
+        let remote = Caller { ip: [10, 0, 0, 1].into(), token: None };
+        let policy = Policy::new(None, vec![], 0, 0);
+        let reply = handle(&backend, &policy, &remote, request("admin_peers", json!([]))).await;
+        assert_eq!(code(reply), Some(crate::access::UNAUTHORIZED));
+        assert!(handle(&backend, &policy, &remote, request("validator_set", json!([]))).await.unwrap()["result"].is_object());
+
+        let status = answer(&backend, request("node_status", json!([]))).await.unwrap();
+        assert_eq!((status["result"]["sync"].as_str(), &status["result"]["chain_id"]), (Some("synced"), &json!(7)));
+    }
+
+    #[tokio::test]
+    async fn serves_proofs_that_verify_against_block_roots() {
+        let backend = backend().await;
+        let consensus = &backend.consensus;
+        let request = |method: &str, params: Value| json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let owner = account();
+
+        let head = answer(&backend, request("state_getProof", json!([owner]))).await.unwrap()["result"].clone();
+        let root: Hash = serde_json::from_value(head["state_root"].clone()).unwrap();
+        let account: Account = serde_json::from_value(head["account"].clone()).unwrap();
+        let proof: StateProof = serde_json::from_value(head["proof"].clone()).unwrap();
+        assert!(proof.verify(&root, &owner, Some(&account)));
+        assert_eq!((account.balance, &head["height"]), (1_000_000_000, &json!(0)));
+        assert_eq!(answer(&backend, request("state_getProof", json!([owner, 1]))).await.unwrap()["result"], Value::Null);
+
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|value| {
+                let to = BOB.to_string();
+                Transaction { chain_id: 7, nonce: value, to, value, ..Transaction::default() }.sign(&signer())
+            })
+            .collect();
+        let block = BlockProposal {
+            block_hash: "0xb1".to_string(),
+            state_root: root.to_string(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions: transactions.clone(),
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        };
+        {
+            let mut state = consensus.consensus_state.write().await;
+            for tx in &transactions {
+                let outcome = TxOutcome { tx_hash: tx.hash.clone(), gas_used: 0, gas_price: 1, error: None, events: vec![], output: vec![] };
+                state.receipts.included(tx, &outcome, &block.block_hash, 1);
+            }
+            state.recent_blocks.push_back(block.clone());
+        }
+        let vote = consensus::Vote::new(&block, "v1", 10, 3).sign(&signer());
+        consensus.record_vote(vote).await;
+
+        let reply = answer(&backend, request("tx_getInclusionProof", json!([transactions[1].hash]))).await.unwrap()["result"].clone();
+        let proof: InclusionProof = serde_json::from_value(reply["proof"].clone()).unwrap();
+        let transactions_root: Hash = block.header().transactions_root.parse().unwrap();
+        assert!(proof.verify(&transactions_root, &transactions[1].hash));
+        assert_eq!((&reply["block_height"], proof.index), (&json!(1), 1));
+        assert_eq!(answer(&backend, request("tx_getInclusionProof", json!(["0xnone"]))).await.unwrap()["result"], Value::Null);
+
+        let finalized = answer(&backend, request("state_getProof", json!([owner, 1]))).await.unwrap()["result"].clone();
+        assert_eq!((&finalized["block_hash"], &finalized["state_root"]), (&json!("0xb1"), &json!(root)));
+    }
+
+    #[tokio::test]
+    async fn accepts_transactions_over_http() {
+        let backend = backend().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let policy = Arc::new(Policy::new(None, vec![], 1, 3));
+        let server = tokio::spawn(serve(listener, backend, policy, false, async {
+            let _ = stopped.await;
+        }));
+
+        let tx = Transaction {
+            chain_id: 7,
+            to: BOB.to_string(),
+            value: 5,
+            gas_limit: 21_000,
+            max_fee_per_gas: 1,
+            ..Transaction::default()
+        }
+        .sign(&signer());
+        let client = Client::new(url.clone()).unwrap();
+        assert_eq!(client.submit(&tx).await.unwrap(), tx.hash);
+        let resubmitted = client.submit(&tx).await.unwrap_err();
+        assert!(resubmitted.to_string().contains("already pending"));
+        assert_eq!(client.receipt(&tx.hash).await.unwrap().unwrap().status, TxStatus::Pending);
+        let limited = client.validators().await.unwrap_err();
+        assert!(limited.to_string().contains("rate limit"));
+        // Probes are not rate limited
+        let ready = reqwest::get(format!("{}/ready", url)).await.unwrap();
+        assert_eq!(ready.status(), reqwest::StatusCode::OK);
+        assert_eq!(reqwest::get(format!("{}/health", url)).await.unwrap().status(), reqwest::StatusCode::OK);
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,43 @@
+//! Signing backends for the transactions the CLI sends: a key unlocked
+//! from the keystore, or a Ledger holding the key itself.
+
+use anyhow::Result;
+use consensus::address::Address;
+use consensus::{Cosignature, Transaction};
+use ed25519_dalek::SigningKey;
+
+use crate::keys;
+
+/// Signs transactions from one account.
+pub trait Signer {
+    /// The hex-encoded ed25519 public key.
+    fn public_key(&self) -> String;
+
+    /// The account, the key's address.
+    fn address(&self) -> Address;
+
+    /// `tx` sent from the account, with `from`, `hash` and `signature` set.
+    fn sign_transaction(&mut self, tx: Transaction) -> Result<Transaction>;
+
+    /// The key's cosignature of `tx`, from a multisig it is one of the
+    /// keys of.
+    fn cosign(&mut self, tx: &Transaction) -> Result<Cosignature>;
+}
+
+impl Signer for SigningKey {
+    fn public_key(&self) -> String {
+        keys::public_key_hex(self)
+    }
+
+    fn address(&self) -> Address {
+        Address::from_key(&self.verifying_key())
+    }
+
+    fn sign_transaction(&mut self, tx: Transaction) -> Result<Transaction> {
+        Ok(tx.sign(self))
+    }
+
+    fn cosign(&mut self, tx: &Transaction) -> Result<Cosignature> {
+        Ok(tx.cosign(self))
+    }
+}
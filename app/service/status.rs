@@ -0,0 +1,140 @@
+//! Node status behind `node_status`, `/health` and `/ready`.
+//!
+//! `/health` only says the process is up and serving. `/ready` says the
+//! node is synced and fit to take traffic: it has peers, unless it runs
+//! without bootnodes, and its head is no older than `MAX_HEAD_AGE`. A node
+//! that has not seen a block yet has no head to be stale. Nor is a node
+//! the watchdog finds short of a resource ready; see `watchdog`. The
+//! status also lists how each subsystem is doing; see `supervisor`.
+
+use consensus::pruning::{Pruning, PruningStats};
+use consensus::QubeNode;
+use networking::PeerCount;
+use serde::Serialize;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::role::NodeRole;
+use crate::supervisor::{SubsystemHealth, Subsystems};
+use crate::watchdog::Pressure;
+
+/// Age of the newest verified block beyond which the node counts as behind.
+pub const MAX_HEAD_AGE: Duration = Duration::from_secs(120);
+
+/// Facts about the running node that consensus does not track.
+#[derive(Clone, Debug)]
+pub struct NodeInfo {
+    pub role: NodeRole,
+    pub peers: PeerCount,
+    /// Whether the node is meant to have peers, i.e. has bootnodes
+    pub needs_peers: bool,
+    pub started: Instant,
+    pub pressure: Pressure,
+    pub subsystems: Subsystems,
+}
+
+impl NodeInfo {
+    pub fn new(role: NodeRole, peers: PeerCount, needs_peers: bool) -> Self {
+        Self { role, peers, needs_peers, started: Instant::now(), pressure: Pressure::default(), subsystems: Subsystems::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    Synced,
+    Syncing,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatus {
+    pub version: &'static str,
+    pub role: NodeRole,
+    pub chain_id: u64,
+    pub node_id: String,
+    pub sync: SyncState,
+    /// Why the node is not synced
+    pub problems: Vec<String>,
+    pub finalized_height: u64,
+    pub finalized_head: Option<String>,
+    /// Newest verified block
+    pub head: Option<String>,
+    pub head_age_secs: Option<u64>,
+    pub peer_count: usize,
+    pub mempool_size: usize,
+    pub state_pruning: Pruning,
+    pub pruning: PruningStats,
+    pub uptime_secs: u64,
+    pub subsystems: Vec<SubsystemHealth>,
+}
+
+impl NodeStatus {
+    pub fn is_ready(&self) -> bool {
+        self.sync == SyncState::Synced
+    }
+}
+
+pub async fn status(consensus: &QubeNode, info: &NodeInfo) -> NodeStatus {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let state = consensus.consensus_state.read().await;
+    let head = state.recent_blocks.back();
+    let head_age_secs = head.map(|block| now.saturating_sub(block.timestamp));
+    let peer_count = info.peers.get();
+
+    let mut problems = Vec::new();
+    if info.needs_peers && peer_count == 0 {
+        problems.push("no connected peers".to_string());
+    }
+    if let Some(age) = head_age_secs.filter(|age| *age > MAX_HEAD_AGE.as_secs()) {
+        problems.push(format!("newest block is {}s old", age));
+    }
+    problems.extend(info.pressure.critical());
+    NodeStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        role: info.role,
+        chain_id: consensus.chain_id,
+        node_id: consensus.node_id.clone(),
+        sync: if problems.is_empty() { SyncState::Synced } else { SyncState::Syncing },
+        problems,
+        finalized_height: state.finalized_blocks.len() as u64,
+        finalized_head: state.finalized_blocks.last().cloned(),
+        head: head.map(|block| block.block_hash.clone()),
+        head_age_secs,
+        peer_count,
+        mempool_size: state.mempool.len(),
+        state_pruning: state.pruner.mode(),
+        pruning: state.pruner.stats().clone(),
+        uptime_secs: info.started.elapsed().as_secs(),
+        subsystems: info.subsystems.health(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::BlockProposal;
+
+    #[tokio::test]
+    async fn not_ready_without_peers_or_with_a_stale_head() {
+        let consensus = QubeNode::new("v1".to_string(), 7, 10, vec![]).await;
+        let standalone = status(&consensus, &NodeInfo::new(NodeRole::Full, PeerCount::default(), false)).await;
+        assert!(standalone.is_ready());
+        assert_eq!((standalone.head, standalone.mempool_size), (None, 0));
+
+        let networked = status(&consensus, &NodeInfo::new(NodeRole::Full, PeerCount::default(), true)).await;
+        assert_eq!(networked.problems, ["no connected peers"]);
+
+        consensus.consensus_state.write().await.recent_blocks.push_back(BlockProposal {
+            block_hash: "0xb1".to_string(),
+            state_root: "r".to_string(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        });
+        let stale = status(&consensus, &NodeInfo::new(NodeRole::Full, PeerCount::default(), false)).await;
+        assert_eq!((stale.sync, stale.head.as_deref()), (SyncState::Syncing, Some("0xb1")));
+        assert!(stale.problems[0].contains("old"));
+    }
+}
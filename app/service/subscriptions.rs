@@ -0,0 +1,397 @@
+//! WebSocket endpoint on `rpc.ws_addr`: every JSON-RPC method, plus
+//! subscriptions fed from the consensus event stream.
+//!
+//! `subscribe_newHeads`, `subscribe_finalized`, `subscribe_txStatus`
+//! (params `[tx_hash]`) and `subscribe_logs` (params `[filter?]`, as for
+//! `logs_get`) return a subscription id. Events then arrive as
+//! `{"jsonrpc": "2.0", "method": "subscription", "params": {"subscription": id, "result": ...}}`
+//! until `unsubscribe` (params `[id]`).
+//!
+//! A connection holds at most `MAX_SUBSCRIPTIONS` subscriptions. A client
+//! that falls `OUTBOUND_QUEUE` messages behind is disconnected rather than
+//! buffered without bound. A subscription that misses consensus events
+//! ends with an error in place of `result`, so the client knows to catch
+//! up over RPC and resubscribe.
+//!
+//! Calls are admitted by the same `access` policy as over HTTP, with the
+//! caller's token taken from the handshake; browsers must connect from an
+//! origin in `rpc.cors_origins`.
+
+use anyhow::Result;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use consensus::events::ConsensusEvent;
+use consensus::logs::LogFilter;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::task::AbortHandle;
+
+use crate::access::{Caller, Policy};
+use crate::rpc_server::{self, error, response, Backend, RpcError, INVALID_PARAMS, SERVER_ERROR};
+
+pub const MAX_SUBSCRIPTIONS: usize = 16;
+
+/// Messages queued for a client before it counts as too slow.
+const OUTBOUND_QUEUE: usize = 256;
+
+#[derive(Clone)]
+struct WsState {
+    backend: Arc<Backend>,
+    policy: Arc<Policy>,
+    closing: watch::Receiver<bool>,
+}
+
+/// Serves WebSocket clients on `listener` until `shutdown` completes, then
+/// closes their connections.
+pub async fn serve(
+    listener: TcpListener,
+    backend: Arc<Backend>,
+    policy: Arc<Policy>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let (close, closing) = watch::channel(false);
+    let app = Router::new().route("/", get(upgrade)).with_state(WsState { backend, policy, closing });
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown.await;
+            let _ = close.send(true);
+        })
+        .await?;
+    Ok(())
+}
+
+async fn upgrade(
+    State(state): State<WsState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !state.policy.allows_origin(&headers) {
+        return (StatusCode::FORBIDDEN, "origin not allowed").into_response();
+    }
+    let caller = state.policy.caller(addr, &headers);
+    ws.on_upgrade(move |socket| Connection::new(state.backend, state.policy, caller).run(socket, state.closing))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    NewHeads,
+    Finalized,
+    TxStatus(String),
+    Logs(LogFilter),
+}
+
+impl Filter {
+    /// The notification payload, when `event` is one the subscriber wants.
+    fn matches(&self, event: &ConsensusEvent) -> Option<Value> {
+        match (self, event) {
+            (Filter::NewHeads, ConsensusEvent::NewHead(header)) => serde_json::to_value(header).ok(),
+            (Filter::Finalized, ConsensusEvent::Finalized { block_hash, height }) => {
+                Some(json!({ "block_hash": block_hash, "height": height }))
+            }
+            (Filter::TxStatus(wanted), ConsensusEvent::TxStatus { hash, status, block_hash }) if wanted == hash => {
+                Some(json!({ "status": status, "block_hash": block_hash }))
+            }
+            // A block's matching logs arrive together
+            (Filter::Logs(filter), ConsensusEvent::Logs { logs }) => {
+                let matching: Vec<_> = logs.iter().filter(|log| filter.matches(log)).collect();
+                (!matching.is_empty()).then(|| json!(matching))
+            }
+            _ => None,
+        }
+    }
+}
+
+struct Connection {
+    backend: Arc<Backend>,
+    policy: Arc<Policy>,
+    caller: Caller,
+    outbound: mpsc::Sender<Message>,
+    queue: mpsc::Receiver<Message>,
+    /// Raised when the outbound queue overflows
+    overloaded: Arc<Notify>,
+    subscriptions: HashMap<u64, AbortHandle>,
+    next_id: u64,
+}
+
+impl Connection {
+    fn new(backend: Arc<Backend>, policy: Arc<Policy>, caller: Caller) -> Self {
+        let (outbound, queue) = mpsc::channel(OUTBOUND_QUEUE);
+        Self {
+            backend,
+            policy,
+            caller,
+            outbound,
+            queue,
+            overloaded: Arc::new(Notify::new()),
+            subscriptions: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    async fn run(mut self, socket: WebSocket, mut closing: watch::Receiver<bool>) {
+        let (mut sink, mut stream) = socket.split();
+        let close = loop {
+            tokio::select! {
+                message = stream.next() => match message {
+                    Some(Ok(Message::Text(text))) => {
+                        if let Some(reply) = self.request(&text).await {
+                            self.send(reply);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break None,
+                    Some(Ok(_)) => {}
+                },
+                Some(message) = self.queue.recv() => {
+                    if sink.send(message).await.is_err() {
+                        break None;
+                    }
+                }
+                _ = self.overloaded.notified() => {
+                    tracing::debug!("disconnecting a websocket client that fell behind");
+                    break Some(CloseFrame { code: close_code::AGAIN, reason: "too slow; reconnect".into() });
+                }
+                _ = closing.changed() => break Some(CloseFrame { code: close_code::AWAY, reason: "node stopping".into() }),
+            }
+        };
+        if let Some(frame) = close {
+            let _ = sink.send(Message::Close(Some(frame))).await;
+        }
+        for subscription in self.subscriptions.values() {
+            subscription.abort();
+        }
+    }
+
+    fn send(&self, message: Value) {
+        if self.outbound.try_send(Message::Text(message.to_string())).is_err() {
+            self.overloaded.notify_one();
+        }
+    }
+
+    async fn request(&mut self, text: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(e) => return Some(response(Value::Null, Err(error(rpc_server::PARSE_ERROR, e.to_string())))),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = request.get("params").and_then(Value::as_array).cloned().unwrap_or_default();
+        let method = request.get("method").and_then(Value::as_str);
+        if let Some(method) = method.filter(|method| method.starts_with("subscribe_") || *method == "unsubscribe") {
+            if let Err(e) = self.policy.permit(&self.caller, method) {
+                return Some(response(id, Err(e)));
+            }
+        }
+        let filter = match method {
+            Some("subscribe_newHeads") => Filter::NewHeads,
+            Some("subscribe_finalized") => Filter::Finalized,
+            Some("subscribe_txStatus") => match rpc_server::param(&params, 0, "tx_hash") {
+                Ok(hash) => Filter::TxStatus(hash),
+                Err(e) => return Some(response(id, Err(e))),
+            },
+            Some("subscribe_logs") => match params.is_empty() {
+                true => Filter::Logs(LogFilter::default()),
+                false => match rpc_server::param(&params, 0, "filter") {
+                    Ok(filter) => Filter::Logs(filter),
+                    Err(e) => return Some(response(id, Err(e))),
+                },
+            },
+            Some("unsubscribe") => return Some(response(id, self.unsubscribe(&params))),
+            _ => return rpc_server::handle(&self.backend, &self.policy, &self.caller, request).await,
+        };
+        match self.subscribe(filter) {
+            // The id goes out before any event the new subscription forwards
+            Ok((subscription, start)) => {
+                self.send(response(id, Ok(json!(subscription))));
+                start();
+                None
+            }
+            Err(e) => Some(response(id, Err(e))),
+        }
+    }
+
+    /// Registers a subscription; events flow once the returned closure runs.
+    fn subscribe(&mut self, filter: Filter) -> Result<(u64, impl FnOnce()), RpcError> {
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(error(SERVER_ERROR, format!("at most {} subscriptions per connection", MAX_SUBSCRIPTIONS)));
+        }
+        let subscription = self.next_id;
+        self.next_id += 1;
+        let mut events = self.backend.consensus.subscribe();
+        let outbound = self.outbound.clone();
+        let overloaded = Arc::clone(&self.overloaded);
+        let (start, started) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            if started.await.is_err() {
+                return;
+            }
+            loop {
+                let params = match events.recv().await {
+                    Ok(event) => match filter.matches(&event) {
+                        Some(result) => json!({ "subscription": subscription, "result": result }),
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        let message = format!("missed {} events; resubscribe", missed);
+                        json!({ "subscription": subscription, "error": { "code": SERVER_ERROR, "message": message } })
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                let ended = params.get("error").is_some();
+                let notification = json!({ "jsonrpc": "2.0", "method": "subscription", "params": params });
+                if outbound.try_send(Message::Text(notification.to_string())).is_err() {
+                    overloaded.notify_one();
+                    return;
+                }
+                if ended {
+                    return;
+                }
+            }
+        });
+        self.subscriptions.insert(subscription, task.abort_handle());
+        Ok((subscription, move || {
+            let _ = start.send(());
+        }))
+    }
+
+    fn unsubscribe(&mut self, params: &[Value]) -> Result<Value, RpcError> {
+        let subscription: u64 = rpc_server::param(params, 0, "subscription")?;
+        match self.subscriptions.remove(&subscription) {
+            Some(task) => {
+                task.abort();
+                Ok(Value::Bool(true))
+            }
+            None => Err(error(INVALID_PARAMS, format!("no subscription {}", subscription))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::Admin;
+    use crate::logging::LogHandle;
+    use crate::role::NodeRole;
+    use crate::status::NodeInfo;
+    use consensus::events::TxStatus;
+    use consensus::genesis::{ChainSpec, GenesisValidator};
+    use consensus::logs::Log;
+    use consensus::receipts::TxEvent;
+    use consensus::{QubeNode, Transaction};
+    use ed25519_dalek::SigningKey;
+    use networking::{P2PNetworking, PeerCount};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// The dev genesis funds the validator's key.
+    fn signer() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    async fn call(client: &mut Client, method: &str, params: Value) {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        client.send(WsMessage::Text(request.to_string())).await.unwrap();
+    }
+
+    async fn next(client: &mut Client) -> Value {
+        let message = client.next().await.unwrap().unwrap();
+        serde_json::from_str(message.to_text().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn filters_select_their_events() {
+        let status = |hash: &str| ConsensusEvent::TxStatus {
+            hash: hash.to_string(),
+            status: TxStatus::Included,
+            block_hash: Some("0xb".to_string()),
+        };
+        let finalized = ConsensusEvent::Finalized { block_hash: "0xb".to_string(), height: 3 };
+        let filter = Filter::TxStatus("0xt".to_string());
+        assert_eq!(filter.matches(&status("0xt")), Some(json!({ "status": "included", "block_hash": "0xb" })));
+        assert_eq!(filter.matches(&status("0xu")), None);
+        assert_eq!(filter.matches(&finalized), None);
+        assert_eq!(Filter::Finalized.matches(&finalized), Some(json!({ "block_hash": "0xb", "height": 3 })));
+        assert_eq!(Filter::NewHeads.matches(&finalized), None);
+
+        let log = |tx_hash: &str, kind: &str| Log {
+            block_hash: "0xb".to_string(),
+            height: 3,
+            tx_hash: tx_hash.to_string(),
+            index: 0,
+            event: TxEvent { kind: kind.to_string(), attributes: Default::default() },
+        };
+        let logs = ConsensusEvent::Logs { logs: vec![log("0xt", "transfer"), log("0xu", "counted")] };
+        let filter = Filter::Logs(LogFilter { kinds: vec!["counted".to_string()], ..LogFilter::default() });
+        assert_eq!(filter.matches(&logs).unwrap()[0]["tx_hash"], "0xu");
+        assert_eq!(filter.matches(&logs).unwrap().as_array().unwrap().len(), 1);
+        assert_eq!(Filter::Logs(LogFilter { kinds: vec!["deploy".to_string()], ..LogFilter::default() }).matches(&logs), None);
+        assert_eq!(filter.matches(&finalized), None);
+    }
+
+    #[tokio::test]
+    async fn streams_transaction_status_and_limits_subscriptions() {
+        let consensus = Arc::new(QubeNode::new("v1".to_string(), 7, 10, vec![]).await);
+        let validator = GenesisValidator {
+            node_id: "v1".to_string(),
+            public_key: crate::keys::public_key_hex(&signer()),
+            stake: 10,
+            registration: None,
+        };
+        consensus.load_genesis(&ChainSpec::dev(7, validator, 0)).await.unwrap();
+        let info = NodeInfo::new(NodeRole::Full, PeerCount::default(), false);
+        let log = LogHandle::detached();
+        let admin = Admin::new(P2PNetworking::new().await.unwrap().peer_control(), log, std::path::Path::new("unused"));
+        let backend = Arc::new(Backend { consensus: Arc::clone(&consensus), info, admin, metrics: None });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let policy = Arc::new(Policy::new(None, vec![], 0, 0));
+        let server = tokio::spawn(serve(listener, backend, policy, async {
+            let _ = stopped.await;
+        }));
+        let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+        let tx = Transaction {
+            chain_id: 7,
+            to: BOB.to_string(),
+            value: 5,
+            gas_limit: 21_000,
+            max_fee_per_gas: 1,
+            ..Transaction::default()
+        }
+        .sign(&signer());
+        call(&mut client, "subscribe_txStatus", json!([tx.hash])).await;
+        for _ in 1..MAX_SUBSCRIPTIONS {
+            call(&mut client, "subscribe_finalized", json!([])).await;
+        }
+        call(&mut client, "subscribe_newHeads", json!([])).await;
+        assert_eq!(next(&mut client).await["result"], 0);
+        for _ in 1..MAX_SUBSCRIPTIONS {
+            assert!(next(&mut client).await["result"].is_u64());
+        }
+        assert!(next(&mut client).await["error"]["message"].as_str().unwrap().contains("at most"));
+
+        consensus.submit_transaction(tx).await.unwrap();
+        let notification = next(&mut client).await;
+        assert_eq!(notification["params"]["subscription"], 0);
+        assert_eq!(notification["params"]["result"]["status"], "pending");
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+}
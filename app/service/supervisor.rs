@@ -0,0 +1,274 @@
+//! Supervision of the node's subsystems.
+//!
+//! Every subsystem runs as a task the supervisor owns. One whose work
+//! can't be picked up again, like consensus draining its proposal queue
+//! or the network's event loop, runs once, and its exit ends the node.
+//! The rest are rebuilt under a `Restart` policy when they panic, fail or
+//! stop before shutdown, after a backoff doubling from `min_backoff` up to
+//! `max_backoff`; one that needs more than `max_restarts` restarts within
+//! `RESTART_WINDOW` is given up on, which ends the node too.
+//!
+//! How each subsystem is doing is kept in `Subsystems` and reported by
+//! `node_status`.
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+
+/// Span over which a subsystem's restarts count towards its policy's limit.
+pub const RESTART_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// When and how often a subsystem is restarted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Restart {
+    pub max_restarts: usize,
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Restart {
+    /// For subsystems the node can rebuild from its config, like the API
+    /// servers and background jobs.
+    pub const BACKGROUND: Restart = Restart {
+        max_restarts: 5,
+        min_backoff: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(60),
+    };
+
+    /// Wait before the restart following `restarts` recent ones.
+    fn backoff(&self, restarts: usize) -> Duration {
+        self.min_backoff.saturating_mul(1 << restarts.min(16)).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Waiting out its backoff after stopping unexpectedly
+    Restarting,
+    Stopped,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubsystemHealth {
+    pub name: &'static str,
+    pub state: TaskState,
+    /// Since the node started
+    pub restarts: usize,
+    /// Why it last stopped unexpectedly
+    pub last_error: Option<String>,
+}
+
+/// How each supervised subsystem is doing, shared with the API servers.
+#[derive(Debug, Clone, Default)]
+pub struct Subsystems(Arc<Mutex<BTreeMap<&'static str, SubsystemHealth>>>);
+
+impl Subsystems {
+    /// Every subsystem, by name.
+    pub fn health(&self) -> Vec<SubsystemHealth> {
+        self.0.lock().expect("Subsystems lock poisoned").values().cloned().collect()
+    }
+
+    fn update(&self, name: &'static str, update: impl FnOnce(&mut SubsystemHealth)) {
+        let mut subsystems = self.0.lock().expect("Subsystems lock poisoned");
+        let health = subsystems.entry(name).or_insert(SubsystemHealth { name, state: TaskState::Running, restarts: 0, last_error: None });
+        update(health);
+    }
+
+    /// Records how a subsystem ended for good.
+    fn ended(&self, name: &'static str, result: &Result<()>) {
+        self.update(name, |health| match result {
+            Ok(()) => health.state = TaskState::Stopped,
+            Err(e) => {
+                health.state = TaskState::Failed;
+                health.last_error = Some(format!("{:#}", e));
+            }
+        });
+    }
+}
+
+/// Aborts a subsystem's task when whatever awaits it is dropped, so that
+/// aborting the supervisor's tasks stops the subsystems too.
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Awaits a subsystem's task. Panics become errors and cancellation
+/// counts as a clean stop.
+async fn outcome(handle: JoinHandle<Result<()>>) -> Result<()> {
+    let _abort = AbortOnDrop(handle.abort_handle());
+    match handle.await {
+        Ok(result) => result,
+        Err(e) if e.is_cancelled() => Ok(()),
+        Err(e) => Err(anyhow!("panicked: {}", e)),
+    }
+}
+
+pub struct Supervisor {
+    tasks: JoinSet<(&'static str, Result<()>)>,
+    subsystems: Subsystems,
+    /// Set once the node is shutting down, when subsystems are expected
+    /// to stop
+    stopping: watch::Sender<bool>,
+}
+
+impl Supervisor {
+    /// A supervisor reporting to `subsystems`.
+    pub fn new(subsystems: Subsystems) -> Self {
+        Self { tasks: JoinSet::new(), subsystems, stopping: watch::channel(false).0 }
+    }
+
+    /// Spawns `task`, whose exit is final, returning a handle that stops
+    /// it as a clean stop.
+    pub fn spawn<F>(&mut self, name: &'static str, task: F) -> AbortHandle
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let handle = tokio::spawn(task);
+        let abort = handle.abort_handle();
+        let subsystems = self.subsystems.clone();
+        subsystems.update(name, |_| {});
+        self.tasks.spawn(async move {
+            let result = outcome(handle).await;
+            subsystems.ended(name, &result);
+            (name, result)
+        });
+        abort
+    }
+
+    /// Spawns the task `make` builds, and a new one under `restart` each
+    /// time it stops before shutdown.
+    pub fn spawn_restarting<M, F>(&mut self, name: &'static str, restart: Restart, mut make: M)
+    where
+        M: FnMut() -> F + Send + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let subsystems = self.subsystems.clone();
+        let mut stopping = self.stopping.subscribe();
+        subsystems.update(name, |_| {});
+        self.tasks.spawn(async move {
+            let mut recent = VecDeque::new();
+            loop {
+                let result = outcome(tokio::spawn(make())).await;
+                if *stopping.borrow() {
+                    subsystems.ended(name, &result);
+                    return (name, result);
+                }
+                let error = result.err().unwrap_or_else(|| anyhow!("exited"));
+                recent.retain(|restarted: &Instant| restarted.elapsed() < RESTART_WINDOW);
+                if recent.len() >= restart.max_restarts {
+                    let result = Err(error.context(format!("gave up after {} restarts in {:?}", recent.len(), RESTART_WINDOW)));
+                    subsystems.ended(name, &result);
+                    return (name, result);
+                }
+                let backoff = restart.backoff(recent.len());
+                tracing::warn!(subsystem = name, error = %format!("{:#}", error), retry_in = ?backoff, "subsystem stopped; restarting");
+                subsystems.update(name, |health| {
+                    health.state = TaskState::Restarting;
+                    health.last_error = Some(format!("{:#}", error));
+                });
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = stopping.wait_for(|stopping| *stopping) => {
+                        subsystems.ended(name, &Ok(()));
+                        return (name, Ok(()));
+                    }
+                }
+                recent.push_back(Instant::now());
+                subsystems.update(name, |health| {
+                    health.state = TaskState::Running;
+                    health.restarts += 1;
+                });
+            }
+        });
+    }
+
+    /// The next subsystem to end for good, with how it ended.
+    pub async fn join_next(&mut self) -> Option<(&'static str, Result<()>)> {
+        let joined = self.tasks.join_next().await?;
+        Some(joined.expect("supervised tasks report their own panics"))
+    }
+
+    /// Expects subsystems to stop from now on, rather than restarting them.
+    pub fn stop(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    /// Aborts every subsystem still running.
+    pub fn abort_all(&mut self) {
+        self.tasks.abort_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn reports_panics_by_name() {
+        let subsystems = Subsystems::default();
+        let mut supervisor = Supervisor::new(subsystems.clone());
+        supervisor.spawn("flaky", async { panic!("boom") });
+        let (name, result) = supervisor.join_next().await.unwrap();
+        assert_eq!(name, "flaky");
+        assert!(result.unwrap_err().to_string().contains("panicked"));
+        assert_eq!(subsystems.health()[0].state, TaskState::Failed);
+
+        let abort = supervisor.spawn("endless", std::future::pending());
+        abort.abort();
+        let (name, result) = supervisor.join_next().await.unwrap();
+        assert_eq!((name, result.is_ok()), ("endless", true));
+    }
+
+    #[tokio::test]
+    async fn restarts_with_backoff_until_it_gives_up() {
+        let subsystems = Subsystems::default();
+        let mut supervisor = Supervisor::new(subsystems.clone());
+        let restart = Restart { max_restarts: 3, min_backoff: Duration::from_millis(10), max_backoff: Duration::from_millis(20) };
+        assert_eq!([restart.backoff(0), restart.backoff(1), restart.backoff(5)].map(|d| d.as_millis()), [10, 20, 20]);
+        let started = Instant::now();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&runs);
+        supervisor.spawn_restarting("flaky", restart, move || {
+            let run = counted.fetch_add(1, Ordering::Relaxed);
+            async move {
+                match run {
+                    0 => panic!("boom"),
+                    _ => Err(anyhow!("failed run {}", run)),
+                }
+            }
+        });
+        let (name, result) = supervisor.join_next().await.unwrap();
+        assert_eq!((name, runs.load(Ordering::Relaxed)), ("flaky", 4));
+        assert!(format!("{:#}", result.unwrap_err()).contains("gave up after 3 restarts"));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        let health = &subsystems.health()[0];
+        assert_eq!((health.state, health.restarts), (TaskState::Failed, 3));
+
+        let (stop, stopped) = watch::channel(false);
+        supervisor.spawn_restarting("steady", Restart::BACKGROUND, move || {
+            let mut stopped = stopped.clone();
+            async move {
+                let _ = stopped.wait_for(|stopped| *stopped).await;
+                Ok(())
+            }
+        });
+        supervisor.stop();
+        stop.send_replace(true);
+        let (name, result) = supervisor.join_next().await.unwrap();
+        assert_eq!((name, result.is_ok()), ("steady", true));
+        assert_eq!(subsystems.health()[1].state, TaskState::Stopped);
+    }
+}
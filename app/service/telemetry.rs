@@ -0,0 +1,168 @@
+//! Opt-in reporting to a telemetry collector, for public node dashboards.
+//!
+//! With `telemetry.url` set, the node connects to the collector over
+//! WebSocket and sends it JSON text messages: `system.connected` once per
+//! connection, with the node's name, version, role and chain, then
+//! `system.interval` every `telemetry.interval_secs`, with its heights,
+//! peers, mempool size and how long recent proposals took to verify. The
+//! node sends nothing else and ignores what the collector sends back.
+//!
+//! A collector that is down or drops the connection is retried with
+//! backoff up to `MAX_BACKOFF`; the node runs the same without one.
+
+use anyhow::{bail, Result};
+use consensus::genesis::ChainSpec;
+use consensus::QubeNode;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::TelemetryConfig;
+use crate::status::{self, NodeInfo};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct Telemetry {
+    url: String,
+    interval: Duration,
+    consensus: Arc<QubeNode>,
+    info: NodeInfo,
+    /// Sent first on every connection
+    connected: Value,
+}
+
+impl Telemetry {
+    /// A reporter for `config`, if it names a collector.
+    pub fn new(config: &TelemetryConfig, consensus: Arc<QubeNode>, info: NodeInfo, spec: &ChainSpec) -> Option<Self> {
+        let url = config.url.clone()?;
+        let connected = json!({
+            "msg": "system.connected",
+            "name": config.name.as_deref().unwrap_or(&consensus.node_id),
+            "node_id": consensus.node_id,
+            "version": env!("CARGO_PKG_VERSION"),
+            "role": info.role,
+            "chain": spec.name,
+            "chain_id": spec.chain_id,
+            "genesis_hash": spec.genesis_hash(),
+        });
+        Some(Self { url, interval: Duration::from_secs(config.interval_secs), consensus, info, connected })
+    }
+
+    /// Reports until `shutdown` completes, reconnecting whenever the
+    /// connection fails.
+    pub async fn run(self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tokio::pin!(shutdown);
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let reported = tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                reported = self.report(&mut backoff) => reported,
+            };
+            if let Err(e) = reported {
+                tracing::warn!(url = %self.url, error = %e, retry_in = ?backoff, "telemetry connection failed");
+            }
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = tokio::time::sleep(backoff) => {}
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Reports over one connection until it fails, resetting `backoff`
+    /// once connected.
+    async fn report(&self, backoff: &mut Duration) -> Result<()> {
+        let (mut socket, _) = tokio_tungstenite::connect_async(self.url.as_str()).await?;
+        tracing::info!(url = %self.url, "connected to telemetry collector");
+        *backoff = MIN_BACKOFF;
+        socket.send(Message::Text(stamped(self.connected.clone()).to_string())).await?;
+        let mut ticks = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => socket.send(Message::Text(self.interval_message().await.to_string())).await?,
+                incoming = socket.next() => match incoming {
+                    None | Some(Ok(Message::Close(_))) => bail!("collector closed the connection"),
+                    Some(Err(e)) => return Err(e.into()),
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+    }
+
+    async fn interval_message(&self) -> Value {
+        let status = status::status(&self.consensus, &self.info).await;
+        let height = self.consensus.consensus_state.read().await.current_height;
+        stamped(json!({
+            "msg": "system.interval",
+            "height": height,
+            "finalized_height": status.finalized_height,
+            "sync": status.sync,
+            "peers": status.peer_count,
+            "mempool_size": status.mempool_size,
+            "verification": self.consensus.verification_times(),
+        }))
+    }
+}
+
+/// `message` with `ts`, the time it is sent in Unix milliseconds.
+fn stamped(mut message: Value) -> Value {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    message["ts"] = json!(now.as_millis() as u64);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::NodeRole;
+    use consensus::genesis::GenesisValidator;
+    use networking::PeerCount;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_tungstenite::WebSocketStream;
+
+    async fn receive(socket: &mut WebSocketStream<TcpStream>) -> Value {
+        match socket.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected a text message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_to_the_collector_and_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = TelemetryConfig {
+            url: Some(format!("ws://{}", listener.local_addr().unwrap())),
+            name: Some("public-node".to_string()),
+            interval_secs: 1,
+        };
+        let validator = GenesisValidator { node_id: "v1".to_string(), public_key: "11".repeat(32), stake: 10, registration: None };
+        let spec = ChainSpec::dev(7, validator, 0);
+        let consensus = Arc::new(QubeNode::new("v1".to_string(), 7, 10, vec![]).await);
+        let info = NodeInfo::new(NodeRole::Full, PeerCount::default(), false);
+        assert!(Telemetry::new(&TelemetryConfig::default(), Arc::clone(&consensus), info.clone(), &spec).is_none());
+        let telemetry = Telemetry::new(&config, consensus, info, &spec).unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let reporter = tokio::spawn(telemetry.run(async {
+            let _ = stopped.await;
+        }));
+
+        let mut socket = tokio_tungstenite::accept_async(listener.accept().await.unwrap().0).await.unwrap();
+        let connected = receive(&mut socket).await;
+        assert_eq!((&connected["msg"], &connected["name"]), (&json!("system.connected"), &json!("public-node")));
+        assert_eq!((&connected["role"], &connected["genesis_hash"]), (&json!("full"), &json!(spec.genesis_hash())));
+        let interval = receive(&mut socket).await;
+        assert_eq!((&interval["msg"], &interval["height"], &interval["peers"]), (&json!("system.interval"), &json!(0), &json!(0)));
+        assert_eq!(interval["verification"]["samples"], json!(0));
+
+        drop(socket);
+        let mut socket = tokio_tungstenite::accept_async(listener.accept().await.unwrap().0).await.unwrap();
+        assert_eq!(receive(&mut socket).await["msg"], json!("system.connected"));
+        stop.send(()).unwrap();
+        reporter.await.unwrap().unwrap();
+    }
+}
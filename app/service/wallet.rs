@@ -0,0 +1,283 @@
+//! `cubiq wallet`: an account's balance and history, and transfers and
+//! staking signed here, by a keystore key or a Ledger, then submitted
+//! through a node or, with `--offline`, printed as JSON for
+//! `cubiq wallet broadcast` to submit from a machine that has one.
+//! Staking is through `cubiq_client::staking`.
+//!
+//! A multisig's transactions pass between its keys' holders as JSON
+//! files: `multisig propose` prints one unsigned, each holder adds a
+//! cosignature with `multisig sign`, and `multisig combine` gathers
+//! cosignatures made on separate copies, until `broadcast` can submit it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use consensus::address::Address;
+use consensus::execution::GAS_SCHEDULE;
+use consensus::multisig::Multisig;
+use consensus::{Transaction, TxKind};
+use cubiq_client::staking::RegistryCall;
+use cubiq_client::Client;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{FeeArgs, GlobalArgs, OfflineArgs, SendArgs, SignerArgs};
+use crate::commands;
+use crate::keystore::Keystore;
+use crate::ledger::Ledger;
+use crate::signer::Signer;
+
+/// Fills in and signs transactions, then submits them through a node,
+/// or prints them when offline.
+pub struct Sender {
+    client: Option<Client>,
+    chain_id: Option<u64>,
+}
+
+impl Sender {
+    pub fn new(offline: &OfflineArgs, rpc: &str) -> Result<Self> {
+        let client = if offline.offline { None } else { Some(Client::new(rpc)?) };
+        Ok(Sender { client, chain_id: offline.chain_id })
+    }
+
+    pub async fn send(&self, signer: &mut dyn Signer, unsigned: Transaction, fee: &FeeArgs) -> Result<()> {
+        let tx = self.sign(signer, unsigned, fee).await?;
+        match &self.client {
+            Some(client) => submit(client, tx).await,
+            None => {
+                println!("{}", serde_json::to_string(&tx)?);
+                Ok(())
+            }
+        }
+    }
+
+    /// `unsigned` from `signer`'s account, filled in as by `fill`.
+    async fn sign(&self, signer: &mut dyn Signer, unsigned: Transaction, fee: &FeeArgs) -> Result<Transaction> {
+        let unsigned = self.fill(&signer.address(), unsigned, fee).await?;
+        signer.sign_transaction(unsigned)
+    }
+
+    /// `unsigned` for the chain the node is on, with `account`'s next
+    /// nonce and the node's fee estimate unless the flags name them, which
+    /// they must offline.
+    async fn fill(&self, account: &Address, mut unsigned: Transaction, fee: &FeeArgs) -> Result<Transaction> {
+        unsigned.chain_id = match (self.chain_id, &self.client) {
+            (Some(chain_id), _) => chain_id,
+            (None, Some(client)) => client.status().await?.chain_id,
+            (None, None) => bail!("--offline needs --chain-id"),
+        };
+        unsigned.nonce = match (fee.nonce, &self.client) {
+            (Some(nonce), _) => nonce,
+            (None, Some(client)) => client.nonce(&account.to_string()).await?,
+            (None, None) => bail!("--offline needs --nonce"),
+        };
+        (unsigned.max_fee_per_gas, unsigned.max_priority_fee_per_gas) = match (fee.max_fee_per_gas, fee.max_priority_fee_per_gas, &self.client) {
+            (Some(max_fee), Some(tip), _) => (max_fee, tip),
+            (max_fee, tip, Some(client)) => {
+                let estimate = client.fee_estimate().await?;
+                let suggested = tip.unwrap_or(estimate.max_priority_fee_per_gas);
+                let max_fee = max_fee.unwrap_or_else(|| estimate.base_fee.saturating_mul(2).saturating_add(suggested));
+                (max_fee, tip.unwrap_or(suggested.min(max_fee)))
+            }
+            (_, _, None) => bail!("--offline needs --max-fee-per-gas and --max-priority-fee-per-gas"),
+        };
+        unsigned.gas_limit = GAS_SCHEDULE.intrinsic(&unsigned);
+        Ok(unsigned)
+    }
+}
+
+async fn submit(client: &Client, tx: Transaction) -> Result<()> {
+    let hash = client.submit(&tx).await?;
+    println!("{}", hash);
+    Ok(())
+}
+
+pub async fn transfer(global: &GlobalArgs, to: &Address, value: u64, args: &SendArgs) -> Result<()> {
+    send(global, Transaction { to: to.to_string(), value, ..Transaction::default() }, args).await
+}
+
+pub async fn delegate(global: &GlobalArgs, validator: &str, amount: u64, args: &SendArgs) -> Result<()> {
+    send(global, RegistryCall::Delegate { node_id: validator.to_string() }.transaction(amount), args).await
+}
+
+pub async fn undelegate(global: &GlobalArgs, validator: &str, amount: u64, args: &SendArgs) -> Result<()> {
+    send(global, RegistryCall::Undelegate { node_id: validator.to_string(), amount }.transaction(0), args).await
+}
+
+async fn send(global: &GlobalArgs, unsigned: Transaction, args: &SendArgs) -> Result<()> {
+    let sender = Sender::new(&args.offline, &args.rpc.rpc)?;
+    sender.send(commands::signer(global, &args.signer)?.as_mut(), unsigned, &args.fee).await
+}
+
+/// `account`, else the account of the key or Ledger `signer` picks,
+/// which needs no password.
+pub fn account(global: &GlobalArgs, account: Option<Address>, signer: &SignerArgs) -> Result<Address> {
+    if let Some(account) = account {
+        return Ok(account);
+    }
+    if signer.ledger {
+        return Ok(Ledger::connect(signer.ledger_path.clone())?.address());
+    }
+    let key = Keystore::new(&global.data_dir).load(&signer.key)?;
+    key.address().with_context(|| format!("key {} is {}, but accounts are ed25519 keys", signer.key, key.key_type))
+}
+
+pub async fn balance(account: &Address, rpc: &str) -> Result<()> {
+    println!("{}", Client::new(rpc)?.balance(&account.to_string()).await?);
+    Ok(())
+}
+
+/// Prints `account`'s finalized transactions, newest first.
+pub async fn history(account: &Address, limit: usize, before: Option<u64>, rpc: &str) -> Result<()> {
+    let account = account.to_string();
+    let page = Client::new(rpc)?.transactions_by_address(&account, before, Some(limit)).await?;
+    for entry in &page.items {
+        let (direction, counterparty) = match (entry.from == account, entry.to == account) {
+            (true, true) => ("self", account.as_str()),
+            (true, false) => ("out", entry.to.as_str()),
+            _ => ("in", entry.from.as_str()),
+        };
+        println!("{}\t{}\t{}\t{}\t{}", entry.height, entry.tx_hash, direction, counterparty, entry.value);
+    }
+    if let Some(next) = page.next {
+        eprintln!("Older transactions: --before {}", next);
+    }
+    Ok(())
+}
+
+/// Submits a transaction printed by `--offline` or `multisig combine`,
+/// read from `path` or, for `-`, stdin.
+pub async fn broadcast(path: &Path, rpc: &str) -> Result<()> {
+    let tx = read_transaction(path)?;
+    tx.verify_signature().map_err(|e| anyhow!("{}", e))?;
+    submit(&Client::new(rpc)?, tx).await
+}
+
+/// The transaction in the JSON file at `path`, or on stdin for `-`, whose
+/// hash must match its fields.
+fn read_transaction(path: &Path) -> Result<Transaction> {
+    let json = if path == Path::new("-") {
+        let mut json = String::new();
+        std::io::stdin().read_to_string(&mut json)?;
+        json
+    } else {
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?
+    };
+    let tx: Transaction = serde_json::from_str(&json).context("not a transaction")?;
+    if tx.hash != tx.compute_hash() {
+        bail!("the transaction's hash does not match its fields");
+    }
+    Ok(tx)
+}
+
+/// Creates the multisig of `members`, whose `threshold` must cosign its
+/// transactions, funded with `value` from the signer's account.
+pub async fn create_multisig(global: &GlobalArgs, threshold: u32, members: &[Address], value: u64, args: &SendArgs) -> Result<()> {
+    let keys = members
+        .iter()
+        .map(|member| {
+            member.verifying_key().with_context(|| format!("member {}", member))?;
+            Ok(hex::encode(member.as_bytes()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let multisig = Multisig::new(threshold, keys).map_err(|e| anyhow!("the multisig {}", e))?;
+    let data = serde_json::to_vec(&multisig)?;
+    send(global, Transaction { kind: TxKind::CreateMultisig, value, data, ..Transaction::default() }, args).await?;
+    eprintln!("Multisig account: {}", multisig.address());
+    Ok(())
+}
+
+/// Prints a transfer of `value` from the multisig at `from` to `to`, with
+/// no cosignatures yet.
+pub async fn propose(from: &Address, to: &Address, value: u64, fee: &FeeArgs, offline: &OfflineArgs, rpc: &str) -> Result<()> {
+    let unsigned = Transaction { to: to.to_string(), value, ..Transaction::default() };
+    let tx = Sender::new(offline, rpc)?.fill(from, unsigned, fee).await?.from_account(from);
+    println!("{}", serde_json::to_string(&tx)?);
+    Ok(())
+}
+
+/// Prints the proposal at `path` with `signer`'s cosignature added.
+pub fn cosign(path: &Path, signer: &mut dyn Signer) -> Result<()> {
+    let mut tx = read_transaction(path)?;
+    let cosignature = signer.cosign(&tx)?;
+    tx.cosignatures.retain(|existing| existing.public_key != cosignature.public_key);
+    tx.cosignatures.push(cosignature);
+    tx.verify_signature().map_err(|e| anyhow!("{}", e))?;
+    println!("{}", serde_json::to_string(&tx)?);
+    Ok(())
+}
+
+/// Prints the proposal cosigned separately at each of `paths` with every
+/// cosignature they hold.
+pub fn combine(paths: &[PathBuf]) -> Result<()> {
+    let copies = paths
+        .iter()
+        .map(|path| read_transaction(path).with_context(|| path.display().to_string()))
+        .collect::<Result<Vec<_>>>()?;
+    println!("{}", serde_json::to_string(&merge(copies)?)?);
+    Ok(())
+}
+
+/// One transaction with the cosignatures of all of `copies`, which must
+/// be of the same one.
+fn merge(copies: Vec<Transaction>) -> Result<Transaction> {
+    let mut copies = copies.into_iter();
+    let mut tx = copies.next().context("nothing to combine")?;
+    for copy in copies {
+        if copy.hash != tx.hash {
+            bail!("transaction {} is not {}, so their cosignatures don't combine", copy.hash, tx.hash);
+        }
+        for cosignature in copy.cosignatures {
+            if !tx.cosignatures.iter().any(|existing| existing.public_key == cosignature.public_key) {
+                tx.cosignatures.push(cosignature);
+            }
+        }
+    }
+    tx.verify_signature().map_err(|e| anyhow!("{}", e))?;
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[tokio::test]
+    async fn offline_transactions_are_signed_from_the_flags_alone() {
+        let offline = OfflineArgs { offline: true, chain_id: Some(7) };
+        let sender = Sender::new(&offline, "http://127.0.0.1:1").unwrap();
+        let mut key = SigningKey::from_bytes(&[3; 32]);
+        let fee = FeeArgs { max_fee_per_gas: Some(20), max_priority_fee_per_gas: Some(2), nonce: Some(4) };
+        let unsigned = RegistryCall::Delegate { node_id: "validator-1".to_string() }.transaction(100);
+        let tx = sender.sign(&mut key, unsigned.clone(), &fee).await.unwrap();
+        assert_eq!((tx.chain_id, tx.nonce, tx.max_fee_per_gas, tx.value), (7, 4, 20, 100));
+        assert_eq!(tx.gas_limit, GAS_SCHEDULE.intrinsic(&tx));
+        assert!(tx.verify_signature().is_ok());
+
+        let no_nonce = FeeArgs { nonce: None, ..fee };
+        assert!(sender.sign(&mut key, unsigned, &no_nonce).await.unwrap_err().to_string().contains("--nonce"));
+    }
+
+    #[tokio::test]
+    async fn cosignatures_made_on_separate_copies_combine() {
+        let offline = OfflineArgs { offline: true, chain_id: Some(7) };
+        let sender = Sender::new(&offline, "http://127.0.0.1:1").unwrap();
+        let keys: Vec<SigningKey> = (1..=3).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+        let multisig = Multisig::new(2, keys.iter().map(|key| key.public_key())).unwrap();
+        let fee = FeeArgs { max_fee_per_gas: Some(20), max_priority_fee_per_gas: Some(2), nonce: Some(0) };
+        let unsigned = Transaction { to: Address::from_bytes([0xbb; 32]).to_string(), value: 5, ..Transaction::default() };
+        let proposal = sender.fill(&multisig.address(), unsigned, &fee).await.unwrap().from_account(&multisig.address());
+
+        let copies: Vec<Transaction> = keys[..2]
+            .iter()
+            .map(|key| Transaction { cosignatures: vec![proposal.cosign(key)], ..proposal.clone() })
+            .collect();
+        let combined = merge(copies.clone()).unwrap();
+        assert_eq!(combined.cosignatures.len(), 2);
+        assert!(multisig.check(&combined.cosignatures).is_ok());
+        assert_eq!(merge(vec![combined.clone(), copies[0].clone()]).unwrap().cosignatures, combined.cosignatures);
+
+        let other = Transaction { value: 6, ..proposal.clone() }.from_account(&multisig.address());
+        let other = Transaction { cosignatures: vec![other.cosign(&keys[2])], ..other };
+        assert!(merge(vec![combined, other]).unwrap_err().to_string().contains("don't combine"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Resource watchdog.
+//!
+//! Every `watchdog.interval_secs` the node measures the free space on its
+//! data dir's filesystem, its resident memory against the machine's and
+//! its open file descriptors against its limit. Past a soft threshold it
+//! warns, once until the resource recovers. Past a hard one it stops
+//! admitting transactions, fails `/ready` and answers RPC calls other than
+//! `admin_*` with `UNAVAILABLE`, rather than run out in the middle of
+//! writing a block; it carries on once every resource is back under its
+//! hard threshold. Memory and file descriptors are only measured on Linux.
+
+use anyhow::Result;
+use consensus::QubeNode;
+use std::collections::BTreeMap;
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::config::WatchdogConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Soft,
+    Hard,
+}
+
+/// A resource past one of its thresholds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breach {
+    pub resource: &'static str,
+    pub level: Level,
+    pub detail: String,
+}
+
+/// What the watchdog measured; figures the platform doesn't offer are
+/// `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub disk_free: Option<u64>,
+    pub memory: Option<u64>,
+    pub memory_total: Option<u64>,
+    pub fds: Option<u64>,
+    pub fd_limit: Option<u64>,
+}
+
+/// Why the node is refusing work, shared with the API servers. Empty
+/// while every resource is under its hard threshold.
+#[derive(Debug, Clone, Default)]
+pub struct Pressure(Arc<Mutex<Vec<String>>>);
+
+impl Pressure {
+    pub fn critical(&self) -> Vec<String> {
+        self.0.lock().expect("Pressure lock poisoned").clone()
+    }
+
+    fn set(&self, reasons: Vec<String>) {
+        *self.0.lock().expect("Pressure lock poisoned") = reasons;
+    }
+}
+
+#[derive(Clone)]
+pub struct Watchdog {
+    config: WatchdogConfig,
+    data_dir: PathBuf,
+    consensus: Arc<QubeNode>,
+    pressure: Pressure,
+    /// Resources past a threshold at the last check, and which threshold
+    breached: BTreeMap<&'static str, Level>,
+}
+
+impl Watchdog {
+    /// A watchdog for `data_dir` reporting to `pressure`, unless `config`
+    /// disables it.
+    pub fn new(config: &WatchdogConfig, data_dir: &Path, consensus: Arc<QubeNode>, pressure: Pressure) -> Option<Self> {
+        (config.interval_secs > 0).then(|| Self {
+            config: config.clone(),
+            data_dir: data_dir.to_path_buf(),
+            consensus,
+            pressure,
+            breached: BTreeMap::new(),
+        })
+    }
+
+    /// Checks resources until `shutdown` completes.
+    pub async fn run(mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tokio::pin!(shutdown);
+        let mut ticks = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = ticks.tick() => {}
+            }
+            let data_dir = self.data_dir.clone();
+            let usage = tokio::task::spawn_blocking(move || measure(&data_dir)).await?;
+            self.apply(assess(&self.config, &usage));
+        }
+    }
+
+    /// Logs what changed since the last check, and pauses or resumes
+    /// admission and the API servers.
+    fn apply(&mut self, breaches: Vec<Breach>) {
+        for breach in &breaches {
+            if self.breached.get(breach.resource) == Some(&breach.level) {
+                continue;
+            }
+            match breach.level {
+                Level::Soft => tracing::warn!(resource = breach.resource, detail = %breach.detail, "resource running low"),
+                Level::Hard => tracing::error!(resource = breach.resource, detail = %breach.detail, "resource exhausted"),
+            }
+        }
+        for resource in self.breached.keys().filter(|resource| !breaches.iter().any(|breach| breach.resource == **resource)) {
+            tracing::info!(resource, "resource recovered");
+        }
+        self.breached = breaches.iter().map(|breach| (breach.resource, breach.level)).collect();
+
+        let critical: Vec<String> = breaches
+            .iter()
+            .filter(|breach| breach.level == Level::Hard)
+            .map(|breach| format!("{}: {}", breach.resource, breach.detail))
+            .collect();
+        let pause = !critical.is_empty();
+        match (self.consensus.set_admission_paused(pause), pause) {
+            (false, true) => tracing::error!("pausing transaction admission and RPC until resources recover"),
+            (true, false) => tracing::info!("admitting transactions and serving RPC again"),
+            _ => {}
+        }
+        self.pressure.set(critical);
+    }
+}
+
+/// The resources in `usage` past their thresholds in `config`.
+pub fn assess(config: &WatchdogConfig, usage: &Usage) -> Vec<Breach> {
+    let mut breaches = Vec::new();
+    if let Some(free) = usage.disk_free {
+        let level = match free {
+            free if free < config.disk_hard_bytes => Some(Level::Hard),
+            free if free < config.disk_soft_bytes => Some(Level::Soft),
+            _ => None,
+        };
+        if let Some(level) = level {
+            let detail = format!("{} MiB free on the data dir's filesystem", free >> 20);
+            breaches.push(Breach { resource: "disk", level, detail });
+        }
+    }
+    let shares = [
+        ("memory", usage.memory.zip(usage.memory_total), config.memory_soft_percent, config.memory_hard_percent),
+        ("fds", usage.fds.zip(usage.fd_limit), config.fds_soft_percent, config.fds_hard_percent),
+    ];
+    for (resource, figures, soft, hard) in shares {
+        let Some((used, total)) = figures.filter(|(_, total)| *total > 0) else {
+            continue;
+        };
+        let percent = used.saturating_mul(100) / total;
+        let level = match percent {
+            percent if percent >= hard as u64 => Some(Level::Hard),
+            percent if percent >= soft as u64 => Some(Level::Soft),
+            _ => None,
+        };
+        if let Some(level) = level {
+            breaches.push(Breach { resource, level, detail: format!("{}% in use, {} of {}", percent, used, total) });
+        }
+    }
+    breaches
+}
+
+fn measure(data_dir: &Path) -> Usage {
+    Usage {
+        disk_free: fs4::available_space(data_dir).ok(),
+        memory: proc_bytes("/proc/self/status", "VmRSS:"),
+        memory_total: proc_bytes("/proc/meminfo", "MemTotal:"),
+        fds: fs::read_dir("/proc/self/fd").ok().map(|fds| fds.count() as u64),
+        fd_limit: fd_limit(),
+    }
+}
+
+/// A figure in kB from a `/proc` file, in bytes.
+fn proc_bytes(path: &str, field: &str) -> Option<u64> {
+    let contents = fs::read_to_string(path).ok()?;
+    let line = contents.lines().find(|line| line.starts_with(field))?;
+    let kb: u64 = line[field.len()..].trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(unix)]
+fn fd_limit() -> Option<u64> {
+    rlimit::getrlimit(rlimit::Resource::NOFILE).ok().map(|(soft, _)| soft)
+}
+
+#[cfg(not(unix))]
+fn fd_limit() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consensus::Transaction;
+
+    #[tokio::test]
+    async fn pauses_admission_past_a_hard_threshold_until_it_recovers() {
+        let config = WatchdogConfig { disk_soft_bytes: 10 << 30, disk_hard_bytes: 2 << 30, ..WatchdogConfig::default() };
+        let low = Usage { disk_free: Some(5 << 30), memory: Some(50), memory_total: Some(100), fds: Some(96), fd_limit: Some(100) };
+        let breaches = assess(&config, &low);
+        let levels: Vec<_> = breaches.iter().map(|breach| (breach.resource, breach.level)).collect();
+        assert_eq!(levels, [("disk", Level::Soft), ("fds", Level::Hard)]);
+        assert!(assess(&config, &Usage::default()).is_empty());
+
+        let consensus = Arc::new(QubeNode::new("v1".to_string(), 7, 10, vec![]).await);
+        let pressure = Pressure::default();
+        let mut watchdog = Watchdog::new(&config, Path::new("."), Arc::clone(&consensus), pressure.clone()).unwrap();
+        watchdog.apply(breaches);
+        assert_eq!(pressure.critical(), ["fds: 96% in use, 96 of 100"]);
+        let refused = consensus.submit_transaction(Transaction::default()).await.unwrap_err();
+        assert!(refused.contains("not admitting"));
+
+        watchdog.apply(assess(&config, &Usage { fds: Some(10), ..low }));
+        assert!(pressure.critical().is_empty());
+        assert!(!consensus.set_admission_paused(false));
+        assert!(Watchdog::new(&WatchdogConfig { interval_secs: 0, ..config }, Path::new("."), consensus, pressure).is_none());
+    }
+}
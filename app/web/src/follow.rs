@@ -0,0 +1,158 @@
+//! Following a node's finalized blocks over its WebSocket endpoint,
+//! without the browser: `Follower` turns what the node sends into what to
+//! send back and what was certified, so the socket glue stays thin.
+//!
+//! Once open, the follower subscribes with `subscribe_finalized`. For each
+//! block finalized it asks `chain_getFinalityCertificate` and checks the
+//! answer with the light client. A node whose subscription ends, or that
+//! answers with a certificate that does not hold, is left for the next.
+
+use cubiq_light::certificate::{Checkpoint, FinalityCertificate};
+use cubiq_light::LightClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Request id of the subscription; certificate requests count up from
+/// the next.
+const SUBSCRIBE_ID: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Send this request to the node
+    Send(String),
+    /// The light client certified the checkpoint
+    Finalized(Checkpoint),
+    /// Close the connection and follow another node, for this reason
+    Leave(String),
+}
+
+/// A response, or a subscription's notification.
+#[derive(Deserialize)]
+struct Message {
+    id: Option<u64>,
+    result: Option<Value>,
+    error: Option<Value>,
+    params: Option<Notification>,
+}
+
+#[derive(Deserialize)]
+struct Notification {
+    subscription: u64,
+    result: Option<Finalized>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct Finalized {
+    block_hash: String,
+    height: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Follower {
+    subscription: Option<u64>,
+    next_id: u64,
+    /// Finalized blocks asked for, by request id
+    pending: HashMap<u64, (u64, String)>,
+}
+
+impl Follower {
+    /// Starts over on a connection just opened, returning the
+    /// subscription request to send.
+    pub fn opened(&mut self) -> String {
+        *self = Self { next_id: SUBSCRIBE_ID + 1, ..Self::default() };
+        json!({ "jsonrpc": "2.0", "id": SUBSCRIBE_ID, "method": "subscribe_finalized", "params": [] }).to_string()
+    }
+
+    /// What to do about `text`, received from the node.
+    pub fn received(&mut self, client: &mut LightClient, text: &str) -> Option<Step> {
+        let message: Message = serde_json::from_str(text).ok()?;
+        if let Some(notification) = message.params {
+            if Some(notification.subscription) != self.subscription {
+                return None;
+            }
+            if let Some(error) = notification.error {
+                return Some(Step::Leave(format!("subscription ended: {}", error)));
+            }
+            let finalized = notification.result?;
+            let id = self.next_id;
+            self.next_id += 1;
+            let request = json!({ "jsonrpc": "2.0", "id": id, "method": "chain_getFinalityCertificate", "params": [finalized.height] });
+            self.pending.insert(id, (finalized.height, finalized.block_hash));
+            return Some(Step::Send(request.to_string()));
+        }
+
+        let id = message.id?;
+        if let Some(error) = message.error {
+            return if id == SUBSCRIBE_ID {
+                Some(Step::Leave(format!("subscribing failed: {}", error)))
+            } else {
+                self.pending.remove(&id);
+                None
+            };
+        }
+        if id == SUBSCRIBE_ID {
+            self.subscription = message.result.and_then(|id| id.as_u64());
+            return None;
+        }
+        let (height, block_hash) = self.pending.remove(&id)?;
+        // Null once the node no longer has the block or its votes
+        let result = message.result.filter(|result| !result.is_null())?;
+        let certificate: FinalityCertificate = match serde_json::from_value(result) {
+            Ok(certificate) => certificate,
+            Err(e) => return Some(Step::Leave(format!("certificate for height {} does not decode: {}", height, e))),
+        };
+        let checkpoint = &certificate.checkpoint;
+        if (checkpoint.height, &checkpoint.block_hash) != (height, &block_hash) {
+            let served = format!("{} at height {}", checkpoint.block_hash, checkpoint.height);
+            return Some(Step::Leave(format!("asked for {} at height {}, served {}", block_hash, height, served)));
+        }
+        match client.verify_certificate(&certificate) {
+            Ok(()) => Some(Step::Finalized(certificate.checkpoint)),
+            Err(e) => Some(Step::Leave(format!("certificate for height {} does not hold: {}", height, e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cubiq_light::validators::{SnapshotValidator, ValidatorSnapshot};
+
+    fn client() -> LightClient {
+        let validator = SnapshotValidator { node_id: "v1".to_string(), public_key: "00".repeat(32), stake: 10 };
+        LightClient::new(7, 100, ValidatorSnapshot { epoch: 0, validators: vec![validator], supermajority_threshold: 7 }).unwrap()
+    }
+
+    #[test]
+    fn asks_for_certificates_of_finalized_blocks_and_leaves_bad_nodes() {
+        let (mut follower, mut client) = (Follower::default(), client());
+        assert!(follower.opened().contains("subscribe_finalized"));
+        let notify = |subscription: u64, height: u64| {
+            let result = json!({ "block_hash": format!("0xb{}", height), "height": height });
+            json!({ "jsonrpc": "2.0", "method": "subscription", "params": { "subscription": subscription, "result": result } }).to_string()
+        };
+        assert_eq!(follower.received(&mut client, &notify(5, 1)), None);
+        assert_eq!(follower.received(&mut client, r#"{"jsonrpc":"2.0","id":1,"result":5}"#), None);
+        assert_eq!(follower.received(&mut client, &notify(6, 1)), None);
+
+        let Some(Step::Send(request)) = follower.received(&mut client, &notify(5, 1)) else { panic!("no request") };
+        let request: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!((&request["id"], &request["method"], &request["params"]), (&json!(2), &json!("chain_getFinalityCertificate"), &json!([1])));
+        assert_eq!(follower.received(&mut client, r#"{"jsonrpc":"2.0","id":2,"result":null}"#), None);
+
+        follower.received(&mut client, &notify(5, 2));
+        let checkpoint = json!({
+            "height": 2, "block_hash": "0xb2", "state_root": format!("0x{}", "00".repeat(32)),
+            "transactions_root": format!("0x{}", "00".repeat(32)), "gas_used": 0, "transaction_count": 0,
+        });
+        let unsigned = json!({ "jsonrpc": "2.0", "id": 3, "result": { "checkpoint": checkpoint, "votes": [], "proof": [] } });
+        let Some(Step::Leave(reason)) = follower.received(&mut client, &unsigned.to_string()) else { panic!("stayed") };
+        assert!(reason.contains("does not hold: votes hold 0 stake"), "{reason}");
+        assert_eq!(client.head(), None);
+
+        let ended = json!({ "jsonrpc": "2.0", "method": "subscription", "params": { "subscription": 5, "error": { "code": -32000, "message": "lagged" } } });
+        assert!(matches!(follower.received(&mut client, &ended.to_string()), Some(Step::Leave(_))));
+    }
+}
@@ -0,0 +1,278 @@
+//! Browser light node: follows Cubiq finality over the WebSocket endpoint
+//! of untrusted nodes, checking each finalized block's certificate with
+//! `cubiq_light`, and verifies proofs with the mobile verifier.
+//!
+//! ```js
+//! import init, { LightNode } from "cubiq-web";
+//!
+//! await init();
+//! const node = new LightNode(chainId, epochLength, validatorSet);
+//! node.onFinalized((checkpoint) => console.log(checkpoint.height, checkpoint.state_root));
+//! node.followChain(["wss://rpc1.example/", "wss://rpc2.example/"]);
+//! node.verifyAccount(height, address, account, proof);
+//! ```
+//!
+//! `validatorSet` is what `validator_set` answers, from a node the dapp
+//! trusts or shipped with it. Chain ids, epoch lengths and heights are
+//! plain numbers rather than BigInts. Bootnodes are tried in turn: when one
+//! closes, or leaves per `follow`, the next is connected after
+//! `RECONNECT_DELAY_MS`.
+
+use cubiq_light::certificate::FinalityCertificate;
+use cubiq_light::proofs::{AccountState, InclusionProof, StateProof};
+use cubiq_light::validators::ValidatorSnapshot;
+use cubiq_light::LightClient;
+use prover::domain::ProofPurpose;
+use prover::MobileProofVerifier;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use wasm_bindgen::prelude::*;
+use web_sys::{MessageEvent, WebSocket};
+
+use follow::{Follower, Step};
+
+/// How long to wait before connecting to the next bootnode.
+pub const RECONNECT_DELAY_MS: i32 = 2_000;
+
+#[wasm_bindgen]
+extern "C" {
+    /// The global `setTimeout`, there in windows and workers alike
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(callback: &JsValue, millis: i32) -> JsValue;
+}
+
+/// An open socket and the handlers it calls, which must outlive it.
+struct Connection {
+    socket: WebSocket,
+    url: String,
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Events of a socket outliving its handlers would call freed closures
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
+
+struct Shared {
+    chain_id: u64,
+    client: RefCell<LightClient>,
+    verifier: MobileProofVerifier,
+    follower: RefCell<Follower>,
+    on_finalized: RefCell<Vec<js_sys::Function>>,
+    bootnodes: RefCell<Vec<String>>,
+    /// Index of the bootnode to connect to next
+    next: Cell<usize>,
+    connection: RefCell<Option<Connection>>,
+}
+
+impl Shared {
+    fn send(&self, text: &str) {
+        if let Some(connection) = &*self.connection.borrow() {
+            if let Err(e) = connection.socket.send_with_str(text) {
+                web_sys::console::warn_2(&format!("cubiq-web: sending to {} failed:", connection.url).into(), &e);
+            }
+        }
+    }
+
+    fn received(self: &Rc<Self>, text: &str) {
+        let step = self.follower.borrow_mut().received(&mut self.client.borrow_mut(), text);
+        match step {
+            Some(Step::Send(request)) => self.send(&request),
+            Some(Step::Finalized(checkpoint)) => {
+                let Ok(checkpoint) = serde_wasm_bindgen::to_value(&checkpoint) else {
+                    return;
+                };
+                // Cloned, so callbacks may add callbacks
+                let callbacks = self.on_finalized.borrow().clone();
+                for callback in callbacks {
+                    if let Err(e) = callback.call1(&JsValue::NULL, &checkpoint) {
+                        web_sys::console::error_2(&"cubiq-web: onFinalized callback threw:".into(), &e);
+                    }
+                }
+            }
+            Some(Step::Leave(reason)) => {
+                let url = self.connection.borrow().as_ref().map(|connection| connection.url.clone()).unwrap_or_default();
+                web_sys::console::warn_1(&format!("cubiq-web: leaving {}: {}", url, reason).into());
+                self.connection.borrow_mut().take();
+                self.reconnect_later();
+            }
+            None => {}
+        }
+    }
+
+    /// Connects to the next bootnode, if there are any.
+    fn connect(self: &Rc<Self>) {
+        let url = {
+            let bootnodes = self.bootnodes.borrow();
+            if bootnodes.is_empty() {
+                return;
+            }
+            let index = self.next.get() % bootnodes.len();
+            self.next.set(index + 1);
+            bootnodes[index].clone()
+        };
+        let socket = match WebSocket::new(&url) {
+            Ok(socket) => socket,
+            Err(e) => {
+                web_sys::console::warn_2(&format!("cubiq-web: connecting to {} failed:", url).into(), &e);
+                return self.reconnect_later();
+            }
+        };
+        let weak = Rc::downgrade(self);
+        let on_open = Closure::<dyn FnMut()>::new(with(&weak, |shared| {
+            let request = shared.follower.borrow_mut().opened();
+            shared.send(&request);
+        }));
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new({
+            let weak = weak.clone();
+            move |event: MessageEvent| {
+                if let (Some(shared), Some(text)) = (weak.upgrade(), event.data().as_string()) {
+                    shared.received(&text);
+                }
+            }
+        });
+        let on_close = Closure::<dyn FnMut()>::new(with(&weak, |shared| {
+            shared.connection.borrow_mut().take();
+            shared.reconnect_later();
+        }));
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        let connection = Connection { socket, url, _on_open: on_open, _on_message: on_message, _on_close: on_close };
+        // Dropping the one replaced closes it
+        drop(self.connection.replace(Some(connection)));
+    }
+
+    fn reconnect_later(self: &Rc<Self>) {
+        let weak = Rc::downgrade(self);
+        let retry = Closure::once_into_js(move || {
+            if let Some(shared) = weak.upgrade() {
+                if shared.connection.borrow().is_none() {
+                    shared.connect();
+                }
+            }
+        });
+        set_timeout(&retry, RECONNECT_DELAY_MS);
+    }
+}
+
+/// A handler calling `f` while the light node is alive.
+fn with(weak: &Weak<Shared>, f: impl Fn(&Rc<Shared>) + 'static) -> impl FnMut() + 'static {
+    let weak = weak.clone();
+    move || {
+        if let Some(shared) = weak.upgrade() {
+            f(&shared);
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct LightNode {
+    shared: Rc<Shared>,
+}
+
+#[wasm_bindgen]
+impl LightNode {
+    /// A light node of chain `chain_id`, whose epochs are `epoch_length`
+    /// blocks, trusting `validator_set` from its epoch on.
+    #[wasm_bindgen(constructor)]
+    pub fn new(chain_id: f64, epoch_length: f64, validator_set: JsValue) -> Result<LightNode, JsError> {
+        let snapshot: ValidatorSnapshot = serde_wasm_bindgen::from_value(validator_set)?;
+        let chain_id = chain_id as u64;
+        let shared = Shared {
+            chain_id,
+            client: RefCell::new(LightClient::new(chain_id, epoch_length as u64, snapshot)?),
+            verifier: MobileProofVerifier::new(),
+            follower: RefCell::new(Follower::default()),
+            on_finalized: RefCell::new(vec![]),
+            bootnodes: RefCell::new(vec![]),
+            next: Cell::new(0),
+            connection: RefCell::new(None),
+        };
+        Ok(LightNode { shared: Rc::new(shared) })
+    }
+
+    /// Trusts a later validator set, as `validator_set` answers it with
+    /// the `epoch` it takes effect at.
+    #[wasm_bindgen(js_name = addValidatorSet)]
+    pub fn add_validator_set(&self, validator_set: JsValue) -> Result<(), JsError> {
+        let snapshot: ValidatorSnapshot = serde_wasm_bindgen::from_value(validator_set)?;
+        Ok(self.shared.client.borrow_mut().add_snapshot(snapshot)?)
+    }
+
+    /// Follows finality through the WebSocket endpoints `bootnodes`,
+    /// replacing any being followed.
+    #[wasm_bindgen(js_name = followChain)]
+    pub fn follow_chain(&self, bootnodes: Vec<String>) -> Result<(), JsError> {
+        if bootnodes.is_empty() {
+            return Err(JsError::new("followChain needs at least one bootnode"));
+        }
+        *self.shared.bootnodes.borrow_mut() = bootnodes;
+        self.shared.next.set(0);
+        self.shared.connection.borrow_mut().take();
+        self.shared.connect();
+        Ok(())
+    }
+
+    /// Stops following the chain.
+    pub fn stop(&self) {
+        self.shared.bootnodes.borrow_mut().clear();
+        self.shared.connection.borrow_mut().take();
+    }
+
+    /// Calls `callback` with each checkpoint certified, as
+    /// `{height, block_hash, state_root, transactions_root, gas_used,
+    /// transaction_count}`.
+    #[wasm_bindgen(js_name = onFinalized)]
+    pub fn on_finalized(&self, callback: js_sys::Function) {
+        self.shared.on_finalized.borrow_mut().push(callback);
+    }
+
+    /// The newest certified checkpoint, or undefined.
+    pub fn head(&self) -> Result<JsValue, JsError> {
+        let client = self.shared.client.borrow();
+        Ok(serde_wasm_bindgen::to_value(&client.head().cloned())?)
+    }
+
+    /// Whether `proof` is a block finality proof on this chain over
+    /// `public_inputs`, encoded as a proof bundle's are.
+    #[wasm_bindgen(js_name = verifyProof)]
+    pub fn verify_proof(&self, proof: &[u8], public_inputs: &[u8]) -> Result<bool, JsValue> {
+        self.shared.verifier.verify_proof_with_public_inputs(proof, public_inputs, self.shared.chain_id, ProofPurpose::BlockFinality)
+    }
+
+    /// Checks a certificate, as `chain_getFinalityCertificate` answers
+    /// it, keeping its checkpoint if it holds.
+    #[wasm_bindgen(js_name = verifyCertificate)]
+    pub fn verify_certificate(&self, certificate: JsValue) -> Result<(), JsError> {
+        let certificate: FinalityCertificate = serde_wasm_bindgen::from_value(certificate)?;
+        Ok(self.shared.client.borrow_mut().verify_certificate(&certificate)?)
+    }
+
+    /// Checks `state_getProof`'s `account` and `proof` for `address`
+    /// against the checkpoint certified at `height`; a null `account`
+    /// checks that there is none.
+    #[wasm_bindgen(js_name = verifyAccount)]
+    pub fn verify_account(&self, height: f64, address: &str, account: JsValue, proof: JsValue) -> Result<(), JsError> {
+        let account: Option<AccountState> = serde_wasm_bindgen::from_value(account)?;
+        let proof: StateProof = serde_wasm_bindgen::from_value(proof)?;
+        Ok(self.shared.client.borrow().verify_account(height as u64, address, account.as_ref(), &proof)?)
+    }
+
+    /// Checks `tx_getInclusionProof`'s `proof` for `tx_hash` against the
+    /// checkpoint certified at `height`.
+    #[wasm_bindgen(js_name = verifyTransaction)]
+    pub fn verify_transaction(&self, height: f64, tx_hash: &str, proof: JsValue) -> Result<(), JsError> {
+        let proof: InclusionProof = serde_wasm_bindgen::from_value(proof)?;
+        Ok(self.shared.client.borrow().verify_transaction(height as u64, tx_hash, &proof)?)
+    }
+}
+
+pub mod follow;
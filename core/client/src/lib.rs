@@ -0,0 +1,273 @@
+//! Typed async client for a Cubiq node: its JSON-RPC API over HTTP, and
+//! its subscriptions over WebSocket (see `subscriptions`).
+//!
+//! Results decode into the node's own types from `consensus`, the same
+//! ones it encoded them from. `Client::call` reaches any method the typed
+//! ones don't cover.
+
+use consensus::fees::FeeEstimate;
+use consensus::health::ConsensusHealth;
+use consensus::index::{Page, TxEntry};
+use consensus::logs::{Log, LogFilter};
+use consensus::receipts::Receipt;
+use consensus::rotation::KeyRotation;
+use consensus::{BlockProposal, Transaction, Validator};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+
+/// How long an HTTP call may take.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Error {
+    Http(reqwest::Error),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    /// The node answered with a JSON-RPC error
+    Rpc { method: String, code: i64, message: String },
+    /// The result is not what `method` returns
+    Decode { method: String, error: serde_json::Error },
+    /// Subscribing needs `Client::with_ws_url`
+    NoWebSocket,
+    /// The node closed the WebSocket before answering
+    Closed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "{}", e),
+            Error::WebSocket(e) => write!(f, "websocket: {}", e),
+            Error::Rpc { method, code, message } => write!(f, "{} failed ({}): {}", method, code, message),
+            Error::Decode { method, error } => write!(f, "decoding {} result: {}", method, error),
+            Error::NoWebSocket => write!(f, "no websocket URL to subscribe through"),
+            Error::Closed => write!(f, "the node closed the websocket"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(e))
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(default)]
+    id: Value,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl Response {
+    fn decode<T: DeserializeOwned>(self, method: &str) -> Result<T, Error> {
+        if let Some(RpcError { code, message }) = self.error {
+            return Err(Error::Rpc { method: method.to_string(), code, message });
+        }
+        decode(method, self.result.unwrap_or(Value::Null))
+    }
+}
+
+fn decode<T: DeserializeOwned>(method: &str, value: Value) -> Result<T, Error> {
+    serde_json::from_value(value).map_err(|error| Error::Decode { method: method.to_string(), error })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    Synced,
+    Syncing,
+}
+
+/// What `node_status` reports, less its pruning details.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeStatus {
+    pub version: String,
+    pub role: String,
+    pub chain_id: u64,
+    pub node_id: String,
+    pub sync: SyncState,
+    /// Why the node is not synced
+    pub problems: Vec<String>,
+    pub finalized_height: u64,
+    pub finalized_head: Option<String>,
+    /// Newest verified block
+    pub head: Option<String>,
+    pub head_age_secs: Option<u64>,
+    pub peer_count: usize,
+    pub mempool_size: usize,
+    pub uptime_secs: u64,
+    /// How each of the node's subsystems is doing
+    #[serde(default)]
+    pub subsystems: Vec<SubsystemHealth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SubsystemHealth {
+    pub name: String,
+    /// `running`, `restarting`, `stopped` or `failed`
+    pub state: String,
+    pub restarts: usize,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FinalizedHead {
+    pub hash: String,
+    pub height: u64,
+}
+
+/// What `validator_set` reports.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Validators {
+    /// By node id
+    pub validators: Vec<Validator>,
+    pub total_stake: u64,
+    pub supermajority_threshold: u64,
+    /// Key rotations yet to take effect, by node id
+    #[serde(default)]
+    pub pending_rotations: Vec<KeyRotation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    url: String,
+    ws_url: Option<String>,
+    token: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// A client of the JSON-RPC endpoint at `url`, such as
+    /// `http://127.0.0.1:8545`.
+    pub fn new(url: impl Into<String>) -> Result<Self, Error> {
+        let http = reqwest::Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Client { url: url.into(), ws_url: None, token: None, http })
+    }
+
+    /// Subscribes through the WebSocket endpoint at `url`, such as
+    /// `ws://127.0.0.1:8546`.
+    pub fn with_ws_url(mut self, url: impl Into<String>) -> Self {
+        self.ws_url = Some(url.into());
+        self
+    }
+
+    /// Sends `token` as a bearer token, which the node's unsafe methods
+    /// need when it has one configured.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, Error> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let mut post = self.http.post(&self.url).json(&request);
+        if let Some(token) = &self.token {
+            post = post.bearer_auth(token);
+        }
+        let response: Response = post.send().await?.error_for_status()?.json().await?;
+        response.decode(method)
+    }
+
+    pub async fn status(&self) -> Result<NodeStatus, Error> {
+        self.call("node_status", json!([])).await
+    }
+
+    /// A verified block, unless it is unknown or pruned.
+    pub async fn block(&self, hash: &str) -> Result<Option<BlockProposal>, Error> {
+        self.call("chain_getBlock", json!([hash])).await
+    }
+
+    /// The finalized block at `height`, counting from 1.
+    pub async fn block_by_height(&self, height: u64) -> Result<Option<BlockProposal>, Error> {
+        self.call("chain_getBlockByHeight", json!([height])).await
+    }
+
+    pub async fn finalized_head(&self) -> Result<Option<FinalizedHead>, Error> {
+        self.call("chain_getFinalizedHead", json!([])).await
+    }
+
+    pub async fn balance(&self, account: &str) -> Result<u64, Error> {
+        self.call("state_getBalance", json!([account])).await
+    }
+
+    /// The nonce `account`'s next transaction takes, counting those pending.
+    pub async fn nonce(&self, account: &str) -> Result<u64, Error> {
+        self.call("state_getNonce", json!([account])).await
+    }
+
+    pub async fn fee_estimate(&self) -> Result<FeeEstimate, Error> {
+        self.call("fee_estimate", json!([])).await
+    }
+
+    /// Submits a signed transaction and returns its hash.
+    pub async fn submit(&self, tx: &Transaction) -> Result<String, Error> {
+        self.call("tx_submit", json!([tx])).await
+    }
+
+    pub async fn receipt(&self, tx_hash: &str) -> Result<Option<Receipt>, Error> {
+        self.call("tx_getReceipt", json!([tx_hash])).await
+    }
+
+    pub async fn logs(&self, filter: &LogFilter) -> Result<Vec<Log>, Error> {
+        self.call("logs_get", json!([filter])).await
+    }
+
+    pub async fn validators(&self) -> Result<Validators, Error> {
+        self.call("validator_set", json!([])).await
+    }
+
+    pub async fn consensus_health(&self) -> Result<ConsensusHealth, Error> {
+        self.call("consensus_health", json!([])).await
+    }
+
+    /// `address`'s finalized transactions, newest first, from before the
+    /// cursor `before` when given.
+    pub async fn transactions_by_address(&self, address: &str, before: Option<u64>, limit: Option<usize>) -> Result<Page<TxEntry>, Error> {
+        self.call("index_getTransactionsByAddress", json!([address, { "cursor": before, "limit": limit }])).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_decode_to_results_or_errors() {
+        let response: Response = serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"result":{"hash":"0xab","height":3}}"#).unwrap();
+        let head: Option<FinalizedHead> = response.decode("chain_getFinalizedHead").unwrap();
+        assert_eq!(head, Some(FinalizedHead { hash: "0xab".to_string(), height: 3 }));
+
+        let response: Response = serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"result":null}"#).unwrap();
+        assert_eq!(response.decode::<Option<FinalizedHead>>("chain_getFinalizedHead").unwrap(), None);
+
+        let error = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"missing param account"}}"#;
+        let response: Response = serde_json::from_str(error).unwrap();
+        let error = response.decode::<u64>("state_getBalance").unwrap_err();
+        assert!(matches!(error, Error::Rpc { code: -32602, .. }));
+        assert_eq!(error.to_string(), "state_getBalance failed (-32602): missing param account");
+
+        let response: Response = serde_json::from_str(r#"{"jsonrpc":"2.0","id":1,"result":"many"}"#).unwrap();
+        assert!(matches!(response.decode::<u64>("state_getBalance"), Err(Error::Decode { .. })));
+    }
+}
+
+pub mod staking;
+pub mod subscriptions;
@@ -0,0 +1,7 @@
+//! Staking, through transactions to `validator_registry()` that carry a
+//! `RegistryCall` as JSON in their data. A registration or a delegation
+//! bonds the transaction's value to a validator; an undelegation asks for
+//! an amount bonded to one back. See `consensus::staking` for what the
+//! chain does with them.
+
+pub use consensus::staking::{bond_address, validator_registry, RegistryCall};
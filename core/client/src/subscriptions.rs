@@ -0,0 +1,168 @@
+//! Subscriptions over the node's WebSocket endpoint. Each holds its own
+//! connection, which closes when it is dropped.
+//!
+//! A subscription that falls behind the node's events ends with an
+//! error; catch up over RPC, then subscribe again.
+
+use consensus::events::{BlockHeader, TxStatus};
+use consensus::logs::{Log, LogFilter};
+use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::marker::PhantomData;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::{decode, Client, Error, Response, RpcError};
+
+const SUBSCRIBE_ID: u64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Finalized {
+    pub block_hash: String,
+    pub height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TxStatusUpdate {
+    pub status: TxStatus,
+    pub block_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Notification {
+    params: NotificationParams,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    subscription: u64,
+    result: Option<Value>,
+    error: Option<RpcError>,
+}
+
+/// The event in `text`, if it is a notification of subscription `id`,
+/// which ends it when it is an error.
+fn notification<T: DeserializeOwned>(text: &str, id: u64, method: &str) -> Option<Result<T, Error>> {
+    let notification: Notification = serde_json::from_str(text).ok()?;
+    let NotificationParams { subscription, result, error } = notification.params;
+    if subscription != id {
+        return None;
+    }
+    if let Some(RpcError { code, message }) = error {
+        return Some(Err(Error::Rpc { method: method.to_string(), code, message }));
+    }
+    Some(decode(method, result.unwrap_or(Value::Null)))
+}
+
+/// Events of one kind, as `T`.
+pub struct Subscription<T> {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    id: u64,
+    method: String,
+    ended: bool,
+    events: PhantomData<fn() -> T>,
+}
+
+impl Client {
+    /// Headers of blocks as they pass verification.
+    pub async fn subscribe_new_heads(&self) -> Result<Subscription<BlockHeader>, Error> {
+        self.subscribe("subscribe_newHeads", json!([])).await
+    }
+
+    pub async fn subscribe_finalized(&self) -> Result<Subscription<Finalized>, Error> {
+        self.subscribe("subscribe_finalized", json!([])).await
+    }
+
+    /// How the transaction `tx_hash` moves from pending to finalized or
+    /// dropped.
+    pub async fn subscribe_tx_status(&self, tx_hash: &str) -> Result<Subscription<TxStatusUpdate>, Error> {
+        self.subscribe("subscribe_txStatus", json!([tx_hash])).await
+    }
+
+    /// Each verified block's logs matching `filter`, together.
+    pub async fn subscribe_logs(&self, filter: &LogFilter) -> Result<Subscription<Vec<Log>>, Error> {
+        self.subscribe("subscribe_logs", json!([filter])).await
+    }
+
+    pub async fn subscribe<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<Subscription<T>, Error> {
+        let mut request = self.ws_url.as_deref().ok_or(Error::NoWebSocket)?.into_client_request()?;
+        if let Some(token) = &self.token {
+            let bearer = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(tungstenite::Error::from)?;
+            request.headers_mut().insert(AUTHORIZATION, bearer);
+        }
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+        let subscribe = json!({ "jsonrpc": "2.0", "id": SUBSCRIBE_ID, "method": method, "params": params });
+        socket.send(Message::Text(subscribe.to_string())).await?;
+        while let Some(message) = socket.next().await {
+            let Message::Text(text) = message? else {
+                continue;
+            };
+            let Ok(response) = serde_json::from_str::<Response>(&text) else {
+                continue;
+            };
+            if response.id == json!(SUBSCRIBE_ID) {
+                let id = response.decode(method)?;
+                return Ok(Subscription { socket, id, method: method.to_string(), ended: false, events: PhantomData });
+            }
+        }
+        Err(Error::Closed)
+    }
+}
+
+impl<T: DeserializeOwned> Subscription<T> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The next event, or `None` once the subscription has ended.
+    pub async fn next(&mut self) -> Option<Result<T, Error>> {
+        while !self.ended {
+            let text = match self.socket.next().await? {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => break,
+                Ok(_) => continue,
+                Err(e) => {
+                    self.ended = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            if let Some(event) = notification(&text, self.id, &self.method) {
+                self.ended = matches!(event, Err(Error::Rpc { .. }));
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    /// Ends the subscription and closes its connection.
+    pub async fn unsubscribe(mut self) -> Result<(), Error> {
+        let unsubscribe = json!({ "jsonrpc": "2.0", "id": SUBSCRIBE_ID + 1, "method": "unsubscribe", "params": [self.id] });
+        self.socket.send(Message::Text(unsubscribe.to_string())).await?;
+        self.socket.close(None).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notifications_decode_for_their_subscription_only() {
+        let finalized = r#"{"jsonrpc":"2.0","method":"subscription","params":{"subscription":3,"result":{"block_hash":"0xab","height":9}}}"#;
+        let event = notification::<Finalized>(finalized, 3, "subscribe_finalized").unwrap().unwrap();
+        assert_eq!(event, Finalized { block_hash: "0xab".to_string(), height: 9 });
+        assert!(notification::<Finalized>(finalized, 4, "subscribe_finalized").is_none());
+        assert!(notification::<Finalized>(r#"{"jsonrpc":"2.0","id":2,"result":true}"#, 3, "subscribe_finalized").is_none());
+
+        let lagged = r#"{"method":"subscription","params":{"subscription":3,"error":{"code":-32000,"message":"missed 2 events; resubscribe"}}}"#;
+        let error = notification::<Finalized>(lagged, 3, "subscribe_finalized").unwrap().unwrap_err();
+        assert!(error.to_string().contains("resubscribe"));
+    }
+}
@@ -0,0 +1,209 @@
+//! Account addresses.
+//!
+//! An account is named by an `Address`: 32 bytes written in bech32m
+//! (BIP-350) after the human-readable part `cubiq`, such as
+//! `cubiq1qyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqskx33z6`.
+//! The checksum catches any mistyped character and most longer slips, so
+//! a typo fails to parse rather than naming some other account.
+//!
+//! The account of an ed25519 key is its public key. Accounts no key
+//! holds, such as contracts', are hashes (`Address::derive`). EVM
+//! contracts name accounts as Ethereum does, `0x` then 20 hex-encoded
+//! bytes; `check_account` accepts those too.
+
+use bech32::primitives::decode::{CharError, CheckedHrpstring, CheckedHrpstringError, ChecksumError, UncheckedHrpstringError};
+use bech32::{Bech32m, Hrp};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// The human-readable part of every address.
+pub const HRP: &str = "cubiq";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address([u8; 32]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// Not bech32 at all
+    Malformed(String),
+    /// A character is mistyped, or one is missing or extra
+    Checksum,
+    /// Bech32, but another chain's or program's
+    Prefix(String),
+    /// Not the 32 bytes an address holds
+    Length(usize),
+    /// Names an account no ed25519 key holds
+    NotAKey,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::Malformed(why) => write!(f, "not an address: {}", why),
+            AddressError::Checksum => write!(f, "address checksum does not match; check it for a mistyped character"),
+            AddressError::Prefix(hrp) => write!(f, "address starts {}1, not {}1", hrp, HRP),
+            AddressError::Length(len) => write!(f, "address holds {} bytes, not 32", len),
+            AddressError::NotAKey => write!(f, "address is not an ed25519 key's, so signs nothing"),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl From<CheckedHrpstringError> for AddressError {
+    fn from(e: CheckedHrpstringError) -> Self {
+        let why = match e {
+            CheckedHrpstringError::Checksum(ChecksumError::InvalidResidue) => return AddressError::Checksum,
+            CheckedHrpstringError::Checksum(e) => e.to_string(),
+            CheckedHrpstringError::Parse(UncheckedHrpstringError::Char(CharError::MissingSeparator)) => {
+                format!("no '1' after a prefix such as {}", HRP)
+            }
+            CheckedHrpstringError::Parse(UncheckedHrpstringError::Char(CharError::InvalidChar(c))) => {
+                format!("'{}' is not a bech32 character, which leaves out 1, b, i and o after the prefix", c)
+            }
+            e => e.to_string(),
+        };
+        AddressError::Malformed(why)
+    }
+}
+
+impl Address {
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Address(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The account of the holder of `key`.
+    pub fn from_key(key: &VerifyingKey) -> Self {
+        Address(key.to_bytes())
+    }
+
+    /// The account of the holder of the hex-encoded ed25519 `public_key`.
+    pub fn from_public_key(public_key: &str) -> Result<Self, AddressError> {
+        hex::decode(public_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Address)
+            .ok_or_else(|| AddressError::Malformed("not a hex-encoded ed25519 public key".to_string()))
+    }
+
+    /// An account no key holds, hashed from `seed` under `domain`, which
+    /// keeps different kinds of derived account apart.
+    pub fn derive(domain: &[u8], seed: &[u8]) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(domain).update(seed);
+        Address(*hasher.finalize().as_bytes())
+    }
+
+    /// The key that signs for the account, unless it is a derived one.
+    pub fn verifying_key(&self) -> Result<VerifyingKey, AddressError> {
+        VerifyingKey::from_bytes(&self.0).map_err(|_| AddressError::NotAKey)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded = bech32::encode::<Bech32m>(Hrp::parse_unchecked(HRP), &self.0).expect("addresses fit bech32");
+        f.write_str(&encoded)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, AddressError> {
+        let checked = CheckedHrpstring::new::<Bech32m>(s)?;
+        let hrp = checked.hrp().to_lowercase();
+        if hrp != HRP {
+            return Err(AddressError::Prefix(hrp));
+        }
+        let bytes: Vec<u8> = checked.byte_iter().collect();
+        let len = bytes.len();
+        Ok(Address(bytes.try_into().map_err(|_| AddressError::Length(len))?))
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Whether `account` is written as an EVM account, `0x` then 20
+/// hex-encoded bytes.
+pub fn is_evm_account(account: &str) -> bool {
+    account.strip_prefix("0x").is_some_and(|hex| hex.len() == 40 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Checks that `account` names an account: an address, or an EVM account.
+pub fn check_account(account: &str) -> Result<(), AddressError> {
+    if is_evm_account(account) {
+        return Ok(());
+    }
+    account.parse::<Address>().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn addresses_round_trip_through_bech32m() {
+        let key = SigningKey::from_bytes(&[5; 32]).verifying_key();
+        let address = Address::from_key(&key);
+        let text = address.to_string();
+        assert!(text.starts_with("cubiq1"));
+        assert_eq!(text.len(), HRP.len() + 1 + 52 + 6);
+        assert_eq!(text.parse::<Address>().unwrap(), address);
+        assert_eq!(text.to_uppercase().parse::<Address>().unwrap(), address);
+        assert_eq!(address.verifying_key().unwrap(), key);
+        assert_eq!(Address::from_public_key(&hex::encode(key.to_bytes())).unwrap(), address);
+        assert_eq!(serde_json::to_string(&address).unwrap(), format!("\"{}\"", text));
+        assert_eq!(serde_json::from_str::<Address>(&format!("\"{}\"", text)).unwrap(), address);
+    }
+
+    #[test]
+    fn typos_fail_to_parse() {
+        let text = Address::from_bytes([1; 32]).to_string();
+        assert_eq!(text, "cubiq1qyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqskx33z6");
+        for i in HRP.len() + 1..text.len() {
+            let mut typo = text.clone().into_bytes();
+            typo[i] = if typo[i] == b'q' { b'p' } else { b'q' };
+            let typo = String::from_utf8(typo).unwrap();
+            assert_eq!(typo.parse::<Address>(), Err(AddressError::Checksum), "{}", typo);
+        }
+        let mut swapped = text.clone().into_bytes();
+        swapped.swap(10, 11);
+        assert!(String::from_utf8(swapped).unwrap().parse::<Address>().is_err());
+        assert!(matches!(text.replace("cubiq1", "cubiqb").parse::<Address>(), Err(AddressError::Malformed(_))));
+        assert!(text.replace('s', "b").parse::<Address>().unwrap_err().to_string().contains("'b'"));
+        assert!(matches!(text[..text.len() - 1].parse::<Address>(), Err(AddressError::Checksum)));
+
+        let other = bech32::encode::<Bech32m>(Hrp::parse("cosmos").unwrap(), &[1; 32]).unwrap();
+        assert_eq!(other.parse::<Address>(), Err(AddressError::Prefix("cosmos".to_string())));
+        let short = bech32::encode::<Bech32m>(Hrp::parse(HRP).unwrap(), &[1; 20]).unwrap();
+        assert_eq!(short.parse::<Address>(), Err(AddressError::Length(20)));
+    }
+
+    #[test]
+    fn accounts_are_addresses_or_evm_accounts() {
+        assert!(check_account(&Address::derive(b"test", b"seed").to_string()).is_ok());
+        assert!(check_account("0x00000000000000000000000000000000000000aa").is_ok());
+        assert!(check_account("0xbob").is_err());
+        assert!(check_account("").is_err());
+        assert_eq!(Address::derive(b"a", b"b"), Address::derive(b"a", b"b"));
+        assert_ne!(Address::derive(b"a", b"b"), Address::derive(b"b", b"b"));
+    }
+}
@@ -0,0 +1,629 @@
+//! An IBC-style bridge to other chains: light clients of them, channels
+//! over those clients, and transfers of Cubiq's token as packets between
+//! channels.
+//!
+//! Everything the bridge keeps is a `Record`, held by the account
+//! `path_address` derives from its path, so a block's `state_root`, and
+//! `StateProof`s against it, cover it:
+//!
+//! | path                                  | record                                              |
+//! |---------------------------------------|-----------------------------------------------------|
+//! | `clients/{client}`                    | the client's state                                  |
+//! | `clients/{client}/consensus/{height}` | what it trusts of the other chain at `height`       |
+//! | `channels/{channel}`                  | the channel                                         |
+//! | `commitments/{channel}/{sequence}`    | a sent packet's commitment, until acknowledged      |
+//! | `receipts/{channel}/{sequence}`       | that a packet was received                          |
+//! | `acks/{channel}/{sequence}`           | a received packet's acknowledgement's commitment    |
+//!
+//! The other chain keeps commitments and acknowledgements at the same
+//! paths, as their 32 bytes, and a `CommitmentProof` shows one is in its
+//! state at a height the client trusts.
+//!
+//! A `TxKind::Bridge` transaction's `data` is a `Msg` as JSON. Clients
+//! and channels are permissionless and named by whoever creates them. A
+//! channel is bound to its client and the other chain's channel without a
+//! handshake; each channel escrows what is sent over it in its own
+//! account, `escrow_address`, so a channel over a client of a chain that
+//! doesn't exist risks only what senders choose to send over it.
+//!
+//! A `Msg::Transfer` escrows `value` and commits a `Packet` sending it to
+//! a receiver on the other chain, which mints vouchers for it. Relayers
+//! find packets in `bridge_send_packet` events, prove them on the other
+//! chain, and bring back its acknowledgement with `Msg::Acknowledge`;
+//! an error acknowledgement refunds the sender. Packets from the other
+//! chain are received with `Msg::RecvPacket`: vouchers coming back, with
+//! the denomination `{channel}/cubiq` for the other chain's end of the
+//! channel, release as much from escrow to the receiver. Anything else,
+//! and a packet received after its `timeout_timestamp`, is acknowledged
+//! with an error, written in a `bridge_write_ack` event for relayers to
+//! take back. Packets are not timed out by proving they were never
+//! received; the receiving chain acknowledges late ones with an error
+//! instead.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::address::{self, Address};
+use crate::execution::{self, BlockEnv, GasMeter, GAS_SCHEDULE};
+use crate::receipts::TxEvent;
+use crate::state::{Account, Hash, StateTrie};
+use crate::tendermint;
+use crate::Transaction;
+
+/// Prefixes a record's path when deriving the account holding it.
+const PATH_DOMAIN: &[u8] = b"cubiq-bridge-path-v1";
+
+/// Prefixes a channel's id when deriving its escrow account.
+const ESCROW_DOMAIN: &[u8] = b"cubiq-bridge-escrow-v1";
+
+/// Prefixes a packet's encoding when hashing its commitment.
+const PACKET_DOMAIN: &[u8] = b"cubiq-packet-v1";
+
+/// Prefixes an acknowledgement's encoding when hashing its commitment.
+const ACK_DOMAIN: &[u8] = b"cubiq-ack-v1";
+
+/// Denomination of Cubiq's token in the packets that send it.
+pub const NATIVE_DENOM: &str = "cubiq";
+
+/// Longest client or channel id.
+pub const MAX_ID_LEN: usize = 64;
+
+/// A light client of another chain, by the kind of chain it follows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientState {
+    Tendermint(tendermint::ClientState),
+}
+
+/// What a client trusts of one of the other chain's headers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusState {
+    Tendermint(tendermint::ConsensusState),
+}
+
+/// A header for a client to trust, of the client's kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientUpdate {
+    Tendermint(tendermint::Update),
+}
+
+/// Proof that the other chain holds a value at a path, of the client's
+/// kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitmentProof {
+    Tendermint(tendermint::MembershipProof),
+}
+
+impl ClientState {
+    pub fn latest_height(&self) -> u64 {
+        match self {
+            ClientState::Tendermint(client) => client.latest_height,
+        }
+    }
+
+    /// The height of `update`'s header and what to trust of it, checked
+    /// against `trusted` at Unix time `now`.
+    fn verify_update(&self, trusted: &ConsensusState, update: &ClientUpdate, now: u64) -> Result<(u64, ConsensusState), String> {
+        match (self, trusted, update) {
+            (ClientState::Tendermint(client), ConsensusState::Tendermint(trusted), ClientUpdate::Tendermint(update)) => {
+                let consensus = client.verify(trusted, update, now)?;
+                Ok((update.signed_header.header.height, ConsensusState::Tendermint(consensus)))
+            }
+        }
+    }
+
+    fn with_latest_height(&self, height: u64) -> ClientState {
+        match self {
+            ClientState::Tendermint(client) => ClientState::Tendermint(tendermint::ClientState { latest_height: height, ..client.clone() }),
+        }
+    }
+
+    /// Checks that the other chain, in `consensus`, holds `value` at
+    /// `path`.
+    fn verify_membership(&self, consensus: &ConsensusState, proof: &CommitmentProof, path: &str, value: &Hash) -> Result<(), String> {
+        match (self, consensus, proof) {
+            (ClientState::Tendermint(_), ConsensusState::Tendermint(consensus), CommitmentProof::Tendermint(proof)) => {
+                if !proof.verify(&consensus.app_hash, path.as_bytes(), &value.0) {
+                    return Err(format!("proof does not show {} at {}", value, path));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ClientUpdate {
+    fn trusted_height(&self) -> u64 {
+        match self {
+            ClientUpdate::Tendermint(update) => update.trusted_height,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Channel {
+    pub client_id: String,
+    /// The other chain's end of the channel
+    pub counterparty_channel_id: String,
+    /// Sequence of the next packet sent, from 1
+    pub next_sequence: u64,
+}
+
+/// Tokens sent over a channel, as ICS-20 describes them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferData {
+    pub denom: String,
+    pub amount: u64,
+    pub sender: String,
+    pub receiver: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Packet {
+    pub sequence: u64,
+    pub source_channel: String,
+    pub destination_channel: String,
+    pub data: TransferData,
+    /// Unix seconds by the receiving chain's blocks after which the
+    /// packet is refused; 0 for never
+    pub timeout_timestamp: u64,
+}
+
+impl Packet {
+    /// What the sending chain keeps at the packet's `commitments` path:
+    /// blake3 of `PACKET_DOMAIN` and the packet's bincode encoding.
+    pub fn commitment(&self) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(PACKET_DOMAIN).update(&bincode::serialize(self).expect("packets encode"));
+        Hash(*hasher.finalize().as_bytes())
+    }
+}
+
+/// The receiving chain's answer to a packet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Acknowledgement {
+    Success,
+    /// Why the packet was refused; its tokens go back to the sender
+    Error(String),
+}
+
+impl Acknowledgement {
+    /// What the receiving chain keeps at the packet's `acks` path, as
+    /// `Packet::commitment` with `ACK_DOMAIN`.
+    pub fn commitment(&self) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(ACK_DOMAIN).update(&bincode::serialize(self).expect("acknowledgements encode"));
+        Hash(*hasher.finalize().as_bytes())
+    }
+}
+
+/// What the bridge keeps at a path; see the table above.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Record {
+    Client(ClientState),
+    Consensus(ConsensusState),
+    Channel(Channel),
+    Commitment(Hash),
+    Receipt,
+    Acknowledgement(Hash),
+}
+
+impl Record {
+    /// What the record's account commits to in the state trie: blake3 of
+    /// its bincode encoding.
+    pub fn hash(&self) -> Hash {
+        Hash(*blake3::hash(&bincode::serialize(self).expect("records encode")).as_bytes())
+    }
+}
+
+/// What a `TxKind::Bridge` transaction does.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Msg {
+    /// Creates a client trusting `consensus` at the client's latest
+    /// height
+    CreateClient { client_id: String, client: ClientState, consensus: ConsensusState },
+    UpdateClient { client_id: String, update: ClientUpdate },
+    OpenChannel { channel_id: String, client_id: String, counterparty_channel_id: String },
+    /// Sends the transaction's `value` to `receiver` on the other chain
+    Transfer { channel_id: String, receiver: String, timeout_timestamp: u64 },
+    /// Receives a packet the other chain committed, as of a height the
+    /// client trusts
+    RecvPacket { packet: Packet, proof: CommitmentProof, proof_height: u64 },
+    /// Settles a sent packet with the other chain's acknowledgement of it
+    Acknowledge { packet: Packet, acknowledgement: Acknowledgement, proof: CommitmentProof, proof_height: u64 },
+}
+
+pub fn client_path(client_id: &str) -> String {
+    format!("clients/{}", client_id)
+}
+
+pub fn consensus_path(client_id: &str, height: u64) -> String {
+    format!("clients/{}/consensus/{}", client_id, height)
+}
+
+pub fn channel_path(channel_id: &str) -> String {
+    format!("channels/{}", channel_id)
+}
+
+pub fn commitment_path(channel_id: &str, sequence: u64) -> String {
+    format!("commitments/{}/{}", channel_id, sequence)
+}
+
+pub fn receipt_path(channel_id: &str, sequence: u64) -> String {
+    format!("receipts/{}/{}", channel_id, sequence)
+}
+
+pub fn ack_path(channel_id: &str, sequence: u64) -> String {
+    format!("acks/{}/{}", channel_id, sequence)
+}
+
+/// The account holding the record at `path`, which no key holds.
+pub fn path_address(path: &str) -> String {
+    Address::derive(PATH_DOMAIN, path.as_bytes()).to_string()
+}
+
+/// The account holding what was sent over `channel_id`.
+pub fn escrow_address(channel_id: &str) -> String {
+    Address::derive(ESCROW_DOMAIN, channel_id.as_bytes()).to_string()
+}
+
+/// Ids are up to `MAX_ID_LEN` letters, digits, `-`, `_` and `.`, so
+/// never split a path.
+fn check_id(id: &str) -> Result<(), String> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if id.is_empty() || id.len() > MAX_ID_LEN || !id.chars().all(allowed) {
+        return Err(format!("{:?} is not an id of up to {} letters, digits, '-', '_' and '.'", id, MAX_ID_LEN));
+    }
+    Ok(())
+}
+
+/// The records and balances a message reads and writes, over the block's
+/// changes so far, metered as contract storage is.
+struct Store<'a> {
+    state: &'a StateTrie,
+    changes: &'a BTreeMap<String, Account>,
+    meter: &'a mut GasMeter,
+    written: BTreeMap<String, Account>,
+}
+
+impl Store<'_> {
+    fn account(&self, address: &str) -> Account {
+        self.written.get(address).cloned().unwrap_or_else(|| execution::account(self.state, self.changes, address))
+    }
+
+    fn get(&mut self, path: &str) -> Result<Option<Record>, String> {
+        self.meter.charge(GAS_SCHEDULE.storage_read)?;
+        Ok(self.account(&path_address(path)).bridge)
+    }
+
+    /// Sets the record at `path`, or removes it.
+    fn set(&mut self, path: &str, record: Option<Record>) -> Result<(), String> {
+        self.meter.charge(GAS_SCHEDULE.storage_write)?;
+        let address = path_address(path);
+        let account = Account { bridge: record, ..self.account(&address) };
+        self.written.insert(address, account);
+        Ok(())
+    }
+
+    fn client(&mut self, client_id: &str) -> Result<ClientState, String> {
+        match self.get(&client_path(client_id))? {
+            Some(Record::Client(client)) => Ok(client),
+            _ => Err(format!("there is no client {}", client_id)),
+        }
+    }
+
+    fn consensus(&mut self, client_id: &str, height: u64) -> Result<ConsensusState, String> {
+        match self.get(&consensus_path(client_id, height))? {
+            Some(Record::Consensus(consensus)) => Ok(consensus),
+            _ => Err(format!("client {} trusts no header at {}", client_id, height)),
+        }
+    }
+
+    fn channel(&mut self, channel_id: &str) -> Result<Channel, String> {
+        match self.get(&channel_path(channel_id))? {
+            Some(Record::Channel(channel)) => Ok(channel),
+            _ => Err(format!("there is no channel {}", channel_id)),
+        }
+    }
+
+    /// Checks that the other end of `channel` committed `value` at `path`
+    /// as of `height`.
+    fn verify(&mut self, channel: &Channel, height: u64, proof: &CommitmentProof, path: &str, value: &Hash) -> Result<(), String> {
+        let client = self.client(&channel.client_id)?;
+        let consensus = self.consensus(&channel.client_id, height)?;
+        client.verify_membership(&consensus, proof, path, value)
+    }
+
+    fn send(&mut self, from: &str, to: &str, value: u64) -> Result<(), String> {
+        let sender = self.account(from);
+        let balance = sender.balance.checked_sub(value).ok_or_else(|| format!("{} has {}, not {}", from, sender.balance, value))?;
+        if from == to {
+            return Ok(());
+        }
+        let recipient = self.account(to);
+        let credited = recipient.balance.checked_add(value).ok_or("recipient balance overflows")?;
+        self.written.insert(from.to_string(), Account { balance, ..sender });
+        self.written.insert(to.to_string(), Account { balance: credited, ..recipient });
+        Ok(())
+    }
+}
+
+fn event<const N: usize>(kind: &str, attributes: [(&str, String); N]) -> TxEvent {
+    TxEvent { kind: kind.to_string(), attributes: attributes.map(|(key, value)| (key.to_string(), value)).into() }
+}
+
+/// Carries out the `Msg` in `tx.data` over `changes`, which are left
+/// untouched if it fails.
+pub(crate) fn execute(
+    state: &StateTrie,
+    env: &BlockEnv,
+    changes: &mut BTreeMap<String, Account>,
+    meter: &mut GasMeter,
+    tx: &Transaction,
+) -> Result<Vec<TxEvent>, String> {
+    let msg: Msg = serde_json::from_slice(&tx.data).map_err(|e| format!("data is not a bridge message: {}", e))?;
+    if tx.value != 0 && !matches!(msg, Msg::Transfer { .. }) {
+        return Err("only a bridge transfer sends value".to_string());
+    }
+    let mut store = Store { state, changes, meter, written: BTreeMap::new() };
+    let events = match msg {
+        Msg::CreateClient { client_id, client, consensus } => create_client(&mut store, &client_id, client, consensus)?,
+        Msg::UpdateClient { client_id, update } => update_client(&mut store, env, &client_id, &update)?,
+        Msg::OpenChannel { channel_id, client_id, counterparty_channel_id } => {
+            open_channel(&mut store, &channel_id, client_id, counterparty_channel_id)?
+        }
+        Msg::Transfer { channel_id, receiver, timeout_timestamp } => transfer(&mut store, tx, &channel_id, receiver, timeout_timestamp)?,
+        Msg::RecvPacket { packet, proof, proof_height } => recv_packet(&mut store, env, &packet, &proof, proof_height)?,
+        Msg::Acknowledge { packet, acknowledgement, proof, proof_height } => {
+            acknowledge(&mut store, &packet, &acknowledgement, &proof, proof_height)?
+        }
+    };
+    let written = store.written;
+    changes.extend(written);
+    Ok(events)
+}
+
+fn create_client(store: &mut Store, client_id: &str, client: ClientState, consensus: ConsensusState) -> Result<Vec<TxEvent>, String> {
+    check_id(client_id)?;
+    if store.get(&client_path(client_id))?.is_some() {
+        return Err(format!("client {} already exists", client_id));
+    }
+    let height = client.latest_height();
+    store.set(&client_path(client_id), Some(Record::Client(client)))?;
+    store.set(&consensus_path(client_id, height), Some(Record::Consensus(consensus)))?;
+    Ok(vec![event("bridge_create_client", [("client_id", client_id.to_string()), ("height", height.to_string())])])
+}
+
+fn update_client(store: &mut Store, env: &BlockEnv, client_id: &str, update: &ClientUpdate) -> Result<Vec<TxEvent>, String> {
+    let client = store.client(client_id)?;
+    let trusted = store.consensus(client_id, update.trusted_height())?;
+    let (height, consensus) = client.verify_update(&trusted, update, env.timestamp)?;
+    match store.get(&consensus_path(client_id, height))? {
+        Some(Record::Consensus(existing)) if existing != consensus => {
+            return Err(format!("header conflicts with the one client {} trusts at {}", client_id, height));
+        }
+        Some(_) => return Err(format!("client {} already trusts the header at {}", client_id, height)),
+        None => {}
+    }
+    store.set(&consensus_path(client_id, height), Some(Record::Consensus(consensus)))?;
+    if height > client.latest_height() {
+        store.set(&client_path(client_id), Some(Record::Client(client.with_latest_height(height))))?;
+    }
+    Ok(vec![event("bridge_update_client", [("client_id", client_id.to_string()), ("height", height.to_string())])])
+}
+
+fn open_channel(store: &mut Store, channel_id: &str, client_id: String, counterparty_channel_id: String) -> Result<Vec<TxEvent>, String> {
+    check_id(channel_id)?;
+    check_id(&counterparty_channel_id)?;
+    if store.get(&channel_path(channel_id))?.is_some() {
+        return Err(format!("channel {} already exists", channel_id));
+    }
+    store.client(&client_id)?;
+    let attributes = [
+        ("channel_id", channel_id.to_string()),
+        ("client_id", client_id.clone()),
+        ("counterparty_channel_id", counterparty_channel_id.clone()),
+    ];
+    let channel = Channel { client_id, counterparty_channel_id, next_sequence: 1 };
+    store.set(&channel_path(channel_id), Some(Record::Channel(channel)))?;
+    Ok(vec![event("bridge_open_channel", attributes)])
+}
+
+fn transfer(store: &mut Store, tx: &Transaction, channel_id: &str, receiver: String, timeout_timestamp: u64) -> Result<Vec<TxEvent>, String> {
+    let mut channel = store.channel(channel_id)?;
+    if tx.value == 0 {
+        return Err("transfers nothing".to_string());
+    }
+    if receiver.is_empty() || receiver.len() > cubiq_types::MAX_STRING_BYTES {
+        return Err(format!("receiver must be 1 to {} bytes", cubiq_types::MAX_STRING_BYTES));
+    }
+    let packet = Packet {
+        sequence: channel.next_sequence,
+        source_channel: channel_id.to_string(),
+        destination_channel: channel.counterparty_channel_id.clone(),
+        data: TransferData { denom: NATIVE_DENOM.to_string(), amount: tx.value, sender: tx.from.clone(), receiver },
+        timeout_timestamp,
+    };
+    store.send(&tx.from, &escrow_address(channel_id), tx.value).map_err(|e| format!("insufficient balance: {}", e))?;
+    channel.next_sequence += 1;
+    store.set(&channel_path(channel_id), Some(Record::Channel(channel)))?;
+    store.set(&commitment_path(channel_id, packet.sequence), Some(Record::Commitment(packet.commitment())))?;
+    let packet_json = serde_json::to_string(&packet).expect("packets serialize");
+    Ok(vec![event(
+        "bridge_send_packet",
+        [("channel_id", channel_id.to_string()), ("sequence", packet.sequence.to_string()), ("packet", packet_json)],
+    )])
+}
+
+fn recv_packet(store: &mut Store, env: &BlockEnv, packet: &Packet, proof: &CommitmentProof, proof_height: u64) -> Result<Vec<TxEvent>, String> {
+    let channel = store.channel(&packet.destination_channel)?;
+    if channel.counterparty_channel_id != packet.source_channel {
+        return Err(format!(
+            "packet is from channel {}, but channel {} is bound to {}",
+            packet.source_channel, packet.destination_channel, channel.counterparty_channel_id
+        ));
+    }
+    let path = commitment_path(&packet.source_channel, packet.sequence);
+    store.verify(&channel, proof_height, proof, &path, &packet.commitment())?;
+    let receipt = receipt_path(&packet.destination_channel, packet.sequence);
+    if store.get(&receipt)?.is_some() {
+        return Err(format!("packet {} on channel {} was already received", packet.sequence, packet.destination_channel));
+    }
+    store.set(&receipt, Some(Record::Receipt))?;
+    let acknowledgement = if packet.timeout_timestamp != 0 && env.timestamp > packet.timeout_timestamp {
+        Acknowledgement::Error(format!("timed out at {}", packet.timeout_timestamp))
+    } else {
+        match deliver(store, packet) {
+            Ok(()) => Acknowledgement::Success,
+            Err(e) => Acknowledgement::Error(e),
+        }
+    };
+    let commitment = acknowledgement.commitment();
+    store.set(&ack_path(&packet.destination_channel, packet.sequence), Some(Record::Acknowledgement(commitment)))?;
+    let attributes = [
+        ("channel_id", packet.destination_channel.clone()),
+        ("sequence", packet.sequence.to_string()),
+        ("packet", serde_json::to_string(packet).expect("packets serialize")),
+        ("acknowledgement", serde_json::to_string(&acknowledgement).expect("acknowledgements serialize")),
+    ];
+    Ok(vec![event("bridge_write_ack", attributes)])
+}
+
+/// Releases the tokens `packet` brings back from escrow, or says why
+/// they are refused.
+fn deliver(store: &mut Store, packet: &Packet) -> Result<(), String> {
+    let returning = format!("{}/{}", packet.source_channel, NATIVE_DENOM);
+    if packet.data.denom != returning {
+        return Err(format!("only {} is received over this channel, not {}", returning, packet.data.denom));
+    }
+    address::check_account(&packet.data.receiver).map_err(|e| format!("receiver: {}", e))?;
+    store.send(&escrow_address(&packet.destination_channel), &packet.data.receiver, packet.data.amount)
+}
+
+fn acknowledge(
+    store: &mut Store,
+    packet: &Packet,
+    acknowledgement: &Acknowledgement,
+    proof: &CommitmentProof,
+    proof_height: u64,
+) -> Result<Vec<TxEvent>, String> {
+    let channel = store.channel(&packet.source_channel)?;
+    let commitment = commitment_path(&packet.source_channel, packet.sequence);
+    if store.get(&commitment)? != Some(Record::Commitment(packet.commitment())) {
+        return Err(format!("packet {} on channel {} is not awaiting acknowledgement", packet.sequence, packet.source_channel));
+    }
+    let path = ack_path(&packet.destination_channel, packet.sequence);
+    store.verify(&channel, proof_height, proof, &path, &acknowledgement.commitment())?;
+    store.set(&commitment, None)?;
+    if let Acknowledgement::Error(_) = acknowledgement {
+        store.send(&escrow_address(&packet.source_channel), &packet.data.sender, packet.data.amount)?;
+    }
+    let attributes = [
+        ("channel_id", packet.source_channel.clone()),
+        ("sequence", packet.sequence.to_string()),
+        ("success", (*acknowledgement == Acknowledgement::Success).to_string()),
+    ];
+    Ok(vec![event("bridge_acknowledge", attributes)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::Vm;
+    use crate::tendermint::tests::{signed_header, validators};
+    use crate::TxKind;
+
+    const ALICE: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+    const BOB: &str = "cubiq1enxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxqz740yr";
+
+    fn env(timestamp: u64) -> BlockEnv {
+        BlockEnv { chain_id: 7, gas_limit: 10_000_000, base_fee: 1, proposer: None, vm: Vm::Wasm, timestamp }
+    }
+
+    fn run(state: &mut StateTrie, timestamp: u64, value: u64, msg: &Msg) -> Result<Vec<TxEvent>, String> {
+        let tx = Transaction { from: ALICE.to_string(), value, kind: TxKind::Bridge, data: serde_json::to_vec(msg).unwrap(), ..Transaction::default() };
+        let mut changes = BTreeMap::new();
+        let events = execute(state, &env(timestamp), &mut changes, &mut GasMeter::new(1_000_000), &tx)?;
+        state.commit(changes);
+        Ok(events)
+    }
+
+    fn record(state: &StateTrie, path: &str) -> Option<Record> {
+        state.get(&path_address(path)).bridge
+    }
+
+    /// Has the client trust a header at `height` of the other chain,
+    /// whose state holds `entries`, returning proofs of them.
+    fn trust(state: &mut StateTrie, height: u64, entries: &[(String, Hash)]) -> Vec<CommitmentProof> {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = entries.iter().map(|(path, value)| (path.as_bytes().to_vec(), value.0.to_vec())).collect();
+        let (app_hash, proofs) = tendermint::commit_entries(&entries);
+        let update = tendermint::Update {
+            signed_header: signed_header(height, app_hash, &[1, 2, 3], &[1, 2, 3]),
+            validators: validators(&[1, 2, 3]),
+            trusted_height: 1,
+            trusted_validators: validators(&[1, 2, 3]),
+        };
+        run(state, 1_050, 0, &Msg::UpdateClient { client_id: "other-0".to_string(), update: ClientUpdate::Tendermint(update) }).unwrap();
+        proofs.into_iter().map(CommitmentProof::Tendermint).collect()
+    }
+
+    #[test]
+    fn sends_tokens_out_and_takes_them_back() {
+        let mut state = StateTrie::from_balances(&[(ALICE.to_string(), 1_000)].into());
+        let genesis = signed_header(1, Hash::ZERO, &[1, 2, 3], &[]).header;
+        let client = ClientState::Tendermint(tendermint::ClientState { chain_id: "other".to_string(), trusting_period: 3_600, latest_height: 1 });
+        let consensus = ConsensusState::Tendermint(tendermint::ConsensusState::from(&genesis));
+        run(&mut state, 1_010, 0, &Msg::CreateClient { client_id: "other-0".to_string(), client, consensus }).unwrap();
+        let open = Msg::OpenChannel { channel_id: "ch-a".to_string(), client_id: "other-0".to_string(), counterparty_channel_id: "ch-b".to_string() };
+        run(&mut state, 1_010, 0, &open).unwrap();
+        assert!(run(&mut state, 1_010, 0, &open).unwrap_err().contains("already exists"));
+
+        let send = Msg::Transfer { channel_id: "ch-a".to_string(), receiver: "other1bob".to_string(), timeout_timestamp: 0 };
+        assert!(run(&mut state, 1_010, 5_000, &send).unwrap_err().contains("insufficient balance"));
+        let events = run(&mut state, 1_010, 300, &send).unwrap();
+        let sent: Packet = serde_json::from_str(&events[0].attributes["packet"]).unwrap();
+        assert_eq!((sent.sequence, sent.destination_channel.as_str(), sent.data.amount), (1, "ch-b", 300));
+        assert_eq!(record(&state, &commitment_path("ch-a", 1)), Some(Record::Commitment(sent.commitment())));
+        run(&mut state, 1_010, 300, &send).unwrap();
+        assert_eq!((state.get(ALICE).balance, state.get(&escrow_address("ch-a")).balance), (400, 600));
+
+        // The other chain refuses the first packet, and of the second's
+        // vouchers sends 120 back to Bob
+        let back = Packet {
+            sequence: 1,
+            source_channel: "ch-b".to_string(),
+            destination_channel: "ch-a".to_string(),
+            data: TransferData { denom: "ch-b/cubiq".to_string(), amount: 120, sender: "other1bob".to_string(), receiver: BOB.to_string() },
+            timeout_timestamp: 0,
+        };
+        let foreign = Packet { sequence: 2, data: TransferData { denom: "uatom".to_string(), ..back.data.clone() }, ..back.clone() };
+        let refused = Acknowledgement::Error("no".to_string());
+        let entries = [
+            (commitment_path("ch-b", 1), back.commitment()),
+            (commitment_path("ch-b", 2), foreign.commitment()),
+            (ack_path("ch-b", 1), refused.commitment()),
+        ];
+        let proofs = trust(&mut state, 5, &entries);
+        let recv = |packet: &Packet, proof: &CommitmentProof| Msg::RecvPacket { packet: packet.clone(), proof: proof.clone(), proof_height: 5 };
+        assert!(run(&mut state, 1_060, 0, &recv(&back, &proofs[1])).unwrap_err().contains("proof does not show"));
+        let events = run(&mut state, 1_060, 0, &recv(&back, &proofs[0])).unwrap();
+        assert_eq!(events[0].attributes["acknowledgement"], "\"success\"");
+        assert_eq!((state.get(BOB).balance, state.get(&escrow_address("ch-a")).balance), (120, 480));
+        assert!(run(&mut state, 1_060, 0, &recv(&back, &proofs[0])).unwrap_err().contains("already received"));
+        let events = run(&mut state, 1_060, 0, &recv(&foreign, &proofs[1])).unwrap();
+        assert!(events[0].attributes["acknowledgement"].contains("only ch-b/cubiq"));
+        assert_eq!(state.get(BOB).balance, 120);
+
+        let ack = |proof: &CommitmentProof| Msg::Acknowledge { packet: sent.clone(), acknowledgement: refused.clone(), proof: proof.clone(), proof_height: 5 };
+        run(&mut state, 1_060, 0, &ack(&proofs[2])).unwrap();
+        assert_eq!((state.get(ALICE).balance, state.get(&escrow_address("ch-a")).balance), (700, 180));
+        assert_eq!(record(&state, &commitment_path("ch-a", 1)), None);
+        assert!(run(&mut state, 1_060, 0, &ack(&proofs[2])).unwrap_err().contains("not awaiting"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Background compaction of the block store.
+//!
+//! RocksDB compacts as it writes, but the deletes that pruning and the
+//! freezer make leave tombstones it is slow to clear. Every compaction
+//! interval the scheduler compacts the column families with at least
+//! `HEAVY_COMPACTION_BYTES` of compaction pending, largest first and one
+//! at a time, on a blocking thread. Backends that don't compact report
+//! nothing pending, so for them the scheduler only refreshes metrics.
+//!
+//! A full compaction competes with block verification for the disk. While
+//! the node votes on proposals, each compaction waits for `QUIET_PERIOD`
+//! without a new head, but no longer than `MAX_DEFERRAL`, so a busy chain
+//! can't put it off forever.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::ConsensusEvent;
+use crate::metrics::StorageMetrics;
+use crate::store::{BlockStore, StoreStats};
+use crate::QubeNode;
+
+/// Pending compaction worth a manual compaction.
+pub const HEAVY_COMPACTION_BYTES: u64 = 64 << 20;
+
+/// Time without a new head that counts as a gap between blocks.
+pub const QUIET_PERIOD: Duration = Duration::from_secs(5);
+
+/// Longest a compaction waits for a gap.
+pub const MAX_DEFERRAL: Duration = Duration::from_secs(30 * 60);
+
+/// How often storage metrics are refreshed.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the scheduler checks whether a compaction may start.
+const POLL: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub struct Compactor {
+    store: BlockStore,
+    /// Between scheduled passes; without one, nothing is compacted
+    interval: Option<Duration>,
+    metrics: Option<Arc<StorageMetrics>>,
+}
+
+impl Compactor {
+    pub fn new(store: BlockStore, interval: Option<Duration>) -> Self {
+        Self { store, interval, metrics: None }
+    }
+
+    /// Refreshes `metrics` from the store's stats, and records compactions.
+    pub fn with_metrics(mut self, metrics: Arc<StorageMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Schedules compactions, watching `node` for heads, until `shutdown`
+    /// completes. A compaction under way is left to finish on its thread.
+    pub async fn run(self, node: Arc<QubeNode>, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut heads = node.subscribe();
+        let mut last_head = None;
+        let mut poll = tokio::time::interval(POLL);
+        let mut next_stats = Instant::now();
+        let mut next_pass = self.interval.map(|interval| Instant::now() + interval);
+        let mut queue = VecDeque::new();
+        // When the first compaction in `queue` became due
+        let mut waiting_since = Instant::now();
+        let mut deferral_counted = false;
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return,
+                event = heads.recv() => match event {
+                    Ok(ConsensusEvent::NewHead(_)) | Err(RecvError::Lagged(_)) => last_head = Some(Instant::now()),
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => return,
+                },
+                _ = poll.tick() => {
+                    let now = Instant::now();
+                    let pass_due = queue.is_empty() && next_pass.is_some_and(|at| now >= at);
+                    if now >= next_stats || pass_due {
+                        next_stats = now + STATS_INTERVAL;
+                        match self.stats().await {
+                            Some(stats) if pass_due => {
+                                queue.extend(due(&stats));
+                                next_pass = self.interval.map(|interval| now + interval);
+                                waiting_since = now;
+                                deferral_counted = false;
+                            }
+                            _ => {}
+                        }
+                    }
+                    let Some(column) = queue.front().cloned() else {
+                        continue;
+                    };
+                    let proposing = node.voting && !node.is_paused();
+                    if should_wait(proposing, last_head, waiting_since, now) {
+                        if !deferral_counted {
+                            deferral_counted = true;
+                            if let Some(metrics) = &self.metrics {
+                                metrics.observe_deferral();
+                            }
+                        }
+                        continue;
+                    }
+                    let store = self.store.clone();
+                    let name = column.clone();
+                    let compaction = tokio::task::spawn_blocking(move || store.compact(&name));
+                    let result = tokio::select! {
+                        _ = &mut shutdown => return,
+                        result = compaction => result,
+                    };
+                    match result {
+                        Ok(Ok(())) => {
+                            if let Some(metrics) = &self.metrics {
+                                metrics.observe_compaction(&column, now.elapsed());
+                            }
+                        }
+                        Ok(Err(e)) => tracing::error!(column = %column, error = %e, "compaction failed"),
+                        Err(e) => tracing::error!(column = %column, error = %e, "compaction panicked"),
+                    }
+                    queue.pop_front();
+                    waiting_since = Instant::now();
+                    deferral_counted = false;
+                }
+            }
+        }
+    }
+
+    /// The store's stats, also handed to the metrics.
+    async fn stats(&self) -> Option<StoreStats> {
+        let store = self.store.clone();
+        match tokio::task::spawn_blocking(move || store.stats()).await {
+            Ok(Ok(stats)) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.observe(&stats);
+                }
+                Some(stats)
+            }
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, "reading storage stats failed");
+                None
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "reading storage stats panicked");
+                None
+            }
+        }
+    }
+}
+
+/// Column families worth compacting, most pending first.
+fn due(stats: &StoreStats) -> Vec<String> {
+    let mut due: Vec<_> = stats
+        .kv
+        .columns
+        .iter()
+        .filter_map(|column| Some((column.pending_compaction_bytes?, &column.name)))
+        .filter(|(pending, _)| *pending >= HEAVY_COMPACTION_BYTES)
+        .collect();
+    due.sort_by_key(|(pending, _)| Reverse(*pending));
+    due.into_iter().map(|(_, name)| name.clone()).collect()
+}
+
+/// Whether a compaction due since `since` should keep waiting for a gap
+/// between blocks.
+fn should_wait(proposing: bool, last_head: Option<Instant>, since: Instant, now: Instant) -> bool {
+    let recent_head = last_head.is_some_and(|head| now.duration_since(head) < QUIET_PERIOD);
+    proposing && recent_head && now.duration_since(since) < MAX_DEFERRAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::{Backend, ColumnStats, KvStats};
+
+    #[test]
+    fn compacts_columns_with_heavy_compaction_pending() {
+        let column = |name: &str, pending| ColumnStats {
+            name: name.to_string(),
+            keys: None,
+            bytes: None,
+            pending_compaction_bytes: pending,
+        };
+        let stats = StoreStats {
+            latest_height: 1,
+            frozen_height: 0,
+            freezer_bytes: None,
+            kv: KvStats {
+                backend: Backend::RocksDb,
+                total_bytes: None,
+                running_compactions: Some(0),
+                columns: vec![
+                    column("blocks", Some(HEAVY_COMPACTION_BYTES)),
+                    column("hashes", Some(1 << 20)),
+                    column("headers", Some(4 * HEAVY_COMPACTION_BYTES)),
+                    column("state", None),
+                ],
+            },
+        };
+        assert_eq!(due(&stats), ["headers", "blocks"]);
+    }
+
+    #[test]
+    fn waits_for_a_gap_between_blocks_only_while_proposing() {
+        let since = Instant::now();
+        let now = since + Duration::from_secs(60);
+        let head = Some(now - Duration::from_secs(1));
+        assert!(should_wait(true, head, since, now));
+        assert!(!should_wait(false, head, since, now));
+        assert!(!should_wait(true, Some(now - QUIET_PERIOD), since, now));
+        assert!(!should_wait(true, None, since, now));
+        let late = since + MAX_DEFERRAL;
+        assert!(!should_wait(true, Some(late - Duration::from_secs(1)), since, late));
+    }
+}
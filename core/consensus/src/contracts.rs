@@ -0,0 +1,565 @@
+//! WASM smart contracts.
+//!
+//! A contract is an account with code, a WASM module, and storage, a map
+//! of byte strings only that code can change. Both are part of the
+//! account's value in the state trie, so a block's `state_root`, and the
+//! proof of it, covers what its contracts did.
+//!
+//! A `TxKind::Deploy` transaction's `data` is the module. It creates a
+//! contract at `address(from, nonce)` holding `value`, then runs the
+//! module's `init` export if there is one. A `TxKind::Call` transaction
+//! moves `value` to the contract at `to` and runs its `call` export with
+//! `data` as input. Neither export takes or returns anything. A contract
+//! that traps or runs out of gas fails its transaction like a transfer
+//! the sender can't cover: nothing changes but the fee.
+//!
+//! Modules may only import these functions from `env`. Pointers and
+//! lengths are `i32`s into the module's exported `memory`; amounts are
+//! `u64`s passed as `i64`.
+//!
+//! | function                                       | does                                                       |
+//! |------------------------------------------------|------------------------------------------------------------|
+//! | `input(ptr, cap) -> len`                       | copies up to `cap` bytes of the input to `ptr`             |
+//! | `caller(ptr, cap) -> len`                      | the same with the sender's account                         |
+//! | `value() -> i64`                               | what the transaction sent                                  |
+//! | `balance() -> i64`                             | the contract's balance                                     |
+//! | `storage_read(key, key_len, ptr, cap) -> len`  | copies out an entry, or returns -1 if there is none        |
+//! | `storage_write(key, key_len, value, len)`      | sets an entry; an empty value removes it                   |
+//! | `emit(kind, kind_len, data, data_len)`         | an event of the UTF-8 `kind`, with `data` as hex           |
+//! | `transfer(to, to_len, value) -> i32`           | sends from the contract: 0 once sent, 1 if it can't afford |
+//!
+//! Functions that copy out return the whole length, which may be more
+//! than `cap`, and `transfer` traps unless `to` is an account by
+//! `address::check_account`. Execution is deterministic: NaNs are canonicalized,
+//! the relaxed SIMD and threads proposals, whose results may differ
+//! between machines, are off, and memory is capped at `MAX_MEMORY`. Each instruction costs a unit of gas, and each host
+//! function what `GAS_SCHEDULE` says.
+
+use serde::{Deserialize, Serialize};
+use lru::LruCache;
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, OnceLock};
+use wasmtime::{
+    Caller, Config, Engine, Extern, ExternType, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+    WasmFeatures,
+};
+
+use crate::address::{self, Address};
+use crate::execution::{self, GasMeter, GAS_SCHEDULE};
+use crate::receipts::TxEvent;
+use crate::state::{Account, Hash, StateTrie};
+use crate::Transaction;
+
+/// Prefixes what a contract's address is derived from.
+const ADDRESS_DOMAIN: &[u8] = b"cubiq-contract-v1";
+
+/// Largest module a deploy may carry.
+pub const MAX_CODE_SIZE: usize = 256 * 1024;
+
+/// Most linear memory a contract may grow to, in bytes.
+pub const MAX_MEMORY: usize = 16 * 1024 * 1024;
+
+/// Most table elements a contract may have.
+const MAX_TABLE_ELEMENTS: usize = 10_000;
+
+/// Compiled modules kept, by code hash, the least recently run dropped
+/// first.
+const CACHED_MODULES: NonZeroUsize = NonZeroUsize::new(64).unwrap();
+
+/// Proposals whose results may differ between machines.
+const NONDETERMINISTIC: WasmFeatures =
+    WasmFeatures::THREADS.union(WasmFeatures::SHARED_EVERYTHING_THREADS).union(WasmFeatures::RELAXED_SIMD);
+
+/// Code and storage of a contract account, written as hex.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contract {
+    #[serde(with = "hex_bytes")]
+    pub code: Vec<u8>,
+    #[serde(with = "hex_entries")]
+    pub storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Contract {
+    pub fn code_hash(&self) -> Hash {
+        module_hash(&self.code)
+    }
+
+    /// Commits to every entry: blake3 over each key and value in key
+    /// order, each after its length as a big-endian u64. Zero when empty.
+    pub fn storage_root(&self) -> Hash {
+        if self.storage.is_empty() {
+            return Hash::ZERO;
+        }
+        let mut hasher = blake3::Hasher::new();
+        for (key, value) in &self.storage {
+            hasher.update(&(key.len() as u64).to_be_bytes()).update(key);
+            hasher.update(&(value.len() as u64).to_be_bytes()).update(value);
+        }
+        Hash(*hasher.finalize().as_bytes())
+    }
+}
+
+pub(crate) fn module_hash(code: &[u8]) -> Hash {
+    Hash(*blake3::hash(code).as_bytes())
+}
+
+/// Where the contract `deployer` deploys with its transaction `nonce`
+/// goes, an address no key holds.
+pub fn address(deployer: &str, nonce: u64) -> String {
+    let seed = [deployer.as_bytes(), &nonce.to_be_bytes()].concat();
+    Address::derive(ADDRESS_DOMAIN, &seed).to_string()
+}
+
+/// Deploys `tx.data` over `changes`, which are left untouched if it fails.
+pub(crate) fn deploy(
+    state: &StateTrie,
+    changes: &mut BTreeMap<String, Account>,
+    meter: &mut GasMeter,
+    tx: &Transaction,
+) -> Result<Vec<TxEvent>, String> {
+    if tx.data.len() > MAX_CODE_SIZE {
+        return Err(format!("code is {} bytes, the most is {}", tx.data.len(), MAX_CODE_SIZE));
+    }
+    meter.charge(GAS_SCHEDULE.code_byte.saturating_mul(tx.data.len() as u64))?;
+    let module = runtime().module(&tx.data)?;
+    if !matches!(module.get_export("call"), Some(ExternType::Func(_))) {
+        return Err("code exports no call function".to_string());
+    }
+    let address = address(&tx.from, tx.nonce);
+    let mut overlay = Overlay { state, changes, written: BTreeMap::new() };
+    let mut contract = overlay.get(&address);
+    if contract.contract.is_some() {
+        return Err(format!("{} is already a contract", address));
+    }
+    contract.contract = Some(Contract { code: tx.data.clone(), storage: BTreeMap::new() });
+    overlay.written.insert(address.clone(), contract);
+    overlay.send(&tx.from, &address, tx.value)?;
+
+    let attributes = [("contract", address.clone()), ("code_hash", module_hash(&tx.data).to_string())];
+    let mut events = vec![TxEvent { kind: "deploy".to_string(), attributes: attributes.map(|(k, v)| (k.to_string(), v)).into() }];
+    if matches!(module.get_export("init"), Some(ExternType::Func(_))) {
+        events.extend(overlay.run(&module, &address, tx, "init", vec![], meter)?);
+    }
+    let written = overlay.written;
+    changes.extend(written);
+    Ok(events)
+}
+
+/// Calls the contract at `tx.to` over `changes`, which are left untouched
+/// if it fails.
+pub(crate) fn call(
+    state: &StateTrie,
+    changes: &mut BTreeMap<String, Account>,
+    meter: &mut GasMeter,
+    tx: &Transaction,
+) -> Result<Vec<TxEvent>, String> {
+    let mut overlay = Overlay { state, changes, written: BTreeMap::new() };
+    let Some(contract) = overlay.get(&tx.to).contract else {
+        return Err(format!("{} is not a contract", tx.to));
+    };
+    let module = runtime().module(&contract.code)?;
+    overlay.send(&tx.from, &tx.to, tx.value)?;
+    let events = overlay.run(&module, &tx.to, tx, "call", tx.data.clone(), meter)?;
+    let written = overlay.written;
+    changes.extend(written);
+    Ok(events)
+}
+
+/// Accounts written on top of a block's changes so far, kept apart until
+/// a contract succeeds.
+struct Overlay<'a> {
+    state: &'a StateTrie,
+    changes: &'a BTreeMap<String, Account>,
+    written: BTreeMap<String, Account>,
+}
+
+impl Overlay<'_> {
+    fn get(&self, address: &str) -> Account {
+        let changed = self.written.get(address).or_else(|| self.changes.get(address));
+        changed.cloned().unwrap_or_else(|| self.state.get(address))
+    }
+
+    fn send(&mut self, from: &str, to: &str, value: u64) -> Result<(), String> {
+        let mut sender = self.get(from);
+        let left = sender.balance.checked_sub(value);
+        sender.balance = left.ok_or_else(|| format!("insufficient balance: has {}, sends {}", sender.balance, value))?;
+        self.written.insert(from.to_string(), sender);
+        let mut recipient = self.get(to);
+        recipient.balance = recipient.balance.checked_add(value).ok_or("recipient balance overflows")?;
+        self.written.insert(to.to_string(), recipient);
+        Ok(())
+    }
+
+    /// Runs the `entry` export of the contract at `address`, metered by
+    /// `meter`, and writes what it did.
+    fn run(
+        &mut self,
+        module: &Module,
+        address: &str,
+        tx: &Transaction,
+        entry: &str,
+        input: Vec<u8>,
+        meter: &mut GasMeter,
+    ) -> Result<Vec<TxEvent>, String> {
+        let mut account = self.get(address);
+        let mut contract = account.contract.take().expect("only contracts run");
+        let host = Host {
+            contract: address.to_string(),
+            caller: tx.from.clone(),
+            input,
+            value: tx.value,
+            balance: account.balance,
+            storage: std::mem::take(&mut contract.storage),
+            events: vec![],
+            transfers: vec![],
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).table_elements(MAX_TABLE_ELEMENTS).instances(1).build(),
+        };
+        let host = runtime().run(module, host, entry, meter)?;
+        account.balance = host.balance;
+        contract.storage = host.storage;
+        account.contract = Some(contract);
+        self.written.insert(address.to_string(), account);
+        for (to, value) in host.transfers {
+            let mut recipient = self.get(&to);
+            recipient.balance = recipient.balance.checked_add(value).ok_or("recipient balance overflows")?;
+            self.written.insert(to, recipient);
+        }
+        Ok(host.events)
+    }
+}
+
+/// What a running contract sees and has done.
+struct Host {
+    contract: String,
+    caller: String,
+    input: Vec<u8>,
+    value: u64,
+    balance: u64,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+    events: Vec<TxEvent>,
+    /// Sent by the contract, already taken from `balance`
+    transfers: Vec<(String, u64)>,
+    limits: StoreLimits,
+}
+
+struct Runtime {
+    engine: Engine,
+    linker: Linker<Host>,
+    modules: Mutex<LruCache<Hash, Module>>,
+}
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        let mut config = Config::new();
+        config.consume_fuel(true).cranelift_nan_canonicalization(true).wasm_features(NONDETERMINISTIC, false);
+        let engine = Engine::new(&config).expect("the contract engine's config is valid");
+        let mut linker = Linker::new(&engine);
+        link(&mut linker).expect("host functions link once each");
+        Runtime { engine, linker, modules: Mutex::new(LruCache::new(CACHED_MODULES)) }
+    })
+}
+
+impl Runtime {
+    /// `code` compiled, or why it isn't a module.
+    fn module(&self, code: &[u8]) -> Result<Module, String> {
+        let hash = module_hash(code);
+        let mut modules = self.modules.lock().expect("module cache lock poisoned");
+        if let Some(module) = modules.get(&hash) {
+            return Ok(module.clone());
+        }
+        let module = Module::new(&self.engine, code).map_err(|e| format!("invalid code: {}", e.root_cause()))?;
+        modules.put(hash, module.clone());
+        Ok(module)
+    }
+
+    /// Runs `entry` with the fuel `meter` has left, charging what it
+    /// burned.
+    fn run(&self, module: &Module, host: Host, entry: &str, meter: &mut GasMeter) -> Result<Host, String> {
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(meter.remaining()).expect("fuel is on");
+        let result = self
+            .linker
+            .instantiate(&mut store, module)
+            .and_then(|instance| instance.get_typed_func::<(), ()>(&mut store, entry)?.call(&mut store, ()));
+        let left = store.get_fuel().expect("fuel is on");
+        match result {
+            Err(e) if matches!(e.downcast_ref::<Trap>(), Some(Trap::OutOfFuel)) => Err(meter.charge(u64::MAX).unwrap_err()),
+            result => {
+                meter.charge(meter.remaining() - left)?;
+                match result {
+                    Ok(()) => Ok(store.into_data()),
+                    Err(e) => match e.downcast_ref::<Trap>() {
+                        Some(trap) => Err(format!("contract trapped: {}", trap)),
+                        None => Err(format!("contract failed: {}", e.root_cause())),
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn link(linker: &mut Linker<Host>) -> wasmtime::Result<()> {
+    linker.func_wrap("env", "input", |mut caller: Caller<'_, Host>, ptr: i32, cap: i32| {
+        let input = std::mem::take(&mut caller.data_mut().input);
+        let copied = copy_out(&mut caller, ptr, cap, &input);
+        caller.data_mut().input = input;
+        copied
+    })?;
+    linker.func_wrap("env", "caller", |mut caller: Caller<'_, Host>, ptr: i32, cap: i32| {
+        let sender = caller.data().caller.clone();
+        copy_out(&mut caller, ptr, cap, sender.as_bytes())
+    })?;
+    linker.func_wrap("env", "value", |caller: Caller<'_, Host>| caller.data().value as i64)?;
+    linker.func_wrap("env", "balance", |caller: Caller<'_, Host>| caller.data().balance as i64)?;
+    linker.func_wrap("env", "storage_read", |mut caller: Caller<'_, Host>, key: i32, key_len: i32, ptr: i32, cap: i32| {
+        charge(&mut caller, GAS_SCHEDULE.storage_read)?;
+        let key = copy_in(&mut caller, key, key_len)?;
+        match caller.data().storage.get(&key).cloned() {
+            Some(value) => copy_out(&mut caller, ptr, cap, &value),
+            None => Ok(-1),
+        }
+    })?;
+    linker.func_wrap("env", "storage_write", |mut caller: Caller<'_, Host>, key: i32, key_len: i32, value: i32, len: i32| {
+        charge(&mut caller, GAS_SCHEDULE.storage_write)?;
+        let key = copy_in(&mut caller, key, key_len)?;
+        let value = copy_in(&mut caller, value, len)?;
+        let storage = &mut caller.data_mut().storage;
+        if value.is_empty() {
+            storage.remove(&key);
+        } else {
+            storage.insert(key, value);
+        }
+        Ok(())
+    })?;
+    linker.func_wrap("env", "emit", |mut caller: Caller<'_, Host>, kind: i32, kind_len: i32, data: i32, data_len: i32| {
+        charge(&mut caller, GAS_SCHEDULE.event)?;
+        let kind = String::from_utf8(copy_in(&mut caller, kind, kind_len)?).map_err(|_| wasmtime::Error::msg("event kind is not UTF-8"))?;
+        let data = copy_in(&mut caller, data, data_len)?;
+        let host = caller.data_mut();
+        let attributes = [("contract", host.contract.clone()), ("data", hex::encode(data))];
+        host.events.push(TxEvent { kind, attributes: attributes.map(|(k, v)| (k.to_string(), v)).into() });
+        Ok(())
+    })?;
+    linker.func_wrap("env", "transfer", |mut caller: Caller<'_, Host>, to: i32, to_len: i32, value: i64| {
+        charge(&mut caller, GAS_SCHEDULE.contract_transfer)?;
+        let to = String::from_utf8(copy_in(&mut caller, to, to_len)?).map_err(|_| wasmtime::Error::msg("recipient is not UTF-8"))?;
+        address::check_account(&to).map_err(|e| wasmtime::Error::msg(format!("recipient: {}", e)))?;
+        let value = value as u64;
+        let host = caller.data_mut();
+        let Some(left) = host.balance.checked_sub(value) else {
+            return Ok(1);
+        };
+        host.balance = left;
+        host.events.push(execution::transfer_event(&host.contract, &to, value));
+        host.transfers.push((to, value));
+        Ok(0)
+    })?;
+    Ok(())
+}
+
+/// Burns `gas` of the running contract's fuel, or traps out of it.
+fn charge(caller: &mut Caller<'_, Host>, gas: u64) -> wasmtime::Result<()> {
+    let fuel = caller.get_fuel()?;
+    match fuel.checked_sub(gas) {
+        Some(left) => caller.set_fuel(left),
+        None => {
+            caller.set_fuel(0)?;
+            Err(Trap::OutOfFuel.into())
+        }
+    }
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> wasmtime::Result<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory).ok_or_else(|| wasmtime::Error::msg("contract exports no memory"))
+}
+
+/// The `len` bytes at `ptr`, paid for by the byte.
+fn copy_in(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> wasmtime::Result<Vec<u8>> {
+    let (start, len) = (ptr as u32 as usize, len as u32 as usize);
+    charge(caller, GAS_SCHEDULE.host_byte.saturating_mul(len as u64))?;
+    let memory = memory(caller)?;
+    let bytes = start.checked_add(len).and_then(|end| memory.data(&*caller).get(start..end));
+    bytes.map(<[u8]>::to_vec).ok_or_else(|| wasmtime::Error::msg("reads past the end of memory"))
+}
+
+/// Copies as much of `bytes` as fits in `cap` to `ptr`, paid for by the
+/// byte, and returns their whole length.
+fn copy_out(caller: &mut Caller<'_, Host>, ptr: i32, cap: i32, bytes: &[u8]) -> wasmtime::Result<i32> {
+    let copied = &bytes[..bytes.len().min(cap as u32 as usize)];
+    charge(caller, GAS_SCHEDULE.host_byte.saturating_mul(copied.len() as u64))?;
+    let memory = memory(caller)?;
+    memory.write(&mut *caller, ptr as u32 as usize, copied).map_err(|_| wasmtime::Error::msg("writes past the end of memory"))?;
+    Ok(bytes.len() as i32)
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+mod hex_entries {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(entries: &BTreeMap<Vec<u8>, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(entries.iter().map(|(key, value)| (hex::encode(key), hex::encode(value))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, D::Error> {
+        BTreeMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, value)| Ok((hex::decode(key)?, hex::decode(value)?)))
+            .collect::<Result<_, hex::FromHexError>>()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::{execute, BlockEnv};
+    use crate::genesis::Vm;
+    use crate::TxKind;
+    use ed25519_dalek::SigningKey;
+
+    const CHAIN: u64 = 7;
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    /// Counts its calls in storage, or, by the first byte of its input: 1
+    /// traps, 2 loops forever, 3 sends 7 to `BOB`.
+    const COUNTER: &str = r#"
+        (module
+          (import "env" "input" (func $input (param i32 i32) (result i32)))
+          (import "env" "storage_read" (func $read (param i32 i32 i32 i32) (result i32)))
+          (import "env" "storage_write" (func $write (param i32 i32 i32 i32)))
+          (import "env" "emit" (func $emit (param i32 i32 i32 i32)))
+          (import "env" "transfer" (func $transfer (param i32 i32 i64) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "count")
+          (data (i32.const 16) "counted")
+          (data (i32.const 256) "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca")
+          (data (i32.const 48) "short")
+          (func (export "init")
+            (i64.store (i32.const 64) (i64.const 100))
+            (call $write (i32.const 0) (i32.const 5) (i32.const 64) (i32.const 8)))
+          (func (export "call")
+            (drop (call $input (i32.const 128) (i32.const 1)))
+            (block $count
+              (block $send
+                (block $spin
+                  (block $trap
+                    (br_table $count $trap $spin $send $count (i32.load8_u (i32.const 128))))
+                  unreachable)
+                (loop $forever (br $forever)))
+              (if (call $transfer (i32.const 256) (i32.const 64) (i64.const 7))
+                (then (call $emit (i32.const 48) (i32.const 5) (i32.const 0) (i32.const 0))))
+              return)
+            (drop (call $read (i32.const 0) (i32.const 5) (i32.const 64) (i32.const 8)))
+            (i64.store (i32.const 64) (i64.add (i64.load (i32.const 64)) (i64.const 1)))
+            (call $write (i32.const 0) (i32.const 5) (i32.const 64) (i32.const 8))
+            (call $emit (i32.const 16) (i32.const 7) (i32.const 64) (i32.const 8))))
+    "#;
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn env() -> BlockEnv {
+        BlockEnv { chain_id: CHAIN, gas_limit: 10_000_000, base_fee: 1, proposer: None, vm: Vm::Wasm, timestamp: 0 }
+    }
+
+    fn tx(nonce: u64, kind: TxKind, to: &str, value: u64, data: Vec<u8>) -> Transaction {
+        let to = to.to_string();
+        Transaction { chain_id: CHAIN, nonce, kind, to, value, gas_limit: 1_000_000, max_fee_per_gas: 1, data, ..Default::default() }.sign(&key())
+    }
+
+    /// Runs `txs` on `state`, committing them.
+    fn run(state: &mut StateTrie, txs: &[Transaction]) -> Vec<crate::execution::TxOutcome> {
+        let execution = execute(state, &env(), txs).unwrap();
+        let root = execution.post_state(state);
+        state.set_root(root);
+        execution.outcomes
+    }
+
+    /// A state where `key()` has deployed the counter with 50, and its
+    /// address.
+    fn deployed() -> (StateTrie, String) {
+        let sender = Address::from_key(&key().verifying_key()).to_string();
+        let mut state = StateTrie::from_balances(&BTreeMap::from([(sender.clone(), 100_000_000)]));
+        let outcomes = run(&mut state, &[tx(0, TxKind::Deploy, "", 50, wat::parse_str(COUNTER).unwrap())]);
+        assert_eq!(outcomes[0].error, None);
+        (state, address(&sender, 0))
+    }
+
+    #[test]
+    fn deploys_and_calls_contracts_whose_storage_is_under_the_state_root() {
+        let (mut state, contract) = deployed();
+        let account = state.get(&contract);
+        assert_eq!(account.balance, 50);
+        assert_eq!(account.contract.as_ref().unwrap().storage[b"count".as_slice()], 100u64.to_le_bytes());
+        let before = state.root();
+
+        let outcomes = run(&mut state, &[tx(1, TxKind::Call, &contract, 0, vec![]), tx(2, TxKind::Call, &contract, 0, vec![])]);
+        assert_eq!(outcomes[1].events[0].kind, "counted");
+        assert_eq!(outcomes[1].events[0].attributes["data"], hex::encode(102u64.to_le_bytes()));
+        let account = state.get(&contract);
+        assert_eq!(account.contract.as_ref().unwrap().storage[b"count".as_slice()], 102u64.to_le_bytes());
+        // Only the storage changed, and the root with it
+        assert_ne!(state.root(), before);
+        assert!(state.prove(&contract).verify(&state.root(), &contract, Some(&account)));
+        let forged = Account { contract: Some(Contract { storage: BTreeMap::new(), ..account.contract.clone().unwrap() }), ..account };
+        assert!(!state.prove(&contract).verify(&state.root(), &contract, Some(&forged)));
+
+        let encoded = serde_json::to_string(&state.get(&contract)).unwrap();
+        assert!(encoded.contains(&hex::encode(b"count")));
+        assert_eq!(serde_json::from_str::<Account>(&encoded).unwrap(), state.get(&contract));
+    }
+
+    #[test]
+    fn failed_contracts_change_nothing_but_the_fee() {
+        let (mut state, contract) = deployed();
+        let sender = Address::from_key(&key().verifying_key()).to_string();
+        let before = (state.get(&contract), state.get(&sender).balance);
+        let outcomes = run(&mut state, &[tx(1, TxKind::Call, &contract, 5, vec![1]), tx(2, TxKind::Call, &contract, 5, vec![2])]);
+
+        assert!(outcomes[0].error.as_ref().unwrap().contains("contract trapped"));
+        assert!(outcomes[1].error.as_ref().unwrap().contains("out of gas"));
+        assert_eq!(outcomes[1].gas_used, 1_000_000);
+        assert!(outcomes.iter().all(|outcome| outcome.events.is_empty()));
+        assert_eq!(state.get(&contract), before.0);
+        assert_eq!(state.get(&sender).balance, before.1 - outcomes[0].gas_used - outcomes[1].gas_used);
+
+        let calls_nobody = tx(3, TxKind::Call, BOB, 0, vec![]);
+        let garbage = tx(4, TxKind::Deploy, "", 0, vec![0, 97, 115, 109, 9]);
+        let outcomes = run(&mut state, &[calls_nobody, garbage]);
+        assert!(outcomes[0].error.as_ref().unwrap().contains("not a contract"));
+        assert!(outcomes[1].error.as_ref().unwrap().contains("invalid code"));
+        assert_eq!(state.get(&address(&sender, 4)), Account::default());
+        // A deploy names no recipient
+        assert!(execute(&state, &env(), &[tx(5, TxKind::Deploy, BOB, 0, vec![])]).unwrap_err().reason.contains("no recipient"));
+    }
+
+    #[test]
+    fn contracts_send_from_their_balance() {
+        let (mut state, contract) = deployed();
+        let sends = (1..=8).map(|nonce| tx(nonce, TxKind::Call, &contract, 0, vec![3])).collect::<Vec<_>>();
+        let outcomes = run(&mut state, &sends);
+
+        assert_eq!(outcomes[0].error, None);
+        assert_eq!(outcomes[0].events[0].attributes["to"], BOB);
+        // 50 covers seven sends, and the eighth comes up short
+        assert_eq!(state.get(BOB).balance, 49);
+        assert_eq!(state.get(&contract).balance, 1);
+        assert_eq!(outcomes[7].events[0].kind, "short");
+    }
+}
@@ -0,0 +1,70 @@
+//! Events consensus publishes as blocks are verified and finalized.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::logs::Log;
+use crate::merkle;
+use crate::BlockProposal;
+
+/// Events buffered per subscriber before it starts missing them.
+pub const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConsensusEvent {
+    /// A proposal passed verification
+    NewHead(BlockHeader),
+    /// A block gathered a supermajority of stake
+    Finalized { block_hash: String, height: u64 },
+    TxStatus { hash: String, status: TxStatus, block_hash: Option<String> },
+    /// What a verified block's transactions emitted, when they emitted
+    /// anything
+    Logs { logs: Vec<Log> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TxStatus {
+    Pending,
+    Included,
+    Finalized,
+    /// Left the pending pool without being included
+    Dropped,
+}
+
+/// A block without its transaction bodies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub block_hash: String,
+    pub state_root: String,
+    pub zkurl: String,
+    pub proposer_id: String,
+    pub timestamp: u64,
+    pub transaction_count: usize,
+    /// `merkle::transactions_root` of the body; empty in headers stored
+    /// before it was added
+    #[serde(default)]
+    pub transactions_root: String,
+    /// Zero in headers stored before the fee market, as are `gas_used`
+    #[serde(default)]
+    pub base_fee: u64,
+    #[serde(default)]
+    pub gas_used: u64,
+}
+
+impl BlockProposal {
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            block_hash: self.block_hash.clone(),
+            state_root: self.state_root.clone(),
+            zkurl: self.zkurl.to_string(),
+            proposer_id: self.proposer_id.clone(),
+            timestamp: self.timestamp,
+            transaction_count: self.transactions.len(),
+            transactions_root: merkle::transactions_root(&self.transactions).to_string(),
+            base_fee: self.base_fee,
+            gas_used: self.gas_used,
+        }
+    }
+}
@@ -0,0 +1,322 @@
+//! EVM contracts, for chains whose spec sets `vm = "evm"`.
+//!
+//! On such a chain `TxKind::Deploy` and `TxKind::Call` transactions run on
+//! revm instead of the WASM runtime in `contracts`: a deploy's `data` is
+//! init code, and a call's `to` an EVM address with `data` as calldata.
+//! Everything else is as for any transaction. The sender pays its fees
+//! and nonce outside the EVM, which runs at a gas price of zero without
+//! nonce checks and whose gas, by Ethereum's schedule and from the same `gas_limit`,
+//! is what the transaction uses. A revert or halt fails the transaction:
+//! nothing changes but the fee.
+//!
+//! Ethereum's accounts map onto the chain's:
+//!
+//! - The EVM address `a` is the account `0x` followed by `a` in lowercase
+//!   hex. Contracts are created at such addresses, from the sender's
+//!   address and the transaction's nonce as by `CREATE`.
+//! - A sender, whose account is its key's address, has the EVM address
+//!   `sender_address(from)`, which is its account for the transaction.
+//!   Anything sent there in between lands at the address's own account and
+//!   moves to the sender's with its next EVM transaction that succeeds.
+//! - Code and storage are the account's `Contract`, storage slots as
+//!   32-byte big-endian keys and values with zeros left out, so they are
+//!   under the state root as WASM contracts' are.
+//!
+//! Blocks have no number, time or hashes in the EVM: `NUMBER`,
+//! `TIMESTAMP` and `BLOCKHASH` give zero, and so does `COINBASE`, as tips
+//! are paid outside it. Logs become events of kind `log`.
+
+use revm::context::result::{ExecutionResult, Output};
+use revm::context::{BlockEnv as EvmBlock, TxEnv};
+use revm::database_interface::WrapDatabaseRef;
+use revm::primitives::hardfork::SpecId;
+use revm::primitives::{Address, Bytes, TxKind as EvmTxKind, B256, U256};
+use revm::state::{AccountInfo, Bytecode};
+use revm::handler::MainnetContext;
+use revm::{DatabaseRef, ExecuteEvm, MainBuilder};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+
+use crate::contracts::{self, Contract};
+use crate::execution::{BlockEnv, GasMeter};
+use crate::receipts::TxEvent;
+use crate::state::{Account, StateTrie};
+use crate::{Transaction, TxKind};
+
+/// Rules the EVM runs by; changing them is a hard fork.
+const SPEC: SpecId = SpecId::PRAGUE;
+
+/// The EVM address of the sender whose account is the address `from`:
+/// the first 20 bytes of the blake3 hash of it as written.
+pub fn sender_address(from: &str) -> Address {
+    Address::from_slice(&blake3::hash(from.as_bytes()).as_bytes()[..20])
+}
+
+/// The account holding what is at the EVM address `address`.
+pub fn account_of(address: Address) -> String {
+    format!("0x{}", hex::encode(address))
+}
+
+/// Parses an account of the form `account_of` gives.
+pub fn parse_address(account: &str) -> Option<Address> {
+    let bytes = hex::decode(account.strip_prefix("0x")?).ok()?;
+    (bytes.len() == 20).then(|| Address::from_slice(&bytes))
+}
+
+/// Runs `tx`, a deploy or call, over `changes`, which are left untouched
+/// if it fails. Returns its events and what it returned.
+pub(crate) fn run(
+    state: &StateTrie,
+    env: &BlockEnv,
+    changes: &mut BTreeMap<String, Account>,
+    meter: &mut GasMeter,
+    tx: &Transaction,
+) -> Result<(Vec<TxEvent>, Vec<u8>), String> {
+    let kind = match tx.kind {
+        TxKind::Deploy => EvmTxKind::Create,
+        _ => EvmTxKind::Call(parse_address(&tx.to).ok_or_else(|| format!("{} is not an EVM address", tx.to))?),
+    };
+    let caller = sender_address(&tx.from);
+    let db = Db { state, changes, caller, sender: &tx.from, nonce: tx.nonce };
+    let evm_tx = TxEnv {
+        caller,
+        gas_limit: tx.gas_limit,
+        gas_price: 0,
+        kind,
+        value: U256::from(tx.value),
+        data: Bytes::copy_from_slice(&tx.data),
+        nonce: tx.nonce,
+        chain_id: Some(env.chain_id),
+        ..TxEnv::default()
+    };
+    let mut evm = MainnetContext::new(WrapDatabaseRef(&db), SPEC)
+        .modify_cfg_chained(|cfg| {
+            cfg.chain_id = env.chain_id;
+            cfg.disable_nonce_check = true;
+            cfg.tx_gas_limit_cap = Some(u64::MAX);
+        })
+        .modify_block_chained(|block: &mut EvmBlock| {
+            block.gas_limit = env.gas_limit;
+            block.basefee = 0;
+        })
+        .build_mainnet();
+    let executed = evm.transact(evm_tx).map_err(|e| e.to_string())?;
+    meter.charge(executed.result.tx_gas_used().saturating_sub(meter.used()))?;
+
+    let (logs, output) = match executed.result {
+        ExecutionResult::Success { logs, output, .. } => (logs, output),
+        ExecutionResult::Revert { output, .. } => return Err(format!("reverted: 0x{}", hex::encode(output))),
+        ExecutionResult::Halt { reason, .. } => return Err(format!("halted: {}", reason)),
+    };
+    let mut events = vec![];
+    if let Output::Create(_, Some(address)) = &output {
+        let code = executed.state.get(address).and_then(|account| account.info.code.as_ref());
+        let code_hash = contracts::module_hash(code.map_or(&[], Bytecode::original_byte_slice));
+        let attributes = [("contract", account_of(*address)), ("code_hash", code_hash.to_string())];
+        events.push(TxEvent { kind: "deploy".to_string(), attributes: attributes.map(|(k, v)| (k.to_string(), v)).into() });
+    }
+    for log in &logs {
+        let topics = log.topics().iter().map(|topic| format!("0x{}", hex::encode(topic))).collect::<Vec<_>>().join(",");
+        let attributes = [("address", account_of(log.address)), ("topics", topics), ("data", format!("0x{}", hex::encode(&log.data.data)))];
+        events.push(TxEvent { kind: "log".to_string(), attributes: attributes.map(|(k, v)| (k.to_string(), v)).into() });
+    }
+
+    let mut written = BTreeMap::new();
+    for (address, touched) in executed.state.iter().filter(|(_, account)| account.is_touched()) {
+        let key = db.key(*address);
+        if touched.is_selfdestructed() {
+            written.insert(key, Account::default());
+            continue;
+        }
+        let mut account = db.get(*address);
+        account.balance = touched.info.balance.try_into().map_err(|_| format!("{} balance overflows", key))?;
+        if key != tx.from {
+            account.nonce = touched.info.nonce;
+        }
+        let mut contract = account.contract.take().unwrap_or_default();
+        if let Some(code) = &touched.info.code {
+            contract.code = code.original_byte_slice().to_vec();
+        }
+        for (slot, value) in touched.changed_storage_slots() {
+            let slot = slot.to_be_bytes::<32>().to_vec();
+            if value.present_value.is_zero() {
+                contract.storage.remove(&slot);
+            } else {
+                contract.storage.insert(slot, value.present_value.to_be_bytes::<32>().to_vec());
+            }
+        }
+        account.contract = Some(contract).filter(|contract| *contract != Contract::default());
+        written.insert(key, account);
+    }
+    let parked = account_of(caller);
+    let mut waiting = written.get(&parked).cloned().unwrap_or_else(|| db.get_key(&parked));
+    if waiting.balance > 0 {
+        let mut sender = written.get(&tx.from).cloned().unwrap_or_else(|| db.get_key(&tx.from));
+        sender.balance = sender.balance.checked_add(waiting.balance).ok_or("sender balance overflows")?;
+        waiting.balance = 0;
+        written.insert(tx.from.clone(), sender);
+        written.insert(parked, waiting);
+    }
+    changes.extend(written);
+    Ok((events, output.into_data().to_vec()))
+}
+
+/// The chain's state as the EVM sees it, with `caller` standing for the
+/// account `sender`.
+struct Db<'a> {
+    state: &'a StateTrie,
+    changes: &'a BTreeMap<String, Account>,
+    caller: Address,
+    sender: &'a str,
+    /// Of the transaction, the sender's before it
+    nonce: u64,
+}
+
+impl Db<'_> {
+    fn key(&self, address: Address) -> String {
+        match address == self.caller {
+            true => self.sender.to_string(),
+            false => account_of(address),
+        }
+    }
+
+    fn get_key(&self, key: &str) -> Account {
+        self.changes.get(key).cloned().unwrap_or_else(|| self.state.get(key))
+    }
+
+    fn get(&self, address: Address) -> Account {
+        self.get_key(&self.key(address))
+    }
+}
+
+impl DatabaseRef for Db<'_> {
+    type Error = Infallible;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Infallible> {
+        let account = self.get(address);
+        if account == Account::default() {
+            return Ok(None);
+        }
+        let nonce = if address == self.caller { self.nonce } else { account.nonce };
+        let code = account.contract.map_or_else(Bytecode::default, |contract| Bytecode::new_raw(contract.code.into()));
+        Ok(Some(AccountInfo { balance: U256::from(account.balance), nonce, code_hash: code.hash_slow(), code: Some(code), ..AccountInfo::default() }))
+    }
+
+    /// Never needed, as `basic_ref` gives the code.
+    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Infallible> {
+        Ok(Bytecode::default())
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Infallible> {
+        let contract = self.get(address).contract.unwrap_or_default();
+        Ok(contract.storage.get(&index.to_be_bytes::<32>()[..]).map_or(U256::ZERO, |value| U256::from_be_slice(value)))
+    }
+
+    fn block_hash_ref(&self, _number: u64) -> Result<B256, Infallible> {
+        Ok(B256::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::{execute, simulate, TxOutcome};
+    use crate::genesis::Vm;
+    use ed25519_dalek::SigningKey;
+
+    const CHAIN: u64 = 7;
+
+    /// Init code for a counter: each call adds one to slot 0, logs the
+    /// count under the topic 0x2a and returns it. Calls with data revert.
+    const COUNTER: &str = concat!(
+        "6023600c60003960236000f3",
+        "3615600957600080fd5b",
+        "60005460010180600055600052602a60206000a160206000f3",
+    );
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn sender() -> String {
+        crate::address::Address::from_key(&key().verifying_key()).to_string()
+    }
+
+    fn env() -> BlockEnv {
+        BlockEnv { chain_id: CHAIN, gas_limit: 10_000_000, base_fee: 1, proposer: None, vm: Vm::Evm, timestamp: 0 }
+    }
+
+    fn tx(nonce: u64, kind: TxKind, to: &str, value: u64, data: Vec<u8>) -> Transaction {
+        let to = to.to_string();
+        Transaction { chain_id: CHAIN, nonce, kind, to, value, gas_limit: 1_000_000, max_fee_per_gas: 1, data, ..Default::default() }.sign(&key())
+    }
+
+    fn run(state: &mut StateTrie, txs: &[Transaction]) -> Vec<TxOutcome> {
+        let execution = execute(state, &env(), txs).unwrap();
+        let root = execution.post_state(state);
+        state.set_root(root);
+        execution.outcomes
+    }
+
+    /// A state where `key()` has deployed the counter with 50, and its
+    /// account.
+    fn deployed() -> (StateTrie, String) {
+        let mut state = StateTrie::from_balances(&BTreeMap::from([(sender(), 100_000_000)]));
+        let outcomes = run(&mut state, &[tx(0, TxKind::Deploy, "", 50, hex::decode(COUNTER).unwrap())]);
+        assert_eq!(outcomes[0].error, None);
+        let contract = account_of(sender_address(&sender()).create(0));
+        assert_eq!(outcomes[0].events[0].attributes["contract"], contract);
+        (state, contract)
+    }
+
+    #[test]
+    fn deploys_and_calls_evm_contracts_whose_storage_is_under_the_state_root() {
+        let (mut state, contract) = deployed();
+        let account = state.get(&contract);
+        assert_eq!(account.balance, 50);
+        assert_eq!(account.contract.as_ref().unwrap().code, hex::decode(&COUNTER[24..]).unwrap());
+        // The deploy's nonce is the chain's, not the EVM's
+        assert_eq!(state.get(&sender()).nonce, 1);
+
+        let outcomes = run(&mut state, &[tx(1, TxKind::Call, &contract, 0, vec![]), tx(2, TxKind::Call, &contract, 0, vec![])]);
+        let count = |n: u8| [[0; 31].as_slice(), &[n]].concat();
+        assert_eq!(outcomes[1].output, count(2));
+        assert_eq!(outcomes[1].events[0].kind, "log");
+        assert_eq!(outcomes[1].events[0].attributes["topics"], format!("0x{}", hex::encode(count(0x2a))));
+        assert!(outcomes[1].gas_used > crate::execution::TRANSFER_GAS);
+        let storage = state.get(&contract).contract.unwrap().storage;
+        assert_eq!(storage, BTreeMap::from([(vec![0; 32], count(2))]));
+        assert!(state.prove(&contract).verify(&state.root(), &contract, Some(&state.get(&contract))));
+
+        // Simulating a call gives what it would return, changing nothing
+        let root = state.root();
+        let call = Transaction { from: sender(), to: contract.clone(), kind: TxKind::Call, chain_id: CHAIN, ..Transaction::default() };
+        assert_eq!(simulate(&state, &env(), &call).unwrap().output, count(3));
+        assert_eq!(state.root(), root);
+    }
+
+    #[test]
+    fn reverts_change_nothing_but_the_fee() {
+        let (mut state, contract) = deployed();
+        let before = (state.get(&contract), state.get(&sender()).balance);
+        let outcomes = run(&mut state, &[tx(1, TxKind::Call, &contract, 5, vec![1]), tx(2, TxKind::Call, &sender(), 0, vec![])]);
+
+        assert_eq!(outcomes[0].error.as_deref(), Some("reverted: 0x"));
+        assert!(outcomes[1].error.as_ref().unwrap().contains("not an EVM address"));
+        assert_eq!(state.get(&contract), before.0);
+        assert_eq!(state.get(&sender()).balance, before.1 - outcomes[0].gas_used - outcomes[1].gas_used);
+    }
+
+    #[test]
+    fn what_is_sent_to_a_senders_address_reaches_it() {
+        let (mut state, contract) = deployed();
+        let parked = account_of(sender_address(&sender()));
+        let outcomes = run(&mut state, &[tx(1, TxKind::Transfer, &parked, 9, vec![])]);
+        assert_eq!(state.get(&parked).balance, 9);
+        let balance = state.get(&sender()).balance;
+
+        let outcomes = [outcomes, run(&mut state, &[tx(2, TxKind::Call, &contract, 0, vec![])])].concat();
+        assert_eq!(state.get(&parked).balance, 0);
+        assert_eq!(state.get(&sender()).balance, balance + 9 - outcomes[1].gas_used);
+    }
+}
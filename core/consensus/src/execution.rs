@@ -0,0 +1,619 @@
+//! Deterministic execution of a block's transactions.
+//!
+//! Every node runs the same rules over the same state, so a validator can
+//! re-execute a proposal and check its `state_root`. Transactions apply in
+//! block order:
+//!
+//! 1. The hash must match the contents, the transaction must be signed
+//!    by its sender, or cosigned by enough of a multisig sender's keys,
+//!    for this chain with the sender's next nonce,
+//!    `gas_limit` must cover the transaction's intrinsic gas and
+//!    `max_fee_per_gas` must cover the block's base fee, or the whole
+//!    block is invalid. So is a block whose transactions' gas limits add
+//!    up to more than its gas limit.
+//! 2. The transaction's gas price is the base fee plus its tip, as much
+//!    of `max_priority_fee_per_gas` as `max_fee_per_gas` leaves room for.
+//!    The sender pays `gas_limit` at that price up front, or the block is
+//!    invalid.
+//! 3. The sender's nonce goes up by one.
+//! 4. Execution is metered against `gas_limit` by `GAS_SCHEDULE`. The
+//!    intrinsic gas, for the transaction and its data, comes first.
+//! 5. A transfer moves `value` from sender to recipient, unless it calls
+//!    the validator registry, see `staking`; a deploy or
+//!    call runs a contract, see `contracts`, or `evm` on EVM chains; a
+//!    multisig creation funds a new multisig account, see `multisig`; a
+//!    key rotation only records itself, see `rotation`; and a bridge
+//!    message acts on the bridge, see `bridge`. If
+//!    the sender can't cover `value`, the contract fails, or the
+//!    transaction runs out of gas, the transaction fails: it stays in the
+//!    block and pays for the gas it used, all of it when it ran out, but
+//!    changes nothing else.
+//! 6. Gas left over is refunded. Of the gas used, the base fee is burned
+//!    and the tip paid to the block's proposer.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::address;
+use crate::bridge;
+use crate::contracts;
+#[cfg(feature = "evm")]
+use crate::evm;
+use crate::fees::{self, MIN_BASE_FEE};
+use crate::genesis::Vm;
+use crate::multisig;
+use crate::rotation::KeyRotation;
+use crate::receipts::TxEvent;
+use crate::staking;
+use crate::state::{Account, Hash, StateTrie};
+use crate::{Transaction, TxKind};
+
+/// Gas a plain transfer costs.
+pub const TRANSFER_GAS: u64 = 21_000;
+
+/// What each operation costs, in gas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasSchedule {
+    /// Every transaction, covering a transfer
+    pub transaction: u64,
+    /// Each zero byte of `data`
+    pub zero_data_byte: u64,
+    /// Each other byte of `data`
+    pub data_byte: u64,
+    /// Each byte of code a contract is deployed with
+    pub code_byte: u64,
+    /// A contract reading an entry of its storage
+    pub storage_read: u64,
+    /// A contract setting or removing an entry of its storage
+    pub storage_write: u64,
+    /// A contract emitting an event
+    pub event: u64,
+    /// A contract sending from its balance
+    pub contract_transfer: u64,
+    /// Each byte a contract copies in or out of its memory
+    pub host_byte: u64,
+}
+
+pub const GAS_SCHEDULE: GasSchedule = GasSchedule {
+    transaction: TRANSFER_GAS,
+    zero_data_byte: 4,
+    data_byte: 16,
+    code_byte: 200,
+    storage_read: 200,
+    storage_write: 5_000,
+    event: 375,
+    contract_transfer: 9_000,
+    host_byte: 3,
+};
+
+impl GasSchedule {
+    /// Gas `tx` uses before it does anything: the transaction and its
+    /// data.
+    pub fn intrinsic(&self, tx: &Transaction) -> u64 {
+        let zeros = tx.data.iter().filter(|byte| **byte == 0).count() as u64;
+        let others = tx.data.len() as u64 - zeros;
+        let data = zeros.saturating_mul(self.zero_data_byte).saturating_add(others.saturating_mul(self.data_byte));
+        self.transaction.saturating_add(data)
+    }
+}
+
+/// Counts the gas a transaction uses against its limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasMeter {
+    limit: u64,
+    used: u64,
+}
+
+impl GasMeter {
+    pub fn new(limit: u64) -> Self {
+        Self { limit, used: 0 }
+    }
+
+    /// Uses `gas`, or all that is left if that is less.
+    pub fn charge(&mut self, gas: u64) -> Result<(), String> {
+        match self.used.checked_add(gas).filter(|used| *used <= self.limit) {
+            Some(used) => {
+                self.used = used;
+                Ok(())
+            }
+            None => {
+                self.used = self.limit;
+                Err(format!("out of gas: needs more than its limit of {}", self.limit))
+            }
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.used
+    }
+}
+
+/// The block transactions execute in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEnv {
+    pub chain_id: u64,
+    /// Most gas the block's transactions may ask for between them
+    pub gas_limit: u64,
+    pub base_fee: u64,
+    /// Account of the block's proposer, which the tips are paid to; none
+    /// when simulating, as the proposer isn't known yet
+    pub proposer: Option<String>,
+    /// What deploys and calls run on
+    pub vm: Vm,
+    /// Unix seconds, the block's timestamp, or when simulating or
+    /// selecting this node's clock
+    pub timestamp: u64,
+}
+
+/// A transaction that can't be in any valid block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionError {
+    pub tx_hash: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction {} is invalid: {}", self.tx_hash, self.reason)
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// What one transaction did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxOutcome {
+    pub tx_hash: String,
+    /// Metered, at most the transaction's `gas_limit`
+    pub gas_used: u64,
+    /// Paid per unit of gas, the base fee and tip
+    pub gas_price: u64,
+    /// Why the transfer failed; the fee was still paid
+    pub error: Option<String>,
+    pub events: Vec<TxEvent>,
+    /// What an EVM contract returned, or the code it deployed
+    pub output: Vec<u8>,
+}
+
+/// What a transaction would do, found by `simulate`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Simulation {
+    /// Metered, and enough for the transaction as its `gas_limit`
+    pub gas_used: u64,
+    /// The limit it ran with
+    pub gas_limit: u64,
+    /// Per unit of gas, the base fee and tip
+    pub gas_price: u64,
+    /// Why the transfer would fail; the fee would still be paid
+    pub error: Option<String>,
+    pub events: Vec<TxEvent>,
+    /// What an EVM contract would return, as hex
+    #[serde(serialize_with = "hex_output")]
+    pub output: Vec<u8>,
+    /// Each account it would change
+    pub diffs: BTreeMap<String, AccountDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountDiff {
+    pub before: Account,
+    pub after: Account,
+}
+
+/// The result of executing transactions on top of `base`.
+#[derive(Debug, Clone)]
+pub struct Execution {
+    pub base: Hash,
+    /// Accounts as they stand after the last transaction
+    pub changes: BTreeMap<String, Account>,
+    /// One per transaction, in order
+    pub outcomes: Vec<TxOutcome>,
+    /// Sum of the transactions' gas limits, which the block's must cover
+    pub gas_reserved: u64,
+}
+
+impl Execution {
+    fn new(state: &StateTrie) -> Self {
+        Self { base: state.root(), changes: BTreeMap::new(), outcomes: vec![], gas_reserved: 0 }
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.outcomes.iter().map(|outcome| outcome.gas_used).sum()
+    }
+
+    /// Adds the post-state to `state` and returns its root, without
+    /// moving `state`'s current root.
+    pub fn post_state(&self, state: &mut StateTrie) -> Hash {
+        state.apply(&self.base, self.changes.clone())
+    }
+
+    /// Applies `tx` after the transactions already executed, leaving
+    /// everything untouched when it is invalid.
+    fn push(&mut self, state: &StateTrie, env: &BlockEnv, tx: &Transaction) -> Result<(), String> {
+        let left = env.gas_limit - self.gas_reserved;
+        if tx.gas_limit > left {
+            return Err(format!("asks for {} gas, the block has {} of {} left", tx.gas_limit, left, env.gas_limit));
+        }
+        check(env.chain_id, tx)?;
+        multisig::authorize(&account(state, &self.changes, &tx.from), tx)?;
+        let outcome = apply(state, env, &mut self.changes, tx)?;
+        self.gas_reserved += tx.gas_limit;
+        self.outcomes.push(outcome);
+        Ok(())
+    }
+}
+
+/// Executes a block's transactions on the current state, in the block
+/// `env`. Fails if any of them makes the block invalid.
+pub fn execute(state: &StateTrie, env: &BlockEnv, transactions: &[Transaction]) -> Result<Execution, ExecutionError> {
+    let mut execution = Execution::new(state);
+    for tx in transactions {
+        execution.push(state, env, tx).map_err(|reason| ExecutionError { tx_hash: tx.hash.clone(), reason })?;
+    }
+    Ok(execution)
+}
+
+/// Executes `tx` alone on `state` in the block `env`, committing nothing,
+/// so a wallet can show what it would cost and do before it is signed. It
+/// needs no hash or signature and runs as its sender's next transaction.
+/// Without a `max_fee_per_gas` it pays the base fee and no tip, and
+/// without a `gas_limit` it may use as much gas as its sender can pay for,
+/// up to the block's limit. Fails if the transaction would make a block
+/// invalid.
+pub fn simulate(state: &StateTrie, env: &BlockEnv, tx: &Transaction) -> Result<Simulation, String> {
+    let sender = state.get(&tx.from);
+    let mut tx = Transaction { nonce: sender.nonce, ..tx.clone() };
+    if tx.max_fee_per_gas == 0 {
+        tx.max_fee_per_gas = env.base_fee;
+        tx.max_priority_fee_per_gas = 0;
+    }
+    let gas_price = fees::effective_tip(&tx, env.base_fee)
+        .map(|tip| env.base_fee + tip)
+        .ok_or_else(|| format!("offers at most {} per gas, the base fee is {}", tx.max_fee_per_gas, env.base_fee))?;
+    if tx.gas_limit == 0 {
+        let balance = sender.balance.checked_sub(tx.value).filter(|left| *left >= gas_price);
+        let balance = balance.ok_or_else(|| format!("insufficient balance: has {}, sends {} and pays for gas", sender.balance, tx.value))?;
+        tx.gas_limit = (balance / gas_price).min(env.gas_limit);
+    }
+    if tx.gas_limit > env.gas_limit {
+        return Err(format!("asks for {} gas, a block holds {}", tx.gas_limit, env.gas_limit));
+    }
+    check_unsigned(env.chain_id, &tx)?;
+    let mut changes = BTreeMap::new();
+    let outcome = apply(state, env, &mut changes, &tx)?;
+    let diffs = changes
+        .into_iter()
+        .map(|(address, after)| {
+            let before = state.get(&address);
+            (address, AccountDiff { before, after })
+        })
+        .filter(|(_, diff)| diff.before != diff.after)
+        .collect();
+    Ok(Simulation {
+        gas_used: outcome.gas_used,
+        gas_limit: tx.gas_limit,
+        gas_price: outcome.gas_price,
+        error: outcome.error,
+        events: outcome.events,
+        output: outcome.output,
+        diffs,
+    })
+}
+
+fn hex_output<S: serde::Serializer>(output: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("0x{}", hex::encode(output)))
+}
+
+/// Executes `candidates` in order for a block producer, leaving out those
+/// that would make the block invalid, including those that don't fit in
+/// what is left of its gas limit or can't pay its base fee. Returns the
+/// transactions to include and their execution.
+pub fn select(state: &StateTrie, env: &BlockEnv, candidates: &[Transaction]) -> (Vec<Transaction>, Execution) {
+    let mut execution = Execution::new(state);
+    let mut included = vec![];
+    for tx in candidates {
+        if execution.push(state, env, tx).is_ok() {
+            included.push(tx.clone());
+        }
+    }
+    (included, execution)
+}
+
+/// Checks what can be checked of `tx` without state: its hash, signature
+/// and chain, that it offers enough gas to start, and fees that some block
+/// could take.
+pub fn check(chain_id: u64, tx: &Transaction) -> Result<(), String> {
+    if tx.hash != tx.compute_hash() {
+        return Err("hash does not match its contents".to_string());
+    }
+    tx.verify_signature()?;
+    check_unsigned(chain_id, tx)
+}
+
+/// `check` but for the hash and signature.
+fn check_unsigned(chain_id: u64, tx: &Transaction) -> Result<(), String> {
+    if tx.chain_id != chain_id {
+        return Err(format!("signed for chain {}, not {}", tx.chain_id, chain_id));
+    }
+    if tx.kind == TxKind::Deploy && !tx.to.is_empty() {
+        return Err("deploys to a new address, so has no recipient".to_string());
+    }
+    if tx.kind == TxKind::CreateMultisig && !tx.to.is_empty() {
+        return Err("creates a multisig at the address of its keys, so has no recipient".to_string());
+    }
+    if tx.kind == TxKind::RotateKey && (!tx.to.is_empty() || tx.value != 0) {
+        return Err("rotates a validator key, so has no recipient or value".to_string());
+    }
+    if tx.kind == TxKind::Bridge && !tx.to.is_empty() {
+        return Err("acts on the bridge, so has no recipient".to_string());
+    }
+    if !matches!(tx.kind, TxKind::Deploy | TxKind::CreateMultisig | TxKind::RotateKey | TxKind::Bridge) {
+        address::check_account(&tx.to).map_err(|e| format!("recipient: {}", e))?;
+    }
+    let intrinsic = GAS_SCHEDULE.intrinsic(tx);
+    if tx.gas_limit < intrinsic {
+        return Err(format!("has a gas limit of {}, but needs {} to start", tx.gas_limit, intrinsic));
+    }
+    if tx.max_fee_per_gas < MIN_BASE_FEE {
+        return Err(format!("offers at most {} per gas, the least base fee is {}", tx.max_fee_per_gas, MIN_BASE_FEE));
+    }
+    if tx.max_priority_fee_per_gas > tx.max_fee_per_gas {
+        return Err(format!("tips up to {} per gas, more than its {} most", tx.max_priority_fee_per_gas, tx.max_fee_per_gas));
+    }
+    Ok(())
+}
+
+/// Applies `tx`, already checked, over `changes`, which are left
+/// untouched when it is invalid.
+fn apply(state: &StateTrie, env: &BlockEnv, changes: &mut BTreeMap<String, Account>, tx: &Transaction) -> Result<TxOutcome, String> {
+    let mut sender = account(state, changes, &tx.from);
+    if tx.nonce != sender.nonce {
+        return Err(format!("has nonce {}, the sender's next is {}", tx.nonce, sender.nonce));
+    }
+    let tip = fees::effective_tip(tx, env.base_fee)
+        .ok_or_else(|| format!("offers at most {} per gas, the base fee is {}", tx.max_fee_per_gas, env.base_fee))?;
+    // At most `max_fee_per_gas`
+    let gas_price = env.base_fee + tip;
+    let fee = tx.gas_limit.checked_mul(gas_price).ok_or("fee overflows")?;
+    sender.balance = sender.balance.checked_sub(fee).ok_or_else(|| format!("sender cannot pay the {} fee", fee))?;
+    sender.nonce = sender.nonce.checked_add(1).ok_or("sender nonce overflows")?;
+    changes.insert(tx.from.clone(), sender);
+
+    let mut meter = GasMeter::new(tx.gas_limit);
+    let mut outcome = TxOutcome { tx_hash: tx.hash.clone(), gas_used: 0, gas_price, error: None, events: vec![], output: vec![] };
+    let executed = meter.charge(GAS_SCHEDULE.intrinsic(tx)).and_then(|()| match (tx.kind, env.vm) {
+        (TxKind::Transfer, _) if staking::calls_registry(tx) => staking::execute(state, changes, tx).map(|events| (events, vec![])),
+        (TxKind::Transfer, _) => transfer(state, changes, tx).map(|()| (vec![transfer_event(&tx.from, &tx.to, tx.value)], vec![])),
+        (TxKind::Deploy, Vm::Wasm) => contracts::deploy(state, changes, &mut meter, tx).map(|events| (events, vec![])),
+        (TxKind::Call, Vm::Wasm) => contracts::call(state, changes, &mut meter, tx).map(|events| (events, vec![])),
+        (TxKind::CreateMultisig, _) => multisig::create(state, changes, tx).map(|events| (events, vec![])),
+        (TxKind::RotateKey, _) => KeyRotation::parse(tx).map(|rotation| (vec![rotation.event()], vec![])),
+        (TxKind::Bridge, _) => bridge::execute(state, env, changes, &mut meter, tx).map(|events| (events, vec![])),
+        #[cfg(feature = "evm")]
+        (_, Vm::Evm) => evm::run(state, env, changes, &mut meter, tx),
+        #[cfg(not(feature = "evm"))]
+        (_, Vm::Evm) => Err("this node was built without the EVM".to_string()),
+    });
+    match executed {
+        Ok((events, output)) => (outcome.events, outcome.output) = (events, output),
+        Err(e) => outcome.error = Some(e),
+    }
+    // The fee paid for the whole limit, so the refund and tips can't
+    // overflow
+    let mut sender = account(state, changes, &tx.from);
+    sender.balance += meter.remaining() * gas_price;
+    changes.insert(tx.from.clone(), sender);
+    let tips = meter.used() * tip;
+    if let Some(address) = env.proposer.as_ref().filter(|_| tips > 0) {
+        let mut proposer = account(state, changes, address);
+        proposer.balance = proposer.balance.saturating_add(tips);
+        changes.insert(address.clone(), proposer);
+    }
+    outcome.gas_used = meter.used();
+    Ok(outcome)
+}
+
+pub(crate) fn transfer_event(from: &str, to: &str, value: u64) -> TxEvent {
+    let attributes = [("from", from.to_string()), ("to", to.to_string()), ("value", value.to_string())];
+    TxEvent { kind: "transfer".to_string(), attributes: attributes.map(|(key, value)| (key.to_string(), value)).into() }
+}
+
+/// Moves `tx.value`, or nothing.
+fn transfer(state: &StateTrie, changes: &mut BTreeMap<String, Account>, tx: &Transaction) -> Result<(), String> {
+    let sender = account(state, changes, &tx.from);
+    if sender.balance < tx.value {
+        return Err(format!("insufficient balance: has {}, sends {}", sender.balance, tx.value));
+    }
+    if tx.from == tx.to {
+        return Ok(());
+    }
+    let recipient = account(state, changes, &tx.to);
+    let credited = recipient.balance.checked_add(tx.value).ok_or("recipient balance overflows")?;
+    changes.insert(tx.from.clone(), Account { balance: sender.balance - tx.value, ..sender });
+    changes.insert(tx.to.clone(), Account { balance: credited, ..recipient });
+    Ok(())
+}
+
+pub(crate) fn account(state: &StateTrie, changes: &BTreeMap<String, Account>, address: &str) -> Account {
+    changes.get(address).cloned().unwrap_or_else(|| state.get(address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use ed25519_dalek::SigningKey;
+
+    const CHAIN: u64 = 7;
+    const PROPOSER: &str = "0xproposer";
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+    const CAROL: &str = "cubiq1enxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxvenxqz740yr";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn address(seed: u8) -> String {
+        Address::from_key(&key(seed).verifying_key()).to_string()
+    }
+
+    fn tx(from: u8, nonce: u64, to: &str, value: u64, gas_limit: u64) -> Transaction {
+        let to = to.to_string();
+        let unsigned = Transaction { chain_id: CHAIN, nonce, to, value, gas_limit, max_fee_per_gas: MIN_BASE_FEE, ..Default::default() };
+        unsigned.sign(&key(from))
+    }
+
+    fn env() -> BlockEnv {
+        BlockEnv { chain_id: CHAIN, gas_limit: 1_000_000, base_fee: MIN_BASE_FEE, proposer: Some(PROPOSER.to_string()), vm: Vm::Wasm, timestamp: 0 }
+    }
+
+    fn genesis() -> StateTrie {
+        StateTrie::from_balances(&BTreeMap::from([(address(1), 100_000), (address(2), 30_000)]))
+    }
+
+    #[test]
+    fn transfers_charge_fees_and_count_nonces() {
+        let mut state = genesis();
+        let generous = Transaction { max_fee_per_gas: 3, max_priority_fee_per_gas: 1, ..tx(1, 0, &address(2), 500, TRANSFER_GAS) }.sign(&key(1));
+        let txs = [generous, tx(2, 0, CAROL, 50_000, TRANSFER_GAS)];
+        let execution = execute(&state, &env(), &txs).unwrap();
+
+        assert_eq!(execution.gas_used(), 2 * TRANSFER_GAS);
+        assert_eq!(execution.outcomes[0].events[0].attributes["value"], "500");
+        // The second sender can pay the fee but not the value, so only the fee moves
+        assert!(execution.outcomes[1].error.as_ref().unwrap().contains("insufficient balance"));
+        assert!(execution.outcomes[1].events.is_empty());
+        let root = execution.post_state(&mut state);
+        assert_eq!(state.root(), execution.base);
+        state.set_root(root);
+        assert_eq!(state.get(&address(1)), Account { balance: 100_000 - 500 - 2 * TRANSFER_GAS, nonce: 1, ..Account::default() });
+        assert_eq!(state.get(&address(2)), Account { balance: 30_000 + 500 - TRANSFER_GAS, nonce: 1, ..Account::default() });
+        assert_eq!(state.get(CAROL), Account::default());
+        assert_eq!(state.get(PROPOSER).balance, TRANSFER_GAS);
+    }
+
+    #[test]
+    fn execution_is_deterministic() {
+        let txs: Vec<_> = (0..4).map(|n| tx(1, n, &Address::from_bytes([n as u8; 32]).to_string(), n * 100, TRANSFER_GAS)).collect();
+        let (mut a, mut b) = (genesis(), genesis());
+        let root_a = execute(&a, &env(), &txs).unwrap().post_state(&mut a);
+        let root_b = execute(&b, &env(), &txs).unwrap().post_state(&mut b);
+        assert_eq!(root_a, root_b);
+        assert_ne!(root_a, a.root());
+    }
+
+    #[test]
+    fn invalid_transactions_reject_the_block_but_not_a_selection() {
+        let state = genesis();
+        let mut tampered = tx(1, 0, BOB, 1, TRANSFER_GAS);
+        tampered.value = 2;
+        let broke = tx(3, 0, BOB, 0, TRANSFER_GAS);
+        let cheap = tx(1, 0, BOB, 1, TRANSFER_GAS - 1);
+        let free = Transaction { max_fee_per_gas: 0, ..tx(1, 0, BOB, 1, TRANSFER_GAS) }.sign(&key(1));
+        let good = tx(1, 0, BOB, 1, TRANSFER_GAS);
+
+        assert!(execute(&state, &env(), std::slice::from_ref(&tampered)).unwrap_err().reason.contains("hash"));
+        assert!(execute(&state, &env(), &[good.clone(), broke.clone()]).unwrap_err().reason.contains("cannot pay"));
+        assert!(execute(&state, &env(), std::slice::from_ref(&cheap)).unwrap_err().reason.contains("gas"));
+        assert!(execute(&state, &env(), std::slice::from_ref(&free)).unwrap_err().reason.contains("per gas"));
+
+        let (included, execution) = select(&state, &env(), &[tampered, broke, good.clone(), cheap, free]);
+        assert_eq!(included.iter().map(|tx| &tx.hash).collect::<Vec<_>>(), [&good.hash]);
+        assert_eq!(execution.outcomes.len(), 1);
+    }
+
+    #[test]
+    fn transactions_must_be_signed_by_their_sender_for_this_chain_in_nonce_order() {
+        let state = genesis();
+        let good = tx(1, 0, BOB, 1, TRANSFER_GAS);
+        let mut forged = good.clone();
+        forged.from = address(2);
+        forged.hash = forged.compute_hash();
+        let mut unsigned = good.clone();
+        unsigned.signature = tx(2, 0, BOB, 1, TRANSFER_GAS).signature;
+        let other_chain = Transaction { chain_id: CHAIN + 1, ..good.clone() }.sign(&key(1));
+
+        assert!(execute(&state, &env(), &[forged]).unwrap_err().reason.contains("signature"));
+        assert!(execute(&state, &env(), &[unsigned]).unwrap_err().reason.contains("signature"));
+        assert!(execute(&state, &env(), &[other_chain]).unwrap_err().reason.contains("chain 8"));
+        // The same transaction can't apply twice, nor skip ahead
+        assert!(execute(&state, &env(), &[good.clone(), good.clone()]).unwrap_err().reason.contains("nonce 0"));
+        assert!(execute(&state, &env(), &[tx(1, 1, BOB, 1, TRANSFER_GAS)]).unwrap_err().reason.contains("next is 0"));
+        assert_eq!(execute(&state, &env(), &[good, tx(1, 1, BOB, 1, TRANSFER_GAS)]).unwrap().outcomes.len(), 2);
+    }
+
+    #[test]
+    fn meters_gas_refunds_what_is_left_and_fills_blocks_to_their_limit() {
+        let mut state = genesis();
+        let call = Transaction { data: vec![0, 1], ..tx(1, 0, &address(2), 500, 50_000) }.sign(&key(1));
+        let execution = execute(&state, &env(), std::slice::from_ref(&call)).unwrap();
+        assert_eq!((execution.gas_used(), execution.gas_reserved), (TRANSFER_GAS + 4 + 16, 50_000));
+        let root = execution.post_state(&mut state);
+        state.set_root(root);
+        assert_eq!(state.get(&address(1)).balance, 100_000 - 500 - (TRANSFER_GAS + 20));
+        let short = Transaction { data: vec![1], ..tx(1, 1, BOB, 1, TRANSFER_GAS) }.sign(&key(1));
+        assert!(execute(&state, &env(), &[short]).unwrap_err().reason.contains("needs 21016"));
+
+        // Blocks are limited by what their transactions ask for, not use
+        let cramped = BlockEnv { gas_limit: 2 * TRANSFER_GAS - 1, ..env() };
+        let txs = [tx(1, 1, BOB, 1, TRANSFER_GAS), tx(2, 0, BOB, 1, TRANSFER_GAS)];
+        assert!(execute(&state, &cramped, &txs).unwrap_err().reason.contains("block has 20999 of"));
+        let (included, execution) = select(&state, &cramped, &txs);
+        assert_eq!((included.len(), execution.gas_used()), (1, TRANSFER_GAS));
+
+        let mut meter = GasMeter::new(100);
+        meter.charge(60).unwrap();
+        assert!(meter.charge(41).unwrap_err().contains("out of gas"));
+        assert_eq!((meter.used(), meter.remaining()), (100, 0));
+    }
+
+    #[test]
+    fn burns_the_base_fee_and_pays_tips_to_the_proposer() {
+        let mut state = genesis();
+        let block = BlockEnv { base_fee: 2, ..env() };
+        let capped = Transaction { max_fee_per_gas: 4, max_priority_fee_per_gas: 3, ..tx(1, 0, BOB, 1, 22_000) }.sign(&key(1));
+        let execution = execute(&state, &block, std::slice::from_ref(&capped)).unwrap();
+        // The cap leaves room for a tip of 2, and unused gas is refunded
+        assert_eq!(execution.outcomes[0].gas_price, 4);
+        let root = execution.post_state(&mut state);
+        state.set_root(root);
+        assert_eq!(state.get(&address(1)).balance, 100_000 - 1 - 4 * TRANSFER_GAS);
+        assert_eq!(state.get(PROPOSER).balance, 2 * TRANSFER_GAS);
+
+        let priced_out = tx(1, 1, BOB, 1, TRANSFER_GAS);
+        assert!(execute(&state, &block, std::slice::from_ref(&priced_out)).unwrap_err().reason.contains("the base fee is 2"));
+        assert!(select(&state, &block, &[priced_out]).0.is_empty());
+        let overtipping = Transaction { max_fee_per_gas: 20, max_priority_fee_per_gas: 21, ..tx(1, 1, BOB, 1, TRANSFER_GAS) }.sign(&key(1));
+        assert!(check(CHAIN, &overtipping).unwrap_err().contains("tips up to 21"));
+    }
+
+    #[test]
+    fn simulates_unsigned_transactions_without_committing_them() {
+        let state = genesis();
+        let root = state.root();
+        let draft = Transaction { chain_id: CHAIN, from: address(1), to: BOB.to_string(), value: 500, ..Default::default() };
+        let simulation = simulate(&state, &env(), &draft).unwrap();
+        assert_eq!(state.root(), root);
+        assert_eq!((simulation.gas_used, simulation.gas_price, simulation.error), (TRANSFER_GAS, MIN_BASE_FEE, None));
+        // Without a limit it may spend all the sender has left
+        assert_eq!(simulation.gas_limit, 100_000 - 500);
+        assert_eq!(simulation.events[0].attributes["to"], BOB);
+        let sender = &simulation.diffs[&address(1)];
+        assert_eq!(sender.before.balance, 100_000);
+        assert_eq!(sender.after, Account { balance: 100_000 - 500 - TRANSFER_GAS, nonce: 1, ..Account::default() });
+        assert_eq!(simulation.diffs[BOB].after.balance, 500);
+        // The base fee is burned and no tip is paid
+        assert_eq!(simulation.diffs.len(), 2);
+
+        let overdrawn = simulate(&state, &env(), &Transaction { value: 90_000, gas_limit: 50_000, ..draft.clone() }).unwrap();
+        assert!(overdrawn.error.unwrap().contains("insufficient balance"));
+        assert_eq!(overdrawn.diffs[&address(1)].after.balance, 100_000 - TRANSFER_GAS);
+        assert!(simulate(&state, &env(), &Transaction { gas_limit: 2_000_000, ..draft.clone() }).unwrap_err().contains("a block holds"));
+        assert!(simulate(&state, &env(), &Transaction { chain_id: CHAIN + 1, ..draft }).unwrap_err().contains("chain 8"));
+    }
+}
@@ -0,0 +1,108 @@
+//! Fault injection for resilience testing, in builds with the `chaos`
+//! feature.
+//!
+//! Faults are off until an operator sets them, through `admin_setFaults`
+//! on a devnet node. While set, the node drops the share of gossip it
+//! receives that `gossip_drop_percent` says, as though peers never sent
+//! it; waits `proof_fetch_delay_ms` before fetching each proposal's proof;
+//! and fails the share of finalized block writes `storage_error_percent`
+//! says, as a full or failing disk would.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, default)]
+pub struct FaultConfig {
+    pub gossip_drop_percent: u8,
+    pub proof_fetch_delay_ms: u64,
+    pub storage_error_percent: u8,
+}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, percent) in [("gossip_drop_percent", self.gossip_drop_percent), ("storage_error_percent", self.storage_error_percent)] {
+            if percent > 100 {
+                return Err(format!("{} is {}, more than 100", name, percent));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Faults {
+    config: Mutex<FaultConfig>,
+    /// State of the generator deciding which messages and writes fail
+    seed: AtomicU64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self::new(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64)
+    }
+}
+
+impl Faults {
+    /// No faults, failing messages and writes in an order `seed` decides
+    /// once they are set.
+    pub fn new(seed: u64) -> Self {
+        Self { config: Mutex::new(FaultConfig::default()), seed: AtomicU64::new(seed) }
+    }
+
+    pub fn config(&self) -> FaultConfig {
+        *self.config.lock().expect("Faults lock poisoned")
+    }
+
+    /// Replaces the faults injected, returning those before.
+    pub fn set(&self, config: FaultConfig) -> Result<FaultConfig, String> {
+        config.validate()?;
+        Ok(std::mem::replace(&mut *self.config.lock().expect("Faults lock poisoned"), config))
+    }
+
+    /// Whether to drop the gossip message just received.
+    pub fn drop_gossip(&self) -> bool {
+        self.roll(self.config().gossip_drop_percent)
+    }
+
+    pub fn proof_fetch_delay(&self) -> Duration {
+        Duration::from_millis(self.config().proof_fetch_delay_ms)
+    }
+
+    /// An error for the storage write about to happen, if it is to fail.
+    pub fn storage_error(&self) -> Option<String> {
+        self.roll(self.config().storage_error_percent).then(|| "injected storage write error".to_string())
+    }
+
+    /// True `percent` of the time, by splitmix64.
+    fn roll(&self, percent: u8) -> bool {
+        if percent == 0 {
+            return false;
+        }
+        let mut z = self.seed.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) % 100 < percent as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injects_the_configured_share_of_faults() {
+        let faults = Faults::new(7);
+        assert!(!(0..1000).any(|_| faults.drop_gossip() || faults.storage_error().is_some()));
+        let config = FaultConfig { gossip_drop_percent: 30, proof_fetch_delay_ms: 250, storage_error_percent: 100 };
+        assert_eq!(faults.set(config), Ok(FaultConfig::default()));
+        let dropped = (0..10_000).filter(|_| faults.drop_gossip()).count();
+        assert!((2_700..3_300).contains(&dropped), "{dropped}");
+        assert_eq!((faults.proof_fetch_delay(), faults.storage_error().is_some()), (Duration::from_millis(250), true));
+        assert!(faults.set(FaultConfig { gossip_drop_percent: 101, ..config }).is_err());
+        assert_eq!(faults.config(), config);
+    }
+}
@@ -0,0 +1,134 @@
+//! The fee market.
+//!
+//! Every block has a base fee per unit of gas, which each of its
+//! transactions pays for the gas it uses and which is burned. A
+//! transaction also offers its block's proposer a tip per unit of gas, up
+//! to `max_priority_fee_per_gas`, and caps what it pays in all at
+//! `max_fee_per_gas`; it can only be in blocks whose base fee is within
+//! that cap.
+//!
+//! Blocks aim to be `1 / ELASTICITY` full. After a fuller block the base
+//! fee rises, and after an emptier one it falls, by at most
+//! `1 / BASE_FEE_CHANGE_DENOMINATOR` per block and never below
+//! `MIN_BASE_FEE`. Each block carries its base fee, which validators check
+//! against what its parent's gas usage gives.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{BlockProposal, Transaction};
+
+/// Least base fee per unit of gas.
+pub const MIN_BASE_FEE: u64 = 1;
+
+/// How many times its target gas a block may use.
+pub const ELASTICITY: u64 = 2;
+
+/// The base fee moves by at most its own share of this per block.
+pub const BASE_FEE_CHANGE_DENOMINATOR: u64 = 8;
+
+/// Recent blocks whose tips `estimate` samples.
+pub const FEE_HISTORY_BLOCKS: usize = 20;
+
+/// Percentile of recent tips `estimate` suggests.
+pub const TIP_PERCENTILE: usize = 60;
+
+/// Tip suggested when recent blocks paid none.
+pub const DEFAULT_PRIORITY_FEE: u64 = 1;
+
+/// The base fee of the block after one with `base_fee` that used
+/// `gas_used` of `gas_limit`.
+pub fn next_base_fee(base_fee: u64, gas_used: u64, gas_limit: u64) -> u64 {
+    let target = (gas_limit / ELASTICITY).max(1);
+    let change = |gap: u64| base_fee as u128 * gap as u128 / target as u128 / BASE_FEE_CHANGE_DENOMINATOR as u128;
+    let next = if gas_used > target {
+        base_fee.saturating_add(change(gas_used - target).clamp(1, u64::MAX as u128) as u64)
+    } else {
+        base_fee - change(target - gas_used) as u64
+    };
+    next.max(MIN_BASE_FEE)
+}
+
+/// The tip per unit of gas `tx` pays in a block with `base_fee`, or
+/// nothing if it can't be in one.
+pub fn effective_tip(tx: &Transaction, base_fee: u64) -> Option<u64> {
+    let headroom = tx.max_fee_per_gas.checked_sub(base_fee)?;
+    Some(headroom.min(tx.max_priority_fee_per_gas))
+}
+
+/// Fees to offer for a transaction to go in the next few blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeEstimate {
+    /// Of the next block
+    pub base_fee: u64,
+    /// `TIP_PERCENTILE` of the tips paid in the sampled blocks
+    pub max_priority_fee_per_gas: u64,
+    /// Twice the base fee plus the tip, enough to stay includable while
+    /// the base fee rises for several blocks
+    pub max_fee_per_gas: u64,
+    /// How many blocks were sampled
+    pub blocks: usize,
+}
+
+/// Suggests fees for the block after `recent`, the newest last, whose base
+/// fee is `base_fee`.
+pub fn estimate<'a>(recent: impl DoubleEndedIterator<Item = &'a BlockProposal>, base_fee: u64) -> FeeEstimate {
+    let mut blocks = 0;
+    let mut tips = vec![];
+    for block in recent.rev().take(FEE_HISTORY_BLOCKS) {
+        blocks += 1;
+        tips.extend(block.transactions.iter().filter_map(|tx| effective_tip(tx, block.base_fee)));
+    }
+    tips.sort_unstable();
+    let tip = match tips.len() {
+        0 => DEFAULT_PRIORITY_FEE,
+        len => tips[(len - 1) * TIP_PERCENTILE / 100],
+    };
+    FeeEstimate {
+        base_fee,
+        max_priority_fee_per_gas: tip,
+        max_fee_per_gas: base_fee.saturating_mul(2).saturating_add(tip),
+        blocks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: u64 = 30_000_000;
+
+    #[test]
+    fn base_fee_follows_gas_usage_toward_the_target() {
+        assert_eq!(next_base_fee(1_000, LIMIT / 2, LIMIT), 1_000);
+        assert_eq!(next_base_fee(1_000, LIMIT, LIMIT), 1_125);
+        assert_eq!(next_base_fee(1_000, 0, LIMIT), 875);
+        assert_eq!(next_base_fee(1_000, LIMIT * 3 / 4, LIMIT), 1_062);
+        // A fuller block always raises it, and nothing lowers it past the
+        // floor
+        assert_eq!(next_base_fee(MIN_BASE_FEE, LIMIT / 2 + 1, LIMIT), MIN_BASE_FEE + 1);
+        assert_eq!(next_base_fee(MIN_BASE_FEE, 0, LIMIT), MIN_BASE_FEE);
+        assert_eq!(next_base_fee(u64::MAX, LIMIT, LIMIT), u64::MAX);
+    }
+
+    #[test]
+    fn suggests_recent_tips() {
+        let tx = |max_fee_per_gas, max_priority_fee_per_gas| Transaction { max_fee_per_gas, max_priority_fee_per_gas, ..Transaction::default() };
+        let block = |base_fee, transactions| BlockProposal {
+            block_hash: String::new(),
+            state_root: String::new(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions,
+            proposer_id: String::new(),
+            timestamp: 0,
+            base_fee,
+            gas_used: 0,
+        };
+        assert_eq!(effective_tip(&tx(10, 3), 8), Some(2));
+        assert_eq!(effective_tip(&tx(10, 3), 11), None);
+
+        let empty = estimate([].iter(), 10);
+        assert_eq!((empty.max_priority_fee_per_gas, empty.max_fee_per_gas, empty.blocks), (DEFAULT_PRIORITY_FEE, 21, 0));
+        let blocks = [block(10, vec![tx(20, 1), tx(20, 5)]), block(12, vec![tx(20, 2), tx(14, 9), tx(20, 3)])];
+        assert_eq!(estimate(blocks.iter(), 12), FeeEstimate { base_fee: 12, max_priority_fee_per_gas: 2, max_fee_per_gas: 26, blocks: 2 });
+    }
+}
@@ -0,0 +1,218 @@
+//! Append-only flat files for old blocks, the "freezer".
+//!
+//! Headers and bodies of blocks far enough behind the head move out of the
+//! database into one table each. A table is a data file of records back to
+//! back, and an index file with a 16-byte entry per height: the record's
+//! offset and length, big-endian, at `(height - 1) * 16`. A zero length
+//! means there is no record, e.g. for a body the node never saw.
+//!
+//! Heights are frozen in order from 1. Data is written before its index
+//! entry, so after a crash anything past the last complete entry is cut
+//! off when the freezer is opened, and both tables are cut to the shorter.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+const ENTRY_BYTES: u64 = 16;
+
+#[derive(Debug)]
+struct Table {
+    data: File,
+    index: File,
+    /// Heights indexed
+    entries: u64,
+    data_len: u64,
+}
+
+impl Table {
+    fn open(dir: &Path, name: &str) -> io::Result<Self> {
+        let open = |ext: &str| {
+            let path = dir.join(format!("{}.{}", name, ext));
+            OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+        };
+        let mut table = Table { data: open("dat")?, index: open("idx")?, entries: 0, data_len: 0 };
+        let data_len = table.data.metadata()?.len();
+        let mut entries = table.index.metadata()?.len() / ENTRY_BYTES;
+        // Drop entries whose data never made it to disk
+        while entries > 0 {
+            let (offset, len) = table.entry(entries)?;
+            if offset + len <= data_len {
+                break;
+            }
+            entries -= 1;
+        }
+        table.truncate(entries)?;
+        Ok(table)
+    }
+
+    /// Offset and length of `height`'s record.
+    fn entry(&mut self, height: u64) -> io::Result<(u64, u64)> {
+        let mut entry = [0u8; ENTRY_BYTES as usize];
+        self.index.seek(SeekFrom::Start((height - 1) * ENTRY_BYTES))?;
+        self.index.read_exact(&mut entry)?;
+        let (offset, len) = entry.split_at(8);
+        Ok((u64::from_be_bytes(offset.try_into().unwrap()), u64::from_be_bytes(len.try_into().unwrap())))
+    }
+
+    fn truncate(&mut self, entries: u64) -> io::Result<()> {
+        let data_len = match entries {
+            0 => 0,
+            _ => {
+                let (offset, len) = self.entry(entries)?;
+                offset + len
+            }
+        };
+        self.index.set_len(entries * ENTRY_BYTES)?;
+        self.data.set_len(data_len)?;
+        self.entries = entries;
+        self.data_len = data_len;
+        Ok(())
+    }
+
+    fn append(&mut self, record: Option<&[u8]>) -> io::Result<()> {
+        let record = record.unwrap_or_default();
+        self.data.seek(SeekFrom::Start(self.data_len))?;
+        self.data.write_all(record)?;
+        let mut entry = [0u8; ENTRY_BYTES as usize];
+        entry[..8].copy_from_slice(&self.data_len.to_be_bytes());
+        entry[8..].copy_from_slice(&(record.len() as u64).to_be_bytes());
+        self.index.seek(SeekFrom::Start(self.entries * ENTRY_BYTES))?;
+        self.index.write_all(&entry)?;
+        self.data_len += record.len() as u64;
+        self.entries += 1;
+        Ok(())
+    }
+
+    fn get(&mut self, height: u64) -> io::Result<Option<Vec<u8>>> {
+        if height == 0 || height > self.entries {
+            return Ok(None);
+        }
+        let (offset, len) = self.entry(height)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        let mut record = vec![0u8; len as usize];
+        self.data.seek(SeekFrom::Start(offset))?;
+        self.data.read_exact(&mut record)?;
+        Ok(Some(record))
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.data.sync_data()?;
+        self.index.sync_data()
+    }
+}
+
+#[derive(Debug)]
+pub struct Freezer {
+    headers: Mutex<Table>,
+    blocks: Mutex<Table>,
+}
+
+impl Freezer {
+    /// Opens or creates the freezer in `dir`, discarding a torn last write.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let mut headers = Table::open(dir, "headers")?;
+        let mut blocks = Table::open(dir, "blocks")?;
+        let frozen = headers.entries.min(blocks.entries);
+        headers.truncate(frozen)?;
+        blocks.truncate(frozen)?;
+        Ok(Self { headers: Mutex::new(headers), blocks: Mutex::new(blocks) })
+    }
+
+    /// Heights frozen so far; they are `1..=frozen()`.
+    pub fn frozen(&self) -> u64 {
+        self.headers.lock().unwrap().entries
+    }
+
+    /// Appends the next height's encoded header and body. Call `sync` to
+    /// make a run of appends durable.
+    pub fn freeze(&self, height: u64, header: Option<&[u8]>, body: Option<&[u8]>) -> io::Result<()> {
+        let mut headers = self.headers.lock().unwrap();
+        if height != headers.entries + 1 {
+            let message = format!("freezer expected height {}, got {}", headers.entries + 1, height);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        self.blocks.lock().unwrap().append(body)?;
+        headers.append(header)
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.blocks.lock().unwrap().sync()?;
+        self.headers.lock().unwrap().sync()
+    }
+
+    /// Bytes in the data and index files of both tables.
+    pub fn size(&self) -> u64 {
+        [&self.headers, &self.blocks]
+            .iter()
+            .map(|table| {
+                let table = table.lock().unwrap();
+                table.data_len + table.entries * ENTRY_BYTES
+            })
+            .sum()
+    }
+
+    pub fn header(&self, height: u64) -> io::Result<Option<Vec<u8>>> {
+        self.headers.lock().unwrap().get(height)
+    }
+
+    pub fn block(&self, height: u64) -> io::Result<Option<Vec<u8>>> {
+        self.blocks.lock().unwrap().get(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("cubiq-freezer-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn frozen_records_read_back_after_reopening() {
+        let dir = dir("reopen");
+        {
+            let freezer = Freezer::open(&dir).unwrap();
+            freezer.freeze(1, Some(b"h1"), Some(b"body one")).unwrap();
+            freezer.freeze(2, Some(b"h2"), None).unwrap();
+            assert!(freezer.freeze(4, None, None).is_err());
+            freezer.sync().unwrap();
+        }
+        let freezer = Freezer::open(&dir).unwrap();
+        assert_eq!(freezer.frozen(), 2);
+        assert_eq!(freezer.block(1).unwrap().as_deref(), Some(&b"body one"[..]));
+        assert_eq!((freezer.header(2).unwrap().as_deref(), freezer.block(2).unwrap()), (Some(&b"h2"[..]), None));
+        assert_eq!(freezer.block(3).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_writes_are_cut_off() {
+        let dir = dir("torn");
+        {
+            let freezer = Freezer::open(&dir).unwrap();
+            for height in 1..=3 {
+                freezer.freeze(height, Some(b"header"), Some(b"body")).unwrap();
+            }
+        }
+        // The last body lost its data, and the headers got half an entry
+        let blocks = OpenOptions::new().write(true).open(dir.join("blocks.dat")).unwrap();
+        blocks.set_len(9).unwrap();
+        let mut headers = OpenOptions::new().append(true).open(dir.join("headers.idx")).unwrap();
+        headers.write_all(&[0; 7]).unwrap();
+
+        let freezer = Freezer::open(&dir).unwrap();
+        assert_eq!(freezer.frozen(), 2);
+        assert_eq!(freezer.header(3).unwrap(), None);
+        freezer.freeze(3, Some(b"again"), Some(b"body")).unwrap();
+        assert_eq!(freezer.header(3).unwrap().as_deref(), Some(&b"again"[..]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
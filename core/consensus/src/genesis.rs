@@ -0,0 +1,339 @@
+//! Chain specification and genesis state.
+//!
+//! A `ChainSpec` fixes everything nodes must agree on before the first
+//! block: the chain id, the genesis validators and their stakes, initial
+//! account balances and consensus parameters. Specs are JSON, or TOML when
+//! the file name ends in `.toml`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use crate::address::{self, Address};
+use crate::execution::TRANSFER_GAS;
+use crate::fees::MIN_BASE_FEE;
+use crate::registration::Registration;
+use crate::{Validator, ValidatorSet};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainSpec {
+    pub name: String,
+    pub chain_id: u64,
+    /// Unix timestamp of the genesis block
+    pub genesis_time: u64,
+    pub validators: Vec<GenesisValidator>,
+    /// Initial balance per account
+    #[serde(default)]
+    pub accounts: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub params: ConsensusParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    pub node_id: String,
+    /// Hex-encoded ed25519 public key
+    pub public_key: String,
+    pub stake: u64,
+    /// Signed link to its network key and reward account, for `node_id`
+    /// and `public_key`; see `registration`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<Registration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsensusParams {
+    /// Target time between blocks
+    pub block_time_ms: u64,
+    /// Blocks per epoch; validator set changes apply at epoch boundaries
+    pub epoch_length: u64,
+    /// Fraction of total stake that finalizes a block, as numerator and
+    /// denominator
+    pub supermajority_numerator: u64,
+    pub supermajority_denominator: u64,
+    /// Most gas a block's transactions may ask for between them
+    pub block_gas_limit: u64,
+    /// Base fee of the first block; see `fees`
+    pub initial_base_fee: u64,
+    /// What contracts run on; left out when WASM so the genesis hashes of
+    /// specs from before there was a choice stay the same
+    #[serde(skip_serializing_if = "Vm::is_wasm")]
+    pub vm: Vm,
+}
+
+/// The virtual machine a chain's contracts run on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vm {
+    /// WebAssembly modules; see `contracts`
+    #[default]
+    Wasm,
+    /// Ethereum bytecode, for nodes built with the `evm` feature; see
+    /// `evm`
+    Evm,
+}
+
+impl Vm {
+    fn is_wasm(&self) -> bool {
+        *self == Vm::Wasm
+    }
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            block_time_ms: 2_000,
+            epoch_length: 1_000,
+            supermajority_numerator: 2,
+            supermajority_denominator: 3,
+            block_gas_limit: 30_000_000,
+            initial_base_fee: MIN_BASE_FEE,
+            vm: Vm::Wasm,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::Io(err) => write!(f, "Chain spec I/O error: {}", err),
+            GenesisError::Parse(err) => write!(f, "Malformed chain spec: {}", err),
+            GenesisError::Invalid(err) => write!(f, "Invalid chain spec: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
+
+/// Chain id of development chains made by `cubiq init`: "cubi" in ASCII,
+/// which no public network uses, so their transactions replay nowhere.
+pub const DEV_CHAIN_ID: u64 = 0x6375_6269;
+
+impl ChainSpec {
+    /// A single-validator chain for local development, funding the
+    /// validator's account.
+    pub fn dev(chain_id: u64, validator: GenesisValidator, genesis_time: u64) -> Self {
+        let account = match &validator.registration {
+            Some(registration) => registration.reward_account.clone(),
+            None => Address::from_public_key(&validator.public_key).map_or_else(|_| validator.public_key.clone(), |address| address.to_string()),
+        };
+        let accounts = BTreeMap::from([(account, 1_000_000_000)]);
+        Self {
+            name: "cubiq-dev".to_string(),
+            chain_id,
+            genesis_time,
+            validators: vec![validator],
+            accounts,
+            params: ConsensusParams::default(),
+        }
+    }
+
+    /// Reads and validates a spec.
+    pub fn load(path: &Path) -> Result<Self, GenesisError> {
+        let text = std::fs::read_to_string(path).map_err(|e| GenesisError::Io(format!("{}: {}", path.display(), e)))?;
+        let spec: ChainSpec = if is_toml(path) {
+            toml::from_str(&text).map_err(|e| GenesisError::Parse(e.to_string()))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| GenesisError::Parse(e.to_string()))?
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), GenesisError> {
+        let text = if is_toml(path) {
+            toml::to_string_pretty(self).map_err(|e| GenesisError::Parse(e.to_string()))?
+        } else {
+            serde_json::to_string_pretty(self).map_err(|e| GenesisError::Parse(e.to_string()))?
+        };
+        std::fs::write(path, text).map_err(|e| GenesisError::Io(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn validate(&self) -> Result<(), GenesisError> {
+        if self.validators.is_empty() {
+            return Err(GenesisError::Invalid("at least one genesis validator is required".to_string()));
+        }
+        let mut node_ids = HashSet::new();
+        for validator in &self.validators {
+            if !node_ids.insert(validator.node_id.as_str()) {
+                return Err(GenesisError::Invalid(format!("validator {} is listed twice", validator.node_id)));
+            }
+            if validator.stake == 0 {
+                return Err(GenesisError::Invalid(format!("validator {} has no stake", validator.node_id)));
+            }
+            if hex::decode(&validator.public_key).map_or(true, |key| key.len() != 32) {
+                return Err(GenesisError::Invalid(format!(
+                    "validator {} public key is not a hex-encoded ed25519 key",
+                    validator.node_id
+                )));
+            }
+            if let Some(registration) = &validator.registration {
+                let invalid = |why: String| GenesisError::Invalid(format!("validator {} registration {}", validator.node_id, why));
+                if registration.node_id != validator.node_id || !registration.consensus_key.eq_ignore_ascii_case(&validator.public_key) {
+                    return Err(invalid("is for another node id or key".to_string()));
+                }
+                registration.verify().map_err(invalid)?;
+            }
+        }
+        if self.validators.iter().try_fold(0u64, |total, v| total.checked_add(v.stake)).is_none() {
+            return Err(GenesisError::Invalid("total stake overflows".to_string()));
+        }
+        for account in self.accounts.keys() {
+            address::check_account(account).map_err(|e| GenesisError::Invalid(format!("account {}: {}", account, e)))?;
+        }
+        let params = &self.params;
+        if params.supermajority_denominator == 0
+            || params.supermajority_numerator as u128 * 2 <= params.supermajority_denominator as u128
+            || params.supermajority_numerator > params.supermajority_denominator
+        {
+            return Err(GenesisError::Invalid("supermajority must be a fraction above one half".to_string()));
+        }
+        if params.block_time_ms == 0 || params.epoch_length == 0 {
+            return Err(GenesisError::Invalid("block time and epoch length must be positive".to_string()));
+        }
+        if params.block_gas_limit < TRANSFER_GAS {
+            return Err(GenesisError::Invalid(format!("block gas limit must fit a transfer's {} gas", TRANSFER_GAS)));
+        }
+        if params.initial_base_fee < MIN_BASE_FEE {
+            return Err(GenesisError::Invalid(format!("initial base fee must be at least {}", MIN_BASE_FEE)));
+        }
+        if params.vm == Vm::Evm && !cfg!(feature = "evm") {
+            return Err(GenesisError::Invalid("the chain runs the EVM, which this node was built without".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Hash identifying this genesis; nodes with different specs for the
+    /// same chain id are on different chains.
+    pub fn genesis_hash(&self) -> String {
+        let canonical = serde_json::to_vec(self).expect("chain specs serialize");
+        format!("0x{}", blake3::hash(&canonical).to_hex())
+    }
+
+    /// The validator set at height zero.
+    pub fn validator_set(&self) -> ValidatorSet {
+        let validators: HashMap<String, Validator> = self
+            .validators
+            .iter()
+            .map(|v| {
+                let validator = Validator {
+                    node_id: v.node_id.clone(),
+                    stake: v.stake,
+                    public_key: v.public_key.clone(),
+                    is_active: true,
+                    last_vote_time: self.genesis_time,
+                    registration: v.registration.clone(),
+                };
+                (v.node_id.clone(), validator)
+            })
+            .collect();
+        let total_stake: u64 = self.validators.iter().map(|v| v.stake).sum();
+        let supermajority_threshold = supermajority_threshold(total_stake, &self.params);
+        ValidatorSet { validators, total_stake, supermajority_threshold, pending_rotations: BTreeMap::new(), pending_calls: vec![] }
+    }
+}
+
+/// Stake that finalizes a block when validators have `total_stake`
+/// between them: more than `params`' supermajority of it.
+pub fn supermajority_threshold(total_stake: u64, params: &ConsensusParams) -> u64 {
+    (total_stake as u128 * params.supermajority_numerator as u128 / params.supermajority_denominator as u128) as u64 + 1
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "toml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(node_id: &str, stake: u64) -> GenesisValidator {
+        GenesisValidator { node_id: node_id.to_string(), public_key: hex::encode([node_id.len() as u8; 32]), stake, registration: None }
+    }
+
+    #[test]
+    fn round_trips_json_and_toml() {
+        let mut spec = ChainSpec::dev(7, validator("v1", 100), 1_700_000_000);
+        spec.validators.push(validator("v22", 50));
+        for name in ["genesis.json", "genesis.toml"] {
+            let path = std::env::temp_dir().join(format!("cubiq-{}-{}", std::process::id(), name));
+            spec.save(&path).unwrap();
+            let loaded = ChainSpec::load(&path).unwrap();
+            assert_eq!(loaded, spec);
+            assert_eq!(loaded.genesis_hash(), spec.genesis_hash());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn seeds_validator_set_with_supermajority() {
+        let mut spec = ChainSpec::dev(7, validator("v1", 100), 0);
+        spec.validators.push(validator("v22", 50));
+        let set = spec.validator_set();
+        assert_eq!(set.total_stake, 150);
+        assert_eq!(set.supermajority_threshold, 101);
+        assert!(set.validators["v22"].is_active);
+    }
+
+    #[test]
+    fn registered_validators_are_paid_at_their_reward_account() {
+        let key = |seed: u8| ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+        let reward_account = Address::from_key(&key(3).verifying_key());
+        let registration = Registration::signed("v1", &key(1), &key(2), &reward_account);
+        let public_key = registration.consensus_key.clone();
+        let spec = ChainSpec::dev(7, GenesisValidator { public_key, registration: Some(registration), ..validator("v1", 100) }, 0);
+        assert_eq!(spec.validate(), Ok(()));
+        assert_eq!(spec.accounts.keys().collect::<Vec<_>>(), [&reward_account.to_string()]);
+        assert_eq!(spec.validator_set().account("v1"), Some(reward_account.to_string()));
+
+        let mut renamed = spec.clone();
+        renamed.validators[0].node_id = "v2".to_string();
+        assert!(renamed.validate().unwrap_err().to_string().contains("another node id"));
+        let mut forged = spec;
+        forged.validators[0].registration.as_mut().unwrap().reward_account = Address::from_bytes([9; 32]).to_string();
+        assert!(forged.validate().unwrap_err().to_string().contains("signature does not match"));
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        let mut duplicate = ChainSpec::dev(7, validator("v1", 100), 0);
+        duplicate.validators.push(validator("v1", 5));
+        assert!(matches!(duplicate.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut bad_key = ChainSpec::dev(7, validator("v1", 100), 0);
+        bad_key.validators[0].public_key = "0xnot-hex".to_string();
+        assert!(matches!(bad_key.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut typo = ChainSpec::dev(7, validator("v1", 100), 0);
+        let funded = typo.accounts.keys().next().unwrap().clone();
+        assert!(funded.starts_with("cubiq1"));
+        typo.accounts.insert(funded.replacen('q', "p", 2), 5);
+        assert!(typo.validate().unwrap_err().to_string().contains("checksum"));
+
+        let mut weak = ChainSpec::dev(7, validator("v1", 100), 0);
+        weak.params.supermajority_numerator = 1;
+        weak.params.supermajority_denominator = 2;
+        assert!(matches!(weak.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut cramped = ChainSpec::dev(7, validator("v1", 100), 0);
+        cramped.params.block_gas_limit = TRANSFER_GAS - 1;
+        assert!(matches!(cramped.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut free = ChainSpec::dev(7, validator("v1", 100), 0);
+        free.params.initial_base_fee = 0;
+        assert!(matches!(free.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut evm = ChainSpec::dev(7, validator("v1", 100), 0);
+        evm.params.vm = Vm::Evm;
+        assert_eq!(evm.validate().is_ok(), cfg!(feature = "evm"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Consensus health, derived from the blocks the node verifies and the
+//! votes that finalize them.
+//!
+//! Finality latency runs from when consensus takes a proposal off its
+//! queue, as in `timings`, to when votes finalize it; blocks that finalize
+//! before this node verifies them aren't timed. The share of it spent
+//! fetching proofs tells a slow resolver apart from slow voters.
+//!
+//! A validator misses a block if no vote from it for the block has arrived
+//! by the time the next block finalizes, which leaves votes slower than
+//! the supermajority one block to arrive. Only blocks this node saw
+//! finalize are assessed, so a restart doesn't count votes it never kept
+//! as missed.
+//!
+//! A round change is a verified block in a different round from the one
+//! verified before it.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Newest blocks each figure covers: finalized blocks for latency and
+/// missed votes, verified blocks for round changes.
+pub const WINDOW: usize = 64;
+
+/// How long one block took to finalize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finality {
+    pub latency: Duration,
+    pub proof_fetch: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ConsensusHealth {
+    /// Finalized blocks timed, at most `WINDOW`
+    pub finalized_samples: usize,
+    pub mean_finality_ms: u64,
+    pub max_finality_ms: u64,
+    /// Fraction of the finality latency spent fetching proofs
+    pub proof_fetch_share: f64,
+    /// Finalized blocks whose votes are assessed, at most `WINDOW`
+    pub assessed_blocks: usize,
+    /// How many of the assessed blocks each validator missed, for those
+    /// that missed any
+    pub missed_votes: BTreeMap<String, usize>,
+    /// Verified blocks looked at for round changes, at most `WINDOW`
+    pub verified_blocks: usize,
+    /// Round changes per verified block
+    pub round_change_rate: f64,
+}
+
+/// A verified block awaiting finalization.
+#[derive(Debug)]
+struct Verified {
+    block_hash: String,
+    received: Instant,
+    proof_fetch: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    /// Oldest first, at most `WINDOW`
+    verified: VecDeque<Verified>,
+    finality: VecDeque<Finality>,
+    /// Validators that missed each assessed block
+    missed: VecDeque<Vec<String>>,
+    /// Whether each recently verified block changed rounds
+    round_changes: VecDeque<bool>,
+    round: Option<u32>,
+    /// Newest block seen to finalize, whose votes are assessed next
+    last_finalized: Option<String>,
+}
+
+fn push<T>(queue: &mut VecDeque<T>, item: T) {
+    if queue.len() == WINDOW {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+impl HealthTracker {
+    /// Records `block_hash` as verified in `round`, from a proposal taken
+    /// off the queue at `received` whose proof took `proof_fetch` to
+    /// fetch. Returns whether the round changed.
+    pub fn verified(&mut self, block_hash: &str, round: u32, received: Instant, proof_fetch: Duration) -> bool {
+        let changed = self.round.is_some_and(|previous| previous != round);
+        self.round = Some(round);
+        push(&mut self.round_changes, changed);
+        push(&mut self.verified, Verified { block_hash: block_hash.to_string(), received, proof_fetch });
+        changed
+    }
+
+    /// Records `block_hash` as finalized, returning how long it took if
+    /// this node verified it, and the block finalized before it, whose
+    /// votes are due for `missed`.
+    pub fn finalized(&mut self, block_hash: &str) -> (Option<Finality>, Option<String>) {
+        let finality = self.verified.iter().position(|verified| verified.block_hash == block_hash).map(|index| {
+            let verified = self.verified.remove(index).expect("the index was just found");
+            Finality { latency: verified.received.elapsed(), proof_fetch: verified.proof_fetch }
+        });
+        if let Some(finality) = finality {
+            push(&mut self.finality, finality);
+        }
+        (finality, self.last_finalized.replace(block_hash.to_string()))
+    }
+
+    /// Records the validators that missed an assessed block.
+    pub fn missed(&mut self, validators: Vec<String>) {
+        push(&mut self.missed, validators);
+    }
+
+    pub fn summary(&self) -> ConsensusHealth {
+        let finalized_samples = self.finality.len();
+        let latency: Duration = self.finality.iter().map(|finality| finality.latency).sum();
+        let proof_fetch: Duration = self.finality.iter().map(|finality| finality.proof_fetch).sum();
+        let mut missed_votes = BTreeMap::new();
+        for validator in self.missed.iter().flatten() {
+            *missed_votes.entry(validator.clone()).or_insert(0) += 1;
+        }
+        let verified_blocks = self.round_changes.len();
+        let round_changes = self.round_changes.iter().filter(|changed| **changed).count();
+        ConsensusHealth {
+            finalized_samples,
+            mean_finality_ms: latency.checked_div(finalized_samples as u32).unwrap_or_default().as_millis() as u64,
+            max_finality_ms: self.finality.iter().map(|finality| finality.latency.as_millis() as u64).max().unwrap_or(0),
+            proof_fetch_share: if latency.is_zero() { 0.0 } else { proof_fetch.as_secs_f64() / latency.as_secs_f64() },
+            assessed_blocks: self.missed.len(),
+            missed_votes,
+            verified_blocks,
+            round_change_rate: if verified_blocks == 0 { 0.0 } else { round_changes as f64 / verified_blocks as f64 },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_blocks_it_verified_and_assesses_the_one_before() {
+        let mut health = HealthTracker::default();
+        assert_eq!(health.summary(), ConsensusHealth::default());
+        let received = Instant::now() - Duration::from_millis(400);
+        assert!(!health.verified("b1", 0, received, Duration::from_millis(100)));
+        assert!(!health.verified("b2", 0, received, Duration::from_millis(100)));
+        assert!(health.verified("b3", 1, received, Duration::from_millis(100)));
+
+        let (finality, previous) = health.finalized("b1");
+        assert!(finality.unwrap().latency >= Duration::from_millis(400));
+        assert_eq!(previous, None);
+        // Finalized before this node verified it
+        let (finality, previous) = health.finalized("b0");
+        assert_eq!((finality, previous.as_deref()), (None, Some("b1")));
+        health.missed(vec!["v2".to_string()]);
+        assert_eq!(health.finalized("b2").1.as_deref(), Some("b0"));
+        health.missed(vec!["v2".to_string(), "v3".to_string()]);
+
+        let summary = health.summary();
+        assert_eq!((summary.finalized_samples, summary.assessed_blocks, summary.verified_blocks), (2, 2, 3));
+        assert!(summary.mean_finality_ms >= 400 && summary.proof_fetch_share > 0.0 && summary.proof_fetch_share <= 0.25);
+        assert_eq!(summary.missed_votes, BTreeMap::from([("v2".to_string(), 2), ("v3".to_string(), 1)]));
+        assert!((summary.round_change_rate - 1.0 / 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,211 @@
+//! Secondary indices for explorers: transactions by address and blocks by
+//! proposer, added as blocks finalize, and votes by validator, added as
+//! votes are recorded. On restart `QubeNode::restore` rebuilds the first
+//! two from the block bodies still stored; votes are not stored, so the
+//! last starts over empty.
+//!
+//! Lists are paged newest first. Every entry carries a sequence number,
+//! increasing across the whole index, that doubles as the page cursor.
+//! Each key keeps its latest `MAX_ENTRIES_PER_KEY` entries.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{BlockProposal, Vote};
+
+pub const MAX_ENTRIES_PER_KEY: usize = 10_000;
+pub const DEFAULT_PAGE: usize = 25;
+pub const MAX_PAGE: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxEntry {
+    pub seq: u64,
+    pub tx_hash: String,
+    pub block_hash: String,
+    /// Finalized height of the block
+    pub height: u64,
+    pub from: String,
+    pub to: String,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub seq: u64,
+    pub block_hash: String,
+    pub height: u64,
+    pub timestamp: u64,
+    pub transaction_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteEntry {
+    pub seq: u64,
+    pub block_hash: String,
+    pub timestamp: u64,
+}
+
+/// One page of a list, with the cursor for the next, older, page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Postings<T> {
+    lists: HashMap<String, VecDeque<T>>,
+}
+
+impl<T> Default for Postings<T> {
+    fn default() -> Self {
+        Self { lists: HashMap::new() }
+    }
+}
+
+impl<T: Clone> Postings<T> {
+    fn push(&mut self, key: &str, entry: T) {
+        let list = self.lists.entry(key.to_string()).or_default();
+        if list.len() == MAX_ENTRIES_PER_KEY {
+            list.pop_front();
+        }
+        list.push_back(entry);
+    }
+
+    /// Up to `limit` entries older than `before`, newest first.
+    fn page(&self, key: &str, before: Option<u64>, limit: usize, seq: impl Fn(&T) -> u64) -> Page<T> {
+        let Some(list) = self.lists.get(key) else {
+            return Page { items: Vec::new(), next: None };
+        };
+        let end = before.map_or(list.len(), |before| list.partition_point(|entry| seq(entry) < before));
+        let start = end.saturating_sub(limit.clamp(1, MAX_PAGE));
+        let items: Vec<T> = list.range(start..end).rev().cloned().collect();
+        let next = (start > 0).then(|| items.last().map(&seq)).flatten();
+        Page { items, next }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChainIndex {
+    next_seq: u64,
+    transactions: Postings<TxEntry>,
+    blocks: Postings<BlockEntry>,
+    votes: Postings<VoteEntry>,
+}
+
+impl ChainIndex {
+    fn seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
+    /// Indexes a block finalized at `height` and the transactions in it.
+    pub fn finalized(&mut self, block: &BlockProposal, height: u64) {
+        let seq = self.seq();
+        self.blocks.push(
+            &block.proposer_id,
+            BlockEntry {
+                seq,
+                block_hash: block.block_hash.clone(),
+                height,
+                timestamp: block.timestamp,
+                transaction_count: block.transactions.len(),
+            },
+        );
+        for tx in &block.transactions {
+            let seq = self.seq();
+            let entry = TxEntry {
+                seq,
+                tx_hash: tx.hash.clone(),
+                block_hash: block.block_hash.clone(),
+                height,
+                from: tx.from.clone(),
+                to: tx.to.clone(),
+                value: tx.value,
+            };
+            if tx.to != tx.from {
+                self.transactions.push(&tx.to, entry.clone());
+            }
+            self.transactions.push(&tx.from, entry);
+        }
+    }
+
+    pub fn vote(&mut self, vote: &Vote) {
+        let seq = self.seq();
+        let entry = VoteEntry { seq, block_hash: vote.block_hash.clone(), timestamp: vote.timestamp };
+        self.votes.push(&vote.voter_id, entry);
+    }
+
+    /// Transactions sent or received by `address`.
+    pub fn transactions(&self, address: &str, before: Option<u64>, limit: usize) -> Page<TxEntry> {
+        self.transactions.page(address, before, limit, |entry| entry.seq)
+    }
+
+    pub fn blocks_by_proposer(&self, proposer: &str, before: Option<u64>, limit: usize) -> Page<BlockEntry> {
+        self.blocks.page(proposer, before, limit, |entry| entry.seq)
+    }
+
+    pub fn votes_by_validator(&self, validator: &str, before: Option<u64>, limit: usize) -> Page<VoteEntry> {
+        self.votes.page(validator, before, limit, |entry| entry.seq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transaction;
+
+    fn block(hash: &str, transactions: Vec<Transaction>) -> BlockProposal {
+        BlockProposal {
+            block_hash: hash.to_string(),
+            state_root: "r".to_string(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions,
+            proposer_id: "p1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        }
+    }
+
+    fn transfer(from: &str, to: &str, value: u64) -> Transaction {
+        Transaction {
+            hash: format!("0x{}{}{}", from, to, value),
+            from: from.to_string(),
+            to: to.to_string(),
+            value,
+            ..Transaction::default()
+        }
+    }
+
+    #[test]
+    fn pages_newest_first_until_exhausted() {
+        let mut index = ChainIndex::default();
+        for height in 1..=5 {
+            index.finalized(&block(&format!("b{}", height), vec![transfer("alice", "bob", height)]), height);
+        }
+
+        let first = index.transactions("bob", None, 2);
+        assert_eq!(first.items.iter().map(|tx| tx.value).collect::<Vec<_>>(), [5, 4]);
+        let second = index.transactions("bob", first.next, 2);
+        assert_eq!(second.items.iter().map(|tx| tx.value).collect::<Vec<_>>(), [3, 2]);
+        let last = index.transactions("bob", second.next, 2);
+        assert_eq!((last.items.len(), last.next), (1, None));
+
+        assert_eq!(index.transactions("alice", None, 10).items.len(), 5);
+        assert_eq!(index.blocks_by_proposer("p1", None, 1).items[0].block_hash, "b5");
+        assert!(index.transactions("carol", None, 10).items.is_empty());
+    }
+
+    #[test]
+    fn self_transfers_are_listed_once_and_votes_by_voter() {
+        let mut index = ChainIndex::default();
+        index.finalized(&block("b1", vec![transfer("alice", "alice", 1)]), 1);
+        assert_eq!(index.transactions("alice", None, 10).items.len(), 1);
+
+        let vote = Vote { block_hash: "b1".to_string(), voter_id: "v1".to_string(), stake: 1, timestamp: 9, ..Vote::default() };
+        index.vote(&vote);
+        let votes = index.votes_by_validator("v1", None, 0);
+        assert_eq!((votes.items.len(), votes.items[0].timestamp), (1, 9));
+    }
+}
@@ -0,0 +1,743 @@
+//! Key-value storage behind the node's databases.
+//!
+//! A `KvStore` holds named column families, each an ordered map from byte
+//! keys to byte values. Reads go through a `ColumnFamily` handle; writes go
+//! through a `WriteBatch`, applied atomically across column families and
+//! on disk by the time `write` returns.
+//!
+//! Reads through live handles may interleave with writes. Readers that
+//! need several reads to agree, e.g. RPC queries, take a `KvSnapshot`,
+//! which sees every batch written before it and none after. Memory and
+//! RocksDB snapshots are real point-in-time views; sled has none, so its
+//! writes wait until open snapshots are dropped. Keep them short.
+//!
+//! `stats` reports sizes for `cubiq db stats` and the storage metrics, and
+//! `compact` runs a manual compaction; only RocksDB compacts, so the
+//! others report no pending compaction and have nothing to do.
+//!
+//! | backend    | for                                                  |
+//! |------------|------------------------------------------------------|
+//! | `memory`   | tests and devnets; nothing touches disk              |
+//! | `sled`     | the default on-disk database                         |
+//! | `rocksdb`  | large archive nodes; needs the `rocksdb` feature     |
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+
+/// A key and its value.
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+/// Entries in key order.
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<Entry, KvError>> + 'a>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvError {
+    pub backend: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.backend, self.message)
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<sled::Error> for KvError {
+    fn from(e: sled::Error) -> Self {
+        KvError { backend: "sled", message: e.to_string() }
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl From<rocksdb::Error> for KvError {
+    fn from(e: rocksdb::Error) -> Self {
+        KvError { backend: "rocksdb", message: e.to_string() }
+    }
+}
+
+/// Reads from one column family.
+pub trait ColumnFamily: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError>;
+
+    /// Entries with keys between `start` and `end`, in key order.
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> KvIter<'_>;
+
+    /// The entry with the greatest key.
+    fn last(&self) -> Result<Option<Entry>, KvError>;
+
+    fn iter(&self) -> KvIter<'_> {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+pub trait KvStore: Send + Sync {
+    /// The column family `name`, created if it doesn't exist.
+    fn column(&self, name: &str) -> Result<Arc<dyn ColumnFamily>, KvError>;
+
+    /// Applies every write in `batch`, or none of them.
+    fn write(&self, batch: WriteBatch) -> Result<(), KvError>;
+
+    /// A consistent view of every column family as of now.
+    fn snapshot(&self) -> Box<dyn KvSnapshot<'_> + '_>;
+
+    /// Sizes of the store and of each column family in it.
+    fn stats(&self) -> Result<KvStats, KvError>;
+
+    /// Compacts all of column family `name`, blocking until it is done.
+    fn compact(&self, _name: &str) -> Result<(), KvError> {
+        Ok(())
+    }
+}
+
+/// What a `KvStore` knows about its size without scanning its keys;
+/// backends leave out what they can't tell cheaply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KvStats {
+    pub backend: Backend,
+    pub total_bytes: Option<u64>,
+    /// Compactions under way, for backends that compact
+    pub running_compactions: Option<u64>,
+    /// In name order
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColumnStats {
+    pub name: String,
+    /// Estimated by RocksDB
+    pub keys: Option<u64>,
+    /// On disk, or held in memory by the memory backend
+    pub bytes: Option<u64>,
+    /// What RocksDB estimates compaction has yet to rewrite
+    pub pending_compaction_bytes: Option<u64>,
+}
+
+/// Reads that all see the store as it was when the snapshot was taken.
+/// Its columns keep the view open after the snapshot itself is dropped.
+pub trait KvSnapshot<'a>: Send + Sync {
+    fn column(&self, name: &str) -> Result<Box<dyn ColumnFamily + 'a>, KvError>;
+}
+
+/// Inserts and removals across column families, applied in order.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<(String, Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn insert(&mut self, column: &str, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) {
+        self.ops.push((column.to_string(), key.as_ref().to_vec(), Some(value.as_ref().to_vec())));
+    }
+
+    pub fn remove(&mut self, column: &str, key: impl AsRef<[u8]>) {
+        self.ops.push((column.to_string(), key.as_ref().to_vec(), None));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Which `KvStore` the node's databases use.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Memory,
+    #[default]
+    Sled,
+    RocksDb,
+}
+
+impl Backend {
+    /// Opens or creates a store at `path`; the memory backend ignores it.
+    pub fn open(self, path: &Path) -> Result<Arc<dyn KvStore>, KvError> {
+        match self {
+            Backend::Memory => Ok(Arc::new(MemoryKv::default())),
+            Backend::Sled => Ok(Arc::new(SledKv::open(path)?)),
+            #[cfg(feature = "rocksdb")]
+            Backend::RocksDb => Ok(Arc::new(RocksKv::open(path)?)),
+            #[cfg(not(feature = "rocksdb"))]
+            Backend::RocksDb => Err(KvError { backend: "rocksdb", message: "built without the rocksdb feature".to_string() }),
+        }
+    }
+
+    /// Whether data outlives the process.
+    pub fn persistent(self) -> bool {
+        self != Backend::Memory
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Memory => "memory",
+            Backend::Sled => "sled",
+            Backend::RocksDb => "rocksdb",
+        })
+    }
+}
+
+type Columns = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Column families in memory, lost when the last handle is dropped.
+///
+/// Snapshots share the columns until the next write, which copies them.
+#[derive(Default)]
+pub struct MemoryKv {
+    columns: Arc<RwLock<Arc<Columns>>>,
+}
+
+enum MemorySource {
+    Live(Arc<RwLock<Arc<Columns>>>),
+    Snapshot(Arc<Columns>),
+}
+
+struct MemoryColumn {
+    source: MemorySource,
+    name: String,
+}
+
+impl MemoryColumn {
+    /// The columns as of now, or as of the snapshot.
+    fn columns(&self) -> Arc<Columns> {
+        match &self.source {
+            MemorySource::Live(columns) => Arc::clone(&columns.read().expect("Memory store lock poisoned")),
+            MemorySource::Snapshot(columns) => Arc::clone(columns),
+        }
+    }
+}
+
+impl ColumnFamily for MemoryColumn {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        Ok(self.columns().get(&self.name).and_then(|column| column.get(key).cloned()))
+    }
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> KvIter<'_> {
+        let entries: Vec<_> = match self.columns().get(&self.name) {
+            Some(column) => column.range::<[u8], _>((start, end)).map(|(k, v)| Ok((k.clone(), v.clone()))).collect(),
+            None => vec![],
+        };
+        Box::new(entries.into_iter())
+    }
+
+    fn last(&self) -> Result<Option<Entry>, KvError> {
+        Ok(self.columns().get(&self.name).and_then(|column| column.last_key_value()).map(|(k, v)| (k.clone(), v.clone())))
+    }
+}
+
+struct MemorySnapshot(Arc<Columns>);
+
+impl<'a> KvSnapshot<'a> for MemorySnapshot {
+    fn column(&self, name: &str) -> Result<Box<dyn ColumnFamily + 'a>, KvError> {
+        Ok(Box::new(MemoryColumn { source: MemorySource::Snapshot(Arc::clone(&self.0)), name: name.to_string() }))
+    }
+}
+
+impl KvStore for MemoryKv {
+    fn column(&self, name: &str) -> Result<Arc<dyn ColumnFamily>, KvError> {
+        let source = MemorySource::Live(Arc::clone(&self.columns));
+        Ok(Arc::new(MemoryColumn { source, name: name.to_string() }))
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), KvError> {
+        let mut guard = self.columns.write().expect("Memory store lock poisoned");
+        let columns = Arc::make_mut(&mut guard);
+        for (column, key, value) in batch.ops {
+            let column = columns.entry(column).or_default();
+            match value {
+                Some(value) => column.insert(key, value),
+                None => column.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Box<dyn KvSnapshot<'_> + '_> {
+        Box::new(MemorySnapshot(Arc::clone(&self.columns.read().expect("Memory store lock poisoned"))))
+    }
+
+    fn stats(&self) -> Result<KvStats, KvError> {
+        let columns = Arc::clone(&self.columns.read().expect("Memory store lock poisoned"));
+        let mut stats: Vec<ColumnStats> = columns
+            .iter()
+            .map(|(name, column)| ColumnStats {
+                name: name.clone(),
+                keys: Some(column.len() as u64),
+                bytes: Some(column.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum()),
+                pending_compaction_bytes: None,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(KvStats {
+            backend: Backend::Memory,
+            total_bytes: Some(stats.iter().filter_map(|column| column.bytes).sum()),
+            running_compactions: None,
+            columns: stats,
+        })
+    }
+}
+
+/// Holds writes back while snapshots are open, for backends that can't
+/// take snapshots of their own. Snapshots don't wait for each other.
+#[derive(Default)]
+struct Gate {
+    /// Open snapshots, and whether a write is under way
+    state: Mutex<(usize, bool)>,
+    changed: Condvar,
+}
+
+impl Gate {
+    fn lock(&self) -> MutexGuard<'_, (usize, bool)> {
+        self.state.lock().expect("Storage gate poisoned")
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, (usize, bool)>, until: impl Fn(&(usize, bool)) -> bool) -> MutexGuard<'a, (usize, bool)> {
+        self.changed.wait_while(state, |state| !until(state)).expect("Storage gate poisoned")
+    }
+
+    fn read(&self) -> GateRead<'_> {
+        let mut state = self.wait(self.lock(), |(_, writing)| !writing);
+        state.0 += 1;
+        GateRead(self)
+    }
+
+    fn write(&self) -> GateWrite<'_> {
+        let mut state = self.wait(self.lock(), |(readers, writing)| *readers == 0 && !writing);
+        state.1 = true;
+        GateWrite(self)
+    }
+}
+
+struct GateRead<'a>(&'a Gate);
+
+impl Drop for GateRead<'_> {
+    fn drop(&mut self) {
+        self.0.lock().0 -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+struct GateWrite<'a>(&'a Gate);
+
+impl Drop for GateWrite<'_> {
+    fn drop(&mut self) {
+        self.0.lock().1 = false;
+        self.0.changed.notify_all();
+    }
+}
+
+/// A sled database, with one tree per column family.
+pub struct SledKv {
+    db: sled::Db,
+    trees: Mutex<HashMap<String, sled::Tree>>,
+    gate: Gate,
+}
+
+impl SledKv {
+    pub fn open(path: &Path) -> Result<Self, KvError> {
+        Ok(Self { db: sled::open(path)?, trees: Mutex::new(HashMap::new()), gate: Gate::default() })
+    }
+
+    fn tree(&self, name: &str) -> Result<sled::Tree, KvError> {
+        let mut trees = self.trees.lock().expect("Sled tree cache poisoned");
+        if let Some(tree) = trees.get(name) {
+            return Ok(tree.clone());
+        }
+        let tree = self.db.open_tree(name)?;
+        trees.insert(name.to_string(), tree.clone());
+        Ok(tree)
+    }
+}
+
+struct SledColumn<'a> {
+    tree: sled::Tree,
+    /// For snapshots, the hold on writes
+    _reading: Option<Arc<GateRead<'a>>>,
+}
+
+struct SledSnapshot<'a> {
+    kv: &'a SledKv,
+    reading: Arc<GateRead<'a>>,
+}
+
+impl<'a> KvSnapshot<'a> for SledSnapshot<'a> {
+    fn column(&self, name: &str) -> Result<Box<dyn ColumnFamily + 'a>, KvError> {
+        Ok(Box::new(SledColumn { tree: self.kv.tree(name)?, _reading: Some(Arc::clone(&self.reading)) }))
+    }
+}
+
+impl ColumnFamily for SledColumn<'_> {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+        Ok(self.tree.get(key)?.map(|value| value.to_vec()))
+    }
+
+    fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> KvIter<'_> {
+        Box::new(self.tree.range::<&[u8], _>((start, end)).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.to_vec(), value.to_vec()))
+        }))
+    }
+
+    fn last(&self) -> Result<Option<Entry>, KvError> {
+        Ok(self.tree.last()?.map(|(key, value)| (key.to_vec(), value.to_vec())))
+    }
+}
+
+impl KvStore for SledKv {
+    fn column(&self, name: &str) -> Result<Arc<dyn ColumnFamily>, KvError> {
+        Ok(Arc::new(SledColumn { tree: self.tree(name)?, _reading: None }))
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), KvError> {
+        use sled::transaction::{ConflictableTransactionError, TransactionError};
+        use sled::Transactional;
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut batches: BTreeMap<String, sled::Batch> = BTreeMap::new();
+        for (column, key, value) in batch.ops {
+            let batch = batches.entry(column).or_default();
+            match value {
+                Some(value) => batch.insert(key, value),
+                None => batch.remove(key),
+            }
+        }
+        let trees = batches.keys().map(|name| self.tree(name)).collect::<Result<Vec<_>, _>>()?;
+        let _writing = self.gate.write();
+        trees[..]
+            .transaction(|trees| {
+                for (tree, batch) in trees.iter().zip(batches.values()) {
+                    tree.apply_batch(batch)?;
+                }
+                Ok::<_, ConflictableTransactionError<()>>(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Storage(e) => KvError::from(e),
+                TransactionError::Abort(()) => unreachable!("the transaction never aborts"),
+            })?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Box<dyn KvSnapshot<'_> + '_> {
+        Box::new(SledSnapshot { kv: self, reading: Arc::new(self.gate.read()) })
+    }
+
+    /// Sled only knows the size of the whole database; counting a tree's
+    /// keys walks all of it.
+    fn stats(&self) -> Result<KvStats, KvError> {
+        let default = self.db.name();
+        let mut names: Vec<String> = self
+            .db
+            .tree_names()
+            .into_iter()
+            .filter(|name| *name != default)
+            .map(|name| String::from_utf8_lossy(&name).into_owned())
+            .collect();
+        names.sort();
+        let columns = names
+            .into_iter()
+            .map(|name| ColumnStats { name, keys: None, bytes: None, pending_compaction_bytes: None })
+            .collect();
+        Ok(KvStats { backend: Backend::Sled, total_bytes: Some(self.db.size_on_disk()?), running_compactions: None, columns })
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+pub use rocks::RocksKv;
+
+#[cfg(feature = "rocksdb")]
+mod rocks {
+    use super::*;
+    use rocksdb::{properties, CompactOptions, DBWithThreadMode, Direction, IteratorMode, MultiThreaded, Options, WriteOptions};
+
+    type Db = DBWithThreadMode<MultiThreaded>;
+
+    /// A RocksDB database, with one RocksDB column family per column.
+    pub struct RocksKv {
+        db: Arc<Db>,
+    }
+
+    impl RocksKv {
+        pub fn open(path: &Path) -> Result<Self, KvError> {
+            let mut options = Options::default();
+            options.create_if_missing(true);
+            let columns = Db::list_cf(&options, path).unwrap_or_else(|_| vec!["default".to_string()]);
+            Ok(Self { db: Arc::new(Db::open_cf(&options, path, columns)?) })
+        }
+
+        fn handle(&self, name: &str) -> Result<Arc<rocksdb::BoundColumnFamily<'_>>, KvError> {
+            if let Some(handle) = self.db.cf_handle(name) {
+                return Ok(handle);
+            }
+            // Another thread may have created it in the meantime
+            if let Err(e) = self.db.create_cf(name, &Options::default()) {
+                self.db.cf_handle(name).ok_or(e)?;
+            }
+            self.db.cf_handle(name).ok_or_else(|| KvError { backend: "rocksdb", message: format!("no column family {}", name) })
+        }
+    }
+
+    type Snapshot<'a> = rocksdb::SnapshotWithThreadMode<'a, Db>;
+
+    fn mode(start: Bound<&[u8]>) -> IteratorMode<'_> {
+        match start {
+            Bound::Included(key) | Bound::Excluded(key) => IteratorMode::From(key, Direction::Forward),
+            Bound::Unbounded => IteratorMode::Start,
+        }
+    }
+
+    /// Cuts a forward iterator from `start` down to the range.
+    fn bounded<'a>(
+        entries: impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>> + 'a,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> KvIter<'a> {
+        let (start, end) = (start.map(<[u8]>::to_vec), end.map(<[u8]>::to_vec));
+        let entries = entries
+            .skip_while(move |entry| matches!((entry, &start), (Ok((key, _)), Bound::Excluded(start)) if **key == start[..]))
+            .take_while(move |entry| match (entry, &end) {
+                (Ok((key, _)), Bound::Included(end)) => **key <= end[..],
+                (Ok((key, _)), Bound::Excluded(end)) => **key < end[..],
+                _ => true,
+            })
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.into_vec(), value.into_vec()))
+            });
+        Box::new(entries)
+    }
+
+    fn first(mut entries: impl Iterator<Item = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>>) -> Result<Option<Entry>, KvError> {
+        match entries.next() {
+            Some(entry) => {
+                let (key, value) = entry?;
+                Ok(Some((key.into_vec(), value.into_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    struct RocksColumn {
+        db: Arc<Db>,
+        name: String,
+    }
+
+    impl RocksColumn {
+        fn handle(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+            self.db.cf_handle(&self.name).expect("column families are never dropped")
+        }
+    }
+
+    impl ColumnFamily for RocksColumn {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+            Ok(self.db.get_cf(&self.handle(), key)?)
+        }
+
+        fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> KvIter<'_> {
+            bounded(self.db.iterator_cf(&self.handle(), mode(start)), start, end)
+        }
+
+        fn last(&self) -> Result<Option<Entry>, KvError> {
+            first(self.db.iterator_cf(&self.handle(), IteratorMode::End))
+        }
+    }
+
+    struct RocksSnapshot<'a> {
+        kv: &'a RocksKv,
+        snapshot: Arc<Snapshot<'a>>,
+    }
+
+    impl<'a> KvSnapshot<'a> for RocksSnapshot<'a> {
+        fn column(&self, name: &str) -> Result<Box<dyn ColumnFamily + 'a>, KvError> {
+            self.kv.handle(name)?;
+            let snapshot = Arc::clone(&self.snapshot);
+            Ok(Box::new(RocksSnapshotColumn { db: &self.kv.db, snapshot, name: name.to_string() }))
+        }
+    }
+
+    struct RocksSnapshotColumn<'a> {
+        db: &'a Db,
+        snapshot: Arc<Snapshot<'a>>,
+        name: String,
+    }
+
+    impl RocksSnapshotColumn<'_> {
+        fn handle(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+            self.db.cf_handle(&self.name).expect("column families are never dropped")
+        }
+    }
+
+    impl ColumnFamily for RocksSnapshotColumn<'_> {
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, KvError> {
+            Ok(self.snapshot.get_cf(&self.handle(), key)?)
+        }
+
+        fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> KvIter<'_> {
+            bounded(self.snapshot.iterator_cf(&self.handle(), mode(start)), start, end)
+        }
+
+        fn last(&self) -> Result<Option<Entry>, KvError> {
+            first(self.snapshot.iterator_cf(&self.handle(), IteratorMode::End))
+        }
+    }
+
+    impl KvStore for RocksKv {
+        fn column(&self, name: &str) -> Result<Arc<dyn ColumnFamily>, KvError> {
+            self.handle(name)?;
+            Ok(Arc::new(RocksColumn { db: Arc::clone(&self.db), name: name.to_string() }))
+        }
+
+        fn write(&self, batch: WriteBatch) -> Result<(), KvError> {
+            let mut rocks = rocksdb::WriteBatch::default();
+            for (column, key, value) in &batch.ops {
+                let handle = self.handle(column)?;
+                match value {
+                    Some(value) => rocks.put_cf(&handle, key, value),
+                    None => rocks.delete_cf(&handle, key),
+                }
+            }
+            let mut options = WriteOptions::default();
+            options.set_sync(true);
+            Ok(self.db.write_opt(rocks, &options)?)
+        }
+
+        fn snapshot(&self) -> Box<dyn KvSnapshot<'_> + '_> {
+            Box::new(RocksSnapshot { kv: self, snapshot: Arc::new(self.db.snapshot()) })
+        }
+
+        fn stats(&self) -> Result<KvStats, KvError> {
+            let mut names = Db::list_cf(&Options::default(), self.db.path())?;
+            names.retain(|name| name != "default");
+            names.sort();
+            let mut columns = Vec::with_capacity(names.len());
+            for name in names {
+                let handle = self.handle(&name)?;
+                let sst = self.db.property_int_value_cf(&handle, properties::TOTAL_SST_FILES_SIZE)?;
+                let memtables = self.db.property_int_value_cf(&handle, properties::CUR_SIZE_ALL_MEM_TABLES)?;
+                columns.push(ColumnStats {
+                    keys: self.db.property_int_value_cf(&handle, properties::ESTIMATE_NUM_KEYS)?,
+                    bytes: sst.zip(memtables).map(|(sst, memtables)| sst + memtables),
+                    pending_compaction_bytes: self.db.property_int_value_cf(&handle, properties::ESTIMATE_PENDING_COMPACTION_BYTES)?,
+                    name,
+                });
+            }
+            Ok(KvStats {
+                backend: Backend::RocksDb,
+                total_bytes: columns.iter().map(|column| column.bytes).sum(),
+                running_compactions: self.db.property_int_value(properties::NUM_RUNNING_COMPACTIONS)?,
+                columns,
+            })
+        }
+
+        /// Lets automatic compactions carry on alongside, so writes don't
+        /// stall behind a long manual one.
+        fn compact(&self, name: &str) -> Result<(), KvError> {
+            let mut options = CompactOptions::default();
+            options.set_exclusive_manual_compaction(false);
+            self.db.compact_range_cf_opt(&self.handle(name)?, None::<&[u8]>, None::<&[u8]>, &options);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(kv: &dyn KvStore) {
+        let heights = kv.column("heights").unwrap();
+        let mut batch = WriteBatch::default();
+        for n in [3u8, 1, 2] {
+            batch.insert("heights", [n], [n * 10]);
+        }
+        batch.insert("hashes", b"b1", [1]);
+        kv.write(batch).unwrap();
+
+        assert_eq!(heights.get(&[2]).unwrap(), Some(vec![20]));
+        assert_eq!(heights.last().unwrap(), Some((vec![3], vec![30])));
+        let keys: Vec<_> = heights.range(Bound::Excluded(&[1][..]), Bound::Included(&[3][..])).map(|e| e.unwrap().0).collect();
+        assert_eq!(keys, [vec![2], vec![3]]);
+        assert_eq!(kv.column("hashes").unwrap().iter().count(), 1);
+
+        let mut batch = WriteBatch::default();
+        batch.remove("heights", [3]);
+        batch.insert("heights", [1], [11]);
+        kv.write(batch).unwrap();
+        assert_eq!(heights.iter().map(Result::unwrap).collect::<Vec<_>>(), [(vec![1], vec![11]), (vec![2], vec![20])]);
+        assert!(kv.column("empty").unwrap().last().unwrap().is_none());
+
+        let stats = kv.stats().unwrap();
+        let names: Vec<_> = stats.columns.iter().map(|column| column.name.as_str()).collect();
+        assert!(["hashes", "heights"].iter().all(|name| names.contains(name)), "{:?}", names);
+        assert!(stats.total_bytes.is_some());
+        kv.compact("heights").unwrap();
+        assert_eq!(heights.get(&[2]).unwrap(), Some(vec![20]));
+    }
+
+    #[test]
+    fn memory_snapshots_keep_their_point_in_time() {
+        let kv = MemoryKv::default();
+        let mut batch = WriteBatch::default();
+        batch.insert("heights", [1], b"b1");
+        kv.write(batch).unwrap();
+
+        let snapshot = kv.snapshot();
+        let mut batch = WriteBatch::default();
+        batch.insert("heights", [2], b"b2");
+        batch.insert("hashes", b"b2", [2]);
+        kv.write(batch).unwrap();
+
+        let heights = snapshot.column("heights").unwrap();
+        assert_eq!((heights.last().unwrap().unwrap().0, heights.get(&[2]).unwrap()), (vec![1], None));
+        assert!(snapshot.column("hashes").unwrap().get(b"b2").unwrap().is_none());
+        assert_eq!(kv.column("heights").unwrap().iter().count(), 2);
+    }
+
+    #[test]
+    fn sled_writes_wait_for_open_snapshots() {
+        let dir = std::env::temp_dir().join(format!("cubiq-kv-gate-{}", std::process::id()));
+        let kv = Arc::new(SledKv::open(&dir).unwrap());
+        let snapshot = kv.snapshot();
+        let writer = {
+            let kv = Arc::clone(&kv);
+            std::thread::spawn(move || {
+                let mut batch = WriteBatch::default();
+                batch.insert("heights", [1], b"b1");
+                kv.write(batch).unwrap();
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(snapshot.column("heights").unwrap().get(&[1]).unwrap().is_none());
+        drop(snapshot);
+        writer.join().unwrap();
+        assert!(kv.snapshot().column("heights").unwrap().get(&[1]).unwrap().is_some());
+        drop(kv);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backends_agree() {
+        exercise(&MemoryKv::default());
+
+        let dir = std::env::temp_dir().join(format!("cubiq-kv-{}", std::process::id()));
+        exercise(&SledKv::open(&dir).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backends_parse_from_config_names() {
+        for backend in [Backend::Memory, Backend::Sled, Backend::RocksDb] {
+            let name = serde_json::to_value(backend).unwrap();
+            assert_eq!(name.as_str().unwrap(), backend.to_string());
+        }
+        assert!(!Backend::Memory.persistent());
+    }
+}
@@ -1,29 +1,253 @@
-use prover::MobileProofVerifier;
+use address::Address;
+use events::{ConsensusEvent, TxStatus, EVENT_BUFFER};
+use execution::{BlockEnv, Execution};
+use genesis::{ChainSpec, ConsensusParams};
+use health::{ConsensusHealth, HealthTracker};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use index::ChainIndex;
+use logs::LogIndex;
+use mempool::{Mempool, MempoolLimits, Origin};
+use metrics::ConsensusMetrics;
+use receipts::ReceiptIndex;
+use registration::Registration;
+use rotation::KeyRotation;
+use staking::PendingCall;
+use pruning::{Pruning, StatePruner};
+use state::{Hash, StateTrie};
+use store::BlockStore;
+use timings::{Timing, TimingSummary, VerificationTimes};
+use prover::{domain::ProofPurpose, MobileProofVerifier};
 use zkurl::{ZkURL, resolver::{ZkURLResolver, ProofBundle}};
+use schemars::JsonSchema;
 use serde::{Serialize, Deserialize};
-use tokio::sync::{RwLock, mpsc};
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::str::FromStr;
+use tokio::sync::{RwLock, broadcast, mpsc};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Verified blocks kept in memory for queries.
+pub const RECENT_BLOCKS: usize = 1024;
+
+/// Votes each validator may have for blocks not verified here, which
+/// can't finalize until they are. Beyond it the oldest is dropped.
+pub const EARLY_VOTES: usize = 8;
+
+/// How far ahead of this node's clock a proposal's timestamp may be, by
+/// default, for clocks that drift apart.
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct BlockProposal {
     pub block_hash: String,
     pub state_root: String,
-    pub zkurl: String,
+    pub zkurl: ZkURL,
     pub transactions: Vec<Transaction>,
     pub proposer_id: String,
     pub timestamp: u64,
+    /// Burned per unit of gas the transactions use; see `fees`
+    pub base_fee: u64,
+    /// Metered gas of the transactions
+    pub gas_used: u64,
+}
+
+/// This node's clock, in Unix seconds.
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Prefixes the canonical encoding when hashing, so a transaction's
+/// signature can't be passed off as one over anything else.
+const TRANSACTION_DOMAIN: &[u8] = b"cubiq-transaction-v1";
+
+/// What a transaction does besides paying for its gas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    /// Moves `value` to `to`
+    #[default]
+    Transfer,
+    /// Deploys `data` as a contract's code, with `value` as its balance;
+    /// `to` is empty
+    Deploy,
+    /// Calls the contract at `to` with `data`, sending it `value`
+    Call,
+    /// Creates the multisig account of the `multisig::Multisig` in `data`,
+    /// as JSON, with `value` as its balance; `to` is empty
+    CreateMultisig,
+    /// Schedules the `rotation::KeyRotation` in `data`, as JSON, of the
+    /// sender's validator key; `to` is empty and `value` zero
+    RotateKey,
+    /// Carries out the `bridge::Msg` in `data`, as JSON; `to` is empty
+    /// and `value` what a bridge transfer sends
+    Bridge,
+}
+
+/// A transfer, contract deploy or contract call signed by its sender; see
+/// `contracts`.
+///
+/// The canonical encoding is the bincode encoding of every field but
+/// `hash`, `signature` and `cosignatures`, in declaration order. `hash`
+/// is blake3 of `TRANSACTION_DOMAIN` followed by that encoding, and
+/// `signature` is the sender's ed25519 signature of the hash's 32 bytes,
+/// or for a multisig sender `cosignatures` are its keys'; see `multisig`.
+/// `chain_id` stops a transaction being replayed on another network, and
+/// `nonce` on this one: it must equal the sender's count of earlier
+/// transactions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct Transaction {
     pub hash: String,
+    pub chain_id: u64,
+    pub nonce: u64,
+    /// Address of the sender, the account of the key that signs; see
+    /// `address`
     pub from: String,
+    /// Address of the recipient, or on EVM chains an EVM account
     pub to: String,
     pub value: u64,
-    pub gas_used: u64,
+    /// Most gas the transaction may use; the sender pays for all of it up
+    /// front and is refunded what execution leaves
+    pub gas_limit: u64,
+    /// Most the sender pays per unit of gas, base fee and tip together;
+    /// at least `fees::MIN_BASE_FEE`
+    pub max_fee_per_gas: u64,
+    /// Most tip per unit of gas for the block's proposer
+    pub max_priority_fee_per_gas: u64,
+    /// A transfer when left out
+    #[serde(default)]
+    pub kind: TxKind,
     pub data: Vec<u8>,
+    /// Hex-encoded ed25519 signature of `hash`; empty from a multisig
+    pub signature: String,
+    /// Signatures of `hash` by a multisig sender's keys
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cosignatures: Vec<Cosignature>,
+}
+
+/// One key's signature of a multisig account's transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Cosignature {
+    /// Hex-encoded ed25519 public key
+    pub public_key: String,
+    /// Hex-encoded ed25519 signature of the transaction's `hash`
+    pub signature: String,
+}
+
+/// The signed fields of a `Transaction`.
+#[derive(Serialize)]
+struct SignedFields<'a> {
+    chain_id: u64,
+    nonce: u64,
+    from: &'a str,
+    to: &'a str,
+    value: u64,
+    gas_limit: u64,
+    max_fee_per_gas: u64,
+    max_priority_fee_per_gas: u64,
+    kind: TxKind,
+    data: &'a [u8],
+}
+
+impl Transaction {
+    /// The canonical encoding.
+    pub fn encode(&self) -> Vec<u8> {
+        let fields = SignedFields {
+            chain_id: self.chain_id,
+            nonce: self.nonce,
+            from: &self.from,
+            to: &self.to,
+            value: self.value,
+            gas_limit: self.gas_limit,
+            max_fee_per_gas: self.max_fee_per_gas,
+            max_priority_fee_per_gas: self.max_priority_fee_per_gas,
+            kind: self.kind,
+            data: &self.data,
+        };
+        bincode::serialize(&fields).expect("transactions encode")
+    }
+
+    fn signing_hash(&self) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(TRANSACTION_DOMAIN).update(&self.encode());
+        hasher.finalize()
+    }
+
+    /// The hash of the canonical encoding, as `hash` should hold it.
+    pub fn compute_hash(&self) -> String {
+        format!("0x{}", self.signing_hash().to_hex())
+    }
+
+    /// Signs the transaction as sent from `key`'s account, setting `from`,
+    /// `hash` and `signature`.
+    pub fn sign(self, key: &SigningKey) -> Self {
+        let mut tx = self.from_account(&Address::from_key(&key.verifying_key()));
+        tx.signature = hex::encode(key.sign(tx.signing_hash().as_bytes()).to_bytes());
+        tx
+    }
+
+    /// Sets `from` to `account` and `hash` to match, for a signer holding
+    /// the account's key elsewhere, such as a hardware wallet, to sign the
+    /// encoding.
+    pub fn from_account(mut self, account: &Address) -> Self {
+        self.from = account.to_string();
+        self.hash = self.compute_hash();
+        self
+    }
+
+    /// The signature of the holder of `key`, one of a multisig sender's,
+    /// for the transaction as it stands.
+    pub fn cosign(&self, key: &SigningKey) -> Cosignature {
+        Cosignature {
+            public_key: hex::encode(key.verifying_key().to_bytes()),
+            signature: hex::encode(key.sign(self.signing_hash().as_bytes()).to_bytes()),
+        }
+    }
+
+    /// Checks that `from` signed the transaction, or for a multisig sender
+    /// that each cosignature is valid and from a different key. Whether
+    /// they are enough, and from the right keys, depends on the sender's
+    /// account; see `multisig::authorize`.
+    pub fn verify_signature(&self) -> Result<(), String> {
+        if !self.cosignatures.is_empty() {
+            return self.verify_cosignatures();
+        }
+        let from: Address = self.from.parse().map_err(|e| format!("sender: {}", e))?;
+        let key = from.verifying_key().map_err(|e| format!("sender: {}", e))?;
+        let signature: [u8; 64] = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("signature is not a hex-encoded ed25519 signature")?;
+        key.verify_strict(self.signing_hash().as_bytes(), &Signature::from_bytes(&signature))
+            .map_err(|_| "signature does not match the sender".to_string())
+    }
+
+    fn verify_cosignatures(&self) -> Result<(), String> {
+        if !self.signature.is_empty() {
+            return Err("has both a signature and cosignatures".to_string());
+        }
+        if self.cosignatures.len() > multisig::MAX_KEYS {
+            return Err(format!("has {} cosignatures, a multisig has at most {} keys", self.cosignatures.len(), multisig::MAX_KEYS));
+        }
+        let hash = self.signing_hash();
+        let mut keys = HashSet::new();
+        for cosignature in &self.cosignatures {
+            if !keys.insert(cosignature.public_key.as_str()) {
+                return Err(format!("is cosigned twice by {}", cosignature.public_key));
+            }
+            let key: [u8; 32] = hex::decode(&cosignature.public_key)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or("cosigner is not a hex-encoded ed25519 public key")?;
+            let key = VerifyingKey::from_bytes(&key).map_err(|_| "cosigner is not a valid ed25519 public key")?;
+            let signature: [u8; 64] = hex::decode(&cosignature.signature)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or("cosignature is not a hex-encoded ed25519 signature")?;
+            key.verify_strict(hash.as_bytes(), &Signature::from_bytes(&signature))
+                .map_err(|_| format!("cosignature by {} does not match", cosignature.public_key))?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,22 +257,95 @@ pub struct Validator {
     pub public_key: String,
     pub is_active: bool,
     pub last_vote_time: u64,
+    /// What links its keys and reward account; see `registration`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<Registration>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Vote {
     pub block_hash: String,
     pub voter_id: String,
     pub stake: u64,
     pub timestamp: u64,
+    /// The block's roots, gas and transaction count, which the signature
+    /// vouches for with its hash; see `Vote::is_for`
+    pub state_root: Hash,
+    pub transactions_root: Hash,
+    pub gas_used: u64,
+    pub transaction_count: u32,
+    /// Hex-encoded ed25519 signature by the voter's validator key of the
+    /// SSZ signing root of the other fields (see `ssz`)
     pub signature: String,
 }
 
-#[derive(Debug, Clone)]
+impl Vote {
+    /// An unsigned vote for `block`, a verified one, whose state root
+    /// parses; a vote for a block whose root does not is never for it.
+    pub fn new(block: &BlockProposal, voter_id: &str, stake: u64, timestamp: u64) -> Self {
+        Self {
+            block_hash: block.block_hash.clone(),
+            voter_id: voter_id.to_string(),
+            stake,
+            timestamp,
+            state_root: block.state_root.parse().unwrap_or_default(),
+            transactions_root: merkle::transactions_root(&block.transactions),
+            gas_used: block.gas_used,
+            transaction_count: block.transactions.len() as u32,
+            signature: String::new(),
+        }
+    }
+
+    /// Whether the vote is for `block` with the roots, gas and
+    /// transaction count it has.
+    pub fn is_for(&self, block: &BlockProposal) -> bool {
+        self.block_hash == block.block_hash
+            && Ok(self.state_root) == block.state_root.parse()
+            && self.transactions_root == merkle::transactions_root(&block.transactions)
+            && self.gas_used == block.gas_used
+            && self.transaction_count as usize == block.transactions.len()
+    }
+
+    /// Signs the vote; its block hash, which proposals are checked to
+    /// keep to, and the voter's id must fit `cubiq_types::MAX_STRING_BYTES`.
+    pub fn sign(mut self, key: &SigningKey) -> Self {
+        let root = ssz::vote_signing_root(&self).expect("block hashes and node ids fit MAX_STRING_BYTES");
+        self.signature = hex::encode(key.sign(&root).to_bytes());
+        self
+    }
+
+    /// Checks that the holder of `public_key`, hex-encoded, signed the vote.
+    pub fn verify_signature(&self, public_key: &str) -> Result<(), String> {
+        let key: [u8; 32] = hex::decode(public_key)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("voter key is not a hex-encoded ed25519 public key")?;
+        let key = VerifyingKey::from_bytes(&key).map_err(|_| "voter key is not a valid ed25519 public key")?;
+        let signature: [u8; 64] = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("signature is not a hex-encoded ed25519 signature")?;
+        key.verify_strict(&ssz::vote_signing_root(self)?, &Signature::from_bytes(&signature))
+            .map_err(|_| "signature does not match the voter".to_string())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ValidatorSet {
     pub validators: HashMap<String, Validator>,
     pub total_stake: u64,
     pub supermajority_threshold: u64,
+    /// Key rotations not yet in effect, by validator; see `rotation`
+    pub pending_rotations: BTreeMap<String, KeyRotation>,
+    /// Registry calls not yet in effect, in block order; see `staking`
+    #[serde(default)]
+    pub pending_calls: Vec<PendingCall>,
+}
+
+impl Default for ValidatorSet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ValidatorSet {
@@ -57,7 +354,19 @@ impl ValidatorSet {
             validators: HashMap::new(),
             total_stake: 0,
             supermajority_threshold: 0,
+            pending_rotations: BTreeMap::new(),
+            pending_calls: vec![],
+        }
+    }
+
+    /// The account of validator `node_id`: its registered reward account,
+    /// else its key's address.
+    pub fn account(&self, node_id: &str) -> Option<String> {
+        let validator = self.validators.get(node_id)?;
+        if let Some(registration) = &validator.registration {
+            return Some(registration.reward_account.clone());
         }
+        Address::from_public_key(&validator.public_key).ok().map(|address| address.to_string())
     }
 }
 
@@ -65,8 +374,34 @@ impl ValidatorSet {
 pub struct ConsensusState {
     pub current_height: u64,
     pub current_round: u32,
+    /// Votes by `block_hash:voter_id`, for blocks finalized in the last
+    /// `RECENT_BLOCKS` heights and blocks not yet finalized
     pub votes: HashMap<String, Vote>,
+    /// Stake that has voted for each block in `votes`
+    pub tallies: HashMap<String, u64>,
     pub finalized_blocks: Vec<String>,
+    /// The last `RECENT_BLOCKS` verified proposals, oldest first
+    pub recent_blocks: VecDeque<BlockProposal>,
+    /// Transactions waiting for a block
+    pub mempool: Mempool,
+    /// Account state after the newest verified block
+    pub accounts: StateTrie,
+    /// Which of `accounts`' earlier roots are kept
+    pub pruner: StatePruner,
+    pub receipts: ReceiptIndex,
+    pub index: ChainIndex,
+    /// Events of recently verified blocks
+    pub logs: LogIndex,
+    /// From the chain spec
+    pub params: ConsensusParams,
+    /// Base fee of the next block
+    pub base_fee: u64,
+}
+
+impl Default for ConsensusState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConsensusState {
@@ -75,7 +410,17 @@ impl ConsensusState {
             current_height: 0,
             current_round: 0,
             votes: HashMap::new(),
+            tallies: HashMap::new(),
             finalized_blocks: vec![],
+            recent_blocks: VecDeque::new(),
+            mempool: Mempool::default(),
+            accounts: StateTrie::default(),
+            pruner: StatePruner::default(),
+            receipts: ReceiptIndex::default(),
+            index: ChainIndex::default(),
+            logs: LogIndex::default(),
+            params: ConsensusParams::default(),
+            base_fee: ConsensusParams::default().initial_base_fee,
         }
     }
 
@@ -86,54 +431,628 @@ impl ConsensusState {
         }
         m
     }
+
+    fn verified(&self, block_hash: &str) -> bool {
+        self.recent_blocks.iter().any(|block| block.block_hash == block_hash)
+    }
+
+    /// Drops the votes for `block`'s hash that arrived before it was
+    /// verified but vouch for other roots.
+    fn drop_votes_not_for(&mut self, block: &BlockProposal, validators: &ValidatorSet) {
+        let stale: Vec<(String, String)> = self
+            .votes
+            .iter()
+            .filter(|(_, vote)| vote.block_hash == block.block_hash && !vote.is_for(block))
+            .map(|(key, vote)| (key.clone(), vote.voter_id.clone()))
+            .collect();
+        for (key, voter) in stale {
+            self.votes.remove(&key);
+            let stake = validators.validators.get(&voter).map_or(0, |validator| validator.stake);
+            self.untally(&block.block_hash, stake);
+        }
+    }
+
+    /// Makes room for another vote by `voter` for a block not verified
+    /// here, dropping its oldest such vote once it has `EARLY_VOTES`.
+    fn evict_early_vote(&mut self, voter: &str, stake: u64) {
+        let early = self.votes.iter().filter(|(_, vote)| vote.voter_id == voter && !self.verified(&vote.block_hash));
+        let (count, oldest) = early.fold((0, None::<(&String, &Vote)>), |(count, oldest), entry| {
+            let older = oldest.is_none_or(|(_, oldest)| entry.1.timestamp < oldest.timestamp);
+            (count + 1, if older { Some(entry) } else { oldest })
+        });
+        let Some((key, vote)) = oldest.filter(|_| count >= EARLY_VOTES) else {
+            return;
+        };
+        let (key, block_hash) = (key.clone(), vote.block_hash.clone());
+        self.votes.remove(&key);
+        self.untally(&block_hash, stake);
+    }
+
+    fn untally(&mut self, block_hash: &str, stake: u64) {
+        if let Some(tally) = self.tallies.get_mut(block_hash) {
+            *tally = tally.saturating_sub(stake);
+            if *tally == 0 {
+                self.tallies.remove(block_hash);
+            }
+        }
+    }
+
+    /// Drops the votes for the block finalized at `height`.
+    fn forget_votes(&mut self, height: u64) {
+        let Some(block_hash) = height.checked_sub(1).and_then(|index| self.finalized_blocks.get(index as usize)).cloned() else {
+            return;
+        };
+        self.votes.retain(|_, vote| vote.block_hash != block_hash);
+        self.tallies.remove(&block_hash);
+    }
 }
 
 pub struct QubeNode {
     pub node_id: String,
+    pub chain_id: u64,
     pub stake_amount: u64,
     pub validator_set: Arc<RwLock<ValidatorSet>>,
     pub zkurl_resolver: ZkURLResolver,
+    pub verifier: MobileProofVerifier,
     pub consensus_state: Arc<RwLock<ConsensusState>>,
+    /// Whether verified proposals are voted on; nodes that only follow the
+    /// chain verify without voting.
+    pub voting: bool,
+    /// Set by an operator to stop voting without restarting the node
+    paused: AtomicBool,
+    /// Set while the node is short of resources, to turn transactions away
+    admission_paused: AtomicBool,
+    /// Set while this node's clock is too far off for its votes to vouch
+    /// for block timestamps
+    clock_skewed: AtomicBool,
+    /// Tolerated lead of a proposal's timestamp over this node's clock
+    max_clock_skew: Duration,
+    /// Sign this node's votes, whichever its validator is registered with
+    validator_keys: Vec<SigningKey>,
+    /// Where finalized blocks are kept; without one they live in memory only
+    store: Option<BlockStore>,
+    pruning: Pruning,
+    events: broadcast::Sender<ConsensusEvent>,
+    metrics: Option<Arc<ConsensusMetrics>>,
+    timings: Mutex<VerificationTimes>,
+    health: Mutex<HealthTracker>,
+    #[cfg(feature = "chaos")]
+    faults: faults::Faults,
 }
 
 impl QubeNode {
-    pub async fn new(node_id: String, stake_amount: u64, resolver_endpoints: Vec<String>) -> Self {
+    pub async fn new(node_id: String, chain_id: u64, stake_amount: u64, resolver_endpoints: Vec<String>) -> Self {
         Self {
             node_id,
+            chain_id,
             stake_amount,
             validator_set: Arc::new(RwLock::new(ValidatorSet::new())),
             zkurl_resolver: ZkURLResolver::new(resolver_endpoints),
-            consensus_state: Arc::new(RwLock::new(ConsensusState::new())),
+            verifier: MobileProofVerifier::new(),
+            consensus_state: Arc::new(RwLock::new(ConsensusState { mempool: Mempool::new(chain_id), ..ConsensusState::new() })),
+            voting: true,
+            paused: AtomicBool::new(false),
+            admission_paused: AtomicBool::new(false),
+            clock_skewed: AtomicBool::new(false),
+            max_clock_skew: DEFAULT_CLOCK_SKEW,
+            validator_keys: vec![],
+            store: None,
+            pruning: Pruning::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            metrics: None,
+            timings: Mutex::new(VerificationTimes::default()),
+            health: Mutex::new(HealthTracker::default()),
+            #[cfg(feature = "chaos")]
+            faults: faults::Faults::default(),
+        }
+    }
+
+    /// Replaces the default resolver, e.g. with one that can fetch CID
+    /// proofs over the node's p2p network.
+    pub fn with_resolver(mut self, resolver: ZkURLResolver) -> Self {
+        self.zkurl_resolver = resolver;
+        self
+    }
+
+    /// Replaces the default verifier, e.g. with one held to a full node's
+    /// budgets rather than a phone's.
+    pub fn with_verifier(mut self, verifier: MobileProofVerifier) -> Self {
+        self.verifier = verifier;
+        self
+    }
+
+    /// Enables or disables voting on verified proposals.
+    pub fn with_voting(mut self, voting: bool) -> Self {
+        self.voting = voting;
+        self
+    }
+
+    /// Signs votes with `key` while it is the one this node's validator is
+    /// registered with. Given both its current key and the one a pending
+    /// rotation moves it to, the node switches keys as the rotation takes
+    /// effect; see `rotation`.
+    pub fn with_validator_key(mut self, key: SigningKey) -> Self {
+        self.validator_keys.push(key);
+        self
+    }
+
+    /// Persists finalized blocks to `store`; see `restore`.
+    pub fn with_store(mut self, store: BlockStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets which earlier account states are kept; see `pruning`.
+    pub fn with_pruning(mut self, pruning: Pruning) -> Self {
+        self.pruning = pruning;
+        self
+    }
+
+    /// Accepts proposals timestamped up to `skew` ahead of this node's
+    /// clock, rather than `DEFAULT_CLOCK_SKEW`.
+    pub fn with_max_clock_skew(mut self, skew: Duration) -> Self {
+        self.max_clock_skew = skew;
+        self
+    }
+
+    /// Reports heights, proposals, votes and the mempool to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<ConsensusMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Loads the finalized chain from the store, returning its height.
+    /// The newest `RECENT_BLOCKS` stored bodies become the recent blocks,
+    /// and account state is rebuilt by re-executing stored blocks on top
+    /// of genesis or of the stored state base, taking in the key rotations
+    /// and registry calls they make. Fails if a block past the base can't be replayed, rather
+    /// than coming up on a state behind the finalized height. Every stored
+    /// body is indexed again, as it was when it finalized.
+    pub async fn restore(&self) -> Result<u64, String> {
+        let Some(store) = &self.store else {
+            return Ok(0);
+        };
+        let height = store.latest_height().map_err(|e| e.to_string())?;
+        let finalized = store.hashes(1..=height).map(|entry| entry.map(|(_, hash)| hash)).collect::<Result<Vec<_>, _>>();
+        let finalized = finalized.map_err(|e| e.to_string())?;
+        if finalized.len() as u64 != height {
+            return Err(format!("block store has {} of {} heights", finalized.len(), height));
+        }
+        let from = height.saturating_sub(RECENT_BLOCKS as u64) + 1;
+        let recent = store.blocks(from..=height).map(|entry| entry.map(|(_, block)| block)).collect::<Result<VecDeque<_>, _>>();
+        let mut validators = self.validator_set.write().await;
+        let mut state = self.consensus_state.write().await;
+        state.recent_blocks = recent.map_err(|e| e.to_string())?;
+        let mut base = 0;
+        if let Some((height, accounts)) = store.state_base().map_err(|e| e.to_string())? {
+            state.accounts = StateTrie::from_accounts(accounts);
+            let root = state.accounts.root();
+            state.pruner.retain(height, Some(root));
+            if let Some(set) = store.base_validators().map_err(|e| e.to_string())? {
+                *validators = set;
+            }
+            base = height;
+        }
+        // Bodies at or below the base are kept only within retention
+        for entry in store.blocks(1..=base) {
+            let (height, block) = entry.map_err(|e| e.to_string())?;
+            state.index.finalized(&block, height);
+        }
+        for (height, hash) in (1..).zip(&finalized).skip(base as usize) {
+            let stuck = |why: String| format!("cannot rebuild state past height {}: {}", height - 1, why);
+            let block = store
+                .block(hash)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| stuck(format!("block {} at height {} has no stored body", hash, height)))?;
+            let Some(proposer) = validators.account(&block.proposer_id) else {
+                return Err(stuck(format!("proposer {} of block {} is not a validator", block.proposer_id, hash)));
+            };
+            let env = BlockEnv {
+                chain_id: self.chain_id,
+                gas_limit: state.params.block_gas_limit,
+                base_fee: block.base_fee,
+                proposer: Some(proposer),
+                vm: state.params.vm,
+                timestamp: block.timestamp,
+            };
+            let replayed = execution::execute(&state.accounts, &env, &block.transactions)
+                .map(|execution| (execution.post_state(&mut state.accounts), execution.outcomes));
+            match replayed {
+                Ok((root, outcomes)) if root.to_string() == block.state_root => {
+                    state.accounts.set_root(root);
+                    state.pruner.retain(height, Some(root));
+                    validators.bond(&block.transactions, &outcomes, height, &state.params);
+                    validators.advance(&block.transactions, &outcomes, height, state.params.epoch_length);
+                    state.index.finalized(&block, height);
+                }
+                Ok((root, _)) => {
+                    return Err(stuck(format!("block {} replays to state root {}, not {}", hash, root, block.state_root)));
+                }
+                Err(e) => return Err(stuck(format!("block {} does not execute: {}", hash, e))),
+            }
+        }
+        if let Some(head) = finalized.last().map(|hash| store.header(hash)).transpose().map_err(|e| e.to_string())?.flatten() {
+            state.base_fee = fees::next_base_fee(head.base_fee, head.gas_used, state.params.block_gas_limit);
+        }
+        let ConsensusState { accounts, pruner, .. } = &mut *state;
+        pruner.prune(accounts);
+        state.finalized_blocks = finalized;
+        state.current_height = state.current_height.max(height);
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_heights(state.current_height, height);
+        }
+        Ok(height)
+    }
+
+    /// Stops or restarts voting on verified proposals, which are still
+    /// verified and followed. Returns whether voting was paused before.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Stops or restarts admitting transactions to the mempool, whether
+    /// submitted or gossiped. Returns whether admission was paused before.
+    pub fn set_admission_paused(&self, paused: bool) -> bool {
+        self.admission_paused.swap(paused, Ordering::Relaxed)
+    }
+
+    /// Stops or restarts voting while this node's clock is off, apart from
+    /// an operator's pause. Returns whether it was off before.
+    pub fn set_clock_skewed(&self, skewed: bool) -> bool {
+        self.clock_skewed.swap(skewed, Ordering::Relaxed)
+    }
+
+    /// Faults injected for resilience testing; see `faults`.
+    #[cfg(feature = "chaos")]
+    pub fn faults(&self) -> &faults::Faults {
+        &self.faults
+    }
+
+    #[cfg(feature = "chaos")]
+    fn injected_storage_error(&self) -> Option<String> {
+        self.faults.storage_error()
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    fn injected_storage_error(&self) -> Option<String> {
+        None
+    }
+
+    /// How long recent proposals took to verify; see `timings`.
+    pub fn verification_times(&self) -> TimingSummary {
+        self.timings.lock().expect("Timings lock poisoned").summary()
+    }
+
+    /// Finality latency, missed votes and round changes over recent
+    /// blocks; see `health`.
+    pub fn health(&self) -> ConsensusHealth {
+        self.health.lock().expect("Health lock poisoned").summary()
+    }
+
+    /// Heads, finalizations, logs and transaction status changes from now
+    /// on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConsensusEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: ConsensusEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Seeds the validator set from `spec`, which must be for this node's chain.
+    pub async fn load_genesis(&self, spec: &ChainSpec) -> Result<(), String> {
+        if spec.chain_id != self.chain_id {
+            return Err(format!("chain spec is for chain {}, node runs chain {}", spec.chain_id, self.chain_id));
         }
+        *self.validator_set.write().await = spec.validator_set();
+        let mut state = self.consensus_state.write().await;
+        state.accounts = StateTrie::from_balances(&spec.accounts);
+        state.pruner = StatePruner::new(self.pruning);
+        state.params = spec.params;
+        state.base_fee = spec.params.initial_base_fee;
+        let limits = MempoolLimits { max_gas: spec.params.block_gas_limit, ..MempoolLimits::default() };
+        state.mempool = Mempool::new(self.chain_id).with_limits(limits).with_base_fee(state.base_fee);
+        Ok(())
     }
 
-    /// Main consensus loop (call from an async runtime)
+    /// Main consensus loop (call from an async runtime). Returns once every
+    /// proposal sender is dropped.
     pub async fn run(&self, mut proposal_rx: mpsc::Receiver<BlockProposal>, mut vote_tx: mpsc::Sender<Vote>) {
-        loop {
-            if let Some(proposal) = proposal_rx.recv().await {
-                if let Err(e) = self.process_block_proposal(proposal, &mut vote_tx).await {
-                    eprintln!("Proposal processing failed: {:?}", e);
+        while let Some(proposal) = proposal_rx.recv().await {
+            let result = self.process_block_proposal(proposal, &mut vote_tx).await;
+            if let Some(metrics) = &self.metrics {
+                metrics.observe_proposal(result.is_ok());
+            }
+            if let Err(e) = result {
+                tracing::warn!(error = %e, "proposal rejected");
+            }
+        }
+    }
+
+    /// Records a vote received from another validator, finalizing its block
+    /// once voters holding a supermajority of stake agree and this node has
+    /// verified it. Votes from unknown validators, not signed by the
+    /// voter's current key, or vouching for other roots than the block
+    /// verified here has, are ignored.
+    pub async fn record_vote(&self, vote: Vote) -> bool {
+        let block_hash = vote.block_hash.clone();
+        let counted = self.count_vote(vote).await;
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_vote(&block_hash, counted);
+        }
+        counted
+    }
+
+    async fn count_vote(&self, vote: Vote) -> bool {
+        let validator_set = self.validator_set.read().await;
+        let Some(validator) = validator_set.validators.get(&vote.voter_id) else {
+            return false;
+        };
+        if vote.verify_signature(&validator.public_key).is_err() {
+            return false;
+        }
+        let block_hash = vote.block_hash.clone();
+        let key = format!("{}:{}", vote.block_hash, vote.voter_id);
+        let mut state = self.consensus_state.write().await;
+        if state.recent_blocks.iter().any(|block| block.block_hash == block_hash && !vote.is_for(block)) {
+            return false;
+        }
+        if !state.votes.contains_key(&key) {
+            if !state.verified(&block_hash) {
+                state.evict_early_vote(&vote.voter_id, validator.stake);
+            }
+            state.index.vote(&vote);
+            // Stake comes from the validator set, not from what voters claim
+            *state.tallies.entry(block_hash.clone()).or_default() += validator.stake;
+        }
+        state.votes.insert(key, vote);
+        self.finalize_if_agreed(&mut state, &validator_set, &block_hash);
+        true
+    }
+
+    /// Finalizes `block_hash` if voters holding a supermajority of stake
+    /// have voted for it and this node has verified it, unless it already
+    /// is finalized.
+    fn finalize_if_agreed(&self, state: &mut ConsensusState, validator_set: &ValidatorSet, block_hash: &str) {
+        if state.tallies.get(block_hash).is_none_or(|stake| *stake < validator_set.supermajority_threshold) {
+            return;
+        }
+        let Some(block) = state.recent_blocks.iter().find(|block| block.block_hash == block_hash).cloned() else {
+            return;
+        };
+        if state.finalized_blocks.iter().rev().any(|hash| hash == block_hash) {
+            return;
+        }
+        state.finalized_blocks.push(block_hash.to_string());
+        let height = state.finalized_blocks.len() as u64;
+        state.index.finalized(&block, height);
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_finalized(height);
+        }
+        self.assess_finality(state, validator_set, block_hash);
+        // Written under the state lock, so disk and memory agree on
+        // the order of finalized blocks
+        if let Some(store) = &self.store {
+            let written = match self.injected_storage_error() {
+                Some(e) => Err(e),
+                None => store.insert_finalized(height, block_hash, Some(&block)).map_err(|e| e.to_string()),
+            };
+            if let Err(e) = written {
+                tracing::error!(block = %block_hash, height, error = %e, "failed to store finalized block");
+            } else if state.current_height == height {
+                // Moves the base replay starts from up behind the bodies
+                // retention drops; only at the head, where the accounts
+                // and validators held are those after `height`
+                let due = store.state_base_due(height).unwrap_or(false);
+                if due && state.accounts.root().to_string() == block.state_root {
+                    let based = store.set_state_base(height, &state.accounts.accounts(), validator_set);
+                    if let Err(e) = based {
+                        tracing::error!(height, error = %e, "failed to store state base");
+                    }
                 }
             }
         }
+        let root = block.state_root.parse().ok();
+        let ConsensusState { accounts, pruner, .. } = &mut *state;
+        pruner.finalized(accounts, height, root);
+        if let Some(stale) = height.checked_sub(RECENT_BLOCKS as u64) {
+            state.forget_votes(stale);
+        }
+        for hash in state.receipts.finalized(block_hash) {
+            let status = TxStatus::Finalized;
+            self.publish(ConsensusEvent::TxStatus { hash, status, block_hash: Some(block_hash.to_string()) });
+        }
+        self.publish(ConsensusEvent::Finalized { block_hash: block_hash.to_string(), height });
+    }
+
+    /// Updates consensus health for `block_hash` having just finalized,
+    /// assessing the votes for the block that finalized before it.
+    fn assess_finality(&self, state: &ConsensusState, validator_set: &ValidatorSet, block_hash: &str) {
+        let mut health = self.health.lock().expect("Health lock poisoned");
+        let (finality, previous) = health.finalized(block_hash);
+        let missed = previous.map(|previous| {
+            let mut missed: Vec<String> = validator_set
+                .validators
+                .keys()
+                .filter(|voter| !state.votes.contains_key(&format!("{}:{}", previous, voter)))
+                .cloned()
+                .collect();
+            missed.sort();
+            missed
+        });
+        if let Some(missed) = &missed {
+            health.missed(missed.clone());
+        }
+        if let Some(metrics) = &self.metrics {
+            if let Some(finality) = finality {
+                metrics.observe_finality(finality.latency, health.summary().proof_fetch_share);
+            }
+            if let Some(missed) = &missed {
+                metrics.observe_missed(missed);
+            }
+        }
+    }
+
+    /// Queues a transaction submitted to this node, returning its hash.
+    pub async fn submit_transaction(&self, tx: Transaction) -> Result<String, String> {
+        self.admit(tx, Origin::Local).await
+    }
+
+    /// Queues a transaction gossiped by a peer, returning its hash.
+    pub async fn receive_transaction(&self, tx: Transaction) -> Result<String, String> {
+        self.admit(tx, Origin::Peer).await
+    }
+
+    async fn admit(&self, tx: Transaction, origin: Origin) -> Result<String, String> {
+        let admitted = self.insert(tx, origin).await;
+        if let Some(metrics) = &self.metrics {
+            let origin = match origin {
+                Origin::Local => "local",
+                Origin::Peer => "peer",
+            };
+            metrics.observe_admission(origin, admitted.is_ok());
+            let state = self.consensus_state.read().await;
+            metrics.observe_mempool(state.mempool.len(), state.mempool.bytes());
+        }
+        admitted
+    }
+
+    async fn insert(&self, tx: Transaction, origin: Origin) -> Result<String, String> {
+        if self.admission_paused.load(Ordering::Relaxed) {
+            return Err("the node is not admitting transactions for now".to_string());
+        }
+        let hash = tx.hash.clone();
+        let displaced = {
+            let validators = self.validator_set.read().await;
+            let mut guard = self.consensus_state.write().await;
+            let state = &mut *guard;
+            if let Some(block) = state.receipts.get(&hash).and_then(|receipt| receipt.block_hash.as_ref()) {
+                return Err(format!("transaction {} is already in block {}", hash, block));
+            }
+            let height = state.current_height;
+            if tx.kind == TxKind::RotateKey {
+                validators.authorize_rotation(&tx, height + 1, state.params.epoch_length)?;
+            }
+            validators.authorize_registry_call(&tx)?;
+            let receipt = tx.clone();
+            let displaced = state.mempool.insert(tx, origin, &state.accounts, height).map_err(|e| e.to_string())?;
+            state.receipts.pending(&receipt, height);
+            for (tx, reason) in &displaced {
+                state.receipts.dropped(&tx.hash, reason.as_str());
+            }
+            displaced
+        };
+        self.publish(ConsensusEvent::TxStatus { hash: hash.clone(), status: TxStatus::Pending, block_hash: None });
+        for (tx, _) in displaced {
+            self.publish(ConsensusEvent::TxStatus { hash: tx.hash, status: TxStatus::Dropped, block_hash: None });
+        }
+        Ok(hash)
+    }
+
+    /// Up to `max` transactions for the next block, proposed by this
+    /// node at the head's base fee, in the order the mempool offers them,
+    /// leaving out any that would make the block invalid on the head
+    /// state, rotate a key they may not or make a registry call the
+    /// validator set can't take. A node that isn't a validator can't
+    /// propose, and gets none.
+    pub async fn select_transactions(&self, max: usize) -> Vec<Transaction> {
+        let validators = self.validator_set.read().await;
+        let Some(proposer) = validators.account(&self.node_id) else {
+            return vec![];
+        };
+        let state = self.consensus_state.read().await;
+        let (height, epoch_length) = (state.current_height + 1, state.params.epoch_length);
+        let mut registering = validators.clone();
+        let env = BlockEnv {
+            chain_id: self.chain_id,
+            gas_limit: state.params.block_gas_limit,
+            base_fee: state.base_fee,
+            proposer: Some(proposer),
+            vm: state.params.vm,
+            timestamp: unix_now(),
+        };
+        let candidates: Vec<Transaction> = state
+            .mempool
+            .best()
+            .into_iter()
+            .filter(|tx| tx.kind != TxKind::RotateKey || validators.authorize_rotation(tx, height, epoch_length).is_ok())
+            .filter(|tx| registering.stage_registry_call(tx).is_ok())
+            .take(max)
+            .cloned()
+            .collect();
+        execution::select(&state.accounts, &env, &candidates).0
+    }
+
+    /// Fees to offer for the next few blocks, from the tips paid in recent
+    /// ones.
+    pub async fn fee_estimate(&self) -> fees::FeeEstimate {
+        let state = self.consensus_state.read().await;
+        fees::estimate(state.recent_blocks.iter(), state.base_fee)
+    }
+
+    /// What `tx` would do as its sender's next transaction in the next
+    /// block; see `execution::simulate`.
+    pub async fn simulate(&self, tx: &Transaction) -> Result<execution::Simulation, String> {
+        let state = self.consensus_state.read().await;
+        let env = BlockEnv {
+            chain_id: self.chain_id,
+            gas_limit: state.params.block_gas_limit,
+            base_fee: state.base_fee,
+            proposer: None,
+            vm: state.params.vm,
+            timestamp: unix_now(),
+        };
+        execution::simulate(&state.accounts, &env, tx)
+    }
+
+    /// A recently verified block, or a finalized one from the store.
+    /// The lowest finalized height whose block this node can still serve.
+    pub async fn earliest_height(&self) -> u64 {
+        if let Some(earliest) = self.store.as_ref().and_then(|store| store.earliest_height().ok()) {
+            return earliest;
+        }
+        let state = self.consensus_state.read().await;
+        (state.current_height + 1).saturating_sub(state.recent_blocks.len() as u64).max(1)
+    }
+
+    pub async fn block(&self, block_hash: &str) -> Option<BlockProposal> {
+        let state = self.consensus_state.read().await;
+        let recent = state.recent_blocks.iter().find(|block| block.block_hash == block_hash).cloned();
+        recent.or_else(|| self.store.as_ref()?.block(block_hash).ok().flatten())
     }
 
     /// Validate block proposal, fetch and verify proof with mobile verifier, then submit vote
     pub async fn process_block_proposal(&self, proposal: BlockProposal, vote_tx: &mut mpsc::Sender<Vote>) -> Result<(), String> {
+        self.check_timestamp(&proposal).await?;
+
         // Fetch proof bundle by zkurl
-        let zkurl = ZkURL::from_str(&proposal.zkurl).map_err(|e| format!("Invalid zkURL: {e}"))?;
-        let proof_bundle: ProofBundle = self.zkurl_resolver.fetch_proof(&zkurl).await
+        let started = Instant::now();
+        #[cfg(feature = "chaos")]
+        tokio::time::sleep(self.faults.proof_fetch_delay()).await;
+        let proof_bundle: ProofBundle = self.zkurl_resolver.fetch_proof(&proposal.zkurl).await
             .map_err(|e| format!("Failed to fetch proof: {e}"))?;
+        let proof_fetch = started.elapsed();
 
-        // Use the mobile-optimized verifier!
-        let verifier = MobileProofVerifier::new();
-        let is_valid = verifier.verify_proof(&proof_bundle.proof)
+        // Use the mobile-optimized verifier, bound to this chain so proofs
+        // from other networks can't finalize our blocks. The node keeps one
+        // verifier so re-gossiped proofs hit its result cache.
+        let public_inputs = proof_bundle.public_inputs.transcript_bytes();
+        let is_valid = self.verifier
+            .verify_proof_with_public_inputs(&proof_bundle.proof, &public_inputs, self.chain_id, ProofPurpose::BlockFinality)
             .map_err(|e| format!("Proof verify error: {:?}", e))?;
         if !is_valid {
             return Err("Proof did not pass verification".to_string());
         }
 
         // Check block/proof consistency
+        if proposal.block_hash.len() > cubiq_types::MAX_STRING_BYTES {
+            return Err(format!("Block hash is {} bytes, longer than votes can sign", proposal.block_hash.len()));
+        }
         if proposal.block_hash != proof_bundle.public_inputs.block_hash {
             return Err("Block hash mismatch with proof's public inputs!".to_string());
         }
@@ -143,47 +1062,643 @@ impl QubeNode {
         if proposal.transactions.len() as u32 != proof_bundle.public_inputs.transaction_count {
             return Err("Transaction count mismatch!".to_string());
         }
-        let calc_gas: u64 = proposal.transactions.iter().map(|tx| tx.gas_used).sum();
-        if calc_gas != proof_bundle.public_inputs.gas_used {
-            return Err("Gas usage mismatch!".to_string());
+
+        // Re-execute the block, which fails if it asks for more than the
+        // block gas limit or sets the wrong base fee; the proposer's state
+        // root and gas must be ours
+        let (execution, state_root) = self.execute(&proposal).await?;
+        if proposal.state_root != state_root.to_string() {
+            return Err(format!("State root mismatch: block has {}, executing it gives {}", proposal.state_root, state_root));
+        }
+        let proven_gas = proof_bundle.public_inputs.gas_used;
+        if execution.gas_used() != proven_gas || proposal.gas_used != proven_gas {
+            return Err(format!(
+                "Gas usage mismatch: block has {}, proof has {}, executing the block uses {}",
+                proposal.gas_used,
+                proven_gas,
+                execution.gas_used()
+            ));
+        }
+        let round = self.consensus_state.read().await.current_round;
+        let round_changed = self.health.lock().expect("Health lock poisoned").verified(&proposal.block_hash, round, started, proof_fetch);
+        if let Some(metrics) = self.metrics.as_ref().filter(|_| round_changed) {
+            metrics.observe_round_change();
+        }
+        let height = self.accept(&proposal, &execution, state_root).await;
+        let timing = Timing { proof_fetch, verification: started.elapsed() };
+        self.timings.lock().expect("Timings lock poisoned").record(timing);
+        if !self.voting || self.is_paused() || self.clock_skewed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        // Unsigned votes aren't counted, so a node without a key abstains
+        let Some(key) = self.voting_key().await else {
+            tracing::warn!(block = %proposal.block_hash, "no validator key to sign a vote with");
+            return Ok(());
+        };
+
+        if !self.log_vote(height, &proposal.block_hash)? {
+            tracing::warn!(block = %proposal.block_hash, height, "already voted for another block at this height or a later one");
+            return Ok(());
         }
 
         // If passes all checks, create and send vote
-        let ts = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
-        let vote = Vote {
-            block_hash: proposal.block_hash.clone(),
-            voter_id: self.node_id.clone(),
-            stake: self.stake_amount,
-            timestamp: ts,
-            signature: "dummy_signature".to_string(), // TODO: cryptographic signature
-        };
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let vote = Vote::new(&proposal, &self.node_id, self.stake_amount, ts).sign(key);
         vote_tx.send(vote).await.map_err(|e| format!("Failed to send vote: {e}"))?;
         Ok(())
     }
+
+    /// Fails if `proposal` is timestamped before its parent, or further
+    /// ahead of this node's clock than `max_clock_skew`.
+    async fn check_timestamp(&self, proposal: &BlockProposal) -> Result<(), String> {
+        let now = unix_now();
+        let ahead = proposal.timestamp.saturating_sub(now);
+        if ahead > self.max_clock_skew.as_secs() {
+            return Err(format!("Block timestamp is {}s ahead of this node's clock, beyond the {}s tolerated", ahead, self.max_clock_skew.as_secs()));
+        }
+        let state = self.consensus_state.read().await;
+        match state.recent_blocks.back() {
+            Some(parent) if proposal.timestamp < parent.timestamp => {
+                Err(format!("Block timestamp {} is before its parent's, {}", proposal.timestamp, parent.timestamp))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The key the validator set has for this node, if the node holds it,
+    /// else the first it was given.
+    async fn voting_key(&self) -> Option<&SigningKey> {
+        let validators = self.validator_set.read().await;
+        let registered = validators.validators.get(&self.node_id).map(|validator| validator.public_key.as_str());
+        let held = self.validator_keys.iter().find(|key| Some(hex::encode(key.verifying_key().to_bytes())).as_deref() == registered);
+        held.or(self.validator_keys.first())
+    }
+
+    /// Executes `block`'s transactions on the head state, returning the
+    /// execution and the post-state root without moving the head. Fails
+    /// if the block's proposer isn't a validator, its base fee isn't the
+    /// one its parent set, or it rotates a key it may not or makes a
+    /// registry call the validator set can't take.
+    async fn execute(&self, block: &BlockProposal) -> Result<(Execution, Hash), String> {
+        let validators = self.validator_set.read().await;
+        let proposer = validators.account(&block.proposer_id);
+        let proposer = proposer.ok_or_else(|| format!("Proposer {} is not a validator", block.proposer_id))?;
+        let mut state = self.consensus_state.write().await;
+        if block.base_fee != state.base_fee {
+            return Err(format!("Base fee mismatch: block has {}, its parent sets {}", block.base_fee, state.base_fee));
+        }
+        let height = state.current_height + 1;
+        for tx in block.transactions.iter().filter(|tx| tx.kind == TxKind::RotateKey) {
+            validators
+                .authorize_rotation(tx, height, state.params.epoch_length)
+                .map_err(|e| format!("Transaction {} {}", tx.hash, e))?;
+        }
+        let mut registering = validators.clone();
+        for tx in &block.transactions {
+            registering.stage_registry_call(tx).map_err(|e| format!("Transaction {} {}", tx.hash, e))?;
+        }
+        let env = BlockEnv {
+            chain_id: self.chain_id,
+            gas_limit: state.params.block_gas_limit,
+            base_fee: block.base_fee,
+            proposer: Some(proposer),
+            vm: state.params.vm,
+            timestamp: block.timestamp,
+        };
+        let execution = execution::execute(&state.accounts, &env, &block.transactions).map_err(|e| e.to_string())?;
+        let state_root = execution.post_state(&mut state.accounts);
+        Ok((execution, state_root))
+    }
+
+    /// Records a verified proposal as the new head: account state moves to
+    /// `state_root`, the base fee adjusts to its gas usage, its
+    /// transactions move from the mempool into receipts, and pending
+    /// transactions that have waited `PENDING_BLOCKS` blocks or
+    /// that their sender can no longer pay for are dropped. Registry calls
+    /// and key rotations it includes become pending, and pending ones take
+    /// effect if it begins an epoch. Votes that arrived before it finalize
+    /// it if they suffice.
+    async fn accept(&self, proposal: &BlockProposal, execution: &Execution, state_root: Hash) -> u64 {
+        let (height, logs, dropped) = {
+            let mut validators = self.validator_set.write().await;
+            let mut guard = self.consensus_state.write().await;
+            let state = &mut *guard;
+            state.accounts.set_root(state_root);
+            if state.recent_blocks.len() == RECENT_BLOCKS {
+                state.recent_blocks.pop_front();
+            }
+            state.recent_blocks.push_back(proposal.clone());
+            state.drop_votes_not_for(proposal, &validators);
+            state.current_height += 1;
+            state.base_fee = fees::next_base_fee(proposal.base_fee, execution.gas_used(), state.params.block_gas_limit);
+            let height = state.current_height;
+            for call in validators.bond(&proposal.transactions, &execution.outcomes, height, &state.params) {
+                tracing::info!(validator = %call.node_id(), action = call.action(), height, "registry call took effect");
+            }
+            for rotation in validators.advance(&proposal.transactions, &execution.outcomes, height, state.params.epoch_length) {
+                tracing::info!(validator = %rotation.node_id, key = %rotation.public_key, height, "validator key rotated");
+            }
+            for (tx, outcome) in proposal.transactions.iter().zip(&execution.outcomes) {
+                state.receipts.included(tx, outcome, &proposal.block_hash, height);
+            }
+            let logs = state.logs.verified(proposal, height, &execution.outcomes);
+            state.mempool.remove_included(&proposal.transactions);
+            let dropped = state.mempool.revalidate(&state.accounts, height, state.base_fee);
+            for (tx, reason) in &dropped {
+                state.receipts.dropped(&tx.hash, reason.as_str());
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.observe_verified(&proposal.block_hash, height, state.current_round);
+                metrics.observe_mempool(state.mempool.len(), state.mempool.bytes());
+            }
+            (height, logs, dropped)
+        };
+        self.publish(ConsensusEvent::NewHead(proposal.header()));
+        if !logs.is_empty() {
+            self.publish(ConsensusEvent::Logs { logs });
+        }
+        for tx in &proposal.transactions {
+            let block_hash = Some(proposal.block_hash.clone());
+            self.publish(ConsensusEvent::TxStatus { hash: tx.hash.clone(), status: TxStatus::Included, block_hash });
+        }
+        for (tx, _) in dropped {
+            self.publish(ConsensusEvent::TxStatus { hash: tx.hash, status: TxStatus::Dropped, block_hash: None });
+        }
+        let validators = self.validator_set.read().await;
+        let mut state = self.consensus_state.write().await;
+        self.finalize_if_agreed(&mut state, &validators, &proposal.block_hash);
+        height
+    }
+
+    /// Logs in the store that this node votes for `block_hash` at
+    /// `height`, unless it voted at a later height or for another block at
+    /// this one, which it must not contradict, even after a restart on a
+    /// store rolled back below them. Returns whether to vote. A node
+    /// without a store keeps no log.
+    fn log_vote(&self, height: u64, block_hash: &str) -> Result<bool, String> {
+        let Some(store) = &self.store else {
+            return Ok(true);
+        };
+        match store.last_vote().map_err(|e| format!("Failed to read the vote log: {}", e))? {
+            Some((voted, _)) if voted > height => return Ok(false),
+            Some((voted, hash)) if voted == height => return Ok(hash == block_hash),
+            _ => {}
+        }
+        store.log_vote(height, block_hash).map_err(|e| format!("Failed to log vote: {}", e))?;
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use tokio::sync::mpsc;
-    use serde_json;
+    use receipts::PENDING_BLOCKS;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn address(seed: u8) -> String {
+        Address::from_key(&key(seed).verifying_key()).to_string()
+    }
+
+    /// A transfer to `BOB` signed by `key(1)`.
+    fn transfer(nonce: u64, value: u64) -> Transaction {
+        Transaction {
+            chain_id: 42161,
+            nonce,
+            to: BOB.to_string(),
+            value,
+            gas_limit: 21_000,
+            max_fee_per_gas: 1,
+            ..Transaction::default()
+        }
+        .sign(&key(1))
+    }
+
+    /// `node_id` as a genesis validator with `key(seed)`.
+    fn validator(node_id: &str, seed: u8, stake: u64) -> genesis::GenesisValidator {
+        let public_key = hex::encode(key(seed).verifying_key().to_bytes());
+        genesis::GenesisValidator { node_id: node_id.to_string(), public_key, stake, registration: None }
+    }
+
+    /// `voter`'s vote for an empty block `block_hash` with the zero state
+    /// root, signed with `key(seed)`.
+    fn vote(block_hash: &str, voter: &str, seed: u8) -> Vote {
+        Vote { block_hash: block_hash.to_string(), voter_id: voter.to_string(), stake: 10, ..Vote::default() }.sign(&key(seed))
+    }
+
+    /// A dev chain whose validator `v1` holds `key(9)`, funding `key(1)`'s
+    /// account with 100,000.
+    fn spec() -> ChainSpec {
+        let mut spec = ChainSpec::dev(42161, validator("v1", 9, 10), 0);
+        spec.accounts.insert(address(1), 100_000);
+        spec
+    }
+
+    /// Executes `block` on the head state and accepts it, as verification would.
+    async fn execute_and_accept(node: &QubeNode, block: &BlockProposal) {
+        let (execution, state_root) = node.execute(block).await.unwrap();
+        node.accept(block, &execution, state_root).await;
+    }
+
+    /// Accepts an empty block `block_hash`, so votes can finalize it.
+    async fn verify_empty(node: &QubeNode, block_hash: &str) {
+        let block = BlockProposal {
+            block_hash: block_hash.to_string(),
+            state_root: Hash::ZERO.to_string(),
+            zkurl: "zk://prover@unreachable.invalid/block".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: node.consensus_state.read().await.base_fee,
+            gas_used: 0,
+        };
+        execute_and_accept(node, &block).await;
+    }
 
     #[tokio::test]
-    async fn test_node_proposal_handles_invalid_zkurl() {
-        let node = QubeNode::new("tester".to_string(), 10_000, vec![]).await;
-        let (mut tx, mut rx) = mpsc::channel(8);
+    async fn test_node_proposal_handles_unreachable_zkurl() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        let (tx, rx) = mpsc::channel(8);
         let (vote_tx, _vote_rx) = mpsc::channel(8);
         tx.send(BlockProposal {
             block_hash: "h".to_string(),
             state_root: "r".to_string(),
-            zkurl: "invalid-scheme://".to_string(),
+            zkurl: "zk://prover@unreachable.invalid/block1".parse().unwrap(),
             transactions: vec![],
             proposer_id: "p".to_string(),
             timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
         }).await.ok();
         tokio::spawn(async move {
             node.run(rx, vote_tx).await
         });
         // If no panic, test passes for stub
     }
+
+    #[tokio::test]
+    async fn test_proposals_are_timestamped_within_the_skew_window_after_their_parent() {
+        let node = QubeNode::new("tester".to_string(), 7, 10, vec![]).await.with_max_clock_skew(Duration::from_secs(30));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let proposal = |timestamp| BlockProposal {
+            block_hash: "h".to_string(),
+            state_root: "r".to_string(),
+            zkurl: "zk://prover@unreachable.invalid/block1".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "p".to_string(),
+            timestamp,
+            base_fee: 1,
+            gas_used: 0,
+        };
+        let (mut vote_tx, _vote_rx) = mpsc::channel(8);
+        let ahead = node.process_block_proposal(proposal(now + 120), &mut vote_tx).await.unwrap_err();
+        assert!(ahead.contains("ahead of this node's clock"), "{ahead}");
+        // Within the window, the proposal gets as far as fetching its proof
+        let within = node.process_block_proposal(proposal(now + 20), &mut vote_tx).await.unwrap_err();
+        assert!(within.starts_with("Failed to fetch proof"), "{within}");
+
+        node.consensus_state.write().await.recent_blocks.push_back(proposal(now));
+        let early = node.process_block_proposal(proposal(now - 1), &mut vote_tx).await.unwrap_err();
+        assert!(early.contains("before its parent's"), "{early}");
+        assert!(!node.set_clock_skewed(true) && node.set_clock_skewed(false));
+    }
+
+    #[tokio::test]
+    async fn test_records_votes_from_known_validators_only() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        node.load_genesis(&spec()).await.unwrap();
+        assert!(node.record_vote(vote("h", "v1", 9)).await);
+        assert!(!node.record_vote(vote("h", "stranger", 9)).await);
+        // Nor may anyone vote as v1 without its key
+        assert!(!node.record_vote(vote("h2", "v1", 1)).await);
+        let unsigned = Vote { signature: String::new(), ..vote("h2", "v1", 9) };
+        assert!(!node.record_vote(unsigned).await);
+        assert_eq!(node.consensus_state.read().await.group_votes_by_block()["h"].len(), 1);
+        assert!(!node.consensus_state.read().await.tallies.contains_key("h2"));
+
+        // An early vote for other roots is dropped once the block verifies
+        let rerooted = Vote { transactions_root: Hash([1; 32]), signature: String::new(), ..vote("h3", "v1", 9) }.sign(&key(9));
+        assert!(node.record_vote(rerooted.clone()).await);
+        verify_empty(&node, "h3").await;
+        assert!(!node.consensus_state.read().await.tallies.contains_key("h3"));
+        assert!(!node.record_vote(rerooted).await);
+    }
+
+    #[tokio::test]
+    async fn test_supermajority_of_stake_finalizes_once() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        let mut spec = ChainSpec::dev(42161, validator("v1", 1, 40), 0);
+        spec.validators.extend([validator("v2", 2, 35), validator("v3", 3, 25)]);
+        node.load_genesis(&spec).await.unwrap();
+        let mut events = node.subscribe();
+        // Claimed stake is ignored; v1 and v3 hold 65 of the 67 needed
+        let vote = |voter: &str, seed| Vote { stake: 1_000, signature: String::new(), ..vote("h", voter, seed) }.sign(&key(seed));
+
+        node.record_vote(vote("v1", 1)).await;
+        node.record_vote(vote("v3", 3)).await;
+        node.record_vote(vote("v2", 2)).await;
+        node.record_vote(vote("v2", 2)).await;
+        // Not until this node has verified the block itself
+        assert!(node.consensus_state.read().await.finalized_blocks.is_empty());
+        assert_eq!(node.consensus_state.read().await.tallies["h"], 100);
+        verify_empty(&node, "h").await;
+        assert_eq!(node.consensus_state.read().await.finalized_blocks, ["h"]);
+        assert!(matches!(events.try_recv().unwrap(), ConsensusEvent::NewHead(_)));
+        assert_eq!(events.try_recv().unwrap(), ConsensusEvent::Finalized { block_hash: "h".to_string(), height: 1 });
+        node.record_vote(vote("v1", 1)).await;
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_votes_for_unverified_blocks_are_bounded_per_voter() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        node.load_genesis(&spec()).await.unwrap();
+        for n in 0..EARLY_VOTES as u64 + 2 {
+            let vote = Vote { timestamp: n, ..vote(&format!("x{}", n), "v1", 9) }.sign(&key(9));
+            assert!(node.record_vote(vote).await);
+        }
+        let state = node.consensus_state.read().await;
+        assert_eq!((state.votes.len(), state.tallies.len()), (EARLY_VOTES, EARLY_VOTES));
+        assert!(!state.tallies.contains_key("x0") && !state.tallies.contains_key("x1"));
+    }
+
+    #[tokio::test]
+    async fn test_votes_are_missed_if_absent_when_the_next_block_finalizes() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        let mut spec = ChainSpec::dev(42161, validator("v1", 1, 40), 0);
+        spec.validators.extend([validator("v2", 2, 35), validator("v3", 3, 25)]);
+        node.load_genesis(&spec).await.unwrap();
+
+        for block_hash in ["h1", "h2", "h3"] {
+            verify_empty(&node, block_hash).await;
+            node.record_vote(vote(block_hash, "v1", 1)).await;
+            node.record_vote(vote(block_hash, "v2", 2)).await;
+            if block_hash == "h2" {
+                // Late, but before h3 finalizes
+                node.record_vote(vote(block_hash, "v3", 3)).await;
+            }
+        }
+        let health = node.health();
+        assert_eq!(node.consensus_state.read().await.finalized_blocks, ["h1", "h2", "h3"]);
+        assert_eq!(health.assessed_blocks, 2);
+        assert_eq!(health.missed_votes, BTreeMap::from([("v3".to_string(), 1)]));
+        // None went through proposal verification, so none were timed
+        assert_eq!(health.finalized_samples, 0);
+    }
+
+    #[tokio::test]
+    async fn test_submitted_transactions_must_match_their_hash() {
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await;
+        node.load_genesis(&spec()).await.unwrap();
+        let mut tx = transfer(0, 5);
+        assert_eq!(node.submit_transaction(tx.clone()).await.unwrap(), tx.hash);
+        assert!(node.submit_transaction(tx.clone()).await.unwrap_err().contains("already pending"));
+
+        tx.value = 500;
+        assert!(node.submit_transaction(tx.clone()).await.unwrap_err().contains("does not match"));
+        tx.hash = tx.compute_hash();
+        assert!(node.submit_transaction(tx.clone()).await.unwrap_err().contains("signature"));
+        let broke = Transaction { nonce: 0, ..tx }.sign(&key(2));
+        assert!(node.receive_transaction(broke).await.unwrap_err().contains(&format!("{} has 0", address(2))));
+        assert_eq!(node.consensus_state.read().await.mempool.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_receipts_track_inclusion_finality_and_expiry() {
+        let node = QubeNode::new("v1".to_string(), 42161, 10_000, vec![]).await;
+        node.load_genesis(&spec()).await.unwrap();
+        let block = |hash: &str, transactions| BlockProposal {
+            block_hash: hash.to_string(),
+            state_root: Hash::ZERO.to_string(),
+            zkurl: "zk://prover@unreachable.invalid/block1".parse().unwrap(),
+            transactions,
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        };
+        let (included, stale) = (transfer(0, 1), transfer(1, 2));
+        node.submit_transaction(included.clone()).await.unwrap();
+        node.submit_transaction(stale.clone()).await.unwrap();
+        let selected = node.select_transactions(1).await;
+        assert_eq!(selected.iter().map(|tx| &tx.hash).collect::<Vec<_>>(), [&included.hash]);
+
+        let b1 = block("b1", vec![included.clone()]);
+        execute_and_accept(&node, &b1).await;
+        assert!(node.submit_transaction(included.clone()).await.unwrap_err().contains("already in block b1"));
+        // Only a vote for the block's own roots counts
+        assert!(!node.record_vote(vote("b1", "v1", 9)).await);
+        node.record_vote(Vote::new(&b1, "v1", 10, 0).sign(&key(9))).await;
+        for n in 2..=PENDING_BLOCKS {
+            execute_and_accept(&node, &block(&format!("b{}", n), vec![])).await;
+        }
+        let state = node.consensus_state.read().await;
+        let receipt = state.receipts.get(&included.hash).unwrap();
+        assert_eq!((receipt.status, receipt.block_height, receipt.gas_used), (TxStatus::Finalized, Some(1), 21_000));
+        assert_eq!(receipt.events[0].kind, "transfer");
+        let to = logs::LogFilter { attributes: [("to".to_string(), included.to.clone())].into(), ..logs::LogFilter::default() };
+        assert_eq!(state.logs.query(&to).unwrap().iter().map(|log| (log.height, &log.tx_hash)).collect::<Vec<_>>(), [(1, &included.hash)]);
+        assert_eq!(state.accounts.get(&address(1)).balance, 100_000 - 1 - 21_000);
+        let receipt = state.receipts.get(&stale.hash).unwrap();
+        assert_eq!(receipt.status, TxStatus::Dropped);
+        assert!(receipt.reason.as_ref().unwrap().contains("not included"));
+        assert!(state.mempool.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocks_pay_tips_to_their_proposer_at_the_base_fee_their_parent_sets() {
+        let mut spec = spec();
+        spec.params.block_gas_limit = 40_000;
+        spec.params.initial_base_fee = 3;
+        let node = QubeNode::new("v1".to_string(), 42161, 10_000, vec![]).await;
+        node.load_genesis(&spec).await.unwrap();
+        let tx = Transaction { max_fee_per_gas: 4, max_priority_fee_per_gas: 1, ..transfer(0, 5) }.sign(&key(1));
+        node.submit_transaction(tx.clone()).await.unwrap();
+        let mut block = BlockProposal {
+            block_hash: "b1".to_string(),
+            state_root: String::new(),
+            zkurl: "zk://prover@unreachable.invalid/block1".parse().unwrap(),
+            transactions: node.select_transactions(10).await,
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 2,
+            gas_used: 21_000,
+        };
+        assert!(node.execute(&block).await.unwrap_err().contains("has 2, its parent sets 3"));
+        block.base_fee = 3;
+        execute_and_accept(&node, &block).await;
+
+        let state = node.consensus_state.read().await;
+        assert_eq!(state.receipts.get(&tx.hash).unwrap().gas_price, 4);
+        assert_eq!(state.accounts.get(&address(1)).balance, 100_000 - 5 - 4 * 21_000);
+        // The proposer's account, which the dev genesis funds too
+        assert_eq!(state.accounts.get(&address(9)).balance, 1_000_000_000 + 21_000);
+        // Just over the 20,000 target, the base fee still rises
+        assert_eq!(state.base_fee, 3 + 1);
+        drop(state);
+        let estimate = node.fee_estimate().await;
+        assert_eq!((estimate.base_fee, estimate.max_priority_fee_per_gas, estimate.max_fee_per_gas), (4, 1, 9));
+    }
+
+    #[tokio::test]
+    async fn test_rotated_keys_sign_votes_from_their_epoch() {
+        let public_key = |seed| hex::encode(key(seed).verifying_key().to_bytes());
+        let mut spec = ChainSpec::dev(42161, validator("v1", 1, 10), 0);
+        spec.params.epoch_length = 2;
+        let node = QubeNode::new("v1".to_string(), 42161, 10, vec![]).await.with_validator_key(key(1)).with_validator_key(key(2));
+        node.load_genesis(&spec).await.unwrap();
+        let rotation = |epoch| {
+            let unsigned = KeyRotation { node_id: "v1".to_string(), public_key: public_key(2), epoch, registration: None }.transaction();
+            Transaction { chain_id: 42161, gas_limit: 100_000, max_fee_per_gas: 1, ..unsigned }.sign(&key(1))
+        };
+        assert!(node.submit_transaction(rotation(0)).await.unwrap_err().contains("epoch 0 has begun"));
+        node.submit_transaction(rotation(1)).await.unwrap();
+
+        let block = |height: u64, transactions, base_fee| BlockProposal {
+            block_hash: format!("b{}", height),
+            state_root: Hash::ZERO.to_string(),
+            zkurl: format!("zk://prover@unreachable.invalid/block{}", height).parse().unwrap(),
+            transactions,
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee,
+            gas_used: 0,
+        };
+        let base_fee = node.consensus_state.read().await.base_fee;
+        let b1 = block(1, node.select_transactions(10).await, base_fee);
+        execute_and_accept(&node, &b1).await;
+        assert_eq!(node.validator_set.read().await.pending_rotations["v1"].epoch, 1);
+        assert_eq!(node.voting_key().await, Some(&key(1)));
+        assert!(node.record_vote(Vote::new(&b1, "v1", 10, 0).sign(&key(1))).await);
+
+        let base_fee = node.consensus_state.read().await.base_fee;
+        execute_and_accept(&node, &block(2, vec![], base_fee)).await;
+        assert_eq!(node.validator_set.read().await.validators["v1"].public_key, public_key(2));
+        assert_eq!(node.voting_key().await, Some(&key(2)));
+        assert!(!node.record_vote(vote("b2", "v1", 1)).await);
+        assert!(node.record_vote(vote("b2", "v1", 2)).await);
+        assert!(node.select_transactions(10).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_votes_are_not_contradicted_after_a_restart() {
+        let dir = std::env::temp_dir().join(format!("cubiq-vote-log-{}", std::process::id()));
+        {
+            let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await
+                .with_store(BlockStore::open(&dir, None).unwrap());
+            assert_eq!(node.log_vote(1, "b1"), Ok(true));
+        }
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await
+            .with_store(BlockStore::open(&dir, None).unwrap());
+        assert_eq!(node.log_vote(1, "b1"), Ok(true));
+        assert_eq!(node.log_vote(1, "c1"), Ok(false));
+        assert_eq!(node.log_vote(2, "c2"), Ok(true));
+        assert_eq!(node.log_vote(1, "b1"), Ok(false));
+        drop(node);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_finalized_blocks_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("cubiq-restore-{}", std::process::id()));
+        let spec = spec();
+        let tx = transfer(0, 7);
+        let mut block = BlockProposal {
+            block_hash: "b1".to_string(),
+            state_root: String::new(),
+            zkurl: "zk://prover@unreachable.invalid/block1".parse().unwrap(),
+            transactions: vec![tx],
+            proposer_id: "v1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 21_000,
+        };
+        {
+            let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await
+                .with_store(BlockStore::open(&dir, None).unwrap());
+            node.load_genesis(&spec).await.unwrap();
+            let (execution, state_root) = node.execute(&block).await.unwrap();
+            block.state_root = state_root.to_string();
+            node.accept(&block, &execution, state_root).await;
+            node.record_vote(Vote::new(&block, "v1", 10, 0).sign(&key(9))).await;
+            node.record_vote(vote("b2", "v1", 9)).await;
+        }
+
+        let node = QubeNode::new("tester".to_string(), 42161, 10_000, vec![]).await
+            .with_store(BlockStore::open(&dir, None).unwrap());
+        node.load_genesis(&spec).await.unwrap();
+        // b2 was never verified, so its vote did not finalize it
+        assert_eq!(node.restore().await.unwrap(), 1);
+        let state = node.consensus_state.read().await;
+        assert_eq!(state.finalized_blocks, ["b1"]);
+        assert_eq!(state.accounts.root().to_string(), block.state_root);
+        assert_eq!(state.accounts.get(BOB).balance, 7);
+        assert_eq!(state.index.blocks_by_proposer("v1", None, 10).items[0].block_hash, "b1");
+        assert_eq!(state.index.transactions(BOB, None, 10).items.len(), 1);
+        drop(state);
+        assert_eq!(node.block("b1").await.unwrap().proposer_id, "v1");
+        assert!(node.block("b2").await.is_none());
+        drop(node);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_votes_verify_against_their_signers_key() {
+        let vote = Vote { block_hash: "b1".to_string(), voter_id: "v1".to_string(), stake: 10, timestamp: 3, ..Vote::default() };
+        let public_key = hex::encode(key(1).verifying_key().to_bytes());
+        assert!(vote.verify_signature(&public_key).unwrap_err().contains("not a hex-encoded"));
+        let signed = vote.clone().sign(&key(1));
+        signed.verify_signature(&public_key).unwrap();
+        assert!(signed.verify_signature(&hex::encode(key(2).verifying_key().to_bytes())).is_err());
+        let moved = Vote { block_hash: "b2".to_string(), ..signed.clone() };
+        assert_eq!(moved.verify_signature(&public_key).unwrap_err(), "signature does not match the voter");
+        let rerooted = Vote { state_root: Hash([1; 32]), ..signed.clone() };
+        assert!(rerooted.verify_signature(&public_key).is_err());
+        let long = Vote { block_hash: "b".repeat(cubiq_types::MAX_STRING_BYTES + 1), ..signed };
+        assert!(long.verify_signature(&public_key).is_err());
+    }
+
+    #[test]
+    fn test_invalid_zkurl_rejected_before_proposal() {
+        assert!("invalid-scheme://".parse::<ZkURL>().is_err());
+    }
 }
+
+pub mod address;
+pub mod genesis;
+pub mod events;
+pub mod receipts;
+pub mod index;
+pub mod logs;
+pub mod store;
+pub mod freezer;
+pub mod kv;
+pub mod state;
+pub mod merkle;
+pub mod ssz;
+pub mod bridge;
+pub mod tendermint;
+pub mod mempool;
+pub mod execution;
+pub mod contracts;
+pub mod multisig;
+pub mod rotation;
+pub mod registration;
+pub mod staking;
+#[cfg(feature = "evm")]
+pub mod evm;
+pub mod fees;
+pub mod pruning;
+pub mod snapshot;
+pub mod replay;
+pub mod metrics;
+pub mod compaction;
+pub mod timings;
+pub mod health;
+#[cfg(feature = "chaos")]
+pub mod faults;
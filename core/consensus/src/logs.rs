@@ -0,0 +1,255 @@
+//! Logs: the events transactions emit, found by filter.
+//!
+//! As each block is verified its transactions' events become logs, which
+//! `LogIndex` keeps for the newest `MAX_LOG_BLOCKS` blocks verified since
+//! the node started, each with a `Bloom` of its event kinds and
+//! `key=value` attributes. A query only reads the logs of blocks whose
+//! bloom says they may match; a bloom can say so wrongly, but never
+//! misses a block that does.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::execution::TxOutcome;
+use crate::receipts::TxEvent;
+use crate::BlockProposal;
+
+/// Size of a block's bloom.
+pub const BLOOM_BYTES: usize = 256;
+
+/// Bits set per item.
+const BLOOM_HASHES: usize = 3;
+
+/// Blocks whose logs are kept before the oldest are forgotten.
+pub const MAX_LOG_BLOCKS: usize = 10_000;
+
+/// Most logs one query returns.
+pub const MAX_LOGS: usize = 10_000;
+
+/// A 2048-bit Bloom filter, written as `0x`-prefixed hex.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Bloom(pub [u8; BLOOM_BYTES]);
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom([0; BLOOM_BYTES])
+    }
+}
+
+impl Bloom {
+    /// Over the kinds and attributes of `events`.
+    pub fn of<'a>(events: impl IntoIterator<Item = &'a TxEvent>) -> Self {
+        let mut bloom = Bloom::default();
+        for event in events {
+            bloom.add(event.kind.as_bytes());
+            for (key, value) in &event.attributes {
+                bloom.add(attribute(key, value).as_bytes());
+            }
+        }
+        bloom
+    }
+
+    pub fn add(&mut self, item: &[u8]) {
+        for bit in bits(item) {
+            self.0[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Whether `item` may have been added; never false when it was.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        bits(item).into_iter().all(|bit| self.0[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+}
+
+/// The bits of `item`, each from two bytes of its blake3 hash.
+fn bits(item: &[u8]) -> [usize; BLOOM_HASHES] {
+    let hash = blake3::hash(item);
+    let hash = hash.as_bytes();
+    std::array::from_fn(|i| u16::from_be_bytes([hash[2 * i], hash[2 * i + 1]]) as usize % (BLOOM_BYTES * 8))
+}
+
+fn attribute(key: &str, value: &str) -> String {
+    format!("{}={}", key, value)
+}
+
+impl fmt::Display for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Bloom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Bloom({})", self)
+    }
+}
+
+impl Serialize for Bloom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// An event and where it was emitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Log {
+    pub block_hash: String,
+    /// Verified height of the block
+    pub height: u64,
+    pub tx_hash: String,
+    /// Position among the block's logs
+    pub index: usize,
+    #[serde(flatten)]
+    pub event: TxEvent,
+}
+
+/// Which logs to find; every field narrows it, and none matches every
+/// log still kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogFilter {
+    /// Least height, inclusive
+    pub from_height: Option<u64>,
+    /// Greatest height, inclusive
+    pub to_height: Option<u64>,
+    pub block_hash: Option<String>,
+    /// Any of these kinds, or any kind when empty
+    pub kinds: Vec<String>,
+    /// Attributes a log must have, with these values
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, log: &Log) -> bool {
+        self.from_height.is_none_or(|from| log.height >= from)
+            && self.to_height.is_none_or(|to| log.height <= to)
+            && self.block_hash.as_ref().is_none_or(|hash| *hash == log.block_hash)
+            && (self.kinds.is_empty() || self.kinds.contains(&log.event.kind))
+            && self.attributes.iter().all(|(key, value)| log.event.attributes.get(key) == Some(value))
+    }
+
+    /// Whether a block with `bloom` may hold a matching log.
+    fn may_match(&self, bloom: &Bloom) -> bool {
+        (self.kinds.is_empty() || self.kinds.iter().any(|kind| bloom.contains(kind.as_bytes())))
+            && self.attributes.iter().all(|(key, value)| bloom.contains(attribute(key, value).as_bytes()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BlockLogs {
+    block_hash: String,
+    height: u64,
+    bloom: Bloom,
+    logs: Vec<Log>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogIndex {
+    /// Oldest first
+    blocks: VecDeque<BlockLogs>,
+}
+
+impl LogIndex {
+    /// Keeps the logs of `block`, verified at `height` with `outcomes`,
+    /// and returns them.
+    pub fn verified(&mut self, block: &BlockProposal, height: u64, outcomes: &[TxOutcome]) -> Vec<Log> {
+        let events = outcomes.iter().flat_map(|outcome| outcome.events.iter().map(move |event| (&outcome.tx_hash, event)));
+        let logs: Vec<Log> = events
+            .enumerate()
+            .map(|(index, (tx_hash, event))| Log {
+                block_hash: block.block_hash.clone(),
+                height,
+                tx_hash: tx_hash.clone(),
+                index,
+                event: event.clone(),
+            })
+            .collect();
+        if self.blocks.len() == MAX_LOG_BLOCKS {
+            self.blocks.pop_front();
+        }
+        let bloom = Bloom::of(logs.iter().map(|log| &log.event));
+        self.blocks.push_back(BlockLogs { block_hash: block.block_hash.clone(), height, bloom, logs: logs.clone() });
+        logs
+    }
+
+    /// The bloom of a kept block.
+    pub fn bloom(&self, block_hash: &str) -> Option<Bloom> {
+        self.blocks.iter().rev().find(|block| block.block_hash == block_hash).map(|block| block.bloom)
+    }
+
+    /// Kept logs matching `filter`, oldest first, or an error if there
+    /// are more than `MAX_LOGS`.
+    pub fn query(&self, filter: &LogFilter) -> Result<Vec<Log>, String> {
+        let start = filter.from_height.map_or(0, |from| self.blocks.partition_point(|block| block.height < from));
+        let mut found = vec![];
+        for block in self.blocks.range(start..) {
+            if filter.to_height.is_some_and(|to| block.height > to) {
+                break;
+            }
+            if filter.block_hash.as_ref().is_some_and(|hash| *hash != block.block_hash) || !filter.may_match(&block.bloom) {
+                continue;
+            }
+            found.extend(block.logs.iter().filter(|log| filter.matches(log)).cloned());
+            if found.len() > MAX_LOGS {
+                return Err(format!("more than {} logs match; narrow the filter", MAX_LOGS));
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::transfer_event;
+
+    fn block(hash: &str) -> BlockProposal {
+        BlockProposal {
+            block_hash: hash.to_string(),
+            state_root: String::new(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: String::new(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        }
+    }
+
+    fn outcome(tx_hash: &str, events: Vec<TxEvent>) -> TxOutcome {
+        TxOutcome { tx_hash: tx_hash.to_string(), gas_used: 0, gas_price: 1, error: None, events, output: vec![] }
+    }
+
+    #[test]
+    fn blooms_hold_what_was_added() {
+        let bloom = Bloom::of([&transfer_event("0xalice", "0xbob", 5)]);
+        assert!(bloom.contains(b"transfer") && bloom.contains(b"to=0xbob") && bloom.contains(b"value=5"));
+        assert!(!bloom.contains(b"to=0xcarol"));
+        assert!(!Bloom::default().contains(b"transfer"));
+        assert_eq!(Bloom::default().to_string().len(), 2 + 2 * BLOOM_BYTES);
+    }
+
+    #[test]
+    fn finds_logs_by_height_kind_and_attributes() {
+        let mut index = LogIndex::default();
+        let counted = TxEvent { kind: "counted".to_string(), attributes: BTreeMap::from([("contract".to_string(), "c1".to_string())]) };
+        let logs = index.verified(&block("b1"), 1, &[outcome("t1", vec![transfer_event("0xalice", "0xbob", 5), counted.clone()])]);
+        assert_eq!((logs.len(), logs[1].index, logs[1].tx_hash.as_str()), (2, 1, "t1"));
+        index.verified(&block("b2"), 2, &[outcome("t2", vec![]), outcome("t3", vec![transfer_event("0xbob", "0xcarol", 1)])]);
+        index.verified(&block("b3"), 3, &[]);
+
+        let transfers = LogFilter { kinds: vec!["transfer".to_string()], ..LogFilter::default() };
+        let found = index.query(&transfers).unwrap();
+        assert_eq!(found.iter().map(|log| log.height).collect::<Vec<_>>(), [1, 2]);
+        let to_bob = LogFilter { attributes: BTreeMap::from([("to".to_string(), "0xbob".to_string())]), ..transfers.clone() };
+        assert_eq!(index.query(&to_bob).unwrap(), logs[..1]);
+        let later = LogFilter { from_height: Some(2), ..transfers.clone() };
+        assert_eq!(index.query(&later).unwrap()[0].tx_hash, "t3");
+        assert!(index.query(&LogFilter { to_height: Some(0), ..LogFilter::default() }).unwrap().is_empty());
+        assert_eq!(index.query(&LogFilter { block_hash: Some("b1".to_string()), ..LogFilter::default() }).unwrap(), logs);
+        assert_eq!(index.bloom("b3"), Some(Bloom::default()));
+        assert!(index.bloom("b2").unwrap().contains(b"from=0xbob"));
+    }
+}
@@ -0,0 +1,640 @@
+//! Transactions waiting for a block.
+//!
+//! Transactions arrive from RPC and from gossip. Admission runs the checks
+//! block execution will, against the head state: the hash, chain and
+//! signature, or enough of a multisig sender's cosignatures, the minimum
+//! gas and fees, and that the nonce is not yet used. A transaction asking for more gas than a block holds is turned
+//! away too. The sender must also be able to pay for the transaction on top of
+//! everything else it has pooled.
+//!
+//! Each sender's transactions are pending or queued. Pending ones carry
+//! consecutive nonces from the sender's next, so they can go in a block
+//! one after another; the rest are queued until the nonces before them
+//! arrive, and are then promoted. A block producer takes only pending
+//! transactions, by the tip they pay at the head's base fee, highest
+//! first, ties going to the earlier arrival, and each sender's in nonce
+//! order. It takes none of a sender's from the first whose fee cap is
+//! below the base fee, which stay pooled in case the base fee falls.
+//!
+//! A transaction with the nonce of one already pooled replaces it if it
+//! offers at least `REPLACEMENT_BUMP` percent more in both fee cap and
+//! tip; otherwise it is turned away.
+//!
+//! The pool holds at most `MAX_TRANSACTIONS` transactions in `MAX_BYTES`,
+//! and `MAX_PER_SENDER` from any one sender. When it is full, a newcomer
+//! evicts the last transaction of some sender that tips less, queued
+//! transactions before pending ones and the lowest tip first; otherwise
+//! the newcomer is turned away.
+
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+
+use crate::execution;
+use crate::fees::{self, MIN_BASE_FEE};
+use crate::genesis::ConsensusParams;
+use crate::multisig;
+use crate::receipts::PENDING_BLOCKS;
+use crate::state::StateTrie;
+use crate::Transaction;
+
+/// Transactions held at once.
+pub const MAX_TRANSACTIONS: usize = 10_000;
+
+/// Encoded size of the transactions held at once.
+pub const MAX_BYTES: usize = 32 << 20;
+
+/// Transactions held at once from one sender.
+pub const MAX_PER_SENDER: usize = 64;
+
+/// How much more, in percent, a replacement must offer than the
+/// transaction it replaces, in fee cap and in tip.
+pub const REPLACEMENT_BUMP: u64 = 10;
+
+/// Where a pooled transaction came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Submitted to this node, which should gossip it
+    Local,
+    /// Gossiped by a peer
+    Peer,
+}
+
+/// Why a transaction was turned away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MempoolError {
+    HashMismatch(String),
+    AlreadyPending(String),
+    /// Fails a check every block makes; see `execution::check`
+    Invalid(String),
+    /// The nonce is already used
+    Nonce { nonce: u64, expected: u64 },
+    /// Replacing a pooled transaction needs higher fees
+    Underpriced { max_fee_per_gas: u64, max_priority_fee_per_gas: u64 },
+    /// Asks for more gas than a block holds
+    GasLimit { gas_limit: u64, max: u64 },
+    SenderFull(String),
+    InsufficientBalance { sender: String, balance: u64, needed: u64 },
+    TooLarge(usize),
+    Full,
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MempoolError::HashMismatch(hash) => write!(f, "transaction hash {} does not match its contents", hash),
+            MempoolError::AlreadyPending(hash) => write!(f, "transaction {} is already pending", hash),
+            MempoolError::Invalid(reason) => write!(f, "transaction is invalid: {}", reason),
+            MempoolError::Nonce { nonce, expected } => write!(f, "transaction has nonce {}, the sender's next is {}", nonce, expected),
+            MempoolError::Underpriced { max_fee_per_gas, max_priority_fee_per_gas } => write!(
+                f,
+                "replacing a pooled transaction needs a fee cap of at least {} and a tip of at least {} per gas",
+                max_fee_per_gas, max_priority_fee_per_gas
+            ),
+            MempoolError::GasLimit { gas_limit, max } => write!(f, "transaction asks for {} gas, a block holds {}", gas_limit, max),
+            MempoolError::SenderFull(sender) => write!(f, "{} has too many pending transactions", sender),
+            MempoolError::InsufficientBalance { sender, balance, needed } => {
+                write!(f, "{} has {} but needs {} for its pending transactions", sender, balance, needed)
+            }
+            MempoolError::TooLarge(size) => write!(f, "transaction of {} bytes is too large", size),
+            MempoolError::Full => write!(f, "too many pending transactions"),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+/// How much the pool holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolLimits {
+    pub max_transactions: usize,
+    pub max_bytes: usize,
+    pub max_per_sender: usize,
+    /// Most gas one transaction may ask for, the block gas limit
+    pub max_gas: u64,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: MAX_TRANSACTIONS,
+            max_bytes: MAX_BYTES,
+            max_per_sender: MAX_PER_SENDER,
+            max_gas: ConsensusParams::default().block_gas_limit,
+        }
+    }
+}
+
+/// How many of one sender's transactions are pooled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SenderCounts {
+    /// Ready for a block
+    pub pending: usize,
+    /// Waiting for an earlier nonce
+    pub queued: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Pooled {
+    tx: Transaction,
+    origin: Origin,
+    /// Admission order, across senders
+    arrival: u64,
+    /// Head height when admitted
+    height: u64,
+    size: usize,
+}
+
+impl Pooled {
+    /// Higher is taken first, at `base_fee`.
+    fn priority(&self, base_fee: u64) -> (u64, Reverse<u64>) {
+        (fees::effective_tip(&self.tx, base_fee).unwrap_or(0), Reverse(self.arrival))
+    }
+}
+
+/// One sender's transaction hashes.
+#[derive(Debug, Clone, Default)]
+struct Queue {
+    /// Consecutive nonces from the sender's next
+    pending: VecDeque<String>,
+    /// By nonce, each after a gap
+    queued: BTreeMap<u64, String>,
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        self.pending.len() + self.queued.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.queued.is_empty()
+    }
+
+    /// In nonce order.
+    fn hashes(&self) -> impl Iterator<Item = &String> {
+        self.pending.iter().chain(self.queued.values())
+    }
+
+    /// The hash `back` places from the end, in nonce order, and whether
+    /// it is pending.
+    fn nth_back(&self, back: usize) -> Option<(&String, bool)> {
+        match back.checked_sub(self.queued.len()) {
+            None => self.queued.values().nth_back(back).map(|hash| (hash, false)),
+            Some(back) => self.pending.iter().nth_back(back).map(|hash| (hash, true)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mempool {
+    /// Chain transactions must be signed for
+    chain_id: u64,
+    limits: MempoolLimits,
+    /// Of the block after the head
+    base_fee: u64,
+    pooled: HashMap<String, Pooled>,
+    senders: HashMap<String, Queue>,
+    bytes: usize,
+    arrivals: u64,
+}
+
+impl Mempool {
+    pub fn new(chain_id: u64) -> Self {
+        Self { chain_id, base_fee: MIN_BASE_FEE, ..Self::default() }
+    }
+
+    pub fn with_limits(mut self, limits: MempoolLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Orders transactions for a next block with `base_fee`, until
+    /// `revalidate` moves it.
+    pub fn with_base_fee(mut self, base_fee: u64) -> Self {
+        self.base_fee = base_fee;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.pooled.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pooled.is_empty()
+    }
+
+    /// Encoded size of the pooled transactions.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Transaction> {
+        self.pooled.get(hash).map(|pooled| &pooled.tx)
+    }
+
+    pub fn origin(&self, hash: &str) -> Option<Origin> {
+        self.pooled.get(hash).map(|pooled| pooled.origin)
+    }
+
+    /// The nonce `sender`'s next transaction should have, after those
+    /// pending.
+    pub fn next_nonce(&self, sender: &str, state: &StateTrie) -> u64 {
+        state.get(sender).nonce + self.senders.get(sender).map_or(0, |queue| queue.pending.len()) as u64
+    }
+
+    pub fn counts(&self, sender: &str) -> SenderCounts {
+        self.senders.get(sender).map_or_else(SenderCounts::default, |queue| SenderCounts {
+            pending: queue.pending.len(),
+            queued: queue.queued.len(),
+        })
+    }
+
+    /// Admits `tx` if `state` shows its sender can pay for it, returning
+    /// the transactions it displaced, by replacement or eviction, with the
+    /// reason each went.
+    pub fn insert(&mut self, tx: Transaction, origin: Origin, state: &StateTrie, height: u64) -> Result<Vec<(Transaction, String)>, MempoolError> {
+        if tx.hash != tx.compute_hash() {
+            return Err(MempoolError::HashMismatch(tx.hash));
+        }
+        if self.pooled.contains_key(&tx.hash) {
+            return Err(MempoolError::AlreadyPending(tx.hash));
+        }
+        execution::check(self.chain_id, &tx).map_err(MempoolError::Invalid)?;
+        if tx.gas_limit > self.limits.max_gas {
+            return Err(MempoolError::GasLimit { gas_limit: tx.gas_limit, max: self.limits.max_gas });
+        }
+        let account = state.get(&tx.from);
+        multisig::authorize(&account, &tx).map_err(MempoolError::Invalid)?;
+        if tx.nonce < account.nonce {
+            return Err(MempoolError::Nonce { nonce: tx.nonce, expected: account.nonce });
+        }
+        let replaced = self.queue(&tx.from).find(|pooled| pooled.tx.nonce == tx.nonce);
+        if let Some(replaced) = replaced {
+            let bumped = |fee: u64| fee.saturating_mul(100 + REPLACEMENT_BUMP).div_ceil(100);
+            let max_fee_per_gas = bumped(replaced.tx.max_fee_per_gas);
+            let max_priority_fee_per_gas = bumped(replaced.tx.max_priority_fee_per_gas);
+            if tx.max_fee_per_gas < max_fee_per_gas || tx.max_priority_fee_per_gas < max_priority_fee_per_gas {
+                return Err(MempoolError::Underpriced { max_fee_per_gas, max_priority_fee_per_gas });
+            }
+        } else if self.senders.get(&tx.from).map_or(0, |queue| queue.len()) >= self.limits.max_per_sender {
+            return Err(MempoolError::SenderFull(tx.from));
+        }
+        let replaced = replaced.map(|pooled| (pooled.tx.hash.clone(), pooled.size));
+        let needed = self
+            .queue(&tx.from)
+            .map(|pooled| &pooled.tx)
+            .filter(|pooled| pooled.nonce != tx.nonce)
+            .chain([&tx])
+            .try_fold(0u64, |needed, tx| needed.checked_add(cost(tx)?))
+            .unwrap_or(u64::MAX);
+        if needed > account.balance {
+            return Err(MempoolError::InsufficientBalance { sender: tx.from, balance: account.balance, needed });
+        }
+        let size = serde_json::to_vec(&tx).expect("transactions serialize").len();
+        if size > self.limits.max_bytes {
+            return Err(MempoolError::TooLarge(size));
+        }
+
+        let mut displaced = vec![];
+        match replaced {
+            Some((hash, replaced_size)) => {
+                // A replacement takes its predecessor's place, so only
+                // needs room for any growth
+                if self.bytes - replaced_size + size > self.limits.max_bytes {
+                    return Err(MempoolError::Full);
+                }
+                let old = self.pooled.remove(&hash).expect("replaced transactions are pooled");
+                self.bytes -= old.size;
+                let queue = self.senders.get_mut(&tx.from).expect("replaced transactions are queued");
+                match queue.pending.iter_mut().find(|pending| **pending == hash) {
+                    Some(pending) => *pending = tx.hash.clone(),
+                    None => {
+                        queue.queued.insert(tx.nonce, tx.hash.clone());
+                    }
+                }
+                displaced.push((old.tx, format!("replaced by {}", tx.hash)));
+            }
+            None => {
+                let tip = fees::effective_tip(&tx, self.base_fee).unwrap_or(0);
+                for hash in self.make_room(tip, size)? {
+                    if let Some(evicted) = self.remove(&hash) {
+                        displaced.push((evicted, "evicted from a full mempool".to_string()));
+                    }
+                }
+                let queue = self.senders.entry(tx.from.clone()).or_default();
+                queue.queued.insert(tx.nonce, tx.hash.clone());
+                let mut next = account.nonce + queue.pending.len() as u64;
+                while let Some(hash) = queue.queued.remove(&next) {
+                    queue.pending.push_back(hash);
+                    next += 1;
+                }
+            }
+        }
+        self.arrivals += 1;
+        self.bytes += size;
+        let pooled = Pooled { tx, origin, arrival: self.arrivals, height, size };
+        self.pooled.insert(pooled.tx.hash.clone(), pooled);
+        Ok(displaced)
+    }
+
+    /// Hashes to evict so a transaction of `size` paying `tip` fits,
+    /// taking from the backs of senders' queues.
+    fn make_room(&self, tip: u64, size: usize) -> Result<Vec<String>, MempoolError> {
+        let mut evict = vec![];
+        let mut cut: HashMap<&str, usize> = HashMap::new();
+        let (mut count, mut bytes) = (self.pooled.len(), self.bytes);
+        while count >= self.limits.max_transactions || bytes + size > self.limits.max_bytes {
+            let victim = self
+                .senders
+                .iter()
+                .filter_map(|(sender, queue)| {
+                    let (hash, pending) = queue.nth_back(cut.get(sender.as_str()).copied().unwrap_or(0))?;
+                    Some((sender, pending, &self.pooled[hash]))
+                })
+                .map(|(sender, pending, pooled)| (sender, pending, pooled, pooled.priority(self.base_fee)))
+                .filter(|(_, _, _, (pooled_tip, _))| *pooled_tip < tip)
+                .min_by_key(|(_, pending, _, priority)| (*pending, *priority));
+            let Some((sender, _, pooled, _)) = victim else {
+                return Err(MempoolError::Full);
+            };
+            *cut.entry(sender).or_default() += 1;
+            count -= 1;
+            bytes -= pooled.size;
+            evict.push(pooled.tx.hash.clone());
+        }
+        Ok(evict)
+    }
+
+    /// Forgets `hash`, returning its transaction.
+    fn remove(&mut self, hash: &str) -> Option<Transaction> {
+        let pooled = self.pooled.remove(hash)?;
+        self.bytes -= pooled.size;
+        if let Some(queue) = self.senders.get_mut(&pooled.tx.from) {
+            queue.pending.retain(|pending| pending != hash);
+            queue.queued.retain(|_, queued| queued != hash);
+            if queue.is_empty() {
+                self.senders.remove(&pooled.tx.from);
+            }
+        }
+        Some(pooled.tx)
+    }
+
+    /// `sender`'s transactions in nonce order.
+    fn queue<'a>(&'a self, sender: &str) -> impl Iterator<Item = &'a Pooled> + 'a {
+        self.senders.get(sender).into_iter().flat_map(Queue::hashes).map(|hash| &self.pooled[hash])
+    }
+
+    /// Forgets transactions a block included.
+    pub fn remove_included(&mut self, transactions: &[Transaction]) {
+        for tx in transactions {
+            self.remove(&tx.hash);
+        }
+    }
+
+    /// Sorts transactions again after the head moved to `state` at
+    /// `height`, setting the next block's `base_fee`. Drops those that have waited `PENDING_BLOCKS` blocks,
+    /// those whose nonce is used, and those their sender can no longer pay
+    /// for, with the reason each was dropped. Of the rest, those following
+    /// on from the sender's nonce are pending and the others queued.
+    pub fn revalidate(&mut self, state: &StateTrie, height: u64, base_fee: u64) -> Vec<(Transaction, String)> {
+        self.base_fee = base_fee;
+        let mut dropped: Vec<(u64, String, String)> = vec![];
+        for (sender, queue) in &mut self.senders {
+            let account = state.get(sender);
+            let mut next = account.nonce;
+            let mut needed = 0u64;
+            let mut hashes = std::mem::take(&mut queue.queued);
+            hashes.extend(queue.pending.drain(..).map(|hash| (self.pooled[&hash].tx.nonce, hash)));
+            for (nonce, hash) in hashes {
+                let pooled = &self.pooled[&hash];
+                let reason = if nonce < account.nonce {
+                    format!("nonce {} is already used", nonce)
+                } else if height - pooled.height >= PENDING_BLOCKS {
+                    format!("not included within {} blocks", PENDING_BLOCKS)
+                } else {
+                    match cost(&pooled.tx).and_then(|cost| needed.checked_add(cost)) {
+                        Some(total) if total <= account.balance => {
+                            needed = total;
+                            if nonce == next {
+                                queue.pending.push_back(hash);
+                                next += 1;
+                            } else {
+                                queue.queued.insert(nonce, hash);
+                            }
+                            continue;
+                        }
+                        _ => format!("{} can no longer pay for it", sender),
+                    }
+                };
+                dropped.push((pooled.arrival, hash, reason));
+            }
+        }
+        dropped.sort();
+        let dropped = dropped.into_iter().filter_map(|(_, hash, reason)| Some((self.remove(&hash)?, reason))).collect();
+        self.senders.retain(|_, queue| !queue.is_empty());
+        dropped
+    }
+
+    /// Every pending transaction that can pay the base fee, in the order
+    /// a block producer should take them.
+    pub fn best(&self) -> Vec<&Transaction> {
+        let includable = |hash: &String| Some(&self.pooled[hash]).filter(|pooled| pooled.tx.max_fee_per_gas >= self.base_fee);
+        let mut heads: BinaryHeap<_> = self
+            .senders
+            .values()
+            .filter_map(|queue| Some((includable(queue.pending.front()?)?.priority(self.base_fee), &queue.pending, 0)))
+            .collect();
+        let mut best = Vec::with_capacity(self.pooled.len());
+        while let Some((_, queue, position)) = heads.pop() {
+            best.push(&self.pooled[&queue[position]].tx);
+            if let Some(next) = queue.get(position + 1).and_then(includable) {
+                heads.push((next.priority(self.base_fee), queue, position + 1));
+            }
+        }
+        best
+    }
+}
+
+/// Most `tx` can take from its sender: the fee at its cap and the value.
+fn cost(tx: &Transaction) -> Option<u64> {
+    tx.gas_limit.checked_mul(tx.max_fee_per_gas)?.checked_add(tx.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::execution::TRANSFER_GAS;
+    use crate::state::Account;
+    use ed25519_dalek::SigningKey;
+    use std::collections::BTreeMap;
+
+    const CHAIN: u64 = 7;
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn address(seed: u8) -> String {
+        Address::from_key(&key(seed).verifying_key()).to_string()
+    }
+
+    /// Pays the least base fee and no tip.
+    fn tx(from: u8, nonce: u64, value: u64) -> Transaction {
+        priced(from, nonce, value, MIN_BASE_FEE, 0)
+    }
+
+    fn priced(from: u8, nonce: u64, value: u64, max_fee_per_gas: u64, max_priority_fee_per_gas: u64) -> Transaction {
+        let unsigned = Transaction {
+            chain_id: CHAIN,
+            nonce,
+            to: BOB.to_string(),
+            value,
+            gas_limit: TRANSFER_GAS,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            ..Transaction::default()
+        };
+        unsigned.sign(&key(from))
+    }
+
+    fn state(balances: &[(u8, u64)]) -> StateTrie {
+        StateTrie::from_balances(&balances.iter().map(|(seed, balance)| (address(*seed), *balance)).collect::<BTreeMap<_, _>>())
+    }
+
+    #[test]
+    fn admits_only_transactions_their_sender_can_pay_for() {
+        let state = state(&[(1, 2 * TRANSFER_GAS + 10)]);
+        let mut pool = Mempool::new(CHAIN);
+        pool.insert(tx(1, 0, 5), Origin::Local, &state, 0).unwrap();
+        assert_eq!(pool.insert(tx(1, 0, 5), Origin::Peer, &state, 0).unwrap_err(), MempoolError::AlreadyPending(tx(1, 0, 5).hash));
+        // The second transfer only fits next to the first if it sends 5 or less
+        assert!(matches!(pool.insert(tx(1, 1, 6), Origin::Local, &state, 0), Err(MempoolError::InsufficientBalance { .. })));
+        pool.insert(tx(1, 1, 4), Origin::Peer, &state, 0).unwrap();
+        assert!(matches!(pool.insert(tx(2, 0, 0), Origin::Local, &state, 0), Err(MempoolError::InsufficientBalance { .. })));
+
+        let mut tampered = tx(1, 2, 1);
+        tampered.value = 2;
+        assert!(matches!(pool.insert(tampered, Origin::Local, &state, 0), Err(MempoolError::HashMismatch(_))));
+        let cheap = Transaction { gas_limit: TRANSFER_GAS - 1, ..tx(1, 2, 1) }.sign(&key(1));
+        assert!(matches!(pool.insert(cheap, Origin::Local, &state, 0), Err(MempoolError::Invalid(reason)) if reason.contains("gas")));
+        let greedy = Transaction { gas_limit: MempoolLimits::default().max_gas + 1, ..tx(1, 2, 1) }.sign(&key(1));
+        assert!(matches!(pool.insert(greedy, Origin::Local, &state, 0), Err(MempoolError::GasLimit { .. })));
+        let other_chain = Transaction { chain_id: CHAIN + 1, ..tx(1, 2, 1) }.sign(&key(1));
+        assert!(matches!(pool.insert(other_chain, Origin::Local, &state, 0), Err(MempoolError::Invalid(reason)) if reason.contains("chain")));
+        assert_eq!((pool.len(), pool.origin(&tx(1, 1, 4).hash)), (2, Some(Origin::Peer)));
+
+        // Once the first transfer is in a block and paid for, the pool
+        // only keeps what the balance still covers
+        pool.remove_included(&[tx(1, 0, 5)]);
+        let head = StateTrie::from_accounts(BTreeMap::from([(address(1), Account { balance: TRANSFER_GAS, nonce: 1, ..Account::default() })]));
+        let dropped = pool.revalidate(&head, 1, MIN_BASE_FEE);
+        assert_eq!(dropped.len(), 1);
+        assert!(dropped[0].1.contains("can no longer pay"));
+        assert!(pool.is_empty());
+        assert_eq!(pool.bytes(), 0);
+    }
+
+    #[test]
+    fn queues_transactions_until_the_nonces_before_them_arrive() {
+        let state = state(&[(1, 1_000_000), (2, 1_000_000)]);
+        let mut pool = Mempool::new(CHAIN);
+        pool.insert(tx(1, 2, 3), Origin::Local, &state, 0).unwrap();
+        pool.insert(tx(1, 1, 2), Origin::Local, &state, 0).unwrap();
+        assert_eq!((pool.counts(&address(1)), pool.next_nonce(&address(1), &state)), (SenderCounts { pending: 0, queued: 2 }, 0));
+        assert!(pool.best().is_empty());
+        // Filling the gap promotes everything after it
+        let submitted = [tx(1, 0, 1), tx(2, 0, 1), tx(2, 1, 2)];
+        for (height, tx) in submitted.iter().enumerate() {
+            pool.insert(tx.clone(), Origin::Local, &state, height as u64).unwrap();
+        }
+        assert_eq!((pool.counts(&address(1)), pool.next_nonce(&address(1), &state)), (SenderCounts { pending: 3, queued: 0 }, 3));
+        let best: Vec<_> = pool.best().into_iter().map(|tx| (tx.from == address(1), tx.nonce)).collect();
+        assert_eq!(best, [(true, 0), (true, 1), (true, 2), (false, 0), (false, 1)]);
+
+        // A used nonce is turned away, and once a block uses it, dropped
+        let head = StateTrie::from_accounts(BTreeMap::from([
+            (address(1), Account { balance: 1_000_000, ..Account::default() }),
+            (address(2), Account { balance: 1_000_000, nonce: 1, ..Account::default() }),
+        ]));
+        assert_eq!(pool.insert(tx(2, 0, 9), Origin::Local, &head, 2).unwrap_err(), MempoolError::Nonce { nonce: 0, expected: 1 });
+        let dropped = pool.revalidate(&head, 2, MIN_BASE_FEE);
+        assert_eq!(dropped.iter().map(|(tx, reason)| (tx.nonce, reason.as_str())).collect::<Vec<_>>(), [(0, "nonce 0 is already used")]);
+        assert_eq!(pool.counts(&address(2)), SenderCounts { pending: 1, queued: 0 });
+
+        // Expiring a sender's first transaction sends the rest back to
+        // the queue
+        let dropped = pool.revalidate(&state, PENDING_BLOCKS, MIN_BASE_FEE);
+        assert_eq!(dropped.iter().map(|(tx, _)| &tx.hash).collect::<Vec<_>>(), [&tx(1, 2, 3).hash, &tx(1, 1, 2).hash, &tx(1, 0, 1).hash]);
+        assert!(dropped[0].1.contains("not included"));
+        assert_eq!(pool.counts(&address(2)), SenderCounts { pending: 0, queued: 1 });
+    }
+
+    #[test]
+    fn replaces_a_pooled_transaction_offering_enough_more_per_gas() {
+        let state = state(&[(1, 10_000_000)]);
+        let mut pool = Mempool::new(CHAIN);
+        pool.insert(priced(1, 0, 1, 100, 10), Origin::Peer, &state, 0).unwrap();
+        pool.insert(priced(1, 2, 1, 100, 10), Origin::Peer, &state, 0).unwrap();
+        // Both the fee cap and the tip have to go up
+        let expected = MempoolError::Underpriced { max_fee_per_gas: 110, max_priority_fee_per_gas: 11 };
+        assert_eq!(pool.insert(priced(1, 0, 2, 200, 10), Origin::Local, &state, 0).unwrap_err(), expected);
+        assert_eq!(pool.insert(priced(1, 0, 2, 109, 20), Origin::Local, &state, 0).unwrap_err(), expected);
+
+        let replaced = pool.insert(priced(1, 0, 2, 110, 11), Origin::Local, &state, 0).unwrap();
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].0.hash, priced(1, 0, 1, 100, 10).hash);
+        assert_eq!(replaced[0].1, format!("replaced by {}", priced(1, 0, 2, 110, 11).hash));
+        // Queued transactions can be replaced too, and stay queued
+        pool.insert(priced(1, 2, 2, 200, 20), Origin::Local, &state, 0).unwrap();
+        assert_eq!(pool.counts(&address(1)), SenderCounts { pending: 1, queued: 1 });
+        assert_eq!(pool.best().into_iter().map(|tx| &tx.hash).collect::<Vec<_>>(), [&priced(1, 0, 2, 110, 11).hash]);
+        assert_eq!(pool.origin(&priced(1, 0, 2, 110, 11).hash), Some(Origin::Local));
+        assert_eq!(pool.len(), 2);
+        let pooled = [priced(1, 0, 2, 110, 11), priced(1, 2, 2, 200, 20)];
+        assert_eq!(pool.bytes(), pooled.iter().map(|tx| serde_json::to_vec(tx).unwrap().len()).sum::<usize>());
+    }
+
+    #[test]
+    fn offers_transactions_by_the_tip_they_pay_at_the_base_fee() {
+        let state = state(&[(1, 1_000_000), (2, 1_000_000), (3, 1_000_000)]);
+        let mut pool = Mempool::new(CHAIN).with_base_fee(10);
+        // A high tip the cap leaves little room for pays less than a low
+        // one under a high cap
+        pool.insert(priced(1, 0, 1, 12, 8), Origin::Local, &state, 0).unwrap();
+        pool.insert(priced(2, 0, 1, 20, 3), Origin::Local, &state, 0).unwrap();
+        pool.insert(priced(3, 0, 1, 9, 9), Origin::Local, &state, 0).unwrap();
+        pool.insert(priced(2, 1, 1, 9, 9), Origin::Local, &state, 0).unwrap();
+        let best = |pool: &Mempool| pool.best().into_iter().map(|tx| (tx.from == address(2), tx.nonce)).collect::<Vec<_>>();
+        assert_eq!(best(&pool), [(true, 0), (false, 0)]);
+        assert_eq!(pool.counts(&address(2)), SenderCounts { pending: 2, queued: 0 });
+
+        // Priced out transactions stay pooled for when the base fee falls
+        assert!(pool.revalidate(&state, 1, 8).is_empty());
+        assert_eq!(best(&pool), [(false, 0), (true, 0), (false, 0), (true, 1)]);
+    }
+
+    #[test]
+    fn enforces_its_limits() {
+        let state = state(&[(1, 1_000_000), (2, 1_000_000)]);
+        let limits = MempoolLimits { max_transactions: 3, max_per_sender: 2, ..MempoolLimits::default() };
+        let mut pool = Mempool::new(CHAIN).with_limits(limits);
+        pool.insert(tx(1, 0, 1), Origin::Local, &state, 0).unwrap();
+        pool.insert(tx(1, 1, 2), Origin::Local, &state, 0).unwrap();
+        assert_eq!(pool.insert(tx(1, 2, 3), Origin::Local, &state, 0).unwrap_err(), MempoolError::SenderFull(address(1)));
+        pool.insert(tx(2, 0, 1), Origin::Local, &state, 0).unwrap();
+        // Paying no more per gas than anything pooled, a newcomer can't
+        // evict
+        assert_eq!(pool.insert(tx(2, 1, 2), Origin::Local, &state, 0).unwrap_err(), MempoolError::Full);
+        assert_eq!(pool.make_room(1, 0).unwrap(), [tx(2, 0, 1).hash]);
+
+        let size = pool.bytes() / 3;
+        let mut small = Mempool::new(CHAIN).with_limits(MempoolLimits { max_bytes: size, ..MempoolLimits::default() });
+        small.insert(tx(1, 0, 1), Origin::Local, &state, 0).unwrap();
+        assert_eq!(small.insert(tx(2, 0, 1), Origin::Local, &state, 0).unwrap_err(), MempoolError::Full);
+        let large = Transaction { data: vec![0; size], gas_limit: TRANSFER_GAS + 4 * size as u64, ..tx(1, 1, 1) }.sign(&key(1));
+        assert!(matches!(small.insert(large, Origin::Local, &state, 0), Err(MempoolError::TooLarge(_))));
+    }
+}
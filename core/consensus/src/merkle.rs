@@ -0,0 +1,108 @@
+//! Binary Merkle trees over a block's transactions.
+//!
+//! Leaves are the transaction hashes in block order. Each level pairs
+//! neighbours left to right; a level with an odd count pairs its last node
+//! with zero. Hashes are domain separated like the state trie's, see
+//! `cubiq_types::proofs`:
+//!
+//! ```text
+//! leaf   = blake3(0x00 || tx_hash)
+//! branch = blake3(0x01 || left || right)
+//! ```
+//!
+//! `tx_hash` is the hash as written, `0x`-prefixed hex. A block without
+//! transactions has the zero root. An `InclusionProof` lets a light client
+//! check that a transaction is in a block whose `transactions_root` it
+//! trusts.
+
+use cubiq_types::proofs::{branch_hash, transaction_leaf_hash as leaf_hash};
+pub use cubiq_types::proofs::InclusionProof;
+
+use crate::state::Hash;
+use crate::Transaction;
+
+/// The level above `level`.
+fn parents(level: &[Hash]) -> Vec<Hash> {
+    level.chunks(2).map(|pair| branch_hash(&pair[0], pair.get(1).unwrap_or(&Hash::ZERO))).collect()
+}
+
+/// The root over `transactions`, in order.
+pub fn transactions_root(transactions: &[Transaction]) -> Hash {
+    let mut level: Vec<Hash> = transactions.iter().map(|tx| leaf_hash(&tx.hash)).collect();
+    if level.is_empty() {
+        return Hash::ZERO;
+    }
+    while level.len() > 1 {
+        level = parents(&level);
+    }
+    level[0]
+}
+
+/// Proof that `transactions[index]` is under their root, or `None` when
+/// there is no such transaction.
+pub fn prove(transactions: &[Transaction], index: usize) -> Option<InclusionProof> {
+    if index >= transactions.len() {
+        return None;
+    }
+    let mut level: Vec<Hash> = transactions.iter().map(|tx| leaf_hash(&tx.hash)).collect();
+    let mut siblings = vec![];
+    let mut position = index;
+    while level.len() > 1 {
+        siblings.push(level.get(position ^ 1).copied().unwrap_or(Hash::ZERO));
+        level = parents(&level);
+        position /= 2;
+    }
+    Some(InclusionProof { index, siblings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transactions(count: usize) -> Vec<Transaction> {
+        (0..count)
+            .map(|n| Transaction {
+                hash: format!("0x{:064x}", n),
+                from: "0xalice".to_string(),
+                to: "0xbob".to_string(),
+                value: n as u64,
+                ..Transaction::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn proves_every_transaction_in_blocks_of_any_size() {
+        assert_eq!(transactions_root(&[]), Hash::ZERO);
+        assert!(prove(&[], 0).is_none());
+        for count in 1..=9 {
+            let txs = transactions(count);
+            let root = transactions_root(&txs);
+            for (index, tx) in txs.iter().enumerate() {
+                let proof = prove(&txs, index).unwrap();
+                assert!(proof.verify(&root, &tx.hash), "{} of {}", index, count);
+                if count > 1 {
+                    assert!(!proof.verify(&root, &txs[(index + 1) % count].hash));
+                }
+            }
+            assert!(prove(&txs, count).is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_proofs_for_other_positions_or_roots() {
+        let txs = transactions(5);
+        let root = transactions_root(&txs);
+        let proof = prove(&txs, 2).unwrap();
+        assert!(!proof.verify(&transactions_root(&txs[..4]), &txs[2].hash));
+        assert!(!InclusionProof { index: 3, ..proof.clone() }.verify(&root, &txs[2].hash));
+        assert!(!InclusionProof { index: 2 + 8, ..proof.clone() }.verify(&root, &txs[2].hash));
+
+        let mut reordered = txs.clone();
+        reordered.swap(0, 1);
+        assert_ne!(transactions_root(&reordered), root);
+
+        let encoded = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<InclusionProof>(&encoded).unwrap(), proof);
+    }
+}
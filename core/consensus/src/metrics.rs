@@ -0,0 +1,315 @@
+//! Prometheus metrics for consensus, the mempool and the block store.
+//!
+//! Store sizes are gauges refreshed from `BlockStore::stats`, some
+//! labelled by column family. Column figures a backend can't tell are left
+//! out.
+//!
+//! Vote latency is measured from when this node verified a block to when
+//! each vote for it arrives, so votes for blocks the node never verified,
+//! or verified more than `TRACKED_BLOCKS` blocks ago, aren't timed.
+//! Finality latency, missed votes and round changes come from `health`.
+
+use prometheus::{Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::store::StoreStats;
+
+/// Compaction time buckets in seconds, from a small column to a full
+/// rewrite of a large one.
+const COMPACTION_BUCKETS: &[f64] = &[0.1, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0];
+
+/// Vote latency buckets in seconds, from a peer on the same network to
+/// one that fell a round behind.
+const VOTE_LATENCY_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Finality latency buckets in seconds, from a proof fetched from cache
+/// to one a resolver took minutes to serve.
+const FINALITY_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Most recently verified blocks whose votes are timed.
+const TRACKED_BLOCKS: usize = 64;
+
+pub struct ConsensusMetrics {
+    height: IntGauge,
+    finalized_height: IntGauge,
+    round: IntGauge,
+    proposals: IntCounterVec,
+    votes: IntCounterVec,
+    vote_latency: Histogram,
+    finality_latency: Histogram,
+    proof_fetch_share: Gauge,
+    missed_votes: IntCounterVec,
+    round_changes: IntCounter,
+    mempool_transactions: IntGauge,
+    mempool_bytes: IntGauge,
+    admissions: IntCounterVec,
+    /// When each recently verified block was, oldest first
+    verified: Mutex<VecDeque<(String, Instant)>>,
+}
+
+impl ConsensusMetrics {
+    /// Creates the consensus and mempool metrics and registers them with
+    /// `registry`.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            height: IntGauge::new("consensus_height", "Height of the newest verified block")?,
+            finalized_height: IntGauge::new("consensus_finalized_height", "Height of the newest finalized block")?,
+            round: IntGauge::new("consensus_round", "Current consensus round")?,
+            proposals: IntCounterVec::new(
+                Opts::new("consensus_proposals_total", "Block proposals processed, by whether they verified"),
+                &["result"],
+            )?,
+            votes: IntCounterVec::new(
+                Opts::new("consensus_votes_total", "Votes received, by whether they were counted"),
+                &["result"],
+            )?,
+            vote_latency: Histogram::with_opts(
+                HistogramOpts::new("consensus_vote_latency_seconds", "Time from verifying a block to receiving each vote for it")
+                    .buckets(VOTE_LATENCY_BUCKETS.to_vec()),
+            )?,
+            finality_latency: Histogram::with_opts(
+                HistogramOpts::new("consensus_finality_latency_seconds", "Time from taking a proposal off the queue to finalizing it")
+                    .buckets(FINALITY_BUCKETS.to_vec()),
+            )?,
+            proof_fetch_share: Gauge::new("consensus_proof_fetch_share", "Fraction of recent finality latency spent fetching proofs")?,
+            missed_votes: IntCounterVec::new(
+                Opts::new("consensus_missed_votes_total", "Finalized blocks each validator hadn't voted for by the next finalization"),
+                &["validator"],
+            )?,
+            round_changes: IntCounter::new("consensus_round_changes_total", "Verified blocks in a different round from the one before")?,
+            mempool_transactions: IntGauge::new("mempool_transactions", "Pending transactions")?,
+            mempool_bytes: IntGauge::new("mempool_bytes", "Encoded size of pending transactions")?,
+            admissions: IntCounterVec::new(
+                Opts::new("mempool_admissions_total", "Transactions submitted or gossiped, by whether they were admitted"),
+                &["origin", "result"],
+            )?,
+            verified: Mutex::new(VecDeque::with_capacity(TRACKED_BLOCKS)),
+        };
+        registry.register(Box::new(metrics.height.clone()))?;
+        registry.register(Box::new(metrics.finalized_height.clone()))?;
+        registry.register(Box::new(metrics.round.clone()))?;
+        registry.register(Box::new(metrics.proposals.clone()))?;
+        registry.register(Box::new(metrics.votes.clone()))?;
+        registry.register(Box::new(metrics.vote_latency.clone()))?;
+        registry.register(Box::new(metrics.finality_latency.clone()))?;
+        registry.register(Box::new(metrics.proof_fetch_share.clone()))?;
+        registry.register(Box::new(metrics.missed_votes.clone()))?;
+        registry.register(Box::new(metrics.round_changes.clone()))?;
+        registry.register(Box::new(metrics.mempool_transactions.clone()))?;
+        registry.register(Box::new(metrics.mempool_bytes.clone()))?;
+        registry.register(Box::new(metrics.admissions.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn observe_proposal(&self, verified: bool) {
+        self.proposals.with_label_values(&[if verified { "verified" } else { "rejected" }]).inc();
+    }
+
+    /// Records `block_hash` as verified now, at `height` in `round`.
+    pub fn observe_verified(&self, block_hash: &str, height: u64, round: u32) {
+        self.height.set(height as i64);
+        self.round.set(round as i64);
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() == TRACKED_BLOCKS {
+            verified.pop_front();
+        }
+        verified.push_back((block_hash.to_string(), Instant::now()));
+    }
+
+    /// Records a vote for `block_hash`, timing it if it was `counted`.
+    pub fn observe_vote(&self, block_hash: &str, counted: bool) {
+        self.votes.with_label_values(&[if counted { "counted" } else { "ignored" }]).inc();
+        if !counted {
+            return;
+        }
+        let verified = self.verified.lock().unwrap();
+        if let Some((_, at)) = verified.iter().rev().find(|(hash, _)| hash == block_hash) {
+            self.vote_latency.observe(at.elapsed().as_secs_f64());
+        }
+    }
+
+    pub fn observe_heights(&self, height: u64, finalized_height: u64) {
+        self.height.set(height as i64);
+        self.finalized_height.set(finalized_height as i64);
+    }
+
+    pub fn observe_finalized(&self, height: u64) {
+        self.finalized_height.set(height as i64);
+    }
+
+    /// Records a block finalizing `latency` after it was taken off the
+    /// queue, with `proof_fetch_share` the recent share of that spent
+    /// fetching proofs.
+    pub fn observe_finality(&self, latency: Duration, proof_fetch_share: f64) {
+        self.finality_latency.observe(latency.as_secs_f64());
+        self.proof_fetch_share.set(proof_fetch_share);
+    }
+
+    pub fn observe_missed(&self, validators: &[String]) {
+        for validator in validators {
+            self.missed_votes.with_label_values(&[validator]).inc();
+        }
+    }
+
+    pub fn observe_round_change(&self) {
+        self.round_changes.inc();
+    }
+
+    pub fn observe_admission(&self, origin: &str, admitted: bool) {
+        self.admissions.with_label_values(&[origin, if admitted { "admitted" } else { "rejected" }]).inc();
+    }
+
+    pub fn observe_mempool(&self, transactions: usize, bytes: usize) {
+        self.mempool_transactions.set(transactions as i64);
+        self.mempool_bytes.set(bytes as i64);
+    }
+}
+
+pub struct StorageMetrics {
+    height: IntGauge,
+    frozen_height: IntGauge,
+    freezer_bytes: IntGauge,
+    total_bytes: IntGauge,
+    column_bytes: IntGaugeVec,
+    column_keys: IntGaugeVec,
+    pending_compaction_bytes: IntGaugeVec,
+    running_compactions: IntGauge,
+    compactions: IntCounterVec,
+    compaction_duration: Histogram,
+    deferred_compactions: IntCounter,
+}
+
+impl StorageMetrics {
+    /// Creates the storage metrics and registers them with `registry`.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            height: IntGauge::new("storage_latest_height", "Newest finalized height stored")?,
+            frozen_height: IntGauge::new("storage_frozen_height", "Newest height moved to the freezer")?,
+            freezer_bytes: IntGauge::new("storage_freezer_bytes", "Size of the freezer's files")?,
+            total_bytes: IntGauge::new("storage_size_bytes", "Size of the database")?,
+            column_bytes: IntGaugeVec::new(Opts::new("storage_column_bytes", "Size of each column family"), &["column"])?,
+            column_keys: IntGaugeVec::new(Opts::new("storage_column_keys", "Estimated keys in each column family"), &["column"])?,
+            pending_compaction_bytes: IntGaugeVec::new(
+                Opts::new("storage_pending_compaction_bytes", "Bytes compaction is estimated to have yet to rewrite"),
+                &["column"],
+            )?,
+            running_compactions: IntGauge::new("storage_running_compactions", "Compactions under way")?,
+            compactions: IntCounterVec::new(
+                Opts::new("storage_scheduled_compactions_total", "Compactions run by the scheduler, by column family"),
+                &["column"],
+            )?,
+            compaction_duration: Histogram::with_opts(
+                HistogramOpts::new("storage_compaction_duration_seconds", "Time taken by scheduled compactions")
+                    .buckets(COMPACTION_BUCKETS.to_vec()),
+            )?,
+            deferred_compactions: IntCounter::new(
+                "storage_deferred_compactions_total",
+                "Times a scheduled compaction waited because the node was busy",
+            )?,
+        };
+        registry.register(Box::new(metrics.height.clone()))?;
+        registry.register(Box::new(metrics.frozen_height.clone()))?;
+        registry.register(Box::new(metrics.freezer_bytes.clone()))?;
+        registry.register(Box::new(metrics.total_bytes.clone()))?;
+        registry.register(Box::new(metrics.column_bytes.clone()))?;
+        registry.register(Box::new(metrics.column_keys.clone()))?;
+        registry.register(Box::new(metrics.pending_compaction_bytes.clone()))?;
+        registry.register(Box::new(metrics.running_compactions.clone()))?;
+        registry.register(Box::new(metrics.compactions.clone()))?;
+        registry.register(Box::new(metrics.compaction_duration.clone()))?;
+        registry.register(Box::new(metrics.deferred_compactions.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn observe(&self, stats: &StoreStats) {
+        let set = |gauge: &IntGauge, value: Option<u64>| {
+            if let Some(value) = value {
+                gauge.set(value as i64);
+            }
+        };
+        set(&self.height, Some(stats.latest_height));
+        set(&self.frozen_height, Some(stats.frozen_height));
+        set(&self.freezer_bytes, stats.freezer_bytes);
+        set(&self.total_bytes, stats.kv.total_bytes);
+        set(&self.running_compactions, stats.kv.running_compactions);
+        for column in &stats.kv.columns {
+            let figures = [
+                (&self.column_bytes, column.bytes),
+                (&self.column_keys, column.keys),
+                (&self.pending_compaction_bytes, column.pending_compaction_bytes),
+            ];
+            for (gauges, value) in figures {
+                if let Some(value) = value {
+                    gauges.with_label_values(&[&column.name]).set(value as i64);
+                }
+            }
+        }
+    }
+
+    pub fn observe_compaction(&self, column: &str, elapsed: Duration) {
+        self.compactions.with_label_values(&[column]).inc();
+        self.compaction_duration.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_deferral(&self) {
+        self.deferred_compactions.inc();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::{Backend, ColumnStats, KvStats};
+
+    #[test]
+    fn reports_what_the_backend_knows() {
+        let registry = Registry::new();
+        let metrics = StorageMetrics::register(&registry).unwrap();
+        let column = |name: &str, bytes| ColumnStats { name: name.to_string(), keys: None, bytes, pending_compaction_bytes: None };
+        metrics.observe(&StoreStats {
+            latest_height: 12,
+            frozen_height: 4,
+            freezer_bytes: None,
+            kv: KvStats {
+                backend: Backend::Memory,
+                total_bytes: Some(300),
+                running_compactions: None,
+                columns: vec![column("blocks", Some(200)), column("heights", None)],
+            },
+        });
+        metrics.observe_compaction("blocks", Duration::from_secs(2));
+
+        let families = registry.gather();
+        let family = |name: &str| families.iter().find(|family| family.get_name() == name);
+        let gauge = |name: &str| family(name).map(|family| family.get_metric()[0].get_gauge().get_value() as u64);
+        assert_eq!((gauge("storage_latest_height"), gauge("storage_size_bytes")), (Some(12), Some(300)));
+        let columns = family("storage_column_bytes").unwrap().get_metric();
+        assert_eq!(columns.len(), 1);
+        assert_eq!(columns[0].get_label()[0].get_value(), "blocks");
+        assert_eq!(columns[0].get_gauge().get_value(), 200.0);
+        let compactions = family("storage_scheduled_compactions_total").unwrap().get_metric();
+        assert_eq!(compactions[0].get_counter().get_value(), 1.0);
+    }
+
+    #[test]
+    fn times_counted_votes_for_blocks_it_verified() {
+        let registry = Registry::new();
+        let metrics = ConsensusMetrics::register(&registry).unwrap();
+        metrics.observe_verified("b1", 1, 0);
+        metrics.observe_vote("b1", true);
+        metrics.observe_vote("b1", false);
+        metrics.observe_vote("b2", true);
+        metrics.observe_finalized(1);
+
+        let families = registry.gather();
+        let family = |name: &str| families.iter().find(|family| family.get_name() == name).unwrap();
+        assert_eq!(family("consensus_height").get_metric()[0].get_gauge().get_value(), 1.0);
+        assert_eq!(family("consensus_finalized_height").get_metric()[0].get_gauge().get_value(), 1.0);
+        assert_eq!(family("consensus_vote_latency_seconds").get_metric()[0].get_histogram().get_sample_count(), 1);
+        let votes = family("consensus_votes_total").get_metric();
+        let count = |result: &str| votes.iter().find(|m| m.get_label()[0].get_value() == result).unwrap().get_counter().get_value();
+        assert_eq!((count("counted"), count("ignored")), (2.0, 1.0));
+    }
+}
@@ -0,0 +1,227 @@
+//! Multisig accounts, which `threshold` of a set of keys control together.
+//!
+//! A `TxKind::CreateMultisig` transaction's `data` is a `Multisig` as
+//! JSON. It stores the multisig in the account at `Multisig::address`,
+//! which no key holds, and moves `value` there. The multisig is part of
+//! the account's value in the state trie, so proofs of the account cover
+//! who controls it.
+//!
+//! A transaction from a multisig account leaves `signature` empty and
+//! carries `cosignatures` instead: signatures of its hash by at least
+//! `threshold` of the keys, each once. Cosigners sign the same hash, so
+//! each can sign a copy of the unsigned transaction and anyone can
+//! gather the copies into one to submit. A transaction with cosignatures
+//! from any other account is invalid.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::address::Address;
+use crate::execution;
+use crate::receipts::TxEvent;
+use crate::state::{Account, StateTrie};
+use crate::{Cosignature, Transaction};
+
+/// Most keys a multisig has.
+pub const MAX_KEYS: usize = 16;
+
+/// Prefixes a multisig's encoding when deriving its address.
+const ADDRESS_DOMAIN: &[u8] = b"cubiq-multisig-v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Multisig {
+    /// Cosignatures a transaction needs
+    pub threshold: u32,
+    /// Hex-encoded ed25519 public keys, in lowercase, sorted and each once
+    pub keys: Vec<String>,
+}
+
+impl Multisig {
+    /// A multisig of `keys`, in any order and case.
+    pub fn new(threshold: u32, keys: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut keys: Vec<String> = keys.into_iter().map(|key| key.to_ascii_lowercase()).collect();
+        keys.sort();
+        let multisig = Multisig { threshold, keys };
+        multisig.validate()?;
+        Ok(multisig)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keys.is_empty() || self.keys.len() > MAX_KEYS {
+            return Err(format!("has {} keys, not 1 to {}", self.keys.len(), MAX_KEYS));
+        }
+        if self.threshold == 0 || self.threshold as usize > self.keys.len() {
+            return Err(format!("needs {} of its {} keys", self.threshold, self.keys.len()));
+        }
+        for key in &self.keys {
+            if hex::decode(key).map_or(true, |bytes| bytes.len() != 32) || key.bytes().any(|b| b.is_ascii_uppercase()) {
+                return Err(format!("{} is not a lowercase hex-encoded ed25519 public key", key));
+            }
+        }
+        if self.keys.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("keys are not sorted, or one is listed twice".to_string());
+        }
+        Ok(())
+    }
+
+    /// The account the multisig controls, a hash of its threshold and
+    /// keys.
+    pub fn address(&self) -> Address {
+        Address::derive(ADDRESS_DOMAIN, &bincode::serialize(self).expect("multisigs encode"))
+    }
+
+    /// Checks that `cosignatures`, already verified, are from enough of
+    /// the keys.
+    pub fn check(&self, cosignatures: &[Cosignature]) -> Result<(), String> {
+        if let Some(stranger) = cosignatures.iter().find(|c| self.keys.binary_search(&c.public_key).is_err()) {
+            return Err(format!("is cosigned by {}, which is not one of its sender's keys", stranger.public_key));
+        }
+        if cosignatures.len() < self.threshold as usize {
+            return Err(format!("has {} cosignatures, its sender needs {}", cosignatures.len(), self.threshold));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that `tx` is signed as its sender, standing as `sender`, needs:
+/// with enough cosignatures from a multisig, and with none otherwise.
+pub fn authorize(sender: &Account, tx: &Transaction) -> Result<(), String> {
+    match &sender.multisig {
+        Some(multisig) => multisig.check(&tx.cosignatures),
+        None if tx.cosignatures.is_empty() => Ok(()),
+        None => Err("has cosignatures, but its sender is not a multisig".to_string()),
+    }
+}
+
+/// Creates the multisig in `tx.data` over `changes`, which are left
+/// untouched if it fails.
+pub(crate) fn create(state: &StateTrie, changes: &mut BTreeMap<String, Account>, tx: &Transaction) -> Result<Vec<TxEvent>, String> {
+    let multisig: Multisig = serde_json::from_slice(&tx.data).map_err(|e| format!("data is not a multisig: {}", e))?;
+    multisig.validate().map_err(|e| format!("multisig {}", e))?;
+    let address = multisig.address().to_string();
+    let mut sender = execution::account(state, changes, &tx.from);
+    sender.balance = sender
+        .balance
+        .checked_sub(tx.value)
+        .ok_or_else(|| format!("insufficient balance: has {}, sends {}", sender.balance, tx.value))?;
+    let mut account = execution::account(state, changes, &address);
+    if account.multisig.is_some() || account.contract.is_some() {
+        return Err(format!("{} already exists", address));
+    }
+    account.balance = account.balance.checked_add(tx.value).ok_or("multisig balance overflows")?;
+    let attributes = [("multisig", address.clone()), ("threshold", multisig.threshold.to_string()), ("keys", multisig.keys.len().to_string())];
+    account.multisig = Some(multisig);
+    changes.insert(tx.from.clone(), sender);
+    changes.insert(address, account);
+    Ok(vec![TxEvent { kind: "multisig".to_string(), attributes: attributes.map(|(k, v)| (k.to_string(), v)).into() }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::{execute, BlockEnv, TRANSFER_GAS};
+    use crate::genesis::Vm;
+    use crate::TxKind;
+    use ed25519_dalek::SigningKey;
+
+    const CHAIN: u64 = 7;
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn public_key(seed: u8) -> String {
+        hex::encode(key(seed).verifying_key().to_bytes())
+    }
+
+    fn env() -> BlockEnv {
+        BlockEnv { chain_id: CHAIN, gas_limit: 10_000_000, base_fee: 1, proposer: None, vm: Vm::Wasm, timestamp: 0 }
+    }
+
+    fn run(state: &mut StateTrie, txs: &[Transaction]) -> Result<Vec<execution::TxOutcome>, String> {
+        let execution = execute(state, &env(), txs).map_err(|e| e.reason)?;
+        let root = execution.post_state(state);
+        state.set_root(root);
+        Ok(execution.outcomes)
+    }
+
+    /// A state where `key(1)` has created a 2-of-3 multisig of keys 2, 3
+    /// and 4 holding 100,000, and the multisig.
+    fn created() -> (StateTrie, Multisig) {
+        let funder = Address::from_key(&key(1).verifying_key()).to_string();
+        let mut state = StateTrie::from_balances(&BTreeMap::from([(funder, 1_000_000)]));
+        let multisig = Multisig::new(2, [public_key(4), public_key(2), public_key(3)]).unwrap();
+        let data = serde_json::to_vec(&multisig).unwrap();
+        let gas_limit = 100_000;
+        let create = Transaction { chain_id: CHAIN, kind: TxKind::CreateMultisig, value: 100_000, data, gas_limit, max_fee_per_gas: 1, ..Default::default() };
+        let outcomes = run(&mut state, &[create.sign(&key(1))]).unwrap();
+        assert_eq!(outcomes[0].error, None);
+        assert_eq!(outcomes[0].events[0].attributes["multisig"], multisig.address().to_string());
+        (state, multisig)
+    }
+
+    /// A transfer of 5 to `BOB` from `multisig`, cosigned by `cosigners`.
+    fn send(multisig: &Multisig, nonce: u64, cosigners: &[u8]) -> Transaction {
+        let to = BOB.to_string();
+        let unsigned = Transaction { chain_id: CHAIN, nonce, to, value: 5, gas_limit: TRANSFER_GAS, max_fee_per_gas: 1, ..Default::default() };
+        let mut tx = unsigned.from_account(&multisig.address());
+        tx.cosignatures = cosigners.iter().map(|seed| tx.cosign(&key(*seed))).collect();
+        tx
+    }
+
+    #[test]
+    fn multisigs_are_canonical_whatever_order_their_keys_come_in() {
+        let sorted = Multisig::new(2, [public_key(2), public_key(3)]).unwrap();
+        let shuffled = Multisig::new(2, [public_key(3).to_uppercase(), public_key(2)]).unwrap();
+        assert_eq!((sorted.address(), &sorted), (shuffled.address(), &shuffled));
+        assert_ne!(Multisig::new(1, [public_key(2), public_key(3)]).unwrap().address(), sorted.address());
+
+        assert!(Multisig::new(3, [public_key(2), public_key(3)]).is_err());
+        assert!(Multisig::new(0, [public_key(2)]).is_err());
+        assert!(Multisig::new(1, [public_key(2), public_key(2)]).is_err());
+        assert!(Multisig::new(1, ["0xab".to_string()]).is_err());
+        assert!(Multisig::new(1, (0..=MAX_KEYS as u8).map(public_key)).is_err());
+    }
+
+    #[test]
+    fn multisigs_send_with_enough_cosignatures_from_their_keys() {
+        let (mut state, multisig) = created();
+        let address = multisig.address().to_string();
+        assert_eq!(state.get(&address).multisig.as_ref(), Some(&multisig));
+        assert!(state.prove(&address).verify(&state.root(), &address, Some(&state.get(&address))));
+
+        let too_few = send(&multisig, 0, &[2]);
+        assert!(too_few.verify_signature().is_ok());
+        assert!(run(&mut state, &[too_few]).unwrap_err().contains("needs 2"));
+        assert!(run(&mut state, &[send(&multisig, 0, &[2, 9])]).unwrap_err().contains("not one of its sender's keys"));
+        let mut twice = send(&multisig, 0, &[2]);
+        twice.cosignatures.push(twice.cosignatures[0].clone());
+        assert!(run(&mut state, &[twice]).unwrap_err().contains("twice"));
+        let mut forged = send(&multisig, 0, &[2, 3]);
+        forged.value = 500;
+        forged.hash = forged.compute_hash();
+        assert!(run(&mut state, &[forged]).unwrap_err().contains("does not match"));
+
+        let outcomes = run(&mut state, &[send(&multisig, 0, &[4, 2]), send(&multisig, 1, &[2, 3, 4])]).unwrap();
+        assert!(outcomes.iter().all(|outcome| outcome.error.is_none()));
+        assert_eq!(state.get(BOB).balance, 10);
+        assert_eq!(state.get(&address).balance, 100_000 - 10 - 2 * TRANSFER_GAS);
+    }
+
+    #[test]
+    fn only_multisigs_take_cosignatures() {
+        let (mut state, multisig) = created();
+        let sender = Address::from_key(&key(1).verifying_key());
+        let unsigned = Transaction { chain_id: CHAIN, nonce: 1, to: BOB.to_string(), value: 5, gas_limit: TRANSFER_GAS, max_fee_per_gas: 1, ..Default::default() };
+        let mut cosigned = unsigned.clone().from_account(&sender);
+        cosigned.cosignatures = vec![cosigned.cosign(&key(1))];
+        assert!(run(&mut state, &[cosigned]).unwrap_err().contains("not a multisig"));
+
+        let data = serde_json::to_vec(&multisig).unwrap();
+        let again = Transaction { kind: TxKind::CreateMultisig, to: String::new(), value: 0, data, gas_limit: 100_000, ..unsigned };
+        let outcomes = run(&mut state, &[again.sign(&key(1))]).unwrap();
+        assert!(outcomes[0].error.as_ref().unwrap().contains("already exists"));
+    }
+}
@@ -0,0 +1,197 @@
+//! State pruning: dropping trie nodes that no retained root reaches.
+//!
+//! | mode             | roots kept                             |
+//! |------------------|----------------------------------------|
+//! | `archive`        | every root; nothing is pruned          |
+//! | `keep-last-<N>`  | those of the newest N finalized blocks |
+//! | `finalized-only` | that of the newest finalized block     |
+//!
+//! The head's root is always kept, so execution never loses its base.
+//! Pruning runs whenever a block is finalized and sweeps the whole trie,
+//! so its cost grows with the live state, not with the garbage.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::state::{Hash, StateTrie};
+
+/// Finalized roots kept by default, enough for light clients to ask for
+/// proofs at recent heights.
+pub const DEFAULT_KEEP_LAST: u64 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pruning {
+    Archive,
+    KeepLast(u64),
+    FinalizedOnly,
+}
+
+impl Default for Pruning {
+    fn default() -> Self {
+        Pruning::KeepLast(DEFAULT_KEEP_LAST)
+    }
+}
+
+impl fmt::Display for Pruning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pruning::Archive => f.write_str("archive"),
+            Pruning::KeepLast(n) => write!(f, "keep-last-{}", n),
+            Pruning::FinalizedOnly => f.write_str("finalized-only"),
+        }
+    }
+}
+
+impl FromStr for Pruning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "archive" => Ok(Pruning::Archive),
+            "finalized-only" => Ok(Pruning::FinalizedOnly),
+            _ => match s.strip_prefix("keep-last-").map(str::parse) {
+                Some(Ok(0)) => Err("keep-last-N needs N of at least 1".to_string()),
+                Some(Ok(n)) => Ok(Pruning::KeepLast(n)),
+                _ => Err(format!("unknown pruning mode {:?}; expected archive, keep-last-N or finalized-only", s)),
+            },
+        }
+    }
+}
+
+impl Serialize for Pruning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pruning {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// How pruning has gone so far, for `node_status`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PruningStats {
+    pub runs: u64,
+    pub nodes_removed: u64,
+    /// Trie nodes held after the last run
+    pub live_nodes: usize,
+    /// Oldest finalized height whose state is still held
+    pub oldest_retained_height: Option<u64>,
+    pub last_run_ms: u64,
+}
+
+/// Tracks finalized roots and prunes the trie down to those the mode keeps.
+#[derive(Debug, Clone, Default)]
+pub struct StatePruner {
+    mode: Pruning,
+    /// Finalized heights and their post-state roots, oldest first
+    retained: VecDeque<(u64, Hash)>,
+    stats: PruningStats,
+}
+
+impl StatePruner {
+    pub fn new(mode: Pruning) -> Self {
+        Self { mode, ..Self::default() }
+    }
+
+    pub fn mode(&self) -> Pruning {
+        self.mode
+    }
+
+    pub fn stats(&self) -> &PruningStats {
+        &self.stats
+    }
+
+    /// The state root finalized at `height`, if it is still held.
+    pub fn root_at(&self, height: u64) -> Option<Hash> {
+        let index = self.retained.binary_search_by_key(&height, |(height, _)| *height).ok()?;
+        Some(self.retained[index].1)
+    }
+
+    /// Records that `height` was finalized with post-state `root`, when
+    /// the node knows it, then prunes `trie`.
+    pub fn finalized(&mut self, trie: &mut StateTrie, height: u64, root: Option<Hash>) {
+        self.retain(height, root);
+        self.prune(trie);
+    }
+
+    /// Records a finalized root without pruning, e.g. while replaying.
+    pub fn retain(&mut self, height: u64, root: Option<Hash>) {
+        if let Some(root) = root {
+            self.retained.push_back((height, root));
+        }
+        let keep = match self.mode {
+            Pruning::Archive => return,
+            Pruning::KeepLast(n) => n,
+            Pruning::FinalizedOnly => 1,
+        };
+        while self.retained.front().is_some_and(|(oldest, _)| oldest + keep <= height) {
+            self.retained.pop_front();
+        }
+    }
+
+    /// Drops trie nodes that no retained root reaches.
+    pub fn prune(&mut self, trie: &mut StateTrie) {
+        if self.mode != Pruning::Archive {
+            let started = Instant::now();
+            let removed = trie.prune(self.retained.iter().map(|(_, root)| *root));
+            self.stats.runs += 1;
+            self.stats.nodes_removed += removed as u64;
+            self.stats.last_run_ms = started.elapsed().as_millis() as u64;
+        }
+        self.stats.live_nodes = trie.node_count();
+        self.stats.oldest_retained_height = self.retained.front().map(|(height, _)| *height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Account;
+
+    /// Finalizes `blocks` blocks, each changing one account.
+    fn run(mode: Pruning, blocks: u64) -> (StateTrie, StatePruner, Vec<Hash>) {
+        let mut trie = StateTrie::default();
+        let mut pruner = StatePruner::new(mode);
+        let mut roots = vec![];
+        for height in 1..=blocks {
+            let root = trie.commit([(format!("0x{}", height % 4), Account { balance: height, ..Account::default() })]);
+            pruner.finalized(&mut trie, height, Some(root));
+            roots.push(root);
+        }
+        (trie, pruner, roots)
+    }
+
+    #[test]
+    fn modes_parse_and_print() {
+        for mode in ["archive", "keep-last-64", "finalized-only"] {
+            assert_eq!(mode.parse::<Pruning>().unwrap().to_string(), mode);
+        }
+        assert!("keep-last-0".parse::<Pruning>().is_err());
+        assert!("keep-some".parse::<Pruning>().unwrap_err().contains("expected archive"));
+    }
+
+    #[test]
+    fn keeps_only_the_roots_the_mode_retains() {
+        let (trie, pruner, roots) = run(Pruning::KeepLast(3), 10);
+        assert!(roots[7..].iter().all(|root| trie.has_root(root)));
+        assert!(!trie.has_root(&roots[6]));
+        assert_eq!((pruner.root_at(8), pruner.root_at(7)), (Some(roots[7]), None));
+        assert_eq!(pruner.stats().oldest_retained_height, Some(8));
+        assert_eq!(trie.get_at(&roots[7], "0x0").unwrap().unwrap().balance, 8);
+
+        let (trie, pruner, roots) = run(Pruning::FinalizedOnly, 10);
+        assert_eq!(roots.iter().filter(|root| trie.has_root(root)).count(), 1);
+        assert_eq!(pruner.stats().runs, 10);
+
+        let (archive, pruner, roots) = run(Pruning::Archive, 10);
+        assert!(roots.iter().all(|root| archive.has_root(root)));
+        assert!(archive.node_count() > trie.node_count());
+        assert_eq!((pruner.stats().nodes_removed, pruner.root_at(1)), (0, Some(roots[0])));
+    }
+}
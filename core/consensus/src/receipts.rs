@@ -0,0 +1,201 @@
+//! Receipts tracking each transaction from submission until it is
+//! finalized or dropped.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::events::TxStatus;
+use crate::execution::TxOutcome;
+use crate::Transaction;
+
+/// Receipts kept before the oldest are forgotten.
+pub const MAX_RECEIPTS: usize = 100_000;
+
+/// Blocks a transaction may stay pending before it is dropped.
+pub const PENDING_BLOCKS: u64 = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub status: TxStatus,
+    pub block_hash: Option<String>,
+    pub block_height: Option<u64>,
+    pub gas_used: u64,
+    /// Paid per unit of gas, the block's base fee and the tip
+    pub gas_price: u64,
+    /// Emitted during execution
+    pub events: Vec<TxEvent>,
+    /// Why execution failed; a failed transaction still paid its fee
+    pub error: Option<String>,
+    /// Why the transaction was dropped
+    pub reason: Option<String>,
+    /// Chain height when the node first saw the transaction
+    pub seen_at_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TxEvent {
+    pub kind: String,
+    pub attributes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReceiptIndex {
+    receipts: HashMap<String, Receipt>,
+    /// Hashes, oldest first, for eviction
+    order: VecDeque<String>,
+    /// Hashes of the transactions each block includes, so finalizing a
+    /// block touches only its own receipts
+    blocks: HashMap<String, Vec<String>>,
+}
+
+impl ReceiptIndex {
+    pub fn get(&self, tx_hash: &str) -> Option<&Receipt> {
+        self.receipts.get(tx_hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.receipts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.receipts.is_empty()
+    }
+
+    pub fn pending(&mut self, tx: &Transaction, height: u64) {
+        self.entry(tx, height);
+    }
+
+    pub fn included(&mut self, tx: &Transaction, outcome: &TxOutcome, block_hash: &str, height: u64) {
+        let previous = self.entry(tx, height).block_hash.clone();
+        if previous.as_deref() != Some(block_hash) {
+            if let Some(previous) = previous {
+                self.unlink(&previous, &tx.hash);
+            }
+            self.blocks.entry(block_hash.to_string()).or_default().push(tx.hash.clone());
+        }
+        let receipt = self.entry(tx, height);
+        receipt.status = TxStatus::Included;
+        receipt.block_hash = Some(block_hash.to_string());
+        receipt.block_height = Some(height);
+        receipt.gas_used = outcome.gas_used;
+        receipt.gas_price = outcome.gas_price;
+        receipt.events = outcome.events.clone();
+        receipt.error = outcome.error.clone();
+        receipt.reason = None;
+    }
+
+    /// Marks the transactions included in `block_hash` finalized,
+    /// returning their hashes.
+    pub fn finalized(&mut self, block_hash: &str) -> Vec<String> {
+        let mut finalized = Vec::new();
+        for tx_hash in self.blocks.get(block_hash).into_iter().flatten() {
+            if let Some(receipt) = self.receipts.get_mut(tx_hash).filter(|receipt| receipt.status == TxStatus::Included) {
+                receipt.status = TxStatus::Finalized;
+                finalized.push(tx_hash.clone());
+            }
+        }
+        finalized
+    }
+
+    pub fn dropped(&mut self, tx_hash: &str, reason: impl Into<String>) {
+        if let Some(receipt) = self.receipts.get_mut(tx_hash) {
+            receipt.status = TxStatus::Dropped;
+            receipt.reason = Some(reason.into());
+        }
+    }
+
+    fn entry(&mut self, tx: &Transaction, height: u64) -> &mut Receipt {
+        if !self.receipts.contains_key(&tx.hash) {
+            while self.receipts.len() >= MAX_RECEIPTS {
+                let Some(oldest) = self.order.pop_front() else { break };
+                if let Some(block_hash) = self.receipts.remove(&oldest).and_then(|receipt| receipt.block_hash) {
+                    self.unlink(&block_hash, &oldest);
+                }
+            }
+            self.order.push_back(tx.hash.clone());
+        }
+        self.receipts.entry(tx.hash.clone()).or_insert_with(|| Receipt {
+            tx_hash: tx.hash.clone(),
+            status: TxStatus::Pending,
+            block_hash: None,
+            block_height: None,
+            gas_used: 0,
+            gas_price: 0,
+            events: Vec::new(),
+            error: None,
+            reason: None,
+            seen_at_height: height,
+        })
+    }
+
+    fn unlink(&mut self, block_hash: &str, tx_hash: &str) {
+        if let Some(included) = self.blocks.get_mut(block_hash) {
+            included.retain(|hash| hash != tx_hash);
+            if included.is_empty() {
+                self.blocks.remove(block_hash);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(n: u64) -> Transaction {
+        let mut tx = Transaction {
+            from: "0xalice".to_string(),
+            to: "0xbob".to_string(),
+            value: n,
+            gas_limit: 21_000,
+            ..Transaction::default()
+        };
+        tx.hash = tx.compute_hash();
+        tx
+    }
+
+    fn outcome(tx: &Transaction) -> TxOutcome {
+        TxOutcome { tx_hash: tx.hash.clone(), gas_used: tx.gas_limit, gas_price: 1, error: None, events: vec![], output: vec![] }
+    }
+
+    #[test]
+    fn receipts_follow_transactions_through_finality() {
+        let mut index = ReceiptIndex::default();
+        let (a, b) = (tx(1), tx(2));
+        index.pending(&a, 4);
+        assert_eq!(index.get(&a.hash).unwrap().status, TxStatus::Pending);
+
+        index.included(&a, &outcome(&a), "0xb5", 5);
+        index.included(&b, &outcome(&b), "0xb5", 5);
+        let receipt = index.get(&a.hash).unwrap();
+        assert_eq!((receipt.block_height, receipt.gas_used, receipt.seen_at_height), (Some(5), 21_000, 4));
+
+        let mut finalized = index.finalized("0xb5");
+        finalized.sort();
+        let mut expected = vec![a.hash.clone(), b.hash.clone()];
+        expected.sort();
+        assert_eq!(finalized, expected);
+        assert!(index.finalized("0xb5").is_empty());
+
+        // Re-included elsewhere, it finalizes with its new block only
+        let c = tx(3);
+        index.included(&c, &outcome(&c), "0xb6", 6);
+        index.included(&c, &outcome(&c), "0xb7", 6);
+        assert!(index.finalized("0xb6").is_empty());
+        assert_eq!(index.finalized("0xb7"), std::slice::from_ref(&c.hash));
+    }
+
+    #[test]
+    fn dropped_receipts_keep_their_reason() {
+        let mut index = ReceiptIndex::default();
+        let a = tx(1);
+        index.pending(&a, 0);
+        index.dropped(&a.hash, "expired");
+        index.dropped("0xunknown", "expired");
+        let receipt = index.get(&a.hash).unwrap();
+        assert_eq!((receipt.status, receipt.reason.as_deref()), (TxStatus::Dropped, Some("expired")));
+        assert_eq!(index.len(), 1);
+    }
+}
@@ -0,0 +1,131 @@
+//! Validator registrations, which keep a validator's keys apart.
+//!
+//! A validator has three:
+//!
+//! - its network key, the libp2p identity its node connects and gossips
+//!   as, which names it as a peer;
+//! - its consensus key, which signs its votes, so must stay online;
+//! - its reward account, which receives the tips of the blocks it
+//!   proposes and sends its key rotations, and can stay offline or on a
+//!   Ledger.
+//!
+//! A `Registration` links them under the validator's node id. The
+//! consensus and network keys each sign it, so no validator can claim
+//! another's, and the reward account must be neither key's address, so a
+//! node's hot keys never control funds. A validator without one is paid
+//! at, and rotates from, its consensus key's account.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+
+/// Prefixes what registrations sign, so no other signature passes for
+/// one.
+const REGISTRATION_DOMAIN: &[u8] = b"cubiq-registration-v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Registration {
+    pub node_id: String,
+    /// Hex-encoded ed25519 public key the validator votes with; a key
+    /// rotation replaces the registration with one the new key signs
+    pub consensus_key: String,
+    /// Hex-encoded ed25519 public key of the node's libp2p identity
+    pub network_key: String,
+    /// Address tips go to, which authorizes key rotations
+    pub reward_account: String,
+    /// Hex-encoded signatures of the other fields by each key
+    pub consensus_signature: String,
+    pub network_signature: String,
+}
+
+impl Registration {
+    /// Registers `node_id` as voting with `consensus`, connecting with
+    /// `network` and paid at `reward_account`, signed by both keys.
+    pub fn signed(node_id: &str, consensus: &SigningKey, network: &SigningKey, reward_account: &Address) -> Self {
+        let mut registration = Registration {
+            node_id: node_id.to_string(),
+            consensus_key: hex::encode(consensus.verifying_key().to_bytes()),
+            network_key: hex::encode(network.verifying_key().to_bytes()),
+            reward_account: reward_account.to_string(),
+            consensus_signature: String::new(),
+            network_signature: String::new(),
+        };
+        let hash = registration.signing_hash();
+        registration.consensus_signature = hex::encode(consensus.sign(hash.as_bytes()).to_bytes());
+        registration.network_signature = hex::encode(network.sign(hash.as_bytes()).to_bytes());
+        registration
+    }
+
+    fn signing_hash(&self) -> blake3::Hash {
+        let fields = (&self.node_id, &self.consensus_key, &self.network_key, &self.reward_account);
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(REGISTRATION_DOMAIN).update(&bincode::serialize(&fields).expect("registrations encode"));
+        hasher.finalize()
+    }
+
+    /// Checks that both keys signed the registration and that the reward
+    /// account is neither's.
+    pub fn verify(&self) -> Result<(), String> {
+        let hash = self.signing_hash();
+        let consensus = verify_signature("consensus", &self.consensus_key, &self.consensus_signature, &hash)?;
+        let network = verify_signature("network", &self.network_key, &self.network_signature, &hash)?;
+        if consensus == network {
+            return Err("consensus and network keys are the same".to_string());
+        }
+        let reward_account: Address = self.reward_account.parse().map_err(|e| format!("reward account {}: {}", self.reward_account, e))?;
+        if [consensus, network].iter().any(|key| Address::from_key(key) == reward_account) {
+            return Err(format!("reward account {} is a hot key's, so the node would control its funds", self.reward_account));
+        }
+        Ok(())
+    }
+}
+
+fn verify_signature(which: &str, public_key: &str, signature: &str, hash: &blake3::Hash) -> Result<VerifyingKey, String> {
+    let key = Address::from_public_key(public_key)
+        .and_then(|address| address.verifying_key())
+        .map_err(|_| format!("{} key {} is not an ed25519 public key", which, public_key))?;
+    let signature: [u8; 64] = hex::decode(signature)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} signature is not 64 hex-encoded bytes", which))?;
+    key.verify_strict(hash.as_bytes(), &Signature::from_bytes(&signature))
+        .map_err(|_| format!("{} signature does not match", which))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn account(seed: u8) -> Address {
+        Address::from_key(&key(seed).verifying_key())
+    }
+
+    #[test]
+    fn registrations_are_signed_by_both_hot_keys() {
+        let registration = Registration::signed("v1", &key(1), &key(2), &account(3));
+        assert_eq!(registration.verify(), Ok(()));
+        let json = serde_json::to_string(&registration).unwrap();
+        assert_eq!(serde_json::from_str::<Registration>(&json).unwrap(), registration);
+
+        let claimed = Registration { node_id: "v2".to_string(), ..registration.clone() };
+        assert_eq!(claimed.verify(), Err("consensus signature does not match".to_string()));
+        let swapped = Registration { network_key: hex::encode(key(4).verifying_key().to_bytes()), ..registration.clone() };
+        assert!(swapped.verify().unwrap_err().contains("signature does not match"));
+        let unsigned = Registration { network_signature: String::new(), ..registration };
+        assert!(unsigned.verify().unwrap_err().contains("network signature is not"));
+    }
+
+    #[test]
+    fn hot_keys_are_never_the_reward_account() {
+        assert!(Registration::signed("v1", &key(1), &key(2), &account(1)).verify().unwrap_err().contains("hot key's"));
+        assert!(Registration::signed("v1", &key(1), &key(2), &account(2)).verify().unwrap_err().contains("hot key's"));
+        assert!(Registration::signed("v1", &key(1), &key(1), &account(3)).verify().unwrap_err().contains("the same"));
+    }
+}
@@ -0,0 +1,387 @@
+//! Deterministic replay of finalized blocks, for auditing the chain and
+//! for finding where a change to the execution engine parts from history.
+//!
+//! `Replayer` rebuilds the state below the first height asked for, as
+//! `QubeNode::restore` does, then re-executes each stored block and
+//! compares what it gets with what was recorded: the state root and gas
+//! used of the block, and the transactions root of its stored header. A
+//! block whose state root differs ends the replay, since every later one
+//! would be executed on the wrong state; other differences are reported
+//! and the replay goes on. `check_consistency` uses it to find how far a
+//! store can be restored. `ProofChecker` re-verifies a block's proof
+//! bundle the way validators did before voting for it.
+
+use serde::Serialize;
+use std::fmt;
+
+use crate::events::BlockHeader;
+use crate::execution::{self, BlockEnv};
+use crate::genesis::{ChainSpec, ConsensusParams};
+use crate::merkle;
+use crate::state::StateTrie;
+use crate::store::{BlockStore, StoreError, StoreView};
+use crate::ValidatorSet;
+use prover::{domain::ProofPurpose, MobileProofVerifier};
+use zkurl::resolver::ProofBundle;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Store(StoreError),
+    /// The store cannot produce the blocks or state asked for
+    Unavailable(String),
+    /// A block below the first height asked for does not replay to its
+    /// recorded state root
+    Diverged { height: u64, divergences: Vec<Divergence> },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Store(e) => write!(f, "{}", e),
+            ReplayError::Unavailable(what) => write!(f, "{}", what),
+            ReplayError::Diverged { height, divergences } => {
+                write!(f, "block {} diverges before the replayed range", height)?;
+                for divergence in divergences {
+                    write!(f, "; {}", divergence)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<StoreError> for ReplayError {
+    fn from(e: StoreError) -> Self {
+        ReplayError::Store(e)
+    }
+}
+
+/// A value the replay disagrees with the chain on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// `state_root`, `gas_used`, `transactions_root` or `execution`, or
+    /// `proof` and `proof.<public input>` from `ProofChecker`
+    pub field: String,
+    pub recorded: String,
+    pub replayed: String,
+}
+
+impl Divergence {
+    fn new(field: &str, recorded: impl ToString, replayed: impl ToString) -> Self {
+        Self { field: field.to_string(), recorded: recorded.to_string(), replayed: replayed.to_string() }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: recorded {}, replayed {}", self.field, self.recorded, self.replayed)
+    }
+}
+
+/// One re-executed block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReplayedBlock {
+    pub height: u64,
+    /// The stored header
+    pub header: BlockHeader,
+    pub state_root: String,
+    pub gas_used: u64,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayedBlock {
+    pub fn diverged(&self) -> bool {
+        !self.divergences.is_empty()
+    }
+}
+
+/// Re-executes stored blocks in order, yielding one `ReplayedBlock` each
+/// up to the last height asked for or the first wrong state root.
+pub struct Replayer<'a> {
+    store: StoreView<'a>,
+    chain_id: u64,
+    params: ConsensusParams,
+    state: StateTrie,
+    validators: ValidatorSet,
+    next: u64,
+    to: u64,
+}
+
+impl<'a> Replayer<'a> {
+    /// Prepares to replay finalized heights `from..=to`, re-executing the
+    /// blocks below `from` first. Every body from genesis, or from the
+    /// stored state base, up to `to` must still be stored.
+    pub fn new(store: &'a BlockStore, spec: &ChainSpec, from: u64, to: u64) -> Result<Self, ReplayError> {
+        let store = store.view()?;
+        let latest = store.latest_height()?;
+        if from == 0 || from > to || to > latest {
+            return Err(ReplayError::Unavailable(format!("heights {}..={} are not finalized; the store ends at {}", from, to, latest)));
+        }
+        let (base, state) = match store.state_base()? {
+            Some((base, accounts)) => (base, StateTrie::from_accounts(accounts)),
+            None => (0, StateTrie::from_balances(&spec.accounts)),
+        };
+        let validators = store.base_validators()?.unwrap_or_else(|| spec.validator_set());
+        if from <= base {
+            return Err(ReplayError::Unavailable(format!("state before height {} was not kept", base + 1)));
+        }
+        let mut replayer = Self {
+            store,
+            chain_id: spec.chain_id,
+            params: spec.params,
+            state,
+            validators,
+            next: base + 1,
+            to,
+        };
+        while replayer.next < from {
+            let block = replayer.step()?;
+            if block.diverged() {
+                return Err(ReplayError::Diverged { height: block.height, divergences: block.divergences });
+            }
+        }
+        Ok(replayer)
+    }
+
+    fn step(&mut self) -> Result<ReplayedBlock, ReplayError> {
+        let height = self.next;
+        let block = self
+            .store
+            .block_at(height)?
+            .ok_or_else(|| ReplayError::Unavailable(format!("the body of block {} is not stored", height)))?;
+        let header = self.store.header(&block.block_hash)?.unwrap_or_else(|| block.header());
+        let proposer = self.validators.account(&block.proposer_id).ok_or_else(|| {
+            ReplayError::Unavailable(format!("block {} proposer {} is not a validator", height, block.proposer_id))
+        })?;
+        let env = BlockEnv {
+            chain_id: self.chain_id,
+            gas_limit: self.params.block_gas_limit,
+            base_fee: block.base_fee,
+            proposer: Some(proposer),
+            vm: self.params.vm,
+            timestamp: block.timestamp,
+        };
+        let mut divergences = Vec::new();
+        let transactions_root = merkle::transactions_root(&block.transactions).to_string();
+        // Headers stored before the transactions root was added have none
+        if !header.transactions_root.is_empty() && header.transactions_root != transactions_root {
+            divergences.push(Divergence::new("transactions_root", &header.transactions_root, transactions_root));
+        }
+        self.next += 1;
+        let execution = match execution::execute(&self.state, &env, &block.transactions) {
+            Ok(execution) => execution,
+            Err(e) => {
+                divergences.push(Divergence::new("execution", "ok", e));
+                // Nothing after this block can be replayed
+                self.next = self.to + 1;
+                return Ok(ReplayedBlock { height, header, state_root: String::new(), gas_used: 0, divergences });
+            }
+        };
+        let root = execution.post_state(&mut self.state);
+        let gas_used = execution.gas_used();
+        // Blocks from before the fee market record no gas used
+        if block.base_fee != 0 && gas_used != block.gas_used {
+            divergences.push(Divergence::new("gas_used", block.gas_used, gas_used));
+        }
+        if root.to_string() != block.state_root {
+            divergences.push(Divergence::new("state_root", &block.state_root, root));
+            self.next = self.to + 1;
+        } else {
+            self.state.set_root(root);
+            // Earlier roots are not needed again
+            self.state.prune([]);
+            self.validators.bond(&block.transactions, &execution.outcomes, height, &self.params);
+            self.validators.advance(&block.transactions, &execution.outcomes, height, self.params.epoch_length);
+        }
+        Ok(ReplayedBlock { height, header, state_root: root.to_string(), gas_used, divergences })
+    }
+}
+
+impl Iterator for Replayer<'_> {
+    type Item = Result<ReplayedBlock, ReplayError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.to {
+            return None;
+        }
+        let block = self.step();
+        if block.is_err() {
+            self.next = self.to + 1;
+        }
+        Some(block)
+    }
+}
+
+/// How much of the stored chain the node can restore from, as found by
+/// `check_consistency`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consistency {
+    pub latest: u64,
+    /// The newest height whose state replays to its recorded root
+    pub consistent: u64,
+    /// Why the block after `consistent` does not replay, if there is one
+    pub problem: Option<String>,
+}
+
+/// Replays the store from its state base to its newest height, as
+/// `QubeNode::restore` will on start, and finds the last height whose state
+/// can be rebuilt. Blocks after it are what a crash left inconsistent, and
+/// `BlockStore::truncate` to `consistent` rolls them back.
+pub fn check_consistency(store: &BlockStore, spec: &ChainSpec) -> Result<Consistency, ReplayError> {
+    let latest = store.latest_height()?;
+    let base = store.state_base()?.map_or(0, |(base, _)| base);
+    let mut consistency = Consistency { latest, consistent: base, problem: None };
+    if latest == base {
+        return Ok(consistency);
+    }
+    for block in Replayer::new(store, spec, base + 1, latest)? {
+        match block {
+            Ok(block) => match block.divergences.iter().find(|d| d.field == "state_root" || d.field == "execution") {
+                Some(divergence) => {
+                    consistency.problem = Some(format!("block {}: {}", block.height, divergence));
+                    break;
+                }
+                None => consistency.consistent = block.height,
+            },
+            Err(ReplayError::Store(e)) => return Err(ReplayError::Store(e)),
+            Err(e) => {
+                consistency.problem = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    Ok(consistency)
+}
+
+/// Re-verifies stored blocks' proof bundles as validators did before
+/// voting for the blocks.
+pub struct ProofChecker {
+    verifier: MobileProofVerifier,
+    chain_id: u64,
+}
+
+impl ProofChecker {
+    pub fn new(chain_id: u64) -> Self {
+        Self { verifier: MobileProofVerifier::new(), chain_id }
+    }
+
+    /// Checks that `bundle` proves the block `header` records. Returns
+    /// what does not match; nothing if the proof holds.
+    pub fn check(&self, header: &BlockHeader, bundle: &ProofBundle) -> Vec<Divergence> {
+        let inputs = &bundle.public_inputs;
+        let verified = self
+            .verifier
+            .verify_proof_with_public_inputs(&bundle.proof, &inputs.transcript_bytes(), self.chain_id, ProofPurpose::BlockFinality)
+            .map_err(|e| format!("{:?}", e));
+        let mut divergences = match verified {
+            Ok(true) => Vec::new(),
+            Ok(false) => vec![Divergence::new("proof", "valid", "invalid")],
+            Err(e) => vec![Divergence::new("proof", "valid", e)],
+        };
+        if header.block_hash != inputs.block_hash {
+            divergences.push(Divergence::new("proof.block_hash", &header.block_hash, &inputs.block_hash));
+        }
+        if header.state_root != inputs.state_root {
+            divergences.push(Divergence::new("proof.state_root", &header.state_root, &inputs.state_root));
+        }
+        if header.transaction_count as u32 != inputs.transaction_count {
+            divergences.push(Divergence::new("proof.transaction_count", header.transaction_count, inputs.transaction_count));
+        }
+        // Headers stored before the fee market carry no gas used
+        if header.gas_used != 0 && header.gas_used != inputs.gas_used {
+            divergences.push(Divergence::new("proof.gas_used", header.gas_used, inputs.gas_used));
+        }
+        divergences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::TRANSFER_GAS;
+    use crate::fees::MIN_BASE_FEE;
+    use crate::genesis::{GenesisValidator, Vm};
+    use crate::{BlockProposal, Transaction};
+    use ed25519_dalek::SigningKey;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    fn spec() -> ChainSpec {
+        let public_key = hex::encode(key().verifying_key().to_bytes());
+        let validator = GenesisValidator { node_id: "v1".to_string(), public_key, stake: 10, registration: None };
+        ChainSpec::dev(7, validator, 0)
+    }
+
+    /// Finalized blocks each paying `BOB`, the one at `tamper` recording
+    /// a wrong state root.
+    fn chain(blocks: u64, tamper: Option<u64>) -> BlockStore {
+        let store = BlockStore::temporary().unwrap();
+        let mut state = StateTrie::from_balances(&spec().accounts);
+        for height in 1..=blocks {
+            let unsigned = Transaction {
+                chain_id: 7,
+                nonce: height - 1,
+                to: BOB.to_string(),
+                value: height,
+                gas_limit: TRANSFER_GAS,
+                max_fee_per_gas: MIN_BASE_FEE,
+                ..Transaction::default()
+            };
+            let tx = unsigned.sign(&key());
+            let env = BlockEnv { chain_id: 7, gas_limit: spec().params.block_gas_limit, base_fee: MIN_BASE_FEE, proposer: None, vm: Vm::Wasm, timestamp: 0 };
+            let mut root = state.commit(execution::execute(&state, &env, std::slice::from_ref(&tx)).unwrap().changes).to_string();
+            if tamper == Some(height) {
+                root = "ab".repeat(32);
+            }
+            let block = BlockProposal {
+                block_hash: format!("b{}", height),
+                state_root: root,
+                zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+                transactions: vec![tx],
+                proposer_id: "v1".to_string(),
+                timestamp: 0,
+                base_fee: MIN_BASE_FEE,
+                gas_used: TRANSFER_GAS,
+            };
+            store.insert_finalized(height, &block.block_hash, Some(&block)).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn replays_stored_blocks_and_stops_at_a_wrong_state_root() {
+        let store = chain(4, None);
+        let replayed: Vec<ReplayedBlock> = Replayer::new(&store, &spec(), 2, 3).unwrap().map(Result::unwrap).collect();
+        assert_eq!(replayed.iter().map(|block| block.height).collect::<Vec<_>>(), [2, 3]);
+        assert!(replayed.iter().all(|block| !block.diverged() && block.state_root == block.header.state_root));
+        assert_eq!(replayed[1].gas_used, TRANSFER_GAS);
+
+        let store = chain(4, Some(3));
+        let replayed: Vec<ReplayedBlock> = Replayer::new(&store, &spec(), 1, 4).unwrap().map(Result::unwrap).collect();
+        assert_eq!(replayed.len(), 3);
+        assert_eq!(replayed[2].divergences[0].field, "state_root");
+        assert_eq!(replayed[2].divergences[0].recorded, "ab".repeat(32));
+        assert!(matches!(Replayer::new(&store, &spec(), 4, 4), Err(ReplayError::Diverged { height: 3, .. })));
+        assert!(matches!(Replayer::new(&store, &spec(), 3, 5), Err(ReplayError::Unavailable(_))));
+        assert!(matches!(Replayer::new(&store, &spec(), 0, 1), Err(ReplayError::Unavailable(_))));
+    }
+
+    #[test]
+    fn finds_the_last_height_the_state_rebuilds_to() {
+        let store = chain(4, None);
+        assert_eq!(check_consistency(&store, &spec()).unwrap(), Consistency { latest: 4, consistent: 4, problem: None });
+
+        let store = chain(4, Some(3));
+        let found = check_consistency(&store, &spec()).unwrap();
+        assert_eq!((found.latest, found.consistent), (4, 2));
+        assert!(found.problem.unwrap().starts_with("block 3: state_root"));
+        store.truncate(found.consistent).unwrap();
+        assert_eq!(check_consistency(&store, &spec()).unwrap(), Consistency { latest: 2, consistent: 2, problem: None });
+    }
+}
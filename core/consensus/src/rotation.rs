@@ -0,0 +1,303 @@
+//! Validator key rotation.
+//!
+//! A validator replaces its consensus key, without unbonding, by sending
+//! a `TxKind::RotateKey` transaction from its account: its registered
+//! reward account, else the address of its current key (see
+//! `registration`). The transaction's `data` is a `KeyRotation` as JSON,
+//! naming the new key and the epoch it takes effect at, which must be
+//! later than the epoch of the block that includes it. Execution only
+//! checks that a rotation is well formed; whether its sender may rotate
+//! the validator's key depends on the validator set, so nodes check that
+//! with `ValidatorSet::authorize_rotation` when they admit the transaction,
+//! propose a block and verify one. A block with an unauthorized rotation
+//! is invalid.
+//!
+//! A registered validator's rotation also carries its registration
+//! re-signed by the new key and the network key its node connects as
+//! from then on, which may be a new one; see `registration`. The reward
+//! account stays the same.
+//!
+//! A rotation is pending until the head reaches the first block of its
+//! epoch. From then on the validator's votes must be signed by the new
+//! key and votes signed by the old one are ignored, its registration, and
+//! with it the peer it is known as, is the re-signed one, and a validator
+//! without a reward account is paid at the new key's account. A later
+//! rotation of the same validator replaces a pending one.
+//!
+//! Nodes rebuild rotations by replaying blocks when they restart, so
+//! those in blocks before an imported snapshot's height are lost.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::execution::TxOutcome;
+use crate::receipts::TxEvent;
+use crate::registration::Registration;
+use crate::{Transaction, TxKind, ValidatorSet};
+
+/// The epoch the block at `height` is in: blocks `1..epoch_length` are in
+/// epoch 0, and each epoch after begins at a multiple of `epoch_length`.
+pub fn epoch(height: u64, epoch_length: u64) -> u64 {
+    height / epoch_length
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct KeyRotation {
+    pub node_id: String,
+    /// Hex-encoded ed25519 public key the validator votes with from
+    /// `epoch`
+    pub public_key: String,
+    pub epoch: u64,
+    /// A registered validator's registration, signed by `public_key` and
+    /// the network key it connects as from `epoch`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registration: Option<Registration>,
+}
+
+impl KeyRotation {
+    /// A transaction making this rotation, to be filled in and signed by
+    /// the validator's current key.
+    pub fn transaction(&self) -> Transaction {
+        let data = serde_json::to_vec(self).expect("key rotations encode");
+        Transaction { kind: TxKind::RotateKey, data, ..Transaction::default() }
+    }
+
+    /// The rotation `tx` makes, with its key in lowercase, if it is well
+    /// formed: its registration, if any, must be valid and for the
+    /// validator and key it rotates.
+    pub fn parse(tx: &Transaction) -> Result<Self, String> {
+        let mut rotation: KeyRotation = serde_json::from_slice(&tx.data).map_err(|e| format!("data is not a key rotation: {}", e))?;
+        rotation.public_key.make_ascii_lowercase();
+        Address::from_public_key(&rotation.public_key)
+            .and_then(|address| address.verifying_key())
+            .map_err(|_| format!("rotates to {}, which is not an ed25519 public key", rotation.public_key))?;
+        if let Some(registration) = &rotation.registration {
+            registration.verify().map_err(|e| format!("registration: {}", e))?;
+            if registration.node_id != rotation.node_id || !registration.consensus_key.eq_ignore_ascii_case(&rotation.public_key) {
+                return Err(format!("carries a registration of {} with {}, not of the rotated key", registration.node_id, registration.consensus_key));
+            }
+        }
+        Ok(rotation)
+    }
+
+    pub(crate) fn event(&self) -> TxEvent {
+        let attributes = [("node_id", self.node_id.clone()), ("public_key", self.public_key.clone()), ("epoch", self.epoch.to_string())];
+        TxEvent { kind: "key_rotation".to_string(), attributes: attributes.map(|(key, value)| (key.to_string(), value)).into() }
+    }
+}
+
+impl ValidatorSet {
+    /// The rotation `tx` makes in the block at `height`, if it is from the
+    /// account of the validator it names, takes effect in a later epoch and
+    /// rotates to a key no validator has, or is rotating to or registering
+    /// with, and that is not the validator's reward account's. A registered
+    /// validator's rotation must carry its registration, re-signed for the
+    /// same reward account with a network key no other validator has; an
+    /// unregistered one's must not.
+    pub fn authorize_rotation(&self, tx: &Transaction, height: u64, epoch_length: u64) -> Result<KeyRotation, String> {
+        let rotation = KeyRotation::parse(tx)?;
+        let account = self.account(&rotation.node_id).ok_or_else(|| format!("rotates the key of {}, which is not a validator", rotation.node_id))?;
+        if tx.from != account {
+            return Err(format!("rotates the key of {}, but is not from its account {}", rotation.node_id, account));
+        }
+        let current = epoch(height, epoch_length);
+        if rotation.epoch <= current {
+            return Err(format!("rotates at epoch {}, but epoch {} has begun", rotation.epoch, current));
+        }
+        if self.key_taken(&rotation.node_id, &rotation.public_key) {
+            return Err(format!("rotates to {}, which is already a validator's key", rotation.public_key));
+        }
+        let registered = self.validators.get(&rotation.node_id).and_then(|v| v.registration.as_ref());
+        if registered.is_some_and(|r| Address::from_public_key(&rotation.public_key).is_ok_and(|key| key.to_string() == r.reward_account)) {
+            return Err(format!("rotates to {}, its reward account's key, which would put the account's funds online", rotation.public_key));
+        }
+        match (registered, &rotation.registration) {
+            (Some(_), None) => return Err(format!("rotates the key of {}, which is registered, without re-signing its registration", rotation.node_id)),
+            (None, Some(_)) => return Err(format!("carries a registration, but {} is not registered", rotation.node_id)),
+            (Some(current), Some(next)) => {
+                if next.reward_account != current.reward_account {
+                    return Err(format!("moves the reward account of {} to {}; rotations keep it", rotation.node_id, next.reward_account));
+                }
+                if self.network_key_taken(&rotation.node_id, &next.network_key) {
+                    return Err(format!("connects as {}, which is already a validator's network key", next.network_key));
+                }
+            }
+            (None, None) => {}
+        }
+        Ok(rotation)
+    }
+
+    /// Takes in the block at `height` with `transactions` and their
+    /// `outcomes`: its rotations that succeeded become pending, then if
+    /// the block begins an epoch, the rotations due by then take effect.
+    /// Returns those.
+    pub fn advance(&mut self, transactions: &[Transaction], outcomes: &[TxOutcome], height: u64, epoch_length: u64) -> Vec<KeyRotation> {
+        for (tx, outcome) in transactions.iter().zip(outcomes) {
+            if tx.kind != TxKind::RotateKey || outcome.error.is_some() {
+                continue;
+            }
+            if let Ok(rotation) = KeyRotation::parse(tx) {
+                self.pending_rotations.insert(rotation.node_id.clone(), rotation);
+            }
+        }
+        if !height.is_multiple_of(epoch_length) {
+            return vec![];
+        }
+        let current = epoch(height, epoch_length);
+        let due: Vec<String> = self.pending_rotations.values().filter(|r| r.epoch <= current).map(|r| r.node_id.clone()).collect();
+        due.iter()
+            .filter_map(|node_id| {
+                let rotation = self.pending_rotations.remove(node_id)?;
+                let validator = self.validators.get_mut(node_id)?;
+                validator.public_key = rotation.public_key.clone();
+                if let Some(registration) = &rotation.registration {
+                    validator.registration = Some(registration.clone());
+                }
+                Some(rotation)
+            })
+            .collect()
+    }
+
+    /// Whether any validator votes with `public_key`, or a validator other
+    /// than `node_id` is rotating to it, or registering with it; see
+    /// `staking`.
+    pub(crate) fn key_taken(&self, node_id: &str, public_key: &str) -> bool {
+        let pending = self.pending_rotations.values().filter(|r| r.node_id != node_id).map(|r| &r.public_key);
+        let registering = self.pending_registrations().filter(|r| r.node_id != node_id).map(|r| &r.consensus_key);
+        let mut taken = self.validators.values().map(|v| &v.public_key).chain(pending).chain(registering);
+        taken.any(|key| key.eq_ignore_ascii_case(public_key))
+    }
+
+    /// Whether a validator other than `node_id` connects as, or is
+    /// rotating or registering to connect as, `network_key`.
+    pub(crate) fn network_key_taken(&self, node_id: &str, network_key: &str) -> bool {
+        let registered = self.validators.values().filter(|v| v.node_id != node_id).filter_map(|v| v.registration.as_ref());
+        let pending = self.pending_rotations.values().filter(|r| r.node_id != node_id).filter_map(|r| r.registration.as_ref());
+        let registering = self.pending_registrations().filter(|r| r.node_id != node_id);
+        registered.chain(pending).chain(registering).any(|r| r.network_key.eq_ignore_ascii_case(network_key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::{ChainSpec, GenesisValidator};
+    use ed25519_dalek::SigningKey;
+
+    const EPOCH_LENGTH: u64 = 10;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn public_key(seed: u8) -> String {
+        hex::encode(key(seed).verifying_key().to_bytes())
+    }
+
+    fn validators() -> ValidatorSet {
+        let validator = |node_id: &str, seed| GenesisValidator {
+            node_id: node_id.to_string(),
+            public_key: public_key(seed),
+            stake: 10,
+            registration: None,
+        };
+        let mut spec = ChainSpec::dev(7, validator("v1", 1), 0);
+        spec.validators.push(validator("v2", 2));
+        spec.validator_set()
+    }
+
+    /// `v1`'s rotation to `to` at `epoch`, signed by `signer`.
+    fn rotation(signer: u8, to: u8, epoch: u64) -> Transaction {
+        KeyRotation { node_id: "v1".to_string(), public_key: public_key(to), epoch, registration: None }.transaction().sign(&key(signer))
+    }
+
+    /// `v1`'s rotation to `key(3)` at epoch 2, signed by `key(5)` and
+    /// carrying its registration with network key `network` and
+    /// `reward_account`.
+    fn resigned(network: u8, reward_account: &Address) -> Transaction {
+        let registration = Registration::signed("v1", &key(3), &key(network), reward_account);
+        let rotation = KeyRotation { node_id: "v1".to_string(), public_key: public_key(3), epoch: 2, registration: Some(registration) };
+        rotation.transaction().sign(&key(5))
+    }
+
+    fn succeeded(tx: &Transaction) -> TxOutcome {
+        TxOutcome { tx_hash: tx.hash.clone(), gas_used: 0, gas_price: 1, error: None, events: vec![], output: vec![] }
+    }
+
+    #[test]
+    fn only_validators_rotate_their_own_keys_to_later_epochs() {
+        let set = validators();
+        assert_eq!(set.authorize_rotation(&rotation(1, 3, 2), 15, EPOCH_LENGTH).unwrap().public_key, public_key(3));
+        assert!(set.authorize_rotation(&rotation(2, 3, 2), 15, EPOCH_LENGTH).unwrap_err().contains("not from its account"));
+        assert!(set.authorize_rotation(&rotation(1, 3, 1), 15, EPOCH_LENGTH).unwrap_err().contains("epoch 1 has begun"));
+        assert!(set.authorize_rotation(&rotation(1, 2, 2), 15, EPOCH_LENGTH).unwrap_err().contains("already a validator's key"));
+
+        let stranger = KeyRotation { node_id: "v9".to_string(), public_key: public_key(3), epoch: 2, registration: None }.transaction().sign(&key(1));
+        assert!(set.authorize_rotation(&stranger, 15, EPOCH_LENGTH).unwrap_err().contains("not a validator"));
+        let garbage = Transaction { data: b"{\"node_id\":\"v1\",\"public_key\":\"11\",\"epoch\":2}".to_vec(), ..rotation(1, 3, 2) };
+        assert!(KeyRotation::parse(&garbage).unwrap_err().contains("not an ed25519 public key"));
+    }
+
+    #[test]
+    fn registered_validators_rotate_from_their_reward_account() {
+        let mut set = validators();
+        let reward_account = Address::from_key(&key(5).verifying_key());
+        let registration = Registration::signed("v1", &key(1), &key(6), &reward_account);
+        set.validators.get_mut("v1").unwrap().registration = Some(registration);
+        assert!(set.authorize_rotation(&rotation(1, 3, 2), 15, EPOCH_LENGTH).unwrap_err().contains("not from its account"));
+        assert!(set.authorize_rotation(&rotation(5, 3, 2), 15, EPOCH_LENGTH).unwrap_err().contains("without re-signing"));
+        assert!(set.authorize_rotation(&rotation(5, 5, 2), 15, EPOCH_LENGTH).unwrap_err().contains("reward account's key"));
+
+        let tx = resigned(7, &reward_account);
+        assert_eq!(set.authorize_rotation(&tx, 15, EPOCH_LENGTH).unwrap().registration.unwrap().network_key, public_key(7));
+        let moved = resigned(7, &Address::from_key(&key(8).verifying_key()));
+        assert!(set.authorize_rotation(&moved, 15, EPOCH_LENGTH).unwrap_err().contains("rotations keep it"));
+        let v2 = Registration::signed("v2", &key(2), &key(9), &Address::from_key(&key(10).verifying_key()));
+        set.validators.get_mut("v2").unwrap().registration = Some(v2);
+        assert!(set.authorize_rotation(&resigned(9, &reward_account), 15, EPOCH_LENGTH).unwrap_err().contains("already a validator's network key"));
+        let mismatched = KeyRotation { public_key: public_key(4), ..KeyRotation::parse(&tx).unwrap() }.transaction();
+        assert!(KeyRotation::parse(&mismatched).unwrap_err().contains("not of the rotated key"));
+
+        // The network identity changes with the key, at the boundary
+        set.advance(std::slice::from_ref(&tx), &[succeeded(&tx)], 15, EPOCH_LENGTH);
+        assert_eq!(set.validators["v1"].registration.as_ref().unwrap().network_key, public_key(6));
+        set.advance(&[], &[], 2 * EPOCH_LENGTH, EPOCH_LENGTH);
+        let registration = set.validators["v1"].registration.as_ref().unwrap();
+        assert_eq!((registration.consensus_key.as_str(), registration.network_key.as_str()), (public_key(3).as_str(), public_key(7).as_str()));
+        assert_eq!(set.account("v1"), Some(reward_account.to_string()));
+    }
+
+    #[test]
+    fn unregistered_validators_rotate_without_a_registration() {
+        let set = validators();
+        let reward_account = Address::from_key(&key(5).verifying_key());
+        let registration = Registration::signed("v1", &key(3), &key(7), &reward_account);
+        let rotation = KeyRotation { node_id: "v1".to_string(), public_key: public_key(3), epoch: 2, registration: Some(registration) };
+        let tx = rotation.transaction().sign(&key(1));
+        assert!(set.authorize_rotation(&tx, 15, EPOCH_LENGTH).unwrap_err().contains("is not registered"));
+    }
+
+    #[test]
+    fn rotations_take_effect_when_their_epoch_begins() {
+        let mut set = validators();
+        let tx = rotation(1, 3, 2);
+        assert!(set.advance(std::slice::from_ref(&tx), &[succeeded(&tx)], 15, EPOCH_LENGTH).is_empty());
+        assert_eq!(set.pending_rotations["v1"].epoch, 2);
+        let other = KeyRotation { node_id: "v2".to_string(), public_key: public_key(3), epoch: 2, registration: None }.transaction().sign(&key(2));
+        assert!(set.authorize_rotation(&other, 15, EPOCH_LENGTH).unwrap_err().contains("already a validator's key"));
+
+        assert!(set.advance(&[], &[], 19, EPOCH_LENGTH).is_empty());
+        assert_eq!(set.validators["v1"].public_key, public_key(1));
+        let rotated = set.advance(&[], &[], 2 * EPOCH_LENGTH, EPOCH_LENGTH);
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(set.validators["v1"].public_key, public_key(3));
+        assert_eq!(set.account("v1"), Some(Address::from_key(&key(3).verifying_key()).to_string()));
+        assert!(set.pending_rotations.is_empty());
+
+        let failed = TxOutcome { error: Some("out of gas".to_string()), ..succeeded(&tx) };
+        let tx = rotation(3, 4, 3);
+        set.advance(std::slice::from_ref(&tx), &[failed], 25, EPOCH_LENGTH);
+        assert!(set.pending_rotations.is_empty());
+    }
+}
@@ -0,0 +1,446 @@
+//! State snapshots: the accounts and block hashes at a finalized height,
+//! in a chunked, checksummed archive.
+//!
+//! An archive is a magic number, a manifest and the chunks, back to back:
+//!
+//! ```text
+//! "CUBIQSNP" | manifest length, u32 big-endian | manifest JSON | chunk 0 | chunk 1 | ...
+//! ```
+//!
+//! Each chunk is a JSON array of items, accounts first and then block
+//! hashes, of about `CHUNK_BYTES`. The manifest lists every chunk's length
+//! and blake3 hash, so each chunk can be checked on its own, whether read
+//! from a file or fetched from a peer during state sync. A snapshot is only
+//! restored if its accounts hash to the manifest's state root and that root
+//! is the one in the header of the block at the snapshot's height.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::events::BlockHeader;
+use crate::execution::{self, BlockEnv};
+use crate::genesis::ChainSpec;
+use crate::state::{Account, Hash, StateTrie};
+use crate::store::{BlockStore, StoreError, StoreView};
+
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Chunks are cut once they reach this size.
+pub const CHUNK_BYTES: usize = 1 << 20;
+
+const MAGIC: &[u8; 8] = b"CUBIQSNP";
+
+/// Largest manifest read, about 50k chunks' worth.
+const MAX_MANIFEST_BYTES: u32 = 8 << 20;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    Store(StoreError),
+    /// The archive or a chunk does not decode or match its checksum
+    Corrupt(String),
+    /// The snapshot decodes but is not the state it claims to be, or the
+    /// data dir cannot produce the state asked for
+    Mismatch(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot I/O error: {}", e),
+            SnapshotError::Store(e) => write!(f, "{}", e),
+            SnapshotError::Corrupt(what) => write!(f, "snapshot is corrupt: {}", what),
+            SnapshotError::Mismatch(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl From<StoreError> for SnapshotError {
+    fn from(e: StoreError) -> Self {
+        SnapshotError::Store(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SnapshotItem {
+    Account { address: String, account: Account },
+    /// Only the block at the snapshot's height carries its header
+    Block { height: u64, hash: String, header: Option<BlockHeader> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub len: u64,
+    pub hash: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub version: u32,
+    pub chain_id: u64,
+    pub height: u64,
+    pub block_hash: String,
+    pub state_root: Hash,
+    pub chunks: Vec<ChunkInfo>,
+}
+
+impl SnapshotManifest {
+    /// Names the snapshot, so peers can agree on which one they serve.
+    pub fn id(&self) -> Hash {
+        let encoded = serde_json::to_vec(self).expect("manifests serialize");
+        Hash(*blake3::hash(&encoded).as_bytes())
+    }
+
+    /// Checks chunk `index` against its checksum and decodes it.
+    pub fn verify_chunk(&self, index: usize, bytes: &[u8]) -> Result<Vec<SnapshotItem>, SnapshotError> {
+        let info = self.chunks.get(index).ok_or_else(|| SnapshotError::Corrupt(format!("there is no chunk {}", index)))?;
+        if bytes.len() as u64 != info.len || Hash(*blake3::hash(bytes).as_bytes()) != info.hash {
+            return Err(SnapshotError::Corrupt(format!("chunk {} does not match its checksum", index)));
+        }
+        serde_json::from_slice(bytes).map_err(|e| SnapshotError::Corrupt(format!("chunk {}: {}", index, e)))
+    }
+}
+
+/// What a verified snapshot restores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restored {
+    pub accounts: BTreeMap<String, Account>,
+    /// Heights `1..=height` with their hashes, in order
+    pub blocks: Vec<(u64, String, Option<BlockHeader>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub manifest: SnapshotManifest,
+    pub chunks: Vec<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Chunks `accounts` and `blocks`, the state and chain at `height`.
+    pub fn build(
+        chain_id: u64,
+        accounts: &BTreeMap<String, Account>,
+        blocks: Vec<(u64, String, Option<BlockHeader>)>,
+    ) -> Result<Self, SnapshotError> {
+        let (height, block_hash) = match blocks.last() {
+            Some((height, hash, Some(_))) => (*height, hash.clone()),
+            _ => return Err(SnapshotError::Mismatch("a snapshot needs the header of its newest block".to_string())),
+        };
+        let items = accounts
+            .iter()
+            .map(|(address, account)| SnapshotItem::Account { address: address.clone(), account: account.clone() })
+            .chain(blocks.into_iter().map(|(height, hash, header)| SnapshotItem::Block { height, hash, header }));
+        let mut chunks = vec![];
+        let (mut chunk, mut size) = (vec![], 0);
+        for item in items {
+            size += serde_json::to_vec(&item).map_err(|e| SnapshotError::Corrupt(e.to_string()))?.len() + 1;
+            chunk.push(item);
+            if size >= CHUNK_BYTES {
+                chunks.push(encode_chunk(&std::mem::take(&mut chunk))?);
+                size = 0;
+            }
+        }
+        if !chunk.is_empty() {
+            chunks.push(encode_chunk(&chunk)?);
+        }
+        let manifest = SnapshotManifest {
+            version: SNAPSHOT_VERSION,
+            chain_id,
+            height,
+            block_hash,
+            state_root: StateTrie::from_accounts(accounts.clone()).root(),
+            chunks: chunks
+                .iter()
+                .map(|chunk| ChunkInfo { len: chunk.len() as u64, hash: Hash(*blake3::hash(chunk).as_bytes()) })
+                .collect(),
+        };
+        Ok(Self { manifest, chunks })
+    }
+
+    /// Writes the archive to `path`, atomically.
+    pub fn write(&self, path: &Path) -> Result<(), SnapshotError> {
+        let manifest = serde_json::to_vec(&self.manifest).map_err(|e| SnapshotError::Corrupt(e.to_string()))?;
+        let tmp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&(manifest.len() as u32).to_be_bytes())?;
+        file.write_all(&manifest)?;
+        for chunk in &self.chunks {
+            file.write_all(chunk)?;
+        }
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Reads an archive. Chunks are checked by `restore`.
+    pub fn read(path: &Path) -> Result<Self, SnapshotError> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::Corrupt(format!("{} is not a snapshot archive", path.display())));
+        }
+        let mut len = [0u8; 4];
+        file.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len);
+        if len > MAX_MANIFEST_BYTES {
+            return Err(SnapshotError::Corrupt(format!("{} byte manifest", len)));
+        }
+        let mut manifest = vec![0u8; len as usize];
+        file.read_exact(&mut manifest)?;
+        let manifest: SnapshotManifest =
+            serde_json::from_slice(&manifest).map_err(|e| SnapshotError::Corrupt(format!("manifest: {}", e)))?;
+        if manifest.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Corrupt(format!("version {} snapshots are not supported", manifest.version)));
+        }
+        let mut chunks = Vec::with_capacity(manifest.chunks.len());
+        for info in &manifest.chunks {
+            let mut chunk = vec![];
+            file.by_ref().take(info.len).read_to_end(&mut chunk)?;
+            chunks.push(chunk);
+        }
+        if file.read(&mut [0u8; 1])? != 0 {
+            return Err(SnapshotError::Corrupt("trailing bytes after the last chunk".to_string()));
+        }
+        Ok(Self { manifest, chunks })
+    }
+
+    /// Checks every chunk and that the accounts and blocks are the state
+    /// and chain the manifest claims.
+    pub fn restore(&self) -> Result<Restored, SnapshotError> {
+        if self.chunks.len() != self.manifest.chunks.len() {
+            return Err(SnapshotError::Corrupt(format!("{} of {} chunks", self.chunks.len(), self.manifest.chunks.len())));
+        }
+        let mut restored = Restored { accounts: BTreeMap::new(), blocks: vec![] };
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            for item in self.manifest.verify_chunk(index, chunk)? {
+                match item {
+                    SnapshotItem::Account { address, account } => {
+                        restored.accounts.insert(address, account);
+                    }
+                    SnapshotItem::Block { height, hash, header } => restored.blocks.push((height, hash, header)),
+                }
+            }
+        }
+        let root = StateTrie::from_accounts(restored.accounts.clone()).root();
+        if root != self.manifest.state_root {
+            return Err(SnapshotError::Mismatch(format!("accounts hash to {}, manifest says {}", root, self.manifest.state_root)));
+        }
+        match restored.blocks.last() {
+            Some((height, hash, Some(header)))
+                if *height == self.manifest.height && *hash == self.manifest.block_hash && header.state_root == root.to_string() => {}
+            _ => {
+                return Err(SnapshotError::Mismatch(format!(
+                    "block {} at height {} does not commit to state root {}",
+                    self.manifest.block_hash, self.manifest.height, root
+                )))
+            }
+        }
+        Ok(restored)
+    }
+}
+
+fn encode_chunk(items: &[SnapshotItem]) -> Result<Vec<u8>, SnapshotError> {
+    serde_json::to_vec(items).map_err(|e| SnapshotError::Corrupt(e.to_string()))
+}
+
+/// Rebuilds the accounts at `height` by replaying stored blocks on top of
+/// genesis, or of an imported snapshot. Every body from there on must
+/// still be stored.
+pub fn state_at(store: &BlockStore, spec: &ChainSpec, height: u64) -> Result<StateTrie, SnapshotError> {
+    replay(&store.view()?, spec, height)
+}
+
+fn replay(store: &StoreView, spec: &ChainSpec, height: u64) -> Result<StateTrie, SnapshotError> {
+    let (base, mut state) = match store.state_base()? {
+        Some((base, accounts)) => (base, StateTrie::from_accounts(accounts)),
+        None => (0, StateTrie::from_balances(&spec.accounts)),
+    };
+    if height < base {
+        return Err(SnapshotError::Mismatch(format!("state before height {} was not kept", base)));
+    }
+    let validators = spec.validator_set();
+    for height in base + 1..=height {
+        let block = store
+            .block_at(height)?
+            .ok_or_else(|| SnapshotError::Mismatch(format!("the body of block {} is not stored", height)))?;
+        let proposer = validators
+            .account(&block.proposer_id)
+            .ok_or_else(|| SnapshotError::Mismatch(format!("block {} proposer {} is not a validator", height, block.proposer_id)))?;
+        let env = BlockEnv {
+            chain_id: spec.chain_id,
+            gas_limit: spec.params.block_gas_limit,
+            base_fee: block.base_fee,
+            proposer: Some(proposer),
+            vm: spec.params.vm,
+            timestamp: block.timestamp,
+        };
+        let execution = execution::execute(&state, &env, &block.transactions)
+            .map_err(|e| SnapshotError::Mismatch(format!("block {}: {}", height, e)))?;
+        let root = execution.post_state(&mut state);
+        if root.to_string() != block.state_root {
+            return Err(SnapshotError::Mismatch(format!("block {} has state root {}, replay gives {}", height, block.state_root, root)));
+        }
+        state.set_root(root);
+        // Earlier roots are not needed again
+        state.prune([]);
+    }
+    Ok(state)
+}
+
+/// The snapshot of a store at finalized `height`.
+pub fn export(store: &BlockStore, spec: &ChainSpec, height: u64) -> Result<Snapshot, SnapshotError> {
+    // One view throughout, so blocks finalized or pruned meanwhile don't
+    // tear the snapshot
+    let store = store.view()?;
+    let latest = store.latest_height()?;
+    if height == 0 || height > latest {
+        return Err(SnapshotError::Mismatch(format!("height {} is not finalized; the store ends at {}", height, latest)));
+    }
+    let state = replay(&store, spec, height)?;
+    let mut blocks = store.hashes(1..=height).map(|entry| entry.map(|(height, hash)| (height, hash, None))).collect::<Result<Vec<_>, _>>()?;
+    if let Some((_, hash, header)) = blocks.last_mut() {
+        *header = store.header(hash)?;
+    }
+    Snapshot::build(spec.chain_id, &state.accounts(), blocks)
+}
+
+/// Verifies `snapshot` and fills an empty store with it.
+pub fn import(store: &BlockStore, spec: &ChainSpec, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+    if snapshot.manifest.chain_id != spec.chain_id {
+        return Err(SnapshotError::Mismatch(format!(
+            "snapshot is of chain {}, the chain spec is for chain {}",
+            snapshot.manifest.chain_id, spec.chain_id
+        )));
+    }
+    let restored = snapshot.restore()?;
+    store.import(&restored.blocks, snapshot.manifest.height, &restored.accounts)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::TRANSFER_GAS;
+    use crate::fees::MIN_BASE_FEE;
+    use crate::genesis::{GenesisValidator, Vm};
+    use crate::{BlockProposal, Transaction};
+    use ed25519_dalek::SigningKey;
+
+    const BOB: &str = "cubiq1hwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwamhwash6mvca";
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[1; 32])
+    }
+
+    /// A dev chain funding `key`'s account.
+    fn spec() -> ChainSpec {
+        let public_key = hex::encode(key().verifying_key().to_bytes());
+        let validator = GenesisValidator { node_id: "v1".to_string(), public_key, stake: 10, registration: None };
+        ChainSpec::dev(7, validator, 0)
+    }
+
+    /// A store holding `blocks` finalized blocks, each paying `BOB`.
+    fn chain(blocks: u64) -> BlockStore {
+        let store = BlockStore::temporary().unwrap();
+        let mut state = StateTrie::from_balances(&spec().accounts);
+        for height in 1..=blocks {
+            let unsigned = Transaction {
+                chain_id: 7,
+                nonce: height - 1,
+                to: BOB.to_string(),
+                value: height,
+                gas_limit: TRANSFER_GAS,
+                max_fee_per_gas: MIN_BASE_FEE,
+                ..Transaction::default()
+            };
+            let tx = unsigned.sign(&key());
+            let env = BlockEnv { chain_id: 7, gas_limit: spec().params.block_gas_limit, base_fee: MIN_BASE_FEE, proposer: None, vm: Vm::Wasm, timestamp: 0 };
+            let root = state.commit(execution::execute(&state, &env, std::slice::from_ref(&tx)).unwrap().changes);
+            let block = BlockProposal {
+                block_hash: format!("b{}", height),
+                state_root: root.to_string(),
+                zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+                transactions: vec![tx],
+                proposer_id: "v1".to_string(),
+                timestamp: 0,
+                base_fee: MIN_BASE_FEE,
+                gas_used: TRANSFER_GAS,
+            };
+            store.insert_finalized(height, &block.block_hash, Some(&block)).unwrap();
+        }
+        store
+    }
+
+    #[test]
+    fn exports_and_imports_through_an_archive() {
+        let source = chain(3);
+        let snapshot = export(&source, &spec(), 2).unwrap();
+        assert_eq!((snapshot.manifest.height, snapshot.manifest.block_hash.as_str()), (2, "b2"));
+        let path = std::env::temp_dir().join(format!("cubiq-snapshot-{}.snap", std::process::id()));
+        snapshot.write(&path).unwrap();
+        let read = Snapshot::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, snapshot);
+
+        let target = BlockStore::temporary().unwrap();
+        import(&target, &spec(), &read).unwrap();
+        assert_eq!((target.latest_height().unwrap(), target.hash_at(1).unwrap().unwrap()), (2, "b1".to_string()));
+        let state = state_at(&target, &spec(), 2).unwrap();
+        assert_eq!(state.get(BOB).balance, 3);
+        assert!(matches!(import(&target, &spec(), &read), Err(SnapshotError::Store(StoreError::NotEmpty { height: 2 }))));
+        // The imported store carries on where the snapshot ends
+        let block = source.block_at(3).unwrap().unwrap();
+        target.insert_finalized(3, "b3", Some(&block)).unwrap();
+        assert_eq!(state_at(&target, &spec(), 3).unwrap().root().to_string(), block.state_root);
+        assert!(state_at(&target, &spec(), 1).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_snapshots() {
+        let snapshot = export(&chain(2), &spec(), 2).unwrap();
+
+        let mut flipped = snapshot.clone();
+        let last = flipped.chunks[0].len() - 2;
+        flipped.chunks[0][last] ^= 1;
+        assert!(matches!(flipped.restore(), Err(SnapshotError::Corrupt(_))));
+
+        // Consistent chunks that don't hash to the root
+        let mut accounts = snapshot.restore().unwrap().accounts;
+        accounts.insert("0xmallory".to_string(), Account { balance: 1_000, ..Account::default() });
+        let mut forged = Snapshot::build(7, &accounts, snapshot.restore().unwrap().blocks).unwrap();
+        forged.manifest.state_root = snapshot.manifest.state_root;
+        assert!(forged.restore().unwrap_err().to_string().contains("manifest says"));
+        let rebuilt = Snapshot::build(7, &accounts, snapshot.restore().unwrap().blocks).unwrap();
+        assert!(rebuilt.restore().unwrap_err().to_string().contains("does not commit"));
+
+        let mut other_chain = spec();
+        other_chain.chain_id = 8;
+        assert!(import(&BlockStore::temporary().unwrap(), &other_chain, &snapshot).unwrap_err().to_string().contains("chain 7"));
+    }
+
+    #[test]
+    fn large_states_span_chunks() {
+        let accounts = (0..12_000).map(|n| (format!("0x{:040}", n), Account { balance: n + 1, ..Account::default() })).collect();
+        let mut header = chain(1).header("b1").unwrap().unwrap();
+        header.state_root = StateTrie::from_accounts(BTreeMap::clone(&accounts)).root().to_string();
+        let snapshot = Snapshot::build(7, &accounts, vec![(1, "b1".to_string(), Some(header))]).unwrap();
+        assert!(snapshot.manifest.chunks.len() > 1);
+        assert!(snapshot.manifest.verify_chunk(1, &snapshot.chunks[1]).is_ok());
+        assert!(snapshot.manifest.verify_chunk(1, &snapshot.chunks[0]).is_err());
+        assert_eq!(snapshot.restore().unwrap().accounts, accounts);
+    }
+}
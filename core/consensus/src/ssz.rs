@@ -0,0 +1,155 @@
+//! The node's consensus types as `cubiq_types` encodes and merkleizes
+//! them: what votes are signed over and what light clients check.
+//!
+//! Block hashes, node ids and zkURLs are carried as their UTF-8 bytes;
+//! hex hashes and keys as the 32 bytes they encode, so a state root or
+//! validator key that is not one fails to convert. Validators are ordered
+//! by node id.
+
+use cubiq_types::ssz::{List, Ssz};
+use cubiq_types::{from_hex, Text};
+
+use crate::{merkle, BlockProposal, Validator, ValidatorSet, Vote};
+
+fn text(field: &str, value: &str) -> Result<Text, String> {
+    Text::try_from(value).map_err(|e| format!("{}: {}", field, e))
+}
+
+fn bytes32(field: &str, value: &str) -> Result<[u8; 32], String> {
+    from_hex(value).ok_or_else(|| format!("{} is not a hex-encoded 32-byte value", field))
+}
+
+/// What `vote` is signed over, before its domain.
+pub fn vote_data(vote: &Vote) -> Result<cubiq_types::VoteData, String> {
+    Ok(cubiq_types::VoteData {
+        block_hash: text("block hash", &vote.block_hash)?,
+        voter_id: text("voter id", &vote.voter_id)?,
+        stake: vote.stake,
+        timestamp: vote.timestamp,
+        state_root: vote.state_root.0,
+        transactions_root: vote.transactions_root.0,
+        gas_used: vote.gas_used,
+        transaction_count: vote.transaction_count,
+    })
+}
+
+pub fn vote(vote: &Vote) -> Result<cubiq_types::Vote, String> {
+    let signature = hex::decode(&vote.signature).map_err(|_| "signature is not hex".to_string())?;
+    Ok(cubiq_types::Vote {
+        data: vote_data(vote)?,
+        signature: List::new(signature).map_err(|e| format!("signature: {}", e))?,
+    })
+}
+
+pub fn validator(validator: &Validator) -> Result<cubiq_types::Validator, String> {
+    Ok(cubiq_types::Validator {
+        node_id: text("node id", &validator.node_id)?,
+        public_key: bytes32("public key", &validator.public_key)?,
+        stake: validator.stake,
+        is_active: validator.is_active,
+    })
+}
+
+/// The set's validators and stake, without its pending key rotations.
+pub fn validator_set(set: &ValidatorSet) -> Result<cubiq_types::ValidatorSet, String> {
+    let mut validators: Vec<&Validator> = set.validators.values().collect();
+    validators.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+    let validators = validators.into_iter().map(validator).collect::<Result<Vec<_>, _>>()?;
+    Ok(cubiq_types::ValidatorSet {
+        validators: List::new(validators).map_err(|e| format!("validators: {}", e))?,
+        total_stake: set.total_stake,
+        supermajority_threshold: set.supermajority_threshold,
+    })
+}
+
+pub fn block_header(proposal: &BlockProposal) -> Result<cubiq_types::BlockHeader, String> {
+    Ok(cubiq_types::BlockHeader {
+        block_hash: text("block hash", &proposal.block_hash)?,
+        state_root: bytes32("state root", &proposal.state_root)?,
+        transactions_root: merkle::transactions_root(&proposal.transactions).0,
+        transaction_count: transaction_count(proposal)?,
+        zkurl: text("zkURL", &proposal.zkurl.to_string())?,
+        proposer_id: text("proposer id", &proposal.proposer_id)?,
+        timestamp: proposal.timestamp,
+        base_fee: proposal.base_fee,
+        gas_used: proposal.gas_used,
+    })
+}
+
+/// `proposal`'s checkpoint once finalized at `height`, as light clients
+/// follow them.
+pub fn checkpoint(proposal: &BlockProposal, height: u64) -> Result<cubiq_types::Checkpoint, String> {
+    Ok(cubiq_types::Checkpoint {
+        height,
+        block_hash: text("block hash", &proposal.block_hash)?,
+        state_root: bytes32("state root", &proposal.state_root)?,
+        transactions_root: merkle::transactions_root(&proposal.transactions).0,
+        gas_used: proposal.gas_used,
+        transaction_count: transaction_count(proposal)?,
+    })
+}
+
+fn transaction_count(proposal: &BlockProposal) -> Result<u32, String> {
+    u32::try_from(proposal.transactions.len()).map_err(|_| "too many transactions".to_string())
+}
+
+/// `vote`'s signed root; see `Vote::sign`.
+pub fn vote_signing_root(vote: &Vote) -> Result<[u8; 32], String> {
+    Ok(cubiq_types::signing_root(&vote_data(vote)?, cubiq_types::VOTE_DOMAIN))
+}
+
+/// The root a validator set commits to, as a light client snapshots it.
+pub fn validator_set_root(set: &ValidatorSet) -> Result<[u8; 32], String> {
+    Ok(validator_set(set)?.hash_tree_root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(node_id: &str, seed: u8) -> Validator {
+        Validator {
+            node_id: node_id.to_string(),
+            stake: 10,
+            public_key: hex::encode([seed; 32]),
+            is_active: true,
+            last_vote_time: 0,
+            registration: None,
+        }
+    }
+
+    #[test]
+    fn converts_in_a_canonical_order_and_rejects_what_does_not_fit() {
+        let mut set = ValidatorSet::new();
+        for (node_id, seed) in [("v2", 2), ("v1", 1), ("v3", 3)] {
+            set.validators.insert(node_id.to_string(), validator(node_id, seed));
+        }
+        let encoded = validator_set(&set).unwrap();
+        let ids: Vec<&[u8]> = encoded.validators.as_slice().iter().map(|v| v.node_id.as_slice()).collect();
+        assert_eq!(ids, [b"v1", b"v2", b"v3"]);
+        assert_eq!(validator_set_root(&set).unwrap(), encoded.hash_tree_root());
+        set.validators.insert("v4".to_string(), Validator { public_key: "nope".to_string(), ..validator("v4", 4) });
+        assert!(validator_set(&set).unwrap_err().contains("public key"));
+
+        let mut proposal = BlockProposal {
+            block_hash: "0xb1".to_string(),
+            state_root: format!("0x{}", "ab".repeat(32)),
+            zkurl: "zk://prover@example.com/block1".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "v1".to_string(),
+            timestamp: 5,
+            base_fee: 1,
+            gas_used: 0,
+        };
+        let header = block_header(&proposal).unwrap();
+        assert_eq!(cubiq_types::BlockHeader::decode(&header.encode()), Ok(header));
+        assert_eq!(checkpoint(&proposal, 3).unwrap().state_root, [0xab; 32]);
+        proposal.state_root = "r".to_string();
+        assert!(block_header(&proposal).is_err());
+
+        let vote = Vote { block_hash: "0xb1".to_string(), voter_id: "v1".to_string(), stake: 10, timestamp: 5, signature: "00".repeat(64), ..Vote::default() };
+        assert_eq!(vote_signing_root(&vote).unwrap(), cubiq_types::signing_root(&vote_data(&vote).unwrap(), cubiq_types::VOTE_DOMAIN));
+        assert_eq!(super::vote(&vote).unwrap().signature.as_slice(), [0; 64]);
+        assert!(vote_data(&Vote { voter_id: "v".repeat(cubiq_types::MAX_STRING_BYTES + 1), ..vote }).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! Staking, through transfers to `validator_registry()` that carry a
+//! `RegistryCall` as JSON in their data.
+//!
+//! A registration bonds the transaction's value to a new validator under
+//! the `Registration` its keys signed, and must come from the
+//! registration's reward account. A delegation bonds the value to a
+//! validator, and an undelegation, which sends no value, returns an amount
+//! its sender bonded to one. What a call bonds moves to the account
+//! of the validator and sender, `bond_address`, which no key holds, so its
+//! balance is the bond and a block's `state_root` covers it. Execution
+//! only checks that a call is well formed and moves the bond; whether the
+//! validator set takes the call depends on the set, so nodes check that
+//! with `ValidatorSet::authorize_registry_call` when they admit the
+//! transaction, propose a block and verify one, as they do key rotations.
+//! A registration must name a node id, consensus key and network key no
+//! validator has, is rotating to or is registering, and a delegation a
+//! validator or one registering. A block with an unauthorized call is
+//! invalid. Anything bonded can be undelegated, whether or not its
+//! validator still is one.
+//!
+//! A call that succeeded is pending until the head reaches the first
+//! block of the next epoch, when the set takes in the pending calls in
+//! block order: a registration adds its validator, with the bond as its
+//! stake, a delegation adds to a validator's stake and an undelegation
+//! takes from it, removing a validator left with none. The supermajority
+//! follows the new total stake. An undelegation returns its amount at
+//! once, so a validator keeps voting with stake it lost until the epoch
+//! ends.
+//!
+//! Nodes rebuild the set by replaying blocks when they restart, so calls
+//! in blocks before an imported snapshot's height are lost.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::address::Address;
+use crate::execution::{self, TxOutcome};
+use crate::genesis::{self, ConsensusParams};
+use crate::receipts::TxEvent;
+use crate::registration::Registration;
+use crate::state::{Account, StateTrie};
+use crate::{Transaction, TxKind, Validator, ValidatorSet};
+
+/// Domain of the addresses of accounts the chain itself keeps.
+const SYSTEM_DOMAIN: &[u8] = b"cubiq-system-v1";
+
+/// Prefixes a validator and delegator when deriving their bond account.
+const BOND_DOMAIN: &[u8] = b"cubiq-bond-v1";
+
+/// The account whose transactions bond stake to validators, which no key
+/// holds.
+pub fn validator_registry() -> Address {
+    Address::derive(SYSTEM_DOMAIN, b"validator-registry")
+}
+
+/// The account holding what `delegator` has bonded to validator
+/// `node_id`.
+pub fn bond_address(node_id: &str, delegator: &str) -> String {
+    let seed = bincode::serialize(&(node_id, delegator)).expect("bonds encode");
+    Address::derive(BOND_DOMAIN, &seed).to_string()
+}
+
+/// Whether `tx` is a transfer to `validator_registry()`.
+pub fn calls_registry(tx: &Transaction) -> bool {
+    tx.kind == TxKind::Transfer && tx.to.parse::<Address>().is_ok_and(|to| to == validator_registry())
+}
+
+/// What a transaction to `validator_registry()` asks of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RegistryCall {
+    /// A new validator, sent from the registration's reward account
+    Register(Registration),
+    Delegate { node_id: String },
+    Undelegate { node_id: String, amount: u64 },
+}
+
+impl RegistryCall {
+    /// A transaction bonding `value` with this call, to be filled in and
+    /// signed.
+    pub fn transaction(&self, value: u64) -> Transaction {
+        let data = serde_json::to_vec(self).expect("registry calls encode");
+        Transaction { to: validator_registry().to_string(), value, data, ..Transaction::default() }
+    }
+
+    /// The call `tx` makes, if it is well formed: a registration must be
+    /// valid.
+    pub fn parse(tx: &Transaction) -> Result<Self, String> {
+        let call: RegistryCall = serde_json::from_slice(&tx.data).map_err(|e| format!("data is not a registry call: {}", e))?;
+        if let RegistryCall::Register(registration) = &call {
+            registration.verify().map_err(|e| format!("registration: {}", e))?;
+        }
+        Ok(call)
+    }
+
+    /// The validator the call bonds to or unbonds from.
+    pub fn node_id(&self) -> &str {
+        match self {
+            RegistryCall::Register(registration) => &registration.node_id,
+            RegistryCall::Delegate { node_id } | RegistryCall::Undelegate { node_id, .. } => node_id,
+        }
+    }
+
+    /// The call's `action` tag.
+    pub fn action(&self) -> &'static str {
+        match self {
+            RegistryCall::Register(_) => "register",
+            RegistryCall::Delegate { .. } => "delegate",
+            RegistryCall::Undelegate { .. } => "undelegate",
+        }
+    }
+
+    fn event(&self, delegator: &str, amount: u64) -> TxEvent {
+        let attributes = [
+            ("action", self.action().to_string()),
+            ("node_id", self.node_id().to_string()),
+            ("delegator", delegator.to_string()),
+            ("amount", amount.to_string()),
+        ];
+        TxEvent { kind: "stake".to_string(), attributes: attributes.map(|(key, value)| (key.to_string(), value)).into() }
+    }
+}
+
+/// A registry call that succeeded, waiting for the next epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingCall {
+    pub call: RegistryCall,
+    /// What it bonds, or unbonds
+    pub amount: u64,
+}
+
+impl PendingCall {
+    fn new(call: RegistryCall, tx: &Transaction) -> Self {
+        let amount = match &call {
+            RegistryCall::Undelegate { amount, .. } => *amount,
+            RegistryCall::Register(_) | RegistryCall::Delegate { .. } => tx.value,
+        };
+        PendingCall { call, amount }
+    }
+}
+
+/// Carries out the `RegistryCall` in `tx.data` over `changes`, which are
+/// left untouched if it fails.
+pub(crate) fn execute(state: &StateTrie, changes: &mut BTreeMap<String, Account>, tx: &Transaction) -> Result<Vec<TxEvent>, String> {
+    let call = RegistryCall::parse(tx)?;
+    let bond = bond_address(call.node_id(), &tx.from);
+    let amount = match &call {
+        RegistryCall::Register(registration) if registration.reward_account != tx.from => {
+            return Err(format!("registers {} from {}, not its reward account {}", registration.node_id, tx.from, registration.reward_account));
+        }
+        RegistryCall::Register(_) | RegistryCall::Delegate { .. } => {
+            if tx.value == 0 {
+                return Err("bonds no stake".to_string());
+            }
+            send(state, changes, &tx.from, &bond, tx.value).map_err(|e| format!("insufficient balance: {}", e))?;
+            tx.value
+        }
+        RegistryCall::Undelegate { node_id, amount } => {
+            if tx.value != 0 {
+                return Err("undelegates, so sends no value".to_string());
+            }
+            if *amount == 0 {
+                return Err("unbonds no stake".to_string());
+            }
+            let bonded = execution::account(state, changes, &bond).balance;
+            if bonded < *amount {
+                return Err(format!("has {} bonded to {}, not {}", bonded, node_id, amount));
+            }
+            send(state, changes, &bond, &tx.from, *amount)?;
+            *amount
+        }
+    };
+    Ok(vec![call.event(&tx.from, amount)])
+}
+
+/// Moves `value` between two different accounts, or nothing.
+fn send(state: &StateTrie, changes: &mut BTreeMap<String, Account>, from: &str, to: &str, value: u64) -> Result<(), String> {
+    let sender = execution::account(state, changes, from);
+    let balance = sender.balance.checked_sub(value).ok_or_else(|| format!("{} has {}, not {}", from, sender.balance, value))?;
+    let recipient = execution::account(state, changes, to);
+    let credited = recipient.balance.checked_add(value).ok_or("recipient balance overflows")?;
+    changes.insert(from.to_string(), Account { balance, ..sender });
+    changes.insert(to.to_string(), Account { balance: credited, ..recipient });
+    Ok(())
+}
+
+impl ValidatorSet {
+    /// The registry call `tx` makes, if the set can take it: a
+    /// registration must be of a node id no validator has or is
+    /// registering, with keys no validator has, is rotating to or is
+    /// registering, and a delegation to a validator or one registering.
+    /// A transaction that doesn't call the registry makes none.
+    pub fn authorize_registry_call(&self, tx: &Transaction) -> Result<Option<RegistryCall>, String> {
+        if !calls_registry(tx) {
+            return Ok(None);
+        }
+        let call = RegistryCall::parse(tx)?;
+        if let RegistryCall::Delegate { node_id } = &call {
+            if !self.validators.contains_key(node_id) && !self.pending_registrations().any(|r| &r.node_id == node_id) {
+                return Err(format!("delegates to {}, which is not a validator", node_id));
+            }
+        }
+        if let RegistryCall::Register(registration) = &call {
+            let node_id = &registration.node_id;
+            if self.validators.contains_key(node_id) || self.pending_registrations().any(|r| &r.node_id == node_id) {
+                return Err(format!("registers {}, which is already a validator", node_id));
+            }
+            if self.key_taken(node_id, &registration.consensus_key) {
+                return Err(format!("registers {}, which is already a validator's key", registration.consensus_key));
+            }
+            if self.network_key_taken(node_id, &registration.network_key) {
+                return Err(format!("connects as {}, which is already a validator's network key", registration.network_key));
+            }
+        }
+        Ok(Some(call))
+    }
+
+    /// Authorizes `tx`'s registry call, if it makes one, and holds it as
+    /// pending, so the calls after it in the same block are checked as if
+    /// it succeeded and a block can't register a node id or key twice.
+    /// Checks a block's or a proposal's calls on a copy of the set.
+    pub fn stage_registry_call(&mut self, tx: &Transaction) -> Result<(), String> {
+        if let Some(call) = self.authorize_registry_call(tx)? {
+            self.pending_calls.push(PendingCall::new(call, tx));
+        }
+        Ok(())
+    }
+
+    /// Takes in the block at `height` with `transactions` and their
+    /// `outcomes`: its registry calls that succeeded become pending, then
+    /// if the block begins an epoch, the pending calls take effect.
+    /// Returns those.
+    pub fn bond(&mut self, transactions: &[Transaction], outcomes: &[TxOutcome], height: u64, params: &ConsensusParams) -> Vec<RegistryCall> {
+        for (tx, outcome) in transactions.iter().zip(outcomes) {
+            if !calls_registry(tx) || outcome.error.is_some() {
+                continue;
+            }
+            if let Ok(call) = RegistryCall::parse(tx) {
+                self.pending_calls.push(PendingCall::new(call, tx));
+            }
+        }
+        if !height.is_multiple_of(params.epoch_length) || self.pending_calls.is_empty() {
+            return vec![];
+        }
+        let due = std::mem::take(&mut self.pending_calls);
+        for PendingCall { call, amount } in &due {
+            match call {
+                RegistryCall::Register(registration) => self.register(registration, *amount),
+                RegistryCall::Delegate { node_id } => {
+                    if let Some(validator) = self.validators.get_mut(node_id) {
+                        validator.stake = validator.stake.saturating_add(*amount);
+                    }
+                }
+                RegistryCall::Undelegate { node_id, .. } => self.unbond(node_id, *amount),
+            }
+        }
+        self.total_stake = self.validators.values().map(|v| v.stake).sum();
+        self.supermajority_threshold = genesis::supermajority_threshold(self.total_stake, params);
+        due.into_iter().map(|pending| pending.call).collect()
+    }
+
+    /// Adds the validator `registration` registers with `stake`.
+    fn register(&mut self, registration: &Registration, stake: u64) {
+        let validator = Validator {
+            node_id: registration.node_id.clone(),
+            stake,
+            public_key: registration.consensus_key.to_ascii_lowercase(),
+            is_active: true,
+            last_vote_time: 0,
+            registration: Some(registration.clone()),
+        };
+        self.validators.insert(registration.node_id.clone(), validator);
+    }
+
+    /// Takes `amount` from the stake of validator `node_id`, if it is one,
+    /// removing it, and any rotation it has pending, when none is left.
+    fn unbond(&mut self, node_id: &str, amount: u64) {
+        let Some(validator) = self.validators.get_mut(node_id) else {
+            return;
+        };
+        validator.stake = validator.stake.saturating_sub(amount);
+        if validator.stake == 0 {
+            self.validators.remove(node_id);
+            self.pending_rotations.remove(node_id);
+        }
+    }
+
+    /// Registrations waiting for the next epoch.
+    pub(crate) fn pending_registrations(&self) -> impl Iterator<Item = &Registration> {
+        self.pending_calls.iter().filter_map(|pending| match &pending.call {
+            RegistryCall::Register(registration) => Some(registration),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::{ChainSpec, GenesisValidator};
+    use ed25519_dalek::SigningKey;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    /// A dev chain whose only validator is `v1`, holding `key(1)` and
+    /// staking 10.
+    fn spec() -> ChainSpec {
+        let public_key = hex::encode(key(1).verifying_key().to_bytes());
+        ChainSpec::dev(7, GenesisValidator { node_id: "v1".to_string(), public_key, stake: 10, registration: None }, 0)
+    }
+
+    fn account(seed: u8) -> String {
+        Address::from_key(&key(seed).verifying_key()).to_string()
+    }
+
+    fn register(node_id: &str, consensus: u8, network: u8, reward_account: u8, stake: u64) -> Transaction {
+        let registration = Registration::signed(node_id, &key(consensus), &key(network), &account(reward_account).parse().unwrap());
+        Transaction { from: account(reward_account), ..RegistryCall::Register(registration).transaction(stake) }
+    }
+
+    fn succeeded(tx: &Transaction) -> TxOutcome {
+        TxOutcome { tx_hash: tx.hash.clone(), gas_used: 0, gas_price: 0, error: None, events: vec![], output: vec![] }
+    }
+
+    #[test]
+    fn registry_calls_are_tagged_json() {
+        let call = RegistryCall::Undelegate { node_id: "validator-2".to_string(), amount: 40 };
+        let tx = call.transaction(0);
+        assert!(calls_registry(&tx));
+        assert_eq!(String::from_utf8(tx.data.clone()).unwrap(), r#"{"action":"undelegate","node_id":"validator-2","amount":40}"#);
+        assert_eq!(RegistryCall::parse(&tx).unwrap(), call);
+    }
+
+    #[test]
+    fn registrations_bond_from_their_reward_account() {
+        let state = StateTrie::from_accounts([(account(13), Account { balance: 100, ..Account::default() })].into());
+        let tx = register("v2", 11, 12, 13, 60);
+        let mut changes = BTreeMap::new();
+        let events = execute(&state, &mut changes, &tx).unwrap();
+        assert_eq!(events[0].attributes["action"], "register");
+        assert_eq!(changes[&account(13)].balance, 40);
+        assert_eq!(changes[&bond_address("v2", &account(13))].balance, 60);
+
+        let stranger = Transaction { from: account(14), ..tx.clone() };
+        assert!(execute(&state, &mut BTreeMap::new(), &stranger).unwrap_err().contains("not its reward account"));
+        assert!(execute(&state, &mut BTreeMap::new(), &register("v2", 11, 12, 13, 0)).unwrap_err().contains("no stake"));
+        assert!(execute(&state, &mut BTreeMap::new(), &register("v2", 11, 12, 13, 101)).unwrap_err().contains("insufficient balance"));
+        let forged = Transaction { data: String::from_utf8(tx.data).unwrap().replace("v2", "v3").into_bytes(), ..tx };
+        assert!(execute(&state, &mut BTreeMap::new(), &forged).unwrap_err().contains("registration"));
+    }
+
+    #[test]
+    fn registrations_join_the_set_when_the_next_epoch_begins() {
+        let params = spec().params;
+        let mut set = spec().validator_set();
+        assert!(set.authorize_registry_call(&register("v1", 11, 12, 13, 20)).unwrap_err().contains("already a validator"));
+        assert!(set.authorize_registry_call(&register("v2", 1, 12, 13, 20)).unwrap_err().contains("already a validator's key"));
+        let tx = register("v2", 11, 12, 13, 20);
+        assert!(set.authorize_registry_call(&tx).unwrap().is_some());
+        let mut block = set.clone();
+        assert_eq!(block.stage_registry_call(&tx), Ok(()));
+        assert!(block.stage_registry_call(&register("v3", 11, 15, 16, 20)).unwrap_err().contains("already a validator's key"));
+
+        assert!(set.bond(std::slice::from_ref(&tx), &[succeeded(&tx)], 1, &params).is_empty());
+        assert!(!set.validators.contains_key("v2"));
+        assert!(set.authorize_registry_call(&register("v2", 14, 15, 16, 20)).unwrap_err().contains("already a validator"));
+        assert!(set.authorize_registry_call(&register("v3", 11, 15, 16, 20)).unwrap_err().contains("already a validator's key"));
+        assert!(set.authorize_registry_call(&register("v3", 14, 12, 16, 20)).unwrap_err().contains("already a validator's network key"));
+
+        assert_eq!(set.bond(&[], &[], params.epoch_length, &params).len(), 1);
+        assert_eq!(set.validators["v2"].stake, 20);
+        assert_eq!(set.account("v2"), Some(account(13)));
+        assert_eq!(set.total_stake, 30);
+        assert_eq!(set.supermajority_threshold, 21);
+        assert!(set.pending_calls.is_empty());
+    }
+
+    #[test]
+    fn delegations_bond_and_undelegations_return_at_once() {
+        let state = StateTrie::from_accounts([(account(14), Account { balance: 100, ..Account::default() })].into());
+        let delegate = Transaction { from: account(14), ..RegistryCall::Delegate { node_id: "v1".to_string() }.transaction(30) };
+        let mut changes = BTreeMap::new();
+        execute(&state, &mut changes, &delegate).unwrap();
+        assert_eq!(changes[&bond_address("v1", &account(14))].balance, 30);
+
+        let undelegate = |amount| Transaction { from: account(14), ..RegistryCall::Undelegate { node_id: "v1".to_string(), amount }.transaction(0) };
+        let state = StateTrie::from_accounts(changes);
+        assert!(execute(&state, &mut BTreeMap::new(), &undelegate(31)).unwrap_err().contains("has 30 bonded to v1"));
+        assert!(execute(&state, &mut BTreeMap::new(), &undelegate(0)).unwrap_err().contains("no stake"));
+        assert!(execute(&state, &mut BTreeMap::new(), &Transaction { value: 1, ..undelegate(10) }).unwrap_err().contains("sends no value"));
+        let mut changes = BTreeMap::new();
+        let events = execute(&state, &mut changes, &undelegate(10)).unwrap();
+        assert_eq!(events[0].attributes["amount"], "10");
+        assert_eq!(changes[&account(14)].balance, 80);
+        assert_eq!(changes[&bond_address("v1", &account(14))].balance, 20);
+    }
+
+    #[test]
+    fn stake_changes_when_the_next_epoch_begins() {
+        let params = spec().params;
+        let mut set = spec().validator_set();
+        let delegate = |node_id: &str| RegistryCall::Delegate { node_id: node_id.to_string() }.transaction(5);
+        assert!(set.authorize_registry_call(&delegate("v2")).unwrap_err().contains("not a validator"));
+        let register = register("v2", 11, 12, 13, 20);
+        let undelegate = RegistryCall::Undelegate { node_id: "v2".to_string(), amount: 25 }.transaction(0);
+        let block = [register, delegate("v2"), delegate("v1"), undelegate];
+        let mut staged = set.clone();
+        for tx in &block {
+            assert_eq!(staged.stage_registry_call(tx), Ok(()));
+        }
+
+        let outcomes: Vec<TxOutcome> = block.iter().map(succeeded).collect();
+        set.bond(&block[..3], &outcomes[..3], params.epoch_length, &params);
+        assert_eq!((set.validators["v1"].stake, set.validators["v2"].stake, set.total_stake), (15, 25, 40));
+        set.bond(&block[3..], &outcomes[3..], params.epoch_length + 1, &params);
+        assert!(set.validators.contains_key("v2"));
+        set.bond(&[], &[], 2 * params.epoch_length, &params);
+        assert!(!set.validators.contains_key("v2"));
+        assert_eq!((set.total_stake, set.supermajority_threshold), (15, 11));
+    }
+}
@@ -0,0 +1,350 @@
+//! Account state, authenticated by a sparse Merkle trie.
+//!
+//! Accounts sit at the 256-bit blake3 hash of their address. The trie is
+//! kept compact: an empty subtree hashes to zero and a subtree holding a
+//! single account is that account's leaf, so a path is only as deep as it
+//! takes to tell keys apart. Hashes are domain separated:
+//!
+//! ```text
+//! leaf   = blake3(0x00 || key || value)
+//! branch = blake3(0x01 || left || right)
+//! value  = blake3(balance || nonce)
+//!        | blake3(balance || nonce || code_hash || storage_root)
+//!        | blake3(balance || nonce || multisig_address)
+//!        | blake3(balance || nonce || record_hash)
+//! ```
+//!
+//! The second value is a contract's, so its code and storage are under
+//! the state root too; see `contracts`. The third is a multisig's, which
+//! commits to its keys and threshold; see `multisig`. The fourth holds a
+//! bridge record; see `bridge`.
+//!
+//! Nodes are stored by hash and never overwritten, so every root the trie
+//! has had stays readable and provable until it is pruned. A `StateProof`,
+//! from `cubiq_types::proofs` like the hashing, lets a light client check
+//! an account, or its absence, against a block's `state_root`.
+
+use cubiq_types::proofs::{branch_hash, leaf_hash, MAX_DEPTH};
+pub use cubiq_types::proofs::{Hash, ProofLeaf, StateProof, TrieValue};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::bridge::Record;
+use crate::contracts::Contract;
+use crate::multisig::Multisig;
+
+/// Siblings passed on the way down, and the leaf the path ended at.
+type Walk = (Vec<Hash>, Option<(Hash, Account)>);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    /// Transactions sent from the account
+    pub nonce: u64,
+    /// Code and storage of a contract account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract: Option<Contract>,
+    /// Keys that control a multisig account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig: Option<Multisig>,
+    /// What the bridge keeps at the path the account is derived from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<Record>,
+}
+
+impl TrieValue for Account {
+    fn value_hash(&self) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.balance.to_be_bytes()).update(&self.nonce.to_be_bytes());
+        if let Some(contract) = &self.contract {
+            hasher.update(&contract.code_hash().0).update(&contract.storage_root().0);
+        }
+        if let Some(multisig) = &self.multisig {
+            hasher.update(multisig.address().as_bytes());
+        }
+        if let Some(record) = &self.bridge {
+            hasher.update(&record.hash().0);
+        }
+        Hash(*hasher.finalize().as_bytes())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Leaf { key: Hash, address: String, account: Account },
+    Branch { left: Hash, right: Hash },
+}
+
+#[derive(Debug, Clone)]
+pub struct StateTrie {
+    nodes: HashMap<Hash, Node>,
+    root: Hash,
+}
+
+impl Default for StateTrie {
+    fn default() -> Self {
+        Self { nodes: HashMap::new(), root: Hash::ZERO }
+    }
+}
+
+impl StateTrie {
+    /// A trie holding the genesis balances.
+    pub fn from_balances(balances: &BTreeMap<String, u64>) -> Self {
+        let mut trie = Self::default();
+        trie.commit(balances.iter().map(|(address, balance)| (address.clone(), Account { balance: *balance, ..Account::default() })));
+        trie
+    }
+
+    /// A trie holding `accounts`, e.g. restored from a snapshot.
+    pub fn from_accounts(accounts: BTreeMap<String, Account>) -> Self {
+        let mut trie = Self::default();
+        trie.commit(accounts);
+        trie
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Whether `root` is the current root or an earlier one still held.
+    pub fn has_root(&self, root: &Hash) -> bool {
+        *root == Hash::ZERO || self.nodes.contains_key(root)
+    }
+
+    /// The account at the current root; unknown accounts are empty.
+    pub fn get(&self, address: &str) -> Account {
+        self.get_at(&self.root, address).flatten().unwrap_or_default()
+    }
+
+    /// The account at `root`, or `None` when the root is not held.
+    pub fn get_at(&self, root: &Hash, address: &str) -> Option<Option<Account>> {
+        let key = Hash::of_address(address);
+        let (_, leaf) = self.walk(root, &key)?;
+        Some(leaf.filter(|(leaf_key, _)| *leaf_key == key).map(|(_, account)| account))
+    }
+
+    /// Applies the changed accounts and returns the new root. An account
+    /// that is back to empty is removed.
+    pub fn commit(&mut self, changes: impl IntoIterator<Item = (String, Account)>) -> Hash {
+        let base = self.root;
+        self.root = self.apply(&base, changes);
+        self.root
+    }
+
+    /// The root `changes` would give on top of `base`, which must be held.
+    /// The current root is left alone; see `set_root`.
+    pub fn apply(&mut self, base: &Hash, changes: impl IntoIterator<Item = (String, Account)>) -> Hash {
+        let mut root = *base;
+        for (address, account) in changes {
+            let key = Hash::of_address(&address);
+            let leaf = (account != Account::default()).then_some((address, account));
+            root = self.update(root, 0, &key, leaf);
+        }
+        root
+    }
+
+    /// Moves the current root to one that is held.
+    pub fn set_root(&mut self, root: Hash) {
+        assert!(self.has_root(&root), "state root {} is not held", root);
+        self.root = root;
+    }
+
+    /// Every account at the current root, by address.
+    pub fn accounts(&self) -> BTreeMap<String, Account> {
+        let mut accounts = BTreeMap::new();
+        let mut stack = vec![self.root];
+        while let Some(hash) = stack.pop() {
+            match self.nodes.get(&hash) {
+                Some(Node::Leaf { address, account, .. }) => {
+                    accounts.insert(address.clone(), account.clone());
+                }
+                Some(Node::Branch { left, right }) => stack.extend([*left, *right]),
+                None => {}
+            }
+        }
+        accounts
+    }
+
+    /// Trie nodes held, across every root still readable.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Drops the nodes that neither `keep` nor the current root reach,
+    /// returning how many went. Roots that are not held are ignored.
+    pub fn prune(&mut self, keep: impl IntoIterator<Item = Hash>) -> usize {
+        let mut reachable = HashSet::new();
+        let mut stack: Vec<Hash> = keep.into_iter().chain([self.root]).collect();
+        while let Some(hash) = stack.pop() {
+            if !reachable.insert(hash) {
+                continue;
+            }
+            if let Some(Node::Branch { left, right }) = self.nodes.get(&hash) {
+                stack.extend([*left, *right]);
+            }
+        }
+        let before = self.nodes.len();
+        self.nodes.retain(|hash, _| reachable.contains(hash));
+        before - self.nodes.len()
+    }
+
+    /// Proof of the account at the current root, or of its absence.
+    pub fn prove(&self, address: &str) -> StateProof {
+        self.prove_at(&self.root, address).expect("the current root is held")
+    }
+
+    /// Proof against `root`, or `None` when the root is not held.
+    pub fn prove_at(&self, root: &Hash, address: &str) -> Option<StateProof> {
+        let (siblings, leaf) = self.walk(root, &Hash::of_address(address))?;
+        let leaf = leaf.map(|(key, account)| ProofLeaf { key, value_hash: account.value_hash() });
+        Some(StateProof { siblings, leaf })
+    }
+
+    /// Follows `key` down from `root`, returning the siblings passed and
+    /// the leaf the path ends at, which may hold another key.
+    fn walk(&self, root: &Hash, key: &Hash) -> Option<Walk> {
+        let mut siblings = vec![];
+        let mut hash = *root;
+        loop {
+            if hash == Hash::ZERO {
+                return Some((siblings, None));
+            }
+            match self.nodes.get(&hash)? {
+                Node::Leaf { key, account, .. } => return Some((siblings, Some((*key, account.clone())))),
+                Node::Branch { left, right } => {
+                    let (next, sibling) = if key.bit(siblings.len()) == 0 { (left, right) } else { (right, left) };
+                    siblings.push(*sibling);
+                    hash = *next;
+                }
+            }
+        }
+    }
+
+    /// Sets or removes the leaf for `key` below `hash`, returning the
+    /// subtree's new hash.
+    fn update(&mut self, hash: Hash, depth: usize, key: &Hash, leaf: Option<(String, Account)>) -> Hash {
+        if hash == Hash::ZERO {
+            return leaf.map_or(Hash::ZERO, |(address, account)| self.put_leaf(*key, address, account));
+        }
+        match self.nodes[&hash].clone() {
+            Node::Leaf { key: existing, .. } if existing == *key => {
+                leaf.map_or(Hash::ZERO, |(address, account)| self.put_leaf(*key, address, account))
+            }
+            Node::Leaf { key: existing, .. } => match leaf {
+                Some((address, account)) => {
+                    let added = self.put_leaf(*key, address, account);
+                    self.split(depth, (existing, hash), (*key, added))
+                }
+                None => hash,
+            },
+            Node::Branch { left, right } => {
+                let (left, right) = if key.bit(depth) == 0 {
+                    (self.update(left, depth + 1, key, leaf), right)
+                } else {
+                    (left, self.update(right, depth + 1, key, leaf))
+                };
+                self.put_branch(left, right)
+            }
+        }
+    }
+
+    /// The subtree at `depth` holding two leaves whose keys differ.
+    fn split(&mut self, depth: usize, a: (Hash, Hash), b: (Hash, Hash)) -> Hash {
+        debug_assert!(depth < MAX_DEPTH, "distinct keys diverge within {} bits", MAX_DEPTH);
+        match (a.0.bit(depth), b.0.bit(depth)) {
+            (0, 1) => self.put_branch(a.1, b.1),
+            (1, 0) => self.put_branch(b.1, a.1),
+            (0, _) => {
+                let below = self.split(depth + 1, a, b);
+                self.put_branch(below, Hash::ZERO)
+            }
+            _ => {
+                let below = self.split(depth + 1, a, b);
+                self.put_branch(Hash::ZERO, below)
+            }
+        }
+    }
+
+    fn put_leaf(&mut self, key: Hash, address: String, account: Account) -> Hash {
+        let hash = leaf_hash(&key, &account.value_hash());
+        self.nodes.insert(hash, Node::Leaf { key, address, account });
+        hash
+    }
+
+    /// A branch, collapsed to its child when that is a lone leaf.
+    fn put_branch(&mut self, left: Hash, right: Hash) -> Hash {
+        let lone = match (left, right) {
+            (Hash::ZERO, Hash::ZERO) => return Hash::ZERO,
+            (Hash::ZERO, child) | (child, Hash::ZERO) => Some(child),
+            _ => None,
+        };
+        if let Some(child) = lone {
+            if matches!(self.nodes.get(&child), Some(Node::Leaf { .. })) {
+                return child;
+            }
+        }
+        let hash = branch_hash(&left, &right);
+        self.nodes.insert(hash, Node::Branch { left, right });
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(balance: u64) -> Account {
+        Account { balance, ..Account::default() }
+    }
+
+    #[test]
+    fn root_depends_on_contents_not_history() {
+        let mut a = StateTrie::default();
+        a.commit((0..50).map(|n| (format!("0x{}", n), account(n + 1))));
+        let mut b = StateTrie::default();
+        b.commit((0..60).rev().map(|n| (format!("0x{}", n), account(n + 1))));
+        assert_ne!(a.root(), b.root());
+        b.commit((50..60).map(|n| (format!("0x{}", n), Account::default())));
+        assert_eq!(a.root(), b.root());
+        assert_eq!(b.accounts().len(), 50);
+
+        b.commit((0..50).map(|n| (format!("0x{}", n), Account::default())));
+        assert_eq!(b.root(), Hash::ZERO);
+    }
+
+    #[test]
+    fn proves_inclusion_and_absence() {
+        let balances = (0..20).map(|n| (format!("0x{}", n), n * 10 + 1)).collect();
+        let trie = StateTrie::from_balances(&balances);
+        let root = trie.root();
+
+        let proof = trie.prove("0x7");
+        assert!(proof.verify(&root, "0x7", Some(&account(71))));
+        assert!(!proof.verify(&root, "0x7", Some(&account(72))));
+        assert!(!proof.verify(&root, "0x7", None));
+        assert!(!proof.verify(&root, "0x8", Some(&account(71))));
+
+        let absent = trie.prove("0xnobody");
+        assert!(absent.verify(&root, "0xnobody", None));
+        assert!(!absent.verify(&root, "0xnobody", Some(&account(1))));
+
+        let encoded = serde_json::to_string(&proof).unwrap();
+        assert!(encoded.contains("\"0x"));
+        assert_eq!(serde_json::from_str::<StateProof>(&encoded).unwrap(), proof);
+    }
+
+    #[test]
+    fn earlier_roots_stay_readable() {
+        let mut trie = StateTrie::from_balances(&BTreeMap::from([("0xalice".to_string(), 10)]));
+        let before = trie.root();
+        let spent = Account { balance: 4, nonce: 1, ..Account::default() };
+        trie.commit([("0xalice".to_string(), spent.clone()), ("0xbob".to_string(), account(6))]);
+
+        assert_eq!(trie.get("0xalice"), spent);
+        assert_eq!(trie.get_at(&before, "0xalice"), Some(Some(account(10))));
+        assert_eq!(trie.get_at(&before, "0xbob"), Some(None));
+        assert!(trie.prove_at(&before, "0xbob").unwrap().verify(&before, "0xbob", None));
+        assert!(trie.has_root(&before));
+        assert_eq!(trie.get_at(&Hash([7; 32]), "0xalice"), None);
+    }
+}
@@ -0,0 +1,793 @@
+//! Finalized blocks, in a `KvStore` with one column family per lookup:
+//!
+//! | column    | key                 | value                  |
+//! |-----------|---------------------|------------------------|
+//! | `heights` | height, big-endian  | block hash             |
+//! | `hashes`  | block hash          | height, big-endian     |
+//! | `headers` | block hash          | JSON `BlockHeader`     |
+//! | `blocks`  | block hash          | JSON `BlockProposal`   |
+//! | `state`   | account address     | JSON `Account`         |
+//! | `meta`    | `state_height`      | height, big-endian     |
+//! | `meta`    | `validators`        | JSON `ValidatorSet`    |
+//! | `meta`    | `last_vote`         | JSON height and hash   |
+//!
+//! Heights start at 1 and have no gaps. Consensus only finalizes blocks it
+//! has verified, so each is stored with its header and body. With a
+//! retention window, bodies that fall out of it are deleted once a state
+//! base at or above them is stored, so the state at the head can always be
+//! rebuilt; heights and headers are kept.
+//!
+//! With a freezer, headers and bodies of blocks far enough behind the head
+//! move out of the database into append-only files (see
+//! [`crate::freezer`]). Lookups read through to them, so callers can't tell
+//! where a block lives; heights and hashes stay in the database.
+//!
+//! Each block is finalized in one atomic write across the columns it
+//! touches. Lookups on `BlockStore` read the live columns; `view` gives a
+//! read handle that sees the store as of one moment, so a reader never
+//! observes a block half-applied or mixes blocks from before and after a
+//! write.
+//!
+//! A store imported from a snapshot has no bodies below the snapshot
+//! height. Instead `state` holds the accounts as of `state_height`, the
+//! base that later blocks are replayed on. A node with a retention window
+//! moves the base up as it finalizes blocks (see `state_base_due`), storing
+//! the validator set with it; a snapshot's base has none.
+//!
+//! `last_vote` is the newest block this node voted for, logged before the
+//! vote is sent (see `log_vote`). Rolling the store back leaves it, so a
+//! node never votes for another block at a height it voted at.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::ops::{Bound, RangeInclusive};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::events::BlockHeader;
+use crate::freezer::Freezer;
+use crate::kv::{ColumnFamily, KvError, KvStats, KvStore, MemoryKv, SledKv, WriteBatch};
+use crate::state::Account;
+use crate::{BlockProposal, ValidatorSet};
+
+const HEIGHTS: &str = "heights";
+const HASHES: &str = "hashes";
+const HEADERS: &str = "headers";
+const BLOCKS: &str = "blocks";
+const STATE: &str = "state";
+const META: &str = "meta";
+
+const STATE_HEIGHT: &[u8] = b"state_height";
+const VALIDATORS: &[u8] = b"validators";
+const LAST_VOTE: &[u8] = b"last_vote";
+
+/// A height and the accounts as of it.
+pub type StateBase = (u64, BTreeMap<String, Account>);
+
+#[derive(Debug)]
+pub enum StoreError {
+    Db(KvError),
+    /// Reading or writing the freezer's files
+    Io(io::Error),
+    /// A stored value does not decode
+    Corrupt(String),
+    /// Heights must be stored in order, without gaps
+    OutOfOrder { expected: u64, got: u64 },
+    /// Snapshots are only imported into an empty store
+    NotEmpty { height: u64 },
+    /// Heights up to the state base, or in the freezer, are not rolled back
+    Pinned { height: u64, pinned: u64 },
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Db(e) => write!(f, "block store: {}", e),
+            StoreError::Io(e) => write!(f, "block store freezer: {}", e),
+            StoreError::Corrupt(what) => write!(f, "block store is corrupt: {}", what),
+            StoreError::OutOfOrder { expected, got } => {
+                write!(f, "block store expected height {}, got {}", expected, got)
+            }
+            StoreError::NotEmpty { height } => write!(f, "block store already holds {} blocks", height),
+            StoreError::Pinned { height, pinned } => {
+                write!(f, "block store cannot roll back to height {}, below {}", height, pinned)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<KvError> for StoreError {
+    fn from(e: KvError) -> Self {
+        StoreError::Db(e)
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+/// What `cubiq db stats` and the storage metrics report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    pub latest_height: u64,
+    pub frozen_height: u64,
+    /// Without a freezer, none
+    pub freezer_bytes: Option<u64>,
+    pub kv: KvStats,
+}
+
+#[derive(Clone)]
+pub struct BlockStore {
+    kv: Arc<dyn KvStore>,
+    heights: Arc<dyn ColumnFamily>,
+    hashes: Arc<dyn ColumnFamily>,
+    headers: Arc<dyn ColumnFamily>,
+    blocks: Arc<dyn ColumnFamily>,
+    state: Arc<dyn ColumnFamily>,
+    meta: Arc<dyn ColumnFamily>,
+    /// Bodies kept, counting back from the newest height
+    retain: Option<u64>,
+    /// Where old blocks go, and how far behind the head they must be
+    freezer: Option<(Arc<Freezer>, u64)>,
+}
+
+impl BlockStore {
+    /// Opens or creates a sled store at `path`, keeping the bodies of the
+    /// last `retain` blocks, or all of them.
+    pub fn open(path: &Path, retain: Option<u64>) -> Result<Self, StoreError> {
+        Self::with_kv(Arc::new(SledKv::open(path)?), retain)
+    }
+
+    /// A store in memory, gone when dropped.
+    pub fn temporary() -> Result<Self, StoreError> {
+        Self::with_kv(Arc::new(MemoryKv::default()), None)
+    }
+
+    pub fn with_kv(kv: Arc<dyn KvStore>, retain: Option<u64>) -> Result<Self, StoreError> {
+        Ok(Self {
+            heights: kv.column(HEIGHTS)?,
+            hashes: kv.column(HASHES)?,
+            headers: kv.column(HEADERS)?,
+            blocks: kv.column(BLOCKS)?,
+            state: kv.column(STATE)?,
+            meta: kv.column(META)?,
+            kv,
+            retain,
+            freezer: None,
+        })
+    }
+
+    /// Moves headers and bodies of blocks more than `freeze_after` behind
+    /// the head into a freezer in `dir`. Blocks already past that point
+    /// move with the next finalized block.
+    pub fn with_freezer(mut self, dir: &Path, freeze_after: u64) -> Result<Self, StoreError> {
+        self.freezer = Some((Arc::new(Freezer::open(dir)?), freeze_after));
+        Ok(self)
+    }
+
+    /// Heights held in the freezer; they are `1..=frozen_height()`.
+    pub fn frozen_height(&self) -> u64 {
+        self.freezer.as_ref().map_or(0, |(freezer, _)| freezer.frozen())
+    }
+
+    /// Sizes of the database and the freezer.
+    pub fn stats(&self) -> Result<StoreStats, StoreError> {
+        Ok(StoreStats {
+            latest_height: self.latest_height()?,
+            frozen_height: self.frozen_height(),
+            freezer_bytes: self.freezer.as_ref().map(|(freezer, _)| freezer.size()),
+            kv: self.kv.stats()?,
+        })
+    }
+
+    /// Compacts the column family `column`; see `KvStore::compact`.
+    pub fn compact(&self, column: &str) -> Result<(), StoreError> {
+        Ok(self.kv.compact(column)?)
+    }
+
+    /// Records the block finalized at `height`, which must follow the
+    /// newest stored height, in one atomic write, then syncs it to disk.
+    pub fn insert_finalized(&self, height: u64, block_hash: &str, block: Option<&BlockProposal>) -> Result<(), StoreError> {
+        let expected = self.latest_height()? + 1;
+        if height != expected {
+            return Err(StoreError::OutOfOrder { expected, got: height });
+        }
+        let header = block.map(|block| encode(&block.header())).transpose()?;
+        let body = block.map(encode).transpose()?;
+        let state_height = self.reader().state_height()?;
+        let pruned = self.retain.and_then(|retain| height.checked_sub(retain)).filter(|height| (1..=state_height).contains(height));
+        let pruned_hash = match pruned {
+            Some(height) => self.hash_at(height)?,
+            None => None,
+        };
+        let mut batch = WriteBatch::default();
+        batch.insert(HEIGHTS, height.to_be_bytes(), block_hash);
+        batch.insert(HASHES, block_hash, height.to_be_bytes());
+        if let (Some(header), Some(body)) = (&header, &body) {
+            batch.insert(HEADERS, block_hash, header);
+            batch.insert(BLOCKS, block_hash, body);
+        }
+        if let Some(hash) = &pruned_hash {
+            batch.remove(BLOCKS, hash);
+        }
+        self.kv.write(batch)?;
+        self.freeze(height)
+    }
+
+    /// Moves blocks that fell `freeze_after` behind `head` into the
+    /// freezer. They are appended and synced before leaving the database,
+    /// so a crash in between leaves a copy in both, never in neither.
+    fn freeze(&self, head: u64) -> Result<(), StoreError> {
+        let Some((freezer, freeze_after)) = &self.freezer else {
+            return Ok(());
+        };
+        let from = freezer.frozen() + 1;
+        let until = head.saturating_sub(*freeze_after);
+        if from > until {
+            return Ok(());
+        }
+        let mut moved = vec![];
+        for entry in self.hashes(from..=until) {
+            let (height, hash) = entry?;
+            let header = self.headers.get(hash.as_bytes())?;
+            let body = self.blocks.get(hash.as_bytes())?;
+            freezer.freeze(height, header.as_deref(), body.as_deref())?;
+            moved.push(hash);
+        }
+        freezer.sync()?;
+        let mut batch = WriteBatch::default();
+        for hash in moved {
+            batch.remove(HEADERS, &hash);
+            batch.remove(BLOCKS, &hash);
+        }
+        self.kv.write(batch)?;
+        Ok(())
+    }
+
+    /// Whether a node keeping bodies for a retention window should store a
+    /// newer state base with `set_state_base` on finalizing `height`:
+    /// bodies only leave the window once the base has passed them.
+    pub fn state_base_due(&self, height: u64) -> Result<bool, StoreError> {
+        let Some(retain) = self.retain else {
+            return Ok(false);
+        };
+        Ok(height >= self.reader().state_height()? + (retain / 2).max(1))
+    }
+
+    /// Makes `accounts` and `validators`, as of finalized `height`, the
+    /// base that restarts replay from, then deletes the bodies that stayed
+    /// behind for want of one.
+    pub fn set_state_base(&self, height: u64, accounts: &BTreeMap<String, Account>, validators: &ValidatorSet) -> Result<(), StoreError> {
+        let previous = self.reader().state_height()?;
+        let latest = self.latest_height()?;
+        if height <= previous || height > latest {
+            return Err(StoreError::OutOfOrder { expected: previous + 1, got: height });
+        }
+        let mut batch = WriteBatch::default();
+        for entry in self.state.iter() {
+            let (address, _) = entry?;
+            if !accounts.contains_key(std::str::from_utf8(&address).unwrap_or_default()) {
+                batch.remove(STATE, address);
+            }
+        }
+        for (address, account) in accounts {
+            batch.insert(STATE, address, encode(account)?);
+        }
+        batch.insert(META, STATE_HEIGHT, height.to_be_bytes());
+        batch.insert(META, VALIDATORS, encode(validators)?);
+        let prunable = self.retain.map_or(0, |retain| latest.saturating_sub(retain)).min(height);
+        if previous < prunable {
+            for entry in self.hashes(previous + 1..=prunable) {
+                batch.remove(BLOCKS, entry?.1);
+            }
+        }
+        self.kv.write(batch)?;
+        Ok(())
+    }
+
+    /// Rolls the store back to `height`, forgetting every block finalized
+    /// after it, in one atomic write. Returns how many were removed. The
+    /// state base and the freezer's blocks stay, so `height` may not be
+    /// below either.
+    pub fn truncate(&self, height: u64) -> Result<u64, StoreError> {
+        let pinned = self.reader().state_height()?.max(self.frozen_height());
+        if height < pinned {
+            return Err(StoreError::Pinned { height, pinned });
+        }
+        let latest = self.latest_height()?;
+        if height >= latest {
+            return Ok(0);
+        }
+        let mut batch = WriteBatch::default();
+        for entry in self.hashes(height + 1..=latest) {
+            let (height, hash) = entry?;
+            batch.remove(HEIGHTS, height.to_be_bytes());
+            batch.remove(HASHES, &hash);
+            batch.remove(HEADERS, &hash);
+            batch.remove(BLOCKS, &hash);
+        }
+        self.kv.write(batch)?;
+        Ok(latest - height)
+    }
+
+    /// The height and hash of the newest block this node voted for.
+    pub fn last_vote(&self) -> Result<Option<(u64, String)>, StoreError> {
+        self.meta.get(LAST_VOTE)?.map(|vote| decode_json(&vote)).transpose()
+    }
+
+    /// Logs, and syncs to disk, that this node votes for `block_hash` at
+    /// `height`.
+    pub fn log_vote(&self, height: u64, block_hash: &str) -> Result<(), StoreError> {
+        let mut batch = WriteBatch::default();
+        batch.insert(META, LAST_VOTE, encode(&(height, block_hash))?);
+        Ok(self.kv.write(batch)?)
+    }
+
+    /// Fills an empty store from a snapshot: the hashes of heights
+    /// `1..=height`, with headers where known, and the accounts as of
+    /// `height`.
+    pub fn import(
+        &self,
+        blocks: &[(u64, String, Option<BlockHeader>)],
+        height: u64,
+        accounts: &BTreeMap<String, Account>,
+    ) -> Result<(), StoreError> {
+        let latest = self.latest_height()?;
+        if latest != 0 {
+            return Err(StoreError::NotEmpty { height: latest });
+        }
+        for (expected, (got, _, _)) in (1..).zip(blocks) {
+            if *got != expected {
+                return Err(StoreError::OutOfOrder { expected, got: *got });
+            }
+        }
+        if blocks.len() as u64 != height {
+            return Err(StoreError::Corrupt(format!("snapshot at height {} lists {} blocks", height, blocks.len())));
+        }
+        let mut headers = vec![];
+        for (_, hash, header) in blocks {
+            if let Some(header) = header {
+                headers.push((hash, encode(header)?));
+            }
+        }
+        let accounts = accounts
+            .iter()
+            .map(|(address, account)| Ok((address, encode(account)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        let mut batch = WriteBatch::default();
+        for (height, hash, _) in blocks {
+            batch.insert(HEIGHTS, height.to_be_bytes(), hash);
+            batch.insert(HASHES, hash, height.to_be_bytes());
+        }
+        for (hash, header) in &headers {
+            batch.insert(HEADERS, hash, header);
+        }
+        for (address, account) in &accounts {
+            batch.insert(STATE, address, account);
+        }
+        batch.insert(META, STATE_HEIGHT, height.to_be_bytes());
+        self.kv.write(batch)?;
+        Ok(())
+    }
+
+    /// A consistent view of the store as of now, for readers whose
+    /// lookups must agree, e.g. RPC queries spanning several blocks. On
+    /// sled, writes wait until the view is dropped.
+    pub fn view(&self) -> Result<StoreView<'_>, StoreError> {
+        let snapshot = self.kv.snapshot();
+        Ok(StoreView {
+            heights: snapshot.column(HEIGHTS)?,
+            hashes: snapshot.column(HASHES)?,
+            headers: snapshot.column(HEADERS)?,
+            blocks: snapshot.column(BLOCKS)?,
+            state: snapshot.column(STATE)?,
+            meta: snapshot.column(META)?,
+            freezer: self.freezer.as_ref().map(|(freezer, _)| &**freezer),
+        })
+    }
+
+    fn reader(&self) -> Reader<'_> {
+        Reader {
+            heights: &*self.heights,
+            hashes: &*self.hashes,
+            headers: &*self.headers,
+            blocks: &*self.blocks,
+            state: &*self.state,
+            meta: &*self.meta,
+            freezer: self.freezer.as_ref().map(|(freezer, _)| &**freezer),
+        }
+    }
+
+    /// The height and accounts replay starts from, if not genesis.
+    pub fn state_base(&self) -> Result<Option<StateBase>, StoreError> {
+        self.reader().state_base()
+    }
+
+    /// The validator set as of the state base, unless the base came from a
+    /// snapshot.
+    pub fn base_validators(&self) -> Result<Option<ValidatorSet>, StoreError> {
+        self.reader().base_validators()
+    }
+
+    /// The newest stored height, 0 when empty.
+    pub fn latest_height(&self) -> Result<u64, StoreError> {
+        self.reader().latest_height()
+    }
+
+    /// The lowest height whose body is still stored, 1 when none were
+    /// dropped. Bodies go from the bottom up, to a snapshot import below
+    /// the state base and to retention behind the head.
+    pub fn earliest_height(&self) -> Result<u64, StoreError> {
+        let reader = self.reader();
+        let latest = reader.latest_height()?;
+        let base = reader.state_height()?;
+        // Retention drops nothing above the base, and keeps its window
+        let mut height = self.retain.map_or(base, |retain| base.min(latest.saturating_sub(retain))) + 1;
+        while height < latest && reader.block_at(height)?.is_none() {
+            height += 1;
+        }
+        Ok(height)
+    }
+
+    pub fn hash_at(&self, height: u64) -> Result<Option<String>, StoreError> {
+        self.reader().hash_at(height)
+    }
+
+    pub fn height_of(&self, block_hash: &str) -> Result<Option<u64>, StoreError> {
+        self.reader().height_of(block_hash)
+    }
+
+    pub fn header(&self, block_hash: &str) -> Result<Option<BlockHeader>, StoreError> {
+        self.reader().header(block_hash)
+    }
+
+    /// The block's body, unless it was pruned or never seen.
+    pub fn block(&self, block_hash: &str) -> Result<Option<BlockProposal>, StoreError> {
+        self.reader().block(block_hash)
+    }
+
+    pub fn block_at(&self, height: u64) -> Result<Option<BlockProposal>, StoreError> {
+        self.reader().block_at(height)
+    }
+
+    /// Heights and hashes in `heights`, in order.
+    pub fn hashes(&self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, String), StoreError>> + '_ {
+        self.reader().hashes(heights)
+    }
+
+    /// Stored bodies in `heights`, in order, for serving peers that sync.
+    pub fn blocks(&self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, BlockProposal), StoreError>> + '_ {
+        self.reader().blocks(heights)
+    }
+}
+
+/// The store as it was when `BlockStore::view` was called. Blocks
+/// finalized, pruned or frozen since are not seen as such.
+pub struct StoreView<'a> {
+    heights: Box<dyn ColumnFamily + 'a>,
+    hashes: Box<dyn ColumnFamily + 'a>,
+    headers: Box<dyn ColumnFamily + 'a>,
+    blocks: Box<dyn ColumnFamily + 'a>,
+    state: Box<dyn ColumnFamily + 'a>,
+    meta: Box<dyn ColumnFamily + 'a>,
+    freezer: Option<&'a Freezer>,
+}
+
+impl StoreView<'_> {
+    fn reader(&self) -> Reader<'_> {
+        Reader {
+            heights: &*self.heights,
+            hashes: &*self.hashes,
+            headers: &*self.headers,
+            blocks: &*self.blocks,
+            state: &*self.state,
+            meta: &*self.meta,
+            freezer: self.freezer,
+        }
+    }
+
+    pub fn state_base(&self) -> Result<Option<StateBase>, StoreError> {
+        self.reader().state_base()
+    }
+
+    pub fn base_validators(&self) -> Result<Option<ValidatorSet>, StoreError> {
+        self.reader().base_validators()
+    }
+
+    pub fn latest_height(&self) -> Result<u64, StoreError> {
+        self.reader().latest_height()
+    }
+
+    pub fn hash_at(&self, height: u64) -> Result<Option<String>, StoreError> {
+        self.reader().hash_at(height)
+    }
+
+    pub fn height_of(&self, block_hash: &str) -> Result<Option<u64>, StoreError> {
+        self.reader().height_of(block_hash)
+    }
+
+    pub fn header(&self, block_hash: &str) -> Result<Option<BlockHeader>, StoreError> {
+        self.reader().header(block_hash)
+    }
+
+    pub fn block(&self, block_hash: &str) -> Result<Option<BlockProposal>, StoreError> {
+        self.reader().block(block_hash)
+    }
+
+    pub fn block_at(&self, height: u64) -> Result<Option<BlockProposal>, StoreError> {
+        self.reader().block_at(height)
+    }
+
+    pub fn hashes(&self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, String), StoreError>> + '_ {
+        self.reader().hashes(heights)
+    }
+
+    pub fn blocks(&self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, BlockProposal), StoreError>> + '_ {
+        self.reader().blocks(heights)
+    }
+}
+
+/// The lookups, over live columns or a view's.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    heights: &'a dyn ColumnFamily,
+    hashes: &'a dyn ColumnFamily,
+    headers: &'a dyn ColumnFamily,
+    blocks: &'a dyn ColumnFamily,
+    state: &'a dyn ColumnFamily,
+    meta: &'a dyn ColumnFamily,
+    freezer: Option<&'a Freezer>,
+}
+
+impl<'a> Reader<'a> {
+    fn state_base(self) -> Result<Option<StateBase>, StoreError> {
+        let Some(height) = self.meta.get(STATE_HEIGHT)? else {
+            return Ok(None);
+        };
+        let accounts = self
+            .state
+            .iter()
+            .map(|entry| {
+                let (address, account) = entry?;
+                let address = String::from_utf8(address.to_vec())
+                    .map_err(|_| StoreError::Corrupt("account address is not UTF-8".to_string()))?;
+                Ok((address, decode_json(&account)?))
+            })
+            .collect::<Result<_, StoreError>>()?;
+        Ok(Some((decode_height(&height)?, accounts)))
+    }
+
+    /// The height of the state base, 0 for genesis.
+    fn state_height(self) -> Result<u64, StoreError> {
+        self.meta.get(STATE_HEIGHT)?.map_or(Ok(0), |height| decode_height(&height))
+    }
+
+    fn base_validators(self) -> Result<Option<ValidatorSet>, StoreError> {
+        self.meta.get(VALIDATORS)?.map(|validators| decode_json(&validators)).transpose()
+    }
+
+    fn latest_height(self) -> Result<u64, StoreError> {
+        match self.heights.last()? {
+            Some((key, _)) => decode_height(&key),
+            None => Ok(0),
+        }
+    }
+
+    fn hash_at(self, height: u64) -> Result<Option<String>, StoreError> {
+        self.heights.get(&height.to_be_bytes())?.map(|hash| decode_hash(&hash)).transpose()
+    }
+
+    fn height_of(self, block_hash: &str) -> Result<Option<u64>, StoreError> {
+        self.hashes.get(block_hash.as_bytes())?.map(|height| decode_height(&height)).transpose()
+    }
+
+    fn header(self, block_hash: &str) -> Result<Option<BlockHeader>, StoreError> {
+        match self.headers.get(block_hash.as_bytes())? {
+            Some(header) => decode_json(&header).map(Some),
+            None => self.frozen(block_hash, Freezer::header),
+        }
+    }
+
+    fn block(self, block_hash: &str) -> Result<Option<BlockProposal>, StoreError> {
+        match self.blocks.get(block_hash.as_bytes())? {
+            Some(block) => decode_json(&block).map(Some),
+            None => self.frozen(block_hash, Freezer::block),
+        }
+    }
+
+    /// Reads a block's record from the freezer table `read`.
+    fn frozen<T: DeserializeOwned>(
+        self,
+        block_hash: &str,
+        read: fn(&Freezer, u64) -> io::Result<Option<Vec<u8>>>,
+    ) -> Result<Option<T>, StoreError> {
+        let Some(freezer) = self.freezer else {
+            return Ok(None);
+        };
+        match self.height_of(block_hash)? {
+            Some(height) => read(freezer, height)?.map(|record| decode_json(&record)).transpose(),
+            None => Ok(None),
+        }
+    }
+
+    fn block_at(self, height: u64) -> Result<Option<BlockProposal>, StoreError> {
+        match self.hash_at(height)? {
+            Some(hash) => self.block(&hash),
+            None => Ok(None),
+        }
+    }
+
+    fn hashes(self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, String), StoreError>> + 'a {
+        let (start, end) = (heights.start().to_be_bytes(), heights.end().to_be_bytes());
+        self.heights.range(Bound::Included(&start), Bound::Included(&end)).map(|entry| {
+            let (height, hash) = entry?;
+            Ok((decode_height(&height)?, decode_hash(&hash)?))
+        })
+    }
+
+    fn blocks(self, heights: RangeInclusive<u64>) -> impl Iterator<Item = Result<(u64, BlockProposal), StoreError>> + 'a {
+        self.hashes(heights).filter_map(move |entry| match entry {
+            Ok((height, hash)) => self.block(&hash).map(|block| block.map(|block| (height, block))).transpose(),
+            Err(e) => Some(Err(e)),
+        })
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, StoreError> {
+    serde_json::to_vec(value).map_err(|e| StoreError::Corrupt(e.to_string()))
+}
+
+fn decode_height(bytes: &[u8]) -> Result<u64, StoreError> {
+    let bytes: [u8; 8] = bytes.try_into().map_err(|_| StoreError::Corrupt(format!("{} byte height", bytes.len())))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn decode_hash(bytes: &[u8]) -> Result<String, StoreError> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| StoreError::Corrupt("block hash is not UTF-8".to_string()))
+}
+
+fn decode_json<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, StoreError> {
+    serde_json::from_slice(bytes).map_err(|e| StoreError::Corrupt(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(hash: &str) -> BlockProposal {
+        BlockProposal {
+            block_hash: hash.to_string(),
+            state_root: "r".to_string(),
+            zkurl: "zk://prover@example.invalid/block".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "p1".to_string(),
+            timestamp: 0,
+            base_fee: 1,
+            gas_used: 0,
+        }
+    }
+
+    #[test]
+    fn looks_blocks_up_by_height_and_hash() {
+        let store = BlockStore::temporary().unwrap();
+        assert_eq!(store.latest_height().unwrap(), 0);
+        store.insert_finalized(1, "b1", Some(&block("b1"))).unwrap();
+        store.insert_finalized(2, "b2", None).unwrap();
+        store.insert_finalized(3, "b3", Some(&block("b3"))).unwrap();
+
+        assert_eq!(store.latest_height().unwrap(), 3);
+        assert_eq!(store.block_at(1).unwrap().unwrap().block_hash, "b1");
+        assert_eq!((store.height_of("b2").unwrap(), store.block("b2").unwrap().map(|b| b.block_hash)), (Some(2), None));
+        assert_eq!(store.header("b3").unwrap().unwrap().proposer_id, "p1");
+        let hashes: Vec<_> = store.hashes(2..=9).map(Result::unwrap).collect();
+        assert_eq!(hashes, [(2, "b2".to_string()), (3, "b3".to_string())]);
+        let bodies: Vec<_> = store.blocks(1..=3).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(bodies, [1, 3]);
+
+        let gap = store.insert_finalized(5, "b5", None).unwrap_err();
+        assert!(matches!(gap, StoreError::OutOfOrder { expected: 4, got: 5 }));
+    }
+
+    #[test]
+    fn prunes_bodies_outside_the_retention_window() {
+        let dir = std::env::temp_dir().join(format!("cubiq-blockstore-{}", std::process::id()));
+        {
+            let store = BlockStore::open(&dir, Some(2)).unwrap();
+            for height in 1..=3 {
+                let hash = format!("b{}", height);
+                store.insert_finalized(height, &hash, Some(&block(&hash))).unwrap();
+            }
+            // Kept until a state base passes it
+            assert!(store.block("b1").unwrap().is_some());
+            assert!(store.state_base_due(3).unwrap());
+            store.set_state_base(2, &BTreeMap::new(), &ValidatorSet::new()).unwrap();
+            assert!(store.block("b1").unwrap().is_none());
+            assert!(store.header("b1").unwrap().is_some());
+            assert!(!store.state_base_due(2).unwrap());
+            assert_eq!(store.earliest_height().unwrap(), 2);
+            store.insert_finalized(4, "b4", Some(&block("b4"))).unwrap();
+            assert!(store.block("b2").unwrap().is_none());
+            assert_eq!(store.earliest_height().unwrap(), 3);
+        }
+        let reopened = BlockStore::open(&dir, Some(2)).unwrap();
+        assert_eq!((reopened.latest_height().unwrap(), reopened.block_at(3).unwrap().is_some()), (4, true));
+        assert_eq!(reopened.state_base().unwrap().map(|(height, _)| height), Some(2));
+        assert!(reopened.base_validators().unwrap().is_some());
+        drop(reopened);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_to_a_height_above_the_state_base() {
+        let store = BlockStore::temporary().unwrap();
+        for height in 1..=4 {
+            let hash = format!("b{}", height);
+            store.insert_finalized(height, &hash, Some(&block(&hash))).unwrap();
+        }
+        store.set_state_base(2, &BTreeMap::new(), &ValidatorSet::new()).unwrap();
+
+        assert!(matches!(store.truncate(1), Err(StoreError::Pinned { height: 1, pinned: 2 })));
+        assert_eq!(store.truncate(2).unwrap(), 2);
+        assert_eq!(store.latest_height().unwrap(), 2);
+        assert_eq!((store.height_of("b3").unwrap(), store.header("b4").unwrap()), (None, None));
+        store.insert_finalized(3, "c3", Some(&block("c3"))).unwrap();
+        assert_eq!(store.hash_at(3).unwrap().as_deref(), Some("c3"));
+    }
+
+    #[test]
+    fn rolling_back_keeps_the_last_vote() {
+        let store = BlockStore::temporary().unwrap();
+        assert_eq!(store.last_vote().unwrap(), None);
+        store.insert_finalized(1, "b1", Some(&block("b1"))).unwrap();
+        store.log_vote(2, "b2").unwrap();
+        store.truncate(0).unwrap();
+        assert_eq!(store.last_vote().unwrap(), Some((2, "b2".to_string())));
+    }
+
+    #[test]
+    fn reads_through_to_frozen_blocks() {
+        let dir = std::env::temp_dir().join(format!("cubiq-blockstore-freezer-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = BlockStore::temporary().unwrap().with_freezer(&dir, 2).unwrap();
+        for height in 1..=5 {
+            let hash = format!("b{}", height);
+            let block = (height != 2).then(|| block(&hash));
+            store.insert_finalized(height, &hash, block.as_ref()).unwrap();
+        }
+
+        assert_eq!(store.frozen_height(), 3);
+        assert!(store.blocks.get(b"b1").unwrap().is_none());
+        assert_eq!(store.block("b1").unwrap().unwrap().block_hash, "b1");
+        assert_eq!(store.header("b3").unwrap().unwrap().proposer_id, "p1");
+        assert!(store.block("b2").unwrap().is_none());
+        let bodies: Vec<_> = store.blocks(1..=5).map(|entry| entry.unwrap().0).collect();
+        assert_eq!(bodies, [1, 3, 4, 5]);
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.latest_height, stats.frozen_height), (5, 3));
+        assert!(stats.freezer_bytes.unwrap() > 2 * 3 * 16);
+        let blocks = stats.kv.columns.iter().find(|column| column.name == BLOCKS).unwrap();
+        assert_eq!(blocks.keys, Some(2));
+        drop(store);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn views_do_not_see_later_writes() {
+        let store = BlockStore::temporary().unwrap();
+        store.insert_finalized(1, "b1", Some(&block("b1"))).unwrap();
+        let view = store.view().unwrap();
+        store.insert_finalized(2, "b2", Some(&block("b2"))).unwrap();
+
+        assert_eq!((view.latest_height().unwrap(), store.latest_height().unwrap()), (1, 2));
+        assert!(view.block("b2").unwrap().is_none());
+        assert_eq!(view.hashes(1..=2).count(), 1);
+        assert_eq!(store.view().unwrap().block_at(2).unwrap().unwrap().block_hash, "b2");
+    }
+}
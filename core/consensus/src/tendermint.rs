@@ -0,0 +1,395 @@
+//! A Tendermint-style light client of another chain, for the bridge; see
+//! `bridge`.
+//!
+//! The other chain's validators, each an ed25519 key with voting power,
+//! sign its headers, and a header commits to its validators, the next
+//! block's validators and the chain's state as `app_hash`. An update
+//! trusts a header signed by more than two thirds of the power of its
+//! own validators, which must be:
+//!
+//! - for the height after a trusted one, that header's
+//!   `next_validators`;
+//! - for a height further ahead, any set, as long as validators holding
+//!   more than a third of the power of the trusted header's
+//!   `next_validators` signed it too, as Tendermint's skipping
+//!   verification does.
+//!
+//! Trust in a header lapses `trusting_period` seconds after its time, as
+//! the block the update lands in tells it; a client whose every header
+//! has lapsed can't be updated, only replaced by a new one.
+//!
+//! Hashes are SHA-256 Merkle roots, split as Tendermint's are:
+//!
+//! ```text
+//! leaf  = sha256(0x00 || item)
+//! inner = sha256(0x01 || left || right)
+//! root  = sha256("")                                  no items
+//!       | leaf                                        one
+//!       | inner(root(items[..k]), root(items[k..]))   k the largest power of two below the count
+//! ```
+//!
+//! A header's hash is the root over its fields in order, heights and
+//! times as big-endian u64s and strings as their bytes. A validator set's
+//! is the root over each key's 32 bytes followed by its power. Validators
+//! sign the header hash's 32 bytes. The chain's state is the root over
+//! its entries in key order, each `len(key) || key || len(value) ||
+//! value` with big-endian u64 lengths, which a `MembershipProof` proves
+//! an entry of.
+
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::state::Hash;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validator {
+    /// Hex-encoded ed25519 public key
+    pub public_key: String,
+    pub power: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorSet {
+    pub validators: Vec<Validator>,
+}
+
+impl ValidatorSet {
+    pub fn hash(&self) -> Result<Hash, String> {
+        let items = self
+            .validators
+            .iter()
+            .map(|validator| Ok([key_bytes(&validator.public_key)?.as_slice(), &validator.power.to_be_bytes()].concat()))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(merkle_root(&items))
+    }
+
+    fn total_power(&self) -> u64 {
+        self.validators.iter().fold(0, |total, validator| total.saturating_add(validator.power))
+    }
+
+    /// Power of the set's validators with a valid signature of `hash` in
+    /// `commit`, each counted once.
+    fn signed_power(&self, hash: &Hash, commit: &[CommitSig]) -> u64 {
+        let mut counted = HashSet::new();
+        let mut power = 0u64;
+        for sig in commit {
+            let Some(validator) = self.validators.iter().find(|validator| validator.public_key == sig.public_key) else {
+                continue;
+            };
+            if sig.verify(hash) && counted.insert(&validator.public_key) {
+                power = power.saturating_add(validator.power);
+            }
+        }
+        power
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub chain_id: String,
+    pub height: u64,
+    /// Unix seconds
+    pub time: u64,
+    pub app_hash: Hash,
+    pub validators_hash: Hash,
+    pub next_validators_hash: Hash,
+}
+
+impl Header {
+    pub fn hash(&self) -> Hash {
+        let fields = [
+            self.chain_id.as_bytes().to_vec(),
+            self.height.to_be_bytes().to_vec(),
+            self.time.to_be_bytes().to_vec(),
+            self.app_hash.0.to_vec(),
+            self.validators_hash.0.to_vec(),
+            self.next_validators_hash.0.to_vec(),
+        ];
+        merkle_root(&fields)
+    }
+}
+
+/// One validator's signature of a header's hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitSig {
+    /// Hex-encoded ed25519 public key
+    pub public_key: String,
+    /// Hex-encoded ed25519 signature
+    pub signature: String,
+}
+
+impl CommitSig {
+    fn verify(&self, hash: &Hash) -> bool {
+        let Ok(Ok(key)) = key_bytes(&self.public_key).map(|key| VerifyingKey::from_bytes(&key)) else {
+            return false;
+        };
+        let Some(signature) = hex::decode(&self.signature).ok().and_then(|bytes| <[u8; 64]>::try_from(bytes).ok()) else {
+            return false;
+        };
+        key.verify_strict(&hash.0, &Signature::from_bytes(&signature)).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedHeader {
+    pub header: Header,
+    pub commit: Vec<CommitSig>,
+}
+
+/// What the client tracks of the other chain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientState {
+    pub chain_id: String,
+    /// Seconds a header is trusted for after its time
+    pub trusting_period: u64,
+    /// Height of the latest header trusted
+    pub latest_height: u64,
+}
+
+/// What the client keeps of each header it trusts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsensusState {
+    /// Unix seconds
+    pub time: u64,
+    pub app_hash: Hash,
+    pub next_validators_hash: Hash,
+}
+
+impl From<&Header> for ConsensusState {
+    fn from(header: &Header) -> Self {
+        Self { time: header.time, app_hash: header.app_hash, next_validators_hash: header.next_validators_hash }
+    }
+}
+
+/// A header to trust, with the validators that signed it and the height
+/// of the trusted header it is checked against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Update {
+    pub signed_header: SignedHeader,
+    pub validators: ValidatorSet,
+    pub trusted_height: u64,
+    /// The trusted header's `next_validators`; only needed to skip
+    #[serde(default)]
+    pub trusted_validators: ValidatorSet,
+}
+
+impl ClientState {
+    /// Checks `update` against `trusted`, the consensus state at its
+    /// `trusted_height`, at Unix time `now`, and returns the consensus
+    /// state of its header.
+    pub fn verify(&self, trusted: &ConsensusState, update: &Update, now: u64) -> Result<ConsensusState, String> {
+        let header = &update.signed_header.header;
+        if header.chain_id != self.chain_id {
+            return Err(format!("header is for chain {}, not {}", header.chain_id, self.chain_id));
+        }
+        if header.height <= update.trusted_height || header.time <= trusted.time {
+            return Err(format!("header at {} is not after the trusted one at {}", header.height, update.trusted_height));
+        }
+        if now >= trusted.time.saturating_add(self.trusting_period) {
+            return Err(format!("trust in the header at {} lapsed at {}", update.trusted_height, trusted.time.saturating_add(self.trusting_period)));
+        }
+        if update.validators.hash()? != header.validators_hash {
+            return Err("validators do not match the header".to_string());
+        }
+        let hash = header.hash();
+        let commit = &update.signed_header.commit;
+        if header.height == update.trusted_height + 1 {
+            if header.validators_hash != trusted.next_validators_hash {
+                return Err("validators are not those the trusted header named next".to_string());
+            }
+        } else {
+            let trusted_validators = &update.trusted_validators;
+            if trusted_validators.hash()? != trusted.next_validators_hash {
+                return Err("trusted validators do not match the trusted header".to_string());
+            }
+            let (signed, total) = (trusted_validators.signed_power(&hash, commit), trusted_validators.total_power());
+            if (signed as u128) * 3 <= total as u128 {
+                return Err(format!("trusted validators with {} of {} power signed, a third is needed to skip", signed, total));
+            }
+        }
+        let (signed, total) = (update.validators.signed_power(&hash, commit), update.validators.total_power());
+        if (signed as u128) * 3 <= (total as u128) * 2 {
+            return Err(format!("validators with {} of {} power signed, more than two thirds is needed", signed, total));
+        }
+        Ok(ConsensusState::from(header))
+    }
+}
+
+/// Proof that the other chain's state, at some trusted header's
+/// `app_hash`, holds `value` at `key`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipProof {
+    /// The entry's position in key order
+    pub index: u64,
+    /// Entries in the state
+    pub total: u64,
+    /// Sibling hashes from the entry's leaf up
+    pub aunts: Vec<Hash>,
+}
+
+impl MembershipProof {
+    pub fn verify(&self, app_hash: &Hash, key: &[u8], value: &[u8]) -> bool {
+        if self.index >= self.total {
+            return false;
+        }
+        let leaf = leaf_hash(&entry(key, value));
+        root_from_aunts(self.index, self.total, leaf, &self.aunts).is_some_and(|root| root == *app_hash)
+    }
+}
+
+fn key_bytes(public_key: &str) -> Result<[u8; 32], String> {
+    hex::decode(public_key)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("{} is not a hex-encoded ed25519 public key", public_key))
+}
+
+fn entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+    [&(key.len() as u64).to_be_bytes(), key, &(value.len() as u64).to_be_bytes(), value].concat()
+}
+
+fn leaf_hash(item: &[u8]) -> Hash {
+    Hash(Sha256::new().chain_update([0]).chain_update(item).finalize().into())
+}
+
+fn inner_hash(left: &Hash, right: &Hash) -> Hash {
+    Hash(Sha256::new().chain_update([1]).chain_update(left.0).chain_update(right.0).finalize().into())
+}
+
+/// The largest power of two below `count`, which is at least 2.
+fn split_point(count: u64) -> u64 {
+    1 << (63 - (count - 1).leading_zeros())
+}
+
+fn merkle_root(items: &[Vec<u8>]) -> Hash {
+    match items.len() {
+        0 => Hash(Sha256::digest(b"").into()),
+        1 => leaf_hash(&items[0]),
+        count => {
+            let k = split_point(count as u64) as usize;
+            inner_hash(&merkle_root(&items[..k]), &merkle_root(&items[k..]))
+        }
+    }
+}
+
+/// The root over `total` items that `leaf`, at `index`, and `aunts`, the
+/// deepest first, give, or `None` if there are too few or many aunts.
+fn root_from_aunts(index: u64, total: u64, leaf: Hash, aunts: &[Hash]) -> Option<Hash> {
+    if total == 1 {
+        return aunts.is_empty().then_some(leaf);
+    }
+    let (aunt, rest) = aunts.split_last()?;
+    let k = split_point(total);
+    if index < k {
+        Some(inner_hash(&root_from_aunts(index, k, leaf, rest)?, aunt))
+    } else {
+        Some(inner_hash(aunt, &root_from_aunts(index - k, total - k, leaf, rest)?))
+    }
+}
+
+/// Entries as the other chain commits to them, and proofs of each, for
+/// tests standing in for it.
+#[cfg(test)]
+pub(crate) fn commit_entries(entries: &[(Vec<u8>, Vec<u8>)]) -> (Hash, Vec<MembershipProof>) {
+    fn aunts(items: &[Vec<u8>], index: usize) -> Vec<Hash> {
+        if items.len() <= 1 {
+            return vec![];
+        }
+        let k = split_point(items.len() as u64) as usize;
+        let (mut path, sibling) = if index < k {
+            (aunts(&items[..k], index), merkle_root(&items[k..]))
+        } else {
+            (aunts(&items[k..], index - k), merkle_root(&items[..k]))
+        };
+        path.push(sibling);
+        path
+    }
+    let items: Vec<Vec<u8>> = entries.iter().map(|(key, value)| entry(key, value)).collect();
+    let total = items.len() as u64;
+    let proofs = (0..items.len()).map(|index| MembershipProof { index: index as u64, total, aunts: aunts(&items, index) }).collect();
+    (merkle_root(&items), proofs)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    pub(crate) fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    pub(crate) fn validators(seeds: &[u8]) -> ValidatorSet {
+        let validators = seeds.iter().map(|&seed| Validator { public_key: hex::encode(key(seed).verifying_key().to_bytes()), power: 10 }).collect();
+        ValidatorSet { validators }
+    }
+
+    /// A header at `height`, signed by the validators with `signers`'
+    /// seeds, which `seeds` are and will be the validators of.
+    pub(crate) fn signed_header(height: u64, app_hash: Hash, seeds: &[u8], signers: &[u8]) -> SignedHeader {
+        let set = validators(seeds).hash().unwrap();
+        let header = Header {
+            chain_id: "other".to_string(),
+            height,
+            time: 1_000 + height,
+            app_hash,
+            validators_hash: set,
+            next_validators_hash: set,
+        };
+        let hash = header.hash();
+        let commit = signers
+            .iter()
+            .map(|&seed| CommitSig {
+                public_key: hex::encode(key(seed).verifying_key().to_bytes()),
+                signature: hex::encode(key(seed).sign(&hash.0).to_bytes()),
+            })
+            .collect();
+        SignedHeader { header, commit }
+    }
+
+    #[test]
+    fn trusts_headers_signed_by_enough_of_a_trusted_set() {
+        let client = ClientState { chain_id: "other".to_string(), trusting_period: 100, latest_height: 1 };
+        let trusted = ConsensusState::from(&signed_header(1, Hash::ZERO, &[1, 2, 3], &[]).header);
+        let update = |signed_header, seeds: &[u8], trusted_validators| Update {
+            signed_header,
+            validators: validators(seeds),
+            trusted_height: 1,
+            trusted_validators,
+        };
+
+        let next = update(signed_header(2, Hash([2; 32]), &[1, 2, 3], &[1, 2, 3]), &[1, 2, 3], ValidatorSet::default());
+        assert_eq!(client.verify(&trusted, &next, 1_050).unwrap().app_hash, Hash([2; 32]));
+        assert!(client.verify(&trusted, &next, 1_101).unwrap_err().contains("lapsed"));
+        let short = update(signed_header(2, Hash([2; 32]), &[1, 2, 3], &[1, 2]), &[1, 2, 3], ValidatorSet::default());
+        assert!(client.verify(&trusted, &short, 1_050).unwrap_err().contains("20 of 30"));
+        let forged = update(signed_header(2, Hash([2; 32]), &[4, 5, 6], &[4, 5, 6]), &[4, 5, 6], ValidatorSet::default());
+        assert!(client.verify(&trusted, &forged, 1_050).unwrap_err().contains("named next"));
+
+        // Skipping to a new set needs a third of the trusted one
+        let skip = update(signed_header(9, Hash([9; 32]), &[3, 4, 5], &[3, 4, 5]), &[3, 4, 5], validators(&[1, 2, 3]));
+        assert!(client.verify(&trusted, &skip, 1_050).unwrap_err().contains("10 of 30"));
+        let skip = update(signed_header(9, Hash([9; 32]), &[2, 3, 4], &[2, 3, 4]), &[2, 3, 4], validators(&[1, 2, 3]));
+        assert_eq!(client.verify(&trusted, &skip, 1_050).unwrap().time, 1_009);
+        let mut foreign = skip.clone();
+        foreign.signed_header.header.chain_id = "elsewhere".to_string();
+        assert!(client.verify(&trusted, &foreign, 1_050).is_err());
+    }
+
+    #[test]
+    fn proves_every_entry_of_states_of_any_size() {
+        for count in 1..=7u8 {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..count).map(|n| (vec![n], vec![n; 3])).collect();
+            let (root, proofs) = commit_entries(&entries);
+            for (proof, (key, value)) in proofs.iter().zip(&entries) {
+                assert!(proof.verify(&root, key, value), "{:?} of {}", key, count);
+                assert!(!proof.verify(&root, key, b"other"));
+            }
+            let mut short = proofs[0].clone();
+            short.aunts.pop();
+            assert!(count == 1 || !short.verify(&root, &entries[0].0, &entries[0].1));
+        }
+    }
+}
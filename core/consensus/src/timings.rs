@@ -0,0 +1,78 @@
+//! How long the node takes to verify proposals.
+//!
+//! Verification runs from when consensus takes a proposal off its queue to
+//! when the proposal becomes the head, and includes fetching its proof.
+//! Only proposals that verify are timed; rejections often fail fast and
+//! would flatter the figures.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Verified proposals the summary covers, newest last.
+pub const SAMPLES: usize = 64;
+
+/// How long one proposal took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub proof_fetch: Duration,
+    pub verification: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TimingSummary {
+    /// Proposals timed, at most `SAMPLES`
+    pub samples: usize,
+    pub mean_proof_fetch_ms: u64,
+    pub mean_verification_ms: u64,
+    pub max_verification_ms: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct VerificationTimes {
+    recent: VecDeque<Timing>,
+}
+
+impl VerificationTimes {
+    pub fn record(&mut self, timing: Timing) {
+        if self.recent.len() == SAMPLES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(timing);
+    }
+
+    pub fn summary(&self) -> TimingSummary {
+        let samples = self.recent.len();
+        if samples == 0 {
+            return TimingSummary::default();
+        }
+        let mean = |total: Duration| (total / samples as u32).as_millis() as u64;
+        TimingSummary {
+            samples,
+            mean_proof_fetch_ms: mean(self.recent.iter().map(|t| t.proof_fetch).sum()),
+            mean_verification_ms: mean(self.recent.iter().map(|t| t.verification).sum()),
+            max_verification_ms: self.recent.iter().map(|t| t.verification.as_millis() as u64).max().unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_the_newest_samples() {
+        let mut times = VerificationTimes::default();
+        assert_eq!(times.summary(), TimingSummary::default());
+        let ms = Duration::from_millis;
+        times.record(Timing { proof_fetch: ms(1000), verification: ms(5000) });
+        for _ in 1..SAMPLES {
+            times.record(Timing { proof_fetch: ms(10), verification: ms(40) });
+        }
+        assert_eq!(times.summary().max_verification_ms, 5000);
+        times.record(Timing { proof_fetch: ms(10), verification: ms(40) });
+        let summary = times.summary();
+        assert_eq!((summary.samples, summary.mean_proof_fetch_ms, summary.max_verification_ms), (SAMPLES, 10, 40));
+    }
+}
@@ -0,0 +1,318 @@
+//! Finality certificates: what a light client needs to trust a block's
+//! roots without following the chain.
+//!
+//! A certificate is a `Checkpoint`, the votes that finalized its block and
+//! the block's finality proof. Each vote is signed over the block hash
+//! and the checkpoint's roots, gas and transaction count, so validators
+//! holding a supermajority of the snapshot's stake vouch for all of it but
+//! the height; account and inclusion proofs against its roots rest on
+//! them. The proof is checked too, bound to the chain and to the hash,
+//! state root, gas and transaction count as its public inputs, but it
+//! adds nothing yet: the verifier does not check FRI queries or
+//! constraints (see `prover`). Votes decode from the node's `Vote`s and
+//! the proof is a proof bundle's `proof` bytes, as `proof_getByBlock`
+//! answers them.
+
+use cubiq_types::ssz::Ssz;
+use ed25519_dalek::{Signature, VerifyingKey};
+use p3_field::extension::BinomialExtensionField;
+use p3_goldilocks::Goldilocks;
+use prover::domain::{ProofEnvelope, ProofPurpose};
+use prover::{MobileProofVerifier, STARKProof};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::proofs::Hash;
+use crate::validators::ValidatorSnapshot;
+use crate::LightError;
+
+type Envelope = ProofEnvelope<STARKProof<Goldilocks, BinomialExtensionField<Goldilocks, 2>>>;
+
+/// A finalized block's roots, at the finalized height it took.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub block_hash: String,
+    pub state_root: Hash,
+    /// See `consensus::merkle`
+    pub transactions_root: Hash,
+    pub gas_used: u64,
+    pub transaction_count: u32,
+}
+
+impl Checkpoint {
+    /// The checkpoint's SSZ root, as `consensus::ssz::checkpoint` encodes
+    /// it; `None` for a block hash too long to encode.
+    pub fn hash_tree_root(&self) -> Option<[u8; 32]> {
+        let checkpoint = cubiq_types::Checkpoint {
+            height: self.height,
+            block_hash: self.block_hash.as_str().try_into().ok()?,
+            state_root: self.state_root.0,
+            transactions_root: self.transactions_root.0,
+            gas_used: self.gas_used,
+            transaction_count: self.transaction_count,
+        };
+        Some(checkpoint.hash_tree_root())
+    }
+
+    /// The finality proof's public inputs, encoded as
+    /// `zkurl::resolver::PublicInputs::transcript_bytes` does.
+    fn public_inputs(&self) -> Vec<u8> {
+        let state_root = self.state_root.to_string();
+        let mut out = Vec::with_capacity(self.block_hash.len() + state_root.len() + 28);
+        for field in [&self.block_hash, &state_root] {
+            out.extend_from_slice(&(field.len() as u64).to_le_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+        out.extend_from_slice(&self.gas_used.to_le_bytes());
+        out.extend_from_slice(&self.transaction_count.to_le_bytes());
+        out
+    }
+}
+
+/// A validator's vote, as the node encodes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vote {
+    pub block_hash: String,
+    pub voter_id: String,
+    /// What the voter claimed; stake is counted from the snapshot
+    pub stake: u64,
+    pub timestamp: u64,
+    pub state_root: Hash,
+    pub transactions_root: Hash,
+    pub gas_used: u64,
+    pub transaction_count: u32,
+    /// Hex-encoded ed25519 signature by the voter's key
+    pub signature: String,
+}
+
+impl Vote {
+    /// The SSZ signing root the node's votes are signed over; `None` for
+    /// fields too long to sign.
+    fn signing_root(&self) -> Option<[u8; 32]> {
+        let data = cubiq_types::VoteData {
+            block_hash: self.block_hash.as_str().try_into().ok()?,
+            voter_id: self.voter_id.as_str().try_into().ok()?,
+            stake: self.stake,
+            timestamp: self.timestamp,
+            state_root: self.state_root.0,
+            transactions_root: self.transactions_root.0,
+            gas_used: self.gas_used,
+            transaction_count: self.transaction_count,
+        };
+        Some(cubiq_types::signing_root(&data, cubiq_types::VOTE_DOMAIN))
+    }
+
+    /// Whether the vote is for `checkpoint`'s block with its roots, gas
+    /// and transaction count.
+    pub fn is_for(&self, checkpoint: &Checkpoint) -> bool {
+        self.block_hash == checkpoint.block_hash
+            && self.state_root == checkpoint.state_root
+            && self.transactions_root == checkpoint.transactions_root
+            && self.gas_used == checkpoint.gas_used
+            && self.transaction_count == checkpoint.transaction_count
+    }
+
+    /// Whether the holder of `public_key`, hex-encoded, signed the vote.
+    pub fn is_signed_by(&self, public_key: &str) -> bool {
+        let key = hex::decode(public_key).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+        let Some(Ok(key)) = key.map(|key| VerifyingKey::from_bytes(&key)) else {
+            return false;
+        };
+        let Some(signature) = hex::decode(&self.signature).ok().and_then(|bytes| <[u8; 64]>::try_from(bytes).ok()) else {
+            return false;
+        };
+        let Some(root) = self.signing_root() else {
+            return false;
+        };
+        key.verify_strict(&root, &Signature::from_bytes(&signature)).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalityCertificate {
+    pub checkpoint: Checkpoint,
+    pub votes: Vec<Vote>,
+    /// The block's proof bundle's `proof`
+    pub proof: Vec<u8>,
+}
+
+impl FinalityCertificate {
+    /// Checks that validators in `snapshot` holding its supermajority of
+    /// stake voted for the checkpoint, and that the proof is one of the
+    /// block on chain `chain_id`. Only signed votes for the block and its
+    /// roots from validators in the snapshot count, each once.
+    pub fn verify(&self, snapshot: &ValidatorSnapshot, chain_id: u64, verifier: &MobileProofVerifier) -> Result<(), LightError> {
+        let mut voters = HashSet::new();
+        let signed: u64 = self
+            .votes
+            .iter()
+            .filter(|vote| vote.is_for(&self.checkpoint))
+            .filter_map(|vote| Some((vote, snapshot.validator(&vote.voter_id)?)))
+            .filter(|&(vote, validator)| vote.is_signed_by(&validator.public_key) && voters.insert(&validator.node_id))
+            .map(|(_, validator)| validator.stake)
+            .sum();
+        if signed < snapshot.supermajority_threshold {
+            return Err(LightError::InsufficientStake { signed, threshold: snapshot.supermajority_threshold });
+        }
+
+        verify_proof(verifier, &self.proof, &self.checkpoint.public_inputs(), chain_id)
+    }
+}
+
+/// Checks that `proof`, a proof bundle's, is a block finality proof on
+/// chain `chain_id` over `public_inputs`. Unlike the verifier's own
+/// `verify_proof_with_public_inputs`, it needs no JavaScript host to
+/// report a proof that does not decode.
+pub fn verify_proof(verifier: &MobileProofVerifier, proof: &[u8], public_inputs: &[u8], chain_id: u64) -> Result<(), LightError> {
+    let envelope: Envelope =
+        bincode::deserialize(proof).map_err(|e| LightError::InvalidProof(format!("proof does not decode: {}", e)))?;
+    if !verifier.verify_envelope(&envelope, public_inputs, chain_id, ProofPurpose::BlockFinality) {
+        return Err(LightError::InvalidProof("proof is not of this block on this chain".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::validators::SnapshotValidator;
+    use ed25519_dalek::SigningKey;
+    use prover::domain::DomainTag;
+
+    /// Encodes like a `STARKProof` whose queries open nothing, which the
+    /// verifier accepts once it is ground and bound to its inputs.
+    #[derive(Serialize)]
+    struct SampleProof {
+        transcript_seed: [u8; 32],
+        trace_cap: Vec<[u64; 4]>,
+        quotient_chunks_cap: Vec<[u64; 4]>,
+        commit_phase_caps: Vec<Vec<[u64; 4]>>,
+        /// Each query's empty `initial_trees_proof` and `steps`
+        query_proofs: Vec<(Vec<u64>, Vec<u64>)>,
+        final_poly: Vec<[u64; 2]>,
+        pow_witness: u64,
+    }
+
+    pub(crate) fn key(n: u8) -> SigningKey {
+        SigningKey::from_bytes(&[n; 32])
+    }
+
+    /// Validators `v1`, `v2` and `v3` with 40, 30 and 30 stake.
+    pub(crate) fn snapshot(epoch: u64) -> ValidatorSnapshot {
+        let validators = (1..=3)
+            .map(|n| SnapshotValidator {
+                node_id: format!("v{}", n),
+                public_key: hex::encode(key(n).verifying_key().to_bytes()),
+                stake: if n == 1 { 40 } else { 30 },
+            })
+            .collect();
+        ValidatorSnapshot { epoch, validators, supermajority_threshold: 67 }
+    }
+
+    pub(crate) fn proof(checkpoint: &Checkpoint, chain_id: u64) -> Vec<u8> {
+        let domain = DomainTag::new(chain_id, ProofPurpose::BlockFinality);
+        let seed = domain.transcript_seed(&checkpoint.public_inputs());
+        let proof = SampleProof {
+            transcript_seed: seed,
+            trace_cap: vec![[0; 4]],
+            quotient_chunks_cap: vec![[0; 4]],
+            commit_phase_caps: vec![],
+            query_proofs: vec![(vec![], vec![]); prover::VerifierConfig::mobile_optimized().fri_queries],
+            final_poly: vec![],
+            pow_witness: prover::pow::grind_pow_witness(&prover::pow::pow_challenge(&seed, &[&[[0; 4]], &[[0; 4]]], &[]), 16),
+        };
+        bincode::serialize(&ProofEnvelope { domain, proof }).unwrap()
+    }
+
+    /// A certificate for `checkpoint` on chain 7, signed by `voters` as
+    /// the node signs votes.
+    pub(crate) fn certificate(checkpoint: Checkpoint, voters: &[u8]) -> FinalityCertificate {
+        let votes = voters
+            .iter()
+            .map(|&n| {
+                let vote = consensus::Vote {
+                    block_hash: checkpoint.block_hash.clone(),
+                    voter_id: format!("v{}", n),
+                    stake: 0,
+                    timestamp: 3,
+                    state_root: checkpoint.state_root,
+                    transactions_root: checkpoint.transactions_root,
+                    gas_used: checkpoint.gas_used,
+                    transaction_count: checkpoint.transaction_count,
+                    signature: String::new(),
+                };
+                serde_json::from_value(serde_json::to_value(vote.sign(&key(n))).unwrap()).unwrap()
+            })
+            .collect();
+        let proof = proof(&checkpoint, 7);
+        FinalityCertificate { checkpoint, votes, proof }
+    }
+
+    pub(crate) fn checkpoint(height: u64, block_hash: &str) -> Checkpoint {
+        Checkpoint {
+            height,
+            block_hash: block_hash.to_string(),
+            state_root: Hash([height as u8; 32]),
+            transactions_root: Hash::ZERO,
+            gas_used: 21_000,
+            transaction_count: 1,
+        }
+    }
+
+    #[test]
+    fn public_inputs_match_the_provers() {
+        let checkpoint = checkpoint(4, "0xb4");
+        let inputs = zkurl::resolver::PublicInputs {
+            block_hash: "0xb4".to_string(),
+            state_root: checkpoint.state_root.to_string(),
+            gas_used: 21_000,
+            transaction_count: 1,
+        };
+        assert_eq!(checkpoint.public_inputs(), inputs.transcript_bytes());
+
+        let proposal = consensus::BlockProposal {
+            block_hash: "0xb4".to_string(),
+            state_root: checkpoint.state_root.to_string(),
+            zkurl: "zk://prover@example.com/b4".parse().unwrap(),
+            transactions: vec![],
+            proposer_id: "v1".to_string(),
+            timestamp: 3,
+            base_fee: 1,
+            gas_used: 21_000,
+        };
+        let empty = Checkpoint { transaction_count: 0, ..checkpoint };
+        let encoded = consensus::ssz::checkpoint(&proposal, 4).unwrap();
+        assert_eq!(empty.hash_tree_root(), Some(encoded.hash_tree_root()));
+    }
+
+    #[test]
+    fn counts_signed_stake_and_checks_the_proof() {
+        let verifier = MobileProofVerifier::new();
+        let snapshot = snapshot(0);
+        certificate(checkpoint(4, "0xb4"), &[1, 2]).verify(&snapshot, 7, &verifier).unwrap();
+
+        // 70 of 100 stake, but v1 twice, or as v2 with v1's key, is 40
+        let mut twice = certificate(checkpoint(4, "0xb4"), &[1, 1]);
+        assert_eq!(twice.verify(&snapshot, 7, &verifier), Err(LightError::InsufficientStake { signed: 40, threshold: 67 }));
+        twice.votes[1].voter_id = "v2".to_string();
+        assert_eq!(twice.verify(&snapshot, 7, &verifier), Err(LightError::InsufficientStake { signed: 40, threshold: 67 }));
+
+        let mut unsigned = certificate(checkpoint(4, "0xb4"), &[1, 2]);
+        unsigned.votes[1].signature.clear();
+        assert!(matches!(unsigned.verify(&snapshot, 7, &verifier), Err(LightError::InsufficientStake { .. })));
+
+        let foreign = certificate(checkpoint(4, "0xb4"), &[1, 2]);
+        assert!(matches!(foreign.verify(&snapshot, 8, &verifier), Err(LightError::InvalidProof(_))));
+        // The votes vouch for every root, the proof only for some
+        let mut forged = certificate(checkpoint(4, "0xb4"), &[1, 2]);
+        forged.checkpoint.state_root = Hash([9; 32]);
+        assert_eq!(forged.verify(&snapshot, 7, &verifier), Err(LightError::InsufficientStake { signed: 0, threshold: 67 }));
+        let mut forged = certificate(checkpoint(4, "0xb4"), &[1, 2]);
+        forged.checkpoint.transactions_root = Hash([9; 32]);
+        assert_eq!(forged.verify(&snapshot, 7, &verifier), Err(LightError::InsufficientStake { signed: 0, threshold: 67 }));
+        let mut truncated = certificate(checkpoint(4, "0xb4"), &[1, 2]);
+        truncated.proof.truncate(10);
+        assert!(truncated.verify(&snapshot, 7, &verifier).unwrap_err().to_string().contains("does not decode"));
+    }
+}
@@ -0,0 +1,253 @@
+//! Light client for Cubiq: trusts a block's roots once a finality
+//! certificate for it checks out against a validator set snapshot, and
+//! account and transaction proofs once they check out against those
+//! roots, so a wallet can take answers from RPC nodes it does not trust.
+//!
+//! Snapshots come from a source the wallet trusts, the chain spec for
+//! epoch 0 and later ones as validators rotate keys; a snapshot stands
+//! until the next one's epoch (see `validators`). Certificates are checked
+//! against the snapshot of their height's epoch (see `certificate`), and
+//! the newest `KEPT_CHECKPOINTS` certified stay available to prove
+//! against (see `proofs`). `LightClient::save` gives what it knows to
+//! persist between runs. The crate builds for wasm32 as well as native
+//! targets, and does no I/O.
+
+use cubiq_types::proofs::TrieValue;
+use prover::MobileProofVerifier;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use certificate::{Checkpoint, FinalityCertificate};
+use proofs::{AccountState, InclusionProof, StateProof};
+use validators::{epoch, ValidatorSnapshot};
+
+/// Certified checkpoints kept to prove against, dropping the oldest.
+pub const KEPT_CHECKPOINTS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightError {
+    /// A snapshot is malformed; see `ValidatorSnapshot::validate`
+    InvalidSnapshot(String),
+    /// No snapshot is as old as the epoch of the height
+    NoSnapshot { height: u64, epoch: u64 },
+    /// Validly signed votes for the block hold less stake than finalizes it
+    InsufficientStake { signed: u64, threshold: u64 },
+    InvalidProof(String),
+    /// Two blocks are certified at the same height, which a supermajority
+    /// of honest validators never signs
+    Conflict { height: u64, certified: String, conflicting: String },
+    /// No checkpoint certified at the height is kept
+    UnknownCheckpoint(u64),
+    /// An account or inclusion proof does not match the checkpoint's root
+    ProofMismatch(String),
+}
+
+impl fmt::Display for LightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LightError::InvalidSnapshot(what) => write!(f, "invalid validator set snapshot: {}", what),
+            LightError::NoSnapshot { height, epoch } => write!(f, "no validator set snapshot for height {} in epoch {}", height, epoch),
+            LightError::InsufficientStake { signed, threshold } => {
+                write!(f, "votes hold {} stake, {} is needed to finalize", signed, threshold)
+            }
+            LightError::InvalidProof(what) => write!(f, "invalid finality proof: {}", what),
+            LightError::Conflict { height, certified, conflicting } => {
+                write!(f, "height {} has {} certified, but {} is certified as well", height, certified, conflicting)
+            }
+            LightError::UnknownCheckpoint(height) => write!(f, "no certified checkpoint at height {}", height),
+            LightError::ProofMismatch(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl std::error::Error for LightError {}
+
+/// What a `LightClient` knows, to persist between runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedState {
+    pub chain_id: u64,
+    pub epoch_length: u64,
+    pub snapshots: Vec<ValidatorSnapshot>,
+    /// Oldest first
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+pub struct LightClient {
+    chain_id: u64,
+    epoch_length: u64,
+    /// By the epoch they begin at
+    snapshots: BTreeMap<u64, ValidatorSnapshot>,
+    /// By height
+    checkpoints: BTreeMap<u64, Checkpoint>,
+    verifier: MobileProofVerifier,
+}
+
+impl LightClient {
+    /// A client of chain `chain_id`, whose epochs are `epoch_length`
+    /// blocks, trusting `snapshot` from its epoch on.
+    pub fn new(chain_id: u64, epoch_length: u64, snapshot: ValidatorSnapshot) -> Result<Self, LightError> {
+        if epoch_length == 0 {
+            return Err(LightError::InvalidSnapshot("epoch length is zero".to_string()));
+        }
+        let mut client = Self {
+            chain_id,
+            epoch_length,
+            snapshots: BTreeMap::new(),
+            checkpoints: BTreeMap::new(),
+            verifier: MobileProofVerifier::new(),
+        };
+        client.add_snapshot(snapshot)?;
+        Ok(client)
+    }
+
+    /// Replaces the default verifier, held to a phone's budgets.
+    pub fn with_verifier(mut self, verifier: MobileProofVerifier) -> Self {
+        self.verifier = verifier;
+        self
+    }
+
+    /// A client knowing what `saved` holds, trusted as its own earlier
+    /// state: its checkpoints are not checked again.
+    pub fn restore(saved: SavedState) -> Result<Self, LightError> {
+        let mut snapshots = saved.snapshots.into_iter();
+        let first = snapshots.next().ok_or_else(|| LightError::InvalidSnapshot("saved state has no snapshots".to_string()))?;
+        let mut client = Self::new(saved.chain_id, saved.epoch_length, first)?;
+        for snapshot in snapshots {
+            client.add_snapshot(snapshot)?;
+        }
+        client.checkpoints = saved.checkpoints.into_iter().map(|checkpoint| (checkpoint.height, checkpoint)).collect();
+        while client.checkpoints.len() > KEPT_CHECKPOINTS {
+            client.checkpoints.pop_first();
+        }
+        Ok(client)
+    }
+
+    pub fn save(&self) -> SavedState {
+        SavedState {
+            chain_id: self.chain_id,
+            epoch_length: self.epoch_length,
+            snapshots: self.snapshots.values().cloned().collect(),
+            checkpoints: self.checkpoints.values().cloned().collect(),
+        }
+    }
+
+    /// Trusts `snapshot` from its epoch until the next snapshot's,
+    /// replacing any snapshot taken at the same epoch.
+    pub fn add_snapshot(&mut self, snapshot: ValidatorSnapshot) -> Result<(), LightError> {
+        snapshot.validate().map_err(LightError::InvalidSnapshot)?;
+        self.snapshots.insert(snapshot.epoch, snapshot);
+        Ok(())
+    }
+
+    /// The snapshot votes for a block at `height` are checked against.
+    pub fn snapshot_at(&self, height: u64) -> Option<&ValidatorSnapshot> {
+        self.snapshots.range(..=epoch(height, self.epoch_length)).next_back().map(|(_, snapshot)| snapshot)
+    }
+
+    /// Checks `certificate` and, if it holds, keeps its checkpoint to
+    /// prove against.
+    pub fn verify_certificate(&mut self, certificate: &FinalityCertificate) -> Result<(), LightError> {
+        let checkpoint = &certificate.checkpoint;
+        let snapshot = self.snapshot_at(checkpoint.height).ok_or(LightError::NoSnapshot {
+            height: checkpoint.height,
+            epoch: epoch(checkpoint.height, self.epoch_length),
+        })?;
+        certificate.verify(snapshot, self.chain_id, &self.verifier)?;
+        if let Some(certified) = self.checkpoints.get(&checkpoint.height) {
+            if certified.block_hash != checkpoint.block_hash {
+                return Err(LightError::Conflict {
+                    height: checkpoint.height,
+                    certified: certified.block_hash.clone(),
+                    conflicting: checkpoint.block_hash.clone(),
+                });
+            }
+        }
+        self.checkpoints.insert(checkpoint.height, checkpoint.clone());
+        while self.checkpoints.len() > KEPT_CHECKPOINTS {
+            self.checkpoints.pop_first();
+        }
+        Ok(())
+    }
+
+    /// The newest certified checkpoint.
+    pub fn head(&self) -> Option<&Checkpoint> {
+        self.checkpoints.values().next_back()
+    }
+
+    pub fn checkpoint(&self, height: u64) -> Option<&Checkpoint> {
+        self.checkpoints.get(&height)
+    }
+
+    /// Checks that the state at certified `height` holds `account` at
+    /// `address`, or no account when `account` is `None`.
+    pub fn verify_account(&self, height: u64, address: &str, account: Option<&AccountState>, proof: &StateProof) -> Result<(), LightError> {
+        let checkpoint = self.checkpoint(height).ok_or(LightError::UnknownCheckpoint(height))?;
+        if !proof.verify(&checkpoint.state_root, address, account.map(|account| account as &dyn TrieValue)) {
+            return Err(LightError::ProofMismatch(format!("state proof of {} does not match state root {}", address, checkpoint.state_root)));
+        }
+        Ok(())
+    }
+
+    /// Checks that the block certified at `height` holds `tx_hash`; see
+    /// `certificate` for how far its transactions root is trusted.
+    pub fn verify_transaction(&self, height: u64, tx_hash: &str, proof: &InclusionProof) -> Result<(), LightError> {
+        let checkpoint = self.checkpoint(height).ok_or(LightError::UnknownCheckpoint(height))?;
+        if !proof.verify(&checkpoint.transactions_root, tx_hash) {
+            return Err(LightError::ProofMismatch(format!(
+                "inclusion proof of {} does not match transactions root {}",
+                tx_hash, checkpoint.transactions_root
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub mod certificate;
+pub mod proofs;
+pub mod validators;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use certificate::tests::{certificate, checkpoint, key, snapshot};
+    use consensus::state::StateTrie;
+    use proofs::Hash;
+
+    #[test]
+    fn follows_certified_checkpoints_across_snapshots() {
+        let mut client = LightClient::new(7, 10, snapshot(0)).unwrap();
+        assert_eq!(client.head(), None);
+
+        let trie = StateTrie::from_balances(&BTreeMap::from([("0xalice".to_string(), 10)]));
+        let at_four = Checkpoint { state_root: Hash(trie.root().0), ..checkpoint(4, "0xb4") };
+        client.verify_certificate(&certificate(at_four.clone(), &[1, 2])).unwrap();
+        let proof: StateProof = serde_json::from_value(serde_json::to_value(trie.prove("0xalice")).unwrap()).unwrap();
+        let alice = AccountState { balance: 10, ..AccountState::default() };
+        client.verify_account(4, "0xalice", Some(&alice), &proof).unwrap();
+        let rich = AccountState { balance: 11, ..alice };
+        assert!(matches!(client.verify_account(4, "0xalice", Some(&rich), &proof), Err(LightError::ProofMismatch(_))));
+        assert_eq!(client.verify_account(5, "0xalice", None, &proof), Err(LightError::UnknownCheckpoint(5)));
+
+        let conflict = client.verify_certificate(&certificate(checkpoint(4, "0xother"), &[1, 2]));
+        assert!(matches!(conflict, Err(LightError::Conflict { height: 4, .. })));
+
+        // v3's key rotates at epoch 1, so its old signature stops counting
+        let mut rotated = snapshot(1);
+        rotated.validators[2].public_key = hex::encode(key(9).verifying_key().to_bytes());
+        client.add_snapshot(rotated).unwrap();
+        let stale = client.verify_certificate(&certificate(checkpoint(12, "0xb12"), &[1, 3]));
+        assert_eq!(stale, Err(LightError::InsufficientStake { signed: 40, threshold: 67 }));
+        client.verify_certificate(&certificate(checkpoint(12, "0xb12"), &[1, 2])).unwrap();
+        assert_eq!(client.head().map(|head| head.height), Some(12));
+        assert_eq!(client.snapshot_at(9).map(|s| s.epoch), Some(0));
+
+        let saved: SavedState = serde_json::from_str(&serde_json::to_string(&client.save()).unwrap()).unwrap();
+        let restored = LightClient::restore(saved.clone()).unwrap();
+        assert_eq!((restored.head(), restored.snapshots.len()), (client.head(), 2));
+        assert!(LightClient::restore(SavedState { snapshots: vec![], ..saved }).is_err());
+
+        let late = LightClient::new(7, 10, snapshot(1)).unwrap().verify_certificate(&certificate(at_four, &[1, 2]));
+        assert_eq!(late, Err(LightError::NoSnapshot { height: 4, epoch: 0 }));
+    }
+}
@@ -0,0 +1,122 @@
+//! Account and transaction proofs, checked the way the node builds them.
+//!
+//! The proofs and their checks are `cubiq_types::proofs`, which
+//! `consensus::state` and `consensus::merkle` prove with, and decode from
+//! what `state_getProof` and `tx_getInclusionProof` answer. What is left
+//! here is a light client's view of an account, which has the hashes of
+//! a contract's code and storage or a multisig's keys in place of them.
+
+use cubiq_types::proofs::TrieValue;
+pub use cubiq_types::proofs::{Hash, InclusionProof, ProofLeaf, StateProof};
+use serde::{Deserialize, Serialize};
+
+/// An account as the state trie commits to it.
+///
+/// A contract's code and storage, and a multisig's keys, are committed
+/// through their hashes, which a light client takes in place of the code,
+/// storage or keys themselves. A plain account decodes from what
+/// `state_getProof` answers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    pub balance: u64,
+    /// Transactions sent from the account
+    pub nonce: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract: Option<ContractRoots>,
+    /// The 32 bytes of the address a multisig account's keys and
+    /// threshold derive, hex-encoded as a hash is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multisig_address: Option<Hash>,
+    /// See `consensus::bridge::Record::hash`; set on the accounts holding
+    /// bridge records
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge_record: Option<Hash>,
+}
+
+/// What a contract account commits to besides its balance and nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractRoots {
+    /// blake3 of the contract's code
+    pub code_hash: Hash,
+    /// See `consensus::contracts::Contract::storage_root`
+    pub storage_root: Hash,
+}
+
+impl TrieValue for AccountState {
+    fn value_hash(&self) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&self.balance.to_be_bytes()).update(&self.nonce.to_be_bytes());
+        if let Some(contract) = &self.contract {
+            hasher.update(&contract.code_hash.0).update(&contract.storage_root.0);
+        }
+        if let Some(address) = &self.multisig_address {
+            hasher.update(&address.0);
+        }
+        if let Some(record) = &self.bridge_record {
+            hasher.update(&record.0);
+        }
+        Hash(*hasher.finalize().as_bytes())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use consensus::bridge::Record;
+    use consensus::contracts::Contract;
+    use consensus::multisig::Multisig;
+    use consensus::state::{Account, StateTrie};
+    use consensus::{merkle, Transaction};
+    use std::collections::BTreeMap;
+
+    /// Decodes what the node encoded, as a light client gets it.
+    fn convert<T: serde::de::DeserializeOwned>(value: impl Serialize) -> T {
+        serde_json::from_value(serde_json::to_value(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn verifies_account_proofs_the_node_builds() {
+        let contract = Contract { code: vec![0, 97, 115, 109], storage: BTreeMap::from([(vec![1], vec![2, 3])]) };
+        let deployed = Account { balance: 5, nonce: 1, contract: Some(contract.clone()), ..Account::default() };
+        let mut accounts: BTreeMap<String, Account> =
+            (0..20).map(|n| (format!("0x{}", n), Account { balance: n * 10 + 1, ..Account::default() })).collect();
+        accounts.insert("0xcontract".to_string(), deployed);
+        let multisig = Multisig::new(1, ["ab".repeat(32)]).unwrap();
+        accounts.insert("0xshared".to_string(), Account { balance: 9, multisig: Some(multisig.clone()), ..Account::default() });
+        let receipt = Record::Receipt;
+        accounts.insert("0xbridge".to_string(), Account { bridge: Some(receipt.clone()), ..Account::default() });
+        let trie = StateTrie::from_accounts(accounts);
+        let root: Hash = convert(trie.root());
+
+        let plain: AccountState = convert(trie.get("0x7"));
+        assert!(convert::<StateProof>(trie.prove("0x7")).verify(&root, "0x7", Some(&plain)));
+        let richer = AccountState { balance: 72, ..plain.clone() };
+        assert!(!convert::<StateProof>(trie.prove("0x7")).verify(&root, "0x7", Some(&richer)));
+        assert!(!convert::<StateProof>(trie.prove("0x7")).verify(&root, "0x8", Some(&plain)));
+
+        let roots = ContractRoots { code_hash: convert(contract.code_hash()), storage_root: convert(contract.storage_root()) };
+        let contract_account = AccountState { balance: 5, nonce: 1, contract: Some(roots), ..AccountState::default() };
+        assert!(convert::<StateProof>(trie.prove("0xcontract")).verify(&root, "0xcontract", Some(&contract_account)));
+        let shared = AccountState { balance: 9, multisig_address: Some(Hash(*multisig.address().as_bytes())), ..AccountState::default() };
+        assert!(convert::<StateProof>(trie.prove("0xshared")).verify(&root, "0xshared", Some(&shared)));
+        let bridged = AccountState { bridge_record: Some(convert(receipt.hash())), ..AccountState::default() };
+        assert!(convert::<StateProof>(trie.prove("0xbridge")).verify(&root, "0xbridge", Some(&bridged)));
+
+        let absent: StateProof = convert(trie.prove("0xnobody"));
+        assert!(absent.verify(&root, "0xnobody", None));
+        assert!(!absent.verify(&root, "0xnobody", Some(&AccountState::default())));
+    }
+
+    #[test]
+    fn verifies_inclusion_proofs_the_node_builds() {
+        let transactions: Vec<Transaction> =
+            (0..5).map(|n| Transaction { hash: format!("0x{:064x}", n), value: n, ..Transaction::default() }).collect();
+        let root: Hash = convert(merkle::transactions_root(&transactions));
+        for (index, tx) in transactions.iter().enumerate() {
+            let proof: InclusionProof = convert(merkle::prove(&transactions, index).unwrap());
+            assert!(proof.verify(&root, &tx.hash));
+            assert!(!proof.verify(&root, &transactions[(index + 1) % 5].hash));
+            assert!(!InclusionProof { index: index + 8, ..proof }.verify(&root, &tx.hash));
+        }
+    }
+}
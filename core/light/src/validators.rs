@@ -0,0 +1,88 @@
+//! Validator set snapshots a light client checks votes against.
+//!
+//! A snapshot is the validators' keys and stake from the epoch it is
+//! taken at until the next snapshot's: keys only change when a rotation
+//! takes effect at an epoch boundary (see `consensus::rotation`). It
+//! decodes from what `validator_set` answers, with the epoch added.
+
+use serde::{Deserialize, Serialize};
+
+/// The epoch the block at `height` is in, as `consensus::rotation::epoch`
+/// reckons it.
+pub fn epoch(height: u64, epoch_length: u64) -> u64 {
+    height / epoch_length
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotValidator {
+    pub node_id: String,
+    /// Hex-encoded ed25519 public key its votes are signed with
+    pub public_key: String,
+    pub stake: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorSnapshot {
+    /// First epoch the set votes in
+    #[serde(default)]
+    pub epoch: u64,
+    pub validators: Vec<SnapshotValidator>,
+    /// Stake that finalizes a block
+    pub supermajority_threshold: u64,
+}
+
+impl ValidatorSnapshot {
+    pub fn validator(&self, node_id: &str) -> Option<&SnapshotValidator> {
+        self.validators.iter().find(|validator| validator.node_id == node_id)
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.validators.iter().map(|validator| validator.stake).sum()
+    }
+
+    /// Checks that each validator is listed once and that the threshold
+    /// is a majority of the stake, as the chain spec requires, so two
+    /// conflicting blocks can't both reach it.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut seen = std::collections::HashSet::new();
+        if let Some(twice) = self.validators.iter().find(|validator| !seen.insert(&validator.node_id)) {
+            return Err(format!("lists validator {} twice", twice.node_id));
+        }
+        let total = self.validators.iter().map(|validator| validator.stake as u128).sum::<u128>();
+        if (self.supermajority_threshold as u128) * 2 <= total {
+            return Err(format!("threshold {} is not a majority of stake {}", self.supermajority_threshold, total));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decodes_the_validator_set_a_node_answers() {
+        let answer = json!({
+            "validators": [
+                { "node_id": "v1", "stake": 60, "public_key": "aa", "is_active": true, "last_vote_time": 0 },
+                { "node_id": "v2", "stake": 40, "public_key": "bb", "is_active": true, "last_vote_time": 0 },
+            ],
+            "total_stake": 100,
+            "supermajority_threshold": 67,
+            "pending_rotations": [],
+        });
+        let snapshot: ValidatorSnapshot = serde_json::from_value(answer).unwrap();
+        assert_eq!((snapshot.epoch, snapshot.total_stake()), (0, 100));
+        assert_eq!(snapshot.validator("v2").map(|v| v.public_key.as_str()), Some("bb"));
+        snapshot.validate().unwrap();
+
+        let weak = ValidatorSnapshot { supermajority_threshold: 50, ..snapshot.clone() };
+        assert!(weak.validate().unwrap_err().contains("not a majority"));
+        let mut doubled = snapshot.clone();
+        doubled.validators.push(doubled.validators[0].clone());
+        assert_eq!(doubled.validate().unwrap_err(), "lists validator v1 twice");
+        assert_eq!(epoch(99, 100), 0);
+        assert_eq!(epoch(100, 100), 1);
+    }
+}
@@ -0,0 +1,207 @@
+//! Bitswap fetch path for CID zkURLs. A node wants a block by CID from
+//! one provider at a time, and the provider answers with the block or
+//! that it does not have it; Bitswap's want-block exchange, run over the
+//! `BITSWAP_PROTOCOL` request-response protocol as the txsync exchange is.
+//! Go-ipfs's stream-based Bitswap 1.2 is not spoken, so blocks are only
+//! exchanged between Cubiq nodes.
+//!
+//! A block is checked against its CID before the requester sees it, so a
+//! provider answering with other bytes is passed over for the next one.
+
+use async_trait::async_trait;
+use cid::Cid;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::request_response::{Codec, ProtocolName};
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use zkurl::download::DEFAULT_MAX_BODY_BYTES;
+use zkurl::host::verify_block;
+use zkurl::p2p::BlockFetcher;
+use zkurl::ZkURLError;
+
+pub const BITSWAP_PROTOCOL: &str = "/cubiq/bitswap/1.0.0";
+
+/// Largest CID read from a request, in bytes
+const MAX_CID_SIZE: usize = 256;
+
+/// Largest block read, as large as the resolver downloads over HTTP
+const MAX_BLOCK_SIZE: usize = DEFAULT_MAX_BODY_BYTES as usize;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BitswapResponse {
+    Block(Vec<u8>),
+    DontHave,
+}
+
+#[derive(Clone, Debug)]
+pub struct BitswapProtocol;
+
+impl ProtocolName for BitswapProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        BITSWAP_PROTOCOL.as_bytes()
+    }
+}
+
+/// A length-prefixed binary CID, answered by a length-prefixed block
+/// behind a `1`, or a lone `0` for a block the provider does not have.
+#[derive(Clone, Debug, Default)]
+pub struct BitswapCodec;
+
+#[async_trait]
+impl Codec for BitswapCodec {
+    type Protocol = BitswapProtocol;
+    type Request = Cid;
+    type Response = BitswapResponse;
+
+    async fn read_request<T>(&mut self, _: &BitswapProtocol, io: &mut T) -> io::Result<Cid>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_length_prefixed(io, MAX_CID_SIZE).await?;
+        Cid::try_from(bytes.as_slice()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read_response<T>(&mut self, _: &BitswapProtocol, io: &mut T) -> io::Result<BitswapResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut bytes = read_length_prefixed(io, MAX_BLOCK_SIZE + 1).await?;
+        match bytes.first() {
+            Some(1) => {
+                bytes.remove(0);
+                Ok(BitswapResponse::Block(bytes))
+            }
+            Some(0) if bytes.len() == 1 => Ok(BitswapResponse::DontHave),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed bitswap response")),
+        }
+    }
+
+    async fn write_request<T>(&mut self, _: &BitswapProtocol, io: &mut T, cid: Cid) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_length_prefixed(io, cid.to_bytes()).await?;
+        io.close().await
+    }
+
+    async fn write_response<T>(&mut self, _: &BitswapProtocol, io: &mut T, response: BitswapResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = match response {
+            BitswapResponse::Block(block) => [&[1][..], &block].concat(),
+            BitswapResponse::DontHave => vec![0],
+        };
+        write_length_prefixed(io, bytes).await?;
+        io.close().await
+    }
+}
+
+/// A block wanted by the resolver, answered once a provider sends it.
+pub struct BlockRequest {
+    pub cid: Cid,
+    pub providers: Vec<PeerId>,
+    pub reply: oneshot::Sender<Result<Vec<u8>, String>>,
+}
+
+/// Resolver-side handle that asks the swarm's Bitswap behaviour for blocks.
+#[derive(Clone)]
+pub struct BitswapFetcher {
+    requests: mpsc::UnboundedSender<BlockRequest>,
+}
+
+impl BitswapFetcher {
+    pub fn new(requests: mpsc::UnboundedSender<BlockRequest>) -> Self {
+        Self { requests }
+    }
+}
+
+#[async_trait]
+impl BlockFetcher for BitswapFetcher {
+    async fn fetch_block(&self, cid: &Cid, providers: &[String]) -> Result<Vec<u8>, ZkURLError> {
+        let providers = providers.iter().filter_map(|p| PeerId::from_str(p).ok()).collect();
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send(BlockRequest { cid: *cid, providers, reply })
+            .map_err(|_| ZkURLError::Network("networking stopped".to_string()))?;
+        response
+            .await
+            .map_err(|_| ZkURLError::Network("bitswap request dropped".to_string()))?
+            .map_err(ZkURLError::Network)
+    }
+}
+
+/// A block being fetched, from one provider at a time.
+pub struct Fetch {
+    cid: Cid,
+    providers: VecDeque<PeerId>,
+    reply: oneshot::Sender<Result<Vec<u8>, String>>,
+}
+
+impl Fetch {
+    /// Fetches `request.cid` from its providers, or from `peers` when the
+    /// announcement named none.
+    pub fn new(request: BlockRequest, peers: impl IntoIterator<Item = PeerId>) -> Self {
+        let providers = if request.providers.is_empty() {
+            peers.into_iter().collect()
+        } else {
+            request.providers.into()
+        };
+        Self { cid: request.cid, providers, reply: request.reply }
+    }
+
+    pub fn cid(&self) -> Cid {
+        self.cid
+    }
+
+    /// The next provider to ask. Once every one has been asked, tells the
+    /// requester the block was not found and returns `None`.
+    pub fn next_provider(mut self) -> Option<(PeerId, Self)> {
+        match self.providers.pop_front() {
+            Some(peer) => Some((peer, self)),
+            None => {
+                let _ = self.reply.send(Err(format!("no provider sent block {}", self.cid)));
+                None
+            }
+        }
+    }
+
+    /// Answers the requester with `block` if it hashes to the CID, and
+    /// otherwise returns the fetch to ask the next provider.
+    pub fn received(self, block: Vec<u8>) -> Option<Self> {
+        match verify_block(&self.cid, &block) {
+            Ok(true) => {
+                let _ = self.reply.send(Ok(block));
+                None
+            }
+            Ok(false) => Some(self),
+            Err(e) => {
+                let _ = self.reply.send(Err(e.to_string()));
+                None
+            }
+        }
+    }
+}
+
+/// In-memory block store the Bitswap behaviour serves blocks from.
+#[derive(Clone, Default)]
+pub struct MemoryBlockStore {
+    blocks: Arc<Mutex<HashMap<Cid, Vec<u8>>>>,
+}
+
+impl MemoryBlockStore {
+    pub fn get(&self, cid: &Cid) -> Option<Vec<u8>> {
+        self.blocks.lock().expect("Block store lock poisoned").get(cid).cloned()
+    }
+
+    /// Serves `block` to peers that want `cid`. The caller vouches that
+    /// the block hashes to the CID; peers check it themselves.
+    pub fn provide(&self, cid: &Cid, block: Vec<u8>) {
+        self.blocks.lock().expect("Block store lock poisoned").insert(*cid, block);
+    }
+}
@@ -1,54 +1,135 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use futures::StreamExt;
 use libp2p::{
     core::upgrade,
     gossipsub::{
-        Behaviour as Gossipsub, ConfigBuilder, GossipsubEvent, IdentTopic, MessageAuthenticity,
-        MessageId, ValidationMode,
+        Behaviour as Gossipsub, ConfigBuilder, Event as GossipsubEvent, IdentTopic, MessageAuthenticity,
+        ValidationMode,
     },
     identify::{Behaviour as Identify, Config as IdentifyConfig, Event as IdentifyEvent},
-    mdns::{Behaviour as Mdns, Event as MdnsEvent},
-    noise::{AuthenticKeypair, Keypair as NoiseKeypair, NoiseConfig, X25519Spec},
-    swarm::{Swarm, SwarmBuilder, SwarmEvent},
-    tcp::TokioTcpConfig,
-    yamux, Multiaddr, NetworkBehaviour, PeerId, Transport,
+    identity,
+    mdns::{tokio::Behaviour as Mdns, Event as MdnsEvent},
+    noise,
+    multiaddr::Protocol,
+    request_response::{
+        Behaviour as RequestResponse, Config as RequestResponseConfig, Event as RequestResponseEvent,
+        Message as RequestResponseMessage, ProtocolSupport, RequestId,
+    },
+    swarm::{NetworkBehaviour, Swarm, SwarmBuilder, SwarmEvent, THandlerErr},
+    tcp, yamux, PeerId, Transport,
 };
+use cid::Cid;
 use serde::{Deserialize, Serialize};
-use serde_json;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    future::Future,
+    iter,
+    path::PathBuf,
     str::FromStr,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use zkurl::p2p::AvailabilityHints;
+use zkurl::ZkURL;
+
+pub mod bitswap;
+pub mod metrics;
+pub mod peers;
+pub mod redial;
+pub mod txsync;
+
+pub use libp2p::Multiaddr;
+
+use crate::bitswap::{BitswapCodec, BitswapFetcher, BitswapProtocol, BitswapResponse, BlockRequest, Fetch, MemoryBlockStore};
+use crate::metrics::NetworkMetrics;
+use crate::peers::KnownPeer;
+use crate::redial::{Redials, CHECK_INTERVAL};
+use crate::txsync::{TxSync, TxSyncCodec, TxSyncProtocol, TxSyncRequest, TxSyncResponse, MAX_HASHES};
+
+/// Gossip topic of block proposals
+pub const BLOCKS_TOPIC: &str = "cubiq-blocks";
+/// Gossip topic of validator votes
+pub const VOTES_TOPIC: &str = "cubiq-votes";
+/// Gossip topic of proof availability announcements
+pub const PROOFS_TOPIC: &str = "cubiq-proofs";
+/// Gossip topic of finality certificates, all a light node follows
+pub const FINALIZATION_TOPIC: &str = "cubiq-finalization";
+/// Gossip topic of transactions waiting for a block. Nodes subscribed to
+/// it exchange transactions over `txsync` rather than publishing them.
+pub const TRANSACTIONS_TOPIC: &str = "cubiq-transactions";
+
+/// Every gossip topic, the default subscription set
+pub const ALL_TOPICS: [&str; 5] = [BLOCKS_TOPIC, VOTES_TOPIC, PROOFS_TOPIC, FINALIZATION_TOPIC, TRANSACTIONS_TOPIC];
 
 /// Network messages passed between nodes
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum NetworkMessage {
     BlockProposal(BlockProposal),
     Vote(Vote),
-    ProofAnnouncement(String), // zkURL string
-    Finalization(String),      // block hash
+    ProofAnnouncement(ProofAnnouncement),
+    Finalization(FinalityCertificate),
+    Transaction(Transaction),
+}
+
+/// A newly available proof, with hints on where to fetch it from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProofAnnouncement {
+    pub zkurl: ZkURL,
+    #[serde(default)]
+    pub hints: AvailabilityHints,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BlockProposal {
     pub block_hash: String,
     pub state_root: String,
-    pub zkurl: String,
+    pub zkurl: ZkURL,
     pub transactions: Vec<Transaction>,
     pub proposer_id: String,
     pub timestamp: u64,
+    pub base_fee: u64,
+    pub gas_used: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Transaction {
     pub hash: String,
+    pub chain_id: u64,
+    pub nonce: u64,
     pub from: String,
     pub to: String,
     pub value: u64,
-    pub gas_used: u64,
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+    #[serde(default)]
+    pub kind: TxKind,
     pub data: Vec<u8>,
+    pub signature: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cosignatures: Vec<Cosignature>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cosignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxKind {
+    #[default]
+    Transfer,
+    Deploy,
+    Call,
+    CreateMultisig,
+    RotateKey,
+    Bridge,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -57,23 +138,50 @@ pub struct Vote {
     pub voter_id: String,
     pub stake: u64,
     pub timestamp: u64,
+    pub state_root: String,
+    pub transactions_root: String,
+    pub gas_used: u64,
+    pub transaction_count: u32,
     pub signature: String,
 }
 
+/// A finalized block's roots, the votes that finalized it and its proof,
+/// as `chain_getFinalityCertificate` answers them; light nodes check it
+/// with `cubiq_light::certificate::FinalityCertificate::verify`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FinalityCertificate {
+    pub checkpoint: Checkpoint,
+    pub votes: Vec<Vote>,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The finalized height, counting from 1
+    pub height: u64,
+    pub block_hash: String,
+    pub state_root: String,
+    pub transactions_root: String,
+    pub gas_used: u64,
+    pub transaction_count: u32,
+}
+
 #[derive(NetworkBehaviour)]
-#[behaviour(event_process = true)]
+#[behaviour(out_event = "CubiqBehaviourEvent")]
 pub struct CubiqBehaviour {
     gossipsub: Gossipsub,
     mdns: Mdns,
     identify: Identify,
+    bitswap: RequestResponse<BitswapCodec>,
+    tx_sync: RequestResponse<TxSyncCodec>,
 }
 
 impl CubiqBehaviour {
-    pub async fn new(local_key: libp2p::identity::Keypair) -> Result<Self> {
+    pub fn new(local_key: identity::Keypair, topics: &[&str]) -> Result<Self> {
         let gossipsub_config = ConfigBuilder::default()
             .heartbeat_interval(Duration::from_secs(1))
             .validation_mode(ValidationMode::Strict)
-            .max_transmit_size(1 * 1024 * 1024) // 1 MB
+            .max_transmit_size(1024 * 1024) // 1 MB
             .duplicate_cache_time(Duration::from_secs(60))
             .build()
             .expect("Valid gossipsub config");
@@ -81,90 +189,307 @@ impl CubiqBehaviour {
         let mut gossipsub = Gossipsub::new(
             MessageAuthenticity::Signed(local_key.clone()),
             gossipsub_config,
-        )?;
-
-        let topics = [
-            "cubiq-blocks",
-            "cubiq-votes",
-            "cubiq-proofs",
-            "cubiq-finalization",
-        ];
+        )
+        .map_err(|e| anyhow!(e))?;
+
         for topic in topics {
-            gossipsub.subscribe(IdentTopic::new(topic))?;
+            gossipsub.subscribe(&IdentTopic::new(*topic))?;
         }
 
-        let mdns = Mdns::new(Default::default()).await?;
+        let mdns = Mdns::new(Default::default(), local_key.public().to_peer_id())?;
         let identify = Identify::new(IdentifyConfig::new(
             "/cubiq/1.0.0".into(),
             local_key.public(),
         ));
 
+        let bitswap = RequestResponse::new(
+            BitswapCodec,
+            iter::once((BitswapProtocol, ProtocolSupport::Full)),
+            RequestResponseConfig::default(),
+        );
+        let tx_sync = RequestResponse::new(
+            TxSyncCodec,
+            iter::once((TxSyncProtocol, ProtocolSupport::Full)),
+            RequestResponseConfig::default(),
+        );
+
         Ok(Self {
             gossipsub,
             mdns,
             identify,
+            bitswap,
+            tx_sync,
         })
     }
 }
 
+/// Addresses the node listens on and dials at startup.
+#[derive(Clone, Debug)]
+pub struct NetworkConfig {
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Peers dialled at startup, as multiaddrs ending in `/p2p/<peer id>`
+    pub bootnodes: Vec<Multiaddr>,
+    /// Where known peers are loaded from at startup and saved on shutdown
+    pub peer_store: Option<PathBuf>,
+    /// Gossip topics to subscribe to
+    pub topics: Vec<&'static str>,
+    /// Secret of the ed25519 key the node's peer id derives from; a new
+    /// one each start when unset
+    pub identity: Option<[u8; 32]>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            listen_addrs: vec![Multiaddr::from_str("/ip4/0.0.0.0/tcp/0").expect("valid multiaddr")],
+            bootnodes: Vec::new(),
+            peer_store: None,
+            topics: ALL_TOPICS.to_vec(),
+            identity: None,
+        }
+    }
+}
+
+/// Number of peers with an open connection, readable while the event
+/// loop runs
+#[derive(Clone, Debug, Default)]
+pub struct PeerCount(Arc<AtomicUsize>);
+
+impl PeerCount {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A peer as the event loop sees it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerInfo {
+    pub peer_id: String,
+    pub addr: Option<String>,
+    /// Unix timestamp of the last contact, 0 if never reached
+    pub last_seen: u64,
+    pub connected: bool,
+    /// Redialled whenever its connection drops
+    pub is_static: bool,
+    pub banned: bool,
+}
+
+enum PeerCommand {
+    AddStatic(Multiaddr, oneshot::Sender<Result<PeerId>>),
+    RemoveStatic(PeerId, oneshot::Sender<bool>),
+    Ban(PeerId, oneshot::Sender<()>),
+    Unban(PeerId, oneshot::Sender<bool>),
+    List(oneshot::Sender<Vec<PeerInfo>>),
+}
+
+/// Handle for changing the peer set while the event loop runs. Static
+/// peers and bans last until the node restarts.
+#[derive(Clone, Debug)]
+pub struct PeerControl(mpsc::UnboundedSender<PeerCommand>);
+
+impl PeerControl {
+    /// Dials `addr`, which must end in `/p2p/<peer id>`, and keeps
+    /// redialling it; returns the peer id
+    pub async fn add_static(&self, addr: Multiaddr) -> Result<String> {
+        let peer_id = self.request(|reply| PeerCommand::AddStatic(addr, reply)).await??;
+        Ok(peer_id.to_string())
+    }
+
+    /// Stops redialling a static peer; returns whether it was one
+    pub async fn remove_static(&self, peer_id: &str) -> Result<bool> {
+        let peer_id = PeerId::from_str(peer_id)?;
+        self.request(|reply| PeerCommand::RemoveStatic(peer_id, reply)).await
+    }
+
+    /// Disconnects a peer and refuses it from now on
+    pub async fn ban(&self, peer_id: &str) -> Result<()> {
+        let peer_id = PeerId::from_str(peer_id)?;
+        self.request(|reply| PeerCommand::Ban(peer_id, reply)).await
+    }
+
+    /// Returns whether the peer was banned
+    pub async fn unban(&self, peer_id: &str) -> Result<bool> {
+        let peer_id = PeerId::from_str(peer_id)?;
+        self.request(|reply| PeerCommand::Unban(peer_id, reply)).await
+    }
+
+    pub async fn peers(&self) -> Result<Vec<PeerInfo>> {
+        self.request(PeerCommand::List).await
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> PeerCommand) -> Result<T> {
+        let (reply, answer) = oneshot::channel();
+        self.0.send(command(reply)).map_err(|_| anyhow!("networking is not running"))?;
+        answer.await.map_err(|_| anyhow!("networking stopped"))
+    }
+}
+
 /// Main P2P networking structure
 pub struct P2PNetworking {
     pub swarm: Swarm<CubiqBehaviour>,
     pub peer_list: HashMap<PeerId, u64>, // peer id to last seen unix timestamp
+    peer_addrs: HashMap<PeerId, Multiaddr>,
+    peer_store: Option<PathBuf>,
     pub sender: mpsc::UnboundedSender<NetworkMessage>,
     pub receiver: mpsc::UnboundedReceiver<NetworkMessage>,
+    inbound_sender: mpsc::UnboundedSender<NetworkMessage>,
+    inbound: Option<mpsc::UnboundedReceiver<NetworkMessage>>,
+    block_store: MemoryBlockStore,
+    block_sender: mpsc::UnboundedSender<BlockRequest>,
+    block_requests: mpsc::UnboundedReceiver<BlockRequest>,
+    /// Blocks wanted from a provider by each outstanding request
+    block_fetches: HashMap<RequestId, Fetch>,
+    connected: PeerCount,
+    static_peers: HashMap<PeerId, Multiaddr>,
+    redials: Redials,
+    banned: HashSet<PeerId>,
+    control_sender: mpsc::UnboundedSender<PeerCommand>,
+    controls: mpsc::UnboundedReceiver<PeerCommand>,
+    /// Whether transactions are exchanged at all, per the topics
+    relays_transactions: bool,
+    tx_sync: TxSync,
+    /// Hashes asked for by each outstanding `Get`
+    tx_requests: HashMap<RequestId, Vec<String>>,
+    metrics: Option<Arc<NetworkMetrics>>,
 }
 
 impl P2PNetworking {
-    /// Create a new P2P networking instance
+    /// Create a new P2P networking instance listening on an ephemeral port
     pub async fn new() -> Result<Self> {
-        let local_key = libp2p::identity::Keypair::generate_ed25519();
-        let local_peer_id = PeerId::from(local_key.public());
-        println!("Local peer id: {:?}", local_peer_id);
+        Self::with_config(NetworkConfig::default()).await
+    }
 
-        // Noise keys from libp2p identity keys
-        let noise_keys = NoiseKeypair::<X25519Spec>::new()
-            .into_authentic(&local_key)
-            .expect("Noise key generation failed");
+    /// Create a P2P networking instance listening and dialling per `config`
+    pub async fn with_config(config: NetworkConfig) -> Result<Self> {
+        let local_key = match config.identity {
+            Some(secret) => identity::Keypair::ed25519_from_bytes(secret).map_err(|e| anyhow!("network identity: {}", e))?,
+            None => identity::Keypair::generate_ed25519(),
+        };
+        let local_peer_id = PeerId::from(local_key.public());
+        tracing::info!(peer_id = %local_peer_id, "local peer id");
 
-        let transport = TokioTcpConfig::new()
-            .nodelay(true)
+        let transport = tcp::tokio::Transport::new(tcp::Config::default().nodelay(true))
             .upgrade(upgrade::Version::V1)
-            .authenticate(NoiseConfig::xx(noise_keys).into_authenticated())
+            .authenticate(noise::Config::new(&local_key)?)
             .multiplex(yamux::Config::default())
             .boxed();
 
-        let behaviour = CubiqBehaviour::new(local_key.clone()).await?;
+        let relays_transactions = config.topics.contains(&TRANSACTIONS_TOPIC);
+        let behaviour = CubiqBehaviour::new(local_key, &config.topics)?;
 
-        let swarm = SwarmBuilder::with_executor(
-            transport,
-            behaviour,
-            local_peer_id,
-            Box::new(|fut| {
-                tokio::spawn(fut);
-            }),
-        );
+        let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id).build();
 
-        let mut swarm = swarm;
-
-        swarm.listen_on(Multiaddr::from_str("/ip4/0.0.0.0/tcp/0")?)?;
+        for addr in config.listen_addrs {
+            swarm.listen_on(addr)?;
+        }
+        for addr in config.bootnodes {
+            if let Err(e) = swarm.dial(addr.clone()) {
+                tracing::warn!(bootnode = %addr, error = %e, "failed to dial bootnode");
+            }
+        }
+        let known_peers = match &config.peer_store {
+            Some(path) => peers::load(path)?,
+            None => Vec::new(),
+        };
+        let mut peer_list = HashMap::new();
+        let mut peer_addrs = HashMap::new();
+        for peer in known_peers {
+            let (Ok(peer_id), Ok(addr)) = (PeerId::from_str(&peer.peer_id), Multiaddr::from_str(&peer.addr)) else {
+                continue;
+            };
+            if swarm.dial(addr.clone()).is_ok() {
+                peer_list.insert(peer_id, peer.last_seen);
+                peer_addrs.insert(peer_id, addr);
+            }
+        }
 
         let (sender, receiver) = mpsc::unbounded_channel();
+        let (inbound_sender, inbound) = mpsc::unbounded_channel();
+        let (block_sender, block_requests) = mpsc::unbounded_channel();
+        let (control_sender, controls) = mpsc::unbounded_channel();
 
         Ok(Self {
             swarm,
-            peer_list: HashMap::new(),
+            peer_list,
+            peer_addrs,
+            peer_store: config.peer_store,
             sender,
             receiver,
+            inbound_sender,
+            inbound: Some(inbound),
+            block_store: MemoryBlockStore::default(),
+            block_sender,
+            block_requests,
+            block_fetches: HashMap::new(),
+            connected: PeerCount::default(),
+            static_peers: HashMap::new(),
+            redials: Redials::default(),
+            banned: HashSet::new(),
+            control_sender,
+            controls,
+            relays_transactions,
+            tx_sync: TxSync::default(),
+            tx_requests: HashMap::new(),
+            metrics: None,
         })
     }
 
+    /// Counts peers and gossip in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<NetworkMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Messages received from peers. Can be taken once; later calls
+    /// return `None`.
+    pub fn inbound(&mut self) -> Option<mpsc::UnboundedReceiver<NetworkMessage>> {
+        self.inbound.take()
+    }
+
+    /// Handle for `ZkURLResolver::with_block_fetcher`, fetching CID proofs
+    /// over this node's Bitswap behaviour.
+    pub fn block_fetcher(&self) -> BitswapFetcher {
+        BitswapFetcher::new(self.block_sender.clone())
+    }
+
+    /// Store the Bitswap protocol serves blocks from; see
+    /// `MemoryBlockStore::provide`.
+    pub fn block_store(&self) -> MemoryBlockStore {
+        self.block_store.clone()
+    }
+
+    /// This node's peer id, as announcements name providers.
+    pub fn local_peer_id(&self) -> String {
+        self.swarm.local_peer_id().to_string()
+    }
+
+    /// Handle to the number of connected peers
+    pub fn peer_count(&self) -> PeerCount {
+        self.connected.clone()
+    }
+
+    /// Handle for adding, removing and banning peers
+    pub fn peer_control(&self) -> PeerControl {
+        PeerControl(self.control_sender.clone())
+    }
+
     /// Run the event loop for the networking layer
-    pub async fn run(mut self) -> Result<()> {
-        println!("Starting P2P networking event loop");
+    pub async fn run(self) -> Result<()> {
+        self.run_until(std::future::pending()).await
+    }
+
+    /// Run the event loop until `shutdown` completes, then save the peer
+    /// store
+    pub async fn run_until(mut self, shutdown: impl Future<Output = ()>) -> Result<()> {
+        tracing::debug!("starting p2p event loop");
+        tokio::pin!(shutdown);
+        let mut redial_check = tokio::time::interval(CHECK_INTERVAL);
 
         loop {
             tokio::select! {
+                _ = &mut shutdown => {
+                    return self.save_peers();
+                },
                 event = self.swarm.next() => {
                     if let Some(event) = event {
                         self.handle_swarm_event(event).await?;
@@ -173,23 +498,102 @@ impl P2PNetworking {
                 Some(message) = self.receiver.recv() => {
                     self.handle_outgoing_message(message).await?;
                 },
+                Some(request) = self.block_requests.recv() => {
+                    self.handle_block_request(request);
+                },
+                Some(command) = self.controls.recv() => {
+                    self.handle_peer_command(command);
+                },
+                _ = redial_check.tick() => {
+                    self.redial_static_peers();
+                },
+            }
+        }
+    }
+
+    /// Dials the disconnected static peers whose backoff has run out
+    fn redial_static_peers(&mut self) {
+        for peer_id in self.redials.take_due(Instant::now()) {
+            let Some(addr) = self.static_peers.get(&peer_id) else {
+                self.redials.forget(&peer_id);
+                continue;
+            };
+            if let Err(e) = self.swarm.dial(addr.clone()) {
+                tracing::warn!(peer = %peer_id, error = %e, "failed to redial static peer");
+                self.redials.failed(peer_id, Instant::now());
             }
         }
     }
 
+    fn save_peers(&self) -> Result<()> {
+        let Some(path) = &self.peer_store else {
+            return Ok(());
+        };
+        let known: Vec<KnownPeer> = self
+            .peer_addrs
+            .iter()
+            .map(|(peer_id, addr)| KnownPeer {
+                peer_id: peer_id.to_string(),
+                addr: addr.to_string(),
+                last_seen: self.peer_list.get(peer_id).copied().unwrap_or_default(),
+            })
+            .collect();
+        peers::save(path, &known)
+    }
+
     async fn handle_swarm_event(
         &mut self,
-        event: SwarmEvent<<CubiqBehaviour as NetworkBehaviour>::Event, anyhow::Error>,
+        event: SwarmEvent<CubiqBehaviourEvent, THandlerErr<CubiqBehaviour>>,
     ) -> Result<()> {
         use CubiqBehaviourEvent::*;
         match event {
             SwarmEvent::Behaviour(Gossipsub(event)) => self.handle_gossipsub_event(event).await?,
             SwarmEvent::Behaviour(Mdns(event)) => self.handle_mdns_event(event)?,
             SwarmEvent::Behaviour(Identify(event)) => {
-                println!("Identify event: {:?}", event);
+                tracing::trace!(?event, "identify event");
+            }
+            SwarmEvent::Behaviour(Bitswap(event)) => self.handle_bitswap_event(event),
+            SwarmEvent::Behaviour(TxSync(event)) => self.handle_tx_sync_event(event),
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                if num_established.get() == 1 {
+                    let peers = self.connected.0.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(metrics) = &self.metrics {
+                        metrics.observe_peers(peers);
+                    }
+                }
+                if self.banned.contains(&peer_id) {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                    return Ok(());
+                }
+                self.redials.forget(&peer_id);
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                self.peer_list.insert(peer_id, now);
+                if endpoint.is_dialer() {
+                    self.peer_addrs.insert(peer_id, endpoint.get_remote_address().clone());
+                }
+                if num_established.get() == 1 {
+                    self.announce_held(peer_id);
+                }
+            }
+            SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                let peers = self.connected.0.fetch_sub(1, Ordering::Relaxed) - 1;
+                if let Some(metrics) = &self.metrics {
+                    metrics.observe_peers(peers);
+                }
+                self.tx_sync.disconnected(&peer_id);
+                if self.static_peers.contains_key(&peer_id) {
+                    self.redials.lost(peer_id, Instant::now());
+                    self.redial_static_peers();
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. }
+                if self.static_peers.contains_key(&peer_id) && !self.swarm.is_connected(&peer_id) =>
+            {
+                tracing::debug!(peer = %peer_id, %error, "static peer unreachable");
+                self.redials.failed(peer_id, Instant::now());
             }
             SwarmEvent::NewListenAddr { address, .. } => {
-                println!("Listening on {:?}", address);
+                tracing::info!(%address, "listening");
             }
             _ => {}
         }
@@ -203,31 +607,172 @@ impl P2PNetworking {
             message,
         } = event
         {
-            if let Ok(net_msg) = serde_json::from_slice::<NetworkMessage>(&message.data) {
-                println!(
-                    "Received message from {:?}: {:?}",
-                    propagation_source, net_msg
-                );
-                // TODO: forward into consensus or other logic
+            if self.banned.contains(&propagation_source) {
+                return Ok(());
+            }
+            let decoded = serde_json::from_slice::<NetworkMessage>(&message.data);
+            if let Some(metrics) = &self.metrics {
+                match &decoded {
+                    Ok(_) => metrics.observe_message("in", message.topic.as_str(), message.data.len()),
+                    Err(_) => metrics.observe_undecodable(message.topic.as_str()),
+                }
+            }
+            if let Ok(net_msg) = decoded {
+                tracing::trace!(peer = %propagation_source, topic = message.topic.as_str(), msg = ?net_msg, "gossip received");
+                // From peers that still publish transactions
+                if let NetworkMessage::Transaction(tx) = &net_msg {
+                    self.tx_sync.insert(tx.clone());
+                }
+                // Nobody listening is not an error; the message is dropped
+                let _ = self.inbound_sender.send(net_msg);
             } else {
-                eprintln!("Failed to deserialize network message");
+                tracing::debug!(peer = %propagation_source, topic = message.topic.as_str(), "undecodable gossip message");
             }
         }
         Ok(())
     }
 
+    fn handle_block_request(&mut self, request: BlockRequest) {
+        // Without announced providers, ask every peer we know about
+        let peers: Vec<PeerId> = self.peer_list.keys().copied().collect();
+        self.fetch_block(Fetch::new(request, peers));
+    }
+
+    /// Asks the next provider of `fetch` for its block.
+    fn fetch_block(&mut self, fetch: Fetch) {
+        let Some((peer, fetch)) = fetch.next_provider() else {
+            return;
+        };
+        let request_id = self.swarm.behaviour_mut().bitswap.send_request(&peer, fetch.cid());
+        self.block_fetches.insert(request_id, fetch);
+    }
+
+    fn handle_bitswap_event(&mut self, event: RequestResponseEvent<Cid, BitswapResponse>) {
+        match event {
+            RequestResponseEvent::Message { peer, message } => match message {
+                RequestResponseMessage::Request { request, channel, .. } => {
+                    if self.banned.contains(&peer) {
+                        return;
+                    }
+                    let response = match self.block_store.get(&request) {
+                        Some(block) => BitswapResponse::Block(block),
+                        None => BitswapResponse::DontHave,
+                    };
+                    // Fails only if the peer has gone
+                    let _ = self.swarm.behaviour_mut().bitswap.send_response(channel, response);
+                }
+                RequestResponseMessage::Response { request_id, response } => {
+                    let Some(fetch) = self.block_fetches.remove(&request_id) else {
+                        return;
+                    };
+                    let unanswered = match response {
+                        BitswapResponse::Block(block) => fetch.received(block),
+                        BitswapResponse::DontHave => Some(fetch),
+                    };
+                    if let Some(fetch) = unanswered {
+                        tracing::debug!(%peer, cid = %fetch.cid(), "peer did not send the block");
+                        self.fetch_block(fetch);
+                    }
+                }
+            },
+            RequestResponseEvent::OutboundFailure { request_id, .. } => {
+                if let Some(fetch) = self.block_fetches.remove(&request_id) {
+                    self.fetch_block(fetch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_tx_sync_event(&mut self, event: RequestResponseEvent<TxSyncRequest, TxSyncResponse>) {
+        match event {
+            RequestResponseEvent::Message { peer, message } => {
+                if self.banned.contains(&peer) || !self.relays_transactions {
+                    return;
+                }
+                match message {
+                    RequestResponseMessage::Request { request, channel, .. } => {
+                        let response = match request {
+                            TxSyncRequest::Announce(mut hashes) => {
+                                hashes.truncate(MAX_HASHES);
+                                let wanted = self.tx_sync.announced(peer, &hashes);
+                                if !wanted.is_empty() {
+                                    let request = TxSyncRequest::Get(wanted.clone());
+                                    let request_id = self.swarm.behaviour_mut().tx_sync.send_request(&peer, request);
+                                    self.tx_requests.insert(request_id, wanted);
+                                }
+                                TxSyncResponse::Ack
+                            }
+                            TxSyncRequest::Get(mut hashes) => {
+                                hashes.truncate(MAX_HASHES);
+                                TxSyncResponse::Transactions(self.tx_sync.bodies(peer, &hashes))
+                            }
+                        };
+                        // Fails only if the peer has gone
+                        let _ = self.swarm.behaviour_mut().tx_sync.send_response(channel, response);
+                    }
+                    RequestResponseMessage::Response { request_id, response } => {
+                        // Announcements are only acknowledged
+                        let Some(requested) = self.tx_requests.remove(&request_id) else {
+                            return;
+                        };
+                        let txs = match response {
+                            TxSyncResponse::Transactions(txs) => txs,
+                            TxSyncResponse::Ack => vec![],
+                        };
+                        for tx in self.tx_sync.received(peer, &requested, txs) {
+                            // Passed on as gossip would, before the mempool checks it
+                            self.announce(&tx.hash);
+                            let _ = self.inbound_sender.send(NetworkMessage::Transaction(tx));
+                        }
+                    }
+                }
+            }
+            RequestResponseEvent::OutboundFailure { request_id, .. } => {
+                if let Some(requested) = self.tx_requests.remove(&request_id) {
+                    self.tx_sync.failed(&requested);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Announces `hash` to the connected peers not known to hold it.
+    fn announce(&mut self, hash: &str) {
+        let connected: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer in self.tx_sync.lacking(hash, connected) {
+            self.swarm.behaviour_mut().tx_sync.send_request(&peer, TxSyncRequest::Announce(vec![hash.to_string()]));
+        }
+    }
+
+    /// Announces every transaction held to a newly connected peer.
+    fn announce_held(&mut self, peer: PeerId) {
+        if !self.relays_transactions {
+            return;
+        }
+        let held: Vec<String> = self.tx_sync.hashes().cloned().collect();
+        let hashes: Vec<String> = held.into_iter().filter(|hash| !self.tx_sync.lacking(hash, [peer]).is_empty()).collect();
+        for chunk in hashes.chunks(MAX_HASHES) {
+            self.swarm.behaviour_mut().tx_sync.send_request(&peer, TxSyncRequest::Announce(chunk.to_vec()));
+        }
+    }
+
     fn handle_mdns_event(&mut self, event: MdnsEvent) -> Result<()> {
         use MdnsEvent::*;
         match event {
             Discovered(list) => {
-                for (peer_id, _addr) in list {
+                for (peer_id, addr) in list {
+                    if self.banned.contains(&peer_id) {
+                        continue;
+                    }
                     self.swarm
                         .behaviour_mut()
                         .gossipsub
                         .add_explicit_peer(&peer_id);
                     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
                     self.peer_list.insert(peer_id, now);
-                    println!("mDNS Discovered peer: {}", peer_id);
+                    self.peer_addrs.insert(peer_id, addr);
+                    tracing::debug!(peer = %peer_id, "mdns discovered peer");
                 }
             }
             Expired(list) => {
@@ -237,23 +782,103 @@ impl P2PNetworking {
                         .gossipsub
                         .remove_explicit_peer(&peer_id);
                     self.peer_list.remove(&peer_id);
-                    println!("mDNS Expired peer: {}", peer_id);
+                    self.peer_addrs.remove(&peer_id);
+                    tracing::debug!(peer = %peer_id, "mdns peer expired");
                 }
             }
         }
         Ok(())
     }
 
+    fn handle_peer_command(&mut self, command: PeerCommand) {
+        match command {
+            PeerCommand::AddStatic(addr, reply) => {
+                let _ = reply.send(self.add_static(addr));
+            }
+            PeerCommand::RemoveStatic(peer_id, reply) => {
+                let removed = self.static_peers.remove(&peer_id).is_some();
+                self.redials.forget(&peer_id);
+                if removed {
+                    self.swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                }
+                let _ = reply.send(removed);
+            }
+            PeerCommand::Ban(peer_id, reply) => {
+                self.banned.insert(peer_id);
+                self.static_peers.remove(&peer_id);
+                self.redials.forget(&peer_id);
+                self.swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
+                // Forgotten peers are not saved to the peer store either
+                self.peer_list.remove(&peer_id);
+                self.peer_addrs.remove(&peer_id);
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+                let _ = reply.send(());
+            }
+            PeerCommand::Unban(peer_id, reply) => {
+                let _ = reply.send(self.banned.remove(&peer_id));
+            }
+            PeerCommand::List(reply) => {
+                let _ = reply.send(self.peer_infos());
+            }
+        }
+    }
+
+    fn add_static(&mut self, addr: Multiaddr) -> Result<PeerId> {
+        let peer_id = match addr.iter().last() {
+            Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).map_err(|_| anyhow!("{} has an invalid peer id", addr))?,
+            _ => return Err(anyhow!("{} does not end in /p2p/<peer id>", addr)),
+        };
+        if self.banned.contains(&peer_id) {
+            return Err(anyhow!("peer {} is banned", peer_id));
+        }
+        if !self.swarm.is_connected(&peer_id) {
+            self.swarm.dial(addr.clone())?;
+            self.redials.dialed(peer_id, Instant::now());
+        }
+        self.swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
+        self.peer_addrs.insert(peer_id, addr.clone());
+        self.static_peers.insert(peer_id, addr);
+        Ok(peer_id)
+    }
+
+    fn peer_infos(&self) -> Vec<PeerInfo> {
+        let ids: HashSet<&PeerId> =
+            self.peer_list.keys().chain(self.static_peers.keys()).chain(self.banned.iter()).collect();
+        let mut infos: Vec<PeerInfo> = ids
+            .into_iter()
+            .map(|peer_id| PeerInfo {
+                peer_id: peer_id.to_string(),
+                addr: self.peer_addrs.get(peer_id).map(|addr| addr.to_string()),
+                last_seen: self.peer_list.get(peer_id).copied().unwrap_or_default(),
+                connected: self.swarm.is_connected(peer_id),
+                is_static: self.static_peers.contains_key(peer_id),
+                banned: self.banned.contains(peer_id),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        infos
+    }
+
     async fn handle_outgoing_message(&mut self, message: NetworkMessage) -> Result<()> {
+        if let (NetworkMessage::Transaction(tx), true) = (&message, self.relays_transactions) {
+            if self.tx_sync.insert(tx.clone()) {
+                self.announce(&tx.hash);
+            }
+            return Ok(());
+        }
         let topic = match &message {
-            NetworkMessage::BlockProposal(_) => "cubiq-blocks",
-            NetworkMessage::Vote(_) => "cubiq-votes",
-            NetworkMessage::ProofAnnouncement(_) => "cubiq-proofs",
-            NetworkMessage::Finalization(_) => "cubiq-finalization",
+            NetworkMessage::BlockProposal(_) => BLOCKS_TOPIC,
+            NetworkMessage::Vote(_) => VOTES_TOPIC,
+            NetworkMessage::ProofAnnouncement(_) => PROOFS_TOPIC,
+            NetworkMessage::Finalization(_) => FINALIZATION_TOPIC,
+            NetworkMessage::Transaction(_) => TRANSACTIONS_TOPIC,
         };
 
-        let topic = IdentTopic::new(topic);
         let data = serde_json::to_vec(&message)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_message("out", topic, data.len());
+        }
+        let topic = IdentTopic::new(topic);
 
         self.swarm.behaviour_mut().gossipsub.publish(topic, data)?;
 
@@ -267,6 +892,20 @@ pub enum CubiqBehaviourEvent {
     Gossipsub(GossipsubEvent),
     Mdns(MdnsEvent),
     Identify(IdentifyEvent),
+    Bitswap(RequestResponseEvent<Cid, BitswapResponse>),
+    TxSync(RequestResponseEvent<TxSyncRequest, TxSyncResponse>),
+}
+
+impl From<RequestResponseEvent<Cid, BitswapResponse>> for CubiqBehaviourEvent {
+    fn from(event: RequestResponseEvent<Cid, BitswapResponse>) -> Self {
+        CubiqBehaviourEvent::Bitswap(event)
+    }
+}
+
+impl From<RequestResponseEvent<TxSyncRequest, TxSyncResponse>> for CubiqBehaviourEvent {
+    fn from(event: RequestResponseEvent<TxSyncRequest, TxSyncResponse>) -> Self {
+        CubiqBehaviourEvent::TxSync(event)
+    }
 }
 
 impl From<GossipsubEvent> for CubiqBehaviourEvent {
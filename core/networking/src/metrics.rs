@@ -0,0 +1,53 @@
+//! Prometheus metrics for the p2p layer.
+//!
+//! Gossip is counted by topic, which the node's subscriptions bound, and
+//! by direction: `in` for messages peers published, `out` for the node's
+//! own.
+
+use prometheus::{IntCounterVec, IntGauge, Opts, Registry};
+
+pub struct NetworkMetrics {
+    peers: IntGauge,
+    messages: IntCounterVec,
+    message_bytes: IntCounterVec,
+    undecodable_messages: IntCounterVec,
+}
+
+impl NetworkMetrics {
+    /// Creates the networking metrics and registers them with `registry`.
+    pub fn register(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Self {
+            peers: IntGauge::new("network_peers", "Peers with an open connection")?,
+            messages: IntCounterVec::new(
+                Opts::new("network_gossip_messages_total", "Gossip messages by direction and topic"),
+                &["direction", "topic"],
+            )?,
+            message_bytes: IntCounterVec::new(
+                Opts::new("network_gossip_bytes_total", "Encoded size of gossip messages by direction and topic"),
+                &["direction", "topic"],
+            )?,
+            undecodable_messages: IntCounterVec::new(
+                Opts::new("network_undecodable_messages_total", "Gossip messages from peers that failed to decode, by topic"),
+                &["topic"],
+            )?,
+        };
+        registry.register(Box::new(metrics.peers.clone()))?;
+        registry.register(Box::new(metrics.messages.clone()))?;
+        registry.register(Box::new(metrics.message_bytes.clone()))?;
+        registry.register(Box::new(metrics.undecodable_messages.clone()))?;
+        Ok(metrics)
+    }
+
+    pub(crate) fn observe_peers(&self, peers: usize) {
+        self.peers.set(peers as i64);
+    }
+
+    pub(crate) fn observe_message(&self, direction: &str, topic: &str, bytes: usize) {
+        self.messages.with_label_values(&[direction, topic]).inc();
+        self.message_bytes.with_label_values(&[direction, topic]).inc_by(bytes as u64);
+    }
+
+    pub(crate) fn observe_undecodable(&self, topic: &str) {
+        self.undecodable_messages.with_label_values(&[topic]).inc();
+    }
+}
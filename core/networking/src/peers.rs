@@ -0,0 +1,32 @@
+//! Known peers persisted across restarts, so a node can rejoin the network
+//! without relying only on bootnodes and mDNS.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownPeer {
+    pub peer_id: String,
+    /// Multiaddr the peer was last reached on
+    pub addr: String,
+    /// Unix timestamp of the last contact
+    pub last_seen: u64,
+}
+
+/// Reads the peer store; a missing file is an empty store.
+pub fn load(path: &Path) -> Result<Vec<KnownPeer>> {
+    match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).with_context(|| format!("parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Replaces the peer store atomically, so a crash mid-write keeps the
+/// previous copy.
+pub fn save(path: &Path, peers: &[KnownPeer]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(peers)?).with_context(|| format!("writing {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("replacing {}", path.display()))
+}
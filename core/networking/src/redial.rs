@@ -0,0 +1,62 @@
+//! When to redial static peers. A static peer that drops is dialled again
+//! at once; each dial that fails doubles the wait before the next, up to
+//! `MAX_DELAY`, so an unreachable peer is retried for as long as it stays
+//! static without being dialled in a tight loop.
+
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Wait after the first failed redial
+pub const FIRST_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between redials, and how long a dial may stay
+/// outstanding before it is retried
+pub const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// How often due redials are looked for
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct Redials {
+    /// Failed dials in a row and when to dial next, per disconnected peer
+    due: HashMap<PeerId, (u32, Instant)>,
+}
+
+impl Redials {
+    /// `peer` disconnected; it is due now.
+    pub fn lost(&mut self, peer: PeerId, now: Instant) {
+        self.due.insert(peer, (0, now));
+    }
+
+    /// A dial to `peer` was started outside `due`.
+    pub fn dialed(&mut self, peer: PeerId, now: Instant) {
+        let failures = self.due.get(&peer).map_or(0, |(failures, _)| *failures);
+        self.due.insert(peer, (failures, now + MAX_DELAY));
+    }
+
+    /// A dial to `peer` failed; it is due again after the backoff.
+    pub fn failed(&mut self, peer: PeerId, now: Instant) {
+        let failures = self.due.get(&peer).map_or(0, |(failures, _)| *failures).saturating_add(1);
+        self.due.insert(peer, (failures, now + delay(failures)));
+    }
+
+    /// `peer` connected, or is no longer static.
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.due.remove(peer);
+    }
+
+    /// Peers due a redial at `now`, marked as being dialled.
+    pub fn take_due(&mut self, now: Instant) -> Vec<PeerId> {
+        let due: Vec<PeerId> = self.due.iter().filter(|(_, (_, at))| *at <= now).map(|(peer, _)| *peer).collect();
+        for peer in &due {
+            self.dialed(*peer, now);
+        }
+        due
+    }
+}
+
+/// Wait before the next redial after `failures` failed ones in a row.
+fn delay(failures: u32) -> Duration {
+    FIRST_DELAY.saturating_mul(1u32.checked_shl(failures.saturating_sub(1)).unwrap_or(u32::MAX)).min(MAX_DELAY)
+}
@@ -0,0 +1,254 @@
+//! Transaction reconciliation, so each body crosses each link at most
+//! once. A node announces the hashes of transactions it holds to the
+//! peers not known to have them, and a peer requests from the announcer
+//! only the bodies it has not seen. Both run over the `TX_SYNC_PROTOCOL`
+//! request-response protocol.
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
+use libp2p::request_response::{Codec, ProtocolName};
+use libp2p::PeerId;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+
+use crate::Transaction;
+
+pub const TX_SYNC_PROTOCOL: &str = "/cubiq/txsync/1.0.0";
+
+/// Most hashes one announcement or request carries
+pub const MAX_HASHES: usize = 256;
+
+/// Bodies kept to answer requests, oldest forgotten first
+pub const CACHED_TXS: usize = 4096;
+
+/// Hashes remembered per peer as known to it
+pub const KNOWN_PER_PEER: usize = 8192;
+
+/// Largest request or response read, in bytes
+const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TxSyncRequest {
+    /// Hashes of transactions the sender holds
+    Announce(Vec<String>),
+    /// Hashes whose bodies the sender wants
+    Get(Vec<String>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TxSyncResponse {
+    Ack,
+    /// The requested bodies still held, in no particular order
+    Transactions(Vec<Transaction>),
+}
+
+#[derive(Clone, Debug)]
+pub struct TxSyncProtocol;
+
+impl ProtocolName for TxSyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        TX_SYNC_PROTOCOL.as_bytes()
+    }
+}
+
+/// Length-prefixed JSON, like gossip messages.
+#[derive(Clone, Debug, Default)]
+pub struct TxSyncCodec;
+
+#[async_trait]
+impl Codec for TxSyncCodec {
+    type Protocol = TxSyncProtocol;
+    type Request = TxSyncRequest;
+    type Response = TxSyncResponse;
+
+    async fn read_request<T>(&mut self, _: &TxSyncProtocol, io: &mut T) -> io::Result<TxSyncRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &TxSyncProtocol, io: &mut T) -> io::Result<TxSyncResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_json(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &TxSyncProtocol, io: &mut T, request: TxSyncRequest) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &request).await
+    }
+
+    async fn write_response<T>(&mut self, _: &TxSyncProtocol, io: &mut T, response: TxSyncResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_json(io, &response).await
+    }
+}
+
+async fn read_json<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let bytes = read_length_prefixed(io, MAX_MESSAGE_SIZE).await?;
+    serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_json<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    write_length_prefixed(io, serde_json::to_vec(message)?).await?;
+    io.close().await
+}
+
+/// Hashes remembered up to a capacity, the oldest forgotten first.
+#[derive(Debug)]
+struct RecentHashes {
+    hashes: HashSet<String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl RecentHashes {
+    fn new(capacity: usize) -> Self {
+        Self { hashes: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+
+    /// Returns whether `hash` was new.
+    fn insert(&mut self, hash: &str) -> bool {
+        if self.hashes.contains(hash) {
+            return false;
+        }
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        self.hashes.insert(hash.to_string());
+        self.order.push_back(hash.to_string());
+        true
+    }
+
+    fn remove(&mut self, hash: &str) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|held| held != hash);
+        }
+    }
+}
+
+/// Which transactions this node holds, and which each connected peer is
+/// known to hold because it announced, requested or was sent them.
+#[derive(Debug)]
+pub struct TxSync {
+    bodies: HashMap<String, Transaction>,
+    /// Hashes of `bodies`, oldest first
+    order: VecDeque<String>,
+    /// Hashes held or being fetched
+    seen: RecentHashes,
+    known: HashMap<PeerId, RecentHashes>,
+}
+
+impl Default for TxSync {
+    fn default() -> Self {
+        Self {
+            bodies: HashMap::new(),
+            order: VecDeque::new(),
+            seen: RecentHashes::new(2 * CACHED_TXS),
+            known: HashMap::new(),
+        }
+    }
+}
+
+impl TxSync {
+    /// Keeps `tx` to answer requests; returns whether it was new.
+    pub fn insert(&mut self, tx: Transaction) -> bool {
+        self.seen.insert(&tx.hash);
+        if self.bodies.contains_key(&tx.hash) {
+            return false;
+        }
+        if self.order.len() == CACHED_TXS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.bodies.remove(&oldest);
+            }
+        }
+        self.order.push_back(tx.hash.clone());
+        self.bodies.insert(tx.hash.clone(), tx);
+        true
+    }
+
+    /// Hashes of the bodies held, oldest first.
+    pub fn hashes(&self) -> impl Iterator<Item = &String> {
+        self.order.iter()
+    }
+
+    /// Those of `peers` not known to hold `hash`, which from now on are.
+    pub fn lacking(&mut self, hash: &str, peers: impl IntoIterator<Item = PeerId>) -> Vec<PeerId> {
+        peers.into_iter().filter(|peer| self.known_to(*peer).insert(hash)).collect()
+    }
+
+    /// Records that `peer` holds `hashes` and returns those never seen
+    /// here, which are then counted as being fetched.
+    pub fn announced(&mut self, peer: PeerId, hashes: &[String]) -> Vec<String> {
+        let known = self.known_to(peer);
+        for hash in hashes {
+            known.insert(hash);
+        }
+        hashes.iter().filter(|hash| self.seen.insert(hash)).cloned().collect()
+    }
+
+    /// The bodies of `hashes` still held, which `peer` then holds.
+    pub fn bodies(&mut self, peer: PeerId, hashes: &[String]) -> Vec<Transaction> {
+        let found: Vec<Transaction> = hashes.iter().filter_map(|hash| self.bodies.get(hash)).cloned().collect();
+        let known = self.known_to(peer);
+        for tx in &found {
+            known.insert(&tx.hash);
+        }
+        found
+    }
+
+    /// Takes the bodies `peer` sent for `requested`, ignoring any not
+    /// asked for, and returns the new ones. Hashes it did not send may be
+    /// fetched from the next peer announcing them.
+    pub fn received(&mut self, peer: PeerId, requested: &[String], txs: Vec<Transaction>) -> Vec<Transaction> {
+        let mut fresh = vec![];
+        let mut delivered = HashSet::new();
+        for tx in txs {
+            if !requested.contains(&tx.hash) || !delivered.insert(tx.hash.clone()) {
+                continue;
+            }
+            self.known_to(peer).insert(&tx.hash);
+            if self.insert(tx.clone()) {
+                fresh.push(tx);
+            }
+        }
+        self.failed(requested.iter().filter(|hash| !delivered.contains(*hash)));
+        fresh
+    }
+
+    /// Forgets that `hashes` were being fetched, after their request failed.
+    pub fn failed<'a>(&mut self, hashes: impl IntoIterator<Item = &'a String>) {
+        for hash in hashes {
+            if !self.bodies.contains_key(hash) {
+                self.seen.remove(hash);
+            }
+        }
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.known.remove(peer);
+    }
+
+    fn known_to(&mut self, peer: PeerId) -> &mut RecentHashes {
+        self.known.entry(peer).or_insert_with(|| RecentHashes::new(KNOWN_PER_PEER))
+    }
+}
@@ -0,0 +1,101 @@
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::domain::ProofPurpose;
+
+/// Default number of verification outcomes remembered per verifier.
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+/// blake3 digest identifying one verification request.
+pub type CacheKey = [u8; 32];
+
+/// Computes the cache key for a verification of `proof_bytes` over
+/// `public_inputs`. The domain is included because the same bytes can be
+/// valid for one chain/purpose and invalid for another.
+pub fn cache_key(proof_bytes: &[u8], public_inputs: &[u8], domain: Option<(u64, ProofPurpose)>) -> CacheKey {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&(proof_bytes.len() as u64).to_le_bytes());
+    hasher.update(proof_bytes);
+    hasher.update(&(public_inputs.len() as u64).to_le_bytes());
+    hasher.update(public_inputs);
+    if let Some((chain_id, purpose)) = domain {
+        hasher.update(&chain_id.to_le_bytes());
+        hasher.update(&[purpose as u8]);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// LRU map from proof digest to verification outcome, so re-gossiped or
+/// re-fetched proofs are not fully verified twice on the same device.
+pub struct VerificationCache {
+    // None when caching is disabled (capacity 0)
+    entries: Option<Mutex<LruCache<CacheKey, bool>>>,
+}
+
+impl VerificationCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity).map(|cap| Mutex::new(LruCache::new(cap))),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<bool> {
+        let entries = self.entries.as_ref()?;
+        entries.lock().expect("Verification cache lock poisoned").get(key).copied()
+    }
+
+    pub fn insert(&self, key: CacheKey, valid: bool) {
+        if let Some(entries) = &self.entries {
+            entries.lock().expect("Verification cache lock poisoned").put(key, valid);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().expect("Verification cache lock poisoned").clear();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+            .as_ref()
+            .map_or(0, |entries| entries.lock().expect("Verification cache lock poisoned").len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = VerificationCache::new(2);
+        let (a, b, c) = (cache_key(b"a", b"", None), cache_key(b"b", b"", None), cache_key(b"c", b"", None));
+        cache.insert(a, true);
+        cache.insert(b, false);
+        assert_eq!(cache.get(&a), Some(true));
+        cache.insert(c, true);
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn zero_capacity_disables_cache() {
+        let cache = VerificationCache::new(0);
+        let key = cache_key(b"proof", b"inputs", Some((1, ProofPurpose::BlockFinality)));
+        cache.insert(key, true);
+        assert_eq!(cache.get(&key), None);
+    }
+
+    #[test]
+    fn domain_changes_key() {
+        let mainnet = cache_key(b"proof", b"in", Some((1, ProofPurpose::BlockFinality)));
+        let testnet = cache_key(b"proof", b"in", Some((2, ProofPurpose::BlockFinality)));
+        assert_ne!(mainnet, testnet);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use wasm_bindgen::prelude::*;
+
+/// Domain separator prefix absorbed first into every proof transcript.
+pub const TRANSCRIPT_DOMAIN_PREFIX: &[u8] = b"cubiq/proof-transcript/v1";
+
+/// What a proof attests to. Committed into the transcript together with the
+/// chain id, so a proof generated for one purpose can't be reused for another.
+#[cfg_attr(feature = "std", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum ProofPurpose {
+    BlockFinality = 1,
+    StateTransition = 2,
+    EpochAggregate = 3,
+}
+
+/// Chain id and purpose a proof is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainTag {
+    pub chain_id: u64,
+    pub purpose: ProofPurpose,
+}
+
+impl DomainTag {
+    pub fn new(chain_id: u64, purpose: ProofPurpose) -> Self {
+        Self { chain_id, purpose }
+    }
+
+    /// Computes the transcript seed the prover commits to: the domain prefix,
+    /// chain id, purpose and public inputs, hashed in that order.
+    pub fn transcript_seed(&self, public_inputs: &[u8]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(TRANSCRIPT_DOMAIN_PREFIX);
+        hasher.update(&self.chain_id.to_le_bytes());
+        hasher.update(&[self.purpose as u8]);
+        hasher.update(&(public_inputs.len() as u64).to_le_bytes());
+        hasher.update(public_inputs);
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// Serialized form of a proof as it travels between prover and verifier.
+/// The domain tag is carried in the clear so verifiers can reject a foreign
+/// proof before doing any expensive work.
+#[derive(Serialize, Deserialize)]
+pub struct ProofEnvelope<P> {
+    pub domain: DomainTag,
+    pub proof: P,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_differs_across_chains() {
+        let mainnet = DomainTag::new(42161, ProofPurpose::BlockFinality);
+        let testnet = DomainTag::new(421614, ProofPurpose::BlockFinality);
+        assert_ne!(mainnet.transcript_seed(b"inputs"), testnet.transcript_seed(b"inputs"));
+    }
+
+    #[test]
+    fn seed_differs_across_purposes() {
+        let finality = DomainTag::new(1, ProofPurpose::BlockFinality);
+        let transition = DomainTag::new(1, ProofPurpose::StateTransition);
+        assert_ne!(finality.transcript_seed(b"x"), transition.transcript_seed(b"x"));
+    }
+}
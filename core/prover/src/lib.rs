@@ -1,52 +1,131 @@
+//! Without the default `std` feature the crate builds as `no_std + alloc`:
+//! only core verification of already-decoded proofs is available, with no
+//! timing, bincode decoding, or WASM bindings.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
 use p3_field::extension::BinomialExtensionField;
+use p3_field::{BasedVectorSpace, PrimeField64};
 use p3_goldilocks::Goldilocks;
 // use p3_matrix::dense::RowMajorMatrix;
-use js_sys;
 use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "std")]
 use std::time::Instant;
+#[cfg(feature = "std")]
 use wasm_bindgen::prelude::*;
-use web_sys;
+
+#[cfg(feature = "std")]
+use crate::cache::VerificationCache;
+use crate::domain::{DomainTag, ProofEnvelope, ProofPurpose};
+#[cfg(feature = "std")]
+use crate::report::VerificationReport;
 
 type F = Goldilocks;
 type EF = BinomialExtensionField<F, 2>;
 
 /// MobileProofVerifier struct exposed to WASM or native.
-#[wasm_bindgen]
+#[cfg_attr(feature = "std", wasm_bindgen)]
 pub struct MobileProofVerifier {
     config: VerifierConfig,
+    #[cfg(feature = "std")]
+    cache: VerificationCache,
 }
 
-#[wasm_bindgen]
+#[cfg_attr(feature = "std", wasm_bindgen)]
 impl MobileProofVerifier {
-    #[wasm_bindgen(constructor)]
+    #[cfg_attr(feature = "std", wasm_bindgen(constructor))]
     pub fn new() -> Self {
+        Self::with_config(VerifierConfig::mobile_optimized())
+    }
+}
+
+impl Default for MobileProofVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+#[wasm_bindgen]
+impl MobileProofVerifier {
+    /// Create a verifier remembering up to `capacity` verification outcomes.
+    /// A capacity of 0 disables caching.
+    #[wasm_bindgen]
+    pub fn with_cache_capacity(capacity: usize) -> MobileProofVerifier {
         Self {
             config: VerifierConfig::mobile_optimized(),
+            cache: VerificationCache::new(capacity),
         }
     }
 
+    /// Forget all cached verification outcomes.
+    #[wasm_bindgen]
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
     /// Verify proof bytes, return true if valid, false otherwise.
     ///
     /// Errors are converted to `JsValue` for WASM consumers.
     #[wasm_bindgen]
     pub fn verify_proof(&self, proof_bytes: &[u8]) -> Result<bool, JsValue> {
+        let key = cache::cache_key(proof_bytes, &[], None);
+        if let Some(valid) = self.cache.get(&key) {
+            return Ok(valid);
+        }
+
         let proof = self
             .deserialize_proof(proof_bytes)
             .map_err(|e| JsValue::from_str(&format!("Failed to deserialize proof: {}", e)))?;
 
-        let start = Instant::now();
-        let result = self.verify_stark_proof(&proof);
-        let elapsed = start.elapsed();
+        let valid = self.timed(|| self.verify_stark_proof(&proof));
+        self.cache.insert(key, valid);
+        Ok(valid)
+    }
 
-        if elapsed.as_millis() > self.config.max_verification_time_ms {
-            web_sys::console::warn_1(&JsValue::from_str(&format!(
-                "Warning: Proof verification took {}ms (target < {}ms)",
-                elapsed.as_millis(),
-                self.config.max_verification_time_ms
-            )));
+    /// Verify an enveloped proof bound to `chain_id` and `purpose` over the
+    /// given public inputs.
+    ///
+    /// Returns false if the envelope was produced for another chain or
+    /// purpose, or if the transcript seed does not commit to these inputs.
+    #[wasm_bindgen]
+    pub fn verify_proof_with_public_inputs(
+        &self,
+        envelope_bytes: &[u8],
+        public_inputs: &[u8],
+        chain_id: u64,
+        purpose: ProofPurpose,
+    ) -> Result<bool, JsValue> {
+        let key = cache::cache_key(envelope_bytes, public_inputs, Some((chain_id, purpose)));
+        if let Some(valid) = self.cache.get(&key) {
+            return Ok(valid);
         }
 
-        Ok(result)
+        let envelope = self
+            .deserialize_envelope(envelope_bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to deserialize proof envelope: {}", e)))?;
+
+        let valid = self.timed(|| self.verify_envelope(&envelope, public_inputs, chain_id, purpose));
+        self.cache.insert(key, valid);
+        Ok(valid)
+    }
+
+    /// Same as `verify_proof_detailed`, with the report serialized to JSON
+    /// for WASM consumers.
+    #[wasm_bindgen(js_name = verify_proof_detailed)]
+    pub fn verify_proof_detailed_json(
+        &self,
+        envelope_bytes: &[u8],
+        public_inputs: &[u8],
+        chain_id: u64,
+        purpose: ProofPurpose,
+    ) -> Result<String, JsValue> {
+        self.verify_proof_detailed(envelope_bytes, public_inputs, chain_id, purpose)
+            .to_json()
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize report: {}", e)))
     }
 
     /// Returns current memory usage in bytes (approximation for WASM).
@@ -60,25 +139,136 @@ impl MobileProofVerifier {
     }
 }
 
+#[cfg(feature = "std")]
 impl MobileProofVerifier {
+    /// Verify an enveloped proof like `verify_proof_with_public_inputs`,
+    /// timing each stage. Bypasses the result cache so timings are real.
+    ///
+    /// A proof that fails to deserialize yields an invalid report with only
+    /// the `deserialize` stage recorded.
+    pub fn verify_proof_detailed(
+        &self,
+        envelope_bytes: &[u8],
+        public_inputs: &[u8],
+        chain_id: u64,
+        purpose: ProofPurpose,
+    ) -> VerificationReport {
+        let mut report = VerificationReport::new(envelope_bytes.len(), self.config.soundness_bits());
+
+        let start = Instant::now();
+        let envelope = self.deserialize_envelope(envelope_bytes);
+        report.record("deserialize", start.elapsed());
+        let Ok(envelope) = envelope else {
+            return report;
+        };
+
+        let proof = &envelope.proof;
+        report.valid = report.time("domain", || self.verify_domain(&envelope, public_inputs, chain_id, purpose))
+            && report.time("structure", || self.verify_proof_structure(proof))
+            && report.time("proof_of_work", || self.verify_proof_of_work(proof))
+            && report.time("fri", || self.verify_fri_consistency(proof))
+            && report.time("constraints", || self.verify_constraints(proof));
+        report
+    }
+
     // Deserialize proof from binary form using bincode
     fn deserialize_proof(&self, bytes: &[u8]) -> Result<STARKProof<F, EF>, bincode::Error> {
         bincode::deserialize(bytes)
     }
 
+    fn deserialize_envelope(
+        &self,
+        bytes: &[u8],
+    ) -> Result<ProofEnvelope<STARKProof<F, EF>>, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+
+    // Run verification and warn if it exceeded the mobile time budget
+    fn timed(&self, verify: impl FnOnce() -> bool) -> bool {
+        let start = Instant::now();
+        let result = verify();
+        let elapsed = start.elapsed();
+
+        if elapsed.as_millis() > self.config.max_verification_time_ms {
+            web_sys::console::warn_1(&JsValue::from_str(&format!(
+                "Warning: Proof verification took {}ms (target < {}ms)",
+                elapsed.as_millis(),
+                self.config.max_verification_time_ms
+            )));
+        }
+
+        result
+    }
+}
+
+impl MobileProofVerifier {
+    /// A verifier held to `config`'s budgets and soundness parameters.
+    pub fn with_config(config: VerifierConfig) -> Self {
+        Self {
+            config,
+            #[cfg(feature = "std")]
+            cache: VerificationCache::new(cache::DEFAULT_CACHE_CAPACITY),
+        }
+    }
+
+    /// Verify an already-decoded envelope against the expected domain and
+    /// public inputs. This is the entry point for `no_std` hosts, which
+    /// decode proofs with their own serde format.
+    pub fn verify_envelope(
+        &self,
+        envelope: &ProofEnvelope<STARKProof<F, EF>>,
+        public_inputs: &[u8],
+        chain_id: u64,
+        purpose: ProofPurpose,
+    ) -> bool {
+        self.verify_domain(envelope, public_inputs, chain_id, purpose)
+            && self.verify_stark_proof(&envelope.proof)
+    }
+
+    // The envelope must be tagged for this chain/purpose and its transcript
+    // must commit to the expected public inputs
+    fn verify_domain(
+        &self,
+        envelope: &ProofEnvelope<STARKProof<F, EF>>,
+        public_inputs: &[u8],
+        chain_id: u64,
+        purpose: ProofPurpose,
+    ) -> bool {
+        let expected = DomainTag::new(chain_id, purpose);
+        envelope.domain == expected
+            && envelope.proof.transcript_seed == expected.transcript_seed(public_inputs)
+    }
+
     // Mobile-optimized STARK verification (simplified)
     fn verify_stark_proof(&self, proof: &STARKProof<F, EF>) -> bool {
         if !self.verify_proof_structure(proof) {
             return false;
         }
+        if !self.verify_proof_of_work(proof) {
+            return false;
+        }
         if !self.verify_fri_consistency(proof) {
             return false;
         }
         self.verify_constraints(proof)
     }
 
+    // Every commitment is present and the proof opens exactly the queries
+    // the config counts on for its soundness
     fn verify_proof_structure(&self, proof: &STARKProof<F, EF>) -> bool {
-        !proof.trace_cap.is_empty() && !proof.quotient_chunks_cap.is_empty()
+        !proof.trace_cap.is_empty()
+            && !proof.quotient_chunks_cap.is_empty()
+            && proof.fri_proof.query_proofs.len() == self.config.fri_queries
+    }
+
+    // Grinding check: the prover must have spent ~2^pow_bits work on the
+    // transcript and every commitment
+    fn verify_proof_of_work(&self, proof: &STARKProof<F, EF>) -> bool {
+        pow::check_pow_witness(
+            &proof.pow_challenge(),
+            proof.fri_proof.pow_witness,
+            self.config.pow_bits,
+        )
     }
 
     fn verify_fri_consistency(&self, _proof: &STARKProof<F, EF>) -> bool {
@@ -94,16 +284,41 @@ impl MobileProofVerifier {
 
 #[derive(Serialize, Deserialize)]
 pub struct STARKProof<F, EF> {
+    /// Fiat-Shamir transcript seed, see `DomainTag::transcript_seed`
+    transcript_seed: [u8; 32],
     trace_cap: Vec<[F; 4]>,
     quotient_chunks_cap: Vec<[F; 4]>,
     fri_proof: FRIProof<F, EF>,
 }
 
+impl STARKProof<F, EF> {
+    /// What `pow_witness` is ground against, see `pow::pow_challenge`.
+    fn pow_challenge(&self) -> [u8; 32] {
+        let words = |cap: &[[F; 4]]| -> Vec<[u64; 4]> { cap.iter().map(|digest| digest.map(|x| x.as_canonical_u64())).collect() };
+        let mut caps = alloc::vec![words(&self.trace_cap), words(&self.quotient_chunks_cap)];
+        caps.extend(self.fri_proof.commit_phase_caps.iter().map(|cap| words(cap)));
+        let caps: Vec<&[[u64; 4]]> = caps.iter().map(Vec::as_slice).collect();
+        let final_poly: Vec<[u64; 2]> = self
+            .fri_proof
+            .final_poly
+            .iter()
+            .map(|coefficient| {
+                let base: &[F] = coefficient.as_basis_coefficients_slice();
+                [base[0].as_canonical_u64(), base[1].as_canonical_u64()]
+            })
+            .collect();
+        pow::pow_challenge(&self.transcript_seed, &caps, &final_poly)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct FRIProof<F, EF> {
     commit_phase_caps: Vec<Vec<[F; 4]>>,
     query_proofs: Vec<QueryProof<F, EF>>,
     final_poly: Vec<EF>,
+    /// Proof-of-work nonce ground against the transcript seed and every
+    /// commitment, see `STARKProof::pow_challenge`
+    pow_witness: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -118,10 +333,16 @@ pub struct FRIQueryStep<F, EF> {
     opening_proof: Vec<[F; 4]>,
 }
 
-struct VerifierConfig {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifierConfig {
     pub max_memory_mb: usize,
     pub max_verification_time_ms: u128,
+    /// FRI queries every proof must open
     pub fri_queries: usize,
+    /// log2 of the FRI blowup factor
+    pub log_blowup: usize,
+    /// Proof-of-work grinding bits required from the prover
+    pub pow_bits: u32,
 }
 
 impl VerifierConfig {
@@ -130,23 +351,51 @@ impl VerifierConfig {
             max_memory_mb: 400,
             max_verification_time_ms: 500,
             fri_queries: 80,
+            log_blowup: 1,
+            pow_bits: 16,
         }
     }
+
+    /// For nodes verifying every block: a server's memory and time
+    /// budget, with the same queries and grinding as `mobile_optimized`,
+    /// since proofs open exactly `fri_queries` queries.
+    pub fn full_node() -> Self {
+        Self {
+            max_memory_mb: 4096,
+            max_verification_time_ms: 5_000,
+            ..Self::mobile_optimized()
+        }
+    }
+
+    /// Conjectured soundness of these parameters in bits: each query
+    /// contributes `log_blowup` bits, and grinding adds `pow_bits` on top.
+    /// Proofs only reach it once their queries are checked, which
+    /// `verify_fri_consistency` does not do yet.
+    pub fn soundness_bits(&self) -> usize {
+        self.fri_queries * self.log_blowup + self.pow_bits as usize
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use p3_field::PrimeCharacteristicRing;
+
+    fn queries(n: usize) -> Vec<QueryProof<F, EF>> {
+        (0..n).map(|_| QueryProof { initial_trees_proof: vec![], steps: vec![] }).collect()
+    }
 
     #[test]
     fn basic_proof_structure_check() {
         let proof = STARKProof {
+            transcript_seed: [0u8; 32],
             trace_cap: vec![[Goldilocks::ZERO; 4]; 1],
             quotient_chunks_cap: vec![[Goldilocks::ZERO; 4]; 1],
             fri_proof: FRIProof {
                 commit_phase_caps: vec![vec![[Goldilocks::ZERO; 4]]],
-                query_proofs: vec![],
+                query_proofs: queries(80),
                 final_poly: vec![],
+                pow_witness: 0,
             },
         };
         let verifier = MobileProofVerifier::new();
@@ -156,15 +405,147 @@ mod tests {
     #[test]
     fn empty_proof_structure_check() {
         let proof = STARKProof {
+            transcript_seed: [0u8; 32],
             trace_cap: vec![],
             quotient_chunks_cap: vec![],
             fri_proof: FRIProof {
                 commit_phase_caps: vec![],
                 query_proofs: vec![],
                 final_poly: vec![],
+                pow_witness: 0,
             },
         };
         let verifier = MobileProofVerifier::new();
         assert!(!verifier.verify_proof_structure(&proof));
     }
+
+    fn sample_proof(seed: [u8; 32], commit_phase_caps: Vec<Vec<[F; 4]>>) -> STARKProof<F, EF> {
+        let mut proof = STARKProof::<F, EF> {
+            transcript_seed: seed,
+            trace_cap: vec![[Goldilocks::ZERO; 4]; 1],
+            quotient_chunks_cap: vec![[Goldilocks::ZERO; 4]; 1],
+            fri_proof: FRIProof {
+                commit_phase_caps,
+                query_proofs: queries(VerifierConfig::mobile_optimized().fri_queries),
+                final_poly: vec![],
+                pow_witness: 0,
+            },
+        };
+        let pow_bits = VerifierConfig::mobile_optimized().pow_bits;
+        proof.fri_proof.pow_witness = pow::grind_pow_witness(&proof.pow_challenge(), pow_bits);
+        proof
+    }
+
+    #[test]
+    fn proof_with_too_few_queries_is_rejected() {
+        let mut proof = sample_proof([1u8; 32], vec![]);
+        proof.fri_proof.query_proofs.pop();
+        let verifier = MobileProofVerifier::new();
+        assert!(!verifier.verify_proof_structure(&proof));
+        assert!(!verifier.verify_stark_proof(&proof));
+    }
+
+    #[test]
+    fn mobile_config_soundness() {
+        assert_eq!(VerifierConfig::mobile_optimized().soundness_bits(), 96);
+        assert_eq!(VerifierConfig::full_node().soundness_bits(), 96);
+    }
+
+    fn sample_envelope(domain: DomainTag, public_inputs: &[u8]) -> Vec<u8> {
+        let proof = sample_proof(domain.transcript_seed(public_inputs), vec![]);
+        bincode::serialize(&ProofEnvelope { domain, proof }).unwrap()
+    }
+
+    #[test]
+    fn envelope_verifies_for_matching_domain() {
+        let bytes = sample_envelope(DomainTag::new(42161, ProofPurpose::BlockFinality), b"block");
+        let verifier = MobileProofVerifier::new();
+        assert!(verifier
+            .verify_proof_with_public_inputs(&bytes, b"block", 42161, ProofPurpose::BlockFinality)
+            .unwrap());
+    }
+
+    #[test]
+    fn proof_without_grinding_is_rejected() {
+        let mut proof = sample_proof([1u8; 32], vec![]);
+        let challenge = proof.pow_challenge();
+        proof.fri_proof.pow_witness = (0..).find(|w| !pow::check_pow_witness(&challenge, *w, 16)).unwrap();
+        let verifier = MobileProofVerifier::new();
+        assert!(!verifier.verify_stark_proof(&proof));
+    }
+
+    #[test]
+    fn grinding_binds_the_commitments() {
+        let layer = vec![vec![[Goldilocks::ONE; 4]]];
+        let mut proof = sample_proof([1u8; 32], layer);
+        let verifier = MobileProofVerifier::new();
+        assert!(verifier.verify_stark_proof(&proof));
+        // A prover picking another commitment after grinding must grind
+        // again; only one in 2^16 choices keeps the old witness valid
+        let witness = proof.fri_proof.pow_witness;
+        let before = proof.pow_challenge();
+        for n in 2u64.. {
+            proof.fri_proof.commit_phase_caps[0][0][0] = Goldilocks::from_u64(n);
+            if !pow::check_pow_witness(&proof.pow_challenge(), witness, 16) {
+                break;
+            }
+        }
+        assert_ne!(proof.pow_challenge(), before);
+        assert!(!verifier.verify_stark_proof(&proof));
+    }
+
+    #[test]
+    fn repeated_verification_hits_cache() {
+        let bytes = sample_envelope(DomainTag::new(1, ProofPurpose::BlockFinality), b"block");
+        let verifier = MobileProofVerifier::with_cache_capacity(4);
+        for _ in 0..3 {
+            assert!(verifier
+                .verify_proof_with_public_inputs(&bytes, b"block", 1, ProofPurpose::BlockFinality)
+                .unwrap());
+        }
+        assert_eq!(verifier.cache.len(), 1);
+        verifier.clear_cache();
+        assert_eq!(verifier.cache.len(), 0);
+    }
+
+    #[test]
+    fn detailed_report_covers_all_stages() {
+        let bytes = sample_envelope(DomainTag::new(1, ProofPurpose::BlockFinality), b"block");
+        let verifier = MobileProofVerifier::new();
+        let report = verifier.verify_proof_detailed(&bytes, b"block", 1, ProofPurpose::BlockFinality);
+        assert!(report.valid);
+        assert_eq!(report.proof_size, bytes.len());
+        assert_eq!(report.fri_queries_checked, 0);
+        assert_eq!(report.soundness_bits, VerifierConfig::mobile_optimized().soundness_bits());
+        assert_eq!(report.stage_timings.len(), 6);
+    }
+
+    #[test]
+    fn detailed_report_stops_at_failed_stage() {
+        let bytes = sample_envelope(DomainTag::new(2, ProofPurpose::BlockFinality), b"block");
+        let verifier = MobileProofVerifier::new();
+        let report = verifier.verify_proof_detailed(&bytes, b"block", 1, ProofPurpose::BlockFinality);
+        assert!(!report.valid);
+        let last = report.stage_timings.last().unwrap();
+        assert_eq!(last.stage, "domain");
+    }
+
+    #[test]
+    fn envelope_rejected_on_other_chain_or_inputs() {
+        let bytes = sample_envelope(DomainTag::new(421614, ProofPurpose::BlockFinality), b"block");
+        let verifier = MobileProofVerifier::new();
+        assert!(!verifier
+            .verify_proof_with_public_inputs(&bytes, b"block", 42161, ProofPurpose::BlockFinality)
+            .unwrap());
+        assert!(!verifier
+            .verify_proof_with_public_inputs(&bytes, b"other", 421614, ProofPurpose::BlockFinality)
+            .unwrap());
+    }
 }
+
+#[cfg(feature = "std")]
+pub mod cache;
+pub mod domain;
+pub mod pow;
+#[cfg(feature = "std")]
+pub mod report;
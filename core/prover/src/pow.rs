@@ -0,0 +1,93 @@
+/// Domain separator for the proof-of-work grinding challenge.
+const POW_DOMAIN: &[u8] = b"cubiq/fri-pow/v2";
+
+/// The challenge a proof is ground against: the transcript seed, then
+/// every commitment the prover made (the trace cap, the quotient cap and
+/// each FRI commit-phase cap, in order) and the FRI final polynomial, as
+/// canonical field elements with each list prefixed by its length.
+/// Grinding after the commitments are fixed means a prover can't grind
+/// once and then pick them freely.
+pub fn pow_challenge(transcript_seed: &[u8; 32], caps: &[&[[u64; 4]]], final_poly: &[[u64; 2]]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(POW_DOMAIN);
+    hasher.update(transcript_seed);
+    hasher.update(&(caps.len() as u64).to_le_bytes());
+    for cap in caps {
+        hasher.update(&(cap.len() as u64).to_le_bytes());
+        for word in cap.iter().flatten() {
+            hasher.update(&word.to_le_bytes());
+        }
+    }
+    hasher.update(&(final_poly.len() as u64).to_le_bytes());
+    for word in final_poly.iter().flatten() {
+        hasher.update(&word.to_le_bytes());
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// Hashes the challenge together with a candidate witness.
+fn pow_digest(challenge: &[u8; 32], witness: u64) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(POW_DOMAIN);
+    hasher.update(challenge);
+    hasher.update(&witness.to_le_bytes());
+    *hasher.finalize().as_bytes()
+}
+
+/// Number of leading zero bits in a digest.
+fn leading_zero_bits(digest: &[u8; 32]) -> u32 {
+    let mut zeros = 0;
+    for byte in digest {
+        if *byte == 0 {
+            zeros += 8;
+        } else {
+            zeros += byte.leading_zeros();
+            break;
+        }
+    }
+    zeros
+}
+
+/// Checks that `witness` grinds `challenge` to at least `bits` leading zero bits.
+pub fn check_pow_witness(challenge: &[u8; 32], witness: u64, bits: u32) -> bool {
+    bits == 0 || leading_zero_bits(&pow_digest(challenge, witness)) >= bits
+}
+
+/// Prover-side search for a witness satisfying `check_pow_witness`.
+///
+/// Expected cost is 2^bits hashes, so keep `bits` modest on constrained devices.
+pub fn grind_pow_witness(challenge: &[u8; 32], bits: u32) -> u64 {
+    (0..=u64::MAX)
+        .find(|witness| check_pow_witness(challenge, *witness, bits))
+        .expect("Proof-of-work search space exhausted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_witness_passes_check() {
+        let seed = [7u8; 32];
+        let witness = grind_pow_witness(&seed, 8);
+        assert!(check_pow_witness(&seed, witness, 8));
+    }
+
+    #[test]
+    fn challenge_commits_to_every_word() {
+        let seed = [7u8; 32];
+        let (trace, quotient, layer) = ([[1, 2, 3, 4]], [[5, 6, 7, 8]], [[9, 9, 9, 9]]);
+        let challenge = pow_challenge(&seed, &[&trace, &quotient, &layer], &[[1, 2]]);
+        assert_ne!(challenge, pow_challenge(&seed, &[&trace, &quotient, &[[9, 9, 9, 0]]], &[[1, 2]]));
+        assert_ne!(challenge, pow_challenge(&seed, &[&trace, &quotient], &[[1, 2]]));
+        assert_ne!(challenge, pow_challenge(&seed, &[&trace, &quotient, &layer], &[[1, 3]]));
+        assert_ne!(challenge, pow_challenge(&[8u8; 32], &[&trace, &quotient, &layer], &[[1, 2]]));
+        // Moving a digest from one cap to the next changes the framing
+        assert_ne!(pow_challenge(&seed, &[&[[1, 2, 3, 4], [5, 6, 7, 8]], &[]], &[]), pow_challenge(&seed, &[&trace, &quotient], &[]));
+    }
+
+    #[test]
+    fn zero_bits_always_passes() {
+        assert!(check_pow_witness(&[0u8; 32], 12345, 0));
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Time spent in one verification stage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub micros: u64,
+}
+
+/// Diagnostics for a single verification, so operators can see where mobile
+/// verification time goes. Stages after the first failing one are not run
+/// and therefore have no timing entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub valid: bool,
+    pub stage_timings: Vec<StageTiming>,
+    /// FRI queries whose openings were checked; 0 until
+    /// `verify_fri_consistency` checks any
+    pub fri_queries_checked: usize,
+    pub proof_size: usize,
+    /// Conjectured soundness of the verifier's parameters, see
+    /// `VerifierConfig::soundness_bits`
+    pub soundness_bits: usize,
+}
+
+impl VerificationReport {
+    pub fn new(proof_size: usize, soundness_bits: usize) -> Self {
+        Self {
+            valid: false,
+            stage_timings: vec![],
+            fri_queries_checked: 0,
+            proof_size,
+            soundness_bits,
+        }
+    }
+
+    /// Records an externally measured stage duration.
+    pub fn record(&mut self, stage: &str, elapsed: Duration) {
+        self.stage_timings.push(StageTiming {
+            stage: stage.to_string(),
+            micros: elapsed.as_micros() as u64,
+        });
+    }
+
+    /// Runs and times a stage, returning its outcome.
+    pub fn time(&mut self, stage: &str, check: impl FnOnce() -> bool) -> bool {
+        let start = Instant::now();
+        let passed = check();
+        self.record(stage, start.elapsed());
+        passed
+    }
+
+    /// Total time across all recorded stages, in microseconds.
+    pub fn total_micros(&self) -> u64 {
+        self.stage_timings.iter().map(|t| t.micros).sum()
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_stages_in_order() {
+        let mut report = VerificationReport::new(128, 96);
+        assert!(report.time("structure", || true));
+        assert!(!report.time("fri", || false));
+        let stages: Vec<_> = report.stage_timings.iter().map(|t| t.stage.as_str()).collect();
+        assert_eq!(stages, vec!["structure", "fri"]);
+    }
+
+    #[test]
+    fn json_roundtrip() {
+        let mut report = VerificationReport::new(64, 96);
+        report.record("deserialize", Duration::from_micros(42));
+        let parsed: VerificationReport = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.total_micros(), 42);
+    }
+}
@@ -0,0 +1,252 @@
+//! Cubiq's consensus types in SSZ (see `ssz`): the wire format and
+//! merkleization other languages implement to check what validators
+//! sign and what light clients prove, with no dependency on the node.
+//!
+//! The node converts its own types to these; see `consensus::ssz`. Hashes
+//! and keys the node carries as hex are `Vector[byte, 32]` here; block
+//! hashes and node ids, which are free-form, are `List[byte,
+//! MAX_STRING_BYTES]`.
+//!
+//! A vote is signed over `signing_root(&vote.data, VOTE_DOMAIN)`, which
+//! commits to the vote's fields and, through the domain, to what it is.
+//! Its fields carry the block's roots, gas and transaction count, so the
+//! votes finalizing a block vouch for all of its `Checkpoint` but the
+//! height, which is only known once the block is finalized.
+//!
+//! Account and transaction proofs, checked against a block's roots, are
+//! in `proofs`.
+
+use ssz::{ByteList, Chunk, List, Ssz};
+
+/// Longest block hash, node id or zkURL, in bytes.
+pub const MAX_STRING_BYTES: usize = 1024;
+
+/// Most validators a set holds.
+pub const MAX_VALIDATORS: usize = 1 << 16;
+
+/// Domain of a vote's signing root.
+pub const VOTE_DOMAIN: &[u8] = b"cubiq-vote-v3";
+
+pub type Text = ByteList<MAX_STRING_BYTES>;
+
+/// ```text
+/// class SigningData(Container):
+///     object_root: Bytes32
+///     domain: Bytes32
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningData {
+    pub object_root: [u8; 32],
+    /// The domain's tag, right-padded with zeros
+    pub domain: [u8; 32],
+}
+
+container!(SigningData { object_root: [u8; 32], domain: [u8; 32] });
+
+/// What a signature over `object` in `domain` signs: the root of
+/// `SigningData`, so no signature in one domain passes for one in
+/// another.
+pub fn signing_root<T: Ssz>(object: &T, domain: &[u8]) -> Chunk {
+    assert!(domain.len() <= 32, "domain tags are at most 32 bytes");
+    let mut padded = [0; 32];
+    padded[..domain.len()].copy_from_slice(domain);
+    SigningData { object_root: object.hash_tree_root(), domain: padded }.hash_tree_root()
+}
+
+/// ```text
+/// class VoteData(Container):
+///     block_hash: List[byte, MAX_STRING_BYTES]
+///     voter_id: List[byte, MAX_STRING_BYTES]
+///     stake: uint64
+///     timestamp: uint64
+///     state_root: Bytes32
+///     transactions_root: Bytes32
+///     gas_used: uint64
+///     transaction_count: uint32
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoteData {
+    pub block_hash: Text,
+    pub voter_id: Text,
+    pub stake: u64,
+    pub timestamp: u64,
+    pub state_root: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub gas_used: u64,
+    pub transaction_count: u32,
+}
+
+container!(VoteData {
+    block_hash: Text,
+    voter_id: Text,
+    stake: u64,
+    timestamp: u64,
+    state_root: [u8; 32],
+    transactions_root: [u8; 32],
+    gas_used: u64,
+    transaction_count: u32,
+});
+
+/// ```text
+/// class Vote(Container):
+///     data: VoteData
+///     signature: List[byte, 64]  # ed25519, empty when unsigned
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vote {
+    pub data: VoteData,
+    pub signature: ByteList<64>,
+}
+
+container!(Vote { data: VoteData, signature: ByteList<64> });
+
+/// ```text
+/// class Validator(Container):
+///     node_id: List[byte, MAX_STRING_BYTES]
+///     public_key: Bytes32  # ed25519
+///     stake: uint64
+///     is_active: boolean
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validator {
+    pub node_id: Text,
+    pub public_key: [u8; 32],
+    pub stake: u64,
+    pub is_active: bool,
+}
+
+container!(Validator { node_id: Text, public_key: [u8; 32], stake: u64, is_active: bool });
+
+/// ```text
+/// class ValidatorSet(Container):
+///     validators: List[Validator, MAX_VALIDATORS]  # by node id
+///     total_stake: uint64
+///     supermajority_threshold: uint64
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatorSet {
+    pub validators: List<Validator, MAX_VALIDATORS>,
+    pub total_stake: u64,
+    pub supermajority_threshold: u64,
+}
+
+container!(ValidatorSet { validators: List<Validator, MAX_VALIDATORS>, total_stake: u64, supermajority_threshold: u64 });
+
+/// ```text
+/// class Checkpoint(Container):
+///     height: uint64
+///     block_hash: List[byte, MAX_STRING_BYTES]
+///     state_root: Bytes32
+///     transactions_root: Bytes32
+///     gas_used: uint64
+///     transaction_count: uint32
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub block_hash: Text,
+    pub state_root: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub gas_used: u64,
+    pub transaction_count: u32,
+}
+
+container!(Checkpoint {
+    height: u64,
+    block_hash: Text,
+    state_root: [u8; 32],
+    transactions_root: [u8; 32],
+    gas_used: u64,
+    transaction_count: u32,
+});
+
+/// A block, with its transactions committed through their root.
+///
+/// ```text
+/// class BlockHeader(Container):
+///     block_hash: List[byte, MAX_STRING_BYTES]
+///     state_root: Bytes32
+///     transactions_root: Bytes32
+///     transaction_count: uint32
+///     zkurl: List[byte, MAX_STRING_BYTES]
+///     proposer_id: List[byte, MAX_STRING_BYTES]
+///     timestamp: uint64
+///     base_fee: uint64
+///     gas_used: uint64
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub block_hash: Text,
+    pub state_root: [u8; 32],
+    pub transactions_root: [u8; 32],
+    pub transaction_count: u32,
+    pub zkurl: Text,
+    pub proposer_id: Text,
+    pub timestamp: u64,
+    pub base_fee: u64,
+    pub gas_used: u64,
+}
+
+container!(BlockHeader {
+    block_hash: Text,
+    state_root: [u8; 32],
+    transactions_root: [u8; 32],
+    transaction_count: u32,
+    zkurl: Text,
+    proposer_id: Text,
+    timestamp: u64,
+    base_fee: u64,
+    gas_used: u64,
+});
+
+/// Decodes `N` bytes from hex, with or without a `0x` prefix.
+pub fn from_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.len() != N * 2 || !s.is_ascii() {
+        return None;
+    }
+    let mut out = [0; N];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+pub mod proofs;
+pub mod ssz;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn votes_round_trip_and_sign_per_domain() {
+        let data = VoteData {
+            block_hash: "0xb1".try_into().unwrap(),
+            voter_id: "v1".try_into().unwrap(),
+            stake: 10,
+            timestamp: 5,
+            state_root: [2; 32],
+            transactions_root: [3; 32],
+            gas_used: 21_000,
+            transaction_count: 1,
+        };
+        let vote = Vote { data: data.clone(), signature: List::new(vec![7; 64]).unwrap() };
+        assert_eq!(Vote::decode(&vote.encode()), Ok(vote.clone()));
+        assert_eq!(vote.data.hash_tree_root(), data.hash_tree_root());
+
+        let root = signing_root(&data, VOTE_DOMAIN);
+        assert_ne!(root, signing_root(&data, b"cubiq-other-v1"));
+        let later = VoteData { timestamp: 6, ..data.clone() };
+        assert_ne!(root, signing_root(&later, VOTE_DOMAIN));
+        let other_state = VoteData { state_root: [4; 32], ..data };
+        assert_ne!(root, signing_root(&other_state, VOTE_DOMAIN));
+
+        let validator = Validator { node_id: "v1".try_into().unwrap(), public_key: [1; 32], stake: 10, is_active: true };
+        let set = ValidatorSet { validators: List::new(vec![validator]).unwrap(), total_stake: 10, supermajority_threshold: 7 };
+        assert_eq!(ValidatorSet::decode(&set.encode()), Ok(set));
+        assert!(Text::try_from("x".repeat(MAX_STRING_BYTES + 1).as_str()).is_err());
+        assert_eq!(from_hex::<2>("0xabcd"), Some([0xab, 0xcd]));
+        assert_eq!(from_hex::<2>("abc"), None);
+    }
+}
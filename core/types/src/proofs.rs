@@ -0,0 +1,166 @@
+//! Account and transaction proofs, and the hashing the node's state trie
+//! and transaction trees are built with, so a light client checks proofs
+//! with the code the node proves them with.
+//!
+//! Both trees hash with blake3, domain separated:
+//!
+//! ```text
+//! leaf   = blake3(0x00 || key || value)    # state trie
+//!        | blake3(0x00 || tx_hash)         # transactions
+//! branch = blake3(0x01 || left || right)
+//! ```
+//!
+//! See `consensus::state` for the trie and what an account's value is,
+//! and `consensus::merkle` for a block's transactions.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// State keys are 256 bits, so no account proof is deeper.
+pub const MAX_DEPTH: usize = 256;
+
+/// A trie key or node hash, written as `0x`-prefixed hex.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash(pub [u8; 32]);
+
+impl Hash {
+    pub const ZERO: Hash = Hash([0; 32]);
+
+    /// Where `address` sits in the state trie.
+    pub fn of_address(address: &str) -> Self {
+        Hash(*blake3::hash(address.as_bytes()).as_bytes())
+    }
+
+    /// The path bit at `depth`: 0 goes left, 1 right.
+    pub fn bit(&self, depth: usize) -> u8 {
+        (self.0[depth / 8] >> (7 - depth % 8)) & 1
+    }
+}
+
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim_start_matches("0x")).map_err(|e| format!("{} is not hex: {}", s, e))?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|_| format!("{} is not 32 bytes", s))?;
+        Ok(Hash(bytes))
+    }
+}
+
+impl Serialize for Hash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A state trie leaf holding the value hashing to `value_hash` at `key`.
+pub fn leaf_hash(key: &Hash, value_hash: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[0]).update(&key.0).update(&value_hash.0);
+    Hash(*hasher.finalize().as_bytes())
+}
+
+/// A transaction tree leaf; `tx_hash` is the hash as written.
+pub fn transaction_leaf_hash(tx_hash: &str) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[0]).update(tx_hash.as_bytes());
+    Hash(*hasher.finalize().as_bytes())
+}
+
+pub fn branch_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[1]).update(&left.0).update(&right.0);
+    Hash(*hasher.finalize().as_bytes())
+}
+
+/// An account as the state trie commits to it: the node's own accounts,
+/// and a light client's view of them.
+pub trait TrieValue {
+    fn value_hash(&self) -> Hash;
+}
+
+/// The leaf a proof's path ends at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofLeaf {
+    pub key: Hash,
+    pub value_hash: Hash,
+}
+
+/// Siblings from the root down to where an account is, or would be.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    pub siblings: Vec<Hash>,
+    /// The account's leaf; for an absent account, the leaf of another
+    /// account sharing its path, or none when the path ends empty
+    pub leaf: Option<ProofLeaf>,
+}
+
+impl StateProof {
+    /// Whether `root` holds `account` at `address`, or no account when
+    /// `account` is `None`.
+    pub fn verify(&self, root: &Hash, address: &str, account: Option<&dyn TrieValue>) -> bool {
+        if self.siblings.len() > MAX_DEPTH {
+            return false;
+        }
+        let key = Hash::of_address(address);
+        let depth = self.siblings.len();
+        let mut hash = match (account, &self.leaf) {
+            (Some(account), Some(leaf)) if leaf.key == key && leaf.value_hash == account.value_hash() => {
+                leaf_hash(&leaf.key, &leaf.value_hash)
+            }
+            // Another account's leaf proves absence only if `key` would
+            // have landed in its subtree
+            (None, Some(leaf)) if leaf.key != key && (0..depth).all(|d| leaf.key.bit(d) == key.bit(d)) => {
+                leaf_hash(&leaf.key, &leaf.value_hash)
+            }
+            (None, None) => Hash::ZERO,
+            _ => return false,
+        };
+        for (depth, sibling) in self.siblings.iter().enumerate().rev() {
+            hash = if key.bit(depth) == 0 { branch_hash(&hash, sibling) } else { branch_hash(sibling, &hash) };
+        }
+        hash == *root
+    }
+}
+
+/// Siblings from a transaction's leaf up to its block's root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// The transaction's position in its block
+    pub index: usize,
+    pub siblings: Vec<Hash>,
+}
+
+impl InclusionProof {
+    /// Whether `root` holds `tx_hash`, as written, at `index`.
+    pub fn verify(&self, root: &Hash, tx_hash: &str) -> bool {
+        // Bits of `index` above the proof's depth would go unchecked
+        if self.siblings.len() < usize::BITS as usize && self.index >> self.siblings.len() != 0 {
+            return false;
+        }
+        let mut hash = transaction_leaf_hash(tx_hash);
+        for (depth, sibling) in self.siblings.iter().enumerate() {
+            hash = if (self.index >> depth) & 1 == 0 { branch_hash(&hash, sibling) } else { branch_hash(sibling, &hash) };
+        }
+        hash == *root
+    }
+}
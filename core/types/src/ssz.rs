@@ -0,0 +1,440 @@
+//! Simple Serialize (SSZ), as Ethereum's consensus specs define it, so
+//! other languages' SSZ libraries read what Cubiq writes and agree on
+//! its roots.
+//!
+//! Encoding: integers are little-endian, booleans one byte. A container
+//! is its fields in order, each variable-size field replaced by a 4-byte
+//! offset to where its bytes begin after the fixed part; lists are
+//! likewise, without a length prefix.
+//!
+//! Merkleization: a value's hash tree root is the SHA-256 merkle root of
+//! its 32-byte chunks, padded with zero chunks to a power of two: a basic
+//! value's encoding, a container's fields' roots, or a list's elements
+//! (packed when basic), padded to the list's limit, with the length mixed
+//! in.
+
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+pub const BYTES_PER_CHUNK: usize = 32;
+
+/// Bytes of an offset to a variable-size field.
+pub const OFFSET_LEN: usize = 4;
+
+pub type Chunk = [u8; BYTES_PER_CHUNK];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SszError {
+    /// Fewer or more bytes than the type's fixed length
+    WrongLength { expected: usize, found: usize },
+    /// An offset points outside the bytes, or before an earlier one
+    BadOffset(usize),
+    /// A list holds more than its limit
+    TooLong { limit: usize, found: usize },
+    /// A boolean byte is neither 0 nor 1
+    BadBool(u8),
+}
+
+impl fmt::Display for SszError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SszError::WrongLength { expected, found } => write!(f, "expected {} bytes, found {}", expected, found),
+            SszError::BadOffset(offset) => write!(f, "offset {} is out of order or out of bounds", offset),
+            SszError::TooLong { limit, found } => write!(f, "list of {} exceeds its limit of {}", found, limit),
+            SszError::BadBool(byte) => write!(f, "boolean byte is {}, not 0 or 1", byte),
+        }
+    }
+}
+
+impl std::error::Error for SszError {}
+
+pub trait Ssz: Sized {
+    /// Encoded length of a fixed-size type; `None` for variable-size ones.
+    const FIXED_LEN: Option<usize>;
+    /// Whether the type is a basic one (an integer or boolean), which
+    /// lists pack rather than merkleize element by element.
+    const BASIC: bool = false;
+
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    fn decode(bytes: &[u8]) -> Result<Self, SszError>;
+
+    fn hash_tree_root(&self) -> Chunk;
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+pub fn hash(left: &[u8], right: &[u8]) -> Chunk {
+    Sha256::new().chain_update(left).chain_update(right).finalize().into()
+}
+
+/// The merkle root of `chunks`, padded with zero chunks to the power of
+/// two at or above `limit` chunks, or above their count without one.
+pub fn merkleize(chunks: &[Chunk], limit: Option<usize>) -> Chunk {
+    let count = limit.unwrap_or(chunks.len());
+    assert!(chunks.len() <= count, "{} chunks exceed the limit of {}", chunks.len(), count);
+    let depth = count.next_power_of_two().trailing_zeros();
+    let mut layer = chunks.to_vec();
+    let mut zero = [0; BYTES_PER_CHUNK];
+    for _ in 0..depth {
+        if !layer.len().is_multiple_of(2) {
+            layer.push(zero);
+        }
+        layer = layer.chunks(2).map(|pair| hash(&pair[0], &pair[1])).collect();
+        zero = hash(&zero, &zero);
+    }
+    layer.first().copied().unwrap_or(zero)
+}
+
+pub fn mix_in_length(root: &Chunk, length: usize) -> Chunk {
+    let mut encoded = [0; BYTES_PER_CHUNK];
+    encoded[..8].copy_from_slice(&(length as u64).to_le_bytes());
+    hash(root, &encoded)
+}
+
+/// `bytes` in chunks, the last right-padded with zeros.
+pub fn pack(bytes: &[u8]) -> Vec<Chunk> {
+    bytes
+        .chunks(BYTES_PER_CHUNK)
+        .map(|part| {
+            let mut chunk = [0; BYTES_PER_CHUNK];
+            chunk[..part.len()].copy_from_slice(part);
+            chunk
+        })
+        .collect()
+}
+
+fn fixed<const N: usize>(bytes: &[u8]) -> Result<[u8; N], SszError> {
+    bytes.try_into().map_err(|_| SszError::WrongLength { expected: N, found: bytes.len() })
+}
+
+fn read_offset(bytes: &[u8], at: usize) -> Result<usize, SszError> {
+    let offset = bytes.get(at..at + OFFSET_LEN).ok_or(SszError::BadOffset(at))?;
+    Ok(u32::from_le_bytes(fixed(offset)?) as usize)
+}
+
+macro_rules! uint {
+    ($($ty:ty),*) => {$(
+        impl Ssz for $ty {
+            const FIXED_LEN: Option<usize> = Some(std::mem::size_of::<$ty>());
+            const BASIC: bool = true;
+
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn decode(bytes: &[u8]) -> Result<Self, SszError> {
+                Ok(<$ty>::from_le_bytes(fixed(bytes)?))
+            }
+
+            fn hash_tree_root(&self) -> Chunk {
+                pack(&self.to_le_bytes())[0]
+            }
+        }
+    )*};
+}
+
+uint!(u8, u16, u32, u64);
+
+impl Ssz for bool {
+    const FIXED_LEN: Option<usize> = Some(1);
+    const BASIC: bool = true;
+
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SszError> {
+        match fixed::<1>(bytes)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [byte] => Err(SszError::BadBool(byte)),
+        }
+    }
+
+    fn hash_tree_root(&self) -> Chunk {
+        pack(&[*self as u8])[0]
+    }
+}
+
+/// `Vector[byte, 32]`, for hashes and keys.
+impl Ssz for [u8; 32] {
+    const FIXED_LEN: Option<usize> = Some(32);
+
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SszError> {
+        fixed(bytes)
+    }
+
+    fn hash_tree_root(&self) -> Chunk {
+        *self
+    }
+}
+
+/// `List[T, N]`: at most `N` elements.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct List<T, const N: usize>(Vec<T>);
+
+/// `List[byte, N]`, for strings and variable-length bytes.
+pub type ByteList<const N: usize> = List<u8, N>;
+
+impl<T, const N: usize> List<T, N> {
+    pub fn new(items: Vec<T>) -> Result<Self, SszError> {
+        if items.len() > N {
+            return Err(SszError::TooLong { limit: N, found: items.len() });
+        }
+        Ok(List(items))
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+}
+
+impl<const N: usize> TryFrom<&str> for ByteList<N> {
+    type Error = SszError;
+
+    fn try_from(s: &str) -> Result<Self, SszError> {
+        List::new(s.as_bytes().to_vec())
+    }
+}
+
+impl<T: Ssz, const N: usize> Ssz for List<T, N> {
+    const FIXED_LEN: Option<usize> = None;
+
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        if T::FIXED_LEN.is_some() {
+            self.0.iter().for_each(|item| item.encode_to(out));
+            return;
+        }
+        let mut variable = Vec::new();
+        for item in &self.0 {
+            out.extend_from_slice(&((self.0.len() * OFFSET_LEN + variable.len()) as u32).to_le_bytes());
+            item.encode_to(&mut variable);
+        }
+        out.extend_from_slice(&variable);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SszError> {
+        if let Some(len) = T::FIXED_LEN {
+            if !bytes.len().is_multiple_of(len) {
+                return Err(SszError::WrongLength { expected: bytes.len() / len * len, found: bytes.len() });
+            }
+            return List::new(bytes.chunks(len).map(T::decode).collect::<Result<_, _>>()?);
+        }
+        if bytes.is_empty() {
+            return Ok(List(vec![]));
+        }
+        let first = read_offset(bytes, 0)?;
+        if first % OFFSET_LEN != 0 || first == 0 || first > bytes.len() {
+            return Err(SszError::BadOffset(first));
+        }
+        let count = first / OFFSET_LEN;
+        if count > N {
+            return Err(SszError::TooLong { limit: N, found: count });
+        }
+        let offsets = (0..count).map(|i| read_offset(bytes, i * OFFSET_LEN)).collect::<Result<Vec<_>, _>>()?;
+        let items = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = offsets.get(i + 1).copied().unwrap_or(bytes.len());
+                if start > end || end > bytes.len() {
+                    return Err(SszError::BadOffset(start));
+                }
+                T::decode(&bytes[start..end])
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(List(items))
+    }
+
+    fn hash_tree_root(&self) -> Chunk {
+        let root = if T::BASIC {
+            let size = T::FIXED_LEN.expect("basic types are fixed-size");
+            let limit = (N * size).div_ceil(BYTES_PER_CHUNK);
+            let mut bytes = Vec::with_capacity(self.0.len() * size);
+            self.0.iter().for_each(|item| item.encode_to(&mut bytes));
+            merkleize(&pack(&bytes), Some(limit))
+        } else {
+            let roots: Vec<Chunk> = self.0.iter().map(Ssz::hash_tree_root).collect();
+            merkleize(&roots, Some(N))
+        };
+        mix_in_length(&root, self.0.len())
+    }
+}
+
+/// A container's fixed length, given each field's `Ssz::FIXED_LEN`.
+pub const fn fixed_len(lens: &[Option<usize>]) -> Option<usize> {
+    let mut total = 0;
+    let mut i = 0;
+    while i < lens.len() {
+        match lens[i] {
+            Some(len) => total += len,
+            None => return None,
+        }
+        i += 1;
+    }
+    Some(total)
+}
+
+/// Splits a container's encoding into its fields', given each field's
+/// `Ssz::FIXED_LEN`.
+pub fn split_fields<'a>(bytes: &'a [u8], lens: &[Option<usize>]) -> Result<Vec<&'a [u8]>, SszError> {
+    let fixed_part: usize = lens.iter().map(|len| len.unwrap_or(OFFSET_LEN)).sum();
+    if bytes.len() < fixed_part {
+        return Err(SszError::WrongLength { expected: fixed_part, found: bytes.len() });
+    }
+    let mut fields = Vec::with_capacity(lens.len());
+    // Variable-size fields, as their index and offset
+    let mut offsets = Vec::new();
+    let mut at = 0;
+    for len in lens {
+        match len {
+            Some(len) => {
+                fields.push(&bytes[at..at + len]);
+                at += len;
+            }
+            None => {
+                offsets.push((fields.len(), read_offset(bytes, at)?));
+                fields.push(&[][..]);
+                at += OFFSET_LEN;
+            }
+        }
+    }
+    match offsets.first() {
+        None if bytes.len() != fixed_part => return Err(SszError::WrongLength { expected: fixed_part, found: bytes.len() }),
+        Some(&(_, first)) if first != fixed_part => return Err(SszError::BadOffset(first)),
+        _ => {}
+    }
+    for (i, &(index, start)) in offsets.iter().enumerate() {
+        let end = offsets.get(i + 1).map_or(bytes.len(), |&(_, next)| next);
+        if start > end || end > bytes.len() {
+            return Err(SszError::BadOffset(start));
+        }
+        fields[index] = &bytes[start..end];
+    }
+    Ok(fields)
+}
+
+/// Appends a field's encoding to the bytes given.
+pub type FieldEncoder<'a> = &'a dyn Fn(&mut Vec<u8>);
+
+/// Encodes a container's fields, given as `(FIXED_LEN, encoder)` pairs.
+pub fn encode_fields(out: &mut Vec<u8>, fields: &[(Option<usize>, FieldEncoder)]) {
+    let fixed_part: usize = fields.iter().map(|(len, _)| len.unwrap_or(OFFSET_LEN)).sum();
+    let mut variable = Vec::new();
+    for (len, encode) in fields {
+        match len {
+            Some(_) => encode(out),
+            None => {
+                out.extend_from_slice(&((fixed_part + variable.len()) as u32).to_le_bytes());
+                encode(&mut variable);
+            }
+        }
+    }
+    out.extend_from_slice(&variable);
+}
+
+/// Implements `Ssz` for a struct as an SSZ container of its fields, in
+/// the order listed.
+#[macro_export]
+macro_rules! container {
+    ($name:ident { $($field:ident: $ty:ty),* $(,)? }) => {
+        impl $crate::ssz::Ssz for $name {
+            const FIXED_LEN: Option<usize> = $crate::ssz::fixed_len(&[$(<$ty as $crate::ssz::Ssz>::FIXED_LEN),*]);
+
+            fn encode_to(&self, out: &mut Vec<u8>) {
+                $crate::ssz::encode_fields(out, &[
+                    $((<$ty as $crate::ssz::Ssz>::FIXED_LEN, &|out: &mut Vec<u8>| $crate::ssz::Ssz::encode_to(&self.$field, out))),*
+                ]);
+            }
+
+            fn decode(bytes: &[u8]) -> Result<Self, $crate::ssz::SszError> {
+                let fields = $crate::ssz::split_fields(bytes, &[$(<$ty as $crate::ssz::Ssz>::FIXED_LEN),*])?;
+                let mut fields = fields.into_iter();
+                Ok($name { $($field: <$ty as $crate::ssz::Ssz>::decode(fields.next().expect("a slice per field"))?),* })
+            }
+
+            fn hash_tree_root(&self) -> $crate::ssz::Chunk {
+                $crate::ssz::merkleize(&[$($crate::ssz::Ssz::hash_tree_root(&self.$field)),*], None)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ethereum's `Checkpoint`, whose roots are well known.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct EthCheckpoint {
+        epoch: u64,
+        root: [u8; 32],
+    }
+
+    container!(EthCheckpoint { epoch: u64, root: [u8; 32] });
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Mixed {
+        id: u32,
+        name: ByteList<16>,
+        flags: List<bool, 4>,
+        names: List<ByteList<16>, 4>,
+    }
+
+    container!(Mixed { id: u32, name: ByteList<16>, flags: List<bool, 4>, names: List<ByteList<16>, 4> });
+
+    #[test]
+    fn encodes_and_merkleizes_as_the_spec_does() {
+        let zero = EthCheckpoint { epoch: 0, root: [0; 32] };
+        assert_eq!(EthCheckpoint::FIXED_LEN, Some(40));
+        assert_eq!(zero.encode(), vec![0; 40]);
+        assert_eq!(hex(&zero.hash_tree_root()), "f5a5fd42d16a20302798ef6ed309979b43003d2320d9f0e8ea9831a92759fb4b");
+        let checkpoint = EthCheckpoint { epoch: 3, root: [0xaa; 32] };
+        assert_eq!(EthCheckpoint::decode(&checkpoint.encode()), Ok(checkpoint.clone()));
+        assert_eq!(checkpoint.hash_tree_root(), hash(&pack(&[3])[0], &[0xaa; 32]));
+
+        assert_eq!(merkleize(&[], Some(0)), [0; 32]);
+        assert_eq!(merkleize(&[[1; 32]], None), [1; 32]);
+        assert_eq!(merkleize(&[[1; 32]], Some(3)), hash(&hash(&[1; 32], &[0; 32]), &hash(&[0; 32], &[0; 32])));
+        let empty: ByteList<64> = List::new(vec![]).unwrap();
+        assert_eq!(empty.hash_tree_root(), mix_in_length(&hash(&[0; 32], &[0; 32]), 0));
+
+        let mixed = Mixed {
+            id: 7,
+            name: "cubiq".try_into().unwrap(),
+            flags: List::new(vec![true, false]).unwrap(),
+            names: List::new(vec!["a".try_into().unwrap(), "bc".try_into().unwrap()]).unwrap(),
+        };
+        let encoded = mixed.encode();
+        // id, then offsets 16, 21 and 23, then the variable parts
+        assert_eq!(&encoded[..16], &[7, 0, 0, 0, 16, 0, 0, 0, 21, 0, 0, 0, 23, 0, 0, 0]);
+        assert_eq!(&encoded[16..], b"cubiq\x01\x00\x08\x00\x00\x00\x09\x00\x00\x00abc");
+        assert_eq!(Mixed::decode(&encoded), Ok(mixed.clone()));
+        assert_eq!(mixed.flags.hash_tree_root(), mix_in_length(&pack(&[1, 0])[0], 2));
+
+        assert_eq!(u64::decode(&[1, 2]), Err(SszError::WrongLength { expected: 8, found: 2 }));
+        assert_eq!(bool::decode(&[2]), Err(SszError::BadBool(2)));
+        assert!(matches!(ByteList::<2>::decode(b"abc"), Err(SszError::TooLong { limit: 2, found: 3 })));
+        let mut shifted = encoded.clone();
+        shifted[4] = 15;
+        assert_eq!(Mixed::decode(&shifted), Err(SszError::BadOffset(15)));
+        assert!(Mixed::decode(&encoded[..10]).is_err());
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::str::FromStr;
+use zkurl::ZkURL;
+
+// Parsing must never panic, and anything that parses must survive a
+// display/parse round trip unchanged.
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(zkurl) = ZkURL::from_str(s) {
+        let reparsed = ZkURL::from_str(&zkurl.to_string()).expect("displayed zkURL must parse");
+        assert_eq!(reparsed, zkurl);
+    }
+});
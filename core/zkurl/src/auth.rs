@@ -0,0 +1,203 @@
+//! Credentials for private prover endpoints and client TLS settings.
+//!
+//! Secrets never appear in `Debug` output or error messages: credentials
+//! print as redacted and their header values are marked sensitive, and
+//! userinfo is stripped from URLs before they are reported.
+//!
+//! Clients from `TlsConfig::build_client` do not follow redirects; `send`
+//! does, so that a credential only ever goes to the origin it was
+//! configured for. reqwest drops `Authorization` on a redirect to another
+//! host, but forwards custom headers such as `X-Api-Key`.
+
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, Identity, Method, RequestBuilder, Response, StatusCode};
+use std::fmt;
+use std::time::Duration;
+
+use crate::proxy::ProxyConfig;
+use crate::ZkURLError;
+
+/// Redirects `send` follows before giving up, as many as reqwest would.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Clone)]
+enum Scheme {
+    Bearer(String),
+    Basic { username: String, password: Option<String> },
+    Header(HeaderName, HeaderValue),
+}
+
+/// Credential sent with every request to one endpoint.
+#[derive(Clone)]
+pub struct Credential(Scheme);
+
+impl Credential {
+    /// `Authorization: Bearer <token>`
+    pub fn bearer(token: impl Into<String>) -> Self {
+        Credential(Scheme::Bearer(token.into()))
+    }
+
+    /// HTTP basic auth
+    pub fn basic(username: impl Into<String>, password: Option<String>) -> Self {
+        Credential(Scheme::Basic { username: username.into(), password })
+    }
+
+    /// Custom header, e.g. `X-Api-Key`. Fails if the name or value is not
+    /// valid in an HTTP header.
+    pub fn header(name: &str, value: &str) -> Result<Self, ZkURLError> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| ZkURLError::Config(format!("invalid auth header name {:?}", name)))?;
+        let mut value = HeaderValue::from_str(value)
+            .map_err(|_| ZkURLError::Config(format!("invalid value for auth header {}", name)))?;
+        value.set_sensitive(true);
+        Ok(Credential(Scheme::Header(name, value)))
+    }
+
+    pub(crate) fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.0 {
+            Scheme::Bearer(token) => request.bearer_auth(token),
+            Scheme::Basic { username, password } => request.basic_auth(username, password.as_ref()),
+            Scheme::Header(name, value) => request.header(name.clone(), value.clone()),
+        }
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Scheme::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+            Scheme::Basic { username, .. } => write!(f, "Basic({}:<redacted>)", username),
+            Scheme::Header(name, _) => write!(f, "Header({}: <redacted>)", name),
+        }
+    }
+}
+
+/// Client TLS settings: a client certificate for mTLS and extra trusted
+/// roots for privately issued server certificates.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    /// PEM bundle holding the client certificate chain and its private key
+    pub client_identity_pem: Option<Vec<u8>>,
+    /// PEM certificates trusted in addition to the system roots
+    pub root_certificates_pem: Vec<Vec<u8>>,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("client_identity_pem", &self.client_identity_pem.as_ref().map(|_| "<redacted>"))
+            .field("root_certificates_pem", &self.root_certificates_pem.len())
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Builds an HTTP client with these settings, routed through `proxy`.
+    /// It leaves redirects to `send`.
+    pub(crate) fn build_client(&self, timeout: Duration, proxy: &ProxyConfig) -> Result<Client, ZkURLError> {
+        let mut builder = proxy.apply(Client::builder().timeout(timeout).redirect(Policy::none()));
+        if let Some(pem) = &self.client_identity_pem {
+            let identity = Identity::from_pem(pem)
+                .map_err(|e| ZkURLError::Config(format!("invalid client certificate: {}", e)))?;
+            builder = builder.identity(identity);
+        }
+        for pem in &self.root_certificates_pem {
+            let certificate = Certificate::from_pem(pem)
+                .map_err(|e| ZkURLError::Config(format!("invalid root certificate: {}", e)))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        builder
+            .build()
+            .map_err(|e| ZkURLError::Config(format!("failed to build HTTP client: {}", e)))
+    }
+}
+
+/// Sends `request`, following redirects, with `credential` added to every
+/// hop that stays on the origin the request was first sent to. Errors are
+/// described without the URL, which may carry userinfo.
+pub(crate) async fn send(request: RequestBuilder, credential: Option<&Credential>) -> Result<Response, String> {
+    let (client, request) = request.build_split();
+    let mut request = request.map_err(|e| e.without_url().to_string())?;
+    let origin = request.url().origin();
+    for _ in 0..=MAX_REDIRECTS {
+        let hop = request.try_clone().ok_or("request body cannot be resent")?;
+        let mut hop = RequestBuilder::from_parts(client.clone(), hop);
+        if let Some(credential) = credential.filter(|_| request.url().origin() == origin) {
+            hop = credential.apply(hop);
+        }
+        let response = hop.send().await.map_err(|e| e.without_url().to_string())?;
+        let status = response.status();
+        let redirect = matches!(
+            status,
+            StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT
+        );
+        let location = response.headers().get(LOCATION).and_then(|l| l.to_str().ok());
+        let Some(next) = location.filter(|_| redirect).and_then(|l| response.url().join(l).ok()) else {
+            return Ok(response);
+        };
+        // As browsers do, 303, and 301 or 302 after a POST, become a bodiless GET
+        let keeps_method = matches!(status, StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT);
+        if status == StatusCode::SEE_OTHER || (!keeps_method && request.method() == Method::POST) {
+            *request.method_mut() = Method::GET;
+            *request.body_mut() = None;
+            request.headers_mut().remove(CONTENT_TYPE);
+            request.headers_mut().remove(CONTENT_LENGTH);
+        }
+        *request.url_mut() = next;
+    }
+    Err(format!("more than {} redirects", MAX_REDIRECTS))
+}
+
+/// Strips `user:password@` from a URL so it can be logged or reported.
+pub fn redact_url(url: &str) -> String {
+    let after_scheme = url.find("://").map_or(0, |i| i + 3);
+    let authority_end = url[after_scheme..].find(['/', '?', '#']).map_or(url.len(), |i| after_scheme + i);
+    match url[after_scheme..authority_end].rfind('@') {
+        Some(at) => format!("{}<redacted>@{}", &url[..after_scheme], &url[after_scheme + at + 1..]),
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::AUTHORIZATION;
+
+    fn applied(credential: &Credential) -> reqwest::Request {
+        credential.apply(Client::new().get("https://prover.example/proof/p1")).build().unwrap()
+    }
+
+    #[test]
+    fn credentials_are_sensitive_and_redacted() {
+        let bearer = Credential::bearer("s3cret");
+        let request = applied(&bearer);
+        let value = request.headers().get(AUTHORIZATION).unwrap();
+        assert_eq!(value, "Bearer s3cret");
+        assert!(value.is_sensitive());
+        assert!(!format!("{:?}", bearer).contains("s3cret"));
+
+        let header = Credential::header("x-api-key", "k3y").unwrap();
+        let request = applied(&header);
+        assert!(request.headers().get("x-api-key").unwrap().is_sensitive());
+        assert_eq!(format!("{:?}", header), "Header(x-api-key: <redacted>)");
+    }
+
+    #[test]
+    fn rejects_invalid_configuration() {
+        assert!(matches!(Credential::header("bad header", "v"), Err(ZkURLError::Config(_))));
+        assert!(matches!(Credential::header("x-api-key", "line\nbreak"), Err(ZkURLError::Config(_))));
+        let tls = TlsConfig { client_identity_pem: Some(b"not a pem".to_vec()), ..TlsConfig::default() };
+        assert!(matches!(tls.build_client(Duration::from_secs(1), &ProxyConfig::default()), Err(ZkURLError::Config(_))));
+    }
+
+    #[test]
+    fn redacts_userinfo() {
+        assert_eq!(redact_url("https://alice:pw@prover.example/proof/a@b"), "https://<redacted>@prover.example/proof/a@b");
+        assert_eq!(redact_url("https://prover.example/proof/p1"), "https://prover.example/proof/p1");
+    }
+}
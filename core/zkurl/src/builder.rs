@@ -0,0 +1,222 @@
+use cid::Cid;
+use std::str::FromStr;
+
+use crate::{syntax, ZkURL, ZkURLError, ZkURLHost, ZkURLMetadata};
+
+/// Builder for programmatically constructed zkURLs.
+///
+/// Every component is validated on `build()`, so a prover can never emit a
+/// zkURL that fails to parse back.
+#[derive(Debug, Clone, Default)]
+pub struct ZkURLBuilder {
+    prover_id: Option<String>,
+    domain: Option<String>,
+    ipfs_cid: Option<String>,
+    port: Option<u16>,
+    path_prefix: Vec<String>,
+    proof_id: Option<String>,
+    metadata: Option<ZkURLMetadata>,
+}
+
+impl ZkURL {
+    /// Start building a zkURL, e.g.
+    /// `ZkURL::builder().prover_id("p1").domain("proofs.cubiq.dev").proof_id("block1").build()`.
+    pub fn builder() -> ZkURLBuilder {
+        ZkURLBuilder::default()
+    }
+}
+
+impl ZkURLBuilder {
+    pub fn prover_id(mut self, prover_id: impl Into<String>) -> Self {
+        self.prover_id = Some(prover_id.into());
+        self
+    }
+
+    /// DNS domain serving the proof. Mutually exclusive with `ipfs_cid`.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Content identifier of the proof on IPFS. Mutually exclusive with `domain`.
+    pub fn ipfs_cid(mut self, cid: impl Into<String>) -> Self {
+        self.ipfs_cid = Some(cid.into());
+        self
+    }
+
+    /// Port for DNS hosts not serving on 443.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Path under which the prover serves proofs, e.g. `"api/v2"`.
+    pub fn path_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.path_prefix = prefix.into().split('/').map(str::to_string).collect();
+        self
+    }
+
+    /// Proof id, or `*` / `start-end` to address a collection or range.
+    pub fn proof_id(mut self, proof_id: impl Into<String>) -> Self {
+        self.proof_id = Some(proof_id.into());
+        self
+    }
+
+    pub fn metadata(mut self, metadata: ZkURLMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Validate all components and assemble the zkURL.
+    pub fn build(self) -> Result<ZkURL, ZkURLError> {
+        let host = match (self.domain, self.ipfs_cid) {
+            (Some(_), Some(_)) => {
+                return Err(invalid("host", "domain and ipfs_cid are mutually exclusive"))
+            }
+            (None, None) => return Err(invalid("host", "either domain or ipfs_cid is required")),
+            (Some(domain), None) => {
+                syntax::validate_domain(&domain, 0)?;
+                // Undotted hosts without a prover parse back as CIDs
+                if !domain.contains('.') && self.prover_id.is_none() {
+                    return Err(invalid("domain", "single-label domains require a prover_id"));
+                }
+                ZkURLHost::Domain(domain)
+            }
+            (None, Some(cid)) => {
+                let cid = Cid::from_str(&cid).map_err(|e| invalid("ipfs_cid", e.to_string()))?;
+                ZkURLHost::Cid(cid)
+            }
+        };
+
+        if let Some(prover_id) = &self.prover_id {
+            validate_token("prover_id", prover_id, &['-', '_', '.'])?;
+        }
+
+        match self.port {
+            Some(0) => return Err(invalid("port", "must be in 1-65535")),
+            Some(_) if host.as_cid().is_some() => {
+                return Err(invalid("port", "content-addressed zkURLs cannot specify a port"))
+            }
+            _ => {}
+        }
+        for segment in &self.path_prefix {
+            validate_token("path_prefix", segment, &['-', '_', '.'])?;
+        }
+
+        let proof_id = self.proof_id.ok_or_else(|| invalid("proof_id", "is required"))?;
+        if proof_id != "*" {
+            validate_token("proof_id", &proof_id, &['-', '_', '.'])?;
+        }
+
+        let mut metadata = self.metadata;
+        if let Some(metadata) = &mut metadata {
+            validate_token("version", &metadata.version, &['.'])?;
+            validate_token("proof_type", &metadata.proof_type, &['-', '_'])?;
+            if let Some(compression) = &metadata.compression {
+                validate_token("compression", compression, &['-', '_'])?;
+            }
+            // Kept normalized, as parsing would, so `checksum_matches` can compare it
+            metadata.checksum = metadata.checksum.as_deref().map(crate::parse_checksum_hint).transpose()?;
+            for (key, value) in &metadata.extra {
+                validate_token("metadata key", key, &['-', '_'])?;
+                validate_token("metadata value", value, &['-', '_', '.'])?;
+            }
+        }
+
+        Ok(ZkURL {
+            prover_id: self.prover_id,
+            host,
+            port: self.port,
+            path_prefix: self.path_prefix,
+            proof_id,
+            metadata,
+        })
+    }
+}
+
+fn invalid(component: &'static str, reason: impl Into<String>) -> ZkURLError {
+    ZkURLError::InvalidComponent {
+        component,
+        reason: reason.into(),
+    }
+}
+
+/// Non-empty ASCII alphanumerics plus the given extra characters.
+pub(crate) fn validate_token(component: &'static str, value: &str, extra: &[char]) -> Result<(), ZkURLError> {
+    if value.is_empty() {
+        return Err(invalid(component, "must not be empty"));
+    }
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_alphanumeric() && !extra.contains(c)) {
+        return Err(invalid(component, format!("contains invalid character {:?}", c)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn builds_parseable_url() {
+        let zkurl = ZkURL::builder()
+            .prover_id("prover123")
+            .domain("domain.com")
+            .proof_id("block1024")
+            .metadata(ZkURLMetadata::new("v1", Some("gzip".to_string()), "stark"))
+            .build()
+            .unwrap();
+        assert_eq!(zkurl.to_string(), "zk://prover123@domain.com/block1024#version=v1&compression=gzip&type=stark");
+        assert_eq!(ZkURL::from_str(&zkurl.to_string()).unwrap(), zkurl);
+    }
+
+    #[test]
+    fn keeps_the_checksum_normalized() {
+        let checksum = blake3::hash(b"proof").to_hex().to_string();
+        let metadata = ZkURLMetadata { checksum: Some(checksum.to_ascii_uppercase()), ..ZkURLMetadata::new("v1", None, "stark") };
+        let zkurl = ZkURL::builder().prover_id("p1").domain("domain.com").proof_id("block1").metadata(metadata).build().unwrap();
+        let metadata = zkurl.metadata.as_ref().unwrap();
+        assert_eq!(metadata.checksum.as_deref(), Some(checksum.as_str()));
+        assert!(metadata.checksum_matches(b"proof"));
+        assert_eq!(ZkURL::from_str(&zkurl.to_string()).unwrap(), zkurl);
+    }
+
+    #[test]
+    fn builds_url_with_port_and_prefix() {
+        let zkurl = ZkURL::builder()
+            .prover_id("prover123")
+            .domain("proofs.internal")
+            .port(8443)
+            .path_prefix("api/v2")
+            .proof_id("block7")
+            .build()
+            .unwrap();
+        assert_eq!(zkurl.to_string(), "zk://prover123@proofs.internal:8443/api/v2/block7");
+        assert!(ZkURL::builder().domain("d.com").path_prefix("a//b").proof_id("p").build().is_err());
+    }
+
+    #[test]
+    fn rejects_domain_and_cid_together() {
+        let result = ZkURL::builder()
+            .domain("domain.com")
+            .ipfs_cid("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG")
+            .proof_id("block1")
+            .build();
+        assert!(matches!(result, Err(ZkURLError::InvalidComponent { component: "host", .. })));
+    }
+
+    #[test]
+    fn rejects_bad_characters_and_empty_parts() {
+        let bad_prover = ZkURL::builder().prover_id("a@b").domain("d.com").proof_id("p").build();
+        assert!(matches!(bad_prover, Err(ZkURLError::InvalidComponent { component: "prover_id", .. })));
+
+        let empty_proof = ZkURL::builder().ipfs_cid("QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG").proof_id("").build();
+        assert!(matches!(empty_proof, Err(ZkURLError::InvalidComponent { component: "proof_id", .. })));
+
+        let bad_domain = ZkURL::builder().domain("-bad.com").proof_id("p").build();
+        assert!(matches!(bad_domain, Err(ZkURLError::InvalidComponent { component: "domain", .. })));
+
+        let bad_cid = ZkURL::builder().ipfs_cid("QmHash123").proof_id("p").build();
+        assert!(matches!(bad_cid, Err(ZkURLError::InvalidComponent { component: "ipfs_cid", .. })));
+    }
+}
@@ -0,0 +1,477 @@
+//! Two-tier cache for resolved proof bundles.
+//!
+//! The memory tier is an LRU bounded by an approximate byte budget; the
+//! optional disk tier stores one JSON file per bundle so restarts do not
+//! re-download multi-megabyte proofs. Both tiers expire entries after a TTL.
+//!
+//! Entries are checked again whenever they are read: against a digest
+//! taken when they were inserted, so a corrupted entry is dropped instead
+//! of served, and against the zkURL's `checksum` hint, which the cache
+//! key leaves out.
+//!
+//! Entries fetched over HTTP keep the response `ETag`. Once such an entry
+//! expires it is kept as stale rather than dropped, so the resolver can
+//! revalidate it with a conditional request instead of downloading it again.
+
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::resolver::ProofBundle;
+use crate::{ZkURL, ZkURLHost};
+
+/// Default memory budget for cached bundles.
+pub const DEFAULT_MEMORY_BUDGET_BYTES: usize = 64 * 1024 * 1024;
+/// Default time a cached bundle stays valid.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Approximate bytes of bundles kept in memory; 0 disables the memory tier
+    pub memory_budget_bytes: usize,
+    pub ttl: Duration,
+    /// Directory for the disk tier; `None` disables it
+    pub disk_dir: Option<PathBuf>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            memory_budget_bytes: DEFAULT_MEMORY_BUDGET_BYTES,
+            ttl: DEFAULT_CACHE_TTL,
+            disk_dir: None,
+        }
+    }
+}
+
+/// Snapshot of cache hit/miss counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub memory_hits: u64,
+    pub disk_hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Stale entries the origin confirmed unchanged
+    pub revalidations: u64,
+    /// Entries dropped because they no longer matched their digest
+    #[serde(default)]
+    pub corrupt: u64,
+}
+
+/// Where a cached bundle came from and the `ETag` it was served with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revalidation {
+    pub url: String,
+    pub etag: String,
+}
+
+/// Result of a cache lookup.
+#[derive(Debug, Clone)]
+pub enum CacheLookup {
+    Fresh(ProofBundle),
+    /// Expired, but revalidatable against its origin
+    Stale(ProofBundle, Revalidation),
+    Miss,
+}
+
+/// Canonical cache key: the CID for content-addressed zkURLs (the same
+/// content is the same proof wherever it is referenced), otherwise the zkURL
+/// without its metadata hints.
+pub fn cache_key(zkurl: &ZkURL) -> String {
+    match &zkurl.host {
+        ZkURLHost::Cid(cid) => cid.to_string(),
+        ZkURLHost::Domain(_) => ZkURL { metadata: None, ..zkurl.clone() }.to_string(),
+    }
+}
+
+struct MemoryEntry {
+    bundle: ProofBundle,
+    /// `entry_digest` of the bundle as inserted
+    digest: String,
+    size: usize,
+    stored_at: Instant,
+    revalidation: Option<Revalidation>,
+}
+
+struct MemoryTier {
+    entries: LruCache<String, MemoryEntry>,
+    used_bytes: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    /// Unix timestamp (seconds) the entry was written
+    stored_at: u64,
+    bundle: ProofBundle,
+    #[serde(default)]
+    revalidation: Option<Revalidation>,
+    /// `entry_digest` of the bundle as inserted; entries written without
+    /// one are treated as corrupt
+    #[serde(default)]
+    digest: String,
+}
+
+pub struct BundleCache {
+    config: CacheConfig,
+    memory: Mutex<MemoryTier>,
+    memory_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    revalidations: AtomicU64,
+    corrupt: AtomicU64,
+}
+
+impl BundleCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            memory: Mutex::new(MemoryTier {
+                entries: LruCache::unbounded(),
+                used_bytes: 0,
+            }),
+            memory_hits: AtomicU64::new(0),
+            disk_hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            revalidations: AtomicU64::new(0),
+            corrupt: AtomicU64::new(0),
+        }
+    }
+
+    /// Looks up a fresh bundle, memory tier first. Disk hits are promoted into memory.
+    pub fn get(&self, zkurl: &ZkURL) -> Option<ProofBundle> {
+        match self.lookup(zkurl) {
+            CacheLookup::Fresh(bundle) => Some(bundle),
+            CacheLookup::Stale(..) | CacheLookup::Miss => None,
+        }
+    }
+
+    /// Like `get`, but also returns expired entries that carry an `ETag`.
+    /// Stale results count as misses until `refresh` confirms them.
+    pub fn lookup(&self, zkurl: &ZkURL) -> CacheLookup {
+        let key = cache_key(zkurl);
+        let hinted = |bundle: &ProofBundle| zkurl.metadata.as_ref().is_none_or(|m| m.checksum_matches(&bundle.proof));
+        {
+            let mut memory = self.memory.lock().expect("Bundle cache lock poisoned");
+            if memory.entries.peek(&key).is_some_and(|entry| entry.digest != entry_digest(&entry.bundle)) {
+                let corrupt = memory.entries.pop(&key).expect("entry was just found");
+                memory.used_bytes -= corrupt.size;
+                self.corrupt.fetch_add(1, Ordering::Relaxed);
+            }
+            match memory.entries.get(&key) {
+                Some(entry) if !hinted(&entry.bundle) => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    return CacheLookup::Miss;
+                }
+                Some(entry) if entry.stored_at.elapsed() <= self.config.ttl => {
+                    self.memory_hits.fetch_add(1, Ordering::Relaxed);
+                    return CacheLookup::Fresh(entry.bundle.clone());
+                }
+                Some(MemoryEntry { bundle, revalidation: Some(revalidation), .. }) => {
+                    self.misses.fetch_add(1, Ordering::Relaxed);
+                    return CacheLookup::Stale(bundle.clone(), revalidation.clone());
+                }
+                Some(_) => {
+                    if let Some(expired) = memory.entries.pop(&key) {
+                        memory.used_bytes -= expired.size;
+                    }
+                }
+                None => {}
+            }
+        }
+
+        let Some(entry) = self.read_disk(&key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return CacheLookup::Miss;
+        };
+        let digest = entry_digest(&entry.bundle);
+        if entry.digest != digest {
+            self.remove_disk(&key);
+            self.corrupt.fetch_add(1, Ordering::Relaxed);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return CacheLookup::Miss;
+        }
+        if !hinted(&entry.bundle) {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return CacheLookup::Miss;
+        }
+        let fresh = unix_now().saturating_sub(entry.stored_at) <= self.config.ttl.as_secs();
+        match (fresh, entry.revalidation) {
+            (true, revalidation) => {
+                self.disk_hits.fetch_add(1, Ordering::Relaxed);
+                self.insert_memory(key, entry.bundle.clone(), digest, revalidation);
+                CacheLookup::Fresh(entry.bundle)
+            }
+            (false, Some(revalidation)) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                CacheLookup::Stale(entry.bundle, revalidation)
+            }
+            (false, None) => {
+                self.remove_disk(&key);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                CacheLookup::Miss
+            }
+        }
+    }
+
+    /// Stores a verified bundle in both tiers. Disk write failures are
+    /// ignored: the disk tier is an optimization, not a source of truth.
+    pub fn insert(&self, zkurl: &ZkURL, bundle: &ProofBundle) {
+        self.insert_revalidatable(zkurl, bundle, None);
+    }
+
+    /// Like `insert`, remembering where the bundle came from so it can be
+    /// revalidated once it expires.
+    pub fn insert_revalidatable(&self, zkurl: &ZkURL, bundle: &ProofBundle, revalidation: Option<Revalidation>) {
+        let key = cache_key(zkurl);
+        let digest = entry_digest(bundle);
+        self.write_disk(&key, bundle, &digest, revalidation.clone());
+        self.insert_memory(key, bundle.clone(), digest, revalidation);
+    }
+
+    /// Restarts the TTL of a stale entry its origin confirmed unchanged.
+    pub fn refresh(&self, zkurl: &ZkURL, bundle: &ProofBundle, revalidation: Revalidation) {
+        self.revalidations.fetch_add(1, Ordering::Relaxed);
+        self.insert_revalidatable(zkurl, bundle, Some(revalidation));
+    }
+
+    /// Drops a zkURL's bundle from both tiers.
+    pub fn invalidate(&self, zkurl: &ZkURL) {
+        let key = cache_key(zkurl);
+        let mut memory = self.memory.lock().expect("Bundle cache lock poisoned");
+        if let Some(entry) = memory.entries.pop(&key) {
+            memory.used_bytes -= entry.size;
+        }
+        self.remove_disk(&key);
+    }
+
+    /// Empties the memory tier and removes every cached file from the disk tier.
+    pub fn clear(&self) {
+        let mut memory = self.memory.lock().expect("Bundle cache lock poisoned");
+        memory.entries.clear();
+        memory.used_bytes = 0;
+        if let Some(dir) = &self.config.disk_dir {
+            if let Ok(files) = std::fs::read_dir(dir) {
+                for file in files.flatten() {
+                    if file.path().extension().is_some_and(|ext| ext == "json") {
+                        let _ = std::fs::remove_file(file.path());
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            memory_hits: self.memory_hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            revalidations: self.revalidations.load(Ordering::Relaxed),
+            corrupt: self.corrupt.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Approximate bytes currently held by the memory tier.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory.lock().expect("Bundle cache lock poisoned").used_bytes
+    }
+
+    fn insert_memory(&self, key: String, bundle: ProofBundle, digest: String, revalidation: Option<Revalidation>) {
+        let size = approx_size(&bundle);
+        // Bundles larger than the whole budget would evict everything else
+        if size > self.config.memory_budget_bytes {
+            return;
+        }
+        let mut memory = self.memory.lock().expect("Bundle cache lock poisoned");
+        if let Some(old) = memory.entries.pop(&key) {
+            memory.used_bytes -= old.size;
+        }
+        while memory.used_bytes + size > self.config.memory_budget_bytes {
+            match memory.entries.pop_lru() {
+                Some((_, evicted)) => {
+                    memory.used_bytes -= evicted.size;
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+        memory.used_bytes += size;
+        memory.entries.put(key, MemoryEntry { bundle, digest, size, stored_at: Instant::now(), revalidation });
+    }
+
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.config.disk_dir.as_ref()?;
+        Some(dir.join(format!("{}.json", blake3::hash(key.as_bytes()).to_hex())))
+    }
+
+    fn read_disk(&self, key: &str) -> Option<DiskEntry> {
+        let path = self.disk_path(key)?;
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+    }
+
+    fn remove_disk(&self, key: &str) {
+        if let Some(path) = self.disk_path(key) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    fn write_disk(&self, key: &str, bundle: &ProofBundle, digest: &str, revalidation: Option<Revalidation>) {
+        let Some(path) = self.disk_path(key) else {
+            return;
+        };
+        let entry = DiskEntry { stored_at: unix_now(), bundle: bundle.clone(), revalidation, digest: digest.to_string() };
+        if let Ok(bytes) = serde_json::to_vec(&entry) {
+            if std::fs::create_dir_all(path.parent().expect("cache file has a parent")).is_ok() {
+                // Write then rename so readers never see a partial file
+                let tmp = path.with_extension("tmp");
+                if std::fs::write(&tmp, bytes).is_ok() {
+                    let _ = std::fs::rename(tmp, path);
+                }
+            }
+        }
+    }
+}
+
+/// blake3 hex digest of everything in a bundle: what its signature covers,
+/// and the signature.
+fn entry_digest(bundle: &ProofBundle) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&bundle.signing_bytes());
+    hasher.update(bundle.signature.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+fn approx_size(bundle: &ProofBundle) -> usize {
+    bundle.proof.len()
+        + bundle.signature.len()
+        + bundle.prover_id.len()
+        + bundle.public_inputs.block_hash.len()
+        + bundle.public_inputs.state_root.len()
+        + std::mem::size_of::<ProofBundle>()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{ProofMetadata, PublicInputs};
+    use std::str::FromStr;
+
+    fn bundle(proof_len: usize) -> ProofBundle {
+        ProofBundle {
+            proof: vec![7; proof_len],
+            public_inputs: PublicInputs {
+                block_hash: String::new(),
+                state_root: String::new(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: "prover".to_string(),
+            timestamp: 0,
+            metadata: ProofMetadata {
+                version: "v1".to_string(),
+                compression: None,
+                size_bytes: proof_len,
+            },
+        }
+    }
+
+    fn url(proof_id: &str) -> ZkURL {
+        ZkURL::from_str(&format!("zk://prover@example.com/{}", proof_id)).unwrap()
+    }
+
+    #[test]
+    fn evicts_to_stay_within_budget() {
+        let overhead = std::mem::size_of::<ProofBundle>() + "prover".len();
+        let cache = BundleCache::new(CacheConfig {
+            memory_budget_bytes: 2 * (1000 + overhead),
+            ..CacheConfig::default()
+        });
+        cache.insert(&url("a"), &bundle(1000));
+        cache.insert(&url("b"), &bundle(1000));
+        assert!(cache.get(&url("a")).is_some());
+        cache.insert(&url("c"), &bundle(1000));
+
+        assert!(cache.get(&url("b")).is_none());
+        assert!(cache.memory_bytes() <= 2 * (1000 + overhead));
+        assert_eq!(cache.stats(), CacheStats { memory_hits: 1, disk_hits: 0, misses: 1, evictions: 1, revalidations: 0, corrupt: 0 });
+    }
+
+    #[test]
+    fn metadata_does_not_change_key_and_invalidate_drops_entry() {
+        let cache = BundleCache::new(CacheConfig::default());
+        cache.insert(&url("a"), &bundle(10));
+        assert!(cache.get(&url("a#version=v2&type=stark")).is_some());
+        cache.invalidate(&url("a"));
+        assert!(cache.get(&url("a")).is_none());
+    }
+
+    #[test]
+    fn disk_tier_survives_restart_and_expires() {
+        let dir = std::env::temp_dir().join(format!("zkurl-cache-test-{}", std::process::id()));
+        let config = CacheConfig { disk_dir: Some(dir.clone()), ..CacheConfig::default() };
+        BundleCache::new(config.clone()).insert(&url("a"), &bundle(10));
+
+        let restarted = BundleCache::new(config.clone());
+        assert_eq!(restarted.get(&url("a")).map(|b| b.proof.len()), Some(10));
+        assert_eq!(restarted.stats().disk_hits, 1);
+
+        let expired = BundleCache::new(CacheConfig { ttl: Duration::ZERO, ..config });
+        expired.insert(&url("b"), &bundle(10));
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(expired.get(&url("b")).is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn hits_are_checked_before_being_served() {
+        let dir = std::env::temp_dir().join(format!("zkurl-cache-corrupt-{}", std::process::id()));
+        let config = CacheConfig { disk_dir: Some(dir.clone()), ..CacheConfig::default() };
+        BundleCache::new(config.clone()).insert(&url("a"), &bundle(10));
+
+        // A zkURL with another proof's checksum is not served this one
+        let cache = BundleCache::new(config.clone());
+        let other = blake3::hash(b"other").to_hex();
+        assert!(cache.get(&url(&format!("a#version=v1&type=stark&checksum={}", other))).is_none());
+        let checksum = blake3::hash(&[7; 10]).to_hex();
+        assert!(cache.get(&url(&format!("a#version=v1&type=stark&checksum={}", checksum))).is_some());
+
+        // Flip a proof byte on disk
+        let path = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut entry: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        entry["bundle"]["proof"][0] = 8.into();
+        std::fs::write(&path, serde_json::to_vec(&entry).unwrap()).unwrap();
+        let restarted = BundleCache::new(config);
+        assert!(restarted.get(&url("a")).is_none());
+        assert_eq!((restarted.stats().corrupt, restarted.stats().disk_hits), (1, 0));
+        assert!(!path.exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn expired_entries_with_etag_stay_stale_until_refreshed() {
+        let cache = BundleCache::new(CacheConfig { ttl: Duration::ZERO, ..CacheConfig::default() });
+        let revalidation = Revalidation { url: "https://example.com/proof/a".to_string(), etag: "\"v1\"".to_string() };
+        cache.insert_revalidatable(&url("a"), &bundle(10), Some(revalidation.clone()));
+        cache.insert(&url("b"), &bundle(10));
+        std::thread::sleep(Duration::from_millis(5));
+
+        let CacheLookup::Stale(stale, found) = cache.lookup(&url("a")) else {
+            panic!("expected a stale entry");
+        };
+        assert_eq!(found, revalidation);
+        assert!(matches!(cache.lookup(&url("b")), CacheLookup::Miss));
+
+        cache.refresh(&url("a"), &stale, found);
+        assert_eq!(cache.stats().revalidations, 1);
+    }
+}
@@ -0,0 +1,156 @@
+//! Wire encodings for proof bundles and `Accept`/`Content-Type` negotiation.
+//!
+//! JSON is always understood; CBOR and bincode carry proof bytes as raw
+//! byte strings instead of number arrays and are preferred when offered.
+
+use crate::resolver::ProofBundle;
+use crate::ZkURLError;
+
+/// `Accept` header sent by the resolver, binary encodings first.
+pub const ACCEPT_BUNDLE: &str =
+    "application/cbor, application/octet-stream+bincode;q=0.9, application/json;q=0.5";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BundleEncoding {
+    Json,
+    Cbor,
+    Bincode,
+}
+
+impl BundleEncoding {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BundleEncoding::Json => "application/json",
+            BundleEncoding::Cbor => "application/cbor",
+            BundleEncoding::Bincode => "application/octet-stream+bincode",
+        }
+    }
+
+    /// Encoding named by a `Content-Type` header; parameters such as
+    /// `; charset=utf-8` are ignored. Unknown types fall back to JSON.
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        let mime = content_type
+            .and_then(|ct| ct.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase());
+        match mime.as_deref() {
+            Some("application/cbor") => BundleEncoding::Cbor,
+            Some("application/octet-stream+bincode") => BundleEncoding::Bincode,
+            _ => BundleEncoding::Json,
+        }
+    }
+
+    /// Server side: the best encoding a client's `Accept` header allows.
+    /// Highest q-value wins, ties go to the more compact encoding, and
+    /// anything unparseable (or a missing header) gets JSON.
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let mut best = (0.0f32, BundleEncoding::Json);
+        for item in accept.unwrap_or("").split(',') {
+            let mut parts = item.split(';');
+            let mime = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            let encoding = match mime.as_str() {
+                "application/cbor" => BundleEncoding::Cbor,
+                "application/octet-stream+bincode" => BundleEncoding::Bincode,
+                "application/json" | "*/*" | "application/*" => BundleEncoding::Json,
+                _ => continue,
+            };
+            if q > best.0 || (q == best.0 && encoding.rank() < best.1.rank()) {
+                best = (q, encoding);
+            }
+        }
+        best.1
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            BundleEncoding::Cbor => 0,
+            BundleEncoding::Bincode => 1,
+            BundleEncoding::Json => 2,
+        }
+    }
+}
+
+/// Serializes a bundle for serving with `encoding.content_type()`.
+pub fn encode_bundle(bundle: &ProofBundle, encoding: BundleEncoding) -> Result<Vec<u8>, ZkURLError> {
+    let encode_error = |reason: String| ZkURLError::InvalidComponent { component: "bundle", reason };
+    match encoding {
+        BundleEncoding::Json => serde_json::to_vec(bundle).map_err(|e| encode_error(e.to_string())),
+        BundleEncoding::Cbor => {
+            let mut out = Vec::new();
+            ciborium::into_writer(bundle, &mut out).map_err(|e| encode_error(e.to_string()))?;
+            Ok(out)
+        }
+        BundleEncoding::Bincode => bincode::serialize(bundle).map_err(|e| encode_error(e.to_string())),
+    }
+}
+
+pub fn decode_bundle(body: &[u8], encoding: BundleEncoding) -> Result<ProofBundle, ZkURLError> {
+    let decode_error = |e: String| ZkURLError::Network(format!("Failed to parse {}: {}", encoding.content_type(), e));
+    match encoding {
+        BundleEncoding::Json => serde_json::from_slice(body).map_err(|e| decode_error(e.to_string())),
+        BundleEncoding::Cbor => ciborium::from_reader(body).map_err(|e| decode_error(e.to_string())),
+        BundleEncoding::Bincode => bincode::deserialize(body).map_err(|e| decode_error(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{ProofMetadata, PublicInputs};
+
+    fn bundle() -> ProofBundle {
+        ProofBundle {
+            proof: (0..=255).collect(),
+            public_inputs: PublicInputs {
+                block_hash: "0xabc".to_string(),
+                state_root: "0xdef".to_string(),
+                gas_used: 21_000,
+                transaction_count: 1,
+            },
+            signature: "sig".to_string(),
+            prover_id: "prover".to_string(),
+            timestamp: 1_700_000_000,
+            metadata: ProofMetadata {
+                version: "v1".to_string(),
+                compression: None,
+                size_bytes: 256,
+            },
+        }
+    }
+
+    #[test]
+    fn every_encoding_roundtrips_and_binary_is_smaller() {
+        let json = encode_bundle(&bundle(), BundleEncoding::Json).unwrap();
+        for encoding in [BundleEncoding::Json, BundleEncoding::Cbor, BundleEncoding::Bincode] {
+            let bytes = encode_bundle(&bundle(), encoding).unwrap();
+            assert_eq!(decode_bundle(&bytes, encoding).unwrap().proof, bundle().proof);
+            if encoding != BundleEncoding::Json {
+                assert!(bytes.len() * 2 < json.len(), "{:?} is {} bytes", encoding, bytes.len());
+            }
+        }
+    }
+
+    #[test]
+    fn content_type_detection() {
+        assert_eq!(BundleEncoding::from_content_type(Some("application/CBOR")), BundleEncoding::Cbor);
+        assert_eq!(
+            BundleEncoding::from_content_type(Some("application/json; charset=utf-8")),
+            BundleEncoding::Json
+        );
+        assert_eq!(BundleEncoding::from_content_type(None), BundleEncoding::Json);
+    }
+
+    #[test]
+    fn negotiation_honours_q_values() {
+        assert_eq!(BundleEncoding::negotiate(Some(ACCEPT_BUNDLE)), BundleEncoding::Cbor);
+        assert_eq!(
+            BundleEncoding::negotiate(Some("application/json, application/octet-stream+bincode;q=0.8")),
+            BundleEncoding::Json
+        );
+        assert_eq!(BundleEncoding::negotiate(Some("text/html")), BundleEncoding::Json);
+        assert_eq!(BundleEncoding::negotiate(None), BundleEncoding::Json);
+    }
+}
@@ -0,0 +1,85 @@
+//! Decompression of `Content-Encoding` bodies and metadata-declared proof
+//! compression, with output capped to defuse decompression bombs.
+
+use std::io::Read;
+
+use crate::ZkURLError;
+
+/// `Accept-Encoding` header sent by the resolver.
+pub const ACCEPT_ENCODING: &str = "zstd, gzip";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Parses a `Content-Encoding` value or a zkURL `compression` hint.
+    /// `None` means identity; unsupported codings are an error rather than
+    /// being passed through as garbage proof bytes.
+    pub fn parse(name: &str) -> Result<Option<Self>, ZkURLError> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "identity" | "none" => Ok(None),
+            "gzip" | "x-gzip" => Ok(Some(Compression::Gzip)),
+            "zstd" => Ok(Some(Compression::Zstd)),
+            other => Err(ZkURLError::InvalidComponent {
+                component: "compression",
+                reason: format!("unsupported compression {:?}", other),
+            }),
+        }
+    }
+}
+
+/// Decompresses `data`, failing once the output would exceed `limit` bytes.
+pub fn decompress(data: &[u8], compression: Compression, limit: u64) -> Result<Vec<u8>, ZkURLError> {
+    let corrupt = |e: std::io::Error| ZkURLError::ContentMismatch(format!("corrupt {:?} data: {}", compression, e));
+    let reader: Box<dyn Read + '_> = match compression {
+        Compression::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(data).map_err(corrupt)?),
+    };
+    // Read one byte past the limit to tell "exactly at limit" from "over"
+    let mut out = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut out).map_err(corrupt)?;
+    if out.len() as u64 > limit {
+        return Err(ZkURLError::TooLarge { limit, size: out.len() as u64 });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrips_gzip_and_zstd() {
+        let data = b"proof bytes ".repeat(100);
+        assert_eq!(decompress(&gzip(&data), Compression::Gzip, 4096).unwrap(), data);
+        let zstd = zstd::encode_all(&data[..], 3).unwrap();
+        assert_eq!(decompress(&zstd, Compression::Zstd, 4096).unwrap(), data);
+    }
+
+    #[test]
+    fn caps_decompression_bombs() {
+        let bomb = gzip(&vec![0u8; 1 << 20]);
+        assert!(bomb.len() < 4096);
+        assert_eq!(
+            decompress(&bomb, Compression::Gzip, 1024),
+            Err(ZkURLError::TooLarge { limit: 1024, size: 1025 })
+        );
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!(Compression::parse("GZIP").unwrap(), Some(Compression::Gzip));
+        assert_eq!(Compression::parse("identity").unwrap(), None);
+        assert!(Compression::parse("br").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Streaming proof downloads with a hard size cap, `Range` resume,
+//! `ETag` revalidation and progress reporting.
+
+use reqwest::header::{
+    ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE,
+};
+use reqwest::{Client, Method, StatusCode};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::{self, redact_url, Credential};
+use crate::codec::ACCEPT_BUNDLE;
+use crate::compression::{self, decompress, Compression};
+use crate::ZkURLError;
+
+/// Default cap on a downloaded bundle body. JSON number arrays inflate
+/// proof bytes up to ~4x, so this leaves room for a 5 MB proof.
+pub const DEFAULT_MAX_BODY_BYTES: u64 = 24 * 1024 * 1024;
+
+/// How many times an interrupted body is resumed before giving up.
+const MAX_RESUMES: u32 = 3;
+
+/// Bytes received so far for one download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub received: u64,
+    /// From `Content-Length`, when the server sent one
+    pub total: Option<u64>,
+}
+
+/// A fully received response body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downloaded {
+    pub body: Vec<u8>,
+    /// `Content-Type` of the response, for picking a decoder
+    pub content_type: Option<String>,
+    /// `ETag` of the response, for revalidating a cached copy later
+    pub etag: Option<String>,
+}
+
+/// Called after every received chunk, e.g. to drive a mobile progress bar.
+pub type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+/// Downloads `url` into memory without ever holding more than `max_bytes`.
+/// The request advertises every bundle encoding in `ACCEPT_BUNDLE` and
+/// gzip/zstd transfer compression; a compressed body is decoded before it
+/// is returned, and must also fit in `max_bytes` once decoded.
+///
+/// A `Content-Length` above the limit is rejected before the body is read.
+/// If the connection drops mid-body and the server advertised
+/// `Accept-Ranges: bytes`, the download resumes from the last received byte.
+pub async fn download(
+    client: &Client,
+    method: Method,
+    url: &str,
+    timeout: Duration,
+    max_bytes: u64,
+    progress: Option<&ProgressCallback>,
+    credential: Option<&Credential>,
+) -> Result<Downloaded, ZkURLError> {
+    let request = Request { method, url, if_none_match: None, credential, timeout, max_bytes, progress };
+    fetch(client, request)
+        .await?
+        .ok_or(ZkURLError::HttpStatus(StatusCode::NOT_MODIFIED.as_u16()))
+}
+
+/// Conditional `GET` of `url` with `If-None-Match: etag`. Returns `None`
+/// when the server answers `304 Not Modified`, so an unchanged bundle costs
+/// a round trip instead of a download; otherwise behaves like `download`.
+pub async fn download_if_none_match(
+    client: &Client,
+    url: &str,
+    etag: &str,
+    timeout: Duration,
+    max_bytes: u64,
+    progress: Option<&ProgressCallback>,
+    credential: Option<&Credential>,
+) -> Result<Option<Downloaded>, ZkURLError> {
+    let request = Request { method: Method::GET, url, if_none_match: Some(etag), credential, timeout, max_bytes, progress };
+    fetch(client, request).await
+}
+
+struct Request<'a> {
+    method: Method,
+    url: &'a str,
+    if_none_match: Option<&'a str>,
+    credential: Option<&'a Credential>,
+    timeout: Duration,
+    max_bytes: u64,
+    progress: Option<&'a ProgressCallback>,
+}
+
+async fn fetch(client: &Client, request: Request<'_>) -> Result<Option<Downloaded>, ZkURLError> {
+    let Request { method, url, if_none_match, credential, timeout, max_bytes, progress } = request;
+    let mut body: Vec<u8> = Vec::new();
+    let mut total = None;
+    let mut content_type = None;
+    let mut etag = None;
+    let mut content_encoding = None;
+    let mut resumes = 0;
+
+    loop {
+        let mut request = client
+            .request(method.clone(), url)
+            .header(ACCEPT, ACCEPT_BUNDLE)
+            .header(ACCEPT_ENCODING, compression::ACCEPT_ENCODING)
+            .timeout(timeout);
+        if !body.is_empty() {
+            request = request.header(RANGE, format!("bytes={}-", body.len()));
+        } else if let Some(etag) = if_none_match {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let mut response = auth::send(request, credential).await.map_err(ZkURLError::Network)?;
+
+        match response.status() {
+            StatusCode::NOT_MODIFIED if if_none_match.is_some() && body.is_empty() => return Ok(None),
+            // The server ignored the range; start over
+            StatusCode::OK => body.clear(),
+            StatusCode::PARTIAL_CONTENT if !body.is_empty() => {}
+            status if status.is_success() => body.clear(),
+            status => return Err(ZkURLError::HttpStatus(status.as_u16())),
+        }
+
+        if content_type.is_none() || response.status() == StatusCode::OK {
+            content_type = response.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string);
+            etag = response.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(str::to_string);
+            content_encoding = match response.headers().get(CONTENT_ENCODING) {
+                Some(v) => Compression::parse(v.to_str().unwrap_or("unknown"))?,
+                None => None,
+            };
+        }
+        let content_length = header_u64(&response, CONTENT_LENGTH);
+        if let Some(len) = content_length {
+            let size = body.len() as u64 + len;
+            if size > max_bytes {
+                return Err(ZkURLError::TooLarge { limit: max_bytes, size });
+            }
+            total = Some(size);
+        }
+        let resumable = response
+            .headers()
+            .get(ACCEPT_RANGES)
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"bytes"));
+
+        loop {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    let size = (body.len() + chunk.len()) as u64;
+                    if size > max_bytes {
+                        return Err(ZkURLError::TooLarge { limit: max_bytes, size });
+                    }
+                    body.extend_from_slice(&chunk);
+                    if let Some(progress) = progress {
+                        progress(&DownloadProgress {
+                            url: redact_url(url),
+                            received: body.len() as u64,
+                            total,
+                        });
+                    }
+                }
+                Ok(None) => {
+                    if let Some(compression) = content_encoding {
+                        body = decompress(&body, compression, max_bytes)?;
+                    }
+                    return Ok(Some(Downloaded { body, content_type, etag }));
+                }
+                Err(_) if resumable && !body.is_empty() && resumes < MAX_RESUMES => {
+                    resumes += 1;
+                    break;
+                }
+                Err(e) => return Err(ZkURLError::Network(format!("Failed to read body: {}", e.without_url()))),
+            }
+        }
+    }
+}
+
+fn header_u64(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<u64> {
+    response.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves each canned raw HTTP response to one connection, in order,
+    /// and records the request heads it received.
+    async fn serve(responses: Vec<Vec<u8>>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/proof/p1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut head = vec![0u8; 4096];
+                let n = socket.read(&mut head).await.unwrap();
+                seen.lock().unwrap().push(String::from_utf8_lossy(&head[..n]).to_string());
+                let _ = socket.write_all(&response).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (url, requests)
+    }
+
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut out = format!("HTTP/1.1 {}\r\nconnection: close\r\n{}\r\n", status, headers).into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_content_length_before_reading() {
+        let (url, _) = serve(vec![response("200 OK", "content-length: 100\r\n", &[0; 100])]).await;
+        let result = download(&Client::new(), Method::GET, &url, Duration::from_secs(5), 50, None, None).await;
+        assert_eq!(result, Err(ZkURLError::TooLarge { limit: 50, size: 100 }));
+    }
+
+    #[tokio::test]
+    async fn caps_streamed_body_without_content_length() {
+        let (url, _) = serve(vec![response("200 OK", "", &[7; 100])]).await;
+        let result = download(&Client::new(), Method::GET, &url, Duration::from_secs(5), 50, None, None).await;
+        assert!(matches!(result, Err(ZkURLError::TooLarge { limit: 50, .. })));
+    }
+
+    #[tokio::test]
+    async fn decodes_content_encoding() {
+        let compressed = zstd::encode_all(&b"bundle"[..], 3).unwrap();
+        let headers = format!("content-length: {}\r\ncontent-encoding: zstd\r\n", compressed.len());
+        let (url, requests) = serve(vec![response("200 OK", &headers, &compressed)]).await;
+        let downloaded = download(&Client::new(), Method::GET, &url, Duration::from_secs(5), 1024, None, None).await.unwrap();
+        assert_eq!(downloaded.body, b"bundle");
+        assert!(requests.lock().unwrap()[0].to_ascii_lowercase().contains("accept-encoding: zstd, gzip"));
+    }
+
+    #[tokio::test]
+    async fn resumes_interrupted_body_with_range_and_reports_progress() {
+        let (url, requests) = serve(vec![
+            // Promises 10 bytes but hangs up after 4
+            response("200 OK", "content-length: 10\r\naccept-ranges: bytes\r\ncontent-type: application/cbor\r\n", b"0123"),
+            response("206 Partial Content", "content-length: 6\r\naccept-ranges: bytes\r\n", b"456789"),
+        ])
+        .await;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        let progress: ProgressCallback = Arc::new(move |p: &DownloadProgress| sink.lock().unwrap().push(p.received));
+
+        let downloaded = download(&Client::new(), Method::GET, &url, Duration::from_secs(5), 1024, Some(&progress), None).await.unwrap();
+        assert_eq!(downloaded.body, b"0123456789");
+        assert!(requests.lock().unwrap()[1].to_ascii_lowercase().contains("range: bytes=4-"));
+        assert_eq!(seen.lock().unwrap().last(), Some(&10));
+        assert_eq!(downloaded.content_type.as_deref(), Some("application/cbor"));
+    }
+
+    #[tokio::test]
+    async fn conditional_request_returns_none_on_not_modified() {
+        let (url, requests) = serve(vec![
+            response("304 Not Modified", "etag: \"v1\"\r\n", b""),
+            response("200 OK", "content-length: 6\r\netag: \"v2\"\r\n", b"bundle"),
+        ])
+        .await;
+        let client = Client::new();
+        let unchanged = download_if_none_match(&client, &url, "\"v1\"", Duration::from_secs(5), 1024, None, None).await;
+        assert_eq!(unchanged, Ok(None));
+        assert!(requests.lock().unwrap()[0].to_ascii_lowercase().contains("if-none-match: \"v1\""));
+
+        let changed = download_if_none_match(&client, &url, "\"v1\"", Duration::from_secs(5), 1024, None, None).await.unwrap().unwrap();
+        assert_eq!(changed.body, b"bundle");
+        assert_eq!(changed.etag.as_deref(), Some("\"v2\""));
+    }
+
+    #[tokio::test]
+    async fn credentials_stay_on_their_origin_across_redirects() {
+        let (elsewhere, foreign) = serve(vec![response("200 OK", "content-length: 6\r\n", b"bundle")]).await;
+        let (url, home) = serve(vec![
+            response("302 Found", "location: /proof/moved\r\n", b""),
+            response("307 Temporary Redirect", &format!("location: {}\r\n", elsewhere), b""),
+        ])
+        .await;
+        let client = crate::auth::TlsConfig::default()
+            .build_client(Duration::from_secs(5), &crate::proxy::ProxyConfig::default())
+            .unwrap();
+        let credential = Credential::header("x-api-key", "k3y").unwrap();
+        let downloaded = download(&client, Method::GET, &url, Duration::from_secs(5), 1024, None, Some(&credential)).await.unwrap();
+        assert_eq!(downloaded.body, b"bundle");
+        let home = home.lock().unwrap();
+        assert!(home[1].starts_with("GET /proof/moved "));
+        assert!(home.iter().all(|head| head.contains("x-api-key: k3y")));
+        assert!(!foreign.lock().unwrap()[0].contains("k3y"));
+    }
+}
@@ -0,0 +1,552 @@
+//! Prover gateway: requesting proofs from external prover farms.
+//!
+//! A farm takes proving jobs over HTTP:
+//!
+//! - `POST /jobs` with a `JobRequest` queues a job and answers with its
+//!   `JobStatus`; `429` or `503` when its queue is full
+//! - `GET /jobs/{job_id}` returns the job's `JobStatus`
+//! - `GET /jobs/{job_id}/events` streams each new `JobStatus` as a
+//!   server-sent event, ending after the job is done or failed
+//!
+//! A done job carries an `Attestation`, signed with the farm's prover
+//! key, of which circuit version proved which target into the proof at
+//! which zkURL. `ProverGateway` submits to the configured farms in order,
+//! polls the job to completion, checks the attestation against the farm's
+//! key and the target it asked for, and fails over to the next farm if a
+//! submission is refused, the job fails or stalls, or the attestation
+//! does not check out. Farms whose circuit is open (see `CircuitBreaker`)
+//! are skipped.
+//!
+//! At most `max_in_flight` jobs run at once per gateway; further calls to
+//! `prove` queue until one finishes.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use futures::stream::{self, BoxStream, StreamExt};
+use reqwest::header::ACCEPT;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::auth::{self, redact_url, Credential, TlsConfig};
+use crate::hedge::origin;
+use crate::proxy::ProxyConfig;
+use crate::resolver::PublicInputs;
+use crate::retry::{CircuitBreaker, EndpointAttempt, ResolveError};
+use crate::{ZkURL, ZkURLError};
+
+/// Jobs a gateway runs at once unless configured otherwise.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 4;
+
+/// Status polls in a row that may fail before a job counts as stalled.
+const MAX_POLL_FAILURES: u32 = 3;
+
+/// What to prove.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTarget {
+    /// A block the farm fetches and re-executes itself
+    Block { block_hash: String, height: u64 },
+    /// An execution trace the producer already has
+    Trace {
+        #[serde(with = "serde_bytes")]
+        trace: Vec<u8>,
+        public_inputs: PublicInputs,
+    },
+}
+
+impl JobTarget {
+    /// blake3 digest of the target, as attestations commit to it.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        match self {
+            JobTarget::Block { block_hash, height } => {
+                hasher.update(&[0]);
+                hasher.update(&(block_hash.len() as u64).to_le_bytes());
+                hasher.update(block_hash.as_bytes());
+                hasher.update(&height.to_le_bytes());
+            }
+            JobTarget::Trace { trace, public_inputs } => {
+                hasher.update(&[1]);
+                hasher.update(blake3::hash(trace).as_bytes());
+                hasher.update(&public_inputs.transcript_bytes());
+            }
+        }
+        *hasher.finalize().as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobRequest {
+    pub target: JobTarget,
+    /// Circuit version the proof must use; the farm's choice when unset
+    #[serde(default)]
+    pub circuit_version: Option<String>,
+}
+
+/// A farm's signed statement that `circuit_version` proved the target
+/// with digest `target` into the proof at `zkurl`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub job_id: String,
+    pub prover_id: String,
+    pub circuit_version: String,
+    /// Hex of `JobTarget::digest`
+    pub target: String,
+    pub zkurl: ZkURL,
+    pub timestamp: u64,
+    /// Hex-encoded ed25519 signature over `signing_bytes`
+    pub signature: String,
+}
+
+impl Attestation {
+    /// Bytes the signature covers: every field but the signature.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut out = b"cubiq-prover-attestation-v1".to_vec();
+        let zkurl = self.zkurl.to_string();
+        for field in [&self.job_id, &self.prover_id, &self.circuit_version, &self.target, &zkurl] {
+            out.extend_from_slice(&(field.len() as u64).to_le_bytes());
+            out.extend_from_slice(field.as_bytes());
+        }
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+        out
+    }
+
+    /// Signs as the farm holding `key`.
+    pub fn sign(&mut self, key: &SigningKey) {
+        self.signature = hex::encode(key.sign(&self.signing_bytes()).to_bytes());
+    }
+
+    pub fn verify_signature(&self, key: &VerifyingKey) -> bool {
+        let Some(signature) = hex::decode(&self.signature).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) else {
+            return false;
+        };
+        key.verify_strict(&self.signing_bytes(), &signature).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a prover; `position` 0 is next
+    Queued {
+        #[serde(default)]
+        position: Option<u64>,
+    },
+    Proving {
+        /// 0.0-1.0, when the farm can tell
+        #[serde(default)]
+        progress: Option<f64>,
+    },
+    Done { attestation: Box<Attestation> },
+    Failed { reason: String },
+}
+
+impl JobState {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, JobState::Done { .. } | JobState::Failed { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub job_id: String,
+    #[serde(flatten)]
+    pub state: JobState,
+}
+
+/// A job submitted to a farm, enough to poll it again after a restart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    /// The farm's base URL
+    pub endpoint: String,
+    pub prover_id: String,
+    pub job_id: String,
+}
+
+/// A proof a farm produced, at `attestation.zkurl`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proved {
+    pub job: Job,
+    pub attestation: Attestation,
+}
+
+/// A prover farm and the key its attestations are signed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GatewayEndpoint {
+    pub url: String,
+    pub prover_id: String,
+    pub key: VerifyingKey,
+}
+
+pub struct ProverGateway {
+    client: Client,
+    endpoints: Vec<GatewayEndpoint>,
+    /// Keyed by endpoint origin
+    credentials: HashMap<String, Credential>,
+    /// Circuit versions attestations may name; any when empty
+    circuit_versions: Vec<String>,
+    timeout: Duration,
+    poll_interval: Duration,
+    job_timeout: Duration,
+    in_flight: Semaphore,
+    circuit_breaker: CircuitBreaker,
+}
+
+impl Default for ProverGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProverGateway {
+    pub fn new() -> Self {
+        Self {
+            client: TlsConfig::default()
+                .build_client(Duration::from_secs(30), &ProxyConfig::default())
+                .expect("Failed to build HTTP client"),
+            endpoints: Vec::new(),
+            credentials: HashMap::new(),
+            circuit_versions: Vec::new(),
+            timeout: Duration::from_secs(30),
+            poll_interval: Duration::from_secs(2),
+            job_timeout: Duration::from_secs(600),
+            in_flight: Semaphore::new(DEFAULT_MAX_IN_FLIGHT),
+            circuit_breaker: CircuitBreaker::default(),
+        }
+    }
+
+    /// Adds a farm, tried after those added before it.
+    pub fn with_endpoint(mut self, url: impl Into<String>, prover_id: impl Into<String>, key: VerifyingKey) -> Self {
+        let url = url.into().trim_end_matches('/').to_string();
+        self.endpoints.push(GatewayEndpoint { url, prover_id: prover_id.into(), key });
+        self
+    }
+
+    /// Authenticate requests to `endpoint`'s origin with `credential`.
+    pub fn with_credential(mut self, endpoint: &str, credential: Credential) -> Self {
+        self.credentials.insert(origin(endpoint).to_string(), credential);
+        self
+    }
+
+    /// Accept only proofs attested to one of `versions`.
+    pub fn with_circuit_versions(mut self, versions: Vec<String>) -> Self {
+        self.circuit_versions = versions;
+        self
+    }
+
+    /// Timeout of each HTTP request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long one farm gets to finish a job before `prove` fails over.
+    pub fn with_job_timeout(mut self, job_timeout: Duration) -> Self {
+        self.job_timeout = job_timeout;
+        self
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.in_flight = Semaphore::new(max_in_flight.max(1));
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = circuit_breaker;
+        self
+    }
+
+    /// Proves `request` on the first farm that delivers an attested proof.
+    pub async fn prove(&self, request: &JobRequest) -> Result<Proved, ZkURLError> {
+        if self.endpoints.is_empty() {
+            return Err(ZkURLError::Config("no prover gateway endpoints configured".to_string()));
+        }
+        let _permit = self.in_flight.acquire().await.expect("gateway semaphore is never closed");
+        let mut failures = ResolveError::default();
+        for endpoint in &self.endpoints {
+            if !self.circuit_breaker.allows(&endpoint.url) {
+                let error = "circuit open".to_string();
+                failures.attempts.push(EndpointAttempt { url: redact_url(&endpoint.url), attempts: 0, error });
+                continue;
+            }
+            match self.prove_on(endpoint, request).await {
+                Ok(proved) => {
+                    self.circuit_breaker.record_success(&endpoint.url);
+                    return Ok(proved);
+                }
+                Err(e) => {
+                    tracing::warn!(endpoint = %redact_url(&endpoint.url), error = %e, "proving job failed, failing over");
+                    self.circuit_breaker.record_failure(&endpoint.url);
+                    failures.attempts.push(EndpointAttempt { url: redact_url(&endpoint.url), attempts: 1, error: e.to_string() });
+                }
+            }
+        }
+        Err(ZkURLError::Resolve(failures))
+    }
+
+    async fn prove_on(&self, endpoint: &GatewayEndpoint, request: &JobRequest) -> Result<Proved, ZkURLError> {
+        let job = self.submit(endpoint, request).await?;
+        let outcome = tokio::time::timeout(self.job_timeout, self.wait(&job)).await;
+        let attestation = outcome.map_err(|_| ZkURLError::Network(format!("job {} timed out", job.job_id)))??;
+        self.check(endpoint, request, &job, &attestation)?;
+        Ok(Proved { job, attestation })
+    }
+
+    /// Queues `request` on `endpoint` without waiting for it.
+    pub async fn submit(&self, endpoint: &GatewayEndpoint, request: &JobRequest) -> Result<Job, ZkURLError> {
+        let url = format!("{}/jobs", endpoint.url);
+        let status: JobStatus = self.send(self.client.post(&url).json(request), &url).await?;
+        Ok(Job { endpoint: endpoint.url.clone(), prover_id: endpoint.prover_id.clone(), job_id: status.job_id })
+    }
+
+    pub async fn status(&self, job: &Job) -> Result<JobStatus, ZkURLError> {
+        let url = format!("{}/jobs/{}", job.endpoint, job.job_id);
+        self.send(self.client.get(&url), &url).await
+    }
+
+    /// The job's statuses as the farm reports them, ending after a done
+    /// or failed one.
+    pub fn watch(&self, job: &Job) -> BoxStream<'static, Result<JobStatus, ZkURLError>> {
+        let url = format!("{}/jobs/{}/events", job.endpoint, job.job_id);
+        let request = self.client.get(&url).header(ACCEPT, "text/event-stream");
+        let credential = self.credentials.get(origin(&url)).cloned();
+        let opened = async move {
+            let response = auth::send(request, credential.as_ref())
+                .await
+                .map_err(|e| ZkURLError::Network(format!("watch {} failed: {}", redact_url(&url), e)))?;
+            if !response.status().is_success() {
+                return Err(ZkURLError::HttpStatus(response.status().as_u16()));
+            }
+            Ok(response)
+        };
+        stream::once(opened)
+            .flat_map(|opened| match opened {
+                Ok(response) => events(response).boxed(),
+                Err(e) => stream::once(async { Err(e) }).boxed(),
+            })
+            .boxed()
+    }
+
+    /// Polls `job` until it is done, returning its attestation.
+    async fn wait(&self, job: &Job) -> Result<Attestation, ZkURLError> {
+        let mut failures = 0;
+        loop {
+            match self.status(job).await {
+                Ok(JobStatus { state: JobState::Done { attestation }, .. }) => return Ok(*attestation),
+                Ok(JobStatus { state: JobState::Failed { reason }, .. }) => {
+                    return Err(ZkURLError::Network(format!("job {} failed: {}", job.job_id, reason)))
+                }
+                Ok(_) => failures = 0,
+                Err(e) => {
+                    failures += 1;
+                    if failures >= MAX_POLL_FAILURES {
+                        return Err(e);
+                    }
+                }
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Checks the attestation is the farm's and for what was asked.
+    fn check(&self, endpoint: &GatewayEndpoint, request: &JobRequest, job: &Job, attestation: &Attestation) -> Result<(), ZkURLError> {
+        let mismatch = |what: &str| Err(ZkURLError::ContentMismatch(format!("attestation for job {}: {}", job.job_id, what)));
+        if !attestation.verify_signature(&endpoint.key) {
+            return mismatch("not signed by the prover's key");
+        }
+        if attestation.prover_id != endpoint.prover_id || attestation.job_id != job.job_id {
+            return mismatch("names another prover or job");
+        }
+        if attestation.target != hex::encode(request.target.digest()) {
+            return mismatch("proves another target");
+        }
+        let version = &attestation.circuit_version;
+        if request.circuit_version.as_ref().is_some_and(|wanted| wanted != version)
+            || (!self.circuit_versions.is_empty() && !self.circuit_versions.contains(version))
+        {
+            return mismatch(&format!("circuit version {} is not accepted", version));
+        }
+        Ok(())
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, request: RequestBuilder, url: &str) -> Result<T, ZkURLError> {
+        let response = auth::send(request.timeout(self.timeout), self.credentials.get(origin(url)))
+            .await
+            .map_err(|e| ZkURLError::Network(format!("{} failed: {}", redact_url(url), e)))?;
+        if !response.status().is_success() {
+            return Err(ZkURLError::HttpStatus(response.status().as_u16()));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| ZkURLError::Network(format!("Failed to parse job status: {}", e)))
+    }
+}
+
+/// Statuses in the `data:` lines of a server-sent event stream.
+fn events(response: reqwest::Response) -> impl futures::Stream<Item = Result<JobStatus, ZkURLError>> {
+    stream::unfold(Some((response, String::new())), |state| async move {
+        let (mut response, mut buffer) = state?;
+        loop {
+            if let Some(end) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..end + 2).collect();
+                let data: Vec<&str> = event.lines().filter_map(|line| line.strip_prefix("data:")).map(str::trim_start).collect();
+                if data.is_empty() {
+                    continue;
+                }
+                return Some(match serde_json::from_str::<JobStatus>(&data.join("\n")) {
+                    Ok(status) if status.state.is_terminal() => (Ok(status), None),
+                    Ok(status) => (Ok(status), Some((response, buffer))),
+                    Err(e) => (Err(ZkURLError::Network(format!("Failed to parse job event: {}", e))), None),
+                });
+            }
+            match response.chunk().await {
+                Ok(Some(chunk)) => buffer.push_str(&String::from_utf8_lossy(&chunk).replace("\r\n", "\n")),
+                Ok(None) => return None,
+                Err(e) => return Some((Err(ZkURLError::Network(format!("job events: {}", e.without_url()))), None)),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// How a mock farm behaves.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Farm {
+        Refuses,
+        Fails,
+        /// Signs with a key other than the one configured for it
+        Forges,
+        Proves,
+    }
+
+    struct Mock {
+        farm: Farm,
+        prover_id: &'static str,
+        key: SigningKey,
+        request: std::sync::Mutex<Option<JobRequest>>,
+        polls: AtomicU32,
+    }
+
+    impl Mock {
+        fn state(&self) -> JobState {
+            if self.polls.fetch_add(1, Ordering::SeqCst) == 0 {
+                return JobState::Queued { position: Some(0) };
+            }
+            if self.farm == Farm::Fails {
+                return JobState::Failed { reason: "out of memory".to_string() };
+            }
+            let request = self.request.lock().unwrap().clone().unwrap();
+            let zkurl: ZkURL = format!("zk://{}@proofs.example.com/job1", self.prover_id).parse().unwrap();
+            let mut attestation = Attestation {
+                job_id: "job1".to_string(),
+                prover_id: self.prover_id.to_string(),
+                circuit_version: "v2".to_string(),
+                target: hex::encode(request.target.digest()),
+                zkurl,
+                timestamp: 100,
+                signature: String::new(),
+            };
+            let key = if self.farm == Farm::Forges { SigningKey::from_bytes(&[9; 32]) } else { self.key.clone() };
+            attestation.sign(&key);
+            JobState::Done { attestation: Box::new(attestation) }
+        }
+    }
+
+    async fn submit(State(mock): State<Arc<Mock>>, Json(request): Json<JobRequest>) -> axum::response::Response {
+        if mock.farm == Farm::Refuses {
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+        *mock.request.lock().unwrap() = Some(request);
+        Json(JobStatus { job_id: "job1".to_string(), state: JobState::Queued { position: Some(3) } }).into_response()
+    }
+
+    async fn status(State(mock): State<Arc<Mock>>, Path(_): Path<String>) -> Json<JobStatus> {
+        Json(JobStatus { job_id: "job1".to_string(), state: mock.state() })
+    }
+
+    async fn events(State(mock): State<Arc<Mock>>, Path(_): Path<String>) -> impl IntoResponse {
+        let mut body = String::from(": keepalive\n\n");
+        for _ in 0..2 {
+            let status = JobStatus { job_id: "job1".to_string(), state: mock.state() };
+            body.push_str(&format!("event: status\r\ndata: {}\r\n\r\n", serde_json::to_string(&status).unwrap()));
+        }
+        ([("content-type", "text/event-stream")], body)
+    }
+
+    async fn spawn_farm(farm: Farm, prover_id: &'static str, seed: u8) -> (String, VerifyingKey) {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let verifying_key = key.verifying_key();
+        let mock = Arc::new(Mock { farm, prover_id, key, request: Default::default(), polls: AtomicU32::new(0) });
+        let app = Router::new()
+            .route("/jobs", post(submit))
+            .route("/jobs/:job_id", get(status))
+            .route("/jobs/:job_id/events", get(events))
+            .with_state(mock);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, verifying_key)
+    }
+
+    fn request() -> JobRequest {
+        JobRequest { target: JobTarget::Block { block_hash: "0xb1".to_string(), height: 7 }, circuit_version: None }
+    }
+
+    #[tokio::test]
+    async fn fails_over_until_a_farm_attests_a_proof() {
+        let mut gateway = ProverGateway::new().with_poll_interval(Duration::from_millis(10));
+        for (farm, prover_id, seed) in [(Farm::Refuses, "a", 1), (Farm::Fails, "b", 2), (Farm::Forges, "c", 3), (Farm::Proves, "d", 4)] {
+            let (url, key) = spawn_farm(farm, prover_id, seed).await;
+            gateway = gateway.with_endpoint(url, prover_id, key);
+        }
+
+        let proved = gateway.prove(&request()).await.unwrap();
+        assert_eq!((proved.job.prover_id.as_str(), proved.attestation.circuit_version.as_str()), ("d", "v2"));
+        assert_eq!(proved.attestation.zkurl.to_string(), "zk://d@proofs.example.com/job1");
+
+        let pinned = JobRequest { circuit_version: Some("v3".to_string()), ..request() };
+        let ZkURLError::Resolve(failures) = gateway.prove(&pinned).await.unwrap_err() else {
+            panic!("expected every farm to fail");
+        };
+        let errors: Vec<&str> = failures.attempts.iter().map(|attempt| attempt.error.as_str()).collect();
+        assert_eq!(errors[0], "HTTP error: 503");
+        assert!(errors[1].contains("out of memory"));
+        assert!(errors[2].contains("not signed by the prover's key"));
+        assert!(errors[3].contains("circuit version v2 is not accepted"));
+    }
+
+    #[tokio::test]
+    async fn streams_statuses_until_the_job_ends() {
+        let (url, key) = spawn_farm(Farm::Proves, "d", 4).await;
+        let gateway = ProverGateway::new().with_endpoint(&url, "d", key);
+        let job = gateway.submit(&gateway.endpoints[0], &request()).await.unwrap();
+        assert_eq!(job.job_id, "job1");
+
+        let statuses: Vec<JobStatus> = gateway.watch(&job).map(Result::unwrap).collect().await;
+        assert!(matches!(statuses[0].state, JobState::Queued { position: Some(0) }));
+        assert!(matches!(statuses[1].state, JobState::Done { .. }));
+        assert!(matches!(gateway.status(&job).await.unwrap().state, JobState::Done { .. }));
+
+        let missing = Job { job_id: "nope".to_string(), endpoint: format!("{}/missing", url), ..job };
+        assert_eq!(gateway.watch(&missing).next().await.unwrap().unwrap_err(), ZkURLError::HttpStatus(404));
+    }
+}
@@ -0,0 +1,128 @@
+//! Per-endpoint latency tracking for hedged proof fetches.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Default delay before each successive endpoint is raced.
+pub const DEFAULT_HEDGE_DELAY: Duration = Duration::from_millis(250);
+
+/// Weight of the newest sample in the moving average.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// Observed behaviour of one endpoint (keyed by URL origin).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EndpointLatency {
+    pub endpoint: String,
+    /// Exponentially weighted latency; failures count as a full timeout
+    pub ewma_ms: f64,
+    pub successes: u64,
+    pub failures: u64,
+}
+
+impl EndpointLatency {
+    /// Lower is healthier: latency inflated by the endpoint's failure rate.
+    pub fn health_score(&self) -> f64 {
+        let total = (self.successes + self.failures).max(1) as f64;
+        self.ewma_ms * (1.0 + self.failures as f64 / total)
+    }
+}
+
+#[derive(Default)]
+pub struct EndpointStats {
+    entries: Mutex<HashMap<String, EndpointLatency>>,
+}
+
+impl EndpointStats {
+    pub fn record_success(&self, url: &str, latency: Duration) {
+        self.record(url, latency, true);
+    }
+
+    /// Records a failed or rejected fetch, penalised as if it took `timeout`.
+    pub fn record_failure(&self, url: &str, timeout: Duration) {
+        self.record(url, timeout, false);
+    }
+
+    fn record(&self, url: &str, latency: Duration, success: bool) {
+        let endpoint = origin(url).to_string();
+        let sample = latency.as_secs_f64() * 1000.0;
+        let mut entries = self.entries.lock().expect("Endpoint stats lock poisoned");
+        let entry = entries.entry(endpoint.clone()).or_insert(EndpointLatency {
+            endpoint,
+            ewma_ms: sample,
+            successes: 0,
+            failures: 0,
+        });
+        entry.ewma_ms = EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * entry.ewma_ms;
+        if success {
+            entry.successes += 1;
+        } else {
+            entry.failures += 1;
+        }
+    }
+
+    /// Orders candidates healthiest first. Endpoints without samples keep
+    /// their configured position ahead of measured ones, so new gateways get
+    /// probed instead of starved; ties keep configured order.
+    pub fn order<T>(&self, candidates: Vec<T>, url: impl Fn(&T) -> &str) -> Vec<T> {
+        let entries = self.entries.lock().expect("Endpoint stats lock poisoned");
+        let mut keyed: Vec<(f64, T)> = candidates
+            .into_iter()
+            .map(|c| (entries.get(origin(url(&c))).map_or(0.0, EndpointLatency::health_score), c))
+            .collect();
+        keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        keyed.into_iter().map(|(_, c)| c).collect()
+    }
+
+    pub fn snapshot(&self) -> Vec<EndpointLatency> {
+        let entries = self.entries.lock().expect("Endpoint stats lock poisoned");
+        let mut snapshot: Vec<_> = entries.values().cloned().collect();
+        snapshot.sort_by(|a, b| a.ewma_ms.total_cmp(&b.ewma_ms));
+        snapshot
+    }
+}
+
+/// `scheme://authority` of a URL, used as the stats key.
+pub(crate) fn origin(url: &str) -> &str {
+    let after_scheme = url.find("://").map_or(0, |i| i + 3);
+    match url[after_scheme..].find('/') {
+        Some(slash) => &url[..after_scheme + slash],
+        None => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_by_latency_with_unmeasured_first() {
+        let stats = EndpointStats::default();
+        stats.record_success("https://fast.dev/proof/a", Duration::from_millis(20));
+        stats.record_failure("https://slow.dev/proof/a", Duration::from_secs(5));
+        let ordered = stats.order(
+            vec![
+                "https://slow.dev/proof/b".to_string(),
+                "https://fast.dev/proof/b".to_string(),
+                "https://new.dev/proof/b".to_string(),
+            ],
+            |url| url.as_str(),
+        );
+        assert_eq!(ordered, vec!["https://new.dev/proof/b", "https://fast.dev/proof/b", "https://slow.dev/proof/b"]);
+    }
+
+    #[test]
+    fn keys_by_origin() {
+        let stats = EndpointStats::default();
+        stats.record_success("https://a.dev:8443/x/proof/1", Duration::from_millis(10));
+        stats.record_success("https://a.dev:8443/y/proof/2", Duration::from_millis(30));
+        stats.record_failure("https://a.dev:8443/z/proof/3", Duration::from_millis(16));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].endpoint, "https://a.dev:8443");
+        assert_eq!(snapshot[0].successes, 2);
+        assert!((snapshot[0].ewma_ms - 16.0).abs() < 1e-9);
+        assert!((snapshot[0].health_score() - 16.0 * (1.0 + 1.0 / 3.0)).abs() < 1e-9);
+    }
+}
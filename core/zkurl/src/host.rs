@@ -0,0 +1,178 @@
+use cid::Cid;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{syntax, ZkURLError};
+
+/// Multihash code for sha2-256.
+const SHA2_256: u64 = 0x12;
+/// Multihash code for blake3.
+const BLAKE3: u64 = 0x1e;
+/// Multicodec code for raw binary blocks.
+const RAW: u64 = 0x55;
+/// Multicodec code for UnixFS (dag-pb) nodes, which every CIDv0 is.
+const DAG_PB: u64 = 0x70;
+
+/// Where a zkURL's proof lives: a DNS-hosted prover endpoint or a
+/// content-addressed IPFS object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZkURLHost {
+    Domain(String),
+    Cid(Cid),
+}
+
+impl ZkURLHost {
+    /// Parses the host component found at byte `offset` of a zkURL.
+    ///
+    /// CIDs never contain '.', so dotted hosts are DNS names. Other hosts must
+    /// be a valid CIDv0/CIDv1, except that a zkURL naming a prover may use a
+    /// single-label domain (e.g. an internal hostname). A CID of any codec
+    /// parses, though only raw blocks resolve; see `require_raw`.
+    pub fn parse(host: &str, offset: usize, has_prover: bool) -> Result<Self, ZkURLError> {
+        if host.is_empty() {
+            return Err(ZkURLError::EmptyComponent {
+                component: "host",
+                offset,
+            });
+        }
+        if !host.contains('.') {
+            match parse_cid(host, offset) {
+                Ok(cid) => return Ok(ZkURLHost::Cid(cid)),
+                Err(e) if !has_prover => return Err(e),
+                Err(_) => {}
+            }
+        }
+        syntax::validate_domain(host, offset)?;
+        Ok(ZkURLHost::Domain(host.to_string()))
+    }
+
+    pub fn as_cid(&self) -> Option<&Cid> {
+        match self {
+            ZkURLHost::Cid(cid) => Some(cid),
+            ZkURLHost::Domain(_) => None,
+        }
+    }
+
+    pub fn as_domain(&self) -> Option<&str> {
+        match self {
+            ZkURLHost::Domain(domain) => Some(domain),
+            ZkURLHost::Cid(_) => None,
+        }
+    }
+}
+
+fn parse_cid(host: &str, offset: usize) -> Result<Cid, ZkURLError> {
+    Cid::from_str(host).map_err(|e| ZkURLError::InvalidComponent {
+        component: "cid",
+        reason: format!("{} at byte {}", e, offset),
+    })
+}
+
+/// Refuses a verified block whose CID is not of raw bytes. A zkURL may
+/// name any CID, but a proof bundle is published as a single raw block; a
+/// dag-pb block (every CIDv0, and CIDv1 with codec 0x70) is a UnixFS node
+/// whose bytes are not the bundle, even when they hash to the CID.
+pub(crate) fn require_raw(cid: &Cid) -> Result<(), ZkURLError> {
+    let reason = match cid.codec() {
+        RAW => return Ok(()),
+        DAG_PB => format!("{} is a dag-pb CID, whose block is a UnixFS node", cid),
+        codec => format!("{} has codec 0x{:x}", cid, codec),
+    };
+    Err(ZkURLError::InvalidComponent {
+        component: "cid",
+        reason: format!("{}; proofs resolve only from raw blocks (codec 0x55)", reason),
+    })
+}
+
+/// CIDv1 of a raw block hashed with sha2-256, as Kubo's `block/put`
+/// produces with `cid-codec=raw`.
+pub fn raw_block_cid(block: &[u8]) -> Cid {
+    let hash = cid::multihash::Multihash::<64>::wrap(SHA2_256, &Sha256::digest(block))
+        .expect("sha2-256 digest fits in a multihash");
+    Cid::new_v1(RAW, hash)
+}
+
+/// Checks that `block` hashes to the multihash inside `cid`.
+///
+/// `block` must be the raw IPLD block (as served by trustless gateways for
+/// `application/vnd.ipld.raw`), not a UnixFS-decoded file. Only sha2-256 and
+/// blake3 multihashes are supported.
+pub fn verify_block(cid: &Cid, block: &[u8]) -> Result<bool, ZkURLError> {
+    let expected = cid.hash();
+    let digest: Vec<u8> = match expected.code() {
+        SHA2_256 => Sha256::digest(block).to_vec(),
+        BLAKE3 => blake3::hash(block).as_bytes().to_vec(),
+        code => {
+            return Err(ZkURLError::InvalidComponent {
+                component: "cid",
+                reason: format!("unsupported multihash code 0x{:x}", code),
+            })
+        }
+    };
+    Ok(digest.get(..expected.size() as usize) == Some(expected.digest()))
+}
+
+impl fmt::Display for ZkURLHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZkURLHost::Domain(domain) => write!(f, "{}", domain),
+            ZkURLHost::Cid(cid) => write!(f, "{}", cid),
+        }
+    }
+}
+
+impl Serialize for ZkURLHost {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ZkURLHost {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let host = String::deserialize(deserializer)?;
+        ZkURLHost::parse(&host, 0, true).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::multihash::Multihash;
+
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const CID_V1: &str = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+
+    #[test]
+    fn parses_cid_versions() {
+        assert!(matches!(ZkURLHost::parse(CID_V0, 0, false), Ok(ZkURLHost::Cid(_))));
+        assert!(matches!(ZkURLHost::parse(CID_V1, 0, false), Ok(ZkURLHost::Cid(_))));
+        assert_eq!(ZkURLHost::parse(CID_V1, 0, false).unwrap().to_string(), CID_V1);
+    }
+
+    #[test]
+    fn rejects_malformed_cid_without_prover() {
+        assert!(matches!(
+            ZkURLHost::parse("QmHash123", 5, false),
+            Err(ZkURLError::InvalidComponent { component: "cid", .. })
+        ));
+    }
+
+    #[test]
+    fn prover_hosts_are_domains() {
+        assert_eq!(
+            ZkURLHost::parse("proofs.cubiq.dev", 0, true).unwrap(),
+            ZkURLHost::Domain("proofs.cubiq.dev".to_string())
+        );
+    }
+
+    #[test]
+    fn verifies_raw_block_against_cid() {
+        let block = b"proof bytes";
+        let hash = Multihash::<64>::wrap(SHA2_256, &Sha256::digest(block)).unwrap();
+        let cid = Cid::new_v1(0x55, hash);
+        assert!(verify_block(&cid, block).unwrap());
+        assert!(!verify_block(&cid, b"tampered").unwrap());
+    }
+}
@@ -0,0 +1,80 @@
+//! Where content-addressed proofs are fetched from: HTTP gateways and an
+//! optional local Kubo node.
+
+use cid::Cid;
+use std::time::Duration;
+
+pub const DEFAULT_IPFS_GATEWAY: &str = "https://ipfs.io";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpfsGateway {
+    /// Gateway origin, e.g. `https://ipfs.io` or `http://127.0.0.1:8080`
+    pub url: String,
+    pub timeout: Duration,
+}
+
+impl IpfsGateway {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            timeout,
+        }
+    }
+
+    /// Trustless-gateway URL returning the raw block, so it can be hashed
+    /// against the CID.
+    pub fn block_url(&self, cid: &Cid) -> String {
+        format!("{}/ipfs/{}?format=raw", self.url, cid)
+    }
+}
+
+/// IPFS sources for CID zkURLs. Gateways are listed in preference order
+/// (self-hosted first); the resolver reorders them by observed health.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpfsConfig {
+    pub gateways: Vec<IpfsGateway>,
+    /// Kubo RPC API origin, e.g. `http://127.0.0.1:5001`. Always tried first.
+    pub kubo_api: Option<String>,
+    pub kubo_timeout: Duration,
+}
+
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        Self {
+            gateways: vec![IpfsGateway::new(DEFAULT_IPFS_GATEWAY, Duration::from_millis(5000))],
+            kubo_api: None,
+            kubo_timeout: Duration::from_millis(2000),
+        }
+    }
+}
+
+impl IpfsConfig {
+    /// Kubo RPC URL for the raw block. `block/get` is used rather than
+    /// `cat`, which returns UnixFS-decoded file contents that do not hash to
+    /// the CID. Kubo's RPC only accepts POST.
+    pub fn kubo_url(&self, cid: &Cid) -> Option<String> {
+        let api = self.kubo_api.as_deref()?.trim_end_matches('/');
+        Some(format!("{}/api/v0/block/get?arg={}", api, cid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+
+    #[test]
+    fn builds_gateway_and_kubo_urls() {
+        let cid = Cid::from_str(CID_V0).unwrap();
+        let config = IpfsConfig {
+            gateways: vec![IpfsGateway::new("http://127.0.0.1:8080/", Duration::from_secs(1))],
+            kubo_api: Some("http://127.0.0.1:5001/".to_string()),
+            ..IpfsConfig::default()
+        };
+        assert_eq!(config.gateways[0].block_url(&cid), format!("http://127.0.0.1:8080/ipfs/{}?format=raw", CID_V0));
+        assert_eq!(config.kubo_url(&cid).unwrap(), format!("http://127.0.0.1:5001/api/v0/block/get?arg={}", CID_V0));
+        assert_eq!(IpfsConfig::default().kubo_url(&cid), None);
+    }
+}
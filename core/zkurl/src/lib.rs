@@ -1,41 +1,119 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::fmt;
 
+pub use crate::host::ZkURLHost;
+pub use crate::retry::ResolveError;
+pub use crate::target::ZkURLTarget;
+
 /// Represents a zkURL (zero-knowledge URL) reference as used by the Cubiq network.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct ZkURL {
     /// Optional Prover identifier (could be public key or unique string)
     pub prover_id: Option<String>,
-    /// Domain or content hash (IPFS hash or DNS)
-    pub domain_or_hash: String,
-    /// Proof identifier (unique within the domain or hash)
+    /// DNS domain or IPFS content identifier
+    #[schemars(with = "String")]
+    pub host: ZkURLHost,
+    /// Optional port for DNS hosts not serving on 443
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Path segments before the proof id (e.g. `api/v2` for private deployments)
+    #[serde(default)]
+    pub path_prefix: Vec<String>,
+    /// Proof identifier (unique within the domain or hash); the last path segment.
+    /// `*` and `start-end` address collections and ranges, see [`ZkURL::target`].
     pub proof_id: String,
     /// Optional metadata (versioning, compression, type)
     pub metadata: Option<ZkURLMetadata>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 pub struct ZkURLMetadata {
     pub version: String,
     pub compression: Option<String>,
     pub proof_type: String,
+    /// Expected proof size, so resolvers can pre-allocate and reject oversized bodies
+    #[serde(default)]
+    pub size_bytes: Option<u64>,
+    /// blake3 hex digest of the proof bytes, after any `compression` is
+    /// undone
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Unix timestamp after which the proof should not be fetched
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// Unrecognized key=value pairs, preserved so newer metadata survives
+    /// a parse/display round trip through older nodes
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
 }
 
 /// Errors for parsing/handling zkURLs
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ZkURLError {
     InvalidScheme,
-    InvalidFormat,
-    ParseError(String),
+    /// The zkURL's structure is malformed at byte `offset`
+    ParseError { offset: usize, reason: String },
+    /// Fetching a proof from the network failed
+    Network(String),
+    /// Fetched content does not match the zkURL's CID or checksum
+    ContentMismatch(String),
+    /// An endpoint answered with a non-success HTTP status
+    HttpStatus(u16),
+    /// A response body exceeded the configured size limit
+    TooLarge { limit: u64, size: u64 },
+    /// Every endpoint failed; lists each one and why
+    Resolve(ResolveError),
+    /// A component failed validation (e.g. when building a zkURL)
+    InvalidComponent { component: &'static str, reason: String },
+    /// A character that must be percent-encoded appeared raw
+    InvalidCharacter { component: &'static str, offset: usize, found: char },
+    /// A `%` escape was malformed or decoded to invalid UTF-8
+    InvalidPercentEncoding { component: &'static str, offset: usize },
+    /// A required component was empty
+    EmptyComponent { component: &'static str, offset: usize },
+    /// The zkURL's `expires` hint is in the past
+    Expired { expires_at: u64 },
+    /// Resolver credentials or TLS settings are invalid
+    Config(String),
+    /// Reading or writing the local proof store failed
+    Storage(String),
+    /// The resolver is offline and has no local copy of the proof
+    Offline,
 }
 
 impl fmt::Display for ZkURLError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ZkURLError::InvalidScheme => write!(f, "Invalid zkURL scheme"),
-            ZkURLError::InvalidFormat => write!(f, "Invalid zkURL format"),
-            ZkURLError::ParseError(err) => write!(f, "Parse error: {}", err),
+            ZkURLError::ParseError { offset, reason } => {
+                write!(f, "Parse error at byte {}: {}", offset, reason)
+            }
+            ZkURLError::Network(err) => write!(f, "Network error: {}", err),
+            ZkURLError::ContentMismatch(err) => write!(f, "Content mismatch: {}", err),
+            ZkURLError::HttpStatus(status) => write!(f, "HTTP error: {}", status),
+            ZkURLError::TooLarge { limit, size } => {
+                write!(f, "Response of {} bytes exceeds the {} byte limit", size, limit)
+            }
+            ZkURLError::Resolve(err) => write!(f, "Resolve error: {}", err),
+            ZkURLError::InvalidComponent { component, reason } => {
+                write!(f, "Invalid zkURL {}: {}", component, reason)
+            }
+            ZkURLError::InvalidCharacter { component, offset, found } => {
+                write!(f, "Invalid character {:?} in zkURL {} at byte {}", found, component, offset)
+            }
+            ZkURLError::InvalidPercentEncoding { component, offset } => {
+                write!(f, "Invalid percent-encoding in zkURL {} at byte {}", component, offset)
+            }
+            ZkURLError::EmptyComponent { component, offset } => {
+                write!(f, "Empty zkURL {} at byte {}", component, offset)
+            }
+            ZkURLError::Expired { expires_at } => write!(f, "zkURL expired at {}", expires_at),
+            ZkURLError::Config(err) => write!(f, "Invalid resolver configuration: {}", err),
+            ZkURLError::Storage(err) => write!(f, "Proof store error: {}", err),
+            ZkURLError::Offline => write!(f, "Proof is not available offline"),
         }
     }
 }
@@ -46,61 +124,177 @@ impl FromStr for ZkURL {
     type Err = ZkURLError;
 
     /// Parses a zkURL string:  
-    /// Format: zk://[proverID]@[domain_or_hash]/[proof_id]#[metadata]
+    /// Format: zk://[proverID]@[domain_or_cid][:port]/[path_prefix/][proof_id]#[metadata]
+    ///
+    /// The proof id is the last path segment; a '/' inside it must be
+    /// percent-encoded. Ports are only allowed on DNS hosts.
+    ///
+    /// Prover id, proof id and metadata values are percent-decoded; the host
+    /// must be a valid CID, or a DNS name when a prover is given. Errors
+    /// carry the offending byte offset.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if !s.starts_with("zk://") {
-            return Err(ZkURLError::InvalidScheme);
-        }
-        let url_part = &s[5..]; // Remove "zk://"
-        let parts: Vec<&str> = url_part.split('@').collect();
+        parser::parse(s)
+    }
+}
 
-        let (prover_id, remaining) = if parts.len() == 2 {
-            (Some(parts[0].to_string()), parts[1])
-        } else {
-            (None, parts[0])
-        };
 
-        let (domain_hash_and_path, metadata_str) = if let Some(hash_pos) = remaining.find('#') {
-            let (left, right) = remaining.split_at(hash_pos);
-            (left, Some(&right[1..]))
-        } else {
-            (remaining, None)
-        };
+impl fmt::Display for ZkURL {
+    /// Writes the canonical `zk://[proverID@]host/proof_id[#metadata]` form.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "zk://")?;
+        if let Some(prover_id) = &self.prover_id {
+            write!(f, "{}@", syntax::percent_encode(prover_id, b""))?;
+        }
+        write!(f, "{}", self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        for segment in &self.path_prefix {
+            write!(f, "/{}", syntax::percent_encode(segment, b""))?;
+        }
+        write!(f, "/{}", syntax::percent_encode(&self.proof_id, b"*"))?;
+        if let Some(metadata) = &self.metadata {
+            write!(f, "#{}", metadata)?;
+        }
+        Ok(())
+    }
+}
 
-        let path_parts: Vec<&str> = domain_hash_and_path.splitn(2, '/').collect();
-        if path_parts.len() != 2 {
-            return Err(ZkURLError::InvalidFormat);
+impl ZkURLMetadata {
+    pub fn new(version: impl Into<String>, compression: Option<String>, proof_type: impl Into<String>) -> Self {
+        Self {
+            version: version.into(),
+            compression,
+            proof_type: proof_type.into(),
+            size_bytes: None,
+            checksum: None,
+            expires_at: None,
+            extra: BTreeMap::new(),
         }
-        let domain_or_hash = path_parts[0].to_string();
-        let proof_id = path_parts[1].to_string();
+    }
 
-        let metadata = if let Some(meta_str) = metadata_str {
-            Some(ZkURLMetadata::parse(meta_str)?)
-        } else {
-            None
-        };
+    /// Whether the `expires` hint lies before `now` (unix seconds).
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at < now)
+    }
 
-        Ok(ZkURL {
-            prover_id,
-            domain_or_hash,
-            proof_id,
-            metadata,
-        })
+    /// Checks `proof` against the `checksum` hint; true when no checksum is set.
+    pub fn checksum_matches(&self, proof: &[u8]) -> bool {
+        self.checksum
+            .as_deref()
+            .is_none_or(|checksum| blake3::hash(proof).to_hex().as_str() == checksum)
     }
-}
 
-impl ZkURLMetadata {
-    /// Parses the metadata segment (e.g., "v1&gzip&stark")
+    /// Parses the metadata segment, either key=value
+    /// ("version=v1&compression=gzip&type=stark") or the legacy positional
+    /// form ("v1&gzip&stark").
+    ///
+    /// In the positional form an empty compression slot ("v1&&stark") means
+    /// no compression. Missing version and type default to "v1" and "stark".
     pub fn parse(s: &str) -> Result<Self, ZkURLError> {
-        let parts: Vec<&str> = s.split('&').collect();
+        Self::parse_at(s, 0)
+    }
+
+    /// Parses a metadata segment located at byte `offset` of a zkURL, so
+    /// errors point into the full string.
+    fn parse_at(s: &str, offset: usize) -> Result<Self, ZkURLError> {
+        if s.contains('=') {
+            Self::parse_key_value(s, offset)
+        } else {
+            Self::parse_positional(s, offset)
+        }
+    }
+
+    fn parse_positional(s: &str, offset: usize) -> Result<Self, ZkURLError> {
+        let mut parts = Vec::new();
+        let mut part_offset = offset;
+        for raw in s.split('&') {
+            parts.push(syntax::percent_decode("metadata", raw, part_offset, b"")?);
+            part_offset += raw.len() + 1;
+        }
+        Ok(ZkURLMetadata::new(
+            parts.first().cloned().unwrap_or_else(|| "v1".to_string()),
+            parts.get(1).filter(|s| !s.is_empty()).cloned(),
+            parts.get(2).cloned().unwrap_or_else(|| "stark".to_string()),
+        ))
+    }
+
+    fn parse_key_value(s: &str, offset: usize) -> Result<Self, ZkURLError> {
+        let mut pairs = BTreeMap::new();
+        let mut part_offset = offset;
+        for raw in s.split('&') {
+            let eq = raw.find('=').ok_or(ZkURLError::InvalidCharacter {
+                component: "metadata",
+                offset: part_offset,
+                found: raw.chars().next().unwrap_or('&'),
+            })?;
+            let key = syntax::percent_decode("metadata key", &raw[..eq], part_offset, b"")?;
+            if key.is_empty() {
+                return Err(ZkURLError::EmptyComponent { component: "metadata key", offset: part_offset });
+            }
+            let value = syntax::percent_decode("metadata value", &raw[eq + 1..], part_offset + eq + 1, b"")?;
+            if pairs.insert(key, value).is_some() {
+                return Err(ZkURLError::InvalidComponent {
+                    component: "metadata",
+                    reason: format!("duplicate key at byte {}", part_offset),
+                });
+            }
+            part_offset += raw.len() + 1;
+        }
         Ok(ZkURLMetadata {
-            version: parts.get(0).unwrap_or(&"v1").to_string(),
-            compression: parts.get(1).map(|s| s.to_string()),
-            proof_type: parts.get(2).unwrap_or(&"stark").to_string(),
+            version: pairs.remove("version").unwrap_or_else(|| "v1".to_string()),
+            compression: pairs.remove("compression").filter(|c| !c.is_empty()),
+            proof_type: pairs.remove("type").unwrap_or_else(|| "stark".to_string()),
+            size_bytes: pairs.remove("size").map(|v| parse_u64_hint("size", &v)).transpose()?,
+            checksum: pairs.remove("checksum").map(|v| parse_checksum_hint(&v)).transpose()?,
+            expires_at: pairs.remove("expires").map(|v| parse_u64_hint("expires", &v)).transpose()?,
+            extra: pairs,
         })
     }
 }
 
+fn parse_u64_hint(key: &'static str, value: &str) -> Result<u64, ZkURLError> {
+    value.parse().map_err(|_| ZkURLError::InvalidComponent {
+        component: key,
+        reason: format!("expected an unsigned integer, got {:?}", value),
+    })
+}
+
+/// Checksums are 64-character blake3 hex digests, normalized to lowercase.
+pub(crate) fn parse_checksum_hint(value: &str) -> Result<String, ZkURLError> {
+    if value.len() != 64 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ZkURLError::InvalidComponent {
+            component: "checksum",
+            reason: "expected 64 hex characters (blake3)".to_string(),
+        });
+    }
+    Ok(value.to_ascii_lowercase())
+}
+
+impl fmt::Display for ZkURLMetadata {
+    /// Writes the key=value form: known keys first, then unknown keys sorted.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "version={}", syntax::percent_encode(&self.version, b""))?;
+        if let Some(compression) = &self.compression {
+            write!(f, "&compression={}", syntax::percent_encode(compression, b""))?;
+        }
+        write!(f, "&type={}", syntax::percent_encode(&self.proof_type, b""))?;
+        if let Some(size) = self.size_bytes {
+            write!(f, "&size={}", size)?;
+        }
+        if let Some(checksum) = &self.checksum {
+            write!(f, "&checksum={}", checksum)?;
+        }
+        if let Some(expires_at) = self.expires_at {
+            write!(f, "&expires={}", expires_at)?;
+        }
+        for (key, value) in &self.extra {
+            write!(f, "&{}={}", syntax::percent_encode(key, b""), syntax::percent_encode(value, b""))?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,7 +304,7 @@ mod tests {
         let url = "zk://prover123@domain.com/block1024#v1&gzip&stark";
         let parsed = ZkURL::from_str(url).unwrap();
         assert_eq!(parsed.prover_id, Some("prover123".to_string()));
-        assert_eq!(parsed.domain_or_hash, "domain.com");
+        assert_eq!(parsed.host, ZkURLHost::Domain("domain.com".to_string()));
         assert_eq!(parsed.proof_id, "block1024");
         let meta = parsed.metadata.expect("Metadata should exist");
         assert_eq!(meta.version, "v1");
@@ -120,14 +314,169 @@ mod tests {
 
     #[test]
     fn test_parse_ipfs_content_only() {
-        let url = "zk://QmHash123/block1";
+        let url = "zk://QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG/block1";
         let parsed = ZkURL::from_str(url).unwrap();
         assert_eq!(parsed.prover_id, None);
-        assert_eq!(parsed.domain_or_hash, "QmHash123");
+        assert_eq!(parsed.host.as_cid().unwrap().to_string(), "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG");
         assert_eq!(parsed.proof_id, "block1");
         assert!(parsed.metadata.is_none());
     }
 
+    #[test]
+    fn test_display_roundtrip() {
+        for url in [
+            "zk://prover123@domain.com/block1024#version=v1&compression=gzip&type=stark",
+            "zk://QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG/block1",
+            "zk://prover@bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi/block2",
+            "zk://prover@domain.com/block7#version=v2&type=snark&region=eu",
+        ] {
+            let parsed = ZkURL::from_str(url).unwrap();
+            assert_eq!(parsed.to_string(), url);
+            assert_eq!(ZkURL::from_str(&parsed.to_string()).unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn test_positional_metadata_displays_as_key_value() {
+        let parsed = ZkURL::from_str("zk://prover@domain.com/block7#v2&&snark").unwrap();
+        assert_eq!(parsed.to_string(), "zk://prover@domain.com/block7#version=v2&type=snark");
+    }
+
+    #[test]
+    fn test_key_value_metadata_without_compression() {
+        let meta = ZkURLMetadata::parse("type=snark&version=v3").unwrap();
+        assert_eq!(meta.version, "v3");
+        assert_eq!(meta.compression, None);
+        assert_eq!(meta.proof_type, "snark");
+        assert!(meta.extra.is_empty());
+    }
+
+    #[test]
+    fn test_key_value_metadata_preserves_unknown_keys() {
+        let meta = ZkURLMetadata::parse("version=v1&type=stark&region=eu&tier=gold").unwrap();
+        assert_eq!(meta.extra.get("region"), Some(&"eu".to_string()));
+        assert_eq!(meta.extra.get("tier"), Some(&"gold".to_string()));
+        assert!(ZkURLMetadata::parse("version=v1&version=v2").is_err());
+        assert!(ZkURLMetadata::parse("version=v1&gzip").is_err());
+    }
+
+    #[test]
+    fn test_display_without_compression_keeps_proof_type() {
+        let zkurl = ZkURL {
+            prover_id: None,
+            host: ZkURLHost::Domain("domain.com".to_string()),
+            port: None,
+            path_prefix: vec![],
+            proof_id: "block1".to_string(),
+            metadata: Some(ZkURLMetadata::new("v1", None, "stark")),
+        };
+        let reparsed = ZkURL::from_str(&zkurl.to_string()).unwrap();
+        assert_eq!(reparsed, zkurl);
+    }
+
+    #[test]
+    fn test_percent_encoded_components_roundtrip() {
+        let zkurl = ZkURL {
+            prover_id: Some("prover@eu".to_string()),
+            host: ZkURLHost::Domain("domain.com".to_string()),
+            port: None,
+            path_prefix: vec!["epoch 4".to_string()],
+            proof_id: "block#1/a".to_string(),
+            metadata: None,
+        };
+        let encoded = zkurl.to_string();
+        assert_eq!(encoded, "zk://prover%40eu@domain.com/epoch%204/block%231%2Fa");
+        assert_eq!(ZkURL::from_str(&encoded).unwrap(), zkurl);
+    }
+
+    #[test]
+    fn test_rejects_raw_reserved_characters_with_offset() {
+        let result = ZkURL::from_str("zk://prover@domain.com/block 1");
+        assert_eq!(
+            result,
+            Err(ZkURLError::InvalidCharacter { component: "proof_id", offset: 28, found: ' ' })
+        );
+        let result = ZkURL::from_str("zk://a@b@domain.com/block1");
+        assert!(matches!(result, Err(ZkURLError::InvalidCharacter { offset: 8, found: '@', .. })));
+        let result = ZkURL::from_str("zk://prover@dömain.com/block1");
+        assert!(matches!(result, Err(ZkURLError::InvalidCharacter { component: "domain", offset: 13, .. })));
+    }
+
+    #[test]
+    fn test_rejects_empty_components() {
+        assert!(matches!(
+            ZkURL::from_str("zk://prover@domain.com/"),
+            Err(ZkURLError::EmptyComponent { component: "proof_id", offset: 23 })
+        ));
+        assert!(matches!(
+            ZkURL::from_str("zk:///block1"),
+            Err(ZkURLError::EmptyComponent { component: "host", offset: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_malformed_cid() {
+        assert!(matches!(
+            ZkURL::from_str("zk://QmHash123/block1"),
+            Err(ZkURLError::InvalidComponent { component: "cid", .. })
+        ));
+    }
+
+    #[test]
+    fn test_size_checksum_and_expiry_hints() {
+        let checksum = blake3::hash(b"proof").to_hex().to_string();
+        let url = format!(
+            "zk://prover@domain.com/block1#version=v1&type=stark&size=5&checksum={}&expires=1700000000",
+            checksum
+        );
+        let parsed = ZkURL::from_str(&url).unwrap();
+        assert_eq!(parsed.to_string(), url);
+        let meta = parsed.metadata.unwrap();
+        assert_eq!(meta.size_bytes, Some(5));
+        assert!(meta.checksum_matches(b"proof"));
+        assert!(!meta.checksum_matches(b"forged"));
+        assert!(meta.is_expired(1700000001));
+        assert!(!meta.is_expired(1699999999));
+    }
+
+    #[test]
+    fn test_rejects_malformed_hints() {
+        assert!(ZkURLMetadata::parse("size=big").is_err());
+        assert!(ZkURLMetadata::parse("checksum=abc").is_err());
+        assert!(ZkURLMetadata::parse("expires=-1").is_err());
+    }
+
+    #[test]
+    fn test_port_and_path_prefix() {
+        let url = "zk://prover@proofs.internal:8443/api/v2/block7";
+        let parsed = ZkURL::from_str(url).unwrap();
+        assert_eq!(parsed.host, ZkURLHost::Domain("proofs.internal".to_string()));
+        assert_eq!(parsed.port, Some(8443));
+        assert_eq!(parsed.path_prefix, vec!["api".to_string(), "v2".to_string()]);
+        assert_eq!(parsed.proof_id, "block7");
+        assert_eq!(parsed.to_string(), url);
+    }
+
+    #[test]
+    fn test_rejects_bad_ports() {
+        assert!(matches!(
+            ZkURL::from_str("zk://prover@host.dev:84a3/block1"),
+            Err(ZkURLError::InvalidCharacter { component: "port", offset: 23, found: 'a' })
+        ));
+        assert!(matches!(
+            ZkURL::from_str("zk://prover@host.dev:70000/block1"),
+            Err(ZkURLError::InvalidComponent { component: "port", .. })
+        ));
+        assert!(matches!(
+            ZkURL::from_str("zk://prover@host.dev:/block1"),
+            Err(ZkURLError::EmptyComponent { component: "port", offset: 21 })
+        ));
+        assert!(matches!(
+            ZkURL::from_str("zk://QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG:80/block1"),
+            Err(ZkURLError::InvalidComponent { component: "port", .. })
+        ));
+    }
+
     #[test]
     fn test_invalid_url_scheme() {
         let url = "http://domain.com/block";
@@ -135,4 +484,25 @@ mod tests {
         assert!(matches!(result, Err(ZkURLError::InvalidScheme)));
     }
 }
+pub mod auth;
+pub mod builder;
+pub mod cache;
+pub mod codec;
+pub mod compression;
+pub mod download;
+pub mod gateway;
+pub mod hedge;
+pub mod host;
+pub mod ipfs;
+pub mod metrics;
+pub mod p2p;
+mod parser;
+pub mod policy;
+pub mod proxy;
+pub mod publish;
 pub mod resolver;
+pub mod retry;
+pub mod server;
+pub mod store;
+pub mod syntax;
+pub mod target;
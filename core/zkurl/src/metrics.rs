@@ -0,0 +1,186 @@
+//! Prometheus metrics for proof resolution.
+//!
+//! Endpoint labels are the redacted origin (scheme, host and port) so that
+//! label cardinality stays bounded and credentials never reach a scrape.
+
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+use std::time::Duration;
+
+use crate::auth::redact_url;
+use crate::hedge::origin;
+use crate::ZkURLError;
+
+/// Latency buckets in seconds, from a warm cache hit to a slow download.
+const LATENCY_BUCKETS: &[f64] = &[0.001, 0.005, 0.025, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Where a resolved bundle came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FetchSource {
+    Cache,
+    /// The local proof store
+    Store,
+    /// A stale cache entry its origin confirmed or replaced
+    Revalidated,
+    Network,
+}
+
+impl FetchSource {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            FetchSource::Cache => "cache",
+            FetchSource::Store => "store",
+            FetchSource::Revalidated => "revalidated",
+            FetchSource::Network => "network",
+        }
+    }
+}
+
+pub struct ResolverMetrics {
+    fetch_duration: HistogramVec,
+    cache_lookups: IntCounterVec,
+    endpoint_requests: IntCounterVec,
+    endpoint_duration: HistogramVec,
+    endpoint_bytes: IntCounterVec,
+}
+
+impl ResolverMetrics {
+    /// Creates the resolver metrics and registers them with `registry`.
+    pub fn register(registry: &Registry) -> Result<Self, ZkURLError> {
+        let metrics = Self {
+            fetch_duration: HistogramVec::new(
+                HistogramOpts::new("zkurl_fetch_duration_seconds", "Time to resolve a zkURL, by source or error")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["source"],
+            )
+            .map_err(config_error)?,
+            cache_lookups: IntCounterVec::new(
+                Opts::new("zkurl_cache_lookups_total", "Bundle cache lookups by result"),
+                &["result"],
+            )
+            .map_err(config_error)?,
+            endpoint_requests: IntCounterVec::new(
+                Opts::new("zkurl_endpoint_requests_total", "Proof requests per endpoint by outcome"),
+                &["endpoint", "outcome"],
+            )
+            .map_err(config_error)?,
+            endpoint_duration: HistogramVec::new(
+                HistogramOpts::new("zkurl_endpoint_request_duration_seconds", "Proof request latency per endpoint")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["endpoint"],
+            )
+            .map_err(config_error)?,
+            endpoint_bytes: IntCounterVec::new(
+                Opts::new("zkurl_endpoint_bytes_total", "Bundle bytes downloaded per endpoint"),
+                &["endpoint"],
+            )
+            .map_err(config_error)?,
+        };
+        registry.register(Box::new(metrics.fetch_duration.clone())).map_err(config_error)?;
+        registry.register(Box::new(metrics.cache_lookups.clone())).map_err(config_error)?;
+        registry.register(Box::new(metrics.endpoint_requests.clone())).map_err(config_error)?;
+        registry.register(Box::new(metrics.endpoint_duration.clone())).map_err(config_error)?;
+        registry.register(Box::new(metrics.endpoint_bytes.clone())).map_err(config_error)?;
+        Ok(metrics)
+    }
+
+    /// `source` is a `FetchSource` name, or `"error"`.
+    pub(crate) fn observe_fetch(&self, source: &str, elapsed: Duration) {
+        self.fetch_duration.with_label_values(&[source]).observe(elapsed.as_secs_f64());
+    }
+
+    /// `result` is `"fresh"`, `"stale"` or `"miss"`.
+    pub(crate) fn observe_cache(&self, result: &str) {
+        self.cache_lookups.with_label_values(&[result]).inc();
+    }
+
+    pub(crate) fn observe_endpoint(&self, url: &str, success: bool, elapsed: Duration, bytes: usize) {
+        let endpoint = endpoint_label(url);
+        let outcome = if success { "success" } else { "failure" };
+        self.endpoint_requests.with_label_values(&[&endpoint, outcome]).inc();
+        self.endpoint_duration.with_label_values(&[&endpoint]).observe(elapsed.as_secs_f64());
+        self.endpoint_bytes.with_label_values(&[&endpoint]).inc_by(bytes as u64);
+    }
+}
+
+/// Endpoint name used in metrics and trace spans.
+pub(crate) fn endpoint_label(url: &str) -> String {
+    redact_url(origin(url))
+}
+
+fn config_error(error: prometheus::Error) -> ZkURLError {
+    ZkURLError::Config(format!("metrics: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{BundleCache, CacheConfig};
+    use crate::resolver::{ProofBundle, ProofMetadata, PublicInputs, ZkURLResolver};
+    use crate::retry::RetryPolicy;
+    use crate::server::ProofServer;
+    use crate::store::{MemoryProofStore, ProofStore};
+    use crate::ZkURL;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    fn counter(registry: &Registry, name: &str, labels: &[(&str, &str)]) -> u64 {
+        registry
+            .gather()
+            .iter()
+            .filter(|family| family.get_name() == name)
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                labels.iter().all(|(key, value)| {
+                    metric.get_label().iter().any(|l| l.get_name() == *key && l.get_value() == *value)
+                })
+            })
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn records_endpoint_outcomes_and_cache_hits() {
+        let store = MemoryProofStore::default();
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let bundle = ProofBundle {
+            proof: vec![5; 16],
+            public_inputs: PublicInputs {
+                block_hash: String::new(),
+                state_root: String::new(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: "prover".to_string(),
+            timestamp,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: 16 },
+        };
+        store.put("block1", bundle).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(ProofServer::new(Arc::new(store)).serve(listener));
+
+        let registry = Registry::new();
+        let metrics = Arc::new(ResolverMetrics::register(&registry).unwrap());
+        let resolver = ZkURLResolver::new(vec![server.clone()])
+            .with_hedge_delay(Duration::from_millis(1))
+            .with_retry_policy(RetryPolicy { max_retries: 0, ..RetryPolicy::default() })
+            .with_cache(BundleCache::new(CacheConfig::default()))
+            .with_metrics(metrics);
+        let zkurl: ZkURL = "zk://prover@127.0.0.1.nip.invalid/block1".parse().unwrap();
+        resolver.fetch_proof(&zkurl).await.unwrap();
+        resolver.fetch_proof(&zkurl).await.unwrap();
+
+        assert_eq!(counter(&registry, "zkurl_endpoint_requests_total", &[("endpoint", &server), ("outcome", "success")]), 1);
+        assert_eq!(counter(&registry, "zkurl_cache_lookups_total", &[("result", "miss")]), 1);
+        assert_eq!(counter(&registry, "zkurl_cache_lookups_total", &[("result", "fresh")]), 1);
+        let fetches = registry.gather().into_iter().find(|f| f.get_name() == "zkurl_fetch_duration_seconds").unwrap();
+        let sources: Vec<_> = fetches.get_metric().iter().map(|m| m.get_label()[0].get_value().to_string()).collect();
+        assert!(sources.contains(&"cache".to_string()) && sources.contains(&"network".to_string()));
+    }
+
+    #[test]
+    fn endpoint_labels_are_redacted_origins() {
+        assert_eq!(endpoint_label("https://ops:pw@proofs.example:8443/api/proof/p1"), "https://<redacted>@proofs.example:8443");
+    }
+}
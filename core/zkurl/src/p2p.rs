@@ -0,0 +1,64 @@
+//! Peer-to-peer fetch path for content-addressed proofs.
+//!
+//! The resolver does not own a libp2p swarm; the networking crate plugs its
+//! Bitswap client in through `BlockFetcher`.
+
+use async_trait::async_trait;
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+
+use crate::ZkURLError;
+
+/// Fetches raw IPLD blocks from peers (e.g. over Bitswap).
+#[async_trait]
+pub trait BlockFetcher: Send + Sync {
+    /// Returns the raw block for `cid`, asking `providers` (peer ids) first
+    /// when any are known. The resolver verifies the block against the CID.
+    async fn fetch_block(&self, cid: &Cid, providers: &[String]) -> Result<Vec<u8>, ZkURLError>;
+}
+
+/// When the resolver uses the p2p path for CID zkURLs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum P2pMode {
+    /// HTTP gateways only
+    Off,
+    /// HTTP first, p2p if every HTTP source fails
+    HttpFirst,
+    /// p2p first, HTTP if the block cannot be fetched from peers
+    P2pFirst,
+    /// p2p first when the announcement named providers, otherwise HTTP first
+    #[default]
+    Auto,
+}
+
+impl P2pMode {
+    pub fn prefers_p2p(self, hints: &AvailabilityHints) -> bool {
+        match self {
+            P2pMode::P2pFirst => true,
+            P2pMode::Auto => !hints.bitswap_providers.is_empty(),
+            P2pMode::Off | P2pMode::HttpFirst => false,
+        }
+    }
+}
+
+/// Where a proof is known to be available, carried in proof announcements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailabilityHints {
+    /// Peer ids that announced they can serve the proof's blocks
+    #[serde(default)]
+    pub bitswap_providers: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_prefers_p2p_only_with_providers() {
+        let hinted = AvailabilityHints { bitswap_providers: vec!["12D3KooW".to_string()] };
+        assert!(P2pMode::Auto.prefers_p2p(&hinted));
+        assert!(!P2pMode::Auto.prefers_p2p(&AvailabilityHints::default()));
+        assert!(P2pMode::P2pFirst.prefers_p2p(&AvailabilityHints::default()));
+        assert!(!P2pMode::HttpFirst.prefers_p2p(&hinted));
+    }
+}
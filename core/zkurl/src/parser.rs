@@ -0,0 +1,227 @@
+//! Single-pass zkURL scanner.
+//!
+//! The scanner walks the bytes once through a small state machine and only
+//! records component spans. Every split point is an ASCII delimiter, so the
+//! spans always fall on char boundaries and slicing never panics; component
+//! decoding and validation happen afterwards on those spans.
+
+use std::ops::Range;
+
+use crate::{syntax, ZkURL, ZkURLError, ZkURLHost, ZkURLMetadata};
+
+const SCHEME: &str = "zk://";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// `[prover@]host`, up to ':' or '/'
+    Authority,
+    /// Digits after the host's ':', up to '/'
+    Port,
+    /// '/'-separated path segments, up to '#'
+    Path,
+    /// Everything after the first '#' in the path
+    Metadata,
+}
+
+/// Byte ranges of each component within the full zkURL string.
+#[derive(Debug, Default)]
+struct Spans {
+    prover: Option<Range<usize>>,
+    host: Range<usize>,
+    port: Option<Range<usize>>,
+    segments: Vec<Range<usize>>,
+    metadata: Option<Range<usize>>,
+}
+
+fn parse_error(offset: usize, reason: &str) -> ZkURLError {
+    ZkURLError::ParseError {
+        offset,
+        reason: reason.to_string(),
+    }
+}
+
+fn scan(s: &str) -> Result<Spans, ZkURLError> {
+    if !s.starts_with(SCHEME) {
+        return Err(ZkURLError::InvalidScheme);
+    }
+    let mut spans = Spans::default();
+    let mut state = State::Authority;
+    let mut start = SCHEME.len();
+
+    for (i, &b) in s.as_bytes().iter().enumerate().skip(SCHEME.len()) {
+        match (state, b) {
+            (State::Authority, b'@') if spans.prover.is_none() => {
+                spans.prover = Some(start..i);
+                start = i + 1;
+            }
+            (State::Authority, b'@') => {
+                return Err(ZkURLError::InvalidCharacter { component: "host", offset: i, found: '@' })
+            }
+            (State::Authority, b':') => {
+                spans.host = start..i;
+                start = i + 1;
+                state = State::Port;
+            }
+            (State::Authority, b'/') => {
+                spans.host = start..i;
+                start = i + 1;
+                state = State::Path;
+            }
+            (State::Authority | State::Port, b'#') => {
+                return Err(parse_error(i, "metadata before the proof id"))
+            }
+            (State::Port, b'/') => {
+                spans.port = Some(start..i);
+                start = i + 1;
+                state = State::Path;
+            }
+            (State::Port, b':' | b'@') => return Err(parse_error(i, "unexpected character after port separator")),
+            (State::Path, b'/') => {
+                spans.segments.push(start..i);
+                start = i + 1;
+            }
+            (State::Path, b'#') => {
+                spans.segments.push(start..i);
+                start = i + 1;
+                state = State::Metadata;
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    match state {
+        State::Authority | State::Port => return Err(parse_error(s.len(), "expected '/' before the proof id")),
+        State::Path => spans.segments.push(start..s.len()),
+        State::Metadata => spans.metadata = Some(start..s.len()),
+    }
+    Ok(spans)
+}
+
+/// Parses a full zkURL; see `ZkURL::from_str` for the grammar.
+pub(crate) fn parse(s: &str) -> Result<ZkURL, ZkURLError> {
+    let spans = scan(s)?;
+
+    let prover_id = match spans.prover {
+        Some(span) if span.is_empty() => {
+            return Err(ZkURLError::EmptyComponent { component: "prover_id", offset: span.start })
+        }
+        Some(span) => Some(syntax::percent_decode("prover_id", &s[span.clone()], span.start, b"")?),
+        None => None,
+    };
+
+    let port = match spans.port {
+        Some(span) => Some(parse_port(&s[span.clone()], span.start)?),
+        None => None,
+    };
+    let host = ZkURLHost::parse(&s[spans.host.clone()], spans.host.start, prover_id.is_some())?;
+    if port.is_some() && host.as_cid().is_some() {
+        return Err(ZkURLError::InvalidComponent {
+            component: "port",
+            reason: "content-addressed zkURLs cannot specify a port".to_string(),
+        });
+    }
+
+    let last = spans.segments.len() - 1;
+    let mut segments = Vec::with_capacity(spans.segments.len());
+    for (i, span) in spans.segments.into_iter().enumerate() {
+        if span.is_empty() {
+            return Err(ZkURLError::EmptyComponent { component: "proof_id", offset: span.start });
+        }
+        // '*' is only meaningful as the collection wildcard in the last segment
+        let keep: &[u8] = if i == last { b"*" } else { b"" };
+        segments.push(syntax::percent_decode("proof_id", &s[span.clone()], span.start, keep)?);
+    }
+    let proof_id = segments.pop().unwrap_or_default();
+
+    let metadata = match spans.metadata {
+        Some(span) => Some(ZkURLMetadata::parse_at(&s[span.clone()], span.start)?),
+        None => None,
+    };
+
+    Ok(ZkURL {
+        prover_id,
+        host,
+        port,
+        path_prefix: segments,
+        proof_id,
+        metadata,
+    })
+}
+
+fn parse_port(port: &str, offset: usize) -> Result<u16, ZkURLError> {
+    if port.is_empty() {
+        return Err(ZkURLError::EmptyComponent { component: "port", offset });
+    }
+    if let Some(i) = port.bytes().position(|b| !b.is_ascii_digit()) {
+        return Err(ZkURLError::InvalidCharacter {
+            component: "port",
+            offset: offset + i,
+            found: port[i..].chars().next().unwrap_or('\u{FFFD}'),
+        });
+    }
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(ZkURLError::InvalidComponent {
+            component: "port",
+            reason: format!("{} is not in 1-65535 at byte {}", port, offset),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn structural_errors_carry_offsets() {
+        assert_eq!(
+            parse("zk://prover@host.dev"),
+            Err(parse_error(20, "expected '/' before the proof id"))
+        );
+        assert!(matches!(parse("zk://a@host.dev#v1/p"), Err(ZkURLError::ParseError { offset: 15, .. })));
+        assert!(matches!(parse("zk://a@host.dev:80:81/p"), Err(ZkURLError::ParseError { offset: 18, .. })));
+    }
+
+    fn token() -> impl Strategy<Value = String> {
+        "[a-zA-Z0-9 #@/%?&=*ä_.-]{1,12}"
+    }
+
+    prop_compose! {
+        fn arb_zkurl()(
+            prover_id in proptest::option::of(token()),
+            domain in "[a-z][a-z0-9]{0,8}(\\.[a-z][a-z0-9]{0,8}){1,3}",
+            port in proptest::option::of(1u16..),
+            path_prefix in proptest::collection::vec(token(), 0..3),
+            proof_id in token(),
+            metadata in proptest::option::of((token(), proptest::option::of(token()), token())),
+        ) -> ZkURL {
+            ZkURL {
+                prover_id,
+                host: ZkURLHost::Domain(domain),
+                port,
+                path_prefix,
+                proof_id,
+                metadata: metadata.map(|(v, c, t)| ZkURLMetadata::new(v, c, t)),
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(s in "\\PC*") {
+            let _ = parse(&s);
+        }
+
+        #[test]
+        fn never_panics_after_scheme(s in "zk://[a-z0-9.:@/#%&=*\\PC]{0,40}") {
+            let _ = parse(&s);
+        }
+
+        #[test]
+        fn display_roundtrips(zkurl in arb_zkurl()) {
+            prop_assert_eq!(parse(&zkurl.to_string()), Ok(zkurl));
+        }
+    }
+}
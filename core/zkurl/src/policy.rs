@@ -0,0 +1,84 @@
+//! Limits a fetched bundle must meet before the resolver hands it out.
+
+use std::time::Duration;
+
+use crate::resolver::{ProofBundle, MAX_PROOF_BYTES};
+
+/// Default oldest bundle accepted for proofs of new blocks.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolverPolicy {
+    /// Oldest bundle accepted, judged by its `timestamp`; `None` accepts
+    /// any age. Bundles timestamped in the future are always rejected.
+    pub max_age: Option<Duration>,
+    /// Largest (decompressed) proof accepted
+    pub max_proof_bytes: usize,
+}
+
+impl Default for ResolverPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Some(DEFAULT_MAX_AGE),
+            max_proof_bytes: MAX_PROOF_BYTES,
+        }
+    }
+}
+
+impl ResolverPolicy {
+    /// For proofs of blocks that are already finalized, e.g. during
+    /// catch-up sync: historical proofs are valid however old they are.
+    pub fn finalized() -> Self {
+        Self { max_age: None, ..Self::default() }
+    }
+
+    /// Whether `bundle` meets these limits at unix time `now`.
+    pub fn accepts(&self, bundle: &ProofBundle, now: u64) -> bool {
+        if now < bundle.timestamp {
+            return false;
+        }
+        if self.max_age.is_some_and(|max_age| now - bundle.timestamp > max_age.as_secs()) {
+            return false;
+        }
+        bundle.proof.len() <= self.max_proof_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{ProofMetadata, PublicInputs};
+
+    fn bundle(timestamp: u64, proof_len: usize) -> ProofBundle {
+        ProofBundle {
+            proof: vec![0; proof_len],
+            public_inputs: PublicInputs {
+                block_hash: String::new(),
+                state_root: String::new(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: "prover".to_string(),
+            timestamp,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: proof_len },
+        }
+    }
+
+    #[test]
+    fn finalized_policy_accepts_historical_proofs() {
+        let now = 1_700_000_000;
+        let historical = bundle(now - 30 * 86_400, 10);
+        assert!(!ResolverPolicy::default().accepts(&historical, now));
+        assert!(ResolverPolicy::finalized().accepts(&historical, now));
+        assert!(!ResolverPolicy::finalized().accepts(&bundle(now + 1, 10), now));
+    }
+
+    #[test]
+    fn size_limit_is_configurable() {
+        let now = 1_700_000_000;
+        let policy = ResolverPolicy { max_proof_bytes: 100, ..ResolverPolicy::default() };
+        assert!(policy.accepts(&bundle(now, 100), now));
+        assert!(!policy.accepts(&bundle(now, 101), now));
+    }
+}
@@ -0,0 +1,417 @@
+//! Proof publication: signs a bundle with the prover key, uploads it to
+//! prover endpoints and/or pins it to IPFS, and returns the zkURL that
+//! references it.
+
+use cid::Cid;
+use ed25519_dalek::{Signer, SigningKey};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Client, Method};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::auth::{self, redact_url, Credential, TlsConfig};
+use crate::builder::{validate_token, ZkURLBuilder};
+use crate::codec::{encode_bundle, BundleEncoding};
+use crate::compression::{decompress, Compression};
+use crate::hedge::origin;
+use crate::host::raw_block_cid;
+use crate::proxy::ProxyConfig;
+use crate::resolver::{ProofBundle, MAX_PROOF_BYTES};
+use crate::store::ProofStore;
+use crate::{syntax, ZkURL, ZkURLError, ZkURLMetadata};
+
+/// A prover endpoint that accepts uploads at `{url}/proof/{proof_id}`, the
+/// same path the resolver fetches from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishEndpoint {
+    pub url: String,
+    /// `PUT` or `POST`
+    pub method: Method,
+}
+
+/// Where a bundle was published.
+#[derive(Debug, Clone)]
+pub struct Published {
+    /// Canonical zkURL to embed in a block proposal
+    pub zkurl: ZkURL,
+    /// CID of the pinned bundle, when pinned to IPFS
+    pub cid: Option<Cid>,
+    /// Upload URLs that accepted the bundle
+    pub uploaded: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct BlockPut {
+    #[serde(rename = "Key")]
+    key: String,
+}
+
+pub struct ProofPublisher {
+    client: Client,
+    prover_id: String,
+    signing_key: SigningKey,
+    endpoints: Vec<PublishEndpoint>,
+    kubo_api: Option<String>,
+    encoding: BundleEncoding,
+    proof_type: String,
+    timeout: Duration,
+    /// Keyed by endpoint origin
+    credentials: HashMap<String, Credential>,
+    tls: TlsConfig,
+    proxy: ProxyConfig,
+    store: Option<Arc<dyn ProofStore>>,
+}
+
+impl ProofPublisher {
+    pub fn new(prover_id: impl Into<String>, signing_key: SigningKey) -> Self {
+        Self {
+            client: TlsConfig::default()
+                .build_client(Duration::from_secs(30), &ProxyConfig::default())
+                .expect("Failed to build HTTP client"),
+            prover_id: prover_id.into(),
+            signing_key,
+            endpoints: Vec::new(),
+            kubo_api: None,
+            encoding: BundleEncoding::Json,
+            proof_type: "stark".to_string(),
+            timeout: Duration::from_secs(30),
+            credentials: HashMap::new(),
+            tls: TlsConfig::default(),
+            proxy: ProxyConfig::default(),
+            store: None,
+        }
+    }
+
+    /// Upload to a prover endpoint. The first endpoint becomes the zkURL's
+    /// host when the bundle is not pinned to IPFS.
+    pub fn with_endpoint(mut self, url: impl Into<String>, method: Method) -> Self {
+        let url = url.into().trim_end_matches('/').to_string();
+        self.endpoints.push(PublishEndpoint { url, method });
+        self
+    }
+
+    /// Pin bundles through a Kubo node's RPC API, e.g. `http://127.0.0.1:5001`.
+    /// Pinned bundles get a content-addressed zkURL.
+    pub fn with_kubo(mut self, api: impl Into<String>) -> Self {
+        self.kubo_api = Some(api.into().trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Encoding for endpoint uploads. IPFS blocks are always JSON, which
+    /// is what the resolver's p2p and gateway paths decode.
+    pub fn with_encoding(mut self, encoding: BundleEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// `proof_type` advertised in the zkURL metadata.
+    pub fn with_proof_type(mut self, proof_type: impl Into<String>) -> Self {
+        self.proof_type = proof_type.into();
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Keep a copy of every published bundle locally, e.g. the store an
+    /// embedded `ProofServer` serves from.
+    pub fn with_store(mut self, store: Arc<dyn ProofStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Authenticate uploads to `endpoint`'s origin with `credential`.
+    pub fn with_credential(mut self, endpoint: &str, credential: Credential) -> Self {
+        self.credentials.insert(origin(endpoint).to_string(), credential);
+        self
+    }
+
+    /// Client certificate (mTLS) and extra trusted roots.
+    pub fn with_tls(mut self, tls: &TlsConfig) -> Result<Self, ZkURLError> {
+        self.client = tls.build_client(self.timeout, &self.proxy)?;
+        self.tls = tls.clone();
+        Ok(self)
+    }
+
+    /// Proxies for uploads and the Kubo API.
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> Result<Self, ZkURLError> {
+        self.client = self.tls.build_client(self.timeout, &proxy)?;
+        self.proxy = proxy;
+        Ok(self)
+    }
+
+    /// Stamps the bundle with this prover's id and signs it.
+    pub fn sign(&self, bundle: &mut ProofBundle) {
+        bundle.prover_id = self.prover_id.clone();
+        bundle.signature = hex::encode(self.signing_key.sign(&bundle.signing_bytes()).to_bytes());
+    }
+
+    /// Signs `bundle`, saves it to the local store, pins it (when a Kubo
+    /// node is configured), uploads it to every endpoint, and returns its
+    /// canonical zkURL. Fails if any
+    /// configured destination rejects the bundle.
+    pub async fn publish(&self, mut bundle: ProofBundle, proof_id: &str) -> Result<Published, ZkURLError> {
+        if self.endpoints.is_empty() && self.kubo_api.is_none() {
+            return Err(ZkURLError::Config("no publish endpoints or Kubo node configured".to_string()));
+        }
+        // Reject ids no zkURL could carry before anything is uploaded
+        validate_token("proof_id", proof_id, &['-', '_', '.'])?;
+        self.sign(&mut bundle);
+        if let Some(store) = &self.store {
+            store.put(proof_id, bundle.clone())?;
+        }
+
+        let cid = match &self.kubo_api {
+            Some(api) => Some(self.pin(api, &bundle).await?),
+            None => None,
+        };
+
+        let mut uploaded = Vec::new();
+        for endpoint in &self.endpoints {
+            let url = format!("{}/proof/{}", endpoint.url, syntax::percent_encode(proof_id, b""));
+            self.upload(endpoint.method.clone(), &url, &bundle).await?;
+            uploaded.push(url);
+        }
+
+        // The hints describe the proof as resolvers check it: decompressed
+        let compression = bundle.metadata.compression.as_deref().map(Compression::parse).transpose()?.flatten();
+        let proof = match compression {
+            Some(compression) => decompress(&bundle.proof, compression, MAX_PROOF_BYTES as u64)?,
+            None => bundle.proof.clone(),
+        };
+        let metadata = ZkURLMetadata {
+            size_bytes: Some(proof.len() as u64),
+            checksum: Some(blake3::hash(&proof).to_hex().to_string()),
+            ..ZkURLMetadata::new(&bundle.metadata.version, bundle.metadata.compression.clone(), &self.proof_type)
+        };
+        let builder = ZkURL::builder().prover_id(&self.prover_id).proof_id(proof_id).metadata(metadata);
+        let zkurl = match (&cid, self.endpoints.first()) {
+            (Some(cid), _) => builder.ipfs_cid(cid.to_string()).build()?,
+            (None, Some(endpoint)) => with_endpoint_host(builder, &endpoint.url)?.build()?,
+            (None, None) => unreachable!("checked above"),
+        };
+        Ok(Published { zkurl, cid, uploaded })
+    }
+
+    async fn upload(&self, method: Method, url: &str, bundle: &ProofBundle) -> Result<(), ZkURLError> {
+        let request = self
+            .client
+            .request(method, url)
+            .header(CONTENT_TYPE, self.encoding.content_type())
+            .body(encode_bundle(bundle, self.encoding)?)
+            .timeout(self.timeout);
+        let response = auth::send(request, self.credentials.get(origin(url)))
+            .await
+            .map_err(|e| ZkURLError::Network(format!("upload to {} failed: {}", redact_url(url), e)))?;
+        if !response.status().is_success() {
+            return Err(ZkURLError::HttpStatus(response.status().as_u16()));
+        }
+        Ok(())
+    }
+
+    /// Stores the JSON bundle as a raw block via Kubo's `block/put` and
+    /// checks the node computed the same CID we did.
+    async fn pin(&self, api: &str, bundle: &ProofBundle) -> Result<Cid, ZkURLError> {
+        let block = encode_bundle(bundle, BundleEncoding::Json)?;
+        let expected = raw_block_cid(&block);
+
+        let boundary = format!("cubiq-{:016x}", rand::random::<u64>());
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"bundle\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n",
+            boundary
+        )
+        .into_bytes();
+        body.extend_from_slice(&block);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+        let url = format!("{}/api/v0/block/put?cid-codec=raw&mhtype=sha2-256&pin=true", api);
+        let response = self
+            .client
+            .post(&url)
+            .header(CONTENT_TYPE, format!("multipart/form-data; boundary={}", boundary))
+            .body(body)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| ZkURLError::Network(format!("pin failed: {}", e.without_url())))?;
+        if !response.status().is_success() {
+            return Err(ZkURLError::HttpStatus(response.status().as_u16()));
+        }
+        let put: BlockPut = response
+            .json()
+            .await
+            .map_err(|e| ZkURLError::Network(format!("Failed to parse block/put response: {}", e)))?;
+        if put.key != expected.to_string() {
+            return Err(ZkURLError::ContentMismatch(format!("Kubo pinned {} but the bundle hashes to {}", put.key, expected)));
+        }
+        Ok(expected)
+    }
+}
+
+/// Sets the builder's domain, port and path prefix from an `https://`
+/// endpoint, mirroring how the resolver constructs proof URLs.
+fn with_endpoint_host(builder: ZkURLBuilder, endpoint: &str) -> Result<ZkURLBuilder, ZkURLError> {
+    let rest = endpoint.strip_prefix("https://").ok_or_else(|| {
+        ZkURLError::Config(format!("{} is not https, so no zkURL can resolve to it", redact_url(endpoint)))
+    })?;
+    let (authority, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let mut builder = match host.rsplit_once(':') {
+        Some((domain, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| ZkURLError::Config(format!("invalid port in endpoint {}", redact_url(endpoint))))?;
+            builder.domain(domain).port(port)
+        }
+        None => builder.domain(host),
+    };
+    if !prefix.is_empty() {
+        builder = builder.path_prefix(prefix);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{ProofMetadata, PublicInputs};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn bundle() -> ProofBundle {
+        ProofBundle {
+            proof: vec![1, 2, 3, 4],
+            public_inputs: PublicInputs {
+                block_hash: "0xabc".to_string(),
+                state_root: "0xdef".to_string(),
+                gas_used: 21_000,
+                transaction_count: 1,
+            },
+            signature: String::new(),
+            prover_id: String::new(),
+            timestamp: 1_700_000_000,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: 4 },
+        }
+    }
+
+    fn publisher() -> ProofPublisher {
+        ProofPublisher::new("prover1", SigningKey::from_bytes(&[7; 32]))
+    }
+
+    /// Answers one request with `response` and records the request body.
+    async fn serve_once(response: String) -> (String, Arc<Mutex<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&received);
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                let Some(head_end) = text.find("\r\n\r\n") else { continue };
+                let length: usize = text
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map_or(0, |len| len.trim().parse().unwrap());
+                if n == 0 || request.len() >= head_end + 4 + length {
+                    *sink.lock().unwrap() = request[head_end + 4..].to_vec();
+                    break;
+                }
+            }
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.shutdown().await;
+        });
+        (url, received)
+    }
+
+    fn ok(body: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn signature_covers_bundle_contents() {
+        let publisher = publisher();
+        let key = publisher.signing_key.verifying_key();
+        let mut signed = bundle();
+        publisher.sign(&mut signed);
+        assert_eq!(signed.prover_id, "prover1");
+        assert!(signed.verify_signature(&key));
+
+        signed.public_inputs.gas_used += 1;
+        assert!(!signed.verify_signature(&key));
+    }
+
+    #[tokio::test]
+    async fn pins_uploads_and_returns_content_addressed_zkurl() {
+        let publisher = publisher();
+        let mut signed = bundle();
+        publisher.sign(&mut signed);
+        let expected = raw_block_cid(&encode_bundle(&signed, BundleEncoding::Json).unwrap());
+
+        let (kubo, _) = serve_once(ok(&format!("{{\"Key\":\"{}\",\"Size\":1}}", expected))).await;
+        let (endpoint, uploaded) = serve_once(ok("")).await;
+        let store = Arc::new(crate::store::MemoryProofStore::default());
+        let published = publisher
+            .with_store(store.clone())
+            .with_kubo(kubo)
+            .with_endpoint(endpoint.clone(), Method::PUT)
+            .publish(bundle(), "block-7")
+            .await
+            .unwrap();
+
+        assert_eq!(published.cid, Some(expected));
+        assert_eq!(published.uploaded, vec![format!("{}/proof/block-7", endpoint)]);
+        assert_eq!(published.zkurl.host.as_cid(), Some(&expected));
+        let metadata = published.zkurl.metadata.unwrap();
+        assert!(metadata.checksum_matches(&[1, 2, 3, 4]));
+        let upload: ProofBundle = serde_json::from_slice(&uploaded.lock().unwrap()).unwrap();
+        assert_eq!(upload.signature, signed.signature);
+        assert_eq!(store.get("block-7").unwrap().signature, signed.signature);
+    }
+
+    #[tokio::test]
+    async fn compressed_bundles_resolve_against_their_zkurl() {
+        let raw = vec![42u8; 4096];
+        let mut compressed = bundle();
+        compressed.proof = zstd::encode_all(&raw[..], 3).unwrap();
+        compressed.metadata.compression = Some("zstd".to_string());
+        let publisher = publisher();
+        let mut signed = compressed.clone();
+        publisher.sign(&mut signed);
+        let block = encode_bundle(&signed, BundleEncoding::Json).unwrap();
+        let (kubo, _) = serve_once(ok(&format!("{{\"Key\":\"{}\",\"Size\":1}}", raw_block_cid(&block)))).await;
+        let published = publisher.with_kubo(kubo).publish(compressed, "block-7").await.unwrap();
+
+        assert_eq!(published.zkurl.metadata.as_ref().unwrap().size_bytes, Some(4096));
+        let policy = crate::policy::ResolverPolicy::default();
+        let resolved = crate::resolver::decode_verified_bundle(&published.zkurl, &block, BundleEncoding::Json, &policy).unwrap();
+        assert_eq!(resolved.proof, raw);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_proof_id_before_uploading() {
+        let publisher = publisher().with_endpoint("https://proofs.example", Method::PUT);
+        let result = publisher.publish(bundle(), "block 7").await;
+        assert!(matches!(result, Err(ZkURLError::InvalidCharacter { .. } | ZkURLError::InvalidComponent { .. })));
+    }
+
+    #[test]
+    fn endpoint_host_becomes_canonical_zkurl() {
+        let builder = ZkURL::builder().prover_id("prover1").proof_id("p1");
+        let zkurl = with_endpoint_host(builder, "https://proofs.example:8443/api/v2").unwrap().build().unwrap();
+        assert_eq!(zkurl.to_string(), "zk://prover1@proofs.example:8443/api/v2/p1");
+
+        let builder = ZkURL::builder().prover_id("prover1").proof_id("p1");
+        assert!(matches!(with_endpoint_host(builder, "http://127.0.0.1:8080"), Err(ZkURLError::Config(_))));
+    }
+}
@@ -0,0 +1,177 @@
+//! Per-endpoint retries and circuit breaking for the resolver.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::hedge::origin;
+
+/// How often and how patiently a single endpoint is retried.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Fraction (0.0-1.0) of each backoff that is randomized, so nodes that
+    /// failed together do not retry in lockstep
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `retry` (0-based): exponential in `retry`,
+    /// capped at `max_backoff`, then reduced by up to `jitter` of itself.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self.base_backoff.saturating_mul(1u32.checked_shl(retry).unwrap_or(u32::MAX));
+        let capped = exponential.min(self.max_backoff);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return capped;
+        }
+        capped.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=jitter))
+    }
+}
+
+/// One endpoint's part in a failed resolution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointAttempt {
+    pub url: String,
+    /// Requests actually sent; 0 when the circuit breaker skipped the endpoint
+    pub attempts: u32,
+    /// Why the last attempt failed
+    pub error: String,
+}
+
+/// Every endpoint tried for a zkURL and why each one failed.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ResolveError {
+    pub attempts: Vec<EndpointAttempt>,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "proof not found at any endpoint")?;
+        for attempt in &self.attempts {
+            write!(f, "; {} ({} attempts): {}", attempt.url, attempt.attempts, attempt.error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+/// Skips endpoints (by URL origin) after `failure_threshold` consecutive
+/// failures, for `cooldown`. After the cooldown one request is let through;
+/// a success closes the circuit, another failure reopens it.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    states: Mutex<HashMap<String, BreakerState>>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request to `url` may be sent now.
+    pub fn allows(&self, url: &str) -> bool {
+        let mut states = self.states.lock().expect("Circuit breaker lock poisoned");
+        match states.get_mut(origin(url)) {
+            Some(state) => match state.open_until {
+                Some(until) if Instant::now() < until => false,
+                Some(_) => {
+                    // Half-open: allow this probe, and keep others out until it reports
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                    true
+                }
+                None => true,
+            },
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self, url: &str) {
+        let mut states = self.states.lock().expect("Circuit breaker lock poisoned");
+        states.remove(origin(url));
+    }
+
+    pub fn record_failure(&self, url: &str) {
+        let mut states = self.states.lock().expect("Circuit breaker lock poisoned");
+        let state = states.entry(origin(url).to_string()).or_default();
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.failure_threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Whether `url`'s circuit is currently open.
+    pub fn is_open(&self, url: &str) -> bool {
+        let states = self.states.lock().expect("Circuit breaker lock poisoned");
+        states
+            .get(origin(url))
+            .and_then(|state| state.open_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let policy = RetryPolicy { jitter: 0.0, ..RetryPolicy::default() };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(10), Duration::from_secs(2));
+        assert_eq!(policy.backoff(40), Duration::from_secs(2));
+
+        let jittered = RetryPolicy::default().backoff(1);
+        assert!(jittered <= Duration::from_millis(200) && jittered >= Duration::from_millis(160));
+    }
+
+    #[test]
+    fn breaker_opens_after_threshold_and_half_opens() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+        let url = "https://gw.dev/proof/a";
+        breaker.record_failure(url);
+        assert!(breaker.allows(url));
+        breaker.record_failure("https://gw.dev/proof/b");
+        assert!(!breaker.allows(url));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allows(url));
+        assert!(!breaker.allows(url), "only one half-open probe at a time");
+        breaker.record_success(url);
+        assert!(!breaker.is_open(url));
+    }
+}
@@ -0,0 +1,189 @@
+//! Embedded HTTP server for hosting proofs from a local `ProofStore`.
+//!
+//! Serves the layout the resolver expects: `GET /proof/{proof_id}` returns
+//! the bundle in the encoding the client's `Accept` header prefers, and
+//! `GET /proofs` lists proof ids for collection zkURLs. Bundles carry a
+//! strong `ETag` for revalidation, and `Range` requests are honoured so
+//! interrupted downloads can resume.
+
+use axum::extract::{Path, State};
+use axum::http::header::{ACCEPT, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE, VARY};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::codec::{encode_bundle, BundleEncoding};
+use crate::store::ProofStore;
+
+pub struct ProofServer {
+    store: Arc<dyn ProofStore>,
+}
+
+impl ProofServer {
+    pub fn new(store: Arc<dyn ProofStore>) -> Self {
+        Self { store }
+    }
+
+    /// Routes for embedding in a larger axum application.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/proof/:proof_id", get(get_proof))
+            .route("/proofs", get(list_proofs))
+            .with_state(Arc::clone(&self.store))
+    }
+
+    /// Serves until the listener fails.
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+}
+
+async fn list_proofs(State(store): State<Arc<dyn ProofStore>>) -> Json<Vec<String>> {
+    Json(store.proof_ids())
+}
+
+async fn get_proof(
+    State(store): State<Arc<dyn ProofStore>>,
+    Path(proof_id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(bundle) = store.get(&proof_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let encoding = BundleEncoding::negotiate(headers.get(ACCEPT).and_then(|v| v.to_str().ok()));
+    let body = match encode_bundle(&bundle, encoding) {
+        Ok(body) => body,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let etag = format!("\"{}\"", blake3::hash(&body).to_hex());
+    let common = [
+        (CONTENT_TYPE, encoding.content_type().to_string()),
+        (ETAG, etag.clone()),
+        (ACCEPT_RANGES, "bytes".to_string()),
+        (VARY, "Accept".to_string()),
+    ];
+    if headers.get(IF_NONE_MATCH).and_then(|v| v.to_str().ok()).is_some_and(|tags| etag_matches(tags, &etag)) {
+        return (StatusCode::NOT_MODIFIED, common).into_response();
+    }
+
+    let len = body.len() as u64;
+    match headers.get(RANGE).and_then(|v| v.to_str().ok()).map(|range| parse_range(range, len)) {
+        None => (StatusCode::OK, common, body).into_response(),
+        Some(Some((start, end))) => {
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            let part = body[start as usize..=end as usize].to_vec();
+            (StatusCode::PARTIAL_CONTENT, common, [(CONTENT_RANGE, content_range)], part).into_response()
+        }
+        Some(None) => {
+            (StatusCode::RANGE_NOT_SATISFIABLE, [(CONTENT_RANGE, format!("bytes */{}", len))]).into_response()
+        }
+    }
+}
+
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// Parses a single `bytes=` range into inclusive offsets within `len`.
+/// Returns `None` for unsatisfiable or multi-part ranges.
+fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') || len == 0 {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (len.checked_sub(suffix.min(len))?, len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len - 1)),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{download, download_if_none_match};
+    use crate::resolver::{ProofBundle, ProofMetadata, PublicInputs};
+    use crate::store::MemoryProofStore;
+    use reqwest::{Client, Method};
+    use std::time::Duration;
+
+    async fn spawn_server() -> String {
+        let store = MemoryProofStore::default();
+        let bundle = ProofBundle {
+            proof: vec![9; 64],
+            public_inputs: PublicInputs {
+                block_hash: "0xabc".to_string(),
+                state_root: "0xdef".to_string(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: "prover".to_string(),
+            timestamp: 0,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: 64 },
+        };
+        store.put("p1", bundle).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(ProofServer::new(Arc::new(store)).serve(listener));
+        url
+    }
+
+    #[tokio::test]
+    async fn serves_negotiated_encoding_and_revalidates() {
+        let base = spawn_server().await;
+        let url = format!("{}/proof/p1", base);
+        let timeout = Duration::from_secs(5);
+
+        let downloaded = download(&Client::new(), Method::GET, &url, timeout, 1 << 20, None, None).await.unwrap();
+        assert_eq!(downloaded.content_type.as_deref(), Some("application/cbor"));
+        let encoding = BundleEncoding::from_content_type(downloaded.content_type.as_deref());
+        assert_eq!(crate::codec::decode_bundle(&downloaded.body, encoding).unwrap().proof, vec![9; 64]);
+
+        let etag = downloaded.etag.unwrap();
+        let revalidated = download_if_none_match(&Client::new(), &url, &etag, timeout, 1 << 20, None, None).await;
+        assert_eq!(revalidated, Ok(None));
+
+        let missing = download(&Client::new(), Method::GET, &format!("{}/proof/nope", base), timeout, 1 << 20, None, None).await;
+        assert_eq!(missing.unwrap_err(), crate::ZkURLError::HttpStatus(404));
+        let ids: Vec<String> = Client::new().get(format!("{}/proofs", base)).send().await.unwrap().json().await.unwrap();
+        assert_eq!(ids, vec!["p1"]);
+    }
+
+    #[tokio::test]
+    async fn serves_byte_ranges() {
+        let base = spawn_server().await;
+        let client = Client::new();
+        let url = format!("{}/proof/p1", base);
+        let full = client.get(&url).header("accept", "application/json").send().await.unwrap().bytes().await.unwrap();
+
+        let partial = client.get(&url).header("accept", "application/json").header("range", "bytes=10-").send().await.unwrap();
+        assert_eq!(partial.status().as_u16(), 206);
+        assert_eq!(partial.bytes().await.unwrap(), full[10..]);
+
+        let unsatisfiable = client.get(&url).header("range", format!("bytes={}-", full.len() * 2)).send().await.unwrap();
+        assert_eq!(unsatisfiable.status().as_u16(), 416);
+    }
+
+    #[test]
+    fn parses_range_forms() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some((0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=-10", 100), Some((90, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some((50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=0-1,5-6", 100), None);
+    }
+}
@@ -0,0 +1,330 @@
+//! Local storage for proof bundles a node serves or publishes.
+//!
+//! `DiskProofStore` keeps each bundle once, under the blake3 hash of its
+//! JSON encoding, with an index mapping proof ids to blobs. Proofs are
+//! tagged with the epoch they belong to; garbage collection drops proofs
+//! older than the retention window unless they are pinned (e.g. because
+//! they prove a finalized block).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::codec::{decode_bundle, encode_bundle, BundleEncoding};
+use crate::resolver::ProofBundle;
+use crate::ZkURLError;
+
+/// Bundles addressed by proof id, as served at `/proof/{proof_id}`.
+pub trait ProofStore: Send + Sync {
+    fn get(&self, proof_id: &str) -> Option<ProofBundle>;
+    fn put(&self, proof_id: &str, bundle: ProofBundle) -> Result<(), ZkURLError>;
+    /// Every stored proof id, in ascending order.
+    fn proof_ids(&self) -> Vec<String>;
+}
+
+/// In-memory store, for tests and short-lived provers.
+#[derive(Default)]
+pub struct MemoryProofStore {
+    bundles: RwLock<BTreeMap<String, ProofBundle>>,
+}
+
+impl ProofStore for MemoryProofStore {
+    fn get(&self, proof_id: &str) -> Option<ProofBundle> {
+        self.bundles.read().expect("Proof store lock poisoned").get(proof_id).cloned()
+    }
+
+    fn put(&self, proof_id: &str, bundle: ProofBundle) -> Result<(), ZkURLError> {
+        self.bundles
+            .write()
+            .expect("Proof store lock poisoned")
+            .insert(proof_id.to_string(), bundle);
+        Ok(())
+    }
+
+    fn proof_ids(&self) -> Vec<String> {
+        self.bundles.read().expect("Proof store lock poisoned").keys().cloned().collect()
+    }
+}
+
+/// Which proofs garbage collection keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keep proofs from the newest N epochs seen; `None` keeps every epoch.
+    /// Pinned proofs and proofs stored without an epoch are always kept.
+    pub keep_epochs: Option<u64>,
+}
+
+/// Snapshot of store usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreStats {
+    pub proofs: u64,
+    pub pinned: u64,
+    /// Distinct bundle blobs; identical bundles under several ids share one
+    pub blobs: u64,
+    pub bytes: u64,
+    pub gc_runs: u64,
+    pub gc_removed: u64,
+}
+
+/// Result of one garbage collection pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub removed_proofs: u64,
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    /// blake3 hex of the blob
+    hash: String,
+    size: u64,
+    epoch: Option<u64>,
+    pinned: bool,
+}
+
+pub struct DiskProofStore {
+    dir: PathBuf,
+    policy: RetentionPolicy,
+    index: Mutex<BTreeMap<String, IndexEntry>>,
+    gc_runs: AtomicU64,
+    gc_removed: AtomicU64,
+}
+
+impl DiskProofStore {
+    /// Opens (or creates) a store rooted at `dir`.
+    pub fn open(dir: impl Into<PathBuf>, policy: RetentionPolicy) -> Result<Self, ZkURLError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join("blobs")).map_err(|e| store_error("create store directory", e))?;
+        let index = match std::fs::read(dir.join("index.json")) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| store_error("parse store index", e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(store_error("read store index", e)),
+        };
+        Ok(Self {
+            dir,
+            policy,
+            index: Mutex::new(index),
+            gc_runs: AtomicU64::new(0),
+            gc_removed: AtomicU64::new(0),
+        })
+    }
+
+    /// Stores a bundle as part of `epoch`, subject to the retention policy.
+    pub fn put_in_epoch(&self, proof_id: &str, bundle: &ProofBundle, epoch: Option<u64>) -> Result<(), ZkURLError> {
+        let blob = encode_bundle(bundle, BundleEncoding::Json)?;
+        let hash = blake3::hash(&blob).to_hex().to_string();
+        // Held across the blob write so a concurrent GC cannot delete it
+        // before the index references it
+        let mut index = self.index.lock().expect("Proof store lock poisoned");
+        let path = self.blob_path(&hash);
+        if !path.exists() {
+            write_atomic(&path, &blob)?;
+        }
+        let pinned = index.get(proof_id).is_some_and(|entry| entry.pinned);
+        index.insert(proof_id.to_string(), IndexEntry { hash, size: blob.len() as u64, epoch, pinned });
+        self.save_index(&index)
+    }
+
+    /// Keeps a proof through garbage collection, e.g. once its block is
+    /// finalized. Returns false if the proof is not stored.
+    pub fn pin(&self, proof_id: &str) -> Result<bool, ZkURLError> {
+        self.set_pinned(proof_id, true)
+    }
+
+    pub fn unpin(&self, proof_id: &str) -> Result<bool, ZkURLError> {
+        self.set_pinned(proof_id, false)
+    }
+
+    /// Drops proofs outside the retention window, then deletes blobs no
+    /// remaining proof references.
+    pub fn gc(&self) -> Result<GcReport, ZkURLError> {
+        let mut index = self.index.lock().expect("Proof store lock poisoned");
+        let before = index.len();
+        if let Some(keep) = self.policy.keep_epochs {
+            if let Some(newest) = index.values().filter_map(|entry| entry.epoch).max() {
+                let oldest_kept = newest.saturating_sub(keep.saturating_sub(1));
+                index.retain(|_, entry| entry.pinned || entry.epoch.is_none_or(|epoch| epoch >= oldest_kept));
+            }
+        }
+        let removed_proofs = (before - index.len()) as u64;
+        if removed_proofs > 0 {
+            self.save_index(&index)?;
+        }
+
+        let live: HashSet<&str> = index.values().map(|entry| entry.hash.as_str()).collect();
+        let mut freed_bytes = 0;
+        let blobs = std::fs::read_dir(self.dir.join("blobs")).map_err(|e| store_error("list blobs", e))?;
+        for blob in blobs.flatten() {
+            let path = blob.path();
+            let hash = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+            if !live.contains(hash) {
+                freed_bytes += blob.metadata().map_or(0, |m| m.len());
+                let _ = std::fs::remove_file(&path);
+            }
+        }
+
+        self.gc_runs.fetch_add(1, Ordering::Relaxed);
+        self.gc_removed.fetch_add(removed_proofs, Ordering::Relaxed);
+        Ok(GcReport { removed_proofs, freed_bytes })
+    }
+
+    /// Runs `gc` every `interval` until the returned task is aborted.
+    pub fn spawn_gc(self: Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let store = Arc::clone(&self);
+                if let Ok(Err(e)) = tokio::task::spawn_blocking(move || store.gc()).await {
+                    tracing::warn!(error = %e, "proof store gc failed");
+                }
+            }
+        })
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        let index = self.index.lock().expect("Proof store lock poisoned");
+        let mut blobs = HashSet::new();
+        let mut bytes = 0;
+        for entry in index.values() {
+            if blobs.insert(entry.hash.as_str()) {
+                bytes += entry.size;
+            }
+        }
+        StoreStats {
+            proofs: index.len() as u64,
+            pinned: index.values().filter(|entry| entry.pinned).count() as u64,
+            blobs: blobs.len() as u64,
+            bytes,
+            gc_runs: self.gc_runs.load(Ordering::Relaxed),
+            gc_removed: self.gc_removed.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_pinned(&self, proof_id: &str, pinned: bool) -> Result<bool, ZkURLError> {
+        let mut index = self.index.lock().expect("Proof store lock poisoned");
+        let Some(entry) = index.get_mut(proof_id) else {
+            return Ok(false);
+        };
+        entry.pinned = pinned;
+        self.save_index(&index)?;
+        Ok(true)
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("blobs").join(format!("{}.json", hash))
+    }
+
+    fn save_index(&self, index: &BTreeMap<String, IndexEntry>) -> Result<(), ZkURLError> {
+        let bytes = serde_json::to_vec(index).map_err(|e| store_error("encode store index", e))?;
+        write_atomic(&self.dir.join("index.json"), &bytes)
+    }
+}
+
+impl ProofStore for DiskProofStore {
+    /// Reads the blob and checks it still hashes to its address, so a
+    /// corrupted file is treated as missing rather than served.
+    fn get(&self, proof_id: &str) -> Option<ProofBundle> {
+        let hash = self.index.lock().expect("Proof store lock poisoned").get(proof_id)?.hash.clone();
+        let blob = std::fs::read(self.blob_path(&hash)).ok()?;
+        if blake3::hash(&blob).to_hex().as_str() != hash {
+            return None;
+        }
+        decode_bundle(&blob, BundleEncoding::Json).ok()
+    }
+
+    fn put(&self, proof_id: &str, bundle: ProofBundle) -> Result<(), ZkURLError> {
+        self.put_in_epoch(proof_id, &bundle, None)
+    }
+
+    fn proof_ids(&self) -> Vec<String> {
+        self.index.lock().expect("Proof store lock poisoned").keys().cloned().collect()
+    }
+}
+
+fn store_error(action: &str, error: impl std::fmt::Display) -> ZkURLError {
+    ZkURLError::Storage(format!("failed to {}: {}", action, error))
+}
+
+/// Write then rename so readers never see a partial file.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), ZkURLError> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes).map_err(|e| store_error("write", e))?;
+    std::fs::rename(&tmp, path).map_err(|e| store_error("write", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{ProofMetadata, PublicInputs};
+
+    fn bundle(tag: u8) -> ProofBundle {
+        ProofBundle {
+            proof: vec![tag; 32],
+            public_inputs: PublicInputs {
+                block_hash: String::new(),
+                state_root: String::new(),
+                gas_used: 0,
+                transaction_count: 0,
+            },
+            signature: String::new(),
+            prover_id: "prover".to_string(),
+            timestamp: 0,
+            metadata: ProofMetadata { version: "v1".to_string(), compression: None, size_bytes: 32 },
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zkurl-store-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn deduplicates_and_survives_reopen() {
+        let dir = temp_dir("reopen");
+        let store = DiskProofStore::open(&dir, RetentionPolicy::default()).unwrap();
+        store.put("a", bundle(1)).unwrap();
+        store.put("b", bundle(1)).unwrap();
+        store.put("c", bundle(2)).unwrap();
+        assert_eq!(store.stats().blobs, 2);
+
+        let reopened = DiskProofStore::open(&dir, RetentionPolicy::default()).unwrap();
+        assert_eq!(reopened.proof_ids(), vec!["a", "b", "c"]);
+        assert_eq!(reopened.get("b").unwrap().proof, vec![1; 32]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn corrupted_blob_is_not_served() {
+        let dir = temp_dir("corrupt");
+        let store = DiskProofStore::open(&dir, RetentionPolicy::default()).unwrap();
+        store.put("a", bundle(1)).unwrap();
+        let blob = std::fs::read_dir(dir.join("blobs")).unwrap().next().unwrap().unwrap().path();
+        std::fs::write(blob, b"{}").unwrap();
+        assert!(store.get("a").is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn gc_keeps_recent_epochs_and_pinned_proofs() {
+        let dir = temp_dir("gc");
+        let store = DiskProofStore::open(&dir, RetentionPolicy { keep_epochs: Some(2) }).unwrap();
+        for epoch in 1..=4u8 {
+            store.put_in_epoch(&format!("e{}", epoch), &bundle(epoch), Some(epoch as u64)).unwrap();
+        }
+        store.put("unscoped", bundle(9)).unwrap();
+        assert!(store.pin("e1").unwrap());
+
+        let report = store.gc().unwrap();
+        assert_eq!(report.removed_proofs, 1);
+        assert!(report.freed_bytes > 0);
+        assert_eq!(store.proof_ids(), vec!["e1", "e3", "e4", "unscoped"]);
+        let stats = store.stats();
+        assert_eq!((stats.proofs, stats.pinned, stats.gc_runs, stats.gc_removed), (4, 1, 1, 1));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,144 @@
+//! Character-level rules for zkURL components: percent-encoding and host
+//! validation. Offsets in errors are byte positions in the full zkURL string.
+
+use crate::ZkURLError;
+
+/// RFC 3986 unreserved characters, allowed unescaped in every component.
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Percent-encodes everything outside the unreserved set and `keep`.
+pub fn percent_encode(value: &str, keep: &[u8]) -> String {
+    let mut out = String::with_capacity(value.len());
+    for &b in value.as_bytes() {
+        if is_unreserved(b) || keep.contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Decodes a percent-encoded component that starts at `offset` in the zkURL.
+///
+/// Raw characters outside the unreserved set and `keep` are rejected, as are
+/// malformed escapes and escapes that decode to invalid UTF-8.
+pub fn percent_decode(
+    component: &'static str,
+    value: &str,
+    offset: usize,
+    keep: &[u8],
+) -> Result<String, ZkURLError> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'%' {
+            // Exactly two hex digits; `from_str_radix` alone would take a sign
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|h| std::str::from_utf8(h).ok());
+            let decoded = hex.and_then(|h| u8::from_str_radix(h, 16).ok()).ok_or(
+                ZkURLError::InvalidPercentEncoding {
+                    component,
+                    offset: offset + i,
+                },
+            )?;
+            out.push(decoded);
+            i += 3;
+        } else if is_unreserved(b) || keep.contains(&b) {
+            out.push(b);
+            i += 1;
+        } else {
+            return Err(invalid_char(component, value, offset, i));
+        }
+    }
+    String::from_utf8(out).map_err(|_| ZkURLError::InvalidPercentEncoding { component, offset })
+}
+
+/// Validates a DNS name: 1-253 characters, dot-separated labels of 1-63
+/// alphanumerics or '-', not starting or ending with '-'.
+pub fn validate_domain(domain: &str, offset: usize) -> Result<(), ZkURLError> {
+    if let Some(i) = domain.bytes().position(|b| !b.is_ascii_alphanumeric() && b != b'-' && b != b'.') {
+        return Err(invalid_char("domain", domain, offset, i));
+    }
+    if domain.is_empty() || domain.len() > 253 {
+        return Err(invalid_domain("must be 1-253 characters"));
+    }
+    for label in domain.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(invalid_domain("labels must be 1-63 characters"));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid_domain("labels cannot start or end with '-'"));
+        }
+    }
+    Ok(())
+}
+
+fn invalid_domain(reason: &str) -> ZkURLError {
+    ZkURLError::InvalidComponent {
+        component: "domain",
+        reason: reason.to_string(),
+    }
+}
+
+fn invalid_char(component: &'static str, value: &str, offset: usize, index: usize) -> ZkURLError {
+    ZkURLError::InvalidCharacter {
+        component,
+        offset: offset + index,
+        found: value[index..].chars().next().unwrap_or('\u{FFFD}'),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_roundtrip() {
+        let raw = "block 1#ä@x";
+        let encoded = percent_encode(raw, b"");
+        assert_eq!(encoded, "block%201%23%C3%A4%40x");
+        assert_eq!(percent_decode("proof_id", &encoded, 0, b"").unwrap(), raw);
+    }
+
+    #[test]
+    fn decode_reports_offsets() {
+        assert_eq!(
+            percent_decode("proof_id", "ab%zz", 10, b""),
+            Err(ZkURLError::InvalidPercentEncoding {
+                component: "proof_id",
+                offset: 12
+            })
+        );
+        for signed in ["%+1", "%-1", "%+f"] {
+            assert_eq!(
+                percent_decode("proof_id", signed, 10, b""),
+                Err(ZkURLError::InvalidPercentEncoding { component: "proof_id", offset: 10 })
+            );
+        }
+        assert_eq!(
+            percent_decode("proof_id", "a b", 10, b""),
+            Err(ZkURLError::InvalidCharacter {
+                component: "proof_id",
+                offset: 11,
+                found: ' '
+            })
+        );
+    }
+
+    #[test]
+    fn domain_rules() {
+        assert!(validate_domain("proofs.cubiq.dev", 0).is_ok());
+        assert!(validate_domain("bad..dev", 0).is_err());
+        assert!(matches!(
+            validate_domain("bad_host.dev", 5),
+            Err(ZkURLError::InvalidCharacter { offset: 8, found: '_', .. })
+        ));
+    }
+}
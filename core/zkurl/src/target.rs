@@ -0,0 +1,102 @@
+use crate::ZkURL;
+
+/// Upper bound on proofs a single range zkURL may address, so a typo like
+/// `blocks/1-1000000000` cannot make a resolver enumerate forever.
+pub const MAX_RANGE_LEN: u64 = 10_000;
+
+/// What a zkURL's last path segment refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZkURLTarget {
+    /// A single proof, e.g. `zk://prover@host/epoch42/block7`
+    Single(String),
+    /// Numbered proofs `start..=end` under the path prefix, e.g. `blocks/100-200`
+    Range { start: u64, end: u64 },
+    /// Every proof under the path prefix, e.g. `epoch42/*`
+    Collection,
+}
+
+impl ZkURLTarget {
+    /// Classifies a decoded proof id. Ranges need decimal bounds with
+    /// `start <= end`; anything else that is not `*` is a single proof id.
+    pub fn parse(proof_id: &str) -> Self {
+        if proof_id == "*" {
+            return ZkURLTarget::Collection;
+        }
+        if let Some((start, end)) = proof_id.split_once('-') {
+            if let (Some(start), Some(end)) = (parse_bound(start), parse_bound(end)) {
+                if start <= end {
+                    return ZkURLTarget::Range { start, end };
+                }
+            }
+        }
+        ZkURLTarget::Single(proof_id.to_string())
+    }
+
+    /// Number of proofs addressed, or `None` for collections whose size is
+    /// only known to the serving prover.
+    pub fn proof_count(&self) -> Option<u64> {
+        match self {
+            ZkURLTarget::Single(_) => Some(1),
+            ZkURLTarget::Range { start, end } => Some((end - start).saturating_add(1)),
+            ZkURLTarget::Collection => None,
+        }
+    }
+}
+
+fn parse_bound(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+impl ZkURL {
+    /// The proof, range or collection this zkURL addresses.
+    pub fn target(&self) -> ZkURLTarget {
+        ZkURLTarget::parse(&self.proof_id)
+    }
+
+    /// zkURL for one member of a range or collection: same prover, host and
+    /// path prefix, with `proof_id` in place of the range/wildcard.
+    pub fn member(&self, proof_id: impl Into<String>) -> ZkURL {
+        ZkURL {
+            proof_id: proof_id.into(),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn classifies_targets() {
+        let single = ZkURL::from_str("zk://prover@host.dev/epoch42/block-7").unwrap();
+        assert_eq!(single.target(), ZkURLTarget::Single("block-7".to_string()));
+
+        let collection = ZkURL::from_str("zk://prover@host.dev/epoch42/*").unwrap();
+        assert_eq!(collection.target(), ZkURLTarget::Collection);
+        assert_eq!(collection.path_prefix, vec!["epoch42".to_string()]);
+        assert_eq!(collection.to_string(), "zk://prover@host.dev/epoch42/*");
+
+        let range = ZkURL::from_str("zk://prover@host.dev/blocks/100-200").unwrap();
+        assert_eq!(range.target(), ZkURLTarget::Range { start: 100, end: 200 });
+        assert_eq!(range.target().proof_count(), Some(101));
+    }
+
+    #[test]
+    fn reversed_or_non_numeric_ranges_are_single_ids() {
+        assert_eq!(ZkURLTarget::parse("200-100"), ZkURLTarget::Single("200-100".to_string()));
+        assert_eq!(ZkURLTarget::parse("a-b"), ZkURLTarget::Single("a-b".to_string()));
+        assert_eq!(ZkURLTarget::parse("1-+2"), ZkURLTarget::Single("1-+2".to_string()));
+    }
+
+    #[test]
+    fn wildcard_only_allowed_in_last_segment() {
+        assert!(ZkURL::from_str("zk://prover@host.dev/*/block1").is_err());
+        let member = ZkURL::from_str("zk://prover@host.dev/epoch42/*").unwrap().member("block3");
+        assert_eq!(member.to_string(), "zk://prover@host.dev/epoch42/block3");
+    }
+}